@@ -109,9 +109,11 @@ extern "C" {
 #define XTRIEVE_KEY_FLAG_BINARY          0x0004
 #define XTRIEVE_KEY_FLAG_NULL_KEY        0x0008
 #define XTRIEVE_KEY_FLAG_SEGMENTED       0x0010
-#define XTRIEVE_KEY_FLAG_DESCENDING      0x0020
-#define XTRIEVE_KEY_FLAG_SUPPLEMENTAL    0x0040
-#define XTRIEVE_KEY_FLAG_EXTENDED_TYPE   0x0080
+#define XTRIEVE_KEY_FLAG_ALT_SEQUENCE    0x0020
+#define XTRIEVE_KEY_FLAG_DESCENDING      0x0040
+#define XTRIEVE_KEY_FLAG_SUPPLEMENTAL    0x0080
+#define XTRIEVE_KEY_FLAG_EXTENDED_TYPE   0x0100
+#define XTRIEVE_KEY_FLAG_NO_CASE         0x0400
 
 /* ============================================================================
  * Types
@@ -97,9 +97,11 @@ const (
 	KeyFlagBinary       = 0x0004
 	KeyFlagNullKey      = 0x0008
 	KeyFlagSegmented    = 0x0010
-	KeyFlagDescending   = 0x0020
-	KeyFlagSupplemental = 0x0040
-	KeyFlagExtendedType = 0x0080
+	KeyFlagAltSequence  = 0x0020
+	KeyFlagDescending   = 0x0040
+	KeyFlagSupplemental = 0x0080
+	KeyFlagExtendedType = 0x0100
+	KeyFlagNoCase       = 0x0400
 )
 
 // Request represents a Btrieve request
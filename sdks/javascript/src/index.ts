@@ -91,9 +91,11 @@ export const KeyFlags = {
     BINARY: 0x0004,
     NULL_KEY: 0x0008,
     SEGMENTED: 0x0010,
-    DESCENDING: 0x0020,
-    SUPPLEMENTAL: 0x0040,
-    EXTENDED_TYPE: 0x0080,
+    ALT_SEQUENCE: 0x0020,
+    DESCENDING: 0x0040,
+    SUPPLEMENTAL: 0x0080,
+    EXTENDED_TYPE: 0x0100,
+    NO_CASE: 0x0400,
 } as const;
 
 /**
@@ -258,7 +260,7 @@ export class XtrieveClient {
     /**
      * Open a file
      */
-    async open(filePath: string, mode: number = -1): Promise<BtrieveResponse> {
+    async open(filePath: string, mode: number = 0): Promise<BtrieveResponse> {
         return this.execute({
             operation: Operations.OPEN,
             filePath,
@@ -105,9 +105,11 @@ class KeyFlags
     public const BINARY = 0x0004;
     public const NULL_KEY = 0x0008;
     public const SEGMENTED = 0x0010;
-    public const DESCENDING = 0x0020;
-    public const SUPPLEMENTAL = 0x0040;
-    public const EXTENDED_TYPE = 0x0080;
+    public const ALT_SEQUENCE = 0x0020;
+    public const DESCENDING = 0x0040;
+    public const SUPPLEMENTAL = 0x0080;
+    public const EXTENDED_TYPE = 0x0100;
+    public const NO_CASE = 0x0400;
 }
 
 /**
@@ -0,0 +1,271 @@
+// Run a scripted DOS test in DOSBox-X against xtrieved
+//
+// Prepares a directory to mount as C: (BTRSERL.EXE, the test script and
+// any programs it needs), writes a DOSBox-X config whose nullmodem dials
+// a bridge listening on a free local port, and starts DOSBox-X on it. The
+// autoexec loads BTRSERL, calls the script and exits. Files the script
+// writes to C: are then compared with the expected ones.
+//
+//   dosbox-run TEST.BAT --copy BTRTEST.EXE --expect OUT.TXT=expected/out.txt
+//
+// xtrieved must already be running; the bridge is the xtrieve-bridge
+// relay, so protocol changes are exercised by a real 16-bit requester.
+
+use std::env;
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitCode};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+use xtrieve_bridge::{serve, Options};
+
+const DEFAULT_XTRIEVE_ADDR: &str = "127.0.0.1:7419";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+const DEFAULT_TSR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../dos-client/BTRSERL.EXE");
+/// The name the script is copied to on C:
+const SCRIPT_NAME: &str = "XTTEST.BAT";
+
+const USAGE: &str = "\
+usage: dosbox-run <script.bat> [--dir <path>] [--copy <file>]...
+                  [--expect <DOS name>=<file>]... [--dosbox <program>]
+                  [--tsr <BTRSERL.EXE>] [--timeout <seconds>] [xtrieve_addr]
+
+  --dir <path>       host directory mounted as C: (default: a fresh one
+                     under the temp directory)
+  --copy <file>      also copy <file> to C:
+  --expect N=<file>  after the run, C:\\N must match <file> (line endings
+                     aside)
+  --dosbox <program> DOSBox-X executable (default: dosbox-x)
+  --timeout <s>      kill DOSBox-X after this long (default 120)";
+
+/// The DOSBox-X config for one run
+fn dosbox_conf(port: u16, dir: &Path) -> String {
+    format!(
+        "[cpu]\n\
+         cycles = max\n\
+         \n\
+         [serial]\n\
+         serial1 = nullmodem server:127.0.0.1 port:{}\n\
+         \n\
+         [autoexec]\n\
+         mount c \"{}\"\n\
+         c:\n\
+         BTRSERL\n\
+         call {}\n\
+         exit\n",
+        port,
+        dir.display(),
+        SCRIPT_NAME
+    )
+}
+
+/// Compare DOS output with an expected file, ignoring CR and trailing
+/// blank lines
+fn same_text(actual: &[u8], expected: &[u8]) -> bool {
+    let normalize = |bytes: &[u8]| {
+        let text: Vec<u8> = bytes.iter().copied().filter(|&b| b != b'\r' && b != 0x1A).collect();
+        let end = text.iter().rposition(|&b| b != b'\n').map_or(0, |i| i + 1);
+        text[..end].to_vec()
+    };
+    normalize(actual) == normalize(expected)
+}
+
+/// `name` in `dir`, matched case-insensitively as DOS would
+fn find_dos_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().to_string_lossy().eq_ignore_ascii_case(name))
+        .map(|entry| entry.path())
+}
+
+fn copy_in(file: &Path, dir: &Path, name: Option<&str>) {
+    let name = name.map(str::to_string).unwrap_or_else(|| {
+        file.file_name().map_or_else(String::new, |n| n.to_string_lossy().to_ascii_uppercase())
+    });
+    if let Err(e) = fs::copy(file, dir.join(&name)) {
+        eprintln!("cannot copy {} to C:\\{}: {}", file.display(), name, e);
+        process::exit(2);
+    }
+}
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+
+    let mut args = env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut dir = None;
+    let mut copies = Vec::new();
+    let mut expects = Vec::new();
+    let mut dosbox = "dosbox-x".to_string();
+    let mut tsr = DEFAULT_TSR.to_string();
+    let mut timeout = DEFAULT_TIMEOUT;
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+        let Some(value) = args.next() else {
+            eprintln!("{} needs a value\n{}", arg, USAGE);
+            process::exit(2);
+        };
+        match arg.as_str() {
+            "--dir" => dir = Some(PathBuf::from(value)),
+            "--copy" => copies.push(PathBuf::from(value)),
+            "--expect" => match value.split_once('=') {
+                Some((name, file)) => expects.push((name.to_string(), PathBuf::from(file))),
+                None => {
+                    eprintln!("--expect takes <DOS name>=<file>\n{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            "--dosbox" => dosbox = value,
+            "--tsr" => tsr = value,
+            "--timeout" => match value.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => timeout = Duration::from_secs(seconds),
+                _ => {
+                    eprintln!("bad value '{}' for --timeout\n{}", value, USAGE);
+                    process::exit(2);
+                }
+            },
+            _ => {
+                eprintln!("unknown option {}\n{}", arg, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+    let Some(script) = positional.first() else {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    };
+    let options = Options::new(positional.get(1).map_or(DEFAULT_XTRIEVE_ADDR, |s| s.as_str()));
+
+    // C: with the TSR, the script and the programs it runs
+    let dir = dir.unwrap_or_else(|| env::temp_dir().join(format!("dosbox-run-{}", process::id())));
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("cannot create {}: {}", dir.display(), e);
+        process::exit(2);
+    }
+    copy_in(Path::new(&tsr), &dir, Some("BTRSERL.EXE"));
+    copy_in(Path::new(script), &dir, Some(SCRIPT_NAME));
+    for file in &copies {
+        copy_in(file, &dir, None);
+    }
+    for (name, _) in &expects {
+        if let Some(stale) = find_dos_file(&dir, name) {
+            let _ = fs::remove_file(stale);
+        }
+    }
+
+    // The bridge DOSBox-X dials into
+    let (listener, port) = match TcpListener::bind("127.0.0.1:0").and_then(|l| {
+        let port = l.local_addr()?.port();
+        Ok((l, port))
+    }) {
+        Ok(bound) => bound,
+        Err(e) => {
+            eprintln!("cannot listen: {}", e);
+            process::exit(1);
+        }
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let options = options.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map_or_else(|_| "dosbox".to_string(), |a| a.to_string());
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                info!(session = %peer, "DOSBox-X connected");
+                serve(peer, std::io::BufReader::new(&stream), std::io::BufWriter::new(&stream), &options);
+            });
+        }
+    });
+
+    let conf = dir.join("dosbox-run.conf");
+    if let Err(e) = fs::write(&conf, dosbox_conf(port, &dir)) {
+        eprintln!("cannot write {}: {}", conf.display(), e);
+        process::exit(2);
+    }
+    info!(dir = %dir.display(), port, "starting {}", dosbox);
+    let mut child = match Command::new(&dosbox).arg("-conf").arg(&conf).args(["-fastlaunch", "-exit"]).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!(error = %e, "cannot start {}", dosbox);
+            return ExitCode::FAILURE;
+        }
+    };
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                info!(%status, elapsed = ?started.elapsed(), "DOSBox-X exited");
+                break;
+            }
+            Ok(None) if started.elapsed() < timeout => thread::sleep(Duration::from_millis(200)),
+            Ok(None) => {
+                error!(?timeout, "DOSBox-X still running, killing it");
+                let _ = child.kill();
+                let _ = child.wait();
+                return ExitCode::FAILURE;
+            }
+            Err(e) => {
+                error!(error = %e, "cannot wait for DOSBox-X");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let mut passed = true;
+    for (name, expected_file) in &expects {
+        let actual = find_dos_file(&dir, name).and_then(|path| fs::read(path).ok());
+        let expected = match fs::read(expected_file) {
+            Ok(expected) => expected,
+            Err(e) => {
+                error!(file = %expected_file.display(), error = %e, "cannot read expected output");
+                passed = false;
+                continue;
+            }
+        };
+        match actual {
+            Some(actual) if same_text(&actual, &expected) => info!(file = %name, "matches"),
+            Some(_) => {
+                warn!(file = %name, expected = %expected_file.display(), "differs");
+                passed = false;
+            }
+            None => {
+                warn!(file = %name, "not written");
+                passed = false;
+            }
+        }
+    }
+    if passed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conf_and_compare() {
+        let conf = dosbox_conf(40123, Path::new("/tmp/run"));
+        assert!(conf.contains("serial1 = nullmodem server:127.0.0.1 port:40123\n"));
+        assert!(conf.contains("mount c \"/tmp/run\"\nc:\nBTRSERL\ncall XTTEST.BAT\nexit\n"));
+
+        assert!(same_text(b"STATUS 0\r\nCOUNT 3\r\n\r\n\x1a", b"STATUS 0\nCOUNT 3\n"));
+        assert!(!same_text(b"STATUS 0\r\n", b"STATUS 4\n"));
+    }
+}
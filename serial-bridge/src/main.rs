@@ -1,292 +1,324 @@
-// Serial-to-Xtrieve Bridge (Protocol-Aware)
-// Parses Xtrieve protocol to detect packet boundaries
-//
-// Request:  [op:2][pos:128][dlen:4][data:N][klen:2][key:N][knum:2][plen:2][path:N][lock:2]
-// Response: [status:2][pos:128][dlen:4][data:N][klen:2][key:N]
-
-use std::env;
-use std::io::{Read, Write, BufReader, BufWriter};
-use std::net::{TcpListener, TcpStream};
-use std::thread;
-
-const DEFAULT_LISTEN_PORT: u16 = 7418;
-const DEFAULT_XTRIEVE_ADDR: &str = "127.0.0.1:7419";
-const POS_BLOCK_SIZE: usize = 128;
-
-fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<()> {
-    let mut total = 0;
-    while total < buf.len() {
-        let n = reader.read(&mut buf[total..])?;
-        if n == 0 {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::UnexpectedEof,
-                "connection closed",
-            ));
-        }
-        total += n;
-    }
-    Ok(())
-}
-
-fn read_u16<R: Read>(reader: &mut R) -> std::io::Result<u16> {
-    let mut buf = [0u8; 2];
-    read_exact(reader, &mut buf)?;
-    Ok(u16::from_le_bytes(buf))
-}
-
-fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
-    let mut buf = [0u8; 4];
-    read_exact(reader, &mut buf)?;
-    Ok(u32::from_le_bytes(buf))
-}
-
-/// Wait for sync marker 0xBB 0xBB
-fn wait_for_sync<R: Read>(reader: &mut R) -> std::io::Result<()> {
-    let mut buf = [0u8; 1];
-    let mut found_first = false;
-
-    loop {
-        read_exact(reader, &mut buf)?;
-        if buf[0] == 0xBB {
-            if found_first {
-                // Got 0xBB 0xBB - sync found!
-                return Ok(());
-            }
-            found_first = true;
-        } else {
-            if found_first {
-                println!("    [sync] skipping 0x{:02X} after first 0xBB", buf[0]);
-            } else if buf[0] != 0xFF && buf[0] != 0x00 {
-                println!("    [sync] skipping garbage byte 0x{:02X}", buf[0]);
-            }
-            found_first = false;
-        }
-    }
-}
-
-/// Read a complete Xtrieve request from DOS
-/// Returns the serialized request bytes
-fn read_request<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
-    let mut request = Vec::with_capacity(512);
-
-    // Wait for sync marker first
-    wait_for_sync(reader)?;
-    println!("    [sync] got sync marker");
-
-    // Operation code (2 bytes)
-    let op = read_u16(reader)?;
-    request.extend_from_slice(&op.to_le_bytes());
-    println!("    op={}", op);
-
-    // Position block (128 bytes)
-    let mut pos_block = [0u8; POS_BLOCK_SIZE];
-    read_exact(reader, &mut pos_block)?;
-    request.extend_from_slice(&pos_block);
-
-    // Data length (4 bytes) + data
-    let data_len = read_u32(reader)?;
-    request.extend_from_slice(&data_len.to_le_bytes());
-    println!("    data_len={}", data_len);
-
-    if data_len > 0 {
-        let mut data = vec![0u8; data_len as usize];
-        read_exact(reader, &mut data)?;
-        request.extend_from_slice(&data);
-    }
-
-    // Key length (2 bytes) + key
-    let key_len = read_u16(reader)?;
-    request.extend_from_slice(&key_len.to_le_bytes());
-    println!("    key_len={}", key_len);
-
-    if key_len > 0 {
-        let mut key = vec![0u8; key_len as usize];
-        read_exact(reader, &mut key)?;
-        request.extend_from_slice(&key);
-    }
-
-    // Key number (2 bytes)
-    let key_num = read_u16(reader)?;
-    request.extend_from_slice(&key_num.to_le_bytes());
-
-    // Path length (2 bytes) + path
-    let path_len = read_u16(reader)?;
-    request.extend_from_slice(&path_len.to_le_bytes());
-    println!("    path_len={}", path_len);
-
-    if path_len > 0 {
-        let mut path = vec![0u8; path_len as usize];
-        read_exact(reader, &mut path)?;
-        request.extend_from_slice(&path);
-        if let Ok(s) = std::str::from_utf8(&path) {
-            println!("    path={}", s);
-        }
-    }
-
-    // Lock bias (2 bytes)
-    let lock = read_u16(reader)?;
-    request.extend_from_slice(&lock.to_le_bytes());
-
-    println!("    total request size: {} bytes", request.len());
-    Ok(request)
-}
-
-/// Read a complete Xtrieve response from server
-/// Returns the serialized response bytes
-fn read_response<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
-    let mut response = Vec::with_capacity(512);
-
-    // Status code (2 bytes)
-    let status = read_u16(reader)?;
-    response.extend_from_slice(&status.to_le_bytes());
-    println!("    status={}", status);
-
-    // Position block (128 bytes)
-    let mut pos_block = [0u8; POS_BLOCK_SIZE];
-    read_exact(reader, &mut pos_block)?;
-    response.extend_from_slice(&pos_block);
-
-    // Data length (4 bytes) + data
-    let data_len = read_u32(reader)?;
-    response.extend_from_slice(&data_len.to_le_bytes());
-    println!("    resp_data_len={}", data_len);
-
-    if data_len > 0 {
-        let mut data = vec![0u8; data_len as usize];
-        read_exact(reader, &mut data)?;
-        response.extend_from_slice(&data);
-    }
-
-    // Key length (2 bytes) + key
-    let key_len = read_u16(reader)?;
-    response.extend_from_slice(&key_len.to_le_bytes());
-
-    if key_len > 0 {
-        let mut key = vec![0u8; key_len as usize];
-        read_exact(reader, &mut key)?;
-        response.extend_from_slice(&key);
-    }
-
-    println!("    total response size: {} bytes", response.len());
-    Ok(response)
-}
-
-fn handle_client(dos_stream: TcpStream, xtrieve_addr: &str) {
-    let peer = dos_stream.peer_addr().ok();
-    println!("[+] DOS client connected: {:?}", peer);
-
-    // Connect to Xtrieve server
-    let xtrieve_stream = match TcpStream::connect(xtrieve_addr) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("[-] Failed to connect to Xtrieve: {}", e);
-            return;
-        }
-    };
-    println!("[+] Connected to Xtrieve at {}", xtrieve_addr);
-
-    let mut dos_reader = BufReader::new(&dos_stream);
-    let mut dos_writer = BufWriter::new(&dos_stream);
-    let mut xtrieve_reader = BufReader::new(&xtrieve_stream);
-    let mut xtrieve_writer = BufWriter::new(&xtrieve_stream);
-
-    let mut request_count = 0u64;
-
-    loop {
-        // Read complete request from DOS
-        println!("\n[>] Reading request #{}...", request_count + 1);
-        let request = match read_request(&mut dos_reader) {
-            Ok(r) => r,
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                    println!("[*] DOS client disconnected");
-                } else {
-                    eprintln!("[-] Error reading request: {}", e);
-                }
-                break;
-            }
-        };
-
-        // Forward to Xtrieve
-        println!("[>] Forwarding {} bytes to Xtrieve", request.len());
-        if let Err(e) = xtrieve_writer.write_all(&request) {
-            eprintln!("[-] Error writing to Xtrieve: {}", e);
-            break;
-        }
-        if let Err(e) = xtrieve_writer.flush() {
-            eprintln!("[-] Error flushing to Xtrieve: {}", e);
-            break;
-        }
-
-        // Read complete response from Xtrieve
-        println!("[<] Reading response from Xtrieve...");
-        let response = match read_response(&mut xtrieve_reader) {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("[-] Error reading response: {}", e);
-                break;
-            }
-        };
-
-        // Forward to DOS
-        println!("[<] Forwarding {} bytes to DOS", response.len());
-        if let Err(e) = dos_writer.write_all(&response) {
-            eprintln!("[-] Error writing to DOS: {}", e);
-            break;
-        }
-        if let Err(e) = dos_writer.flush() {
-            eprintln!("[-] Error flushing to DOS: {}", e);
-            break;
-        }
-
-        request_count += 1;
-        println!("[*] Request #{} complete", request_count);
-    }
-
-    println!("[-] Session ended: {} requests processed", request_count);
-}
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    let listen_port: u16 = args.get(1)
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(DEFAULT_LISTEN_PORT);
-
-    let xtrieve_addr = args.get(2)
-        .map(|s| s.as_str())
-        .unwrap_or(DEFAULT_XTRIEVE_ADDR);
-
-    println!("===========================================");
-    println!("  Xtrieve Serial Bridge (Protocol-Aware)");
-    println!("===========================================");
-    println!("Listening on port {} for DOSBox-X", listen_port);
-    println!("Forwarding to Xtrieve at {}", xtrieve_addr);
-    println!();
-    println!("Protocol:");
-    println!("  Request:  [op:2][pos:128][dlen:4][data][klen:2][key][knum:2][plen:2][path][lock:2]");
-    println!("  Response: [status:2][pos:128][dlen:4][data][klen:2][key]");
-    println!();
-    println!("DOSBox-X config:");
-    println!("  serial1=nullmodem server:127.0.0.1 port:{}", listen_port);
-    println!();
-
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", listen_port))
-        .expect("Failed to bind listener");
-
-    println!("[*] Waiting for DOS connections...\n");
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(s) => {
-                let addr = xtrieve_addr.to_string();
-                thread::spawn(move || {
-                    handle_client(s, &addr);
-                });
-            }
-            Err(e) => {
-                eprintln!("[-] Accept error: {}", e);
-            }
-        }
-    }
-}
+// Serial-to-Xtrieve Bridge
+//
+// Front-end for the xtrieve-bridge relay: the DOS side is either
+// DOSBox-X's nullmodem over TCP (default) or a real serial device cabled
+// to a DOS machine (--device). Framing, retransmission, byte stuffing,
+// path translation and the Version/Stat cache live in the library.
+//
+// Logging goes through tracing; RUST_LOG=debug shows every request.
+
+use std::env;
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+use xtrieve_bridge::escape::Escape;
+use xtrieve_bridge::paths::PathMap;
+use xtrieve_bridge::{serve, session, Options};
+
+const DEFAULT_LISTEN_PORT: u16 = 7418;
+const DEFAULT_XTRIEVE_ADDR: &str = "127.0.0.1:7419";
+/// BTRSERL.EXE drives COM1 at 115200 8N1
+const DEFAULT_BAUD: u32 = 115_200;
+/// Reads from the DOS side wake up this often, so a frame that stops
+/// halfway is noticed
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "\
+usage: serial-bridge [--escape <mode>] [--paths <file>] [--cache <seconds>]
+                     [--frame-timeout <seconds>] [--hex] [--stats <seconds>]
+                     [listen_port] [xtrieve_addr]
+       serial-bridge --device <path> [--baud <n>] [--parity none|odd|even]
+                     [--data-bits 5|6|7|8] [--stop-bits 1|2]
+                     [--flow none|hardware|software]
+                     [--escape <mode>] [--paths <file>] [--cache <seconds>]
+                     [--frame-timeout <seconds>] [--hex] [--stats <seconds>]
+                     [xtrieve_addr]
+
+  --escape <mode>    byte stuffing on the DOS link: none, xonxoff (keep
+                     XON/XOFF off the line) or 7bit (also no high bits)
+  --paths <file>     rewrite DOS file paths by the rules in <file>
+  --cache <seconds>  answer repeated Version and Stat calls from memory
+  --frame-timeout <seconds>
+                     drop a frame the line goes silent in for this long
+                     (default 5, 0 waits forever)
+  --hex              log every frame as a hex dump
+  --stats <seconds>  log each session's counters this often
+  RUST_LOG=debug     log every request and response";
+
+/// Line settings for a real serial port
+#[derive(Debug, Clone, PartialEq)]
+struct SerialConfig {
+    device: String,
+    baud: u32,
+    parity: Parity,
+    data_bits: DataBits,
+    stop_bits: StopBits,
+    flow: FlowControl,
+}
+
+impl SerialConfig {
+    fn new(device: String) -> Self {
+        SerialConfig {
+            device,
+            baud: DEFAULT_BAUD,
+            parity: Parity::None,
+            data_bits: DataBits::Eight,
+            stop_bits: StopBits::One,
+            flow: FlowControl::None,
+        }
+    }
+
+    /// Apply one `--option value` pair
+    fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let bad = || format!("bad value '{}' for {}", value, option);
+        match option {
+            "--baud" => self.baud = value.parse().ok().filter(|&b| b > 0).ok_or_else(bad)?,
+            "--parity" => {
+                self.parity = match value {
+                    "none" | "n" => Parity::None,
+                    "odd" | "o" => Parity::Odd,
+                    "even" | "e" => Parity::Even,
+                    _ => return Err(bad()),
+                }
+            }
+            "--data-bits" => {
+                self.data_bits = match value {
+                    "5" => DataBits::Five,
+                    "6" => DataBits::Six,
+                    "7" => DataBits::Seven,
+                    "8" => DataBits::Eight,
+                    _ => return Err(bad()),
+                }
+            }
+            "--stop-bits" => {
+                self.stop_bits = match value {
+                    "1" => StopBits::One,
+                    "2" => StopBits::Two,
+                    _ => return Err(bad()),
+                }
+            }
+            "--flow" => {
+                self.flow = match value {
+                    "none" => FlowControl::None,
+                    "hardware" | "rts/cts" => FlowControl::Hardware,
+                    "software" | "xon/xoff" => FlowControl::Software,
+                    _ => return Err(bad()),
+                }
+            }
+            _ => return Err(format!("unknown option {}", option)),
+        }
+        Ok(())
+    }
+
+    /// Short form for the banner, e.g. `115200 8N1, no flow control`
+    fn describe(&self) -> String {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        let flow = match self.flow {
+            FlowControl::None => "no flow control",
+            FlowControl::Hardware => "RTS/CTS",
+            FlowControl::Software => "XON/XOFF",
+        };
+        format!("{} {}{}{}, {}", self.baud, u8::from(self.data_bits), parity, u8::from(self.stop_bits), flow)
+    }
+}
+
+fn handle_client(dos_stream: TcpStream, options: &Options) {
+    let peer = dos_stream.peer_addr().map_or_else(|_| "unknown".to_string(), |a| a.to_string());
+    info!(session = %peer, "DOS client connected");
+    if let Err(e) = dos_stream.set_read_timeout(Some(READ_TIMEOUT)) {
+        warn!(session = %peer, error = %e, "cannot set read timeout");
+    }
+    serve(peer, BufReader::new(&dos_stream), BufWriter::new(&dos_stream), options);
+}
+
+/// Bridge a serial device until the port fails
+fn run_serial(config: &SerialConfig, options: &Options) {
+    let port = serialport::new(&config.device, config.baud)
+        .parity(config.parity)
+        .data_bits(config.data_bits)
+        .stop_bits(config.stop_bits)
+        .flow_control(config.flow)
+        .timeout(READ_TIMEOUT)
+        .open();
+    let mut port = match port {
+        Ok(port) => port,
+        Err(e) => {
+            error!(device = %config.device, error = %e, "cannot open serial device");
+            process::exit(1);
+        }
+    };
+    // Null-modem cables carry DTR to the DOS side's DSR and DCD
+    if let Err(e) = port.write_data_terminal_ready(true) {
+        warn!(device = %config.device, error = %e, "cannot raise DTR");
+    }
+    info!(device = %config.device, line = %config.describe(), "serial device open");
+
+    let writer = match port.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => {
+            error!(device = %config.device, error = %e, "cannot share serial device");
+            process::exit(1);
+        }
+    };
+    serve(config.device.clone(), BufReader::new(&mut port), BufWriter::new(writer), options);
+}
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+
+    let mut args = env::args().skip(1);
+    let mut positional = Vec::new();
+    let mut serial: Option<SerialConfig> = None;
+    let mut serial_options = Vec::new();
+    let mut options = Options::new(DEFAULT_XTRIEVE_ADDR);
+    let mut stats_interval = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--hex" => options.hex = true,
+            _ if arg.starts_with("--") => {
+                let Some(value) = args.next() else {
+                    eprintln!("{} needs a value\n{}", arg, USAGE);
+                    process::exit(2);
+                };
+                match arg.as_str() {
+                    "--device" => serial = Some(SerialConfig::new(value)),
+                    "--escape" => match Escape::parse(&value) {
+                        Some(mode) => options.escape = mode,
+                        None => {
+                            eprintln!("bad value '{}' for --escape\n{}", value, USAGE);
+                            process::exit(2);
+                        }
+                    },
+                    "--paths" => match PathMap::load(&value) {
+                        Ok(map) => {
+                            info!(rules = map.len(), "path translation on");
+                            options.paths = Arc::new(map);
+                        }
+                        Err(e) => {
+                            eprintln!("{}\n{}", e, USAGE);
+                            process::exit(2);
+                        }
+                    },
+                    "--frame-timeout" => match value.parse::<u64>() {
+                        Ok(0) => options.frame_timeout = None,
+                        Ok(seconds) => options.frame_timeout = Some(Duration::from_secs(seconds)),
+                        Err(_) => {
+                            eprintln!("bad value '{}' for {}\n{}", value, arg, USAGE);
+                            process::exit(2);
+                        }
+                    },
+                    "--stats" | "--cache" => match value.parse::<u64>() {
+                        Ok(seconds) if seconds > 0 => {
+                            let interval = Some(Duration::from_secs(seconds));
+                            if arg == "--stats" {
+                                stats_interval = interval;
+                            } else {
+                                options.cache_ttl = interval;
+                            }
+                        }
+                        _ => {
+                            eprintln!("bad value '{}' for {}\n{}", value, arg, USAGE);
+                            process::exit(2);
+                        }
+                    },
+                    _ => serial_options.push((arg, value)),
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if let Some(interval) = stats_interval {
+        session::report_every(interval);
+    }
+
+    if let Some(config) = &mut serial {
+        for (option, value) in &serial_options {
+            if let Err(e) = config.set(option, value) {
+                eprintln!("{}\n{}", e, USAGE);
+                process::exit(2);
+            }
+        }
+        if let Some(addr) = positional.first() {
+            options.xtrieve_addr = addr.clone();
+        }
+        info!(device = %config.device, line = %config.describe(), escape = ?options.escape, xtrieve = %options.xtrieve_addr, "Xtrieve serial bridge");
+        run_serial(config, &options);
+        return;
+    }
+    if let Some((option, _)) = serial_options.first() {
+        eprintln!("{} only applies with --device\n{}", option, USAGE);
+        process::exit(2);
+    }
+
+    let listen_port: u16 = positional.first()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_LISTEN_PORT);
+
+    if let Some(addr) = positional.get(1) {
+        options.xtrieve_addr = addr.clone();
+    }
+
+    info!(listen_port, escape = ?options.escape, xtrieve = %options.xtrieve_addr, "Xtrieve serial bridge");
+    info!("DOSBox-X config: serial1=nullmodem server:127.0.0.1 port:{}", listen_port);
+
+    let listener = match TcpListener::bind(format!("0.0.0.0:{}", listen_port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(listen_port, error = %e, "cannot listen");
+            process::exit(1);
+        }
+    };
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(s) => {
+                let options = options.clone();
+                thread::spawn(move || {
+                    handle_client(s, &options);
+                });
+            }
+            Err(e) => {
+                warn!(error = %e, "accept failed");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_config() {
+        let mut config = SerialConfig::new("/dev/ttyUSB0".into());
+        assert_eq!(config.describe(), "115200 8N1, no flow control");
+        config.set("--baud", "9600").unwrap();
+        config.set("--parity", "even").unwrap();
+        config.set("--data-bits", "7").unwrap();
+        config.set("--stop-bits", "2").unwrap();
+        config.set("--flow", "hardware").unwrap();
+        assert_eq!(config.describe(), "9600 7E2, RTS/CTS");
+
+        assert!(config.set("--baud", "0").is_err());
+        assert!(config.set("--parity", "mark").is_err());
+        assert!(config.set("--speed", "1").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+//! xtbench - Xtrieve benchmark
+//!
+//! ```text
+//! xtbench [--server <addr>] [--workload insert|get|scan|mixed] [--records <n>]
+//!         [--ops <n>] [--sessions <n>] [--scan-length <n>] [--writes <percent>]
+//!         [--record-size <n>] [--page-size <n>] [--file <name>] [--seed <n>]
+//! ```
+//!
+//! Creates a file keyed on a 4-byte record number and runs one workload
+//! on it from `--sessions` threads, each with its own connection:
+//!
+//! - `insert`: the sessions insert `--records` records in key order
+//! - `get`: GetEqual on random keys
+//! - `scan`: GetGreaterOrEqual on a random key, then GetNext
+//!   `--scan-length` - 1 times
+//! - `mixed`: `--writes` percent Inserts of new keys, the rest GetEqual
+//!
+//! The read workloads load `--records` records first, untimed, and then
+//! share `--ops` operations between the sessions. `--server` takes any
+//! client address, so `file://<dir>` measures the engine in this process
+//! and `host:port` a daemon. The file must not exist yet.
+
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use xtrieve_client::{BtrieveError, BtrieveFile, FileBuilder, KeyBuilder, StatusCode, XtrieveClient};
+
+/// Xtrieve benchmark with canned workloads
+#[derive(Parser, Debug, Clone)]
+#[command(name = "xtbench")]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Server to measure (host:port, unix://, grpc:// or file://<data dir>)
+    #[arg(short, long, default_value = "127.0.0.1:7419")]
+    server: String,
+
+    /// Workload to run
+    #[arg(short, long, value_enum, default_value_t = Workload::Mixed)]
+    workload: Workload,
+
+    /// Records inserted (insert) or loaded before the run (the others)
+    #[arg(long, default_value_t = 10_000)]
+    records: u32,
+
+    /// Operations shared between the sessions (ignored by insert)
+    #[arg(long, default_value_t = 10_000)]
+    ops: u64,
+
+    /// Concurrent sessions
+    #[arg(long, default_value_t = 1)]
+    sessions: u32,
+
+    /// Records read by each scan
+    #[arg(long, default_value_t = 50)]
+    scan_length: u32,
+
+    /// Percentage of mixed operations that insert
+    #[arg(long, default_value_t = 20)]
+    writes: u8,
+
+    /// Record length in bytes (at least 4)
+    #[arg(long, default_value_t = 100)]
+    record_size: u16,
+
+    /// Page size of the file
+    #[arg(long, default_value_t = 4096)]
+    page_size: u16,
+
+    /// File to create on the server
+    #[arg(long, default_value = "XTBENCH.DAT")]
+    file: String,
+
+    /// Seed for the random keys (each session adds its number)
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Workload {
+    Insert,
+    Get,
+    Scan,
+    Mixed,
+}
+
+/// xorshift64*, so runs with the same seed ask for the same keys
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform enough below `n` (which must not be 0)
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Record `id`: the key, then filler
+fn record(id: u32, size: u16) -> Vec<u8> {
+    let mut data: Vec<u8> = (0..size).map(|n| b'a' + ((id as u16).wrapping_add(n) % 26) as u8).collect();
+    data[..4].copy_from_slice(&id.to_le_bytes());
+    data
+}
+
+/// What one session measured
+#[derive(Debug, Default)]
+struct Measured {
+    latencies: Vec<Duration>,
+    /// Operations answered with a Btrieve error status
+    errors: u64,
+    /// Records returned, scans counting every record they read
+    records_read: u64,
+    /// When the session started and finished its operations
+    span: Option<(Instant, Instant)>,
+}
+
+impl Measured {
+    /// Time one operation; Btrieve statuses count as errors, anything else
+    /// (a lost connection) stops the run
+    fn time(&mut self, operation: impl FnOnce() -> Result<u64, BtrieveError>) -> Result<()> {
+        let started = Instant::now();
+        let outcome = operation();
+        self.latencies.push(started.elapsed());
+        match outcome {
+            Ok(read) => self.records_read += read,
+            Err(BtrieveError::Status(_) | BtrieveError::Detailed { .. }) => self.errors += 1,
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+}
+
+/// The whole run
+#[derive(Debug, Default)]
+struct Report {
+    elapsed: Duration,
+    measured: Measured,
+}
+
+fn open(args: &Args) -> Result<BtrieveFile> {
+    let client = XtrieveClient::connect(&args.server).with_context(|| format!("cannot connect to {}", args.server))?;
+    BtrieveFile::open(client, &args.file, 0).with_context(|| format!("cannot open {}", args.file))
+}
+
+fn create(args: &Args) -> Result<()> {
+    if args.record_size < 4 {
+        bail!("--record-size must be at least 4");
+    }
+    let mut client =
+        XtrieveClient::connect(&args.server).with_context(|| format!("cannot connect to {}", args.server))?;
+    match FileBuilder::new(args.record_size)
+        .page_size(args.page_size)
+        .key(KeyBuilder::unsigned(0, 4))
+        .create(&mut client, &args.file)
+    {
+        Err(BtrieveError::Status(StatusCode::FileAlreadyExists | StatusCode::FileInUse)) => {
+            bail!("{} already exists; remove it or pass --file", args.file)
+        }
+        result => result.with_context(|| format!("cannot create {}", args.file)),
+    }
+}
+
+/// Insert records `0..records`, untimed
+fn load(args: &Args) -> Result<()> {
+    let mut file = open(args)?;
+    let ids: Vec<u32> = (0..args.records).collect();
+    for chunk in ids.chunks(1000) {
+        let records: Vec<Vec<u8>> = chunk.iter().map(|&id| record(id, args.record_size)).collect();
+        let report = file.insert_many(&records)?;
+        if let Some((_, e)) = report.failures.first() {
+            bail!("loading failed: {}", e);
+        }
+    }
+    file.close()?;
+    Ok(())
+}
+
+/// Run one session's share of the workload, starting with the others
+fn session(args: &Args, number: u32, start: &Barrier) -> Result<Measured> {
+    let opened = open(args);
+    start.wait();
+    let started = Instant::now();
+    let mut file = opened?;
+
+    let sessions = args.sessions as u64;
+    let ops = args.ops / sessions + u64::from((number as u64) < args.ops % sessions);
+    let records = args.records.max(1) as u64;
+    let mut rng = Rng::new(args.seed.wrapping_add(number as u64));
+    let mut measured = Measured::default();
+
+    match args.workload {
+        Workload::Insert => {
+            let first = args.records as u64 * number as u64 / sessions;
+            let last = args.records as u64 * (number as u64 + 1) / sessions;
+            for id in first..last {
+                let data = record(id as u32, args.record_size);
+                measured.time(|| file.insert(&data).map(|_| 0))?;
+            }
+        }
+        Workload::Get => {
+            for _ in 0..ops {
+                let key = (rng.below(records) as u32).to_le_bytes();
+                measured.time(|| file.get_equal(&key).map(|_| 1))?;
+            }
+        }
+        Workload::Scan => {
+            for _ in 0..ops {
+                let key = (rng.below(records) as u32).to_le_bytes();
+                measured.time(|| {
+                    file.get_greater_or_equal(&key)?;
+                    let mut read = 1;
+                    while read < args.scan_length as u64 {
+                        match file.get_next() {
+                            Ok(_) => read += 1,
+                            Err(BtrieveError::Status(StatusCode::EndOfFile)) => break,
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    Ok(read)
+                })?;
+            }
+        }
+        Workload::Mixed => {
+            // New keys start after the loaded ones, interleaved by session
+            let mut next_id = args.records as u64 + number as u64;
+            for _ in 0..ops {
+                if rng.below(100) < args.writes as u64 {
+                    let data = record(next_id as u32, args.record_size);
+                    next_id += sessions;
+                    measured.time(|| file.insert(&data).map(|_| 0))?;
+                } else {
+                    let key = (rng.below(records) as u32).to_le_bytes();
+                    measured.time(|| file.get_equal(&key).map(|_| 1))?;
+                }
+            }
+        }
+    }
+    measured.span = Some((started, Instant::now()));
+    file.close()?;
+    Ok(measured)
+}
+
+/// Create and load the file, then time the workload across every session
+fn bench(args: &Args) -> Result<Report> {
+    if args.sessions == 0 {
+        bail!("--sessions must be at least 1");
+    }
+    create(args)?;
+    if args.workload != Workload::Insert {
+        let started = Instant::now();
+        load(args)?;
+        eprintln!("loaded {} records in {:.2?}", args.records, started.elapsed());
+    }
+
+    let start = Barrier::new(args.sessions as usize + 1);
+    let mut report = Report::default();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..args.sessions)
+            .map(|number| {
+                let start = &start;
+                scope.spawn(move || session(args, number, start))
+            })
+            .collect();
+        start.wait();
+        let mut outcome = Ok(());
+        let mut spans = Vec::new();
+        for worker in workers {
+            match worker.join().expect("session thread panicked") {
+                Ok(measured) => {
+                    report.measured.latencies.extend(measured.latencies);
+                    report.measured.errors += measured.errors;
+                    report.measured.records_read += measured.records_read;
+                    spans.extend(measured.span);
+                }
+                Err(e) => outcome = Err(e),
+            }
+        }
+        // From the first session starting to the last one finishing
+        if let (Some(first), Some(last)) = (spans.iter().map(|s| s.0).min(), spans.iter().map(|s| s.1).max()) {
+            report.elapsed = last - first;
+        }
+        outcome
+    })?;
+    report.measured.latencies.sort_unstable();
+    Ok(report)
+}
+
+/// The latency below which `fraction` of the sorted samples fall
+fn percentile(sorted: &[Duration], fraction: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn format_report(args: &Args, report: &Report) -> String {
+    let measured = &report.measured;
+    let seconds = report.elapsed.as_secs_f64().max(f64::EPSILON);
+    let ops = measured.latencies.len();
+    let workload = args.workload.to_possible_value().expect("no skipped workloads");
+    let mut out = format!(
+        "{} on {}: {} sessions, {} operations in {:.2?}\n",
+        workload.get_name(),
+        args.server,
+        args.sessions,
+        ops,
+        report.elapsed
+    );
+    out += &format!("  throughput  {:.0} ops/s", ops as f64 / seconds);
+    if args.workload == Workload::Scan {
+        out += &format!(", {:.0} records/s", measured.records_read as f64 / seconds);
+    }
+    out += "\n";
+    out += &format!(
+        "  latency     p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}\n",
+        percentile(&measured.latencies, 0.50),
+        percentile(&measured.latencies, 0.90),
+        percentile(&measured.latencies, 0.99),
+        measured.latencies.last().copied().unwrap_or_default()
+    );
+    if measured.errors > 0 {
+        out += &format!("  errors      {} operations returned a Btrieve status\n", measured.errors);
+    }
+    out
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let report = bench(&args)?;
+    print!("{}", format_report(&args, &report));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_and_records() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let keys: Vec<u64> = (0..100).map(|_| a.below(10)).collect();
+        assert!(keys.iter().all(|&k| k < 10));
+        assert_eq!(keys, (0..100).map(|_| b.below(10)).collect::<Vec<_>>());
+        assert_ne!(Rng::new(8).next(), Rng::new(7).next());
+
+        let data = record(0x0102, 8);
+        assert_eq!(data, [0x02, 0x01, 0, 0, b'c', b'd', b'e', b'f']);
+        let samples: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 0.9), Duration::from_millis(9));
+    }
+
+    #[test]
+    fn test_embedded_run() {
+        let dir = std::env::temp_dir().join(format!("xtbench_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut args = Args::parse_from(["xtbench", "--records", "40", "--ops", "25", "--sessions", "2"]);
+        args.server = format!("file://{}", dir.display());
+
+        let report = bench(&args).unwrap();
+        assert_eq!(report.measured.latencies.len(), 25);
+        assert!(format_report(&args, &report).starts_with("mixed on file://"));
+        assert!(bench(&args).unwrap_err().to_string().contains("already exists"));
+
+        args.workload = Workload::Insert;
+        args.file = "INSERT.DAT".into();
+        assert_eq!(bench(&args).unwrap().measured.latencies.len(), 40);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+//! xtreplay - replay a request trace against a server
+//!
+//! ```text
+//! xtreplay <trace> [--server <addr>] [--speed <x>] [--data] [--show <n>]
+//! ```
+//!
+//! Plays back a trace written by `xtrieved --trace` against another
+//! daemon, or an engine in this process with `file://<data dir>`, and
+//! compares each status (and with `--data` each data and key buffer)
+//! with what the traced server answered. The target's data directory
+//! should hold the files as they were when the trace started.
+//!
+//! Requests run one at a time in the order they were traced, each
+//! traced session on its own connection. `--speed` scales the gaps
+//! between requests: 1 keeps the captured pacing, 2 halves it, and 0
+//! sends every request as soon as the last one returned. The exit status
+//! is 1 when any response differs.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use xtrieve_client::{BtrieveRequest, XtrieveClient};
+use xtrieve_engine::operations::OperationCode;
+use xtrieve_engine::trace::{self, TraceRecord};
+use xtrieve_engine::POSITION_BLOCK_SIZE;
+
+/// Replay an xtrieved request trace and compare the responses
+#[derive(Parser, Debug)]
+#[command(name = "xtreplay")]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Trace file written by `xtrieved --trace`
+    trace: PathBuf,
+
+    /// Server to replay against (host:port, unix://, grpc:// or file://<data dir>)
+    #[arg(short, long, default_value = "127.0.0.1:7419")]
+    server: String,
+
+    /// Pacing relative to the trace (0 = as fast as possible)
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Compare data and key buffers as well as status codes
+    #[arg(long)]
+    data: bool,
+
+    /// Mismatches to print in full
+    #[arg(long, default_value_t = 20)]
+    show: usize,
+}
+
+/// Bytes of a position block that name its file and session; the rest is
+/// cursor state the client hands back unchanged
+const IDENTITY: std::ops::Range<usize> = 64..POSITION_BLOCK_SIZE;
+
+/// Pairs the file and session a traced position block names with the
+/// ones the replay server gave for the same open
+#[derive(Debug, Default)]
+struct PositionMap {
+    blocks: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PositionMap {
+    fn identity(block: &[u8]) -> Option<&[u8]> {
+        let identity = block.get(IDENTITY)?;
+        identity.iter().any(|&b| b != 0).then_some(identity)
+    }
+
+    /// Remember what a traced response's position block became
+    fn learn(&mut self, traced: &[u8], replayed: &[u8]) {
+        if let (Some(traced), Some(replayed)) = (Self::identity(traced), Self::identity(replayed)) {
+            self.blocks.insert(traced.to_vec(), replayed.to_vec());
+        }
+    }
+
+    /// Rewrite a traced request's position block for the replay server
+    fn translate(&self, block: &mut [u8]) {
+        let replayed = Self::identity(block).and_then(|identity| self.blocks.get(identity));
+        if let Some(replayed) = replayed {
+            block[IDENTITY].copy_from_slice(replayed);
+        }
+    }
+}
+
+/// Replay state: a connection per traced session
+struct Replay {
+    server: String,
+    compare_data: bool,
+    clients: HashMap<u64, XtrieveClient>,
+    positions: PositionMap,
+}
+
+impl Replay {
+    /// Send one traced request and describe how its response differs, if it does
+    fn step(&mut self, record: &TraceRecord) -> Result<Option<String>> {
+        let traced = &record.request;
+        let mut position_block = traced.position_block.clone();
+        self.positions.translate(&mut position_block);
+        let request = BtrieveRequest {
+            operation_code: traced.operation_code as u32,
+            position_block,
+            data_buffer: traced.data_buffer.clone(),
+            data_buffer_length: traced.data_buffer.len() as u32,
+            key_buffer: traced.key_buffer.clone(),
+            key_buffer_length: traced.key_buffer.len() as u32,
+            key_number: traced.key_number as i32,
+            file_path: traced.file_path.clone(),
+            open_mode: record.open_mode,
+            lock_bias: traced.lock_bias as u32,
+            client_id: 0,
+            request_id: 0,
+        };
+
+        let client = match self.clients.entry(record.session) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                XtrieveClient::connect(&self.server)
+                    .with_context(|| format!("cannot connect to {}", self.server))?,
+            ),
+        };
+        let response = client
+            .execute(request)
+            .with_context(|| format!("request failed on {}", self.server))?;
+
+        let expected = &record.response;
+        self.positions.learn(&expected.position_block, &response.position_block);
+        let difference = if response.status_code != expected.status_code as u32 {
+            Some(format!("status {}, traced {}", response.status_code, expected.status_code))
+        } else if self.compare_data && response.data_buffer != expected.data_buffer {
+            Some(format!(
+                "data buffer differs ({} bytes, traced {})",
+                response.data_buffer.len(),
+                expected.data_buffer.len()
+            ))
+        } else if self.compare_data && response.key_buffer != expected.key_buffer {
+            Some("key buffer differs".to_string())
+        } else {
+            None
+        };
+        Ok(difference)
+    }
+}
+
+fn operation_name(code: u16) -> String {
+    match OperationCode::from_raw(code as u32) {
+        OperationCode::Unknown => format!("op {}", code),
+        operation => format!("{:?}", operation),
+    }
+}
+
+/// The latency below which `fraction` of the sorted samples fall
+fn percentile(sorted: &[Duration], fraction: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() as f64 * fraction).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+    let mut reader = BufReader::new(
+        File::open(&args.trace).with_context(|| format!("cannot open {}", args.trace.display()))?,
+    );
+    trace::read_header(&mut reader).with_context(|| format!("cannot read {}", args.trace.display()))?;
+
+    let mut replay = Replay {
+        server: args.server.clone(),
+        compare_data: args.data,
+        clients: HashMap::new(),
+        positions: PositionMap::default(),
+    };
+    let mut latencies = Vec::new();
+    let mut mismatches: BTreeMap<String, u64> = BTreeMap::new();
+    let mut traced_elapsed = Duration::ZERO;
+    let started = Instant::now();
+
+    while let Some(record) = TraceRecord::from_reader(&mut reader).context("trace is damaged")? {
+        if args.speed > 0.0 {
+            let due = record.elapsed.div_f64(args.speed);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        traced_elapsed = traced_elapsed.max(record.elapsed);
+
+        let sent = Instant::now();
+        let difference = replay.step(&record)?;
+        latencies.push(sent.elapsed());
+
+        if let Some(difference) = difference {
+            let name = operation_name(record.request.operation_code);
+            let count: u64 = mismatches.values().sum();
+            if (count as usize) < args.show {
+                println!(
+                    "#{} session {} {} {}: {}",
+                    latencies.len(),
+                    record.session,
+                    name,
+                    record.request.file_path,
+                    difference
+                );
+            }
+            *mismatches.entry(name).or_default() += 1;
+        }
+    }
+
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+    println!(
+        "replayed {} requests from {} sessions in {:.2?} ({:.0} ops/s; traced over {:.2?})",
+        latencies.len(),
+        replay.clients.len(),
+        elapsed,
+        latencies.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        traced_elapsed
+    );
+    println!(
+        "latency p50 {:.2?} p99 {:.2?} max {:.2?}",
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default()
+    );
+    if mismatches.is_empty() {
+        println!("all responses match the trace");
+        return Ok(ExitCode::SUCCESS);
+    }
+    println!("{} responses differ from the trace", mismatches.values().sum::<u64>());
+    for (name, count) in &mismatches {
+        println!("  {:<16} {}", name, count);
+    }
+    Ok(ExitCode::FAILURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(path: &str, session: u64, cursor: u8) -> Vec<u8> {
+        let mut block = vec![0u8; POSITION_BLOCK_SIZE];
+        block[0] = cursor;
+        block[64..64 + path.len()].copy_from_slice(path.as_bytes());
+        block[120..].copy_from_slice(&session.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn test_position_map() {
+        let mut positions = PositionMap::default();
+        positions.learn(&block("/srv/a/CUST.DAT", 3, 1), &block("/tmp/b/CUST.DAT", 9, 1));
+        // A block that was never opened during the replay is left alone
+        positions.learn(&[0u8; POSITION_BLOCK_SIZE], &block("/tmp/b/X.DAT", 9, 0));
+
+        let mut request = block("/srv/a/CUST.DAT", 3, 2);
+        positions.translate(&mut request);
+        assert_eq!(request, block("/tmp/b/CUST.DAT", 9, 2));
+
+        let mut unknown = block("/srv/a/ORDERS.DAT", 3, 1);
+        positions.translate(&mut unknown);
+        assert_eq!(unknown, block("/srv/a/ORDERS.DAT", 3, 1));
+        let mut empty = vec![0u8; POSITION_BLOCK_SIZE];
+        positions.translate(&mut empty);
+        assert!(empty.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_micros).collect();
+        assert_eq!(percentile(&samples, 0.50), Duration::from_micros(50));
+        assert_eq!(percentile(&samples, 0.99), Duration::from_micros(99));
+        assert_eq!(percentile(&[], 0.5), Duration::ZERO);
+        assert_eq!(operation_name(5), "GetEqual");
+        assert_eq!(operation_name(200), "op 200");
+    }
+}
@@ -0,0 +1,338 @@
+//! Connection to the Xtrieve daemon, surviving daemon restarts
+//!
+//! When the connection drops, the request in flight is not lost: the bridge
+//! reconnects, reopens every file the DOS session had open (the daemon
+//! forgets them on restart) and sends the request again. Position blocks
+//! carry the file path and cursor, so the DOS side keeps using the ones it
+//! has; the bridge swaps in the session and check the daemon gave the
+//! reopened file. Only when the daemon stays away for RECONNECT_WINDOW does
+//! the DOS side get status 20 (record manager inactive) for that request.
+//!
+//! A request the daemon carried out just before it went away is carried
+//! out again: an insert may then come back with status 5, and an open
+//! transaction is gone.
+//!
+//! Open and Create requests have their DOS path translated on the way
+//! (see paths.rs), and Version/Stat may be answered from the cache.
+
+use std::borrow::Cow;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+
+use crate::cache::ResponseCache;
+use crate::paths::PathMap;
+use crate::protocol::{read_response, POS_BLOCK_SIZE};
+
+/// How long a request waits for the daemon to come back
+const RECONNECT_WINDOW: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+const OP_OPEN: u16 = 0;
+const OP_CLOSE: u16 = 1;
+const OP_CREATE: u16 = 14;
+const OP_RESET: u16 = 28;
+/// Record manager inactive
+const STATUS_INACTIVE: u16 = 20;
+
+/// Position block bytes naming the file (the rest is cursor and session)
+const FILE_NAME: std::ops::Range<usize> = 64..114;
+/// Position block bytes naming the file, the check the daemon stamped at
+/// Open and the session
+const IDENTITY: std::ops::Range<usize> = 64..POS_BLOCK_SIZE;
+/// Position block byte telling apart opens of one file
+const CURSOR_ID: usize = 19;
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+pub struct Backend {
+    addr: String,
+    connection: Option<Connection>,
+    /// Open (or Create) requests of the files the session has open,
+    /// keyed by the identity of their position block
+    opens: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Identities the DOS side may still hold from before a reopen, with
+    /// the ones the daemon gave the same opens since
+    renewed: Vec<(Vec<u8>, Vec<u8>)>,
+    paths: Arc<PathMap>,
+    pub cache: Option<ResponseCache>,
+    pub reconnects: u64,
+}
+
+impl Backend {
+    pub fn new(addr: &str, paths: Arc<PathMap>) -> Self {
+        Backend {
+            addr: addr.to_string(),
+            connection: None,
+            opens: Vec::new(),
+            renewed: Vec::new(),
+            paths,
+            cache: None,
+            reconnects: 0,
+        }
+    }
+
+    /// Connect now rather than on the first request
+    pub fn connect(&mut self) -> io::Result<()> {
+        if self.connection.is_none() {
+            let stream = TcpStream::connect(&self.addr)?;
+            self.connection = Some(Connection {
+                reader: BufReader::new(stream.try_clone()?),
+                writer: BufWriter::new(stream),
+            });
+            info!(addr = %self.addr, "connected to Xtrieve");
+        }
+        Ok(())
+    }
+
+    /// Send a request and read its response, reconnecting and retrying
+    /// while the daemon is away
+    pub fn exchange(&mut self, request: &[u8]) -> Vec<u8> {
+        let translated = self.paths.rewrite(request).map(|(path, translated)| {
+            info!(path = %path, "path translated");
+            translated
+        });
+        let request = translated.as_deref().unwrap_or(request);
+        if let Some(response) = self.cache.as_mut().and_then(|cache| cache.lookup(request)) {
+            debug!("answered from cache");
+            return response;
+        }
+        let started = Instant::now();
+        loop {
+            let had_connection = self.connection.is_some();
+            let result = self.connect().and_then(|()| {
+                if !had_connection && !self.opens.is_empty() {
+                    self.reopen()?;
+                }
+                let request = self.renew(request);
+                let response = self.send(&request)?;
+                Ok((request, response))
+            });
+            match result {
+                Ok((request, response)) => {
+                    self.track(&request, &response);
+                    if let Some(cache) = &mut self.cache {
+                        cache.record(&request, &response);
+                    }
+                    return response;
+                }
+                Err(e) => {
+                    self.connection = None;
+                    if started.elapsed() >= RECONNECT_WINDOW {
+                        warn!(error = %e, "Xtrieve unavailable for {:?}, answering status 20", RECONNECT_WINDOW);
+                        return inactive(request);
+                    }
+                    warn!(error = %e, "Xtrieve connection lost, retrying");
+                    self.reconnects += 1;
+                    thread::sleep(RECONNECT_DELAY);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, request: &[u8]) -> io::Result<Vec<u8>> {
+        let connection = self.connection.as_mut().ok_or(io::ErrorKind::NotConnected)?;
+        debug!(bytes = request.len(), "forwarding to Xtrieve");
+        connection.writer.write_all(request)?;
+        connection.writer.flush()?;
+        read_response(&mut connection.reader)
+    }
+
+    /// Open the session's files again on a fresh connection
+    fn reopen(&mut self) -> io::Result<()> {
+        for i in 0..self.opens.len() {
+            let response = self.send(&open_again(&self.opens[i].1))?;
+            let status = u16::from_le_bytes([response[0], response[1]]);
+            let old = self.opens[i].0.clone();
+            let name = &old[1..1 + FILE_NAME.len()];
+            let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            info!(file = %String::from_utf8_lossy(&name[..end]), status, "reopened");
+            if status != 0 {
+                continue;
+            }
+
+            let new = identity(&response[2..2 + POS_BLOCK_SIZE]);
+            for (_, renewed) in self.renewed.iter_mut().filter(|(_, renewed)| *renewed == old) {
+                *renewed = new.clone();
+            }
+            self.renewed.push((old, new.clone()));
+            self.opens[i].0 = new;
+        }
+        Ok(())
+    }
+
+    /// A request whose position block the daemon knew before a reopen,
+    /// given the identity it has now
+    fn renew<'a>(&self, request: &'a [u8]) -> Cow<'a, [u8]> {
+        let Some(block) = request.get(2..2 + POS_BLOCK_SIZE) else {
+            return Cow::Borrowed(request);
+        };
+        let held = identity(block);
+        match self.renewed.iter().find(|(old, _)| *old == held) {
+            Some((_, new)) => {
+                let mut request = request.to_vec();
+                set_identity(&mut request[2..2 + POS_BLOCK_SIZE], new);
+                Cow::Owned(request)
+            }
+            None => Cow::Borrowed(request),
+        }
+    }
+
+    /// Remember which files are open from a completed request
+    fn track(&mut self, request: &[u8], response: &[u8]) {
+        let op = u16::from_le_bytes([request[0], request[1]]);
+        let status = u16::from_le_bytes([response[0], response[1]]);
+        if status != 0 {
+            return;
+        }
+        match op {
+            OP_OPEN | OP_CREATE => {
+                let key = identity(&response[2..2 + POS_BLOCK_SIZE]);
+                self.opens.retain(|(open, _)| *open != key);
+                self.opens.push((key, request.to_vec()));
+            }
+            OP_CLOSE => {
+                let key = identity(&request[2..2 + POS_BLOCK_SIZE]);
+                self.opens.retain(|(open, _)| *open != key);
+                self.renewed.retain(|(_, renewed)| *renewed != key);
+            }
+            OP_RESET => {
+                self.opens.clear();
+                self.renewed.clear();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// What tells one open apart from the others in a position block: the
+/// cursor id and the identity bytes
+fn identity(position_block: &[u8]) -> Vec<u8> {
+    let mut identity = vec![position_block[CURSOR_ID]];
+    identity.extend_from_slice(&position_block[IDENTITY]);
+    identity
+}
+
+fn set_identity(position_block: &mut [u8], identity: &[u8]) {
+    position_block[CURSOR_ID] = identity[0];
+    position_block[IDENTITY].copy_from_slice(&identity[1..]);
+}
+
+/// A Create that is reopened must not create the file again
+fn open_again(request: &[u8]) -> Vec<u8> {
+    let mut request = request.to_vec();
+    if u16::from_le_bytes([request[0], request[1]]) == OP_CREATE {
+        // [op:2][pos:128][dlen:4][data] -> Open with an empty data buffer
+        let data_len = u32::from_le_bytes(request[130..134].try_into().unwrap()) as usize;
+        request.drain(130..134 + data_len);
+        request.splice(130..130, 0u32.to_le_bytes());
+        request[..2].copy_from_slice(&OP_OPEN.to_le_bytes());
+    }
+    request
+}
+
+/// The response given when the daemon can't be reached: status 20 and
+/// the request's position block unchanged
+fn inactive(request: &[u8]) -> Vec<u8> {
+    let mut response = STATUS_INACTIVE.to_le_bytes().to_vec();
+    response.extend_from_slice(&request[2..2 + POS_BLOCK_SIZE]);
+    response.extend_from_slice(&0u32.to_le_bytes());
+    response.extend_from_slice(&0u16.to_le_bytes());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(op: u16, name: &str, data: &[u8]) -> Vec<u8> {
+        let mut pos = [0u8; POS_BLOCK_SIZE];
+        pos[64..64 + name.len()].copy_from_slice(name.as_bytes());
+        let mut request = op.to_le_bytes().to_vec();
+        request.extend_from_slice(&pos);
+        request.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        request.extend_from_slice(data);
+        request.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        request
+    }
+
+    fn file_name(position_block: &[u8]) -> String {
+        let name = &position_block[FILE_NAME];
+        let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..end]).into_owned()
+    }
+
+    fn response(status: u16, name: &str) -> Vec<u8> {
+        let mut response = inactive(&request(0, name, &[]));
+        response[..2].copy_from_slice(&status.to_le_bytes());
+        response
+    }
+
+    #[test]
+    fn test_track_opens() {
+        let mut backend = Backend::new("127.0.0.1:1", Arc::default());
+        backend.track(&request(OP_OPEN, "", &[]), &response(0, "/d/A.DAT"));
+        backend.track(&request(OP_CREATE, "", &[1, 2, 3]), &response(0, "/d/B.DAT"));
+        backend.track(&request(OP_OPEN, "", &[]), &response(12, "/d/C.DAT"));
+        assert_eq!(backend.opens.len(), 2);
+        backend.track(&request(OP_CLOSE, "/d/A.DAT", &[]), &response(0, "/d/A.DAT"));
+        assert_eq!(backend.opens.len(), 1);
+        assert_eq!(file_name(&response(0, "/d/B.DAT")[2..]), "/d/B.DAT");
+
+        // The Create comes back as an Open without its file spec
+        let reopen = open_again(&backend.opens[0].1);
+        assert_eq!(reopen, request(OP_OPEN, "", &[]));
+        backend.track(&request(OP_RESET, "", &[]), &response(0, ""));
+        assert!(backend.opens.is_empty());
+
+        // Two opens of one file are told apart by their cursor id
+        let mut first = response(0, "/d/A.DAT");
+        first[2 + CURSOR_ID] = 1;
+        let mut second = first.clone();
+        second[2 + CURSOR_ID] = 2;
+        backend.track(&request(OP_OPEN, "", &[]), &first);
+        backend.track(&request(OP_OPEN, "", &[]), &second);
+        assert_eq!(backend.opens.len(), 2);
+        let mut close = request(OP_CLOSE, "/d/A.DAT", &[]);
+        close[2 + CURSOR_ID] = 1;
+        backend.track(&close, &response(0, ""));
+        assert_eq!(backend.opens.len(), 1);
+        assert_eq!(backend.opens[0].0[0], 2);
+
+        let status = inactive(&request(5, "/d/A.DAT", b"key"));
+        assert_eq!(u16::from_le_bytes([status[0], status[1]]), 20);
+        assert_eq!(file_name(&status[2..]), "/d/A.DAT");
+    }
+
+    #[test]
+    fn test_renew_after_reopen() {
+        let mut backend = Backend::new("127.0.0.1:1", Arc::default());
+        let before = response(0, "/d/A.DAT");
+        let mut after = before.clone();
+        after[2 + 120] = 9; // new session
+        after[2 + 114..2 + 120].copy_from_slice(&[0xB7, 1, 4, 3, 2, 1]); // new check
+        backend.track(&request(OP_OPEN, "", &[]), &before);
+        backend.opens[0].0 = identity(&after[2..]);
+        backend.renewed.push((identity(&before[2..]), identity(&after[2..])));
+
+        // The DOS side still holds the old block; its cursor state stays
+        let mut get = request(6, "/d/A.DAT", &[]);
+        get[2] = 1;
+        let renewed = backend.renew(&get);
+        assert_eq!(identity(&renewed[2..]), identity(&after[2..]));
+        assert_eq!((renewed[2], renewed.len()), (1, get.len()));
+        assert!(matches!(backend.renew(&request(6, "/d/B.DAT", &[])), Cow::Borrowed(_)));
+
+        let close = backend.renew(&request(OP_CLOSE, "/d/A.DAT", &[])).into_owned();
+        backend.track(&close, &response(0, ""));
+        assert!(backend.opens.is_empty());
+        assert!(backend.renewed.is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+//! Response cache for metadata operations
+//!
+//! Applications tend to call Version and Stat far more often than their
+//! answers change. With [`Options::cache_ttl`](crate::Options::cache_ttl)
+//! set, the bridge answers repeats of those two from memory for that long
+//! instead of asking the daemon again.
+//! A Stat answer is dropped as soon as the session sends anything that
+//! could change a file (an insert, a close, a transaction end...), so the
+//! record count a session sees after its own writes is always current;
+//! writes by other clients show up once the entry expires.
+//!
+//! A cached answer carries the position block of the request it answers,
+//! so the DOS side's cursor is left as it was.
+
+use std::time::{Duration, Instant};
+
+use crate::protocol::POS_BLOCK_SIZE;
+
+const OP_STAT: u16 = 15;
+const OP_VERSION: u16 = 26;
+
+/// Operations that never change a file: Get, Get Key, Step, Get
+/// Position/Direct, Stat and Version
+fn read_only(op: u16) -> bool {
+    matches!(op % 100, 5..=13 | 15 | 22..=24 | 26 | 33..=35 | 55..=63)
+}
+
+pub struct ResponseCache {
+    ttl: Duration,
+    /// (op, file name, data length, key length) and the response
+    entries: Vec<(Vec<u8>, Instant, Vec<u8>)>,
+    pub hits: u64,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        ResponseCache { ttl, entries: Vec::new(), hits: 0 }
+    }
+
+    /// What identifies a cacheable request, None for other operations
+    fn key(request: &[u8]) -> Option<Vec<u8>> {
+        let op = u16::from_le_bytes([*request.first()?, *request.get(1)?]);
+        if op != OP_STAT && op != OP_VERSION {
+            return None;
+        }
+        // [op:2][pos:128][dlen:4][data][klen:2]
+        let data_len = u32::from_le_bytes(request.get(130..134)?.try_into().ok()?) as usize;
+        let key_len = request.get(134 + data_len..136 + data_len)?;
+        let mut key = request[..2].to_vec();
+        key.extend_from_slice(&request[2 + 64..2 + 120]);
+        key.extend_from_slice(&request[130..134]);
+        key.extend_from_slice(key_len);
+        Some(key)
+    }
+
+    /// A fresh cached response to `request`
+    pub fn lookup(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let key = ResponseCache::key(request)?;
+        let ttl = self.ttl;
+        self.entries.retain(|(_, stored, _)| stored.elapsed() < ttl);
+        let (_, _, response) = self.entries.iter().find(|(k, _, _)| *k == key)?;
+        let mut response = response.clone();
+        response[2..2 + POS_BLOCK_SIZE].copy_from_slice(&request[2..2 + POS_BLOCK_SIZE]);
+        self.hits += 1;
+        Some(response)
+    }
+
+    /// Note a request the daemon answered
+    pub fn record(&mut self, request: &[u8], response: &[u8]) {
+        let op = u16::from_le_bytes([request[0], request[1]]);
+        if !read_only(op) {
+            self.entries.retain(|(key, _, _)| key[..2] == OP_VERSION.to_le_bytes());
+            return;
+        }
+        let status = u16::from_le_bytes([response[0], response[1]]);
+        if let (Some(key), 0) = (ResponseCache::key(request), status) {
+            self.entries.retain(|(k, _, _)| *k != key);
+            self.entries.push((key, Instant::now(), response.to_vec()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(op: u16, name: &str, cursor: u8) -> Vec<u8> {
+        let mut pos = [0u8; POS_BLOCK_SIZE];
+        pos[0] = cursor;
+        pos[64..64 + name.len()].copy_from_slice(name.as_bytes());
+        let mut message = op.to_le_bytes().to_vec();
+        message.extend_from_slice(&pos);
+        message.extend_from_slice(&4u32.to_le_bytes());
+        message.extend_from_slice(&[cursor; 4]);
+        message.extend_from_slice(&[0; 10]);
+        message
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut cache = ResponseCache::new(Duration::from_secs(60));
+        let stat = message(OP_STAT, "/d/A.DAT", 1);
+        assert!(cache.lookup(&stat).is_none());
+        cache.record(&stat, &message(0, "/d/A.DAT", 9));
+
+        // Same file, different cursor and buffer contents
+        let again = message(OP_STAT, "/d/A.DAT", 2);
+        let hit = cache.lookup(&again).unwrap();
+        assert_eq!(&hit[2..2 + POS_BLOCK_SIZE], &again[2..2 + POS_BLOCK_SIZE]);
+        assert_eq!(hit[134], 9);
+        assert!(cache.lookup(&message(OP_STAT, "/d/B.DAT", 1)).is_none());
+
+        let version = message(OP_VERSION, "", 0);
+        cache.record(&version, &message(0, "", 0));
+        cache.record(&message(5, "/d/A.DAT", 1), &message(0, "/d/A.DAT", 1));
+        assert!(cache.lookup(&stat).is_some());
+        cache.record(&message(2, "/d/A.DAT", 1), &message(0, "/d/A.DAT", 1));
+        assert!(cache.lookup(&stat).is_none());
+        assert!(cache.lookup(&version).is_some());
+        assert_eq!(cache.hits, 3);
+
+        // Failed answers and expired entries are not served
+        cache.record(&stat, &message(3, "/d/A.DAT", 1));
+        assert!(cache.lookup(&stat).is_none());
+        let mut cache = ResponseCache::new(Duration::ZERO);
+        cache.record(&version, &message(0, "", 0));
+        assert!(cache.lookup(&version).is_none());
+    }
+}
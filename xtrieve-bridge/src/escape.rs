@@ -0,0 +1,193 @@
+//! Byte stuffing for links that aren't binary-safe
+//!
+//! Some links eat bytes the protocol needs: a modem or terminal server
+//! doing software flow control swallows XON (0x11) and XOFF (0x13), and a
+//! 7-bit line strips the high bit. With an [`Escape`] mode the whole byte
+//! stream, sync markers included, is stuffed below the framing:
+//!
+//! ```text
+//! xonxoff  0x11 0x13 0x91 0x93 0x7D 0x7E  ->  0x7D, byte ^ 0x20
+//! 7bit     as xonxoff, and a byte >= 0x80 ->  0x7E, then its low seven
+//!          bits (stuffed again when they are special)
+//! ```
+//!
+//! Both ends must use the same mode; see docs/bridge/PROTOCOL.md.
+
+use std::io::{self, Read, Write};
+
+/// Next byte is XORed with 0x20
+pub const ESC: u8 = 0x7D;
+/// Next byte (after unstuffing) has its high bit set
+pub const HIGH: u8 = 0x7E;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    /// Bytes go over the link as they are
+    None,
+    XonXoff,
+    SevenBit,
+}
+
+impl Escape {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Escape::None),
+            "xonxoff" | "xon/xoff" => Some(Escape::XonXoff),
+            "7bit" => Some(Escape::SevenBit),
+            _ => None,
+        }
+    }
+
+    fn special(byte: u8) -> bool {
+        matches!(byte, 0x11 | 0x13 | 0x91 | 0x93 | ESC | HIGH)
+    }
+
+    /// Stuff `bytes` onto the end of `out`
+    pub fn encode(self, bytes: &[u8], out: &mut Vec<u8>) {
+        for &byte in bytes {
+            let byte = match self {
+                Escape::None => {
+                    out.push(byte);
+                    continue;
+                }
+                Escape::SevenBit if byte & 0x80 != 0 => {
+                    out.push(HIGH);
+                    byte & 0x7F
+                }
+                _ => byte,
+            };
+            if Escape::special(byte) {
+                out.extend_from_slice(&[ESC, byte ^ 0x20]);
+            } else {
+                out.push(byte);
+            }
+        }
+    }
+}
+
+/// Unstuffing state carried between reads
+#[derive(Debug, Default)]
+struct Decoder {
+    escaped: bool,
+    high: bool,
+}
+
+impl Decoder {
+    fn decode(&mut self, mode: Escape, byte: u8) -> Option<u8> {
+        if mode == Escape::None {
+            return Some(byte);
+        }
+        let byte = if self.escaped {
+            self.escaped = false;
+            byte ^ 0x20
+        } else if byte == ESC {
+            self.escaped = true;
+            return None;
+        } else if byte == HIGH && mode == Escape::SevenBit {
+            self.high = true;
+            return None;
+        } else {
+            byte
+        };
+        if std::mem::take(&mut self.high) {
+            Some(byte | 0x80)
+        } else {
+            Some(byte)
+        }
+    }
+}
+
+/// A reader or writer stuffing or unstuffing the bytes passing through
+pub struct Escaped<T> {
+    inner: T,
+    mode: Escape,
+    decoder: Decoder,
+    raw: Vec<u8>,
+}
+
+impl<T> Escaped<T> {
+    pub fn new(inner: T, mode: Escape) -> Self {
+        Escaped { inner, mode, decoder: Decoder::default(), raw: Vec::new() }
+    }
+}
+
+impl<T: Read> Read for Escaped<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.mode == Escape::None || buf.is_empty() {
+            return self.inner.read(buf);
+        }
+        // Stuffed bytes only ever shrink, so a raw read of buf.len() fits
+        self.raw.resize(buf.len(), 0);
+        loop {
+            let n = self.inner.read(&mut self.raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut out = 0;
+            for &byte in &self.raw[..n] {
+                if let Some(byte) = self.decoder.decode(self.mode, byte) {
+                    buf[out] = byte;
+                    out += 1;
+                }
+            }
+            if out > 0 {
+                return Ok(out);
+            }
+        }
+    }
+}
+
+impl<T: Write> Write for Escaped<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.mode == Escape::None {
+            return self.inner.write(buf);
+        }
+        self.raw.clear();
+        self.mode.encode(buf, &mut self.raw);
+        self.inner.write_all(&self.raw)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        for mode in [Escape::None, Escape::XonXoff, Escape::SevenBit] {
+            let mut line = Escaped::new(Vec::new(), mode);
+            line.write_all(&[0xBB, 0xBB, 0x11, 0x7D]).unwrap();
+            line.write_all(&data).unwrap();
+            let line = line.inner;
+            match mode {
+                Escape::None => assert_eq!(line.len(), 260),
+                Escape::XonXoff => {
+                    assert!(!line.iter().any(|&b| b == 0x11 || b == 0x13));
+                    assert_eq!(&line[..6], [0xBB, 0xBB, ESC, 0x31, ESC, 0x5D]);
+                }
+                Escape::SevenBit => {
+                    assert!(line.iter().all(|&b| b < 0x80 && b != 0x11 && b != 0x13));
+                    assert_eq!(&line[..4], [HIGH, 0x3B, HIGH, 0x3B]);
+                }
+            }
+
+            // Read back a byte at a time, so escapes straddle reads
+            let mut reader = Escaped::new(&line[..], mode);
+            let mut decoded = Vec::new();
+            let mut byte = [0u8; 1];
+            while reader.read(&mut byte).unwrap() == 1 {
+                decoded.push(byte[0]);
+            }
+            assert_eq!(&decoded[..4], [0xBB, 0xBB, 0x11, 0x7D]);
+            assert_eq!(&decoded[4..], &data[..]);
+        }
+        assert_eq!(Escape::parse("7bit"), Some(Escape::SevenBit));
+        assert_eq!(Escape::parse("slip"), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Checked frames for lossy serial lines
+//!
+//! A frame starts with 0xBB and a marker byte:
+//!
+//! ```text
+//! 0xBB 0xBB                          plain request (original protocol)
+//! 0xBB 0xCC [seq:1][len:4][payload][crc:2]   checked request or response
+//! 0xBB 0x06 [seq:1]                  ACK: response `seq` arrived intact
+//! 0xBB 0x15 [seq:1]                  NAK: frame `seq` was damaged, resend it
+//! ```
+//!
+//! The CRC is CRC-16/XMODEM over seq, len and payload. See
+//! docs/bridge/PROTOCOL.md for the DOS side of the exchange.
+
+use std::io::{self, Read, Write};
+
+use crate::protocol::read_exact;
+
+pub const SYNC: u8 = 0xBB;
+pub const PLAIN: u8 = 0xBB;
+pub const CHECKED: u8 = 0xCC;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+
+/// Largest payload accepted: a 64K data buffer plus the fixed fields
+pub const MAX_PAYLOAD: u32 = 70_000;
+
+/// What follows a sync byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    Plain,
+    Checked,
+    Ack,
+    Nak,
+}
+
+/// A checked frame as read from the line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checked {
+    Frame { seq: u8, payload: Vec<u8> },
+    /// Length out of range or CRC mismatch; `seq` may itself be damaged
+    Corrupt { seq: u8 },
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0)
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Skip bytes until a sync byte followed by a known marker. Also returns
+/// how many bytes were skipped, not counting idle-line 0x00 and 0xFF.
+pub fn wait_for_marker<R: Read>(reader: &mut R) -> io::Result<(Marker, usize)> {
+    let mut buf = [0u8; 1];
+    let mut found_first = false;
+    let mut skipped = 0;
+
+    loop {
+        read_exact(reader, &mut buf)?;
+        if found_first {
+            match buf[0] {
+                PLAIN => return Ok((Marker::Plain, skipped)),
+                CHECKED => return Ok((Marker::Checked, skipped)),
+                ACK => return Ok((Marker::Ack, skipped)),
+                NAK => return Ok((Marker::Nak, skipped)),
+                _ => skipped += 2,
+            }
+            found_first = false;
+        } else if buf[0] == SYNC {
+            found_first = true;
+        } else if buf[0] != 0xFF && buf[0] != 0x00 {
+            skipped += 1;
+        }
+    }
+}
+
+/// Read the rest of a checked frame after its marker
+pub fn read_checked<R: Read>(reader: &mut R) -> io::Result<Checked> {
+    let mut header = [0u8; 5];
+    read_exact(reader, &mut header)?;
+    let seq = header[0];
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    if len > MAX_PAYLOAD {
+        return Ok(Checked::Corrupt { seq });
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    read_exact(reader, &mut payload)?;
+    let mut crc = [0u8; 2];
+    read_exact(reader, &mut crc)?;
+
+    let mut covered = header.to_vec();
+    covered.extend_from_slice(&payload);
+    if crc16(&covered) != u16::from_le_bytes(crc) {
+        return Ok(Checked::Corrupt { seq });
+    }
+    Ok(Checked::Frame { seq, payload })
+}
+
+/// Write a checked frame, marker included
+pub fn write_checked<W: Write>(writer: &mut W, seq: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 9);
+    frame.extend_from_slice(&[SYNC, CHECKED, seq]);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    let crc = crc16(&frame[2..]);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Write an ACK or NAK
+pub fn write_reply<W: Write>(writer: &mut W, marker: u8, seq: u8) -> io::Result<()> {
+    writer.write_all(&[SYNC, marker, seq])?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked_frames() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+
+        let mut line = vec![0x00, 0x42];
+        write_checked(&mut line, 7, b"request").unwrap();
+        write_reply(&mut line, NAK, 7).unwrap();
+        let mut damaged = Vec::new();
+        write_checked(&mut damaged, 8, b"request").unwrap();
+        damaged[8] ^= 0x10;
+        line.extend_from_slice(&damaged);
+
+        let mut reader = &line[..];
+        assert_eq!(wait_for_marker(&mut reader).unwrap(), (Marker::Checked, 1));
+        assert_eq!(
+            read_checked(&mut reader).unwrap(),
+            Checked::Frame { seq: 7, payload: b"request".to_vec() }
+        );
+        assert_eq!(wait_for_marker(&mut reader).unwrap(), (Marker::Nak, 0));
+        assert_eq!(reader[0], 7);
+        reader = &reader[1..];
+        assert_eq!(wait_for_marker(&mut reader).unwrap(), (Marker::Checked, 0));
+        assert_eq!(read_checked(&mut reader).unwrap(), Checked::Corrupt { seq: 8 });
+    }
+}
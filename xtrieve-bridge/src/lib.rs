@@ -0,0 +1,205 @@
+//! Protocol-aware relay between DOS Btrieve requesters and xtrieved
+//!
+//! The DOS side (BTRSERL.EXE behind a serial line, DOSBox-X's nullmodem,
+//! or any other byte pipe) sends requests framed by a sync marker, plain
+//! or CRC-checked. This crate finds the message boundaries, forwards each
+//! request to the daemon and writes the response back, with the extras a
+//! slow or flaky link needs: retransmission, byte stuffing, reconnecting
+//! to a restarted daemon, path translation and a Version/Stat cache.
+//!
+//! A front-end owns the transport and hands each session's two halves to
+//! [`serve`]:
+//!
+//! ```ignore
+//! use std::io::{BufReader, BufWriter};
+//! use xtrieve_bridge::{serve, Options};
+//!
+//! let options = Options::new("127.0.0.1:7419");
+//! serve(peer.to_string(), BufReader::new(&stream), BufWriter::new(&stream), &options);
+//! ```
+//!
+//! serial-bridge is the TCP (DOSBox-X) and serial port front-end.
+
+pub mod backend;
+pub mod cache;
+pub mod escape;
+pub mod frame;
+pub mod paths;
+pub mod protocol;
+pub mod session;
+
+use std::io::{Read, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{debug, error, info, warn};
+
+use backend::Backend;
+use cache::ResponseCache;
+use escape::{Escape, Escaped};
+use frame::{Checked, Marker};
+use paths::PathMap;
+use protocol::{read_request, read_u8, well_formed, Deadline, DEFAULT_FRAME_TIMEOUT};
+use session::Session;
+
+/// Settings every session of a front-end shares
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// xtrieved's TCP address
+    pub xtrieve_addr: String,
+    pub paths: Arc<PathMap>,
+    pub escape: Escape,
+    /// How long Version and Stat answers are reused; None turns the
+    /// cache off
+    pub cache_ttl: Option<Duration>,
+    /// Longest silence allowed in the middle of a frame before it is
+    /// discarded; None waits forever
+    pub frame_timeout: Option<Duration>,
+    /// Log every request and response as a hex dump
+    pub hex: bool,
+}
+
+impl Options {
+    pub fn new(xtrieve_addr: &str) -> Self {
+        Options {
+            xtrieve_addr: xtrieve_addr.to_string(),
+            paths: Arc::default(),
+            escape: Escape::None,
+            cache_ttl: None,
+            frame_timeout: Some(DEFAULT_FRAME_TIMEOUT),
+            hex: false,
+        }
+    }
+
+    /// A session's connection to Xtrieve; requests retry if it isn't up yet
+    fn backend(&self) -> Backend {
+        let mut backend = Backend::new(&self.xtrieve_addr, self.paths.clone());
+        backend.cache = self.cache_ttl.map(ResponseCache::new);
+        if let Err(e) = backend.connect() {
+            warn!(error = %e, "failed to connect to Xtrieve");
+        }
+        backend
+    }
+}
+
+/// Serve one DOS session over `reader` and `writer` until the DOS side
+/// goes away. `name` (a peer address, a device) labels its log lines and
+/// counters. Pass buffered halves; each message is flushed as a whole.
+/// Give the transport a read timeout of about a second so a frame that
+/// stops halfway is noticed (see [`Options::frame_timeout`]).
+pub fn serve<R: Read, W: Write>(name: String, reader: R, writer: W, options: &Options) {
+    let session = Session::start(name, options.hex);
+    let mut backend = options.backend();
+    let reader = Escaped::new(reader, options.escape);
+    let writer = Escaped::new(writer, options.escape);
+    relay(Deadline::new(reader, options.frame_timeout), writer, &mut backend, &session);
+    session.log("session ended");
+}
+
+/// Relay requests from DOS to Xtrieve and responses back until the DOS
+/// side fails, counting into `session`. A frame the line goes silent in
+/// is dropped and the relay waits for the next marker.
+fn relay<R: Read, W: Write>(
+    mut dos_reader: Deadline<R>,
+    mut dos_writer: W,
+    backend: &mut Backend,
+    session: &Arc<Session>,
+) {
+    // Last checked exchange: sequence, request and response, kept so a
+    // retransmitted request or a NAKed response is answered again without
+    // running the request twice
+    let mut last: Option<(u8, Vec<u8>, Vec<u8>)> = None;
+
+    let mut run = || -> std::io::Result<()> {
+        loop {
+            // Read complete request from DOS
+            dos_reader.disarm();
+            let (marker, skipped) = frame::wait_for_marker(&mut dos_reader)?;
+            dos_reader.arm();
+            if skipped > 0 {
+                debug!(session = %session.name, skipped, "resync: skipped garbage before {:?} marker", marker);
+                Session::add(&session.resyncs, 1);
+            }
+            let (seq, request) = match marker {
+                Marker::Plain => (None, read_request(&mut dos_reader)?),
+                Marker::Ack => {
+                    let seq = read_u8(&mut dos_reader)?;
+                    debug!(session = %session.name, seq, "response confirmed");
+                    continue;
+                }
+                Marker::Nak => {
+                    let seq = read_u8(&mut dos_reader)?;
+                    match &last {
+                        Some((last_seq, _, response)) if *last_seq == seq => {
+                            info!(session = %session.name, seq, "response damaged, sending it again");
+                            Session::add(&session.retransmits, 1);
+                            frame::write_checked(&mut dos_writer, seq, response)?;
+                        }
+                        _ => debug!(session = %session.name, seq, "NAK with nothing to resend"),
+                    }
+                    continue;
+                }
+                Marker::Checked => match frame::read_checked(&mut dos_reader)? {
+                    Checked::Frame { seq, payload } if well_formed(&payload) => (Some(seq), payload),
+                    Checked::Frame { seq, .. } | Checked::Corrupt { seq } => {
+                        warn!(session = %session.name, seq, "request damaged, asking again");
+                        Session::add(&session.crc_errors, 1);
+                        frame::write_reply(&mut dos_writer, frame::NAK, seq)?;
+                        continue;
+                    }
+                },
+            };
+            session.dump("request", &request);
+            Session::add(&session.bytes_in, request.len() as u64);
+
+            if let (Some(seq), Some((last_seq, last_request, response))) = (seq, &last) {
+                if seq == *last_seq && request == *last_request {
+                    info!(session = %session.name, seq, "request repeated, sending its response again");
+                    Session::add(&session.retransmits, 1);
+                    frame::write_checked(&mut dos_writer, seq, response)?;
+                    continue;
+                }
+            }
+
+            let response = backend.exchange(&request);
+            session.reconnects.store(backend.reconnects, Ordering::Relaxed);
+            if let Some(cache) = &backend.cache {
+                session.cache_hits.store(cache.hits, Ordering::Relaxed);
+            }
+            session.dump("response", &response);
+
+            // Forward to DOS
+            match seq {
+                Some(seq) => {
+                    frame::write_checked(&mut dos_writer, seq, &response)?;
+                    Session::add(&session.bytes_out, response.len() as u64);
+                    last = Some((seq, request, response));
+                }
+                None => {
+                    dos_writer.write_all(&response)?;
+                    dos_writer.flush()?;
+                    Session::add(&session.bytes_out, response.len() as u64);
+                }
+            }
+            Session::add(&session.requests, 1);
+        }
+    };
+    let result = loop {
+        match run() {
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                warn!(session = %session.name, "line went silent mid-frame, discarding it");
+                Session::add(&session.resyncs, 1);
+            }
+            result => break result,
+        }
+    };
+
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            info!(session = %session.name, "DOS side closed the connection")
+        }
+        Err(e) => error!(session = %session.name, error = %e, "session failed"),
+        Ok(()) => {}
+    }
+}
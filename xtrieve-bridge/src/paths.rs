@@ -0,0 +1,183 @@
+//! Path translation rules
+//!
+//! The DOS application opens files by the paths it always used
+//! (`C:\APPS\DATA\CUST.DAT`); a rules file rewrites them before the
+//! request reaches the daemon, so neither the application nor the TSR
+//! needs reconfiguring. One rule per line, `#` starts a comment:
+//!
+//! ```text
+//! C:\APPS\DATA\*.DAT   legacy/app1/     # file name kept, directory replaced
+//! C:\APPS\REPORTS\     legacy/reports/  # everything below the directory
+//! C:\APPS\CONFIG.DAT   app1/config.dat  # one file
+//! ```
+//!
+//! Patterns match case-insensitively, `/` and `\` alike, with DOS `*` and
+//! `?` wildcards. A target ending in `/` gets the file name (or, for a
+//! pattern ending in `\`, the rest of the path) appended. The first rule
+//! that matches wins; other paths pass through unchanged.
+
+use std::fs;
+
+use crate::protocol::POS_BLOCK_SIZE;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// Uppercased, with `\` separators
+    pattern: String,
+    target: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathMap {
+    rules: Vec<Rule>,
+}
+
+fn normalize(path: &str) -> String {
+    path.replace('/', "\\").to_ascii_uppercase()
+}
+
+/// DOS-style wildcard match: `*` any run of characters, `?` any one,
+/// neither crossing a `\`
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => {
+            let run = text.iter().position(|&b| b == b'\\').unwrap_or(text.len());
+            (0..=run).any(|i| glob(rest, &text[i..]))
+        }
+        Some((&p, rest)) => text
+            .split_first()
+            .is_some_and(|(&t, text)| (p == t || (p == b'?' && t != b'\\')) && glob(rest, text)),
+    }
+}
+
+impl PathMap {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        PathMap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [pattern, target] = fields[..] else {
+                return Err(format!("line {}: expected `<dos path> <daemon path>`", number + 1));
+            };
+            rules.push(Rule { pattern: normalize(pattern), target: target.to_string() });
+        }
+        Ok(PathMap { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The daemon path for a DOS path, if a rule matches
+    pub fn translate(&self, dos_path: &str) -> Option<String> {
+        let dos_path = dos_path.trim_end_matches('\0');
+        let path = normalize(dos_path);
+        self.rules.iter().find_map(|rule| {
+            let rest = if rule.pattern.ends_with('\\') {
+                path.strip_prefix(&rule.pattern)?
+            } else if glob(rule.pattern.as_bytes(), path.as_bytes()) {
+                &path[path.rfind('\\').map_or(0, |i| i + 1)..]
+            } else {
+                return None;
+            };
+            if !rule.target.ends_with('/') {
+                return Some(rule.target.clone());
+            }
+            // Keep the case the DOS side sent
+            let rest = &dos_path[dos_path.len() - rest.len()..];
+            Some(format!("{}{}", rule.target, rest.replace('\\', "/")))
+        })
+    }
+
+    /// `request` with its path field translated, or None when it carries
+    /// no path or no rule matches
+    pub fn rewrite(&self, request: &[u8]) -> Option<(String, Vec<u8>)> {
+        if self.rules.is_empty() {
+            return None;
+        }
+        // [op:2][pos][dlen:4][data][klen:2][key][knum:2][plen:2][path][lock:2]
+        let field = |at: usize, len: usize| request.get(at..at + len);
+        let data_len = u32::from_le_bytes(field(2 + POS_BLOCK_SIZE, 4)?.try_into().ok()?) as usize;
+        let key_at = 2 + POS_BLOCK_SIZE + 4 + data_len;
+        let key_len = u16::from_le_bytes(field(key_at, 2)?.try_into().ok()?) as usize;
+        let path_at = key_at + 2 + key_len + 2;
+        let path_len = u16::from_le_bytes(field(path_at, 2)?.try_into().ok()?) as usize;
+        let dos_path = String::from_utf8_lossy(field(path_at + 2, path_len)?);
+        if dos_path.is_empty() {
+            return None;
+        }
+        let path = self.translate(&dos_path)?;
+
+        let mut rewritten = request[..path_at].to_vec();
+        rewritten.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        rewritten.extend_from_slice(path.as_bytes());
+        rewritten.extend_from_slice(&request[path_at + 2 + path_len..]);
+        Some((path, rewritten))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = "\
+# legacy application data
+C:\\APPS\\DATA\\*.DAT   legacy/app1/
+C:\\APPS\\REPORTS\\     legacy/reports/
+c:/apps/config.dat     app1/config.dat
+C:\\APPS\\DATA\\*.?     legacy/other/
+";
+
+    #[test]
+    fn test_translate() {
+        let map = PathMap::parse(RULES).unwrap();
+        assert_eq!(map.translate("C:\\APPS\\DATA\\Cust.dat").as_deref(), Some("legacy/app1/Cust.dat"));
+        assert_eq!(map.translate("c:\\apps\\data\\ORDERS.DAT\0").as_deref(), Some("legacy/app1/ORDERS.DAT"));
+        assert_eq!(map.translate("C:\\APPS\\DATA\\NOTES.X").as_deref(), Some("legacy/other/NOTES.X"));
+        assert_eq!(map.translate("C:\\APPS\\REPORTS\\1994\\Q1.DAT").as_deref(), Some("legacy/reports/1994/Q1.DAT"));
+        assert_eq!(map.translate("C:\\APPS\\CONFIG.DAT").as_deref(), Some("app1/config.dat"));
+        assert_eq!(map.translate("C:\\APPS\\DATA\\SUB\\X.DAT"), None);
+        assert_eq!(map.translate("D:\\CUST.DAT"), None);
+        assert!(PathMap::parse("C:\\ONLY").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_request() {
+        let map = PathMap::parse(RULES).unwrap();
+        let mut request = 0u16.to_le_bytes().to_vec();
+        request.extend_from_slice(&[0u8; POS_BLOCK_SIZE]);
+        request.extend_from_slice(&2u32.to_le_bytes());
+        request.extend_from_slice(b"ab");
+        request.extend_from_slice(&3u16.to_le_bytes());
+        request.extend_from_slice(b"key");
+        request.extend_from_slice(&0u16.to_le_bytes());
+        let path = b"C:\\APPS\\DATA\\CUST.DAT";
+        request.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        request.extend_from_slice(path);
+        request.extend_from_slice(&0xFFFFu16.to_le_bytes());
+
+        let (path, rewritten) = map.rewrite(&request).unwrap();
+        assert_eq!(path, "legacy/app1/CUST.DAT");
+        let tail = &rewritten[2 + POS_BLOCK_SIZE + 4 + 2 + 2 + 3 + 2..];
+        assert_eq!(&tail[..2], &(path.len() as u16).to_le_bytes());
+        assert_eq!(&tail[2..tail.len() - 2], path.as_bytes());
+        assert_eq!(&tail[tail.len() - 2..], [0xFF, 0xFF]);
+        assert_eq!(rewritten.len(), request.len() - 1);
+
+        assert!(map.rewrite(&request[..100]).is_none());
+        assert!(PathMap::default().rewrite(&request).is_none());
+    }
+}
@@ -0,0 +1,257 @@
+//! The Xtrieve wire protocol as the bridge sees it
+//!
+//! ```text
+//! Request:  [op:2][pos:128][dlen:4][data:N][klen:2][key:N][knum:2][plen:2][path:N][lock:2]
+//! Response: [status:2][pos:128][dlen:4][data:N][klen:2][key:N]
+//! ```
+//!
+//! Messages carry no length up front, so they are read field by field to
+//! find where each ends.
+
+use std::io::{self, ErrorKind, Read};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+pub const POS_BLOCK_SIZE: usize = 128;
+
+/// How long the line may go quiet in the middle of a frame by default
+pub const DEFAULT_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A DOS-side reader that rides out read timeouts while the line is
+/// idle, but gives up on a frame once the line has been silent for
+/// `limit` in the middle of it. The transport needs a read timeout of its
+/// own (a second or so) for the limit to be noticed.
+pub struct Deadline<R> {
+    inner: R,
+    limit: Option<Duration>,
+    armed: bool,
+    last_byte: Instant,
+}
+
+impl<R> Deadline<R> {
+    pub fn new(inner: R, limit: Option<Duration>) -> Self {
+        Deadline { inner, limit, armed: false, last_byte: Instant::now() }
+    }
+
+    /// A frame has started: time the silences from now on
+    pub fn arm(&mut self) {
+        self.armed = true;
+        self.last_byte = Instant::now();
+    }
+
+    /// Back to waiting for a frame, for as long as it takes
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl<R: Read> Read for Deadline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.inner.read(buf) {
+                Ok(n) => {
+                    self.last_byte = Instant::now();
+                    return Ok(n);
+                }
+                // An idle line times out rather than closing
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    if let (true, Some(limit)) = (self.armed, self.limit) {
+                        if self.last_byte.elapsed() >= limit {
+                            return Err(io::Error::new(ErrorKind::TimedOut, "line silent mid-frame"));
+                        }
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Fill `buf`; a closed connection is UnexpectedEof
+pub fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<()> {
+    let mut total = 0;
+    while total < buf.len() {
+        let n = reader.read(&mut buf[total..])?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ));
+        }
+        total += n;
+    }
+    Ok(())
+}
+
+pub fn read_u8<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    let mut buf = [0u8; 1];
+    read_exact(reader, &mut buf)?;
+    Ok(buf[0])
+}
+
+pub fn read_u16<R: Read>(reader: &mut R) -> std::io::Result<u16> {
+    let mut buf = [0u8; 2];
+    read_exact(reader, &mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    read_exact(reader, &mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a complete Xtrieve request from DOS, after its sync marker
+/// Returns the serialized request bytes
+pub fn read_request<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut request = Vec::with_capacity(512);
+
+    // Operation code (2 bytes)
+    let op = read_u16(reader)?;
+    request.extend_from_slice(&op.to_le_bytes());
+
+    // Position block (128 bytes)
+    let mut pos_block = [0u8; POS_BLOCK_SIZE];
+    read_exact(reader, &mut pos_block)?;
+    request.extend_from_slice(&pos_block);
+
+    // Data length (4 bytes) + data
+    let data_len = read_u32(reader)?;
+    request.extend_from_slice(&data_len.to_le_bytes());
+
+    if data_len > 0 {
+        let mut data = vec![0u8; data_len as usize];
+        read_exact(reader, &mut data)?;
+        request.extend_from_slice(&data);
+    }
+
+    // Key length (2 bytes) + key
+    let key_len = read_u16(reader)?;
+    request.extend_from_slice(&key_len.to_le_bytes());
+
+    if key_len > 0 {
+        let mut key = vec![0u8; key_len as usize];
+        read_exact(reader, &mut key)?;
+        request.extend_from_slice(&key);
+    }
+
+    // Key number (2 bytes)
+    let key_num = read_u16(reader)?;
+    request.extend_from_slice(&key_num.to_le_bytes());
+
+    // Path length (2 bytes) + path
+    let path_len = read_u16(reader)?;
+    request.extend_from_slice(&path_len.to_le_bytes());
+
+    let mut path = vec![0u8; path_len as usize];
+    if path_len > 0 {
+        read_exact(reader, &mut path)?;
+        request.extend_from_slice(&path);
+    }
+
+    // Lock bias (2 bytes)
+    let lock = read_u16(reader)?;
+    request.extend_from_slice(&lock.to_le_bytes());
+
+    debug!(op, data_len, key_len, key_num, path = %String::from_utf8_lossy(&path), size = request.len(), "request");
+    Ok(request)
+}
+
+/// Read a complete Xtrieve response from server
+/// Returns the serialized response bytes
+pub fn read_response<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut response = Vec::with_capacity(512);
+
+    // Status code (2 bytes)
+    let status = read_u16(reader)?;
+    response.extend_from_slice(&status.to_le_bytes());
+
+    // Position block (128 bytes)
+    let mut pos_block = [0u8; POS_BLOCK_SIZE];
+    read_exact(reader, &mut pos_block)?;
+    response.extend_from_slice(&pos_block);
+
+    // Data length (4 bytes) + data
+    let data_len = read_u32(reader)?;
+    response.extend_from_slice(&data_len.to_le_bytes());
+
+    if data_len > 0 {
+        let mut data = vec![0u8; data_len as usize];
+        read_exact(reader, &mut data)?;
+        response.extend_from_slice(&data);
+    }
+
+    // Key length (2 bytes) + key
+    let key_len = read_u16(reader)?;
+    response.extend_from_slice(&key_len.to_le_bytes());
+
+    if key_len > 0 {
+        let mut key = vec![0u8; key_len as usize];
+        read_exact(reader, &mut key)?;
+        response.extend_from_slice(&key);
+    }
+
+    debug!(status, data_len, key_len, size = response.len(), "response");
+    Ok(response)
+}
+
+/// A checked payload must hold exactly one request
+pub fn well_formed(payload: &[u8]) -> bool {
+    let mut rest = payload;
+    read_request(&mut rest).is_ok() && rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays back reads, a None standing for a read timeout
+    struct Line(Vec<Option<u8>>);
+
+    impl Read for Line {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.first().copied() {
+                None => Ok(0),
+                Some(step) => {
+                    self.0.remove(0);
+                    match step {
+                        Some(byte) => {
+                            buf[0] = byte;
+                            Ok(1)
+                        }
+                        None => {
+                            std::thread::sleep(Duration::from_millis(5));
+                            Err(ErrorKind::TimedOut.into())
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut buf = [0u8; 2];
+
+        // Idle: timeouts are waited out however long they last
+        let mut idle = Deadline::new(Line(vec![None, None, None, Some(1), Some(2)]), Some(Duration::ZERO));
+        read_exact(&mut idle, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2]);
+
+        // Mid-frame: silence past the limit fails the read
+        let mut stalled = Deadline::new(Line(vec![Some(1), None, Some(2)]), Some(Duration::from_millis(1)));
+        stalled.arm();
+        assert_eq!(read_exact(&mut stalled, &mut buf).unwrap_err().kind(), ErrorKind::TimedOut);
+        stalled.disarm();
+        read_exact(&mut stalled, &mut buf[..1]).unwrap();
+        assert_eq!(buf[0], 2);
+
+        // No limit: mid-frame silences are waited out too
+        let mut patient = Deadline::new(Line(vec![Some(1), None, Some(2)]), None);
+        patient.arm();
+        read_exact(&mut patient, &mut buf).unwrap();
+        assert_eq!(read_exact(&mut patient, &mut buf).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
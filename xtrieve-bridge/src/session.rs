@@ -0,0 +1,130 @@
+//! Per-session counters and frame dumps
+//!
+//! Every DOS session (a DOSBox-X connection, a serial device...) counts
+//! requests, bytes each way, resyncs (garbage skipped before a marker),
+//! CRC errors, retransmissions, backend reconnects and cache hits. The
+//! totals are logged when the session ends and, with [`report_every`],
+//! periodically for every session still running.
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use tracing::info;
+
+static SESSIONS: Mutex<Vec<Weak<Session>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Peer address or device path
+    pub name: String,
+    /// Log every frame as a hex dump
+    pub hex: bool,
+    pub requests: AtomicU64,
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub resyncs: AtomicU64,
+    pub crc_errors: AtomicU64,
+    pub retransmits: AtomicU64,
+    pub reconnects: AtomicU64,
+    pub cache_hits: AtomicU64,
+}
+
+impl Session {
+    /// A session the periodic report covers until it is dropped
+    pub fn start(name: String, hex: bool) -> Arc<Self> {
+        let session = Arc::new(Session { name, hex, ..Default::default() });
+        let mut sessions = SESSIONS.lock().unwrap();
+        sessions.retain(|s| s.strong_count() > 0);
+        sessions.push(Arc::downgrade(&session));
+        session
+    }
+
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Log the counters with `message`
+    pub fn log(&self, message: &str) {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        info!(
+            session = %self.name,
+            requests = get(&self.requests),
+            bytes_in = get(&self.bytes_in),
+            bytes_out = get(&self.bytes_out),
+            resyncs = get(&self.resyncs),
+            crc_errors = get(&self.crc_errors),
+            retransmits = get(&self.retransmits),
+            reconnects = get(&self.reconnects),
+            cache_hits = get(&self.cache_hits),
+            "{}",
+            message
+        );
+    }
+
+    /// Log a frame sent or received when hex dumps are on
+    pub fn dump(&self, what: &str, bytes: &[u8]) {
+        if self.hex {
+            info!(session = %self.name, "{} ({} bytes)\n{}", what, bytes.len(), hex_dump(bytes));
+        }
+    }
+}
+
+/// Log every running session's counters every `interval`
+pub fn report_every(interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let sessions: Vec<_> = SESSIONS.lock().unwrap().iter().filter_map(Weak::upgrade).collect();
+        for session in sessions {
+            session.log("session stats");
+        }
+    });
+}
+
+/// Offset, 16 bytes in hex and the printable ones, per line
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(out, "  {:04x} ", line * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(b) => {
+                    let _ = write!(out, " {:02x}", b);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out.pop();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_dump_and_registry() {
+        let dump = hex_dump(b"\xbb\xbbTEST.DAT\x00\x01\x02\x03\x04\x05\x06\x07");
+        assert_eq!(
+            dump,
+            "  0000  bb bb 54 45 53 54 2e 44 41 54 00 01 02 03 04 05  |..TEST.DAT......|\n  \
+             0010  06 07                                            |..|"
+        );
+
+        let session = Session::start("COM1".into(), false);
+        Session::add(&session.requests, 2);
+        assert_eq!(session.requests.load(Ordering::Relaxed), 2);
+        let live = |name: &str| {
+            SESSIONS.lock().unwrap().iter().filter_map(Weak::upgrade).any(|s| s.name == name)
+        };
+        assert!(live("COM1"));
+        drop(session);
+        assert!(!live("COM1"));
+    }
+}
@@ -0,0 +1,10 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::configure()
+            .build_server(false)
+            .compile_protos(&["../proto/xtrieve.proto"], &["../proto"])?;
+    }
+    Ok(())
+}
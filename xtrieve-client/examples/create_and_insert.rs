@@ -44,29 +44,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }],
     };
 
-    // Build data buffer for Create (file spec format)
-    // Format: record_length(2) + page_size(2) + num_keys(2) + reserved(4) = 10 bytes header
-    // Then: key_specs (16 bytes each)
-    let mut data_buffer = Vec::new();
-    data_buffer.extend_from_slice(&100u16.to_le_bytes()); // record_length (0-1)
-    data_buffer.extend_from_slice(&4096u16.to_le_bytes()); // page_size (2-3)
-    data_buffer.extend_from_slice(&1u16.to_le_bytes()); // num_keys (4-5)
-    data_buffer.extend_from_slice(&0u32.to_le_bytes()); // reserved (6-9)
-
-    // Key spec at offset 10 (16 bytes per key)
-    // Format: position(2) + length(2) + flags(2) + key_type(1) + null_value(1) + reserved(8)
-    data_buffer.extend_from_slice(&0u16.to_le_bytes()); // position
-    data_buffer.extend_from_slice(&20u16.to_le_bytes()); // length
-    data_buffer.extend_from_slice(&0u16.to_le_bytes()); // flags (0 = no duplicates, modifiable)
-    data_buffer.push(0); // key_type (0 = STRING)
-    data_buffer.push(0); // null_value
-    data_buffer.extend_from_slice(&[0u8; 8]); // reserved padding
-
     let create_req = BtrieveRequest {
         operation_code: OP_CREATE,
         file_path: test_file.to_string(),
-        data_buffer,
-        data_buffer_length: 26,
+        file_spec: Some(file_spec),
         ..Default::default()
     };
 
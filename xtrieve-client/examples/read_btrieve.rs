@@ -38,7 +38,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     })?;
 
     println!("Records from Btrieve 5.1 file:");
-    println!("{:<8} {}", "ID", "Name");
+    println!("{:<8} Name", "ID");
     println!("{}", "-".repeat(40));
 
     let mut count = 0;
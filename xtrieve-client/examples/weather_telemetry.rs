@@ -98,12 +98,12 @@ impl WeatherObservation {
         // WMO Weather interpretation codes
         match self.weather_code {
             0 => "Clear sky",
-            1 | 2 | 3 => "Partly cloudy",
+            1..=3 => "Partly cloudy",
             45 | 48 => "Foggy",
             51 | 53 | 55 => "Drizzle",
             61 | 63 | 65 => "Rain",
             71 | 73 | 75 => "Snow",
-            80 | 81 | 82 => "Rain showers",
+            80..=82 => "Rain showers",
             95 => "Thunderstorm",
             96 | 99 => "Thunderstorm with hail",
             _ => "Unknown",
@@ -237,7 +237,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Fetch weather data for all cities
     println!("Fetching weather data from Open-Meteo API...\n");
-    println!("{:<15} {:>8} {:>6} {:>10} {}", "City", "Temp(C)", "Humid%", "Wind km/h", "Conditions");
+    println!("{:<15} {:>8} {:>6} {:>10} Conditions", "City", "Temp(C)", "Humid%", "Wind km/h");
     println!("{}", "-".repeat(60));
 
     let mut observations = Vec::new();
@@ -287,7 +287,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Read back all records
     println!("Reading all observations from database...\n");
-    println!("{:<20} {:<15} {:>8} {:>6} {:>10} {}", "Timestamp", "City", "Temp(C)", "Humid%", "Wind km/h", "Conditions");
+    println!("{:<20} {:<15} {:>8} {:>6} {:>10} Conditions", "Timestamp", "City", "Temp(C)", "Humid%", "Wind km/h");
     println!("{}", "-".repeat(80));
 
     // Get first record
@@ -58,12 +58,12 @@ impl WeatherObservation {
 
         let weather_description = match weather_code {
             0 => "Clear sky",
-            1 | 2 | 3 => "Partly cloudy",
+            1..=3 => "Partly cloudy",
             45 | 48 => "Foggy",
             51 | 53 | 55 => "Drizzle",
             61 | 63 | 65 => "Rain",
             71 | 73 | 75 => "Snow",
-            80 | 81 | 82 => "Rain showers",
+            80..=82 => "Rain showers",
             95 => "Thunderstorm",
             96 | 99 => "Thunderstorm with hail",
             _ => "Unknown",
@@ -124,10 +124,8 @@ async fn fetch_observations(client: &mut AsyncXtrieveClient) -> Vec<WeatherObser
         }
     };
 
-    let mut current_pos = pos_block.clone();
-
     if first_resp.status_code == 0 {
-        current_pos = first_resp.position_block.clone();
+        let mut current_pos = first_resp.position_block.clone();
         if let Some(obs) = WeatherObservation::from_bytes(&first_resp.data_buffer) {
             observations.push(obs);
         }
@@ -2,8 +2,20 @@
 //!
 //! This module provides a familiar API for developers who have used Btrieve.
 
-use crate::client::{XtrieveClient, BtrieveRequest};
-use xtrieve_engine::{BtrieveError, BtrieveResult, StatusCode};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+use std::sync::Arc;
+
+use crate::client::{XtrieveClient, BtrieveRequest, BtrieveResponse};
+use crate::ddf::{Column, Table, Value};
+use crate::typed::{self, TypedRecord};
+use crate::builder::{FileBuilder, KeyBuilder};
+use xtrieve_engine::file_manager::cursor::CURSOR_ID;
+use xtrieve_engine::storage::fcr::FileFlags;
+use xtrieve_engine::protocol::BTRIEVE_OPCODES_VERSION;
+use xtrieve_engine::storage::{KeyFlags, KeySpec, KeyType};
+use xtrieve_engine::{BtrieveError, BtrieveResult, ServerInfo, StatusCode, POSITION_BLOCK_SIZE};
 
 /// Operation codes (matching Btrieve)
 pub mod op {
@@ -29,9 +41,277 @@ pub mod op {
     pub const GET_POSITION: u32 = 22;
     pub const GET_DIRECT: u32 = 23;
     pub const STEP_NEXT: u32 = 24;
+    pub const UNLOCK: u32 = 27;
     pub const STEP_FIRST: u32 = 33;
     pub const STEP_LAST: u32 = 34;
     pub const STEP_PREVIOUS: u32 = 35;
+    pub const INSERT_EXTENDED: u32 = 40;
+    pub const CANCEL: u32 = 89;
+    pub const LOCK_REPORT: u32 = 90;
+    pub const TRUNCATE_FILE: u32 = 91;
+    pub const CLONE_FILE: u32 = 92;
+    pub const DELETE_FILE: u32 = 93;
+    pub const RENAME_FILE: u32 = 94;
+    pub const GET_DELETED: u32 = 95;
+    pub const UNDELETE: u32 = 96;
+    pub const QUERY: u32 = 97;
+    pub const SERVER_INFO: u32 = 98;
+    pub const PING: u32 = 99;
+}
+
+/// Turn a non-zero Btrieve status into an error
+pub(crate) fn check_status(response: BtrieveResponse) -> BtrieveResult<BtrieveResponse> {
+    if response.status_code != 0 {
+        return Err(response.to_error());
+    }
+    Ok(response)
+}
+
+/// Longest owner name Btrieve 5.1 accepts
+const MAX_OWNER_LENGTH: usize = 8;
+
+/// Key buffer carrying an owner name, null-terminated
+fn owner_buffer(owner: Option<&str>) -> BtrieveResult<Vec<u8>> {
+    match owner {
+        Some(owner) if owner.is_empty() || owner.len() > MAX_OWNER_LENGTH || owner.contains('\0') => {
+            Err(BtrieveError::Status(StatusCode::InvalidOwner))
+        }
+        Some(owner) => Ok([owner.as_bytes(), &[0]].concat()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Build an Open request; an owner name goes in the key buffer
+pub(crate) fn open_request(path: &str, mode: i32, owner: Option<&str>) -> BtrieveResult<BtrieveRequest> {
+    let key_buffer = owner_buffer(owner)?;
+    Ok(BtrieveRequest {
+        operation_code: op::OPEN,
+        file_path: path.to_string(),
+        open_mode: mode,
+        key_buffer_length: key_buffer.len() as u32,
+        key_buffer,
+        ..Default::default()
+    })
+}
+
+/// Record lock taken by a read, sent as its lock bias
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// One record at a time, waiting for another session's lock to go
+    SingleWait,
+    /// One record at a time, failing with Record In Use (79) at once
+    SingleNoWait,
+    /// Several records held together until `unlock_all`, waiting
+    MultiWait,
+    /// Several records held together until `unlock_all`, not waiting
+    MultiNoWait,
+}
+
+impl LockKind {
+    pub fn bias(&self) -> u32 {
+        match self {
+            LockKind::SingleWait => 100,
+            LockKind::SingleNoWait => 200,
+            LockKind::MultiWait => 300,
+            LockKind::MultiNoWait => 400,
+        }
+    }
+}
+
+/// Unlock (27) of the single record lock, or with `multi` of every
+/// multiple record lock
+fn unlock_request(position_block: &[u8], multi: bool) -> BtrieveRequest {
+    BtrieveRequest {
+        operation_code: op::UNLOCK,
+        position_block: position_block.to_vec(),
+        key_number: if multi { -2 } else { 0 },
+        ..Default::default()
+    }
+}
+
+/// Largest InsertExtended (40) data buffer sent in one request
+const MAX_INSERT_BUFFER: usize = u16::MAX as usize;
+
+/// Outcome of `insert_many`
+#[derive(Debug, Default)]
+pub struct InsertReport {
+    /// Number of records inserted
+    pub inserted: usize,
+    /// Index into the input and the status each rejected record failed with
+    pub failures: Vec<(usize, BtrieveError)>,
+}
+
+impl InsertReport {
+    /// True when every record was inserted
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Account for one InsertExtended batch of `count` records starting at
+    /// `start`, returning the index of the next record to send
+    fn record_batch(&mut self, start: usize, count: usize, response: &BtrieveResponse) -> usize {
+        if response.status_code == 0 {
+            self.inserted += count;
+            return start + count;
+        }
+        // The reply carries how many records went in before the failing one
+        let done = match response.data_buffer.get(0..2) {
+            Some(n) => (u16::from_le_bytes([n[0], n[1]]) as usize).min(count - 1),
+            None => 0,
+        };
+        self.inserted += done;
+        self.failures.push((start + done, response.to_error()));
+        start + done + 1
+    }
+}
+
+/// Pack as many leading `records` as fit into one InsertExtended buffer:
+/// `[count:2]` then `[length:2][record]` per record. The first record is
+/// always packed so an oversized one is rejected by the server, not skipped.
+fn pack_insert_extended<R: AsRef<[u8]>>(records: &[R]) -> (Vec<u8>, usize) {
+    let mut buf = vec![0u8; 2];
+    let mut count = 0usize;
+    for record in records {
+        let record = record.as_ref();
+        if count > 0 && buf.len() + 2 + record.len() > MAX_INSERT_BUFFER {
+            break;
+        }
+        buf.extend_from_slice(&(record.len() as u16).to_le_bytes());
+        buf.extend_from_slice(record);
+        count += 1;
+    }
+    buf[0..2].copy_from_slice(&(count as u16).to_le_bytes());
+    (buf, count)
+}
+
+/// Whether a ServerInfo reply advertises InsertExtended; servers too old
+/// to answer ServerInfo don't have it either
+fn supports_insert_extended(info: BtrieveResult<ServerInfo>) -> BtrieveResult<bool> {
+    match info {
+        Ok(info) => Ok(info.supports(op::INSERT_EXTENDED as u16)),
+        Err(BtrieveError::Status(_) | BtrieveError::Detailed { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether a ServerInfo reply advertises Unlock as 27. Older servers took
+/// 27 for Find Percentage, so sending it there would report success and
+/// leave the lock in place
+fn supports_unlock(info: BtrieveResult<ServerInfo>) -> BtrieveResult<bool> {
+    match info {
+        Ok(info) => Ok(info.protocol_version >= BTRIEVE_OPCODES_VERSION && info.supports(op::UNLOCK as u16)),
+        Err(BtrieveError::Status(_) | BtrieveError::Detailed { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Position block bytes holding cursor state; the file path and session
+/// ID after them belong to the handle, not the saved position
+const CURSOR_STATE_LEN: usize = 64;
+
+/// Format version of `SavedPosition::to_bytes`
+const SAVED_POSITION_VERSION: u8 = 1;
+
+/// A file's cursor, saved so it can be restored later, even in another process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedPosition {
+    /// Path the file was opened with
+    pub file_path: String,
+    /// Current key number
+    pub key_number: i32,
+    /// Raw position block
+    pub position_block: Vec<u8>,
+}
+
+impl SavedPosition {
+    /// Serialize as `[version:1][key_number:4][path_len:2][path][position_block:128]`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![SAVED_POSITION_VERSION];
+        buf.extend_from_slice(&self.key_number.to_le_bytes());
+        buf.extend_from_slice(&(self.file_path.len() as u16).to_le_bytes());
+        buf.extend_from_slice(self.file_path.as_bytes());
+        let mut block = self.position_block.clone();
+        block.resize(POSITION_BLOCK_SIZE, 0);
+        buf.extend_from_slice(&block);
+        buf
+    }
+
+    /// Parse bytes written by `to_bytes`
+    pub fn from_bytes(data: &[u8]) -> BtrieveResult<Self> {
+        let bad = || BtrieveError::Internal("Bad saved position".to_string());
+        if data.first() != Some(&SAVED_POSITION_VERSION) || data.len() < 7 {
+            return Err(bad());
+        }
+        let key_number = i32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+        let path_len = u16::from_le_bytes([data[5], data[6]]) as usize;
+        let rest = &data[7..];
+        if rest.len() != path_len + POSITION_BLOCK_SIZE {
+            return Err(bad());
+        }
+        let file_path = String::from_utf8(rest[..path_len].to_vec()).map_err(|_| bad())?;
+
+        Ok(SavedPosition {
+            file_path,
+            key_number,
+            position_block: rest[path_len..].to_vec(),
+        })
+    }
+}
+
+/// Overlay a saved cursor onto a handle's position block, keeping the
+/// handle's own cursor id, file path, check and session
+fn restore_cursor(position_block: &mut Vec<u8>, file_path: &str, saved: &SavedPosition) -> BtrieveResult<()> {
+    if saved.file_path != file_path || saved.position_block.len() < CURSOR_STATE_LEN {
+        return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
+    }
+    position_block.resize(POSITION_BLOCK_SIZE, 0);
+    let cursor_id = position_block[CURSOR_ID];
+    position_block[..CURSOR_STATE_LEN].copy_from_slice(&saved.position_block[..CURSOR_STATE_LEN]);
+    position_block[CURSOR_ID] = cursor_id;
+    Ok(())
+}
+
+/// Record layout from Stat, used to fit buffers before Insert and Update
+#[derive(Debug, Clone, Copy)]
+struct RecordShape {
+    length: usize,
+    variable: bool,
+}
+
+impl RecordShape {
+    /// Shape from a Stat reply; `None` if the server can't Stat the file
+    fn from_stat(stat: BtrieveResult<FileStatistics>) -> BtrieveResult<Option<Self>> {
+        match stat {
+            Ok(stat) => Ok(Some(RecordShape {
+                length: stat.record_length as usize,
+                variable: stat.flags.contains(FileFlags::VARIABLE_LENGTH),
+            })),
+            Err(BtrieveError::Status(_) | BtrieveError::Detailed { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Zero-pad a short record to the record length, and cut a fixed-length
+    /// record down to it; variable-length records are never truncated
+    fn fit<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        if data.len() < self.length {
+            let mut padded = data.to_vec();
+            padded.resize(self.length, 0);
+            Cow::Owned(padded)
+        } else if data.len() > self.length && !self.variable {
+            Cow::Borrowed(&data[..self.length])
+        } else {
+            Cow::Borrowed(data)
+        }
+    }
+}
+
+/// Fit `data` to `shape` if auto-padding is on
+fn fit_record<'a>(shape: Option<RecordShape>, auto_pad: bool, data: &'a [u8]) -> Cow<'a, [u8]> {
+    match shape {
+        Some(shape) if auto_pad => shape.fit(data),
+        _ => Cow::Borrowed(data),
+    }
 }
 
 /// A record retrieved from a Btrieve file
@@ -41,6 +321,59 @@ pub struct BtrieveRecord {
     pub data: Vec<u8>,
     /// Current key value
     pub key: Vec<u8>,
+    /// Dictionary table of the file it came from
+    schema: Option<Arc<Table>>,
+}
+
+/// A record kept in the recycle bin of a file created with one
+#[derive(Debug, Clone)]
+pub struct DeletedRecord {
+    /// Record data
+    pub data: Vec<u8>,
+    /// When it was deleted, in seconds since the Unix epoch
+    pub deleted_at: u64,
+}
+
+impl BtrieveRecord {
+    /// Decode the record data into a typed struct
+    pub fn decode<T: TypedRecord>(&self) -> BtrieveResult<T> {
+        T::from_record(&self.data)
+    }
+
+    /// Columns of the record, from the dictionary describing its file
+    /// (empty if none does, see `XtrieveClient::load_dictionary`)
+    pub fn fields(&self) -> &[Column] {
+        self.schema.as_deref().map_or(&[], |table| &table.columns)
+    }
+
+    /// Column by name, ignoring case
+    fn column(&self, name: &str) -> BtrieveResult<&Column> {
+        let table = self
+            .schema
+            .as_deref()
+            .ok_or_else(|| BtrieveError::Internal("No dictionary describes this record".to_string()))?;
+        table
+            .column(name)
+            .ok_or_else(|| BtrieveError::Internal(format!("{} has no field {}", table.name, name)))
+    }
+
+    /// Decode the field called `name`
+    pub fn field(&self, name: &str) -> BtrieveResult<Value> {
+        self.column(name)?.decode(&self.data)
+    }
+
+    /// Raw bytes of the field called `name`
+    pub fn field_bytes(&self, name: &str) -> BtrieveResult<&[u8]> {
+        let column = self.column(name)?;
+        typed::slice(&self.data, column.offset as usize, column.size as usize)
+    }
+
+    /// Store a value, written the way `Value` displays it, in the field
+    /// called `name`; pass the data to `update` to write it back
+    pub fn set_field(&mut self, name: &str, text: &str) -> BtrieveResult<()> {
+        let column = self.column(name)?.clone();
+        column.encode(text, &mut self.data)
+    }
 }
 
 /// Handle to an open Btrieve file
@@ -49,26 +382,54 @@ pub struct BtrieveFile {
     file_path: String,
     position_block: Vec<u8>,
     current_key: i32,
+    record_shape: Option<RecordShape>,
+    auto_pad: bool,
+    /// Dictionary table describing the file, if the client has one
+    schema: Option<Arc<Table>>,
 }
 
 impl BtrieveFile {
     /// Open a Btrieve file
-    pub fn open(mut client: XtrieveClient, path: &str, mode: i32) -> BtrieveResult<Self> {
-        let request = BtrieveRequest {
-            operation_code: op::OPEN,
-            file_path: path.to_string(),
-            open_mode: mode,
-            ..Default::default()
-        };
+    pub fn open(client: XtrieveClient, path: &str, mode: i32) -> BtrieveResult<Self> {
+        Self::open_request(client, open_request(path, mode, None)?)
+    }
+
+    /// Open a file protected with an owner name (up to 8 characters)
+    pub fn open_with_owner(client: XtrieveClient, path: &str, mode: i32, owner: &str) -> BtrieveResult<Self> {
+        Self::open_request(client, open_request(path, mode, Some(owner))?)
+    }
 
-        let response = client.execute(request)?;
+    fn open_request(mut client: XtrieveClient, request: BtrieveRequest) -> BtrieveResult<Self> {
+        let path = request.file_path.clone();
+        let response = check_status(client.execute(request)?)?;
 
-        Ok(BtrieveFile {
+        let schema = client.schema(&path);
+        let mut file = BtrieveFile {
             client,
-            file_path: path.to_string(),
+            file_path: path,
             position_block: response.position_block,
             current_key: 0,
-        })
+            record_shape: None,
+            auto_pad: true,
+            schema,
+        };
+        file.record_shape = RecordShape::from_stat(file.stat())?;
+        Ok(file)
+    }
+
+    /// Columns of the file, from the dictionary the client loaded for it
+    /// when it was opened (empty if none describes it)
+    pub fn fields(&self) -> &[Column] {
+        self.schema.as_deref().map_or(&[], |table| &table.columns)
+    }
+
+    /// Turn automatic record fitting on or off (on by default).
+    ///
+    /// When on, Insert and Update zero-pad records shorter than the file's
+    /// record length and truncate longer ones on fixed-length files, which
+    /// would otherwise fail with status 22.
+    pub fn set_auto_pad(&mut self, enabled: bool) {
+        self.auto_pad = enabled;
     }
 
     /// Close the file
@@ -80,12 +441,13 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        self.client.execute(request)?;
+        check_status(self.client.execute(request)?)?;
         Ok(())
     }
 
     /// Insert a record
     pub fn insert(&mut self, data: &[u8]) -> BtrieveResult<()> {
+        let data = fit_record(self.record_shape, self.auto_pad, data);
         let request = BtrieveRequest {
             operation_code: op::INSERT,
             position_block: self.position_block.clone(),
@@ -94,13 +456,73 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
         Ok(())
     }
 
+    /// Insert a typed record
+    pub fn insert_typed<T: TypedRecord>(&mut self, record: &T) -> BtrieveResult<()> {
+        self.insert(&record.to_record()?)
+    }
+
+    /// Update the current record from a typed struct
+    pub fn update_typed<T: TypedRecord>(&mut self, record: &T) -> BtrieveResult<()> {
+        self.update(&record.to_record()?)
+    }
+
+    /// Get Equal, decoding the record into a typed struct
+    pub fn get_typed<T: TypedRecord>(&mut self, key: &[u8]) -> BtrieveResult<T> {
+        self.get_equal(key)?.decode()
+    }
+
+    /// Insert many records, batched into InsertExtended (40) requests when
+    /// the server supports it and one Insert per record otherwise.
+    ///
+    /// A record rejected with a Btrieve status is listed in the report and
+    /// the remaining records are still inserted; a connection error stops
+    /// the whole call.
+    pub fn insert_many<R: AsRef<[u8]>>(&mut self, records: &[R]) -> BtrieveResult<InsertReport> {
+        let mut report = InsertReport::default();
+
+        if !supports_insert_extended(self.client.server_info())? {
+            for (index, record) in records.iter().enumerate() {
+                match self.insert(record.as_ref()) {
+                    Ok(()) => report.inserted += 1,
+                    Err(e @ (BtrieveError::Status(_) | BtrieveError::Detailed { .. })) => report.failures.push((index, e)),
+                    Err(e) => return Err(e),
+                }
+            }
+            return Ok(report);
+        }
+
+        let records: Vec<_> = records
+            .iter()
+            .map(|record| fit_record(self.record_shape, self.auto_pad, record.as_ref()))
+            .collect();
+        let mut next = 0;
+        while next < records.len() {
+            let (data, count) = pack_insert_extended(&records[next..]);
+            let request = BtrieveRequest {
+                operation_code: op::INSERT_EXTENDED,
+                position_block: self.position_block.clone(),
+                data_buffer_length: data.len() as u32,
+                data_buffer: data,
+                ..Default::default()
+            };
+
+            let response = self.client.execute(request)?;
+            next = report.record_batch(next, count, &response);
+            if response.status_code == 0 {
+                self.position_block = response.position_block;
+            }
+        }
+        Ok(report)
+    }
+
     /// Update the current record
     pub fn update(&mut self, data: &[u8]) -> BtrieveResult<()> {
+        let data = fit_record(self.record_shape, self.auto_pad, data);
         let request = BtrieveRequest {
             operation_code: op::UPDATE,
             position_block: self.position_block.clone(),
@@ -109,7 +531,7 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
         Ok(())
     }
@@ -122,16 +544,81 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
         Ok(())
     }
 
+    /// A record from the file's recycle bin, `back` places from the most
+    /// recently deleted, left in the bin. `EndOfFile` past the oldest
+    pub fn get_deleted(&mut self, back: u32) -> BtrieveResult<DeletedRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::GET_DELETED,
+            position_block: self.position_block.clone(),
+            key_number: back as i32,
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        let mut deleted_at = [0u8; 8];
+        let len = response.key_buffer.len().min(8);
+        deleted_at[..len].copy_from_slice(&response.key_buffer[..len]);
+        Ok(DeletedRecord { data: response.data_buffer, deleted_at: u64::from_le_bytes(deleted_at) })
+    }
+
+    /// Insert a record from the recycle bin again, `back` places from the
+    /// most recently deleted, and take it out of the bin. The restored
+    /// record becomes current
+    pub fn undelete(&mut self, back: u32) -> BtrieveResult<BtrieveRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::UNDELETE,
+            position_block: self.position_block.clone(),
+            key_number: back as i32,
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        self.position_block = response.position_block;
+        Ok(BtrieveRecord { data: response.data_buffer, key: Vec::new(), schema: self.schema.clone() })
+    }
+
     /// Set the current key number for subsequent operations
     pub fn set_key(&mut self, key_number: i32) {
         self.current_key = key_number;
     }
 
+    /// Save the cursor (position block and current key)
+    pub fn save_position(&self) -> SavedPosition {
+        SavedPosition {
+            file_path: self.file_path.clone(),
+            key_number: self.current_key,
+            position_block: self.position_block.clone(),
+        }
+    }
+
+    /// Put back a cursor from `save_position`, which may come from an earlier
+    /// handle or process; the file must have been opened with the same path.
+    /// The next GetNext/GetPrevious continues from the saved record.
+    pub fn restore_position(&mut self, saved: &SavedPosition) -> BtrieveResult<()> {
+        restore_cursor(&mut self.position_block, &self.file_path, saved)?;
+        self.current_key = saved.key_number;
+        Ok(())
+    }
+
+    /// Iterate over all records in `key_number` order.
+    ///
+    /// Drives GetFirst then GetNext and stops at end of file; any other
+    /// error is yielded once and ends the iteration. `key_number` becomes
+    /// the current key.
+    pub fn records(&mut self, key_number: i32) -> Records<'_> {
+        self.current_key = key_number;
+        Records {
+            file: self,
+            started: false,
+            done: false,
+        }
+    }
+
     /// Get Equal - find record by exact key match
     pub fn get_equal(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
         let request = BtrieveRequest {
@@ -143,12 +630,78 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: response.key_buffer,
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Get Equal, locking the record found. A record another session
+    /// holds fails with Record In Use (79), or with status 78 once the
+    /// server's lock timeout passes
+    pub fn get_equal_locked(&mut self, key: &[u8], lock: LockKind) -> BtrieveResult<BtrieveRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::GET_EQUAL,
+            position_block: self.position_block.clone(),
+            key_buffer: key.to_vec(),
+            key_buffer_length: key.len() as u32,
+            key_number: self.current_key,
+            lock_bias: lock.bias(),
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        self.position_block = response.position_block;
+
+        Ok(BtrieveRecord {
+            data: response.data_buffer,
+            key: response.key_buffer,
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Release the single record lock held on this file. Locks taken in a
+    /// transaction are kept until it ends. A server from before protocol
+    /// version 6 has no Unlock, and this fails with status 1
+    pub fn unlock(&mut self) -> BtrieveResult<()> {
+        if !supports_unlock(self.client.server_info())? {
+            return Err(BtrieveError::Status(StatusCode::InvalidOperation));
+        }
+        check_status(self.client.execute(unlock_request(&self.position_block, false))?)?;
+        Ok(())
+    }
+
+    /// Release every multiple record lock held on this file
+    pub fn unlock_all(&mut self) -> BtrieveResult<()> {
+        if !supports_unlock(self.client.server_info())? {
+            return Err(BtrieveError::Status(StatusCode::InvalidOperation));
+        }
+        check_status(self.client.execute(unlock_request(&self.position_block, true))?)?;
+        Ok(())
+    }
+
+    /// Iterate over the records whose `key_number` value falls in `bounds`.
+    ///
+    /// Positions with GetGreaterOrEqual (or GetFirst for an open start) and
+    /// walks GetNext until a key passes the end bound, so every duplicate of
+    /// a boundary value is included. Bounds are key values, compared by the
+    /// key's type; for descending keys the walk runs from `end` down to `start`.
+    pub fn range<K: AsRef<[u8]>>(
+        &mut self,
+        key_number: i32,
+        bounds: impl RangeBounds<K>,
+    ) -> BtrieveResult<KeyRange<'_>> {
+        let bounds = KeyBounds::new(&self.stat()?, key_number, bounds)?;
+        self.current_key = key_number;
+        Ok(KeyRange {
+            file: self,
+            bounds,
+            started: false,
+            done: false,
         })
     }
 
@@ -161,15 +714,27 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: response.key_buffer,
+            schema: self.schema.clone(),
         })
     }
 
+    /// GetNext for iterators. A reply that leaves the cursor where it was
+    /// would repeat forever, so it ends the walk like end of file.
+    fn advance(&mut self) -> BtrieveResult<BtrieveRecord> {
+        let before = self.position_block.clone();
+        let record = self.get_next()?;
+        if self.position_block == before {
+            return Err(BtrieveError::Status(StatusCode::EndOfFile));
+        }
+        Ok(record)
+    }
+
     /// Get Previous - get previous record in key order
     pub fn get_previous(&mut self) -> BtrieveResult<BtrieveRecord> {
         let request = BtrieveRequest {
@@ -179,12 +744,13 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: response.key_buffer,
+            schema: self.schema.clone(),
         })
     }
 
@@ -197,12 +763,13 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: response.key_buffer,
+            schema: self.schema.clone(),
         })
     }
 
@@ -215,12 +782,13 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: response.key_buffer,
+            schema: self.schema.clone(),
         })
     }
 
@@ -235,12 +803,13 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: response.key_buffer,
+            schema: self.schema.clone(),
         })
     }
 
@@ -255,12 +824,92 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: response.key_buffer,
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Get Less Than - get last record with key less than given
+    pub fn get_less(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::GET_LESS,
+            position_block: self.position_block.clone(),
+            key_buffer: key.to_vec(),
+            key_buffer_length: key.len() as u32,
+            key_number: self.current_key,
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        self.position_block = response.position_block;
+
+        Ok(BtrieveRecord {
+            data: response.data_buffer,
+            key: response.key_buffer,
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Get Less or Equal
+    pub fn get_less_or_equal(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::GET_LE,
+            position_block: self.position_block.clone(),
+            key_buffer: key.to_vec(),
+            key_buffer_length: key.len() as u32,
+            key_number: self.current_key,
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        self.position_block = response.position_block;
+
+        Ok(BtrieveRecord {
+            data: response.data_buffer,
+            key: response.key_buffer,
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Get Position - physical position of the current record, for a
+    /// later `get_direct`. Currency is left as it was
+    pub fn get_position(&mut self) -> BtrieveResult<u32> {
+        let request = BtrieveRequest {
+            operation_code: op::GET_POSITION,
+            position_block: self.position_block.clone(),
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        let position = response.data_buffer.get(..4)
+            .ok_or_else(|| BtrieveError::Internal("Short Get Position reply".to_string()))?;
+        Ok(u32::from_le_bytes([position[0], position[1], position[2], position[3]]))
+    }
+
+    /// Get Direct - the record at a position from `get_position`, which
+    /// becomes the current record for GetNext/GetPrevious on the current key
+    pub fn get_direct(&mut self, position: u32) -> BtrieveResult<BtrieveRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::GET_DIRECT,
+            position_block: self.position_block.clone(),
+            data_buffer: position.to_le_bytes().to_vec(),
+            data_buffer_length: 4,
+            key_number: self.current_key,
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        self.position_block = response.position_block;
+
+        Ok(BtrieveRecord {
+            data: response.data_buffer,
+            key: response.key_buffer,
+            schema: self.schema.clone(),
         })
     }
 
@@ -272,12 +921,13 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: Vec::new(),
+            schema: self.schema.clone(),
         })
     }
 
@@ -289,12 +939,49 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
         self.position_block = response.position_block;
 
         Ok(BtrieveRecord {
             data: response.data_buffer,
             key: Vec::new(),
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Step Last - get last record physically
+    pub fn step_last(&mut self) -> BtrieveResult<BtrieveRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::STEP_LAST,
+            position_block: self.position_block.clone(),
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        self.position_block = response.position_block;
+
+        Ok(BtrieveRecord {
+            data: response.data_buffer,
+            key: Vec::new(),
+            schema: self.schema.clone(),
+        })
+    }
+
+    /// Step Previous - get previous record physically
+    pub fn step_previous(&mut self) -> BtrieveResult<BtrieveRecord> {
+        let request = BtrieveRequest {
+            operation_code: op::STEP_PREVIOUS,
+            position_block: self.position_block.clone(),
+            ..Default::default()
+        };
+
+        let response = check_status(self.client.execute(request)?)?;
+        self.position_block = response.position_block;
+
+        Ok(BtrieveRecord {
+            data: response.data_buffer,
+            key: Vec::new(),
+            schema: self.schema.clone(),
         })
     }
 
@@ -307,20 +994,37 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        let response = self.client.execute(request)?;
+        let response = check_status(self.client.execute(request)?)?;
+        FileStatistics::from_bytes(&response.data_buffer)
+    }
 
-        // Parse statistics from data buffer
-        let data = &response.data_buffer;
-        if data.len() < 12 {
-            return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
-        }
+    /// Create an empty file at `path` with this file's record length, page
+    /// size, flags and keys, as BUTIL -CLONE does. The owner name isn't
+    /// copied. Xtrieve extension
+    pub fn clone_to(&mut self, path: &str) -> BtrieveResult<()> {
+        let request = BtrieveRequest {
+            operation_code: op::CLONE_FILE,
+            position_block: self.position_block.clone(),
+            data_buffer_length: path.len() as u32,
+            data_buffer: path.as_bytes().to_vec(),
+            ..Default::default()
+        };
 
-        Ok(FileStatistics {
-            record_length: u16::from_le_bytes([data[0], data[1]]),
-            page_size: u16::from_le_bytes([data[2], data[3]]),
-            num_keys: u16::from_le_bytes([data[4], data[5]]),
-            num_records: u32::from_le_bytes([data[6], data[7], data[8], data[9]]),
-        })
+        check_status(self.client.execute(request)?)?;
+        Ok(())
+    }
+
+    /// Remove every record at once, keeping the keys, flags and owner.
+    /// No other client may have the file open. Xtrieve extension
+    pub fn truncate(&mut self) -> BtrieveResult<()> {
+        let request = BtrieveRequest {
+            operation_code: op::TRUNCATE_FILE,
+            position_block: self.position_block.clone(),
+            ..Default::default()
+        };
+
+        check_status(self.client.execute(request)?)?;
+        Ok(())
     }
 
     /// Begin transaction
@@ -331,7 +1035,7 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        self.client.execute(request)?;
+        check_status(self.client.execute(request)?)?;
         Ok(())
     }
 
@@ -343,7 +1047,7 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        self.client.execute(request)?;
+        check_status(self.client.execute(request)?)?;
         Ok(())
     }
 
@@ -355,9 +1059,839 @@ impl BtrieveFile {
             ..Default::default()
         };
 
-        self.client.execute(request)?;
+        check_status(self.client.execute(request)?)?;
         Ok(())
     }
+
+    /// Begin a transaction that is aborted unless committed.
+    ///
+    /// The guard derefs to the file, so operations go through it. Dropping
+    /// it without calling `commit` sends AbortTransaction.
+    pub fn transaction(&mut self) -> BtrieveResult<Transaction<'_>> {
+        self.begin_transaction()?;
+        Ok(Transaction { file: self, active: true })
+    }
+}
+
+/// Transaction guard returned by [`BtrieveFile::transaction`]
+pub struct Transaction<'a> {
+    file: &'a mut BtrieveFile,
+    active: bool,
+}
+
+impl Transaction<'_> {
+    /// Commit with EndTransaction
+    pub fn commit(mut self) -> BtrieveResult<()> {
+        self.active = false;
+        self.file.end_transaction()
+    }
+
+    /// Roll back with AbortTransaction (same as dropping, but reports errors)
+    pub fn abort(mut self) -> BtrieveResult<()> {
+        self.active = false;
+        self.file.abort_transaction()
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = BtrieveFile;
+
+    fn deref(&self) -> &BtrieveFile {
+        self.file
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut BtrieveFile {
+        self.file
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.active {
+            // Nothing useful to do with an error while unwinding
+            let _ = self.file.abort_transaction();
+        }
+    }
+}
+
+// ============================================================================
+// Async File Handle (requires async feature)
+// ============================================================================
+
+#[cfg(feature = "async")]
+pub use async_file::AsyncBtrieveFile;
+
+#[cfg(feature = "async")]
+mod async_file {
+    use super::*;
+    use crate::client::AsyncXtrieveClient;
+    use futures_util::Stream;
+
+    /// Handle to an open Btrieve file over `AsyncXtrieveClient`
+    pub struct AsyncBtrieveFile {
+        client: AsyncXtrieveClient,
+        file_path: String,
+        position_block: Vec<u8>,
+        current_key: i32,
+        record_shape: Option<RecordShape>,
+        auto_pad: bool,
+    }
+
+    impl AsyncBtrieveFile {
+        /// Open a Btrieve file
+        pub async fn open(client: AsyncXtrieveClient, path: &str, mode: i32) -> BtrieveResult<Self> {
+            Self::open_request(client, open_request(path, mode, None)?).await
+        }
+
+        /// Open a file protected with an owner name (up to 8 characters)
+        pub async fn open_with_owner(
+            client: AsyncXtrieveClient,
+            path: &str,
+            mode: i32,
+            owner: &str,
+        ) -> BtrieveResult<Self> {
+            Self::open_request(client, open_request(path, mode, Some(owner))?).await
+        }
+
+        async fn open_request(mut client: AsyncXtrieveClient, request: BtrieveRequest) -> BtrieveResult<Self> {
+            let path = request.file_path.clone();
+            let response = check_status(client.execute(request).await?)?;
+
+            let mut file = AsyncBtrieveFile {
+                client,
+                file_path: path,
+                position_block: response.position_block,
+                current_key: 0,
+                record_shape: None,
+                auto_pad: true,
+            };
+            file.record_shape = RecordShape::from_stat(file.stat().await)?;
+            Ok(file)
+        }
+
+        /// Turn automatic record fitting on or off (see `BtrieveFile::set_auto_pad`)
+        pub fn set_auto_pad(&mut self, enabled: bool) {
+            self.auto_pad = enabled;
+        }
+
+        /// Close the file
+        pub async fn close(mut self) -> BtrieveResult<()> {
+            let request = BtrieveRequest {
+                operation_code: op::CLOSE,
+                position_block: self.position_block.clone(),
+                file_path: self.file_path.clone(),
+                ..Default::default()
+            };
+
+            check_status(self.client.execute(request).await?)?;
+            Ok(())
+        }
+
+        /// Insert a record
+        pub async fn insert(&mut self, data: &[u8]) -> BtrieveResult<()> {
+            let data = fit_record(self.record_shape, self.auto_pad, data);
+            let request = BtrieveRequest {
+                operation_code: op::INSERT,
+                position_block: self.position_block.clone(),
+                data_buffer: data.to_vec(),
+                data_buffer_length: data.len() as u32,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+            Ok(())
+        }
+
+        /// Insert a typed record
+        pub async fn insert_typed<T: TypedRecord>(&mut self, record: &T) -> BtrieveResult<()> {
+            self.insert(&record.to_record()?).await
+        }
+
+        /// Update the current record from a typed struct
+        pub async fn update_typed<T: TypedRecord>(&mut self, record: &T) -> BtrieveResult<()> {
+            self.update(&record.to_record()?).await
+        }
+
+        /// Get Equal, decoding the record into a typed struct
+        pub async fn get_typed<T: TypedRecord>(&mut self, key: &[u8]) -> BtrieveResult<T> {
+            self.get_equal(key).await?.decode()
+        }
+
+        /// Insert many records, batched into InsertExtended (40) requests when
+        /// the server supports it and one Insert per record otherwise
+        pub async fn insert_many<R: AsRef<[u8]>>(&mut self, records: &[R]) -> BtrieveResult<InsertReport> {
+            let mut report = InsertReport::default();
+
+            if !supports_insert_extended(self.client.server_info().await)? {
+                for (index, record) in records.iter().enumerate() {
+                    match self.insert(record.as_ref()).await {
+                        Ok(()) => report.inserted += 1,
+                        Err(e @ (BtrieveError::Status(_) | BtrieveError::Detailed { .. })) => report.failures.push((index, e)),
+                        Err(e) => return Err(e),
+                    }
+                }
+                return Ok(report);
+            }
+
+            let records: Vec<_> = records
+                .iter()
+                .map(|record| fit_record(self.record_shape, self.auto_pad, record.as_ref()))
+                .collect();
+            let mut next = 0;
+            while next < records.len() {
+                let (data, count) = pack_insert_extended(&records[next..]);
+                let request = BtrieveRequest {
+                    operation_code: op::INSERT_EXTENDED,
+                    position_block: self.position_block.clone(),
+                    data_buffer_length: data.len() as u32,
+                    data_buffer: data,
+                    ..Default::default()
+                };
+
+                let response = self.client.execute(request).await?;
+                next = report.record_batch(next, count, &response);
+                if response.status_code == 0 {
+                    self.position_block = response.position_block;
+                }
+            }
+            Ok(report)
+        }
+
+        /// Update the current record
+        pub async fn update(&mut self, data: &[u8]) -> BtrieveResult<()> {
+            let data = fit_record(self.record_shape, self.auto_pad, data);
+            let request = BtrieveRequest {
+                operation_code: op::UPDATE,
+                position_block: self.position_block.clone(),
+                data_buffer: data.to_vec(),
+                data_buffer_length: data.len() as u32,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+            Ok(())
+        }
+
+        /// Delete the current record
+        pub async fn delete(&mut self) -> BtrieveResult<()> {
+            let request = BtrieveRequest {
+                operation_code: op::DELETE,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+            Ok(())
+        }
+
+        /// Set the current key number for subsequent operations
+        pub fn set_key(&mut self, key_number: i32) {
+            self.current_key = key_number;
+        }
+
+        /// Save the cursor (see `BtrieveFile::save_position`)
+        pub fn save_position(&self) -> SavedPosition {
+            SavedPosition {
+                file_path: self.file_path.clone(),
+                key_number: self.current_key,
+                position_block: self.position_block.clone(),
+            }
+        }
+
+        /// Put back a saved cursor (see `BtrieveFile::restore_position`)
+        pub fn restore_position(&mut self, saved: &SavedPosition) -> BtrieveResult<()> {
+            restore_cursor(&mut self.position_block, &self.file_path, saved)?;
+            self.current_key = saved.key_number;
+            Ok(())
+        }
+
+        /// Stream all records in `key_number` order (see `BtrieveFile::records`)
+        pub fn records(&mut self, key_number: i32) -> impl Stream<Item = BtrieveResult<BtrieveRecord>> + '_ {
+            self.current_key = key_number;
+            // State: Some(first call?) while running, None once finished
+            futures_util::stream::unfold((self, Some(true)), |(file, state)| async move {
+                let first = state?;
+                let result = if first {
+                    file.get_first().await
+                } else {
+                    file.advance().await
+                };
+                match result {
+                    Ok(record) => Some((Ok(record), (file, Some(false)))),
+                    Err(BtrieveError::Status(StatusCode::EndOfFile)) => None,
+                    Err(e) => Some((Err(e), (file, None))),
+                }
+            })
+        }
+
+        /// Stream the records whose `key_number` value falls in `bounds`
+        /// (see `BtrieveFile::range`)
+        pub async fn range<K: AsRef<[u8]>>(
+            &mut self,
+            key_number: i32,
+            bounds: impl RangeBounds<K>,
+        ) -> BtrieveResult<impl Stream<Item = BtrieveResult<BtrieveRecord>> + '_> {
+            let bounds = KeyBounds::new(&self.stat().await?, key_number, bounds)?;
+            self.current_key = key_number;
+            // State: Some(first call?) while running, None once finished
+            Ok(futures_util::stream::unfold((self, bounds, Some(true)), |(file, bounds, state)| async move {
+                let mut first = state?;
+                loop {
+                    let result = if first {
+                        match bounds.first_key() {
+                            Some(key) => file.get_greater_or_equal(key).await,
+                            None => file.get_first().await,
+                        }
+                    } else {
+                        file.advance().await
+                    };
+                    first = false;
+                    match result {
+                        Ok(record) if bounds.skip(&record.key) => continue,
+                        Ok(record) if bounds.past_end(&record.key) => return None,
+                        Ok(record) => return Some((Ok(record), (file, bounds, Some(false)))),
+                        Err(BtrieveError::Status(StatusCode::EndOfFile)) => return None,
+                        Err(e) => return Some((Err(e), (file, bounds, None))),
+                    }
+                }
+            }))
+        }
+
+        /// Get Equal - find record by exact key match
+        pub async fn get_equal(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_EQUAL,
+                position_block: self.position_block.clone(),
+                key_buffer: key.to_vec(),
+                key_buffer_length: key.len() as u32,
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get Equal, locking the record found. A record another session
+        /// holds fails with Record In Use (79), or with status 78 once the
+        /// server's lock timeout passes
+        pub async fn get_equal_locked(&mut self, key: &[u8], lock: LockKind) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_EQUAL,
+                position_block: self.position_block.clone(),
+                key_buffer: key.to_vec(),
+                key_buffer_length: key.len() as u32,
+                key_number: self.current_key,
+                lock_bias: lock.bias(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Release the single record lock held on this file. Locks taken in a
+        /// transaction are kept until it ends. A server from before protocol
+        /// version 6 has no Unlock, and this fails with status 1
+        pub async fn unlock(&mut self) -> BtrieveResult<()> {
+            if !supports_unlock(self.client.server_info().await)? {
+                return Err(BtrieveError::Status(StatusCode::InvalidOperation));
+            }
+            check_status(self.client.execute(unlock_request(&self.position_block, false)).await?)?;
+            Ok(())
+        }
+
+        /// Release every multiple record lock held on this file
+        pub async fn unlock_all(&mut self) -> BtrieveResult<()> {
+            if !supports_unlock(self.client.server_info().await)? {
+                return Err(BtrieveError::Status(StatusCode::InvalidOperation));
+            }
+            check_status(self.client.execute(unlock_request(&self.position_block, true)).await?)?;
+            Ok(())
+        }
+
+        /// Get Next - get next record in key order
+        pub async fn get_next(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_NEXT,
+                position_block: self.position_block.clone(),
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// GetNext for streams, ending the walk if the cursor does not move
+        async fn advance(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let before = self.position_block.clone();
+            let record = self.get_next().await?;
+            if self.position_block == before {
+                return Err(BtrieveError::Status(StatusCode::EndOfFile));
+            }
+            Ok(record)
+        }
+
+        /// Get Previous - get previous record in key order
+        pub async fn get_previous(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_PREVIOUS,
+                position_block: self.position_block.clone(),
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get First - get first record in key order
+        pub async fn get_first(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_FIRST,
+                position_block: self.position_block.clone(),
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get Last - get last record in key order
+        pub async fn get_last(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_LAST,
+                position_block: self.position_block.clone(),
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get Greater - get first record with key greater than given
+        pub async fn get_greater(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_GREATER,
+                position_block: self.position_block.clone(),
+                key_buffer: key.to_vec(),
+                key_buffer_length: key.len() as u32,
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get Greater or Equal
+        pub async fn get_greater_or_equal(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_GE,
+                position_block: self.position_block.clone(),
+                key_buffer: key.to_vec(),
+                key_buffer_length: key.len() as u32,
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get Less Than - get last record with key less than given
+        pub async fn get_less(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_LESS,
+                position_block: self.position_block.clone(),
+                key_buffer: key.to_vec(),
+                key_buffer_length: key.len() as u32,
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get Less or Equal
+        pub async fn get_less_or_equal(&mut self, key: &[u8]) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_LE,
+                position_block: self.position_block.clone(),
+                key_buffer: key.to_vec(),
+                key_buffer_length: key.len() as u32,
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Get Position - physical position of the current record, for a
+        /// later `get_direct`. Currency is left as it was
+        pub async fn get_position(&mut self) -> BtrieveResult<u32> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_POSITION,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            let position = response.data_buffer.get(..4)
+                .ok_or_else(|| BtrieveError::Internal("Short Get Position reply".to_string()))?;
+            Ok(u32::from_le_bytes([position[0], position[1], position[2], position[3]]))
+        }
+
+        /// Get Direct - the record at a position from `get_position`, which
+        /// becomes the current record for GetNext/GetPrevious on the current key
+        pub async fn get_direct(&mut self, position: u32) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::GET_DIRECT,
+                position_block: self.position_block.clone(),
+                data_buffer: position.to_le_bytes().to_vec(),
+                data_buffer_length: 4,
+                key_number: self.current_key,
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: response.key_buffer,
+                schema: None,
+            })
+        }
+
+        /// Step First - get first record physically
+        pub async fn step_first(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::STEP_FIRST,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: Vec::new(),
+                schema: None,
+            })
+        }
+
+        /// Step Next - get next record physically
+        pub async fn step_next(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::STEP_NEXT,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: Vec::new(),
+                schema: None,
+            })
+        }
+
+        /// Step Last - get last record physically
+        pub async fn step_last(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::STEP_LAST,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: Vec::new(),
+                schema: None,
+            })
+        }
+
+        /// Step Previous - get previous record physically
+        pub async fn step_previous(&mut self) -> BtrieveResult<BtrieveRecord> {
+            let request = BtrieveRequest {
+                operation_code: op::STEP_PREVIOUS,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            self.position_block = response.position_block;
+
+            Ok(BtrieveRecord {
+                data: response.data_buffer,
+                key: Vec::new(),
+                schema: None,
+            })
+        }
+
+        /// Get file statistics
+        pub async fn stat(&mut self) -> BtrieveResult<FileStatistics> {
+            let request = BtrieveRequest {
+                operation_code: op::STAT,
+                position_block: self.position_block.clone(),
+                file_path: self.file_path.clone(),
+                ..Default::default()
+            };
+
+            let response = check_status(self.client.execute(request).await?)?;
+            FileStatistics::from_bytes(&response.data_buffer)
+        }
+
+        /// Begin transaction
+        pub async fn begin_transaction(&mut self) -> BtrieveResult<()> {
+            let request = BtrieveRequest {
+                operation_code: op::BEGIN_TRANSACTION,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            check_status(self.client.execute(request).await?)?;
+            Ok(())
+        }
+
+        /// End (commit) transaction
+        pub async fn end_transaction(&mut self) -> BtrieveResult<()> {
+            let request = BtrieveRequest {
+                operation_code: op::END_TRANSACTION,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            check_status(self.client.execute(request).await?)?;
+            Ok(())
+        }
+
+        /// Abort (rollback) transaction
+        pub async fn abort_transaction(&mut self) -> BtrieveResult<()> {
+            let request = BtrieveRequest {
+                operation_code: op::ABORT_TRANSACTION,
+                position_block: self.position_block.clone(),
+                ..Default::default()
+            };
+
+            check_status(self.client.execute(request).await?)?;
+            Ok(())
+        }
+    }
+}
+
+/// Iterator over a file's records in key order, see [`BtrieveFile::records`]
+pub struct Records<'a> {
+    file: &'a mut BtrieveFile,
+    started: bool,
+    done: bool,
+}
+
+impl Iterator for Records<'_> {
+    type Item = BtrieveResult<BtrieveRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = if self.started {
+            self.file.advance()
+        } else {
+            self.started = true;
+            self.file.get_first()
+        };
+        match result {
+            Ok(record) => Some(Ok(record)),
+            Err(BtrieveError::Status(StatusCode::EndOfFile)) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Key range resolved against a key's specification, in index order
+pub(crate) struct KeyBounds {
+    spec: KeySpec,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+}
+
+impl KeyBounds {
+    pub(crate) fn new<K: AsRef<[u8]>>(
+        stats: &FileStatistics,
+        key_number: i32,
+        bounds: impl RangeBounds<K>,
+    ) -> BtrieveResult<Self> {
+        let spec = usize::try_from(key_number)
+            .ok()
+            .and_then(|n| stats.keys.get(n))
+            .cloned()
+            .ok_or(BtrieveError::Status(StatusCode::InvalidKeyNumber))?;
+
+        let to_owned = |bound: Bound<&K>| match bound {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let (low, high) = (to_owned(bounds.start_bound()), to_owned(bounds.end_bound()));
+
+        // A descending index stores the high end of the range first
+        let (start, end) = if spec.is_descending() { (high, low) } else { (low, high) };
+        Ok(KeyBounds { spec, start, end })
+    }
+
+    /// Key to position on with GetGreaterOrEqual (None = GetFirst)
+    fn first_key(&self) -> Option<&[u8]> {
+        match &self.start {
+            Bound::Included(k) | Bound::Excluded(k) => Some(k),
+            Bound::Unbounded => None,
+        }
+    }
+
+    /// Check if a key sits on an excluded start bound and must be skipped
+    fn skip(&self, key: &[u8]) -> bool {
+        matches!(&self.start, Bound::Excluded(start) if self.spec.compare(key, start) == Ordering::Equal)
+    }
+
+    /// Check if a key lies beyond the end bound
+    fn past_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => self.spec.compare(key, end) == Ordering::Greater,
+            Bound::Excluded(end) => self.spec.compare(key, end) != Ordering::Less,
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Check if a record's key falls in the range
+    #[cfg(feature = "grpc")]
+    pub(crate) fn holds(&self, record: &[u8]) -> bool {
+        let key = self.spec.extract_key(record);
+        let before_start = match &self.start {
+            Bound::Included(start) => self.spec.compare(&key, start) == Ordering::Less,
+            Bound::Excluded(start) => self.spec.compare(&key, start) != Ordering::Greater,
+            Bound::Unbounded => false,
+        };
+        !before_start && !self.past_end(&key)
+    }
+}
+
+/// Iterator over a key range, see [`BtrieveFile::range`]
+pub struct KeyRange<'a> {
+    file: &'a mut BtrieveFile,
+    bounds: KeyBounds,
+    started: bool,
+    done: bool,
+}
+
+impl Iterator for KeyRange<'_> {
+    type Item = BtrieveResult<BtrieveRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let result = if self.started {
+                self.file.advance()
+            } else {
+                self.started = true;
+                match self.bounds.first_key() {
+                    Some(key) => self.file.get_greater_or_equal(key),
+                    None => self.file.get_first(),
+                }
+            };
+            match result {
+                Ok(record) if self.bounds.skip(&record.key) => continue,
+                Ok(record) if self.bounds.past_end(&record.key) => self.done = true,
+                Ok(record) => return Some(Ok(record)),
+                Err(BtrieveError::Status(StatusCode::EndOfFile)) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
 }
 
 /// File statistics returned by stat operation
@@ -367,9 +1901,42 @@ pub struct FileStatistics {
     pub page_size: u16,
     pub num_keys: u16,
     pub num_records: u32,
+    pub flags: FileFlags,
+    /// Key specifications, indexed by key number
+    pub keys: Vec<KeySpec>,
+}
+
+impl FileStatistics {
+    /// Parse statistics from a Stat data buffer
+    pub(crate) fn from_bytes(data: &[u8]) -> BtrieveResult<Self> {
+        if data.len() < 12 {
+            return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
+        }
+
+        // Key specs follow the 14-byte header: record_length, page_size,
+        // num_keys, num_records, flags, unused_pages
+        let keys = data
+            .get(14..)
+            .unwrap_or(&[])
+            .chunks_exact(KeySpec::SIZE)
+            .filter_map(|chunk| KeySpec::from_bytes(chunk).ok())
+            .collect();
+
+        Ok(FileStatistics {
+            record_length: u16::from_le_bytes([data[0], data[1]]),
+            page_size: u16::from_le_bytes([data[2], data[3]]),
+            num_keys: u16::from_le_bytes([data[4], data[5]]),
+            num_records: u32::from_le_bytes([data[6], data[7], data[8], data[9]]),
+            flags: FileFlags::from_bits_truncate(u16::from_le_bytes([data[10], data[11]])),
+            keys,
+        })
+    }
 }
 
 /// Create a new Btrieve file
+///
+/// Shorthand for a [`FileBuilder`](crate::FileBuilder) with single-segment keys;
+/// use the builder directly for segmented keys and file flags.
 pub fn create_file(
     mut client: XtrieveClient,
     path: &str,
@@ -377,34 +1944,43 @@ pub fn create_file(
     page_size: u16,
     keys: Vec<KeyDefinition>,
 ) -> BtrieveResult<()> {
-    // Build data buffer with file spec
-    let mut data = Vec::new();
-    data.extend_from_slice(&record_length.to_le_bytes());
-    data.extend_from_slice(&page_size.to_le_bytes());
-    data.extend_from_slice(&(keys.len() as u16).to_le_bytes());
-    data.extend_from_slice(&[0u8; 4]); // reserved/flags
-
-    // Add key specifications
-    for key in &keys {
-        data.extend_from_slice(&key.position.to_le_bytes());
-        data.extend_from_slice(&key.length.to_le_bytes());
-        data.extend_from_slice(&key.flags.to_le_bytes());
-        data.extend_from_slice(&[0u8; 4]); // unique_count placeholder
-        data.push(key.key_type);
-        data.push(key.null_value);
-        data.push(0); // acs_number
-        data.push(0); // reserved
-    }
-
-    let request = BtrieveRequest {
-        operation_code: op::CREATE,
+    keys.iter()
+        .fold(FileBuilder::new(record_length).page_size(page_size), |file, key| {
+            file.key(key.to_builder())
+        })
+        .create(&mut client, path)
+}
+
+/// Delete a file no session has open, with its recycle bin
+///
+/// A file with an owner name needs it. Xtrieve extension.
+pub fn delete_file(client: &mut XtrieveClient, path: &str, owner: Option<&str>) -> BtrieveResult<()> {
+    let key_buffer = owner_buffer(owner)?;
+    check_status(client.execute(BtrieveRequest {
+        operation_code: op::DELETE_FILE,
         file_path: path.to_string(),
-        data_buffer: data,
-        data_buffer_length: 10 + (keys.len() as u32 * 16),
+        key_buffer_length: key_buffer.len() as u32,
+        key_buffer,
         ..Default::default()
-    };
+    })?)?;
+    Ok(())
+}
 
-    client.execute(request)?;
+/// Rename a file no session has open, with its recycle bin
+///
+/// A file with an owner name needs it, and nothing may exist at `to`.
+/// Xtrieve extension.
+pub fn rename_file(client: &mut XtrieveClient, from: &str, to: &str, owner: Option<&str>) -> BtrieveResult<()> {
+    let key_buffer = owner_buffer(owner)?;
+    check_status(client.execute(BtrieveRequest {
+        operation_code: op::RENAME_FILE,
+        file_path: from.to_string(),
+        data_buffer_length: to.len() as u32,
+        data_buffer: to.as_bytes().to_vec(),
+        key_buffer_length: key_buffer.len() as u32,
+        key_buffer,
+        ..Default::default()
+    })?)?;
     Ok(())
 }
 
@@ -464,6 +2040,25 @@ impl KeyDefinition {
         }
     }
 
+    fn to_builder(&self) -> KeyBuilder {
+        let flags = KeyFlags::from_bits_truncate(self.flags);
+        let mut key = KeyBuilder::new().segment(
+            self.position,
+            self.length,
+            KeyType::from_raw(self.key_type),
+        );
+        if flags.contains(KeyFlags::DUPLICATES) {
+            key = key.duplicates();
+        }
+        if flags.contains(KeyFlags::MODIFIABLE) {
+            key = key.modifiable();
+        }
+        if flags.contains(KeyFlags::NULL) {
+            key = key.null_value(self.null_value);
+        }
+        key
+    }
+
     /// Create an autoincrement key
     pub fn autoincrement(position: u16, length: u16) -> Self {
         KeyDefinition {
@@ -475,3 +2070,165 @@ impl KeyDefinition {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtrieve_engine::ErrorDetail;
+
+    #[test]
+    fn test_insert_extended_chunking() {
+        let records = vec![vec![1u8; 30_000]; 5];
+        let (buf, count) = pack_insert_extended(&records);
+        assert_eq!(count, 2);
+        assert_eq!(buf.len(), 2 + 2 * (2 + 30_000));
+        assert_eq!(u16::from_le_bytes([buf[0], buf[1]]), 2);
+
+        // An oversized record still goes out alone
+        let (_, count) = pack_insert_extended(&[vec![0u8; 70_000]]);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_owner_name_in_key_buffer() {
+        let request = open_request("F.DAT", 0, Some("SECRET")).unwrap();
+        assert_eq!(request.key_buffer, b"SECRET\0");
+        assert!(open_request("F.DAT", 0, None).unwrap().key_buffer.is_empty());
+        assert!(matches!(
+            open_request("F.DAT", 0, Some("TOOLONGNAME")),
+            Err(BtrieveError::Status(StatusCode::InvalidOwner))
+        ));
+    }
+
+    #[test]
+    fn test_status_detail() {
+        let bare = check_status(BtrieveResponse { status_code: 9, ..Default::default() }).unwrap_err();
+        assert!(matches!(bare, BtrieveError::Status(StatusCode::EndOfFile)));
+        assert!(bare.detail().is_none());
+
+        let detail = ErrorDetail { message: "I/O error: disk full".to_string(), context: "Insert on CUST.DAT".to_string() };
+        let response = BtrieveResponse { status_code: 2, detail: Some(detail.clone()), ..Default::default() };
+        let err = check_status(response).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::IoError);
+        assert_eq!(err.detail(), Some(&detail));
+        assert_eq!(err.to_string(), "Btrieve status 2 (I/O error): I/O error: disk full (Insert on CUST.DAT)");
+    }
+
+    #[test]
+    fn test_reverse_and_direct_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = XtrieveClient::connect(&format!("file://{}", dir.path().display())).unwrap();
+        crate::FileBuilder::new(8).key(crate::KeyBuilder::unsigned(0, 4)).create(&mut client, "PARTS.DAT").unwrap();
+        let mut file = BtrieveFile::open(client, "PARTS.DAT", 0).unwrap();
+        for id in [10u32, 20, 30] {
+            file.insert(&[id.to_le_bytes(), *b"PART"].concat()).unwrap();
+        }
+
+        let key = |id: u32| id.to_le_bytes().to_vec();
+        assert_eq!(file.get_less(&key(30)).unwrap().key, key(20));
+        assert_eq!(file.get_less_or_equal(&key(20)).unwrap().key, key(20));
+        assert_eq!(file.get_previous().unwrap().key, key(10));
+        assert!(matches!(file.get_less(&key(10)), Err(BtrieveError::Status(StatusCode::KeyNotFound))));
+
+        file.get_first().unwrap();
+        let position = file.get_position().unwrap();
+        file.get_last().unwrap();
+        file.get_direct(position).unwrap();
+        file.step_last().unwrap();
+        file.step_previous().unwrap();
+    }
+
+    #[test]
+    fn test_record_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = format!("file://{}", dir.path().display());
+        let mut client = XtrieveClient::connect(&addr).unwrap();
+        crate::FileBuilder::new(8).key(crate::KeyBuilder::unsigned(0, 4)).create(&mut client, "PARTS.DAT").unwrap();
+        let mut mine = BtrieveFile::open(client, "PARTS.DAT", 0).unwrap();
+        let mut theirs = BtrieveFile::open(XtrieveClient::connect(&addr).unwrap(), "PARTS.DAT", 0).unwrap();
+        for id in [1u32, 2] {
+            mine.insert(&[id.to_le_bytes(), *b"PART"].concat()).unwrap();
+        }
+        let key = |id: u32| id.to_le_bytes();
+        let in_use = |result: BtrieveResult<BtrieveRecord>| {
+            matches!(result, Err(BtrieveError::Status(StatusCode::RecordInUse)))
+        };
+
+        mine.get_equal_locked(&key(1), LockKind::SingleNoWait).unwrap();
+        assert!(in_use(theirs.get_equal_locked(&key(1), LockKind::SingleNoWait)));
+        mine.unlock().unwrap();
+        theirs.get_equal_locked(&key(1), LockKind::SingleNoWait).unwrap();
+        theirs.unlock().unwrap();
+
+        mine.get_equal_locked(&key(1), LockKind::MultiNoWait).unwrap();
+        mine.get_equal_locked(&key(2), LockKind::MultiNoWait).unwrap();
+        assert!(in_use(theirs.get_equal_locked(&key(2), LockKind::SingleNoWait)));
+        mine.unlock_all().unwrap();
+        theirs.get_equal_locked(&key(2), LockKind::SingleNoWait).unwrap();
+    }
+
+    #[test]
+    fn test_unlock_needs_btrieve_opcodes() {
+        let mut info = ServerInfo {
+            protocol_version: BTRIEVE_OPCODES_VERSION,
+            engine_version: "0.1.0".to_string(),
+            max_keys: 24,
+            max_page_size: 4096,
+            max_record_length: 4076,
+            position_block_size: POSITION_BLOCK_SIZE as u16,
+            operations: [0u8; 32],
+        };
+        info.set_supported(op::UNLOCK as u16);
+        assert!(supports_unlock(Ok(info.clone())).unwrap());
+
+        // 27 on an older server is Find Percentage
+        info.protocol_version = BTRIEVE_OPCODES_VERSION - 1;
+        assert!(!supports_unlock(Ok(info)).unwrap());
+        assert!(!supports_unlock(Err(BtrieveError::Status(StatusCode::InvalidOperation))).unwrap());
+    }
+
+    #[test]
+    fn test_record_fitting() {
+        let fixed = RecordShape { length: 4, variable: false };
+        assert_eq!(&*fixed.fit(&[1, 2]), &[1, 2, 0, 0]);
+        assert_eq!(&*fixed.fit(&[1, 2, 3, 4, 5]), &[1, 2, 3, 4]);
+
+        let variable = RecordShape { length: 4, variable: true };
+        assert_eq!(&*variable.fit(&[1, 2, 3, 4, 5]), &[1, 2, 3, 4, 5]);
+        assert_eq!(&*fit_record(Some(fixed), false, &[1]), &[1]);
+    }
+
+    #[test]
+    fn test_saved_position_roundtrip() {
+        let mut block = vec![0u8; POSITION_BLOCK_SIZE];
+        block[0] = 1;
+        block[120] = 7;
+        let saved = SavedPosition {
+            file_path: "ORDERS.DAT".to_string(),
+            key_number: 2,
+            position_block: block,
+        };
+        assert_eq!(SavedPosition::from_bytes(&saved.to_bytes()).unwrap(), saved);
+
+        // Restoring keeps the handle's session ID
+        let mut current = vec![0u8; POSITION_BLOCK_SIZE];
+        current[CURSOR_ID] = 2;
+        current[120] = 9;
+        restore_cursor(&mut current, "ORDERS.DAT", &saved).unwrap();
+        assert_eq!((current[0], current[CURSOR_ID], current[120]), (1, 2, 9));
+        assert!(restore_cursor(&mut current, "OTHER.DAT", &saved).is_err());
+    }
+
+    #[test]
+    fn test_insert_report_partial_batch() {
+        let mut report = InsertReport::default();
+        let response = BtrieveResponse {
+            status_code: StatusCode::DuplicateKey as u32,
+            data_buffer: 3u16.to_le_bytes().to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(report.record_batch(10, 8, &response), 14);
+        assert_eq!(report.inserted, 3);
+        assert_eq!(report.failures[0].0, 13);
+    }
+}
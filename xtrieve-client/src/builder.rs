@@ -0,0 +1,368 @@
+//! Builders for the Create (14) data buffer
+//!
+//! ```ignore
+//! use xtrieve_client::{FileBuilder, KeyBuilder, KeyType};
+//!
+//! FileBuilder::new(128)
+//!     .page_size(1024)
+//!     .key(KeyBuilder::new().segment(0, 4, KeyType::UnsignedBinary))
+//!     .key(
+//!         KeyBuilder::new()
+//!             .segment(4, 20, KeyType::String)
+//!             .segment(24, 4, KeyType::Date).descending()
+//!             .duplicates(),
+//!     )
+//!     .create(&mut client, "ORDERS.DAT")?;
+//! ```
+
+use xtrieve_engine::storage::fcr::FileFlags;
+use xtrieve_engine::storage::{KeyFlags, KeySpec, KeyType};
+use xtrieve_engine::{BtrieveError, BtrieveResult};
+
+use crate::btrieve::{check_status, op, FileStatistics};
+use crate::client::{BtrieveRequest, XtrieveClient};
+
+/// Size of the file specification header ahead of the key specs
+const FILE_SPEC_SIZE: usize = 16;
+
+/// File specification for Create
+#[derive(Debug, Clone)]
+pub struct FileBuilder {
+    record_length: u16,
+    page_size: u16,
+    flags: FileFlags,
+    preallocation: u16,
+    keys: Vec<KeyBuilder>,
+}
+
+impl FileBuilder {
+    /// Start a file with fixed records of `record_length` bytes and 1024-byte pages
+    pub fn new(record_length: u16) -> Self {
+        FileBuilder {
+            record_length,
+            page_size: 1024,
+            flags: FileFlags::empty(),
+            preallocation: 0,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Same record length, page size, flags and keys as an existing file
+    pub fn from_statistics(stat: &FileStatistics) -> Self {
+        let mut file = FileBuilder::new(stat.record_length).page_size(stat.page_size);
+        file.flags = stat.flags;
+
+        let mut key = KeyBuilder::new();
+        for spec in &stat.keys {
+            key = key.segment(spec.position, spec.length, spec.key_type);
+            if spec.is_descending() {
+                key = key.descending();
+            }
+            key.duplicates = spec.flags.contains(KeyFlags::DUPLICATES);
+            key.modifiable = spec.flags.contains(KeyFlags::MODIFIABLE);
+            key.null_value = spec.flags.contains(KeyFlags::NULL).then_some(spec.null_value);
+            key.acs_number = spec.flags.contains(KeyFlags::ALT_SEQUENCE).then_some(spec.acs_number);
+            key.no_case = spec.flags.contains(KeyFlags::NO_CASE);
+            if !spec.is_segmented() {
+                file.keys.push(std::mem::take(&mut key));
+            }
+        }
+        file
+    }
+
+    /// Page size: 512, 1024, 2048 or 4096
+    pub fn page_size(mut self, page_size: u16) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Allow variable-length records
+    pub fn variable_records(mut self) -> Self {
+        self.flags |= FileFlags::VARIABLE_LENGTH;
+        self
+    }
+
+    /// Drop trailing blanks from variable-length records
+    pub fn blank_truncation(mut self) -> Self {
+        self.flags |= FileFlags::BLANK_TRUNCATION;
+        self
+    }
+
+    /// Compress the file's pages (LZ4, an Xtrieve extension)
+    pub fn compression(mut self) -> Self {
+        self.flags |= FileFlags::COMPRESSED;
+        self
+    }
+
+    /// Keep deleted records in a recycle bin beside the file, from which
+    /// Undelete restores them (an Xtrieve extension)
+    pub fn recycle_bin(mut self) -> Self {
+        self.flags |= FileFlags::RECYCLE;
+        self
+    }
+
+    /// Log committed changes in a roll-forward log beside the file, which
+    /// `xtutil rollfwd` applies to a restored backup (an Xtrieve extension)
+    pub fn roll_forward(mut self) -> Self {
+        self.flags |= FileFlags::ROLL_FORWARD;
+        self
+    }
+
+    /// Keep the index pages in a file of their own beside the data file
+    /// (an Xtrieve extension); not with compression
+    pub fn split_index(mut self) -> Self {
+        self.flags |= FileFlags::SPLIT_INDEX;
+        self
+    }
+
+    /// Keep each key's index pages in a file of its own beside the data
+    /// file (an Xtrieve extension); not with compression or `split_index`
+    pub fn index_per_key(mut self) -> Self {
+        self.flags |= FileFlags::INDEX_PER_KEY;
+        self
+    }
+
+    /// Reserve `pages` pages when the file is created
+    pub fn preallocate(mut self, pages: u16) -> Self {
+        self.preallocation = pages;
+        self
+    }
+
+    /// Add a key; key numbers follow the order keys are added
+    pub fn key(mut self, key: KeyBuilder) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Encode the Create data buffer: the 16-byte file spec followed by
+    /// one 16-byte key spec per segment
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; FILE_SPEC_SIZE];
+        buf[0..2].copy_from_slice(&self.record_length.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.page_size.to_le_bytes());
+        buf[4..6].copy_from_slice(&(self.keys.len() as u16).to_le_bytes());
+        buf[8..12].copy_from_slice(&(self.flags.bits() as u32).to_le_bytes());
+        buf[14..16].copy_from_slice(&self.preallocation.to_le_bytes());
+
+        for key in &self.keys {
+            for spec in key.specs() {
+                buf.extend_from_slice(&spec.to_bytes());
+            }
+        }
+        buf
+    }
+
+    fn request(&self, path: &str) -> BtrieveResult<BtrieveRequest> {
+        if let Some(key) = self.keys.iter().find(|k| k.segments.is_empty()) {
+            return Err(BtrieveError::Internal(format!("Key {:?} has no segments", key)));
+        }
+        let data = self.to_bytes();
+        Ok(BtrieveRequest {
+            operation_code: op::CREATE,
+            file_path: path.to_string(),
+            data_buffer_length: data.len() as u32,
+            data_buffer: data,
+            ..Default::default()
+        })
+    }
+
+    /// Create the file through a connected client
+    pub fn create(&self, client: &mut XtrieveClient, path: &str) -> BtrieveResult<()> {
+        check_status(client.execute(self.request(path)?)?)?;
+        Ok(())
+    }
+
+    /// Create the file through an async client
+    #[cfg(feature = "async")]
+    pub async fn create_async(
+        &self,
+        client: &mut crate::client::AsyncXtrieveClient,
+        path: &str,
+    ) -> BtrieveResult<()> {
+        check_status(client.execute(self.request(path)?).await?)?;
+        Ok(())
+    }
+}
+
+/// One segment of a key
+#[derive(Debug, Clone)]
+struct Segment {
+    position: u16,
+    length: u16,
+    key_type: KeyType,
+    descending: bool,
+}
+
+/// Key specification, made of one or more segments
+#[derive(Debug, Clone, Default)]
+pub struct KeyBuilder {
+    segments: Vec<Segment>,
+    duplicates: bool,
+    modifiable: bool,
+    null_value: Option<u8>,
+    acs_number: Option<u8>,
+    no_case: bool,
+}
+
+impl KeyBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Single-segment string key
+    pub fn string(position: u16, length: u16) -> Self {
+        Self::new().segment(position, length, KeyType::String)
+    }
+
+    /// Single-segment UTF-8 string key, compared after NFC normalization.
+    /// An Xtrieve extension
+    pub fn utf8(position: u16, length: u16) -> Self {
+        Self::new().segment(position, length, KeyType::Utf8)
+    }
+
+    /// Single-segment signed integer key
+    pub fn integer(position: u16, length: u16) -> Self {
+        Self::new().segment(position, length, KeyType::Integer)
+    }
+
+    /// Single-segment unsigned integer key
+    pub fn unsigned(position: u16, length: u16) -> Self {
+        Self::new().segment(position, length, KeyType::UnsignedBinary)
+    }
+
+    /// Append a segment at 0-based `position`
+    pub fn segment(mut self, position: u16, length: u16, key_type: KeyType) -> Self {
+        self.segments.push(Segment {
+            position,
+            length,
+            key_type,
+            descending: false,
+        });
+        self
+    }
+
+    /// Sort the most recently added segment in descending order
+    pub fn descending(mut self) -> Self {
+        if let Some(segment) = self.segments.last_mut() {
+            segment.descending = true;
+        }
+        self
+    }
+
+    /// Allow duplicate key values
+    pub fn duplicates(mut self) -> Self {
+        self.duplicates = true;
+        self
+    }
+
+    /// Allow Update to change the key value
+    pub fn modifiable(mut self) -> Self {
+        self.modifiable = true;
+        self
+    }
+
+    /// Leave records out of the index when every key byte equals `value`
+    pub fn null_value(mut self, value: u8) -> Self {
+        self.null_value = Some(value);
+        self
+    }
+
+    /// Compare with alternate collating sequence `number`
+    pub fn acs(mut self, number: u8) -> Self {
+        self.acs_number = Some(number);
+        self
+    }
+
+    /// Compare string segments without regard to case, folding through
+    /// code page 437, or 850 with `acs(2)`; UTF-8 segments get Unicode
+    /// case folding. An Xtrieve extension
+    pub fn no_case(mut self) -> Self {
+        self.no_case = true;
+        self
+    }
+
+    /// One key spec per segment; every segment but the last is flagged SEGMENTED
+    fn specs(&self) -> Vec<KeySpec> {
+        let mut flags = KeyFlags::empty();
+        flags.set(KeyFlags::DUPLICATES, self.duplicates);
+        flags.set(KeyFlags::MODIFIABLE, self.modifiable);
+        flags.set(KeyFlags::NULL, self.null_value.is_some());
+        flags.set(KeyFlags::ALT_SEQUENCE, self.acs_number.is_some());
+        flags.set(KeyFlags::NO_CASE, self.no_case);
+
+        let last = self.segments.len().saturating_sub(1);
+        self.segments
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                let mut segment_flags = flags;
+                segment_flags.set(KeyFlags::SEGMENTED, i < last);
+                segment_flags.set(KeyFlags::DESCENDING, segment.descending);
+                KeySpec {
+                    position: segment.position,
+                    length: segment.length,
+                    flags: segment_flags,
+                    key_type: segment.key_type,
+                    null_value: self.null_value.unwrap_or(0),
+                    acs_number: self.acs_number.unwrap_or(0),
+                    unique_count: 0,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segmented_key_layout() {
+        let buf = FileBuilder::new(64)
+            .page_size(512)
+            .variable_records()
+            .preallocate(8)
+            .key(KeyBuilder::unsigned(0, 4))
+            .key(
+                KeyBuilder::string(4, 10)
+                    .segment(14, 4, KeyType::Date)
+                    .descending()
+                    .duplicates(),
+            )
+            .to_bytes();
+
+        // Header plus three segment specs
+        assert_eq!(buf.len(), FILE_SPEC_SIZE + 3 * KeySpec::SIZE);
+        assert_eq!(u16::from_le_bytes([buf[4], buf[5]]), 2);
+        assert_eq!(u16::from_le_bytes([buf[14], buf[15]]), 8);
+
+        let spec = |n: usize| {
+            KeySpec::from_bytes(&buf[FILE_SPEC_SIZE + n * KeySpec::SIZE..]).unwrap()
+        };
+        assert!(!spec(0).is_segmented());
+        assert!(spec(1).is_segmented() && spec(1).allows_duplicates() && !spec(1).is_descending());
+        assert!(!spec(2).is_segmented() && spec(2).is_descending());
+        assert_eq!(spec(2).key_type, KeyType::Date);
+    }
+
+    #[test]
+    fn test_from_statistics() {
+        let file = FileBuilder::new(64)
+            .page_size(2048)
+            .key(KeyBuilder::unsigned(0, 4))
+            .key(KeyBuilder::string(4, 10).segment(14, 4, KeyType::Date).duplicates().null_value(b' ').no_case());
+        let buf = file.to_bytes();
+        let stat = FileStatistics {
+            record_length: 64,
+            page_size: 2048,
+            num_keys: 2,
+            num_records: 0,
+            flags: FileFlags::empty(),
+            keys: buf[FILE_SPEC_SIZE..]
+                .chunks(KeySpec::SIZE)
+                .map(|chunk| KeySpec::from_bytes(chunk).unwrap())
+                .collect(),
+        };
+        assert_eq!(FileBuilder::from_statistics(&stat).to_bytes(), buf);
+    }
+}
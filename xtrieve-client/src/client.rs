@@ -1,66 +1,427 @@
-//! TCP client for connecting to xtrieved
+//! Clients for connecting to xtrieved
 //!
 //! Provides both sync and async clients:
-//! - `XtrieveClient` - Synchronous client using std::net::TcpStream
+//! - `XtrieveClient` - Synchronous client over any `Transport` (TCP, Unix
+//!   socket, gRPC or the embedded engine, picked from the address)
 //! - `AsyncXtrieveClient` - Async client using tokio::net::TcpStream
 
-use std::io::{BufReader, BufWriter, Write};
-use std::net::TcpStream;
-use xtrieve_engine::protocol::{Request, Response, POSITION_BLOCK_SIZE};
-use xtrieve_engine::{BtrieveError, BtrieveResult};
+use std::ops::Range;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use xtrieve_engine::file_manager::cursor::{PositionBlock, CHECK, CURSOR_ID};
+use xtrieve_engine::protocol::POSITION_BLOCK_SIZE;
+use xtrieve_engine::{BtrieveError, BtrieveResult, ErrorDetail, LockReport, ServerInfo, StatusCode};
+
+use crate::ddf::{Dictionary, Table};
+use crate::transport::{self, Transport};
+
+/// Position block bytes the server stamps at Open: the check and the session
+const STAMPED: Range<usize> = CHECK.start..POSITION_BLOCK_SIZE;
+
+/// Payload the server echoes back for a Ping (Xtrieve extension op 99)
+const PING_PAYLOAD: &[u8] = b"XTRIEVE-PING";
+
+/// Check a ping reply carries success and the echoed payload
+fn check_ping(response: &BtrieveResponse) -> BtrieveResult<()> {
+    if response.status_code != 0 {
+        return Err(response.to_error());
+    }
+    if response.data_buffer != PING_PAYLOAD {
+        return Err(BtrieveError::Internal("Ping reply did not echo payload".to_string()));
+    }
+    Ok(())
+}
+
+/// Decode a ServerInfo (98) reply; servers that predate the operation
+/// answer with status 1 (invalid operation)
+fn parse_server_info(response: &BtrieveResponse) -> BtrieveResult<ServerInfo> {
+    if response.status_code != 0 {
+        return Err(response.to_error());
+    }
+    ServerInfo::from_bytes(&response.data_buffer)
+        .map_err(|e| BtrieveError::Internal(format!("Bad server info: {}", e)))
+}
+
+/// What tells one open apart from the others in a position block: the
+/// cursor id and the stamped bytes
+fn identity(position_block: &[u8]) -> Vec<u8> {
+    let block = PositionBlock::from_bytes(position_block);
+    let mut identity = vec![block.data[CURSOR_ID]];
+    identity.extend_from_slice(&block.data[STAMPED]);
+    identity
+}
+
+/// Data file path as dictionaries are matched on: DOS separators and
+/// letter case don't count
+fn schema_key(path: &str) -> String {
+    let path = path.replace('\\', "/").to_ascii_lowercase();
+    path.trim_start_matches("./").to_string()
+}
+
+/// Check if an operation can be repeated without side effects
+///
+/// Reads carry their cursor in the position block, so sending the same
+/// request again on a new connection returns the same record.
+fn is_idempotent(operation_code: u32) -> bool {
+    use crate::btrieve::op;
+    matches!(
+        operation_code,
+        op::GET_EQUAL
+            | op::GET_NEXT
+            | op::GET_PREVIOUS
+            | op::GET_GREATER
+            | op::GET_GE
+            | op::GET_LESS
+            | op::GET_LE
+            | op::GET_FIRST
+            | op::GET_LAST
+            | op::STAT
+            | op::GET_POSITION
+            | op::GET_DIRECT
+            | op::STEP_NEXT
+            | op::STEP_FIRST
+            | op::STEP_LAST
+            | op::STEP_PREVIOUS
+            | op::SERVER_INFO
+            | op::PING
+    )
+}
 
 // ============================================================================
 // Sync Client
 // ============================================================================
 
+/// How `XtrieveClient` recovers from a broken connection
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Connection attempts before giving up
+    pub max_attempts: u32,
+    /// Pause between attempts
+    pub delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Socket timeouts for a client connection; `None` waits indefinitely
+#[derive(Debug, Clone, Default)]
+pub struct Timeouts {
+    /// Limit on establishing the connection
+    pub connect: Option<Duration>,
+    /// Limit on each wait for response bytes
+    pub read: Option<Duration>,
+    /// Limit on each wait to send request bytes
+    pub write: Option<Duration>,
+}
+
+/// A successful Open, remembered so it can be replayed after a reconnect
+struct TrackedOpen {
+    request: BtrieveRequest,
+    /// Cursor id, check and session of the open (see `identity`)
+    identity: Vec<u8>,
+}
+
 /// Synchronous client for connecting to xtrieved daemon
 pub struct XtrieveClient {
-    reader: BufReader<TcpStream>,
-    writer: BufWriter<TcpStream>,
+    /// Address to reconnect to; `None` for a transport supplied by the caller
+    pub(crate) addr: Option<String>,
+    transport: Box<dyn Transport>,
+    server_info: Option<ServerInfo>,
+    keepalive: Option<Duration>,
+    pub(crate) reconnect: Option<ReconnectPolicy>,
+    open_files: Vec<TrackedOpen>,
+    /// Identities of opens lost with a connection, mapped to those of
+    /// their reopens
+    renewed: Vec<(Vec<u8>, Vec<u8>)>,
+    in_transaction: bool,
+    pub(crate) timeouts: Timeouts,
+    /// End of the current `execute_with_deadline` call
+    deadline: Option<Instant>,
+    /// Tables of the loaded dictionaries, by data file path
+    schemas: Vec<(String, Arc<Table>)>,
 }
 
 impl XtrieveClient {
-    /// Connect to xtrieved at the given address (e.g., "127.0.0.1:7419")
+    /// Connect to xtrieved at the given address (e.g., "127.0.0.1:7419").
+    ///
+    /// The scheme picks the transport: `unix://`, `grpc://` or
+    /// `file://<data dir>` for the in-process engine (see `transport`).
     pub fn connect(addr: &str) -> BtrieveResult<Self> {
-        let stream = TcpStream::connect(addr)
-            .map_err(|e| BtrieveError::Internal(format!("Connection failed: {}", e)))?;
+        Self::connect_with(addr, Timeouts::default())
+    }
+
+    /// Connect with connect/read/write timeouts (ignored for `file://`)
+    pub fn connect_with(addr: &str, timeouts: Timeouts) -> BtrieveResult<Self> {
+        let transport = transport::connect(addr, &timeouts)?;
+        let mut client = Self::with_transport(transport);
+        client.addr = Some(addr.to_string());
+        client.timeouts = timeouts;
+        Ok(client)
+    }
+
+    /// Use a caller-supplied transport; such a client can't reconnect
+    pub fn with_transport(transport: Box<dyn Transport>) -> Self {
+        XtrieveClient {
+            addr: None,
+            transport,
+            server_info: None,
+            keepalive: None,
+            reconnect: None,
+            open_files: Vec::new(),
+            renewed: Vec::new(),
+            in_transaction: false,
+            timeouts: Timeouts::default(),
+            deadline: None,
+            schemas: Vec::new(),
+        }
+    }
+
+    /// Change the timeouts; they also apply to connections made by a reconnect
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) -> BtrieveResult<()> {
+        self.transport.set_timeouts(&timeouts)?;
+        self.timeouts = timeouts;
+        Ok(())
+    }
 
-        let reader = BufReader::new(stream.try_clone()
-            .map_err(|e| BtrieveError::Internal(format!("Clone failed: {}", e)))?);
-        let writer = BufWriter::new(stream);
+    /// Reconnect automatically when the connection breaks (`None` turns it off).
+    ///
+    /// After reconnecting, files opened through this client are opened
+    /// again and the failed request is retried if it is an unlocked read
+    /// outside a transaction. Writes, locked reads and anything inside a
+    /// transaction still fail, since the server may already have applied them.
+    pub fn set_reconnect(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect = policy;
+    }
+
+    /// Check the server is alive with a Ping (99) round trip
+    pub fn ping(&mut self) -> BtrieveResult<()> {
+        let response = self.execute(BtrieveRequest {
+            operation_code: crate::btrieve::op::PING,
+            data_buffer: PING_PAYLOAD.to_vec(),
+            ..Default::default()
+        })?;
+        check_ping(&response)
+    }
+
+    /// Query the server's version, limits and supported operations.
+    ///
+    /// The binary protocol transports ask for these with the first
+    /// request on a connection, whose response carries them; others ask
+    /// on the first call. Either way they are cached for the life of the
+    /// connection. Servers that predate the ServerInfo (98) operation
+    /// return `StatusCode::InvalidOperation`.
+    pub fn server_info(&mut self) -> BtrieveResult<ServerInfo> {
+        if let Some(info) = self.server_info.clone().or_else(|| self.transport.server_info()) {
+            self.server_info = Some(info.clone());
+            return Ok(info);
+        }
+        let response = self.execute(BtrieveRequest {
+            operation_code: crate::btrieve::op::SERVER_INFO,
+            ..Default::default()
+        })?;
+        let info = parse_server_info(&response)?;
+        self.server_info = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Fetch the server's lock report (90): record locks turning sessions
+    /// away, sessions waiting on others, and conflict counts
+    pub fn lock_report(&mut self) -> BtrieveResult<LockReport> {
+        let response = self.execute(BtrieveRequest {
+            operation_code: crate::btrieve::op::LOCK_REPORT,
+            ..Default::default()
+        })?;
+        if response.status_code != 0 {
+            return Err(response.to_error());
+        }
+        LockReport::from_bytes(&response.data_buffer)
+            .map_err(|e| BtrieveError::Internal(format!("Bad lock report: {}", e)))
+    }
+
+    /// Cancel (89) the requests another connection sent with `request_id`
+    /// (see `BtrieveRequest::request_id`): they give up with status 200
+    /// at their next check. False if none is running with that id
+    pub fn cancel(&mut self, request_id: u64) -> BtrieveResult<bool> {
+        let response = self.execute(BtrieveRequest {
+            operation_code: crate::btrieve::op::CANCEL,
+            data_buffer: request_id.to_le_bytes().to_vec(),
+            data_buffer_length: 8,
+            ..Default::default()
+        })?;
+        match StatusCode::from_raw(response.status_code as u16) {
+            StatusCode::Success => Ok(true),
+            StatusCode::KeyNotFound => Ok(false),
+            _ => Err(response.to_error()),
+        }
+    }
+
+    /// Read the dictionary in `dir` from the server and describe the
+    /// files it names, so records read from them can be accessed by field
+    /// name (see `BtrieveRecord::field`)
+    pub fn load_dictionary(&mut self, dir: &str) -> BtrieveResult<()> {
+        let dictionary = Dictionary::load(self, dir)?;
+        self.add_dictionary(&dictionary, dir);
+        Ok(())
+    }
+
+    /// Describe files with a dictionary loaded some other way, such as
+    /// `Dictionary::load_local`. `dir` is where it lives relative to the
+    /// server's data directory, which its table locations are relative to.
+    ///
+    /// Only files opened afterwards pick their table up. A later
+    /// dictionary's tables replace earlier ones for the same file.
+    pub fn add_dictionary(&mut self, dictionary: &Dictionary, dir: &str) {
+        for table in &dictionary.tables {
+            let path = schema_key(&table.file_path(dir));
+            self.schemas.retain(|(known, _)| *known != path);
+            self.schemas.push((path, Arc::new(table.clone())));
+        }
+    }
 
-        Ok(XtrieveClient { reader, writer })
+    /// Table describing the file at `path`, from the loaded dictionaries
+    pub fn schema(&self, path: &str) -> Option<Arc<Table>> {
+        let path = schema_key(path);
+        self.schemas.iter().find(|(known, _)| *known == path).map(|(_, table)| table.clone())
+    }
+
+    /// Enable TCP keepalive so a dead server fails reads instead of hanging
+    pub fn set_keepalive(&mut self, idle: Duration) -> BtrieveResult<()> {
+        self.transport.set_keepalive(idle)?;
+        self.keepalive = Some(idle);
+        Ok(())
+    }
+
+    /// Execute a Btrieve operation that must complete within `limit`.
+    ///
+    /// The deadline covers the whole call, including a retry after an
+    /// automatic reconnect. When it passes the connection is closed, since
+    /// the server may still answer the abandoned request.
+    pub fn execute_with_deadline(
+        &mut self,
+        request: BtrieveRequest,
+        limit: Duration,
+    ) -> BtrieveResult<BtrieveResponse> {
+        self.deadline = Some(Instant::now() + limit);
+        let result = self.execute(request);
+        self.deadline = None;
+        result
     }
 
     /// Execute a Btrieve operation
-    pub fn execute(&mut self, request: BtrieveRequest) -> BtrieveResult<BtrieveResponse> {
-        // Convert to wire protocol
-        let wire_req = Request {
-            operation_code: request.operation_code as u16,
-            position_block: request.position_block,
-            data_buffer: request.data_buffer,
-            key_buffer: request.key_buffer,
-            key_number: request.key_number as i16,
-            file_path: request.file_path,
-            lock_bias: request.lock_bias as u16,
+    pub fn execute(&mut self, mut request: BtrieveRequest) -> BtrieveResult<BtrieveResponse> {
+        self.renew(&mut request.position_block);
+
+        let response = match self.round_trip(&request) {
+            Ok(response) => response,
+            Err(e) => {
+                let Some(policy) = self.reconnect.clone() else {
+                    return Err(e);
+                };
+                // A transaction dies with its session, so nothing inside it is safe to repeat
+                let retry = is_idempotent(request.operation_code)
+                    && request.lock_bias == 0
+                    && !self.in_transaction;
+                self.in_transaction = false;
+
+                self.reconnect_with(&policy)?;
+                if !retry {
+                    return Err(e);
+                }
+                self.renew(&mut request.position_block);
+                self.round_trip(&request)?
+            }
         };
 
-        // Send request
-        self.writer.write_all(&wire_req.to_bytes())
-            .map_err(|e| BtrieveError::Internal(format!("Write failed: {}", e)))?;
-        self.writer.flush()
-            .map_err(|e| BtrieveError::Internal(format!("Flush failed: {}", e)))?;
+        self.track(&request, &response);
+        Ok(response)
+    }
+
+    /// Send one request and read its response on the current connection
+    fn round_trip(&mut self, request: &BtrieveRequest) -> BtrieveResult<BtrieveResponse> {
+        self.transport.round_trip(request, self.deadline)
+    }
+
+    /// Record state that must survive a reconnect: open files and transactions
+    fn track(&mut self, request: &BtrieveRequest, response: &BtrieveResponse) {
+        use crate::btrieve::op;
+        if response.status_code != 0 {
+            return;
+        }
+        match request.operation_code {
+            op::OPEN => {
+                self.open_files.push(TrackedOpen {
+                    request: request.clone(),
+                    identity: identity(&response.position_block),
+                });
+            }
+            op::CLOSE => {
+                let closed = identity(&request.position_block);
+                self.open_files.retain(|open| open.identity != closed);
+                self.renewed.retain(|(_, renewed)| *renewed != closed);
+            }
+            op::BEGIN_TRANSACTION => self.in_transaction = true,
+            op::END_TRANSACTION | op::ABORT_TRANSACTION => self.in_transaction = false,
+            _ => {}
+        }
+    }
+
+    /// Point a position block of an open lost with a connection at its
+    /// reopen, keeping its file and cursor
+    fn renew(&self, position_block: &mut Vec<u8>) {
+        if self.renewed.is_empty() || position_block.is_empty() {
+            return;
+        }
+        let held = identity(position_block);
+        if let Some((_, new)) = self.renewed.iter().find(|(old, _)| *old == held) {
+            let mut block = PositionBlock::from_bytes(position_block);
+            block.data[CURSOR_ID] = new[0];
+            block.data[STAMPED].copy_from_slice(&new[1..]);
+            *position_block = block.data.to_vec();
+        }
+    }
 
-        // Read response
-        let wire_resp = Response::from_reader(&mut self.reader)
-            .map_err(|e| BtrieveError::Internal(format!("Read failed: {}", e)))?;
+    /// Re-establish the connection and re-open every tracked file
+    fn reconnect_with(&mut self, policy: &ReconnectPolicy) -> BtrieveResult<()> {
+        let Some(addr) = self.addr.clone() else {
+            return Err(BtrieveError::Internal("Transport cannot reconnect".to_string()));
+        };
+        let mut attempt = 0;
+        self.transport = loop {
+            attempt += 1;
+            match transport::connect(&addr, &self.timeouts) {
+                Ok(transport) => break transport,
+                Err(e) if attempt >= policy.max_attempts => return Err(e),
+                Err(_) => thread::sleep(policy.delay),
+            }
+        };
+        // The server may have been restarted or upgraded
+        self.server_info = None;
+        if let Some(idle) = self.keepalive {
+            self.transport.set_keepalive(idle)?;
+        }
 
-        Ok(BtrieveResponse {
-            status_code: wire_resp.status_code as u32,
-            position_block: wire_resp.position_block,
-            data_buffer: wire_resp.data_buffer,
-            key_buffer: wire_resp.key_buffer,
-        })
+        let tracked = std::mem::take(&mut self.open_files);
+        for mut open in tracked {
+            let response = self.round_trip(&open.request)?;
+            if response.status_code != 0 {
+                // The file is gone; later calls on it report the error
+                continue;
+            }
+            let new = identity(&response.position_block);
+            for (_, renewed) in self.renewed.iter_mut().filter(|(_, renewed)| *renewed == open.identity) {
+                *renewed = new.clone();
+            }
+            self.renewed.push((std::mem::replace(&mut open.identity, new), open.identity.clone()));
+            self.open_files.push(open);
+        }
+        Ok(())
     }
 }
 
@@ -74,8 +435,12 @@ pub use async_client::AsyncXtrieveClient;
 #[cfg(feature = "async")]
 mod async_client {
     use super::*;
+    use crate::transport::enable_keepalive;
+    use socket2::SockRef;
     use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+    use xtrieve_engine::protocol::{Request, Response};
     use tokio::net::TcpStream;
+    use xtrieve_engine::protocol::{CAPABILITIES_FOLLOW, DETAIL_FOLLOWS, POSITION_BLOCK_SIZE};
 
     /// Async client for connecting to xtrieved daemon
     ///
@@ -83,49 +448,182 @@ mod async_client {
     pub struct AsyncXtrieveClient {
         reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
         writer: BufWriter<tokio::net::tcp::OwnedWriteHalf>,
+        server_info: Option<ServerInfo>,
+        timeouts: Timeouts,
+        /// Set when a request was abandoned mid-flight; its late reply
+        /// would be read as the answer to the next request
+        broken: bool,
+        /// Whether the server sends the error detail frame, once known
+        error_detail: Option<bool>,
+        /// Whether the first request, asking for the capabilities frame,
+        /// has been sent
+        greeted: bool,
+    }
+
+    /// Run `future`, failing with "`what` timed out" after `limit`
+    async fn within<T>(
+        limit: Option<Duration>,
+        what: &str,
+        future: impl std::future::Future<Output = BtrieveResult<T>>,
+    ) -> BtrieveResult<T> {
+        match limit {
+            Some(limit) => tokio::time::timeout(limit, future).await
+                .unwrap_or_else(|_| Err(BtrieveError::Internal(format!("{} timed out", what)))),
+            None => future.await,
+        }
     }
 
     impl AsyncXtrieveClient {
         /// Connect to xtrieved at the given address (e.g., "127.0.0.1:7419")
         pub async fn connect(addr: &str) -> BtrieveResult<Self> {
-            let stream = TcpStream::connect(addr).await
-                .map_err(|e| BtrieveError::Internal(format!("Connection failed: {}", e)))?;
+            Self::connect_with(addr, Timeouts::default()).await
+        }
+
+        /// Connect with connect/read/write timeouts
+        pub async fn connect_with(addr: &str, timeouts: Timeouts) -> BtrieveResult<Self> {
+            let stream = within(timeouts.connect, "Connection", async {
+                TcpStream::connect(addr).await
+                    .map_err(|e| BtrieveError::Internal(format!("Connection failed: {}", e)))
+            }).await?;
 
             let (read_half, write_half) = stream.into_split();
             let reader = BufReader::new(read_half);
             let writer = BufWriter::new(write_half);
 
-            Ok(AsyncXtrieveClient { reader, writer })
+            Ok(AsyncXtrieveClient {
+                reader,
+                writer,
+                server_info: None,
+                timeouts,
+                broken: false,
+                error_detail: None,
+                greeted: false,
+            })
+        }
+
+        /// Change the read/write timeouts used by later requests
+        pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+            self.timeouts = timeouts;
+        }
+
+        /// Check the server is alive with a Ping (99) round trip
+        pub async fn ping(&mut self) -> BtrieveResult<()> {
+            let response = self.execute(BtrieveRequest {
+                operation_code: crate::btrieve::op::PING,
+                data_buffer: PING_PAYLOAD.to_vec(),
+                ..Default::default()
+            }).await?;
+            check_ping(&response)
+        }
+
+        /// Query the server's version, limits and supported operations
+        /// (cached for the life of the connection, and known without asking
+        /// once the first request's response has brought them)
+        pub async fn server_info(&mut self) -> BtrieveResult<ServerInfo> {
+            if let Some(info) = &self.server_info {
+                return Ok(info.clone());
+            }
+            let response = self.execute(BtrieveRequest {
+                operation_code: crate::btrieve::op::SERVER_INFO,
+                ..Default::default()
+            }).await?;
+            let info = parse_server_info(&response)?;
+            self.server_info = Some(info.clone());
+            Ok(info)
+        }
+
+        /// Enable TCP keepalive so a dead server fails reads instead of hanging
+        pub fn set_keepalive(&self, idle: Duration) -> BtrieveResult<()> {
+            let stream: &TcpStream = self.writer.get_ref().as_ref();
+            enable_keepalive(SockRef::from(stream), idle)
+        }
+
+        /// Execute a Btrieve operation that must complete within `limit`.
+        ///
+        /// When the deadline passes the connection is unusable, since the
+        /// server may still answer the abandoned request; reconnect to continue.
+        pub async fn execute_with_deadline(
+            &mut self,
+            request: BtrieveRequest,
+            limit: Duration,
+        ) -> BtrieveResult<BtrieveResponse> {
+            match tokio::time::timeout(limit, self.execute(request)).await {
+                Ok(result) => result,
+                Err(_) => {
+                    self.broken = true;
+                    Err(BtrieveError::Internal("Request timed out".to_string()))
+                }
+            }
         }
 
         /// Execute a Btrieve operation asynchronously
         pub async fn execute(&mut self, request: BtrieveRequest) -> BtrieveResult<BtrieveResponse> {
+            if self.broken {
+                return Err(BtrieveError::Internal("Connection unusable after an interrupted request".to_string()));
+            }
+
+            // Ask for the error detail frame unless the server is known not
+            // to send it, and with the first request for the capabilities
+            // frame; one from before a frame refuses with status 1
+            let ask = self.error_detail != Some(false);
+            let mut wire_resp = self.exchange(&request, ask, !self.greeted).await?;
+            if !self.greeted {
+                self.greeted = true;
+                match wire_resp.capabilities.take() {
+                    Some(info) => self.server_info = Some(info),
+                    None if wire_resp.status_code == StatusCode::InvalidOperation.as_raw() => {
+                        wire_resp = self.exchange(&request, ask, false).await?;
+                    }
+                    None => {}
+                }
+            }
+            if ask && self.error_detail.is_none() {
+                self.error_detail = Some(wire_resp.detail.is_some());
+                if wire_resp.detail.is_none() && wire_resp.status_code == StatusCode::InvalidOperation.as_raw() {
+                    wire_resp = self.exchange(&request, false, false).await?;
+                }
+            }
+
+            Ok(BtrieveResponse {
+                status_code: wire_resp.status_code as u32,
+                position_block: wire_resp.position_block,
+                data_buffer: wire_resp.data_buffer,
+                key_buffer: wire_resp.key_buffer,
+                detail: wire_resp.detail.filter(|detail| !detail.message.is_empty()),
+            })
+        }
+
+        async fn exchange(&mut self, request: &BtrieveRequest, error_detail: bool, hello: bool) -> BtrieveResult<Response> {
             // Convert to wire protocol
             let wire_req = Request {
                 operation_code: request.operation_code as u16,
-                position_block: request.position_block,
-                data_buffer: request.data_buffer,
-                key_buffer: request.key_buffer,
+                position_block: request.position_block.clone(),
+                data_buffer: request.data_buffer.clone(),
+                key_buffer: request.key_buffer.clone(),
                 key_number: request.key_number as i16,
-                file_path: request.file_path,
+                file_path: request.file_path.clone(),
                 lock_bias: request.lock_bias as u16,
+                client_id: request.client_id,
+                error_detail,
+                request_id: request.request_id,
+                hello,
             };
 
-            // Send request
-            self.writer.write_all(&wire_req.to_bytes()).await
-                .map_err(|e| BtrieveError::Internal(format!("Write failed: {}", e)))?;
-            self.writer.flush().await
-                .map_err(|e| BtrieveError::Internal(format!("Flush failed: {}", e)))?;
+            // Send request; a write cut short leaves a partial frame on the wire
+            self.broken = true;
+            let writer = &mut self.writer;
+            within(self.timeouts.write, "Write", async {
+                writer.write_all(&wire_req.to_bytes()).await
+                    .map_err(|e| BtrieveError::Internal(format!("Write failed: {}", e)))?;
+                writer.flush().await
+                    .map_err(|e| BtrieveError::Internal(format!("Flush failed: {}", e)))
+            }).await?;
 
             // Read response
-            let wire_resp = self.read_response().await?;
-
-            Ok(BtrieveResponse {
-                status_code: wire_resp.status_code as u32,
-                position_block: wire_resp.position_block,
-                data_buffer: wire_resp.data_buffer,
-                key_buffer: wire_resp.key_buffer,
-            })
+            let read_timeout = self.timeouts.read;
+            let wire_resp = within(read_timeout, "Read", self.read_response()).await?;
+            self.broken = false;
+            Ok(wire_resp)
         }
 
         /// Read response from the stream asynchronously
@@ -136,7 +634,8 @@ mod async_client {
             // Status code
             self.reader.read_exact(&mut buf2).await
                 .map_err(|e| BtrieveError::Internal(format!("Read status failed: {}", e)))?;
-            let status_code = u16::from_le_bytes(buf2);
+            let raw_status = u16::from_le_bytes(buf2);
+            let status_code = raw_status & !(DETAIL_FOLLOWS | CAPABILITIES_FOLLOW);
 
             // Position block
             let mut position_block = vec![0u8; POSITION_BLOCK_SIZE];
@@ -163,11 +662,43 @@ mod async_client {
                     .map_err(|e| BtrieveError::Internal(format!("Read key failed: {}", e)))?;
             }
 
+            // Error detail
+            let detail = if raw_status & DETAIL_FOLLOWS != 0 {
+                let mut text = [String::new(), String::new()];
+                for text in &mut text {
+                    self.reader.read_exact(&mut buf2).await
+                        .map_err(|e| BtrieveError::Internal(format!("Read detail_len failed: {}", e)))?;
+                    let mut bytes = vec![0u8; u16::from_le_bytes(buf2) as usize];
+                    self.reader.read_exact(&mut bytes).await
+                        .map_err(|e| BtrieveError::Internal(format!("Read detail failed: {}", e)))?;
+                    *text = String::from_utf8_lossy(&bytes).to_string();
+                }
+                let [message, context] = text;
+                Some(ErrorDetail { message, context })
+            } else {
+                None
+            };
+
+            // Capabilities
+            let capabilities = if raw_status & CAPABILITIES_FOLLOW != 0 {
+                self.reader.read_exact(&mut buf2).await
+                    .map_err(|e| BtrieveError::Internal(format!("Read info_len failed: {}", e)))?;
+                let mut bytes = vec![0u8; u16::from_le_bytes(buf2) as usize];
+                self.reader.read_exact(&mut bytes).await
+                    .map_err(|e| BtrieveError::Internal(format!("Read info failed: {}", e)))?;
+                Some(ServerInfo::from_bytes(&bytes)
+                    .map_err(|e| BtrieveError::Internal(format!("Bad server info: {}", e)))?)
+            } else {
+                None
+            };
+
             Ok(Response {
                 status_code,
                 position_block,
                 data_buffer,
                 key_buffer,
+                detail,
+                capabilities,
             })
         }
     }
@@ -191,6 +722,9 @@ pub struct BtrieveRequest {
     pub open_mode: i32,
     pub lock_bias: u32,
     pub client_id: u64,
+    /// Id a Cancel (see `XtrieveClient::cancel`) can stop the call by, 0
+    /// for none. The binary protocol carries it; other transports drop it
+    pub request_id: u64,
 }
 
 /// Btrieve response structure
@@ -200,4 +734,75 @@ pub struct BtrieveResponse {
     pub position_block: Vec<u8>,
     pub data_buffer: Vec<u8>,
     pub key_buffer: Vec<u8>,
+    /// The server's account of a failure, from servers that send one
+    pub detail: Option<ErrorDetail>,
+}
+
+impl BtrieveResponse {
+    /// The error this response's status stands for, carrying the detail
+    /// when the server sent one
+    pub fn to_error(&self) -> BtrieveError {
+        let status = StatusCode::from_raw(self.status_code as u16);
+        match &self.detail {
+            Some(detail) => BtrieveError::Detailed { status, detail: detail.clone() },
+            None => BtrieveError::Status(status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btrieve::op;
+    use crate::{FileBuilder, KeyBuilder};
+
+    /// A connection the server has dropped
+    struct Dropped;
+
+    impl Transport for Dropped {
+        fn round_trip(&mut self, _request: &BtrieveRequest, _deadline: Option<Instant>) -> BtrieveResult<BtrieveResponse> {
+            Err(BtrieveError::Internal("Connection reset".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_reconnect_renews_each_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = XtrieveClient::connect(&format!("file://{}", dir.path().display())).unwrap();
+        client.set_reconnect(Some(ReconnectPolicy { max_attempts: 1, delay: Duration::ZERO }));
+        FileBuilder::new(4).key(KeyBuilder::unsigned(0, 4)).create(&mut client, "PARTS.DAT").unwrap();
+        let run = |client: &mut XtrieveClient, operation_code, position_block: &[u8], data_buffer: Vec<u8>| {
+            let response = client.execute(BtrieveRequest {
+                operation_code,
+                position_block: position_block.to_vec(),
+                data_buffer_length: data_buffer.len().max(4) as u32,
+                data_buffer,
+                file_path: "PARTS.DAT".to_string(),
+                ..Default::default()
+            }).unwrap();
+            assert_eq!(response.status_code, 0);
+            response
+        };
+
+        // Two opens of one file, one cursor each
+        let first = run(&mut client, op::OPEN, &[], Vec::new()).position_block;
+        let second = run(&mut client, op::OPEN, &[], Vec::new()).position_block;
+        for part in 1u32..=3 {
+            run(&mut client, op::INSERT, &first, part.to_le_bytes().to_vec());
+        }
+        let first = run(&mut client, op::GET_FIRST, &first, Vec::new()).position_block;
+        let second = run(&mut client, op::GET_LAST, &second, Vec::new()).position_block;
+
+        // The reopens get new checks, and the blocks held before still
+        // name their own open and place
+        client.transport = Box::new(Dropped);
+        let next = run(&mut client, op::GET_NEXT, &first, Vec::new());
+        assert_eq!(next.data_buffer, 2u32.to_le_bytes());
+        assert_ne!(identity(&next.position_block), identity(&first));
+        let previous = run(&mut client, op::GET_PREVIOUS, &second, Vec::new());
+        assert_eq!(previous.data_buffer, 2u32.to_le_bytes());
+        run(&mut client, op::CLOSE, &first, Vec::new());
+        run(&mut client, op::CLOSE, &second, Vec::new());
+        assert!(client.open_files.is_empty());
+    }
 }
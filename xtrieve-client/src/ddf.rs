@@ -0,0 +1,821 @@
+//! Data dictionary (DDF) files
+//!
+//! Xtrieve, Scalable SQL and Pervasive applications describe their Btrieve
+//! files in three dictionary files kept next to the data:
+//!
+//! - `FILE.DDF`: one record per table, naming the file that holds it
+//! - `FIELD.DDF`: one record per column (table, name, type, offset, size)
+//! - `INDEX.DDF`: one record per key segment, pointing at a column
+//!
+//! `Dictionary` joins them into per-table schemas whose columns can decode
+//! raw records into `Value`s, for export tools and the JSON gateway.
+//!
+//! A client given a dictionary (`XtrieveClient::load_dictionary`, or
+//! `add_dictionary` with one from `Dictionary::load_local`) hands each file
+//! it opens the table describing it, so records read from the file can be
+//! accessed by field name instead of by offset:
+//!
+//! ```ignore
+//! client.load_dictionary("accounts")?;
+//! let mut file = BtrieveFile::open(client, "accounts/CUST.DAT", 0)?;
+//! let name = file.get_first()?.field("Name")?;
+//! ```
+//!
+//! ```ignore
+//! use xtrieve_client::ddf::Dictionary;
+//!
+//! let dict = Dictionary::load(&mut client, "accounts")?;
+//! let customers = dict.table("CUSTOMER").unwrap();
+//! for (name, value) in customers.decode(&record.data)? {
+//!     println!("{} = {}", name, value);
+//! }
+//! ```
+
+use std::fmt;
+use std::path::Path;
+
+use xtrieve_engine::storage::KeyType;
+use xtrieve_engine::{BtrieveError, BtrieveResult, StatusCode};
+
+use crate::btrieve::{check_status, op};
+use crate::client::{BtrieveRequest, XtrieveClient};
+use crate::local::EMBEDDED_SCHEME;
+use crate::typed::{self, BtrieveDate, BtrieveTime, Encoding, Field};
+
+pub const FILE_DDF: &str = "FILE.DDF";
+pub const FIELD_DDF: &str = "FIELD.DDF";
+pub const INDEX_DDF: &str = "INDEX.DDF";
+
+/// X$File record: Xf$Id (2), Xf$Name (20), Xf$Loc (64), Xf$Flags (1), then
+/// 10 reserved bytes that are not read
+const FILE_RECORD_LEN: usize = 87;
+/// X$Field record: Xe$Id (2), Xe$File (2), Xe$Name (20), Xe$DataType (1),
+/// Xe$Offset (2), Xe$Size (2), Xe$Dec (1), Xe$Flags (2)
+const FIELD_RECORD_LEN: usize = 32;
+/// X$Index record: Xi$File (2), Xi$Field (2), Xi$Number (2), Xi$Part (2), Xi$Flags (2)
+const INDEX_RECORD_LEN: usize = 10;
+
+/// FIELD.DDF entries with a type code from here up name indexes, not columns
+const FIRST_NON_COLUMN_TYPE: u8 = 227;
+
+/// Column data types (Xe$DataType)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    String,
+    Integer,
+    Float,
+    Date,
+    Time,
+    Decimal,
+    Money,
+    Logical,
+    Numeric,
+    BFloat,
+    LString,
+    ZString,
+    Note,
+    LVar,
+    UnsignedBinary,
+    AutoIncrement,
+    Bit,
+    Other(u8),
+}
+
+impl DataType {
+    /// Type from its name in a mapping file (`string`, `integer`, `money`,
+    /// `unsigned binary`, ...), ignoring case and blanks
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+        Some(match name.to_ascii_lowercase().as_str() {
+            "string" => DataType::String,
+            "integer" => DataType::Integer,
+            "float" => DataType::Float,
+            "date" => DataType::Date,
+            "time" => DataType::Time,
+            "decimal" => DataType::Decimal,
+            "money" => DataType::Money,
+            "logical" => DataType::Logical,
+            "numeric" => DataType::Numeric,
+            "bfloat" => DataType::BFloat,
+            "lstring" => DataType::LString,
+            "zstring" => DataType::ZString,
+            "note" => DataType::Note,
+            "lvar" => DataType::LVar,
+            "unsignedbinary" | "unsigned" => DataType::UnsignedBinary,
+            "autoincrement" | "autoinc" => DataType::AutoIncrement,
+            "bit" => DataType::Bit,
+            _ => return None,
+        })
+    }
+
+    pub fn from_raw(value: u8) -> Self {
+        match value {
+            0 => DataType::String,
+            1 => DataType::Integer,
+            2 => DataType::Float,
+            3 => DataType::Date,
+            4 => DataType::Time,
+            5 => DataType::Decimal,
+            6 => DataType::Money,
+            7 => DataType::Logical,
+            8 => DataType::Numeric,
+            9 => DataType::BFloat,
+            10 => DataType::LString,
+            11 => DataType::ZString,
+            12 => DataType::Note,
+            13 => DataType::LVar,
+            14 => DataType::UnsignedBinary,
+            15 => DataType::AutoIncrement,
+            16 => DataType::Bit,
+            other => DataType::Other(other),
+        }
+    }
+
+    /// Key type for indexing a column of this type, if the engine has one
+    pub fn key_type(&self) -> Option<KeyType> {
+        Some(match self {
+            DataType::String => KeyType::String,
+            DataType::Integer => KeyType::Integer,
+            DataType::Float => KeyType::Float,
+            DataType::Date => KeyType::Date,
+            DataType::Time => KeyType::Time,
+            DataType::Decimal => KeyType::Decimal,
+            DataType::Money => KeyType::Money,
+            DataType::Logical => KeyType::Logical,
+            DataType::Numeric => KeyType::Numeric,
+            DataType::BFloat => KeyType::BFloat,
+            DataType::LString => KeyType::LString,
+            DataType::ZString => KeyType::ZString,
+            DataType::UnsignedBinary => KeyType::UnsignedBinary,
+            DataType::AutoIncrement => KeyType::AutoIncrement,
+            DataType::Note | DataType::LVar | DataType::Bit | DataType::Other(_) => return None,
+        })
+    }
+}
+
+/// A decoded column value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Column past the end of a variable-length record, or a zero date
+    Null,
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    /// Fixed-point number: `unscaled / 10^scale`
+    Decimal { unscaled: i64, scale: u8 },
+    Bool(bool),
+    Text(String),
+    Date(BtrieveDate),
+    Time(BtrieveTime),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::UInt(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Decimal { unscaled, scale: 0 } => write!(f, "{}", unscaled),
+            Value::Decimal { unscaled, scale } => {
+                let divisor = 10u64.pow(*scale as u32);
+                let sign = if *unscaled < 0 { "-" } else { "" };
+                let abs = unscaled.unsigned_abs();
+                write!(f, "{}{}.{:0width$}", sign, abs / divisor, abs % divisor, width = *scale as usize)
+            }
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Text(v) => f.write_str(v),
+            Value::Date(d) => write!(f, "{:04}-{:02}-{:02}", d.year, d.month, d.day),
+            Value::Time(t) => write!(
+                f,
+                "{:02}:{:02}:{:02}.{:02}",
+                t.hours, t.minutes, t.seconds, t.hundredths
+            ),
+            Value::Bytes(v) => v.iter().try_for_each(|b| write!(f, "{:02x}", b)),
+        }
+    }
+}
+
+/// A column of a table (one FIELD.DDF record)
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub id: u16,
+    pub name: String,
+    pub data_type: DataType,
+    /// 0-based offset in the record
+    pub offset: u16,
+    pub size: u16,
+    /// Decimal places for Decimal/Money/Numeric, bit number for Bit
+    pub decimals: u8,
+    pub flags: u16,
+}
+
+impl Column {
+    fn from_record(data: &[u8]) -> BtrieveResult<(u16, Self)> {
+        let data = typed::slice(data, 0, FIELD_RECORD_LEN)?;
+        let file = u16::from_le_bytes([data[2], data[3]]);
+        Ok((
+            file,
+            Column {
+                id: u16::from_le_bytes([data[0], data[1]]),
+                name: String::read(&data[4..24], Encoding::Native)?,
+                data_type: DataType::from_raw(data[24]),
+                offset: u16::from_le_bytes([data[25], data[26]]),
+                size: u16::from_le_bytes([data[27], data[28]]),
+                decimals: data[29],
+                flags: u16::from_le_bytes([data[30], data[31]]),
+            },
+        ))
+    }
+
+    /// How the typed mapping should read this column, if it can
+    pub fn encoding(&self) -> Option<Encoding> {
+        match self.data_type {
+            DataType::ZString => Some(Encoding::ZString),
+            DataType::Decimal | DataType::Money => Some(Encoding::Bcd),
+            DataType::String
+            | DataType::Integer
+            | DataType::Float
+            | DataType::Date
+            | DataType::Time
+            | DataType::UnsignedBinary
+            | DataType::AutoIncrement => Some(Encoding::Native),
+            _ => None,
+        }
+    }
+
+    /// Decode this column from a record
+    pub fn decode(&self, record: &[u8]) -> BtrieveResult<Value> {
+        let bytes = match typed::slice(record, self.offset as usize, self.size as usize) {
+            Ok(bytes) if !bytes.is_empty() => bytes,
+            _ => return Ok(Value::Null),
+        };
+        let value = match self.data_type {
+            DataType::String => Value::Text(String::read(bytes, Encoding::Native)?),
+            DataType::ZString | DataType::Note => Value::Text(String::read(bytes, Encoding::ZString)?),
+            DataType::LString => {
+                let len = (bytes[0] as usize).min(bytes.len() - 1);
+                Value::Text(String::from_utf8_lossy(&bytes[1..1 + len]).into_owned())
+            }
+            DataType::Integer | DataType::AutoIncrement => Value::Int(read_signed(bytes)?),
+            DataType::UnsignedBinary => Value::UInt(u64::read(bytes, Encoding::Native)?),
+            DataType::Float => match bytes.len() {
+                4 => Value::Float(f32::read(bytes, Encoding::Native)? as f64),
+                _ => Value::Float(f64::read(bytes, Encoding::Native)?),
+            },
+            DataType::BFloat => Value::Float(read_mbf(bytes)?),
+            DataType::Decimal => Value::Decimal {
+                unscaled: typed::read_bcd(bytes)?,
+                scale: self.decimals,
+            },
+            DataType::Money => Value::Decimal {
+                unscaled: typed::read_bcd(bytes)?,
+                scale: if self.decimals == 0 { 2 } else { self.decimals },
+            },
+            DataType::Numeric => Value::Decimal {
+                unscaled: read_numeric(bytes)?,
+                scale: self.decimals,
+            },
+            DataType::Logical => Value::Bool(bytes.iter().any(|&b| b != 0)),
+            DataType::Bit => Value::Bool(bytes[0] & (1 << (self.decimals & 7)) != 0),
+            DataType::Date => match BtrieveDate::read(bytes, Encoding::Native)? {
+                date if date == BtrieveDate::default() => Value::Null,
+                date => Value::Date(date),
+            },
+            DataType::Time => Value::Time(BtrieveTime::read(bytes, Encoding::Native)?),
+            DataType::LVar | DataType::Other(_) => Value::Bytes(bytes.to_vec()),
+        };
+        Ok(value)
+    }
+
+    /// Store a value written the way `Value` displays it (`12.50`,
+    /// `1999-12-31`, `true`, ...) into this column of a record. Empty text
+    /// stores blanks or zeros.
+    pub fn encode(&self, text: &str, record: &mut [u8]) -> BtrieveResult<()> {
+        let (offset, size) = (self.offset as usize, self.size as usize);
+        let bytes = record
+            .get_mut(offset..offset + size)
+            .filter(|b| !b.is_empty())
+            .ok_or(BtrieveError::Status(StatusCode::DataBufferTooShort))?;
+        let text = text.trim();
+        let bad = || BtrieveError::Internal(format!("{}: cannot store '{}' as {:?}", self.name, text, self.data_type));
+
+        match self.data_type {
+            DataType::String => text.to_string().write(bytes, Encoding::Native)?,
+            DataType::ZString => text.to_string().write(bytes, Encoding::ZString)?,
+            DataType::LString => {
+                let len = text.len().min(bytes.len() - 1).min(255);
+                bytes.fill(0);
+                bytes[0] = len as u8;
+                bytes[1..1 + len].copy_from_slice(&text.as_bytes()[..len]);
+            }
+            _ if text.is_empty() => bytes.fill(0),
+            DataType::Integer | DataType::AutoIncrement => {
+                let value: i64 = text.parse().map_err(|_| bad())?;
+                let fits = match size {
+                    1 => i8::try_from(value).is_ok(),
+                    2 => i16::try_from(value).is_ok(),
+                    4 => i32::try_from(value).is_ok(),
+                    _ => true,
+                };
+                if !fits {
+                    return Err(bad());
+                }
+                value.write(bytes, Encoding::Native)?;
+            }
+            DataType::UnsignedBinary => {
+                let value: u64 = text.parse().map_err(|_| bad())?;
+                if size < 8 && value >> (size * 8) != 0 {
+                    return Err(bad());
+                }
+                value.write(bytes, Encoding::Native)?;
+            }
+            DataType::Float => {
+                let value: f64 = text.parse().map_err(|_| bad())?;
+                match size {
+                    4 => (value as f32).write(bytes, Encoding::Native)?,
+                    _ => value.write(bytes, Encoding::Native)?,
+                }
+            }
+            DataType::Decimal | DataType::Money | DataType::Numeric => {
+                let scale = match (self.data_type, self.decimals) {
+                    (DataType::Money, 0) => 2,
+                    (_, decimals) => decimals,
+                };
+                let value = parse_scaled(text, scale).ok_or_else(bad)?;
+                match self.data_type {
+                    DataType::Numeric => write_numeric(value, bytes).ok_or_else(bad)?,
+                    _ => typed::write_bcd(value, bytes)?,
+                }
+            }
+            DataType::Logical => {
+                let value = match text.to_ascii_lowercase().as_str() {
+                    "true" | "t" | "yes" | "y" | "1" => 1,
+                    "false" | "f" | "no" | "n" | "0" => 0,
+                    _ => return Err(bad()),
+                };
+                bytes.fill(0);
+                bytes[0] = value;
+            }
+            DataType::Date => {
+                let mut parts = text.splitn(3, '-').map(|p| p.parse::<u16>().ok());
+                let (Some(Some(year)), Some(Some(month)), Some(Some(day))) = (parts.next(), parts.next(), parts.next()) else {
+                    return Err(bad());
+                };
+                let date = BtrieveDate { day: day as u8, month: month as u8, year };
+                date.write(bytes, Encoding::Native)?;
+            }
+            DataType::Time => {
+                let (clock, hundredths) = text.split_once('.').unwrap_or((text, "0"));
+                let mut parts = clock.split(':').map(|p| p.parse::<u8>().ok());
+                let mut next = || parts.next().map_or(Some(0), |p| p);
+                let (Some(hours), Some(minutes), Some(seconds)) = (next(), next(), next()) else {
+                    return Err(bad());
+                };
+                let hundredths = hundredths.parse().map_err(|_| bad())?;
+                BtrieveTime { hundredths, seconds, minutes, hours }.write(bytes, Encoding::Native)?;
+            }
+            DataType::BFloat | DataType::Note | DataType::LVar | DataType::Bit | DataType::Other(_) => {
+                return Err(bad())
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `12.5` at scale 2 is 1250; more fractional digits than the scale allows
+/// is an error rather than a silent rounding
+fn parse_scaled(text: &str, scale: u8) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && fraction.is_empty()
+        || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+        || fraction.len() > scale as usize
+    {
+        return None;
+    }
+    let mut value: i64 = 0;
+    let padded = fraction.chars().chain(std::iter::repeat('0')).take(scale as usize);
+    for c in whole.chars().chain(padded) {
+        value = value.checked_mul(10)?.checked_add(c.to_digit(10)? as i64)?;
+    }
+    Some(if negative { -value } else { value })
+}
+
+/// Zero-padded ASCII digits, negatives carrying the sign as an overpunch
+/// on the last digit
+fn write_numeric(value: i64, bytes: &mut [u8]) -> Option<()> {
+    let digits = value.unsigned_abs().to_string();
+    if digits.len() > bytes.len() {
+        return None;
+    }
+    let pad = bytes.len() - digits.len();
+    bytes[..pad].fill(b'0');
+    bytes[pad..].copy_from_slice(digits.as_bytes());
+    if value < 0 {
+        let last = bytes.len() - 1;
+        bytes[last] = match bytes[last] {
+            b'0' => b'}',
+            d => d - b'1' + b'J',
+        };
+    }
+    Some(())
+}
+
+/// Sign-extend a 1, 2, 4 or 8 byte little-endian integer
+fn read_signed(bytes: &[u8]) -> BtrieveResult<i64> {
+    Ok(match bytes.len() {
+        1 => i8::read(bytes, Encoding::Native)? as i64,
+        2 => i16::read(bytes, Encoding::Native)? as i64,
+        4 => i32::read(bytes, Encoding::Native)? as i64,
+        _ => i64::read(bytes, Encoding::Native)?,
+    })
+}
+
+/// Microsoft Binary Format float (4 or 8 bytes): exponent in the last
+/// byte, sign in the top bit of the one before it
+fn read_mbf(bytes: &[u8]) -> BtrieveResult<f64> {
+    let (&exponent, mantissa) = bytes
+        .split_last()
+        .filter(|(_, m)| !m.is_empty())
+        .ok_or(BtrieveError::Status(StatusCode::DataBufferTooShort))?;
+    if exponent == 0 {
+        return Ok(0.0);
+    }
+    let negative = mantissa[mantissa.len() - 1] & 0x80 != 0;
+    // Restore the implied leading 1 bit
+    let bits = mantissa
+        .iter()
+        .rev()
+        .enumerate()
+        .fold(0u64, |acc, (i, &b)| (acc << 8) | if i == 0 { (b | 0x80) as u64 } else { b as u64 });
+    let value = bits as f64 * 2f64.powi(exponent as i32 - 128 - (mantissa.len() * 8) as i32);
+    Ok(if negative { -value } else { value })
+}
+
+/// ASCII digits whose last character may carry the sign as an overpunch
+/// (`{`, `A`-`I` positive; `}`, `J`-`R` negative)
+pub(crate) fn read_numeric(bytes: &[u8]) -> BtrieveResult<i64> {
+    let bad = || BtrieveError::Internal("Invalid NUMERIC digit".to_string());
+    let mut value: i64 = 0;
+    let mut negative = false;
+    for (i, &b) in bytes.iter().enumerate() {
+        let digit = match b {
+            b'0'..=b'9' => b - b'0',
+            b' ' => continue,
+            b'-' if i == 0 => {
+                negative = true;
+                continue;
+            }
+            b'{' | b'}' if i == bytes.len() - 1 => {
+                negative = b == b'}';
+                0
+            }
+            b'A'..=b'I' if i == bytes.len() - 1 => b - b'A' + 1,
+            b'J'..=b'R' if i == bytes.len() - 1 => {
+                negative = true;
+                b - b'J' + 1
+            }
+            _ => return Err(bad()),
+        };
+        value = value
+            .checked_mul(10)
+            .and_then(|v| v.checked_add(digit as i64))
+            .ok_or_else(bad)?;
+    }
+    Ok(if negative { -value } else { value })
+}
+
+/// A segment of an index (one INDEX.DDF record)
+#[derive(Debug, Clone, Copy)]
+pub struct IndexSegment {
+    /// Column ID (`Column::id`)
+    pub column: u16,
+    /// Key flags (duplicates, modifiable, descending, ...)
+    pub flags: u16,
+}
+
+/// An index (key) of a table
+#[derive(Debug, Clone)]
+pub struct Index {
+    /// Key number
+    pub number: u16,
+    pub segments: Vec<IndexSegment>,
+}
+
+/// A table (one FILE.DDF record) with its columns and indexes
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub id: u16,
+    pub name: String,
+    /// Data file as written in the dictionary, usually relative to it
+    pub location: String,
+    pub flags: u8,
+    /// Columns in ID order
+    pub columns: Vec<Column>,
+    /// Indexes by key number
+    pub indexes: Vec<Index>,
+}
+
+impl Table {
+    fn from_record(data: &[u8]) -> BtrieveResult<Self> {
+        let data = typed::slice(data, 0, FILE_RECORD_LEN)?;
+        Ok(Table {
+            id: u16::from_le_bytes([data[0], data[1]]),
+            name: String::read(&data[2..22], Encoding::Native)?,
+            location: String::read(&data[22..86], Encoding::Native)?,
+            flags: data[86],
+            columns: Vec::new(),
+            indexes: Vec::new(),
+        })
+    }
+
+    /// Column by name, ignoring case
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn column_by_id(&self, id: u16) -> Option<&Column> {
+        self.columns.iter().find(|c| c.id == id)
+    }
+
+    /// Path of the data file relative to the data directory, given the
+    /// directory the dictionary was loaded from
+    pub fn file_path(&self, dictionary_dir: &str) -> String {
+        let location = self.location.replace('\\', "/");
+        if dictionary_dir.is_empty() || location.starts_with('/') {
+            location
+        } else {
+            format!("{}/{}", dictionary_dir.trim_end_matches('/'), location)
+        }
+    }
+
+    /// Bytes needed to hold every column
+    pub fn record_length(&self) -> usize {
+        self.columns
+            .iter()
+            .map(|c| c.offset as usize + c.size as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Decode every column of a record, in column order
+    pub fn decode(&self, record: &[u8]) -> BtrieveResult<Vec<(&str, Value)>> {
+        self.columns
+            .iter()
+            .map(|c| Ok((c.name.as_str(), c.decode(record)?)))
+            .collect()
+    }
+}
+
+/// The tables described by a FILE/FIELD/INDEX.DDF set
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    pub tables: Vec<Table>,
+}
+
+impl Dictionary {
+    /// Join raw FILE.DDF, FIELD.DDF and INDEX.DDF records
+    pub fn from_records<F, C, I>(files: F, fields: C, indexes: I) -> BtrieveResult<Self>
+    where
+        F: IntoIterator,
+        F::Item: AsRef<[u8]>,
+        C: IntoIterator,
+        C::Item: AsRef<[u8]>,
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut tables = files
+            .into_iter()
+            .map(|r| Table::from_record(r.as_ref()))
+            .collect::<BtrieveResult<Vec<_>>>()?;
+
+        for record in fields {
+            let (file, column) = Column::from_record(record.as_ref())?;
+            if let DataType::Other(code) = column.data_type {
+                if code >= FIRST_NON_COLUMN_TYPE {
+                    continue;
+                }
+            }
+            if let Some(table) = tables.iter_mut().find(|t| t.id == file) {
+                table.columns.push(column);
+            }
+        }
+
+        let mut parts = Vec::new();
+        for record in indexes {
+            let data = typed::slice(record.as_ref(), 0, INDEX_RECORD_LEN)?;
+            let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+            parts.push((word(0), word(4), word(6), IndexSegment { column: word(2), flags: word(8) }));
+        }
+        parts.sort_by_key(|&(file, number, part, _)| (file, number, part));
+
+        for table in &mut tables {
+            table.columns.sort_by_key(|c| c.id);
+            for &(_, number, _, segment) in parts.iter().filter(|p| p.0 == table.id) {
+                match table.indexes.last_mut() {
+                    Some(index) if index.number == number => index.segments.push(segment),
+                    _ => table.indexes.push(Index { number, segments: vec![segment] }),
+                }
+            }
+        }
+        Ok(Dictionary { tables })
+    }
+
+    /// Read the dictionary in `dir` (relative to the server's data directory)
+    pub fn load(client: &mut XtrieveClient, dir: &str) -> BtrieveResult<Self> {
+        let path = |name: &str| match dir {
+            "" => name.to_string(),
+            dir => format!("{}/{}", dir.trim_end_matches('/'), name),
+        };
+        Self::from_records(
+            read_all(client, &path(FILE_DDF))?,
+            read_all(client, &path(FIELD_DDF))?,
+            read_all(client, &path(INDEX_DDF))?,
+        )
+    }
+
+    /// Read the dictionary files in a local directory with the embedded
+    /// engine, without a server
+    pub fn load_local(dir: impl AsRef<Path>) -> BtrieveResult<Self> {
+        let mut client = XtrieveClient::connect(&format!("{}{}", EMBEDDED_SCHEME, dir.as_ref().display()))?;
+        Self::load(&mut client, "")
+    }
+
+    /// Table by name, ignoring case
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|t| t.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Every record of a file in physical order
+fn read_all(client: &mut XtrieveClient, path: &str) -> BtrieveResult<Vec<Vec<u8>>> {
+    let opened = check_status(client.execute(BtrieveRequest {
+        operation_code: op::OPEN,
+        file_path: path.to_string(),
+        ..Default::default()
+    })?)?;
+    let mut position_block = opened.position_block;
+
+    let mut records = Vec::new();
+    let mut operation = op::STEP_FIRST;
+    let result = loop {
+        let response = client.execute(BtrieveRequest {
+            operation_code: operation,
+            position_block: position_block.clone(),
+            ..Default::default()
+        });
+        match response.and_then(check_status) {
+            Ok(response) => {
+                position_block = response.position_block;
+                records.push(response.data_buffer);
+                operation = op::STEP_NEXT;
+            }
+            Err(BtrieveError::Status(StatusCode::EndOfFile)) => break Ok(records),
+            Err(e) => break Err(e),
+        }
+    };
+
+    client.execute(BtrieveRequest {
+        operation_code: op::CLOSE,
+        position_block,
+        ..Default::default()
+    })?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_record(id: u16, name: &str, loc: &str) -> Vec<u8> {
+        let mut r = vec![b' '; FILE_RECORD_LEN];
+        r[0..2].copy_from_slice(&id.to_le_bytes());
+        r[2..2 + name.len()].copy_from_slice(name.as_bytes());
+        r[22..22 + loc.len()].copy_from_slice(loc.as_bytes());
+        r[86] = 0;
+        r
+    }
+
+    fn field_record(id: u16, file: u16, name: &str, data_type: u8, offset: u16, size: u16, dec: u8) -> Vec<u8> {
+        let mut r = vec![0u8; FIELD_RECORD_LEN];
+        r[0..2].copy_from_slice(&id.to_le_bytes());
+        r[2..4].copy_from_slice(&file.to_le_bytes());
+        r[4..24].fill(b' ');
+        r[4..4 + name.len()].copy_from_slice(name.as_bytes());
+        r[24] = data_type;
+        r[25..27].copy_from_slice(&offset.to_le_bytes());
+        r[27..29].copy_from_slice(&size.to_le_bytes());
+        r[29] = dec;
+        r
+    }
+
+    fn index_record(file: u16, field: u16, number: u16, part: u16) -> Vec<u8> {
+        [file, field, number, part, 0].iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_dictionary_join() {
+        let dict = Dictionary::from_records(
+            [file_record(10, "Customer", "DATA\\CUST.DAT")],
+            [
+                field_record(102, 10, "Balance", 5, 34, 6, 2),
+                field_record(100, 10, "Id", 14, 0, 4, 0),
+                field_record(101, 10, "Name", 0, 4, 30, 0),
+                field_record(103, 10, "ByName", 255, 0, 0, 0),
+            ],
+            [index_record(10, 101, 1, 0), index_record(10, 100, 0, 0), index_record(10, 102, 1, 1)],
+        )
+        .unwrap();
+
+        let table = dict.table("CUSTOMER").unwrap();
+        let names: Vec<_> = table.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Id", "Name", "Balance"]);
+        assert_eq!(table.record_length(), 40);
+        assert_eq!(table.file_path("acct"), "acct/DATA/CUST.DAT");
+        assert_eq!(table.indexes.len(), 2);
+        assert_eq!(table.indexes[1].segments.iter().map(|s| s.column).collect::<Vec<_>>(), [101, 102]);
+
+        let mut record = vec![b' '; 40];
+        record[0..4].copy_from_slice(&42u32.to_le_bytes());
+        record[4..7].copy_from_slice(b"Ada");
+        record[34..40].copy_from_slice(&[0x00, 0x00, 0x01, 0x23, 0x45, 0x6D]);
+        let row = table.decode(&record).unwrap();
+        assert_eq!(row[0], ("Id", Value::UInt(42)));
+        assert_eq!(row[1], ("Name", Value::Text("Ada".into())));
+        assert_eq!(row[2].1.to_string(), "-1234.56");
+    }
+
+    #[test]
+    fn test_schema_registry() {
+        use crate::{BtrieveFile, FileBuilder, KeyBuilder};
+
+        let dict = Dictionary::from_records(
+            [file_record(10, "Customer", "DATA\\CUST.DAT")],
+            [field_record(100, 10, "Id", 14, 0, 4, 0), field_record(101, 10, "Name", 0, 4, 30, 0)],
+            [index_record(10, 100, 0, 0)],
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("acct/DATA")).unwrap();
+        let mut client = XtrieveClient::connect(&format!("{}{}", EMBEDDED_SCHEME, dir.path().display())).unwrap();
+        client.add_dictionary(&dict, "acct");
+        assert!(client.schema("ACCT\\data\\cust.dat").is_some());
+
+        FileBuilder::new(34).key(KeyBuilder::unsigned(0, 4)).create(&mut client, "acct/DATA/CUST.DAT").unwrap();
+        let mut file = BtrieveFile::open(client, "acct/DATA/CUST.DAT", 0).unwrap();
+        let names: Vec<_> = file.fields().iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Id", "Name"]);
+        let data = [&42u32.to_le_bytes()[..], &[b' '; 30]].concat();
+        file.insert(&data).unwrap();
+
+        // Records read from the file carry its table
+        let mut record = file.get_first().unwrap();
+        assert_eq!(record.fields().len(), 2);
+        record.data = data;
+        assert_eq!(record.field("id").unwrap(), Value::UInt(42));
+        assert_eq!(record.field_bytes("Name").unwrap(), [b' '; 30]);
+        record.set_field("Name", "Bob").unwrap();
+        assert_eq!(record.field("Name").unwrap(), Value::Text("Bob".into()));
+        assert!(record.field("Balance").is_err());
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let columns = [
+            field_record(1, 1, "Id", 1, 0, 2, 0),
+            field_record(2, 1, "Name", 0, 2, 6, 0),
+            field_record(3, 1, "Total", 6, 8, 4, 0),
+            field_record(4, 1, "Due", 3, 12, 4, 0),
+            field_record(5, 1, "Qty", 8, 16, 4, 1),
+        ]
+        .map(|r| Column::from_record(&r).unwrap().1);
+        let mut record = vec![0u8; 20];
+        for (column, text) in columns.iter().zip(["-300", "Ada", "-12.5", "1999-12-31", "-4.5"]) {
+            column.encode(text, &mut record).unwrap();
+        }
+        let shown: Vec<_> = columns.iter().map(|c| c.decode(&record).unwrap().to_string()).collect();
+        assert_eq!(shown, ["-300", "Ada", "-12.50", "1999-12-31", "-4.5"]);
+        assert_eq!(&record[16..20], b"004N");
+
+        assert!(columns[0].encode("70000", &mut record).is_err());
+        assert!(columns[2].encode("1.005", &mut record).is_err());
+        assert!(columns[3].encode("yesterday", &mut record).is_err());
+    }
+
+    #[test]
+    fn test_legacy_numbers() {
+        // 1.5 and -10.0 in 4-byte MBF
+        assert_eq!(read_mbf(&[0x00, 0x00, 0x40, 0x81]).unwrap(), 1.5);
+        assert_eq!(read_mbf(&[0x00, 0x00, 0xA0, 0x84]).unwrap(), -10.0);
+        assert_eq!(read_numeric(b"00012J").unwrap(), -121);
+        assert_eq!(read_numeric(b"  0042").unwrap(), 42);
+        // Short records leave trailing columns empty
+        let column = Column::from_record(&field_record(1, 1, "X", 1, 8, 2, 0)).unwrap().1;
+        assert_eq!(column.decode(&[0u8; 4]).unwrap(), Value::Null);
+    }
+}
@@ -0,0 +1,255 @@
+//! Record filters
+//!
+//! A filter is a list of conditions on the columns of a table, in the
+//! WHERE syntax of `query`, joined by AND and OR:
+//!
+//! ```text
+//! column op literal [AND|OR column op literal ...]
+//! ```
+//!
+//! AND binds tighter than OR, so `Status = 'X' AND Amount > 100 OR Rush = 1`
+//! keeps rush orders as well as large ones with status X. There are no
+//! parentheses: a filter is always a flat run of terms, which is what the
+//! extended Get/Step operations take.
+//!
+//! Each term is compiled against its column to an offset, length, type
+//! and the literal stored the way the column stores it, so records are
+//! tested on their bytes without decoding them.
+//!
+//! ```ignore
+//! use xtrieve_client::filter::Filter;
+//!
+//! let filter = Filter::parse("Status = 'X' AND Amount > 100", &table.columns)?;
+//! let wanted: Vec<_> = records.filter(|r| filter.matches(r)).collect();
+//! ```
+
+use std::cmp::Ordering;
+
+use xtrieve_engine::BtrieveResult;
+
+use crate::ddf::{read_numeric, Column, DataType};
+use crate::query::{sql_error, CompareOp, Literal, Parser};
+use crate::typed;
+
+/// How a term joins the one after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    And,
+    Or,
+    /// Last term of the filter
+    End,
+}
+
+/// One compiled comparison
+#[derive(Debug, Clone)]
+pub struct Term {
+    /// The column compared; its offset, size, type and decimals locate
+    /// and interpret the field
+    pub column: Column,
+    pub op: CompareOp,
+    /// The literal as the column would store it (`column.size` bytes)
+    pub value: Vec<u8>,
+    pub connector: Connector,
+}
+
+impl Term {
+    fn compile(column: &Column, op: CompareOp, literal: &Literal, connector: Connector) -> BtrieveResult<Self> {
+        if !comparable(column.data_type) {
+            return Err(sql_error(format_args!("cannot filter on {} ({:?})", column.name, column.data_type)));
+        }
+        let text = match literal {
+            Literal::Int(v) => v.to_string(),
+            Literal::Float(v) => v.to_string(),
+            Literal::Text(t) => t.clone(),
+        };
+        let mut value = vec![0u8; column.size as usize];
+        Column { offset: 0, ..column.clone() }.encode(&text, &mut value)?;
+        Ok(Term { column: column.clone(), op, value, connector })
+    }
+
+    /// Test the field of `record` this term looks at; records too short
+    /// to hold it never match
+    pub fn matches(&self, record: &[u8]) -> bool {
+        let offset = self.column.offset as usize;
+        record
+            .get(offset..offset + self.column.size as usize)
+            .and_then(|field| compare(self.column.data_type, field, &self.value))
+            .is_some_and(|ordering| self.op.test(ordering))
+    }
+}
+
+/// A compiled filter
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub terms: Vec<Term>,
+}
+
+impl Filter {
+    /// Parse `expression` and compile it against `columns`
+    pub fn parse(expression: &str, columns: &[Column]) -> BtrieveResult<Self> {
+        let mut parser = Parser::new(expression)?;
+        let mut filter = Filter::default();
+        loop {
+            let condition = parser.condition()?;
+            let column = columns
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(&condition.column))
+                .ok_or_else(|| sql_error(format_args!("no column {}", condition.column)))?;
+            let connector = if parser.is_keyword("AND") {
+                Connector::And
+            } else if parser.is_keyword("OR") {
+                Connector::Or
+            } else {
+                Connector::End
+            };
+            filter.terms.push(Term::compile(column, condition.op, &condition.value, connector)?);
+            if connector == Connector::End {
+                break;
+            }
+            parser.next();
+        }
+        if let Some(token) = parser.peek() {
+            return Err(sql_error(format_args!("unexpected {:?}", token)));
+        }
+        Ok(filter)
+    }
+
+    /// Whether `record` passes: any run of AND-joined terms all of which
+    /// hold. An empty filter passes everything.
+    pub fn matches(&self, record: &[u8]) -> bool {
+        if self.terms.is_empty() {
+            return true;
+        }
+        let mut group = true;
+        for term in &self.terms {
+            group = group && term.matches(record);
+            if term.connector != Connector::And {
+                if group {
+                    return true;
+                }
+                group = true;
+            }
+        }
+        false
+    }
+}
+
+fn comparable(data_type: DataType) -> bool {
+    !matches!(
+        data_type,
+        DataType::BFloat | DataType::Note | DataType::LVar | DataType::Bit | DataType::Other(_)
+    )
+}
+
+fn signed(bytes: &[u8]) -> i64 {
+    let mut buf = if bytes.last().is_some_and(|b| b & 0x80 != 0) { [0xff; 8] } else { [0; 8] };
+    buf[..bytes.len()].copy_from_slice(bytes);
+    i64::from_le_bytes(buf)
+}
+
+fn unsigned(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+/// Order two stored values of a type
+fn compare(data_type: DataType, field: &[u8], value: &[u8]) -> Option<Ordering> {
+    let text = |bytes: &[u8]| -> usize {
+        bytes.iter().rposition(|&b| b != b' ' && b != 0).map_or(0, |i| i + 1)
+    };
+    match data_type {
+        DataType::Integer | DataType::AutoIncrement if field.len() <= 8 => Some(signed(field).cmp(&signed(value))),
+        DataType::UnsignedBinary | DataType::Logical if field.len() <= 8 => {
+            Some(unsigned(field).cmp(&unsigned(value)))
+        }
+        DataType::Float => match field.len() {
+            4 => f32::from_le_bytes(field.try_into().ok()?).partial_cmp(&f32::from_le_bytes(value.try_into().ok()?)),
+            8 => f64::from_le_bytes(field.try_into().ok()?).partial_cmp(&f64::from_le_bytes(value.try_into().ok()?)),
+            _ => None,
+        },
+        // Trailing blanks and NULs are padding
+        DataType::String => Some(field[..text(field)].cmp(&value[..text(value)])),
+        DataType::ZString => {
+            let end = |bytes: &[u8]| bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            Some(field[..end(field)].cmp(&value[..end(value)]))
+        }
+        DataType::LString => {
+            let len = |bytes: &[u8]| 1 + (bytes[0] as usize).min(bytes.len() - 1);
+            Some(field[1..len(field)].cmp(&value[1..len(value)]))
+        }
+        // Stored day, month, year (LE); hundredths, seconds, minutes, hours
+        DataType::Date if field.len() == 4 => {
+            let date = |b: &[u8]| (u16::from_le_bytes([b[2], b[3]]), b[1], b[0]);
+            Some(date(field).cmp(&date(value)))
+        }
+        DataType::Time if field.len() == 4 => Some(field.iter().rev().cmp(value.iter().rev())),
+        // Both sides have the column's scale
+        DataType::Decimal | DataType::Money => Some(typed::read_bcd(field).ok()?.cmp(&typed::read_bcd(value).ok()?)),
+        DataType::Numeric => Some(read_numeric(field).ok()?.cmp(&read_numeric(value).ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: DataType, offset: u16, size: u16, decimals: u8) -> Column {
+        Column { id: 0, name: name.to_string(), data_type, offset, size, decimals, flags: 0 }
+    }
+
+    fn orders() -> Vec<Column> {
+        vec![
+            column("Status", DataType::String, 0, 4, 0),
+            column("Amount", DataType::Decimal, 4, 5, 2),
+            column("Rush", DataType::Logical, 9, 1, 0),
+            column("Due", DataType::Date, 10, 4, 0),
+            column("Qty", DataType::Integer, 14, 2, 0),
+        ]
+    }
+
+    fn order(values: [&str; 5]) -> Vec<u8> {
+        let columns = orders();
+        let mut record = vec![0u8; 16];
+        for (column, value) in columns.iter().zip(values) {
+            column.encode(value, &mut record).unwrap();
+        }
+        record
+    }
+
+    #[test]
+    fn test_parse_and_compile() {
+        let filter = Filter::parse("status = 'X' AND Amount > 100 OR Rush = 1", &orders()).unwrap();
+        let connectors: Vec<_> = filter.terms.iter().map(|t| t.connector).collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or, Connector::End]);
+        let offsets: Vec<_> = filter.terms.iter().map(|t| t.column.offset).collect();
+        assert_eq!(offsets, [0, 4, 9]);
+        assert_eq!(filter.terms[0].value, b"X   ");
+        assert_eq!(filter.terms[1].value, [0x00, 0x00, 0x10, 0x00, 0x0f]);
+        assert_eq!(filter.terms[2].op, CompareOp::Eq);
+
+        assert!(Filter::parse("Price > 1", &orders()).is_err());
+        assert!(Filter::parse("Qty > 70000", &orders()).is_err());
+        assert!(Filter::parse("Qty > 1 AND", &orders()).is_err());
+        assert!(Filter::parse("Qty > 1 Rush = 1", &orders()).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let columns = orders();
+        let matches = |expression: &str, record: &[u8]| Filter::parse(expression, &columns).unwrap().matches(record);
+        let big = order(["X", "250.00", "n", "2024-03-01", "-3"]);
+        let rush = order(["Y", "5.50", "y", "2023-12-31", "12"]);
+
+        assert!(matches("Status = 'X' AND Amount > 100 OR Rush = 1", &big));
+        assert!(matches("Status = 'X' AND Amount > 100 OR Rush = 1", &rush));
+        assert!(!matches("Status = 'X' AND Amount > 300 OR Rush = 1", &big));
+        assert!(matches("Due >= '2024-01-01'", &big));
+        assert!(!matches("Due >= '2024-01-01'", &rush));
+        assert!(matches("Qty < 0 AND Amount <= 250", &big));
+        assert!(matches("Status <> 'X'", &rush));
+        assert!(Filter::default().matches(&big));
+        assert!(!matches("Qty > 0", &big[..10]));
+    }
+}
@@ -2,11 +2,42 @@
 //!
 //! Provides a Btrieve-compatible API for accessing Xtrieve database files.
 
+// Lets `#[derive(TypedRecord)]` output resolve inside this crate too
+extern crate self as xtrieve_client;
+
 pub mod client;
 pub mod btrieve;
+pub mod builder;
+pub mod ddf;
+pub mod filter;
+pub mod local;
+pub mod mock;
+pub mod query;
+pub mod transport;
+pub mod typed;
+#[cfg(feature = "grpc")]
+pub mod watch;
+
+/// Generated gRPC client for the xtrieved tonic service
+#[cfg(feature = "grpc")]
+pub mod proto {
+    tonic::include_proto!("xtrieve");
+}
 
-pub use client::{XtrieveClient, BtrieveRequest, BtrieveResponse};
+pub use client::{XtrieveClient, ReconnectPolicy, Timeouts, BtrieveRequest, BtrieveResponse};
 #[cfg(feature = "async")]
 pub use client::AsyncXtrieveClient;
-pub use btrieve::{BtrieveFile, BtrieveRecord};
-pub use xtrieve_engine::{BtrieveError, BtrieveResult, StatusCode};
+pub use btrieve::{BtrieveFile, BtrieveRecord, DeletedRecord, InsertReport, KeyRange, LockKind, Records, SavedPosition, Transaction};
+#[cfg(feature = "async")]
+pub use btrieve::AsyncBtrieveFile;
+pub use builder::{FileBuilder, KeyBuilder};
+pub use local::LocalBtrieveFile;
+pub use mock::{MockScript, MockXtrieveClient};
+pub use transport::Transport;
+pub use typed::TypedRecord;
+#[cfg(feature = "grpc")]
+pub use watch::{ChangeEvent, ChangeKind, ResumeToken, Watch, WatchRange};
+#[cfg(feature = "derive")]
+pub use xtrieve_derive::TypedRecord;
+pub use xtrieve_engine::{BtrieveError, BtrieveResult, ErrorDetail, LockReport, ServerInfo, StatusCode};
+pub use xtrieve_engine::storage::KeyType;
@@ -0,0 +1,227 @@
+//! Embedded (in-process) engine
+//!
+//! A `file://<data dir>` address makes `XtrieveClient` call
+//! `xtrieve_engine::operations::Engine` directly instead of talking to a
+//! daemon, so everything built on the client (`BtrieveFile`, iterators,
+//! transactions, typed records) works unchanged without a network hop.
+//!
+//! ```ignore
+//! use xtrieve_client::LocalBtrieveFile;
+//!
+//! let mut file = LocalBtrieveFile::open("./data", "CUSTOMER.DAT", 0)?;
+//! let record = file.get_first()?;
+//! ```
+
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use xtrieve_engine::file_manager::cursor::PositionBlock;
+use xtrieve_engine::operations::{Engine, OperationCode, OperationRequest};
+use xtrieve_engine::{BtrieveError, BtrieveResult};
+
+use crate::btrieve::{op, BtrieveFile};
+use crate::client::{BtrieveRequest, BtrieveResponse, XtrieveClient};
+
+/// Address prefix that selects the embedded engine
+pub(crate) const EMBEDDED_SCHEME: &str = "file://";
+
+/// Page cache size of the process-wide engine (the daemon's default)
+const CACHE_PAGES: usize = 10_000;
+
+/// Session IDs for embedded clients, above the ids clients pick themselves
+static SESSION_COUNTER: AtomicU64 = AtomicU64::new(1 << 32);
+
+/// Engine shared by every embedded client in the process, so files opened
+/// from several clients share one file table, page cache and lock manager
+fn shared_engine() -> Arc<Engine> {
+    static ENGINE: OnceLock<Arc<Engine>> = OnceLock::new();
+    ENGINE.get_or_init(|| Arc::new(Engine::new(CACHE_PAGES))).clone()
+}
+
+/// The engine embedded clients in this process use, for registering
+/// hooks (`Engine::add_hook`) on it
+pub fn embedded_engine() -> Arc<Engine> {
+    shared_engine()
+}
+
+/// One client's session on the in-process engine
+pub(crate) struct Embedded {
+    engine: Arc<Engine>,
+    data_dir: PathBuf,
+    session_id: u64,
+    /// Position blocks of files still open, closed on drop to flush their pages
+    open_files: Vec<Vec<u8>>,
+}
+
+impl Embedded {
+    /// Start a session resolving relative paths against `data_dir`
+    pub(crate) fn open(data_dir: &str) -> BtrieveResult<Self> {
+        Self::open_on(shared_engine(), data_dir)
+    }
+
+    /// Start a session on an engine of the caller's own
+    pub(crate) fn open_on(engine: Arc<Engine>, data_dir: &str) -> BtrieveResult<Self> {
+        let data_dir = match data_dir {
+            "" => PathBuf::from("."),
+            dir => PathBuf::from(dir),
+        };
+        if !data_dir.is_dir() {
+            return Err(BtrieveError::Internal(format!(
+                "Data directory not found: {}",
+                data_dir.display()
+            )));
+        }
+
+        Ok(Embedded {
+            engine,
+            data_dir,
+            session_id: SESSION_COUNTER.fetch_add(1, Ordering::SeqCst),
+            open_files: Vec::new(),
+        })
+    }
+
+    /// Execute a request the way xtrieved would for a connection
+    pub(crate) fn execute(&mut self, request: &BtrieveRequest) -> BtrieveResponse {
+        let file_path = match request.file_path.as_str() {
+            "" => None,
+            path => Some(self.resolve(path).to_string_lossy().to_string()),
+        };
+        let operation = OperationCode::from_raw(request.operation_code);
+        // Clone File and Rename File name a new path in the data buffer
+        let new_path = matches!(operation, OperationCode::CloneFile | OperationCode::RenameFile);
+        let data_buffer = match &request.data_buffer {
+            to if new_path && !to.is_empty() => {
+                let end = to.iter().position(|&b| b == 0).unwrap_or(to.len());
+                let to = String::from_utf8_lossy(&to[..end]).to_string();
+                self.resolve(&to).to_string_lossy().as_bytes().to_vec()
+            }
+            data => data.clone(),
+        };
+        // An explicit client id wins; position blocks carry the session
+        // that opened the file
+        let session_id = match PositionBlock::from_bytes(&request.position_block).get_session_id() {
+            _ if request.client_id != 0 => request.client_id,
+            0 => self.session_id,
+            stored => stored,
+        };
+
+        let result = self.engine.execute(session_id, OperationRequest {
+            operation,
+            file_path,
+            position_block: request.position_block.clone(),
+            data_buffer,
+            key_buffer: request.key_buffer.clone(),
+            key_number: request.key_number,
+            data_length: 0,
+            key_length: 0,
+            open_mode: request.open_mode,
+            lock_bias: request.lock_bias as i32,
+        });
+
+        let mut position_block = PositionBlock::from_bytes(&result.position_block);
+        position_block.set_session_id(session_id);
+        let response = BtrieveResponse {
+            status_code: result.status.as_raw() as u32,
+            position_block: position_block.data.to_vec(),
+            data_buffer: result.data_buffer,
+            key_buffer: result.key_buffer,
+            detail: result.detail,
+        };
+        self.track(request, &response);
+        response
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        let path = Path::new(path);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.data_dir.join(path)
+        }
+    }
+
+    /// Remember open files so dropping the client closes them
+    fn track(&mut self, request: &BtrieveRequest, response: &BtrieveResponse) {
+        if response.status_code != 0 {
+            return;
+        }
+        match request.operation_code {
+            op::OPEN => self.open_files.push(response.position_block.clone()),
+            op::CLOSE => {
+                let closed = PositionBlock::from_bytes(&request.position_block);
+                let key = (closed.file_path(), closed.get_cursor_id());
+                self.open_files.retain(|block| {
+                    let block = PositionBlock::from_bytes(block);
+                    (block.file_path(), block.get_cursor_id()) != key
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Embedded {
+    fn drop(&mut self) {
+        // No daemon outlives this process to flush dirty pages later
+        for position_block in std::mem::take(&mut self.open_files) {
+            let session_id = PositionBlock::from_bytes(&position_block).get_session_id();
+            self.engine.execute(session_id, OperationRequest {
+                operation: OperationCode::Close,
+                position_block,
+                ..Default::default()
+            });
+        }
+    }
+}
+
+/// A `BtrieveFile` on the in-process engine, for single-process applications
+pub struct LocalBtrieveFile {
+    file: BtrieveFile,
+}
+
+impl LocalBtrieveFile {
+    /// Open `path` (relative to `data_dir` unless absolute) without a daemon
+    pub fn open(data_dir: impl AsRef<Path>, path: &str, mode: i32) -> BtrieveResult<Self> {
+        Ok(LocalBtrieveFile {
+            file: BtrieveFile::open(Self::connect(data_dir.as_ref())?, path, mode)?,
+        })
+    }
+
+    /// Open a file protected with an owner name
+    pub fn open_with_owner(
+        data_dir: impl AsRef<Path>,
+        path: &str,
+        mode: i32,
+        owner: &str,
+    ) -> BtrieveResult<Self> {
+        let client = Self::connect(data_dir.as_ref())?;
+        Ok(LocalBtrieveFile {
+            file: BtrieveFile::open_with_owner(client, path, mode, owner)?,
+        })
+    }
+
+    fn connect(data_dir: &Path) -> BtrieveResult<XtrieveClient> {
+        XtrieveClient::connect(&format!("{}{}", EMBEDDED_SCHEME, data_dir.display()))
+    }
+
+    /// The underlying `BtrieveFile`
+    pub fn into_inner(self) -> BtrieveFile {
+        self.file
+    }
+}
+
+impl Deref for LocalBtrieveFile {
+    type Target = BtrieveFile;
+
+    fn deref(&self) -> &BtrieveFile {
+        &self.file
+    }
+}
+
+impl DerefMut for LocalBtrieveFile {
+    fn deref_mut(&mut self) -> &mut BtrieveFile {
+        &mut self.file
+    }
+}
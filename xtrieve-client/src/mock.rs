@@ -0,0 +1,221 @@
+//! Mock client for application tests
+//!
+//! `MockXtrieveClient` is a `Transport` running a private engine over a
+//! scratch directory that is removed when it is dropped, so code written
+//! against `XtrieveClient` and `BtrieveFile` can be tested without a
+//! daemon. A `MockScript` handle, kept after the mock is moved into a
+//! client, injects failures and latency and records the operations sent.
+//!
+//! ```ignore
+//! use xtrieve_client::{btrieve::op, MockXtrieveClient, StatusCode};
+//!
+//! let mock = MockXtrieveClient::new()?;
+//! let script = mock.script();
+//! let mut client = mock.into_client();
+//! script.fail(op::INSERT, StatusCode::DiskFull);
+//! ```
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use xtrieve_engine::operations::Engine;
+use xtrieve_engine::{BtrieveError, BtrieveResult, StatusCode};
+
+use crate::client::{BtrieveRequest, BtrieveResponse, XtrieveClient};
+use crate::local::Embedded;
+use crate::transport::Transport;
+
+/// Page cache of a mock's engine; test files are small
+const MOCK_CACHE_PAGES: usize = 256;
+
+/// Tells apart the scratch directories of mocks in one process
+static MOCK_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A failure waiting for the request it applies to
+#[derive(Debug, Clone)]
+enum Failure {
+    /// Answer with this status instead of running the request
+    Status { operation_code: Option<u32>, status: StatusCode },
+    /// Fail the round trip as a dropped connection does
+    Disconnect,
+}
+
+impl Failure {
+    fn applies_to(&self, request: &BtrieveRequest) -> bool {
+        match self {
+            Failure::Status { operation_code: Some(code), .. } => *code == request.operation_code,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    failures: VecDeque<Failure>,
+    latency: Duration,
+    operations: Vec<u32>,
+}
+
+/// Controls a `MockXtrieveClient` from the test, after the mock has been
+/// handed to a client
+#[derive(Debug, Clone, Default)]
+pub struct MockScript(Arc<Mutex<Script>>);
+
+impl MockScript {
+    fn lock(&self) -> MutexGuard<'_, Script> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Answer the next request with `operation_code` with `status`, without
+    /// running it. Queued failures are used up in order
+    pub fn fail(&self, operation_code: u32, status: StatusCode) {
+        self.lock().failures.push_back(Failure::Status { operation_code: Some(operation_code), status });
+    }
+
+    /// Answer the next request, whatever it is, with `status`
+    pub fn fail_next(&self, status: StatusCode) {
+        self.lock().failures.push_back(Failure::Status { operation_code: None, status });
+    }
+
+    /// Fail the next round trip as a broken connection would
+    pub fn disconnect_next(&self) {
+        self.lock().failures.push_back(Failure::Disconnect);
+    }
+
+    /// Delay every response by `latency`. A request whose deadline passes
+    /// first fails as a timed out one does
+    pub fn set_latency(&self, latency: Duration) {
+        self.lock().latency = latency;
+    }
+
+    /// Operation codes of the requests sent so far, in order
+    pub fn operations(&self) -> Vec<u32> {
+        self.lock().operations.clone()
+    }
+}
+
+/// Removes the mock's data directory once its engine session is gone
+struct ScratchDir(PathBuf);
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A `Transport` over a private in-process engine with scriptable failures
+pub struct MockXtrieveClient {
+    embedded: Embedded,
+    script: MockScript,
+    /// Declared after `embedded` so files are closed before it goes
+    _dir: ScratchDir,
+}
+
+impl MockXtrieveClient {
+    /// A mock with an empty data directory of its own
+    pub fn new() -> BtrieveResult<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "xtrieve-mock-{}-{}",
+            std::process::id(),
+            MOCK_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let dir = ScratchDir(dir);
+        let engine = Arc::new(Engine::new(MOCK_CACHE_PAGES));
+        Ok(MockXtrieveClient {
+            embedded: Embedded::open_on(engine, &dir.0.to_string_lossy())?,
+            script: MockScript::default(),
+            _dir: dir,
+        })
+    }
+
+    /// Handle for scripting failures and reading back the operations sent
+    pub fn script(&self) -> MockScript {
+        self.script.clone()
+    }
+
+    /// A client whose requests all go to this mock
+    pub fn into_client(self) -> XtrieveClient {
+        XtrieveClient::with_transport(Box::new(self))
+    }
+}
+
+impl Transport for MockXtrieveClient {
+    fn round_trip(
+        &mut self,
+        request: &BtrieveRequest,
+        deadline: Option<Instant>,
+    ) -> BtrieveResult<BtrieveResponse> {
+        let (failure, latency) = {
+            let mut script = self.script.lock();
+            script.operations.push(request.operation_code);
+            let at = script.failures.iter().position(|f| f.applies_to(request));
+            (at.and_then(|at| script.failures.remove(at)), script.latency)
+        };
+
+        if !latency.is_zero() {
+            let wait = match deadline {
+                Some(deadline) => latency.min(deadline.saturating_duration_since(Instant::now())),
+                None => latency,
+            };
+            thread::sleep(wait);
+            if wait < latency {
+                return Err(BtrieveError::Internal("Request timed out".to_string()));
+            }
+        }
+
+        match failure {
+            Some(Failure::Status { status, .. }) => Ok(BtrieveResponse {
+                status_code: status.as_raw() as u32,
+                position_block: request.position_block.clone(),
+                ..Default::default()
+            }),
+            Some(Failure::Disconnect) => Err(BtrieveError::Internal("Connection reset by mock".to_string())),
+            None => Ok(self.embedded.execute(request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btrieve::op;
+    use crate::{BtrieveFile, FileBuilder, KeyBuilder};
+
+    #[test]
+    fn test_scripted_failures() {
+        let mock = MockXtrieveClient::new().unwrap();
+        let script = mock.script();
+        let mut client = mock.into_client();
+        FileBuilder::new(8).key(KeyBuilder::unsigned(0, 4)).create(&mut client, "PARTS.DAT").unwrap();
+        let mut file = BtrieveFile::open(client, "PARTS.DAT", 0).unwrap();
+
+        script.fail(op::INSERT, StatusCode::DiskFull);
+        assert!(matches!(file.insert(&[1u8; 8]), Err(BtrieveError::Status(StatusCode::DiskFull))));
+        file.insert(&[1u8; 8]).unwrap();
+
+        script.disconnect_next();
+        assert!(matches!(file.get_first(), Err(BtrieveError::Internal(_))));
+        assert_eq!(file.get_first().unwrap().key, [1u8; 4]);
+
+        let ops = script.operations();
+        assert_eq!(ops.iter().filter(|&&code| code == op::INSERT).count(), 2);
+    }
+
+    #[test]
+    fn test_latency() {
+        let mock = MockXtrieveClient::new().unwrap();
+        mock.script().set_latency(Duration::from_millis(50));
+        let mut client = mock.into_client();
+        let ping = || BtrieveRequest { operation_code: op::PING, ..Default::default() };
+
+        let started = Instant::now();
+        client.execute(ping()).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(client.execute_with_deadline(ping(), Duration::from_millis(5)).is_err());
+    }
+}
@@ -0,0 +1,917 @@
+//! SQL SELECT over DDF tables
+//!
+//! A small subset of SQL for ad-hoc reporting on legacy data described by
+//! a data dictionary (see `ddf`):
+//!
+//! ```text
+//! SELECT * | column [, column ...] FROM table
+//!     [WHERE column op literal [AND column op literal ...]]
+//!     [LIMIT n]
+//! ```
+//!
+//! `op` is one of `= <> != < <= > >=`; literals are numbers or quoted
+//! text (`'O''Brien'`), with dates written as `'YYYY-MM-DD'`.
+//!
+//! When a condition bounds the first segment of an ascending index, the
+//! scan starts at the lower bound with GetGreaterOrEqual, follows that key
+//! with GetNext and stops once an upper bound is passed. Otherwise the
+//! file is stepped through physically. Either way every condition is
+//! checked again on the decoded row.
+//!
+//! ```ignore
+//! use xtrieve_client::query;
+//!
+//! let result = query::query(&mut client, "accounts",
+//!     "SELECT Id, Name FROM Customer WHERE Id >= 100 AND Id < 200")?;
+//! print!("{}", result);
+//! ```
+//!
+//! `query` runs the statement on the daemon (Query, op 97) when the server
+//! supports it and falls back to issuing the Btrieve calls itself.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use xtrieve_engine::{BtrieveError, BtrieveResult, StatusCode};
+
+use crate::btrieve::{check_status, op};
+use crate::client::{BtrieveRequest, BtrieveResponse, XtrieveClient};
+use crate::ddf::{Column, DataType, Dictionary, Table, Value};
+
+/// Key flag marking a descending segment
+const DESCENDING: u16 = 0x0040;
+
+pub(crate) fn sql_error(message: impl fmt::Display) -> BtrieveError {
+    BtrieveError::Internal(format!("SQL: {}", message))
+}
+
+/// Comparison operator of a WHERE condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub(crate) fn test(self, ordering: Ordering) -> bool {
+        match self {
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+        }
+    }
+
+    /// Condition gives a starting point in ascending key order
+    fn is_lower_bound(self) -> bool {
+        matches!(self, CompareOp::Eq | CompareOp::Gt | CompareOp::Ge)
+    }
+
+    /// Condition fails for good once the key passes it
+    fn is_upper_bound(self) -> bool {
+        matches!(self, CompareOp::Eq | CompareOp::Lt | CompareOp::Le)
+    }
+}
+
+/// A constant in a WHERE condition
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Literal {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Literal::Int(v) => Some(*v as f64),
+            Literal::Float(v) => Some(*v),
+            Literal::Text(_) => None,
+        }
+    }
+}
+
+/// `column op literal`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub column: String,
+    pub op: CompareOp,
+    pub value: Literal,
+}
+
+impl Condition {
+    /// Check a decoded value; NULLs never match
+    pub fn matches(&self, value: &Value) -> bool {
+        compare(value, &self.value).is_some_and(|ordering| self.op.test(ordering))
+    }
+}
+
+/// Order a column value against a literal, `None` if they don't compare
+fn compare(value: &Value, literal: &Literal) -> Option<Ordering> {
+    match (value, literal) {
+        (Value::Null, _) => None,
+        (Value::Int(v), Literal::Int(l)) => Some(v.cmp(l)),
+        (Value::UInt(v), Literal::Int(l)) => Some(if *l < 0 {
+            Ordering::Greater
+        } else {
+            v.cmp(&(*l as u64))
+        }),
+        (Value::Text(v), Literal::Text(l)) => Some(v.trim_end().cmp(l.as_str())),
+        (Value::Text(v), l) => v.trim().parse::<f64>().ok()?.partial_cmp(&l.as_f64()?),
+        (Value::Date(_) | Value::Time(_), Literal::Text(l)) => {
+            Some(value.to_string().as_str().cmp(l.as_str()))
+        }
+        (Value::Bytes(_), Literal::Text(l)) => Some(value.to_string().cmp(&l.to_lowercase())),
+        (_, l) => {
+            let number = match value {
+                Value::Int(v) => *v as f64,
+                Value::UInt(v) => *v as f64,
+                Value::Float(v) => *v,
+                Value::Decimal { unscaled, scale } => *unscaled as f64 / 10f64.powi(*scale as i32),
+                Value::Bool(v) => *v as u8 as f64,
+                _ => return None,
+            };
+            number.partial_cmp(&l.as_f64()?)
+        }
+    }
+}
+
+/// Encode a literal as the key bytes of a column, if its type has a
+/// byte order the engine sorts on the way the literal compares
+fn key_bytes(column: &Column, literal: &Literal) -> Option<Vec<u8>> {
+    let size = column.size as usize;
+    match (column.data_type, literal) {
+        (DataType::Integer | DataType::AutoIncrement, Literal::Int(v)) => {
+            let fits = match size {
+                1 => i8::try_from(*v).is_ok(),
+                2 => i16::try_from(*v).is_ok(),
+                4 => i32::try_from(*v).is_ok(),
+                8 => true,
+                _ => false,
+            };
+            fits.then(|| v.to_le_bytes()[..size].to_vec())
+        }
+        (DataType::UnsignedBinary, Literal::Int(v)) if *v >= 0 => {
+            let v = *v as u64;
+            (size <= 8 && (size == 8 || v >> (size * 8) == 0)).then(|| v.to_le_bytes()[..size].to_vec())
+        }
+        (DataType::Float, l) => match size {
+            4 => Some((l.as_f64()? as f32).to_le_bytes().to_vec()),
+            8 => Some(l.as_f64()?.to_le_bytes().to_vec()),
+            _ => None,
+        },
+        (DataType::String | DataType::ZString, Literal::Text(text)) => {
+            let pad = if column.data_type == DataType::String { b' ' } else { 0 };
+            let mut key = text.as_bytes().to_vec();
+            key.resize(size, pad);
+            Some(key)
+        }
+        (DataType::Date, Literal::Text(text)) if size == 4 => {
+            let mut parts = text.splitn(3, '-');
+            let year: u16 = parts.next()?.parse().ok()?;
+            let month: u8 = parts.next()?.parse().ok()?;
+            let day: u8 = parts.next()?.parse().ok()?;
+            let mut key = vec![day, month];
+            key.extend_from_slice(&year.to_le_bytes());
+            Some(key)
+        }
+        _ => None,
+    }
+}
+
+/// How a SELECT reads its table
+#[derive(Debug, Clone, PartialEq)]
+pub enum Plan {
+    /// Step through every record in physical order
+    Scan,
+    /// Walk an index in key order
+    Index {
+        key_number: u16,
+        /// GetGreaterOrEqual key, or `None` to start at GetFirst
+        start: Option<Vec<u8>>,
+        /// Conditions on the leading column whose failure ends the walk
+        stop: Vec<Condition>,
+    },
+}
+
+/// A parsed `SELECT` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    /// Selected columns, empty for `*`
+    pub columns: Vec<String>,
+    pub table: String,
+    /// Conditions joined by AND
+    pub conditions: Vec<Condition>,
+    pub limit: Option<usize>,
+}
+
+impl Select {
+    pub fn parse(sql: &str) -> BtrieveResult<Self> {
+        Parser::new(sql)?.select()
+    }
+
+    /// Pick the scan for `table`
+    pub fn plan(&self, table: &Table) -> Plan {
+        let mut fallback = None;
+        for index in &table.indexes {
+            let first = match index.segments.first() {
+                Some(segment) if segment.flags & DESCENDING == 0 => segment,
+                _ => continue,
+            };
+            let Some(column) = table.column_by_id(first.column) else {
+                continue;
+            };
+            let on_column: Vec<_> = self
+                .conditions
+                .iter()
+                .filter(|c| c.column.eq_ignore_ascii_case(&column.name))
+                .collect();
+            let stop: Vec<Condition> = on_column
+                .iter()
+                .filter(|c| c.op.is_upper_bound())
+                .map(|&c| c.clone())
+                .collect();
+
+            let start = on_column
+                .iter()
+                .filter(|c| c.op.is_lower_bound())
+                .find_map(|c| key_bytes(column, &c.value));
+            if let Some(mut start) = start {
+                // Lowest value for the remaining segments
+                let rest: usize = index.segments[1..]
+                    .iter()
+                    .filter_map(|s| table.column_by_id(s.column))
+                    .map(|c| c.size as usize)
+                    .sum();
+                start.resize(start.len() + rest, 0);
+                return Plan::Index {
+                    key_number: index.number,
+                    start: Some(start),
+                    stop,
+                };
+            }
+            if !stop.is_empty() && fallback.is_none() {
+                fallback = Some(Plan::Index {
+                    key_number: index.number,
+                    start: None,
+                    stop,
+                });
+            }
+        }
+        fallback.unwrap_or(Plan::Scan)
+    }
+
+    /// Run against a table of `dictionary`, loaded from `dictionary_dir`
+    pub fn run(
+        &self,
+        client: &mut XtrieveClient,
+        dictionary: &Dictionary,
+        dictionary_dir: &str,
+    ) -> BtrieveResult<ResultSet> {
+        let table = dictionary
+            .table(&self.table)
+            .ok_or_else(|| sql_error(format_args!("no table {}", self.table)))?;
+        let find = |name: &str| {
+            table
+                .columns
+                .iter()
+                .position(|c| c.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| sql_error(format_args!("no column {} in {}", name, table.name)))
+        };
+        let projection = match self.columns.is_empty() {
+            true => (0..table.columns.len()).collect(),
+            false => self.columns.iter().map(|c| find(c)).collect::<BtrieveResult<Vec<_>>>()?,
+        };
+        let filters = self
+            .conditions
+            .iter()
+            .map(|c| Ok((find(&c.column)?, c)))
+            .collect::<BtrieveResult<Vec<_>>>()?;
+
+        let mut result = ResultSet {
+            columns: projection.iter().map(|&i| table.columns[i].name.clone()).collect(),
+            rows: Vec::new(),
+        };
+        if self.limit == Some(0) {
+            return Ok(result);
+        }
+
+        let plan = self.plan(table);
+        let mut cursor = Cursor::open(client, &table.file_path(dictionary_dir))?;
+        let walk = (|| {
+            let stop: Vec<_> = match &plan {
+                Plan::Index { stop, .. } => stop
+                    .iter()
+                    .map(|c| Ok((find(&c.column)?, c)))
+                    .collect::<BtrieveResult<_>>()?,
+                Plan::Scan => Vec::new(),
+            };
+            let mut record = cursor.first(&plan)?;
+            while let Some(data) = record {
+                let column = |i: usize| table.columns[i].decode(&data);
+                for &(i, condition) in &stop {
+                    if !condition.matches(&column(i)?) {
+                        return Ok(());
+                    }
+                }
+                let mut keep = true;
+                for &(i, condition) in &filters {
+                    if !condition.matches(&column(i)?) {
+                        keep = false;
+                        break;
+                    }
+                }
+                if keep {
+                    result
+                        .rows
+                        .push(projection.iter().map(|&i| column(i)).collect::<BtrieveResult<_>>()?);
+                    if Some(result.rows.len()) == self.limit {
+                        return Ok(());
+                    }
+                }
+                record = cursor.next(&plan)?;
+            }
+            Ok(())
+        })();
+        let closed = cursor.close();
+        walk.and(closed)?;
+        Ok(result)
+    }
+}
+
+/// An open data file being walked by a query
+struct Cursor<'a> {
+    client: &'a mut XtrieveClient,
+    position_block: Vec<u8>,
+}
+
+impl<'a> Cursor<'a> {
+    fn open(client: &'a mut XtrieveClient, path: &str) -> BtrieveResult<Self> {
+        let response = check_status(client.execute(BtrieveRequest {
+            operation_code: op::OPEN,
+            file_path: path.to_string(),
+            ..Default::default()
+        })?)?;
+        Ok(Cursor {
+            client,
+            position_block: response.position_block,
+        })
+    }
+
+    fn call(&mut self, request: BtrieveRequest) -> BtrieveResult<Option<Vec<u8>>> {
+        let before = self.position_block.clone();
+        let response = self.client.execute(BtrieveRequest {
+            position_block: before.clone(),
+            ..request
+        });
+        match response.and_then(check_status) {
+            Ok(BtrieveResponse {
+                position_block,
+                data_buffer,
+                ..
+            }) => {
+                // A reply that leaves the cursor in place would repeat forever
+                if request.operation_code == op::GET_NEXT && position_block == before {
+                    return Ok(None);
+                }
+                self.position_block = position_block;
+                Ok(Some(data_buffer))
+            }
+            Err(BtrieveError::Status(StatusCode::EndOfFile | StatusCode::KeyNotFound)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn first(&mut self, plan: &Plan) -> BtrieveResult<Option<Vec<u8>>> {
+        let request = match plan {
+            Plan::Scan => BtrieveRequest {
+                operation_code: op::STEP_FIRST,
+                ..Default::default()
+            },
+            Plan::Index {
+                key_number,
+                start: Some(key),
+                ..
+            } => BtrieveRequest {
+                operation_code: op::GET_GE,
+                key_buffer: key.clone(),
+                key_buffer_length: key.len() as u32,
+                key_number: *key_number as i32,
+                ..Default::default()
+            },
+            Plan::Index { key_number, .. } => BtrieveRequest {
+                operation_code: op::GET_FIRST,
+                key_number: *key_number as i32,
+                ..Default::default()
+            },
+        };
+        self.call(request)
+    }
+
+    fn next(&mut self, plan: &Plan) -> BtrieveResult<Option<Vec<u8>>> {
+        let request = match plan {
+            Plan::Scan => BtrieveRequest {
+                operation_code: op::STEP_NEXT,
+                ..Default::default()
+            },
+            Plan::Index { key_number, .. } => BtrieveRequest {
+                operation_code: op::GET_NEXT,
+                key_number: *key_number as i32,
+                ..Default::default()
+            },
+        };
+        self.call(request)
+    }
+
+    fn close(self) -> BtrieveResult<()> {
+        check_status(self.client.execute(BtrieveRequest {
+            operation_code: op::CLOSE,
+            position_block: self.position_block,
+            ..Default::default()
+        })?)?;
+        Ok(())
+    }
+}
+
+/// Rows returned by a query
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultSet {
+    /// Tab separated text: a header line, then one line per row. Tabs,
+    /// newlines and backslashes are escaped with a backslash, NULL is `\N`.
+    pub fn to_tsv(&self) -> String {
+        let mut out = self.columns.iter().map(|c| escape(c)).collect::<Vec<_>>().join("\t");
+        out.push('\n');
+        for row in &self.rows {
+            let fields: Vec<_> = row
+                .iter()
+                .map(|v| match v {
+                    Value::Null => "\\N".to_string(),
+                    v => escape(&v.to_string()),
+                })
+                .collect();
+            out.push_str(&fields.join("\t"));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Parse `to_tsv` output; values come back as `Text` or `Null`
+    pub fn from_tsv(text: &str) -> BtrieveResult<Self> {
+        let mut lines = text.lines();
+        let columns: Vec<String> = match lines.next() {
+            Some(header) => header.split('\t').map(unescape).collect(),
+            None => return Ok(ResultSet::default()),
+        };
+        let rows = lines
+            .map(|line| {
+                let row: Vec<Value> = line
+                    .split('\t')
+                    .map(|field| match field {
+                        "\\N" => Value::Null,
+                        field => Value::Text(unescape(field)),
+                    })
+                    .collect();
+                if row.len() == columns.len() {
+                    Ok(row)
+                } else {
+                    Err(sql_error("malformed result row"))
+                }
+            })
+            .collect::<BtrieveResult<_>>()?;
+        Ok(ResultSet { columns, rows })
+    }
+}
+
+/// Aligned text table for terminals
+impl fmt::Display for ResultSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|v| v.to_string()).collect())
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([name.chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |f: &mut fmt::Formatter<'_>, row: &[String]| {
+            let padded: Vec<_> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+                .collect();
+            writeln!(f, "{}", padded.join("  ").trim_end())
+        };
+        line(f, &self.columns)?;
+        let rule: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+        line(f, &rule)?;
+        for row in &cells {
+            line(f, row)?;
+        }
+        writeln!(f, "({} rows)", self.rows.len())
+    }
+}
+
+fn escape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Run a query by issuing Btrieve calls from this client
+pub fn execute(client: &mut XtrieveClient, dictionary_dir: &str, sql: &str) -> BtrieveResult<ResultSet> {
+    let select = Select::parse(sql)?;
+    let dictionary = Dictionary::load(client, dictionary_dir)?;
+    select.run(client, &dictionary, dictionary_dir)
+}
+
+/// Run a query on the server with the Query operation (97)
+pub fn execute_remote(
+    client: &mut XtrieveClient,
+    dictionary_dir: &str,
+    sql: &str,
+) -> BtrieveResult<ResultSet> {
+    execute_cancellable(client, dictionary_dir, sql, 0)
+}
+
+/// Run a query on the server as `execute_remote` does, sent with
+/// `request_id` so that a Cancel from another connection can stop it
+/// (see `XtrieveClient::cancel`)
+pub fn execute_cancellable(
+    client: &mut XtrieveClient,
+    dictionary_dir: &str,
+    sql: &str,
+    request_id: u64,
+) -> BtrieveResult<ResultSet> {
+    let response = client.execute(BtrieveRequest {
+        operation_code: op::QUERY,
+        file_path: dictionary_dir.to_string(),
+        data_buffer: sql.as_bytes().to_vec(),
+        data_buffer_length: sql.len() as u32,
+        request_id,
+        ..Default::default()
+    })?;
+    let text = String::from_utf8_lossy(&response.data_buffer);
+    match StatusCode::from_raw(response.status_code as u16) {
+        StatusCode::Success => ResultSet::from_tsv(&text),
+        // The server explains failures that aren't Btrieve statuses
+        StatusCode::UnrecoverableError if !text.is_empty() => {
+            Err(BtrieveError::Internal(text.into_owned()))
+        }
+        _ => Err(response.to_error()),
+    }
+}
+
+/// Run a query on the server if it supports the Query operation, else
+/// from this client
+pub fn query(client: &mut XtrieveClient, dictionary_dir: &str, sql: &str) -> BtrieveResult<ResultSet> {
+    match client.server_info() {
+        Ok(info) if info.supports(op::QUERY as u16) => execute_remote(client, dictionary_dir, sql),
+        _ => execute(client, dictionary_dir, sql),
+    }
+}
+
+// ============================================================================
+// Parser
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Word(String),
+    Number(Literal),
+    Text(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(sql: &str) -> BtrieveResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = sql.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '$' | '#')) {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = match text.parse::<i64>() {
+                Ok(v) => Literal::Int(v),
+                Err(_) => Literal::Float(text.parse().map_err(|_| sql_error(format_args!("bad number {}", text)))?),
+            };
+            tokens.push(Token::Number(number));
+        } else if c == '\'' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('\'') if chars.get(i + 1) == Some(&'\'') => {
+                        text.push('\'');
+                        i += 2;
+                    }
+                    Some('\'') => break,
+                    Some(&c) => {
+                        text.push(c);
+                        i += 1;
+                    }
+                    None => return Err(sql_error("unterminated string")),
+                }
+            }
+            i += 1;
+            tokens.push(Token::Text(text));
+        } else {
+            let two: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = ["<=", ">=", "<>", "!="]
+                .into_iter()
+                .find(|s| *s == two)
+                .or_else(|| ["=", "<", ">", ",", "*", ";"].into_iter().find(|s| s.starts_with(c)))
+                .ok_or_else(|| sql_error(format_args!("unexpected '{}'", c)))?;
+            i += symbol.len();
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    pub(crate) fn new(sql: &str) -> BtrieveResult<Self> {
+        Ok(Parser {
+            tokens: tokenize(sql)?,
+            pos: 0,
+        })
+    }
+
+    pub(crate) fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    pub(crate) fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    pub(crate) fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> BtrieveResult<()> {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(sql_error(format_args!("expected {}", keyword)))
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn identifier(&mut self, what: &str) -> BtrieveResult<String> {
+        match self.next() {
+            Some(Token::Word(w)) => Ok(w),
+            _ => Err(sql_error(format_args!("expected {}", what))),
+        }
+    }
+
+    fn select(mut self) -> BtrieveResult<Select> {
+        self.keyword("SELECT")?;
+        let mut columns = Vec::new();
+        if !self.symbol("*") {
+            loop {
+                columns.push(self.identifier("column name")?);
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+        }
+        self.keyword("FROM")?;
+        let table = self.identifier("table name")?;
+
+        let mut conditions = Vec::new();
+        if self.is_keyword("WHERE") {
+            self.pos += 1;
+            loop {
+                conditions.push(self.condition()?);
+                if !self.is_keyword("AND") {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+
+        let mut limit = None;
+        if self.is_keyword("LIMIT") {
+            self.pos += 1;
+            limit = match self.next() {
+                Some(Token::Number(Literal::Int(n))) if n >= 0 => Some(n as usize),
+                _ => return Err(sql_error("expected row count after LIMIT")),
+            };
+        }
+
+        self.symbol(";");
+        if let Some(token) = self.peek() {
+            return Err(sql_error(format_args!("unexpected {:?}", token)));
+        }
+        Ok(Select {
+            columns,
+            table,
+            conditions,
+            limit,
+        })
+    }
+
+    pub(crate) fn condition(&mut self) -> BtrieveResult<Condition> {
+        let column = self.identifier("column name")?;
+        let op = match self.next() {
+            Some(Token::Symbol("=")) => CompareOp::Eq,
+            Some(Token::Symbol("<>" | "!=")) => CompareOp::Ne,
+            Some(Token::Symbol("<")) => CompareOp::Lt,
+            Some(Token::Symbol("<=")) => CompareOp::Le,
+            Some(Token::Symbol(">")) => CompareOp::Gt,
+            Some(Token::Symbol(">=")) => CompareOp::Ge,
+            _ => return Err(sql_error(format_args!("expected comparison after {}", column))),
+        };
+        let value = match self.next() {
+            Some(Token::Number(n)) => n,
+            Some(Token::Text(t)) => Literal::Text(t),
+            _ => return Err(sql_error(format_args!("expected value for {}", column))),
+        };
+        Ok(Condition { column, op, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddf::{Index, IndexSegment};
+
+    fn column(id: u16, name: &str, data_type: DataType, offset: u16, size: u16) -> Column {
+        Column {
+            id,
+            name: name.to_string(),
+            data_type,
+            offset,
+            size,
+            decimals: 0,
+            flags: 0,
+        }
+    }
+
+    fn customers() -> Table {
+        Table {
+            id: 1,
+            name: "Customer".to_string(),
+            location: "CUST.DAT".to_string(),
+            flags: 0,
+            columns: vec![
+                column(1, "Id", DataType::Integer, 0, 4),
+                column(2, "Name", DataType::String, 4, 10),
+                column(3, "City", DataType::String, 14, 10),
+            ],
+            indexes: vec![
+                Index { number: 0, segments: vec![IndexSegment { column: 1, flags: 0 }] },
+                Index {
+                    number: 1,
+                    segments: vec![IndexSegment { column: 2, flags: 0 }, IndexSegment { column: 1, flags: 0 }],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_parse_select() {
+        let select = Select::parse(
+            "select Id, name FROM Customer WHERE Name >= 'O''Brien' AND Id <> -5 LIMIT 10;",
+        )
+        .unwrap();
+        assert_eq!(select.columns, ["Id", "name"]);
+        assert_eq!(select.table, "Customer");
+        assert_eq!(
+            select.conditions,
+            [
+                Condition { column: "Name".into(), op: CompareOp::Ge, value: Literal::Text("O'Brien".into()) },
+                Condition { column: "Id".into(), op: CompareOp::Ne, value: Literal::Int(-5) },
+            ]
+        );
+        assert_eq!(select.limit, Some(10));
+
+        assert!(Select::parse("SELECT * FROM").is_err());
+        assert!(Select::parse("SELECT * FROM t WHERE a = 'open").is_err());
+        assert!(Select::parse("SELECT * FROM t extra").is_err());
+    }
+
+    #[test]
+    fn test_plan() {
+        let table = customers();
+        let plan = |sql: &str| Select::parse(sql).unwrap().plan(&table);
+
+        assert_eq!(plan("SELECT * FROM Customer WHERE City = 'Rome'"), Plan::Scan);
+        assert_eq!(
+            plan("SELECT * FROM Customer WHERE Id >= 7 AND Id < 9"),
+            Plan::Index {
+                key_number: 0,
+                start: Some(vec![7, 0, 0, 0]),
+                stop: vec![Condition { column: "Id".into(), op: CompareOp::Lt, value: Literal::Int(9) }],
+            }
+        );
+        // Leading segment of a two-part key, padded for the second part
+        assert_eq!(
+            plan("SELECT * FROM Customer WHERE name = 'Ada'"),
+            Plan::Index {
+                key_number: 1,
+                start: Some(b"Ada       \0\0\0\0".to_vec()),
+                stop: vec![Condition { column: "name".into(), op: CompareOp::Eq, value: Literal::Text("Ada".into()) }],
+            }
+        );
+        assert!(matches!(
+            plan("SELECT * FROM Customer WHERE Id <= 3"),
+            Plan::Index { key_number: 0, start: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_conditions_and_tsv() {
+        let ge = |value| Condition { column: "x".into(), op: CompareOp::Ge, value };
+        assert!(ge(Literal::Int(3)).matches(&Value::Int(3)));
+        assert!(ge(Literal::Float(1.5)).matches(&Value::Decimal { unscaled: 150, scale: 2 }));
+        assert!(ge(Literal::Text("Ada".into())).matches(&Value::Text("Ada   ".into())));
+        assert!(!ge(Literal::Int(0)).matches(&Value::Null));
+
+        let result = ResultSet {
+            columns: vec!["Id".into(), "Note".into()],
+            rows: vec![vec![Value::Int(1), Value::Text("a\tb\\c".into())], vec![Value::Int(2), Value::Null]],
+        };
+        let parsed = ResultSet::from_tsv(&result.to_tsv()).unwrap();
+        assert_eq!(parsed.columns, result.columns);
+        assert_eq!(parsed.rows[0][1], Value::Text("a\tb\\c".into()));
+        assert_eq!(parsed.rows[1][1], Value::Null);
+    }
+}
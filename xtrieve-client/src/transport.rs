@@ -0,0 +1,483 @@
+//! Transports that carry Btrieve requests to an engine
+//!
+//! `XtrieveClient` works on one `BtrieveRequest`/`BtrieveResponse` pair
+//! whatever carries it. The transport is picked from the address:
+//!
+//! | Address                         | Transport                          |
+//! |---------------------------------|------------------------------------|
+//! | `host:port`, `tcp://host:port`  | Binary protocol over TCP           |
+//! | `unix:///run/xtrieve.sock`      | Binary protocol over a Unix socket |
+//! | `grpc://host:port`              | gRPC `Execute` (`grpc` feature)    |
+//! | `file:///data/dir`              | Engine in this process             |
+//!
+//! Other transports plug in through `XtrieveClient::with_transport`.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use socket2::{SockRef, TcpKeepalive};
+use xtrieve_engine::protocol::{Request, Response};
+use xtrieve_engine::{BtrieveError, BtrieveResult, ServerInfo, StatusCode};
+
+use crate::btrieve::op;
+use crate::client::{BtrieveRequest, BtrieveResponse, Timeouts};
+use crate::local::{Embedded, EMBEDDED_SCHEME};
+
+/// Carries one request to an engine and brings its response back
+pub trait Transport: Send {
+    /// Send `request` and wait for the response, giving up at `deadline`.
+    ///
+    /// An `Err` means the transport itself failed; Btrieve errors come back
+    /// as a response with a non-zero status.
+    fn round_trip(
+        &mut self,
+        request: &BtrieveRequest,
+        deadline: Option<Instant>,
+    ) -> BtrieveResult<BtrieveResponse>;
+
+    /// Apply read/write timeouts; transports without a socket ignore them
+    fn set_timeouts(&mut self, _timeouts: &Timeouts) -> BtrieveResult<()> {
+        Ok(())
+    }
+
+    /// Enable keepalive probes; transports without a TCP socket ignore it
+    fn set_keepalive(&mut self, _idle: Duration) -> BtrieveResult<()> {
+        Ok(())
+    }
+
+    /// Capabilities the server sent with its first response, if the
+    /// transport asked for them
+    fn server_info(&self) -> Option<ServerInfo> {
+        None
+    }
+}
+
+/// Open the transport an address names (see the module docs)
+pub fn connect(addr: &str, timeouts: &Timeouts) -> BtrieveResult<Box<dyn Transport>> {
+    if let Some(data_dir) = addr.strip_prefix(EMBEDDED_SCHEME) {
+        return Ok(Box::new(Embedded::open(data_dir)?));
+    }
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix://") {
+        return Ok(Box::new(unix::connect(path, timeouts)?));
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(host) = addr.strip_prefix("grpc://") {
+        return Ok(Box::new(grpc::GrpcTransport::connect(host, timeouts)?));
+    }
+    let addr = addr.strip_prefix("tcp://").unwrap_or(addr);
+    Ok(Box::new(connect_tcp(addr, timeouts)?))
+}
+
+impl Transport for Embedded {
+    fn round_trip(
+        &mut self,
+        request: &BtrieveRequest,
+        _deadline: Option<Instant>,
+    ) -> BtrieveResult<BtrieveResponse> {
+        Ok(self.execute(request))
+    }
+}
+
+// ============================================================================
+// Stream sockets (TCP and Unix)
+// ============================================================================
+
+/// The parts of a connected stream socket the binary protocol needs
+trait Socket: Read + Write + Send + Sized {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn set_read_timeout(&self, limit: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, limit: Option<Duration>) -> io::Result<()>;
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+    fn set_read_timeout(&self, limit: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, limit)
+    }
+    fn set_write_timeout(&self, limit: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, limit)
+    }
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// Check if an I/O error is a socket timeout
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Map a connect error, naming timeouts plainly
+fn connect_error(e: io::Error) -> BtrieveError {
+    if is_timeout(&e) {
+        BtrieveError::Internal("Connection timed out".to_string())
+    } else {
+        BtrieveError::Internal(format!("Connection failed: {}", e))
+    }
+}
+
+/// Time left before `deadline`, or a timeout error once it has passed
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|left| !left.is_zero())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "deadline passed"))
+}
+
+/// Turn on TCP keepalive probes after `idle` of silence
+pub(crate) fn enable_keepalive(socket: SockRef<'_>, idle: Duration) -> BtrieveResult<()> {
+    let keepalive = TcpKeepalive::new().with_time(idle).with_interval(idle);
+    socket.set_tcp_keepalive(&keepalive)
+        .map_err(|e| BtrieveError::Internal(format!("Keepalive failed: {}", e)))
+}
+
+/// Reader that shrinks the socket read timeout to the time left before a deadline
+struct DeadlineReader<'a, S: Socket> {
+    inner: &'a mut BufReader<S>,
+    deadline: Instant,
+}
+
+impl<S: Socket> Read for DeadlineReader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Only hits the socket when the buffer is empty
+        if self.inner.buffer().is_empty() {
+            self.inner.get_ref().set_read_timeout(Some(remaining(self.deadline)?))?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Binary protocol over a stream socket
+struct StreamTransport<S: Socket> {
+    reader: BufReader<S>,
+    writer: BufWriter<S>,
+    timeouts: Timeouts,
+    /// Whether the server sends the error detail frame, once known
+    error_detail: Option<bool>,
+    /// Whether the first request, asking for the capabilities frame, has
+    /// been sent
+    greeted: bool,
+    /// Capabilities frame of the first response
+    server_info: Option<ServerInfo>,
+}
+
+impl<S: Socket> StreamTransport<S> {
+    fn new(stream: S, timeouts: &Timeouts) -> BtrieveResult<Self> {
+        let reader = BufReader::new(stream.try_clone()
+            .map_err(|e| BtrieveError::Internal(format!("Clone failed: {}", e)))?);
+        let mut transport = StreamTransport {
+            reader,
+            writer: BufWriter::new(stream),
+            timeouts: Timeouts::default(),
+            error_detail: None,
+            greeted: false,
+            server_info: None,
+        };
+        transport.set_timeouts(timeouts)?;
+        Ok(transport)
+    }
+
+    /// Map a socket error, closing the connection on a timeout: the late
+    /// reply would otherwise be read as the answer to the next request
+    fn error(&self, what: &str, e: io::Error) -> BtrieveError {
+        if is_timeout(&e) {
+            let _ = self.writer.get_ref().shutdown();
+            return BtrieveError::Internal(format!("{} timed out", what));
+        }
+        BtrieveError::Internal(format!("{} failed: {}", what, e))
+    }
+
+    /// Send a request asking for the error detail frame unless the server
+    /// is known not to send it, and the first also for the capabilities
+    /// frame. A server from before either frame refuses the first request
+    /// asking for it with status 1, so it is sent again without
+    fn negotiate(&mut self, request: &BtrieveRequest, deadline: Option<Instant>) -> BtrieveResult<Response> {
+        let ask = self.error_detail != Some(false);
+        let mut response = self.exchange(request, ask, !self.greeted, deadline)?;
+        if !self.greeted {
+            self.greeted = true;
+            self.server_info = response.capabilities.take();
+            if self.server_info.is_none() && response.status_code == StatusCode::InvalidOperation.as_raw() {
+                response = self.exchange(request, ask, false, deadline)?;
+            }
+        }
+        if !ask || self.error_detail.is_some() {
+            return Ok(response);
+        }
+        self.error_detail = Some(response.detail.is_some());
+        if response.detail.is_none() && response.status_code == StatusCode::InvalidOperation.as_raw() {
+            return self.exchange(request, false, false, deadline);
+        }
+        Ok(response)
+    }
+
+    fn exchange(
+        &mut self,
+        request: &BtrieveRequest,
+        error_detail: bool,
+        hello: bool,
+        deadline: Option<Instant>,
+    ) -> BtrieveResult<Response> {
+        let wire_req = Request {
+            operation_code: request.operation_code as u16,
+            position_block: request.position_block.clone(),
+            data_buffer: request.data_buffer.clone(),
+            key_buffer: request.key_buffer.clone(),
+            // Btrieve passes an Open's mode as its key number
+            key_number: match request.operation_code {
+                op::OPEN if request.open_mode != 0 => request.open_mode as i16,
+                _ => request.key_number as i16,
+            },
+            file_path: request.file_path.clone(),
+            lock_bias: request.lock_bias as u16,
+            client_id: request.client_id,
+            error_detail,
+            request_id: request.request_id,
+            hello,
+        };
+
+        // Send request
+        if let Some(deadline) = deadline {
+            // Nothing sent yet, so the connection stays usable
+            let left = remaining(deadline)
+                .map_err(|_| BtrieveError::Internal("Deadline passed before sending".to_string()))?;
+            self.writer.get_ref().set_write_timeout(Some(left))
+                .map_err(|e| BtrieveError::Internal(format!("Set timeout failed: {}", e)))?;
+        }
+        self.writer.write_all(&wire_req.to_bytes())
+            .map_err(|e| self.error("Write", e))?;
+        self.writer.flush()
+            .map_err(|e| self.error("Flush", e))?;
+
+        // Read response
+        match deadline {
+            Some(deadline) => Response::from_reader(&mut DeadlineReader {
+                inner: &mut self.reader,
+                deadline,
+            }),
+            None => Response::from_reader(&mut self.reader),
+        }
+        .map_err(|e| self.error("Read", e))
+    }
+}
+
+impl<S: Socket> Transport for StreamTransport<S> {
+    fn round_trip(
+        &mut self,
+        request: &BtrieveRequest,
+        deadline: Option<Instant>,
+    ) -> BtrieveResult<BtrieveResponse> {
+        let result = self.negotiate(request, deadline);
+        if deadline.is_some() {
+            // Put back the configured timeouts the deadline shortened
+            let timeouts = self.timeouts.clone();
+            self.set_timeouts(&timeouts)?;
+        }
+        let wire_resp = result?;
+
+        Ok(BtrieveResponse {
+            status_code: wire_resp.status_code as u32,
+            position_block: wire_resp.position_block,
+            data_buffer: wire_resp.data_buffer,
+            key_buffer: wire_resp.key_buffer,
+            // An empty frame only says the server could have sent one
+            detail: wire_resp.detail.filter(|detail| !detail.message.is_empty()),
+        })
+    }
+
+    fn set_timeouts(&mut self, timeouts: &Timeouts) -> BtrieveResult<()> {
+        // Both halves share the socket, so one call covers them
+        let stream = self.writer.get_ref();
+        stream.set_read_timeout(timeouts.read)
+            .and_then(|()| stream.set_write_timeout(timeouts.write))
+            .map_err(|e| BtrieveError::Internal(format!("Set timeout failed: {}", e)))?;
+        self.timeouts = timeouts.clone();
+        Ok(())
+    }
+
+    fn server_info(&self) -> Option<ServerInfo> {
+        self.server_info.clone()
+    }
+}
+
+/// Binary protocol over TCP
+struct TcpTransport(StreamTransport<TcpStream>);
+
+impl Transport for TcpTransport {
+    fn round_trip(
+        &mut self,
+        request: &BtrieveRequest,
+        deadline: Option<Instant>,
+    ) -> BtrieveResult<BtrieveResponse> {
+        self.0.round_trip(request, deadline)
+    }
+
+    fn set_timeouts(&mut self, timeouts: &Timeouts) -> BtrieveResult<()> {
+        self.0.set_timeouts(timeouts)
+    }
+
+    fn set_keepalive(&mut self, idle: Duration) -> BtrieveResult<()> {
+        enable_keepalive(SockRef::from(self.0.writer.get_ref()), idle)
+    }
+
+    fn server_info(&self) -> Option<ServerInfo> {
+        self.0.server_info()
+    }
+}
+
+/// Connect to the first address `addr` resolves to that answers within `limit`
+fn connect_timeout(addr: &str, limit: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to");
+    for socket_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, limit) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn connect_tcp(addr: &str, timeouts: &Timeouts) -> BtrieveResult<TcpTransport> {
+    let stream = match timeouts.connect {
+        Some(limit) => connect_timeout(addr, limit),
+        None => TcpStream::connect(addr),
+    }
+    .map_err(connect_error)?;
+    Ok(TcpTransport(StreamTransport::new(stream, timeouts)?))
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    impl Socket for UnixStream {
+        fn try_clone(&self) -> io::Result<Self> {
+            UnixStream::try_clone(self)
+        }
+        fn set_read_timeout(&self, limit: Option<Duration>) -> io::Result<()> {
+            UnixStream::set_read_timeout(self, limit)
+        }
+        fn set_write_timeout(&self, limit: Option<Duration>) -> io::Result<()> {
+            UnixStream::set_write_timeout(self, limit)
+        }
+        fn shutdown(&self) -> io::Result<()> {
+            UnixStream::shutdown(self, Shutdown::Both)
+        }
+    }
+
+    /// Binary protocol over a Unix domain socket; connecting is local and
+    /// immediate, so the connect timeout doesn't apply
+    pub(super) fn connect(path: &str, timeouts: &Timeouts) -> BtrieveResult<StreamTransport<UnixStream>> {
+        let stream = UnixStream::connect(path).map_err(connect_error)?;
+        StreamTransport::new(stream, timeouts)
+    }
+}
+
+// ============================================================================
+// gRPC
+// ============================================================================
+
+#[cfg(feature = "grpc")]
+mod grpc {
+    use super::*;
+    use crate::proto;
+    use crate::proto::xtrieve_client::XtrieveClient as GrpcClient;
+    use tonic::transport::{Channel, Endpoint};
+    use xtrieve_engine::ErrorDetail;
+
+    /// The gRPC `Execute` call, driven from a private single-threaded runtime.
+    ///
+    /// Like the rest of the sync client, it must not be used from inside
+    /// an async runtime.
+    pub(super) struct GrpcTransport {
+        runtime: tokio::runtime::Runtime,
+        client: GrpcClient<Channel>,
+        timeouts: Timeouts,
+    }
+
+    impl GrpcTransport {
+        pub(super) fn connect(host: &str, timeouts: &Timeouts) -> BtrieveResult<Self> {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| BtrieveError::Internal(format!("Runtime failed: {}", e)))?;
+
+            let mut endpoint = Endpoint::from_shared(format!("http://{}", host))
+                .map_err(|e| BtrieveError::Internal(format!("Bad address: {}", e)))?;
+            if let Some(limit) = timeouts.connect {
+                endpoint = endpoint.connect_timeout(limit);
+            }
+            let channel = runtime.block_on(endpoint.connect())
+                .map_err(|e| BtrieveError::Internal(format!("Connection failed: {}", e)))?;
+
+            Ok(GrpcTransport {
+                runtime,
+                client: GrpcClient::new(channel),
+                timeouts: timeouts.clone(),
+            })
+        }
+    }
+
+    impl Transport for GrpcTransport {
+        fn round_trip(
+            &mut self,
+            request: &BtrieveRequest,
+            deadline: Option<Instant>,
+        ) -> BtrieveResult<BtrieveResponse> {
+            let mut grpc_request = tonic::Request::new(proto::BtrieveRequest {
+                operation_code: request.operation_code,
+                position_block: request.position_block.clone(),
+                data_buffer: request.data_buffer.clone(),
+                data_buffer_length: request.data_buffer_length,
+                key_buffer: request.key_buffer.clone(),
+                key_buffer_length: request.key_buffer_length,
+                key_number: request.key_number,
+                file_path: request.file_path.clone(),
+                open_mode: request.open_mode,
+                file_spec: None,
+                lock_bias: request.lock_bias as i32,
+                client_id: request.client_id,
+            });
+            // One call is one exchange, so the read timeout bounds all of it
+            let limit = match deadline {
+                Some(deadline) => Some(remaining(deadline)
+                    .map_err(|_| BtrieveError::Internal("Deadline passed before sending".to_string()))?),
+                None => self.timeouts.read,
+            };
+            if let Some(limit) = limit {
+                grpc_request.set_timeout(limit);
+            }
+
+            let response = self.runtime.block_on(self.client.execute(grpc_request))
+                .map_err(|status| match status.code() {
+                    tonic::Code::DeadlineExceeded | tonic::Code::Cancelled => {
+                        BtrieveError::Internal("Request timed out".to_string())
+                    }
+                    _ => BtrieveError::Internal(format!("gRPC call failed: {}", status.message())),
+                })?
+                .into_inner();
+
+            Ok(BtrieveResponse {
+                status_code: response.status_code,
+                position_block: response.position_block,
+                data_buffer: response.data_buffer,
+                key_buffer: response.key_buffer,
+                detail: (!response.error_message.is_empty()).then_some(ErrorDetail {
+                    message: response.error_message,
+                    context: response.error_context,
+                }),
+            })
+        }
+
+        fn set_timeouts(&mut self, timeouts: &Timeouts) -> BtrieveResult<()> {
+            self.timeouts = timeouts.clone();
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Typed record mapping
+//!
+//! Maps fixed-offset fields of a Btrieve record onto a Rust struct. Implement
+//! `TypedRecord` by hand, or with the `derive` feature:
+//!
+//! ```ignore
+//! use xtrieve_client::typed::{BtrieveDate, TypedRecord};
+//!
+//! #[derive(TypedRecord)]
+//! struct Customer {
+//!     #[btrieve(offset = 0)]
+//!     id: u32,
+//!     #[btrieve(offset = 4, len = 30)]
+//!     name: String,
+//!     #[btrieve(offset = 34, len = 40, zstring)]
+//!     email: String,
+//!     #[btrieve(offset = 74)]
+//!     since: BtrieveDate,
+//!     #[btrieve(offset = 78, len = 6, bcd)]
+//!     balance_cents: i64,
+//! }
+//! ```
+//!
+//! Offsets are 0-based byte positions, like key positions. `len` may be left
+//! out for fixed-width types (integers, floats, dates, times). Strings are
+//! space padded unless marked `zstring`; `bcd` selects packed decimal.
+
+use xtrieve_engine::{BtrieveError, BtrieveResult, StatusCode};
+
+/// A struct stored as a fixed-length Btrieve record
+pub trait TypedRecord: Sized {
+    /// Bytes needed to hold every mapped field
+    const RECORD_LENGTH: usize;
+
+    /// Encode into a record buffer of `RECORD_LENGTH` bytes
+    fn to_record(&self) -> BtrieveResult<Vec<u8>>;
+
+    /// Decode from a record buffer
+    fn from_record(data: &[u8]) -> BtrieveResult<Self>;
+}
+
+/// On-disk representation of a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// The type's natural layout (little-endian numbers, space padded strings)
+    Native,
+    /// NUL terminated string
+    ZString,
+    /// Packed decimal, two digits per byte with a trailing sign nibble
+    Bcd,
+}
+
+/// A value that can be read from and written to a record field
+pub trait Field: Sized {
+    /// Natural width in bytes (0 = the mapping must give `len`)
+    const WIDTH: usize;
+
+    fn read(bytes: &[u8], encoding: Encoding) -> BtrieveResult<Self>;
+
+    fn write(&self, bytes: &mut [u8], encoding: Encoding) -> BtrieveResult<()>;
+}
+
+fn unsupported(type_name: &str, encoding: Encoding) -> BtrieveError {
+    BtrieveError::Internal(format!("{:?} encoding is not supported for {}", encoding, type_name))
+}
+
+/// Borrow `len` bytes at `offset`, failing if the record is too short
+pub fn slice(data: &[u8], offset: usize, len: usize) -> BtrieveResult<&[u8]> {
+    data.get(offset..offset + len)
+        .ok_or(BtrieveError::Status(StatusCode::DataBufferTooShort))
+}
+
+macro_rules! impl_int_field {
+    ($($t:ty),*) => {$(
+        impl Field for $t {
+            const WIDTH: usize = std::mem::size_of::<$t>();
+
+            fn read(bytes: &[u8], encoding: Encoding) -> BtrieveResult<Self> {
+                match encoding {
+                    Encoding::Native => {
+                        let mut buf = [0u8; std::mem::size_of::<$t>()];
+                        let n = bytes.len().min(buf.len());
+                        buf[..n].copy_from_slice(&bytes[..n]);
+                        Ok(<$t>::from_le_bytes(buf))
+                    }
+                    Encoding::Bcd => <$t>::try_from(read_bcd(bytes)?)
+                        .map_err(|_| BtrieveError::Status(StatusCode::DataBufferTooShort)),
+                    Encoding::ZString => Err(unsupported(stringify!($t), encoding)),
+                }
+            }
+
+            fn write(&self, bytes: &mut [u8], encoding: Encoding) -> BtrieveResult<()> {
+                match encoding {
+                    Encoding::Native => {
+                        let le = self.to_le_bytes();
+                        let n = bytes.len().min(le.len());
+                        bytes[..n].copy_from_slice(&le[..n]);
+                        Ok(())
+                    }
+                    Encoding::Bcd => {
+                        let value = i64::try_from(*self)
+                            .map_err(|_| BtrieveError::Internal(format!("{} does not fit in BCD", self)))?;
+                        write_bcd(value, bytes)
+                    }
+                    Encoding::ZString => Err(unsupported(stringify!($t), encoding)),
+                }
+            }
+        }
+    )*};
+}
+
+impl_int_field!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+macro_rules! impl_float_field {
+    ($($t:ty),*) => {$(
+        impl Field for $t {
+            const WIDTH: usize = std::mem::size_of::<$t>();
+
+            fn read(bytes: &[u8], encoding: Encoding) -> BtrieveResult<Self> {
+                if encoding != Encoding::Native {
+                    return Err(unsupported(stringify!($t), encoding));
+                }
+                let buf = bytes.try_into()
+                    .map_err(|_| BtrieveError::Status(StatusCode::DataBufferTooShort))?;
+                Ok(<$t>::from_le_bytes(buf))
+            }
+
+            fn write(&self, bytes: &mut [u8], encoding: Encoding) -> BtrieveResult<()> {
+                if encoding != Encoding::Native || bytes.len() != Self::WIDTH {
+                    return Err(unsupported(stringify!($t), encoding));
+                }
+                bytes.copy_from_slice(&self.to_le_bytes());
+                Ok(())
+            }
+        }
+    )*};
+}
+
+impl_float_field!(f32, f64);
+
+impl Field for String {
+    const WIDTH: usize = 0;
+
+    fn read(bytes: &[u8], encoding: Encoding) -> BtrieveResult<Self> {
+        let text = match encoding {
+            Encoding::Native => {
+                let end = bytes.iter().rposition(|&b| b != b' ' && b != 0).map_or(0, |i| i + 1);
+                &bytes[..end]
+            }
+            Encoding::ZString => {
+                let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                &bytes[..end]
+            }
+            Encoding::Bcd => return Err(unsupported("String", encoding)),
+        };
+        Ok(String::from_utf8_lossy(text).into_owned())
+    }
+
+    fn write(&self, bytes: &mut [u8], encoding: Encoding) -> BtrieveResult<()> {
+        let text = self.as_bytes();
+        match encoding {
+            Encoding::Native => {
+                let n = text.len().min(bytes.len());
+                bytes[..n].copy_from_slice(&text[..n]);
+                bytes[n..].fill(b' ');
+            }
+            Encoding::ZString => {
+                // Always leave room for the terminator
+                let n = text.len().min(bytes.len().saturating_sub(1));
+                bytes[..n].copy_from_slice(&text[..n]);
+                bytes[n..].fill(0);
+            }
+            Encoding::Bcd => return Err(unsupported("String", encoding)),
+        }
+        Ok(())
+    }
+}
+
+impl Field for Vec<u8> {
+    const WIDTH: usize = 0;
+
+    fn read(bytes: &[u8], encoding: Encoding) -> BtrieveResult<Self> {
+        match encoding {
+            Encoding::Native => Ok(bytes.to_vec()),
+            _ => Err(unsupported("Vec<u8>", encoding)),
+        }
+    }
+
+    fn write(&self, bytes: &mut [u8], encoding: Encoding) -> BtrieveResult<()> {
+        if encoding != Encoding::Native {
+            return Err(unsupported("Vec<u8>", encoding));
+        }
+        let n = self.len().min(bytes.len());
+        bytes[..n].copy_from_slice(&self[..n]);
+        bytes[n..].fill(0);
+        Ok(())
+    }
+}
+
+/// Btrieve DATE: day (1), month (1), year (2)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BtrieveDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Field for BtrieveDate {
+    const WIDTH: usize = 4;
+
+    fn read(bytes: &[u8], encoding: Encoding) -> BtrieveResult<Self> {
+        if encoding != Encoding::Native || bytes.len() < 4 {
+            return Err(unsupported("BtrieveDate", encoding));
+        }
+        Ok(BtrieveDate {
+            day: bytes[0],
+            month: bytes[1],
+            year: u16::from_le_bytes([bytes[2], bytes[3]]),
+        })
+    }
+
+    fn write(&self, bytes: &mut [u8], encoding: Encoding) -> BtrieveResult<()> {
+        if encoding != Encoding::Native || bytes.len() < 4 {
+            return Err(unsupported("BtrieveDate", encoding));
+        }
+        bytes[0] = self.day;
+        bytes[1] = self.month;
+        bytes[2..4].copy_from_slice(&self.year.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Btrieve TIME: hundredths (1), seconds (1), minutes (1), hours (1)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BtrieveTime {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub hundredths: u8,
+}
+
+impl Field for BtrieveTime {
+    const WIDTH: usize = 4;
+
+    fn read(bytes: &[u8], encoding: Encoding) -> BtrieveResult<Self> {
+        if encoding != Encoding::Native || bytes.len() < 4 {
+            return Err(unsupported("BtrieveTime", encoding));
+        }
+        Ok(BtrieveTime {
+            hundredths: bytes[0],
+            seconds: bytes[1],
+            minutes: bytes[2],
+            hours: bytes[3],
+        })
+    }
+
+    fn write(&self, bytes: &mut [u8], encoding: Encoding) -> BtrieveResult<()> {
+        if encoding != Encoding::Native || bytes.len() < 4 {
+            return Err(unsupported("BtrieveTime", encoding));
+        }
+        bytes[0] = self.hundredths;
+        bytes[1] = self.seconds;
+        bytes[2] = self.minutes;
+        bytes[3] = self.hours;
+        Ok(())
+    }
+}
+
+/// Decode a packed decimal (sign nibble 0xD or 0xB means negative)
+pub fn read_bcd(bytes: &[u8]) -> BtrieveResult<i64> {
+    let Some((&last, rest)) = bytes.split_last() else {
+        return Ok(0);
+    };
+    let bad = || BtrieveError::Internal("Invalid packed decimal digit".to_string());
+
+    let mut value: i64 = 0;
+    let digits = rest.iter().flat_map(|&b| [b >> 4, b & 0x0F]).chain([last >> 4]);
+    for digit in digits {
+        if digit > 9 {
+            return Err(bad());
+        }
+        value = value.checked_mul(10)
+            .and_then(|v| v.checked_add(digit as i64))
+            .ok_or_else(bad)?;
+    }
+
+    Ok(match last & 0x0F {
+        0x0D | 0x0B => -value,
+        _ => value,
+    })
+}
+
+/// Encode a packed decimal filling `bytes` (sign nibble 0xF or 0xD)
+pub fn write_bcd(value: i64, bytes: &mut [u8]) -> BtrieveResult<()> {
+    if bytes.is_empty() {
+        return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
+    }
+    let mut remaining = value.unsigned_abs();
+    let sign = if value < 0 { 0x0D } else { 0x0F };
+
+    // Nibbles from least significant: the sign, then digits
+    let nibbles = bytes.len() * 2;
+    for i in 1..nibbles {
+        let digit = (remaining % 10) as u8;
+        remaining /= 10;
+        let byte = &mut bytes[(nibbles - 1 - i) / 2];
+        if i % 2 == 1 {
+            *byte = (*byte & 0x0F) | (digit << 4);
+        } else {
+            *byte = (*byte & 0xF0) | digit;
+        }
+    }
+    if remaining != 0 {
+        return Err(BtrieveError::Internal(format!("{} does not fit in {} BCD bytes", value, bytes.len())));
+    }
+    let last = bytes.len() - 1;
+    bytes[last] = (bytes[last] & 0xF0) | sign;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bcd_roundtrip() {
+        let mut buf = [0u8; 4];
+        write_bcd(-12345, &mut buf).unwrap();
+        assert_eq!(buf, [0x00, 0x12, 0x34, 0x5D]);
+        assert_eq!(read_bcd(&buf).unwrap(), -12345);
+
+        write_bcd(7, &mut buf).unwrap();
+        assert_eq!(read_bcd(&buf).unwrap(), 7);
+
+        // 4 bytes hold 7 digits
+        assert!(write_bcd(12_345_678, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_string_padding() {
+        let mut buf = [0xAAu8; 6];
+        "abc".to_string().write(&mut buf, Encoding::Native).unwrap();
+        assert_eq!(&buf, b"abc   ");
+        assert_eq!(String::read(&buf, Encoding::Native).unwrap(), "abc");
+
+        "abcdefgh".to_string().write(&mut buf, Encoding::ZString).unwrap();
+        assert_eq!(&buf, b"abcde\0");
+        assert_eq!(String::read(&buf, Encoding::ZString).unwrap(), "abcde");
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive_roundtrip() {
+        #[derive(Debug, PartialEq, crate::TypedRecord)]
+        #[btrieve(length = 32)]
+        struct Customer {
+            #[btrieve(offset = 0)]
+            id: u32,
+            #[btrieve(offset = 4, len = 10)]
+            name: String,
+            #[btrieve(offset = 14)]
+            since: BtrieveDate,
+            #[btrieve(offset = 18, len = 5, bcd)]
+            balance: i64,
+        }
+
+        let customer = Customer {
+            id: 42,
+            name: "Ada".to_string(),
+            since: BtrieveDate { year: 1991, month: 3, day: 14 },
+            balance: -1999,
+        };
+        let data = customer.to_record().unwrap();
+        assert_eq!(data.len(), 32);
+        assert_eq!(&data[0..4], &42u32.to_le_bytes());
+        assert_eq!(Customer::from_record(&data).unwrap(), customer);
+    }
+}
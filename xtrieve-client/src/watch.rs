@@ -0,0 +1,319 @@
+//! Record change notifications over the gRPC `Watch` feed
+//!
+//! [`XtrieveClient::watch`] follows the changes committed to one file on
+//! a background thread with its own connection. Every event carries a
+//! [`ResumeToken`]; when the connection drops, the watch reconnects and
+//! resumes after the last change it received, so none are missed or seen
+//! twice. A watch that cannot resume (the server restarted, or no longer
+//! holds the changes since) ends with an error.
+
+use std::ops::{Bound, RangeBounds};
+use std::thread;
+
+use tokio::sync::mpsc;
+use tonic::transport::Endpoint;
+use tonic::{Status, Streaming};
+use xtrieve_engine::{BtrieveError, BtrieveResult};
+
+use crate::btrieve::{check_status, open_request, op, FileStatistics, KeyBounds};
+use crate::client::{BtrieveRequest, ReconnectPolicy, Timeouts, XtrieveClient};
+use crate::proto;
+use crate::proto::xtrieve_client::XtrieveClient as GrpcClient;
+
+/// Kind of record change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+    /// Every record was removed by Truncate File
+    Truncate,
+}
+
+/// Where a change sits in the server's change feed. Pass the token of the
+/// last change handled to [`XtrieveClient::watch_after`] to pick up after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumeToken {
+    /// Feed the change came from; a restarted server starts a new one
+    pub feed_id: u64,
+    pub sequence: u64,
+}
+
+/// A change committed to the watched file
+#[derive(Debug, Clone)]
+pub struct ChangeEvent {
+    pub kind: ChangeKind,
+    /// Path of the file as the server resolved it
+    pub file_path: String,
+    /// Server session that made the change
+    pub session_id: u64,
+    /// Record after the change (empty for a delete or truncate)
+    pub data: Vec<u8>,
+    pub token: ResumeToken,
+}
+
+impl From<proto::ChangeEvent> for ChangeEvent {
+    fn from(event: proto::ChangeEvent) -> Self {
+        let kind = match event.kind() {
+            proto::ChangeKind::Insert => ChangeKind::Insert,
+            proto::ChangeKind::Update => ChangeKind::Update,
+            proto::ChangeKind::Delete => ChangeKind::Delete,
+            proto::ChangeKind::Truncate => ChangeKind::Truncate,
+        };
+        ChangeEvent {
+            kind,
+            file_path: event.file_path,
+            session_id: event.session_id,
+            data: event.data_buffer,
+            token: ResumeToken { feed_id: event.feed_id, sequence: event.sequence },
+        }
+    }
+}
+
+/// Key values a watch reports changes for
+#[derive(Debug, Clone)]
+pub struct WatchRange {
+    key_number: i32,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+}
+
+impl WatchRange {
+    /// Records whose `key_number` value falls in `bounds`, compared by the
+    /// key's type as [`BtrieveFile::range`](crate::BtrieveFile::range) does
+    pub fn new<K: AsRef<[u8]>>(key_number: i32, bounds: impl RangeBounds<K>) -> Self {
+        let to_owned = |bound: Bound<&K>| match bound {
+            Bound::Included(k) => Bound::Included(k.as_ref().to_vec()),
+            Bound::Excluded(k) => Bound::Excluded(k.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        WatchRange {
+            key_number,
+            start: to_owned(bounds.start_bound()),
+            end: to_owned(bounds.end_bound()),
+        }
+    }
+}
+
+/// Changes to a watched file, in commit order. See [`XtrieveClient::watch`]
+pub struct Watch {
+    events: mpsc::Receiver<BtrieveResult<ChangeEvent>>,
+}
+
+impl Watch {
+    /// The events as a channel, for use from async code
+    pub fn into_receiver(self) -> mpsc::Receiver<BtrieveResult<ChangeEvent>> {
+        self.events
+    }
+}
+
+/// Waits for the next change. Like the rest of the sync client it must
+/// not be used from inside an async runtime. An error ends the watch
+impl Iterator for Watch {
+    type Item = BtrieveResult<ChangeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.blocking_recv()
+    }
+}
+
+impl XtrieveClient {
+    /// Follow the changes committed to `path` from now on (`grpc://`
+    /// addresses only).
+    ///
+    /// With a `key_range`, only changes whose record falls in it are
+    /// reported; the key is looked up by opening the file through this
+    /// client. An update is judged by its new record; a delete or
+    /// truncate, which carries no record, is always reported. The watch reconnects with
+    /// this client's reconnect policy, or the default one.
+    pub fn watch(&mut self, path: &str, key_range: Option<WatchRange>) -> BtrieveResult<Watch> {
+        self.start_watch(path, key_range, None)
+    }
+
+    /// Like [`watch`](Self::watch), starting after the change `token`
+    /// came from
+    pub fn watch_after(
+        &mut self,
+        path: &str,
+        key_range: Option<WatchRange>,
+        token: ResumeToken,
+    ) -> BtrieveResult<Watch> {
+        self.start_watch(path, key_range, Some(token))
+    }
+
+    fn start_watch(
+        &mut self,
+        path: &str,
+        key_range: Option<WatchRange>,
+        after: Option<ResumeToken>,
+    ) -> BtrieveResult<Watch> {
+        let host = self
+            .addr
+            .as_deref()
+            .and_then(|addr| addr.strip_prefix("grpc://"))
+            .ok_or_else(|| BtrieveError::Internal("Watching needs a grpc:// address".to_string()))?
+            .to_string();
+        let bounds = match key_range {
+            Some(range) => Some(self.key_bounds(path, range)?),
+            None => None,
+        };
+        let feed = Feed {
+            host,
+            path: path.to_string(),
+            bounds,
+            after,
+            policy: self.reconnect.clone().unwrap_or_default(),
+            timeouts: self.timeouts.clone(),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| BtrieveError::Internal(format!("Runtime failed: {}", e)))?;
+        // The first connection is made here, so a bad address fails the call
+        let stream = runtime.block_on(feed.open()).map_err(watch_error)?;
+
+        let (tx, events) = mpsc::channel(256);
+        thread::Builder::new()
+            .name("xtrieve-watch".to_string())
+            .spawn(move || {
+                runtime.block_on(async {
+                    let dropped = tx.clone();
+                    tokio::select! {
+                        _ = dropped.closed() => {}
+                        _ = feed.run(stream, tx) => {}
+                    }
+                })
+            })
+            .map_err(BtrieveError::Io)?;
+        Ok(Watch { events })
+    }
+
+    /// Resolve a watch range against the key specification of `path`
+    fn key_bounds(&mut self, path: &str, range: WatchRange) -> BtrieveResult<KeyBounds> {
+        let opened = check_status(self.execute(open_request(path, 0, None)?)?)?;
+        let stat = self.execute(BtrieveRequest {
+            operation_code: op::STAT,
+            position_block: opened.position_block.clone(),
+            ..Default::default()
+        });
+        self.execute(BtrieveRequest {
+            operation_code: op::CLOSE,
+            position_block: opened.position_block,
+            ..Default::default()
+        })?;
+        let stats = FileStatistics::from_bytes(&check_status(stat?)?.data_buffer)?;
+        KeyBounds::new(&stats, range.key_number, (range.start, range.end))
+    }
+}
+
+/// Map a failed watch to an error; a feed that can't resume is named as such
+fn watch_error(status: Status) -> BtrieveError {
+    match status.code() {
+        tonic::Code::DataLoss => BtrieveError::Internal(format!("Change feed lost: {}", status.message())),
+        _ => BtrieveError::Internal(format!("Watch failed: {}", status.message())),
+    }
+}
+
+/// One watch's connection to the feed, and where it has got to
+struct Feed {
+    host: String,
+    path: String,
+    bounds: Option<KeyBounds>,
+    after: Option<ResumeToken>,
+    policy: ReconnectPolicy,
+    timeouts: Timeouts,
+}
+
+impl Feed {
+    /// Connect and start the feed after the last change received
+    async fn open(&self) -> Result<Streaming<proto::ChangeEvent>, Status> {
+        let mut endpoint = Endpoint::from_shared(format!("http://{}", self.host))
+            .map_err(|e| Status::invalid_argument(format!("Bad address: {}", e)))?;
+        if let Some(limit) = self.timeouts.connect {
+            endpoint = endpoint.connect_timeout(limit);
+        }
+        let channel = endpoint.connect().await
+            .map_err(|e| Status::unavailable(format!("Connection failed: {}", e)))?;
+        let request = proto::WatchRequest {
+            file_path: self.path.clone(),
+            resume_after: self.after.map_or(0, |t| t.sequence),
+            feed_id: self.after.map_or(0, |t| t.feed_id),
+        };
+        Ok(GrpcClient::new(channel).watch(request).await?.into_inner())
+    }
+
+    /// Connect again after a break, as the reconnect policy allows
+    async fn reopen(&self) -> Result<Streaming<proto::ChangeEvent>, Status> {
+        let mut attempt = 1;
+        loop {
+            tokio::time::sleep(self.policy.delay).await;
+            match self.open().await {
+                Err(status) if status.code() == tonic::Code::Unavailable && attempt < self.policy.max_attempts => {
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Pass changes on until the receiver goes or the feed can't continue
+    async fn run(
+        mut self,
+        mut stream: Streaming<proto::ChangeEvent>,
+        tx: mpsc::Sender<BtrieveResult<ChangeEvent>>,
+    ) {
+        loop {
+            let event = match stream.message().await {
+                Ok(Some(event)) => ChangeEvent::from(event),
+                Err(status) if status.code() == tonic::Code::DataLoss => {
+                    let _ = tx.send(Err(watch_error(status))).await;
+                    return;
+                }
+                // The server went away or ended the stream
+                Ok(None) | Err(_) => match self.reopen().await {
+                    Ok(reopened) => {
+                        stream = reopened;
+                        continue;
+                    }
+                    Err(status) => {
+                        let _ = tx.send(Err(watch_error(status))).await;
+                        return;
+                    }
+                },
+            };
+            self.after = Some(event.token);
+            let wanted = match &self.bounds {
+                Some(bounds) => matches!(event.kind, ChangeKind::Delete | ChangeKind::Truncate) || bounds.holds(&event.data),
+                None => true,
+            };
+            if wanted && tx.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = XtrieveClient::connect(&format!("file://{}", dir.path().display())).unwrap();
+        crate::FileBuilder::new(8).key(crate::KeyBuilder::unsigned(0, 4)).create(&mut client, "PARTS.DAT").unwrap();
+
+        let bounds = client.key_bounds("PARTS.DAT", WatchRange::new(0, 20u32.to_le_bytes()..30u32.to_le_bytes())).unwrap();
+        let record = |id: u32| [id.to_le_bytes(), *b"PART"].concat();
+        assert!(!bounds.holds(&record(10)));
+        assert!(bounds.holds(&record(20)));
+        assert!(bounds.holds(&record(29)));
+        assert!(!bounds.holds(&record(30)));
+        // Compared as unsigned integers, not bytes
+        assert!(!bounds.holds(&record(0x115)));
+
+        assert!(client.key_bounds("PARTS.DAT", WatchRange::new(3, 0u32.to_le_bytes()..)).is_err());
+        assert!(client.watch("PARTS.DAT", None).is_err());
+    }
+}
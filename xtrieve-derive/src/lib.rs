@@ -0,0 +1,163 @@
+//! Derive macros for Xtrieve
+//!
+//! `#[derive(TypedRecord)]` implements `xtrieve_client::typed::TypedRecord`
+//! for a struct whose fields carry `#[btrieve(offset = N, ...)]` mappings.
+//! See the `xtrieve_client::typed` module for the attribute reference.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt};
+
+#[proc_macro_derive(TypedRecord, attributes(btrieve))]
+pub fn derive_typed_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// Mapping of one struct field
+struct FieldMapping {
+    ident: syn::Ident,
+    ty: syn::Type,
+    offset: usize,
+    len: Option<usize>,
+    encoding: TokenStream2,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new(input.span(), "TypedRecord needs named fields")),
+        },
+        _ => return Err(syn::Error::new(input.span(), "TypedRecord can only be derived for structs")),
+    };
+
+    // Optional #[btrieve(length = N)] on the struct pads the record
+    let mut min_length = 0usize;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("btrieve")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("length") {
+                min_length = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("expected `length = N`"))
+            }
+        })?;
+    }
+
+    let mappings = fields.iter().map(parse_field).collect::<syn::Result<Vec<_>>>()?;
+
+    let widths: Vec<TokenStream2> = mappings
+        .iter()
+        .map(|m| {
+            let ty = &m.ty;
+            match m.len {
+                Some(len) => quote!(#len),
+                None => quote!(<#ty as ::xtrieve_client::typed::Field>::WIDTH),
+            }
+        })
+        .collect();
+
+    let checks = mappings.iter().zip(&widths).map(|(m, width)| {
+        let message = format!("field `{}` needs `len = N` in its #[btrieve] mapping", m.ident);
+        quote_spanned!(m.ident.span()=> assert!(#width > 0, #message);)
+    });
+
+    let ends = mappings.iter().zip(&widths).map(|(m, width)| {
+        let offset = m.offset;
+        quote! {
+            let end = #offset + #width;
+            if end > length {
+                length = end;
+            }
+        }
+    });
+
+    let writes = mappings.iter().zip(&widths).map(|(m, width)| {
+        let (ident, offset, encoding) = (&m.ident, m.offset, &m.encoding);
+        quote! {
+            ::xtrieve_client::typed::Field::write(
+                &self.#ident,
+                &mut data[#offset..#offset + #width],
+                #encoding,
+            )?;
+        }
+    });
+
+    let reads = mappings.iter().zip(&widths).map(|(m, width)| {
+        let (ident, offset, encoding) = (&m.ident, m.offset, &m.encoding);
+        quote! {
+            #ident: ::xtrieve_client::typed::Field::read(
+                ::xtrieve_client::typed::slice(data, #offset, #width)?,
+                #encoding,
+            )?,
+        }
+    });
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        const _: () = {
+            #(#checks)*
+        };
+
+        impl #impl_generics ::xtrieve_client::typed::TypedRecord for #name #ty_generics #where_clause {
+            const RECORD_LENGTH: usize = {
+                let mut length = #min_length;
+                #(#ends)*
+                length
+            };
+
+            fn to_record(&self) -> ::xtrieve_client::BtrieveResult<::std::vec::Vec<u8>> {
+                let mut data = ::std::vec![0u8; <Self as ::xtrieve_client::typed::TypedRecord>::RECORD_LENGTH];
+                #(#writes)*
+                Ok(data)
+            }
+
+            fn from_record(data: &[u8]) -> ::xtrieve_client::BtrieveResult<Self> {
+                Ok(Self {
+                    #(#reads)*
+                })
+            }
+        }
+    })
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<FieldMapping> {
+    let ident = field.ident.clone().expect("named field");
+    let mut offset = None;
+    let mut len = None;
+    let mut encoding = quote!(::xtrieve_client::typed::Encoding::Native);
+
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("btrieve")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("offset") {
+                offset = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("len") {
+                len = Some(meta.value()?.parse::<LitInt>()?.base10_parse()?);
+            } else if meta.path.is_ident("zstring") {
+                encoding = quote!(::xtrieve_client::typed::Encoding::ZString);
+            } else if meta.path.is_ident("bcd") {
+                encoding = quote!(::xtrieve_client::typed::Encoding::Bcd);
+            } else {
+                return Err(meta.error("expected `offset`, `len`, `zstring` or `bcd`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let offset = offset.ok_or_else(|| {
+        syn::Error::new(ident.span(), "missing #[btrieve(offset = N)] mapping")
+    })?;
+
+    Ok(FieldMapping {
+        ident,
+        ty: field.ty.clone(),
+        offset,
+        len,
+        encoding,
+    })
+}
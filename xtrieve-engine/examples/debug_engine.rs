@@ -3,7 +3,6 @@
 use std::path::PathBuf;
 use xtrieve_engine::file_manager::open_files::{OpenFileTable, OpenMode};
 use xtrieve_engine::storage::btree::IndexNode;
-use xtrieve_engine::storage::key::KeySpec;
 
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
@@ -19,7 +18,7 @@ fn main() -> std::io::Result<()> {
 
     // Open file through engine
     let open_files = OpenFileTable::new();
-    let file = match open_files.open(&path, OpenMode::read_only()) {
+    let file = match open_files.open(&path, OpenMode::read_only(), None) {
         Ok(f) => f,
         Err(e) => {
             println!("Error opening file: {:?}", e);
@@ -39,7 +38,7 @@ fn main() -> std::io::Result<()> {
 
     if f.fcr.num_keys > 0 {
         let key_spec = &f.fcr.keys[0];
-        let root_page = f.fcr.index_roots.get(0).copied().unwrap_or(1);
+        let root_page = f.fcr.index_roots.first().copied().unwrap_or(1);
 
         println!("\nKey 0 spec:");
         println!("  Position: {}", key_spec.position);
@@ -104,7 +103,7 @@ fn main() -> std::io::Result<()> {
 
     drop(f);
     drop(file);
-    open_files.close(&path);
+    let _ = open_files.close(&path);
 
     Ok(())
 }
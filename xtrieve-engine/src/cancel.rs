@@ -0,0 +1,39 @@
+//! Cancellation of long-running operations
+//!
+//! A `CancelToken` goes with an operation into `Engine::execute_cancellable`.
+//! Cancelling it, from any thread, makes the operation give up with status
+//! 200 (`Cancelled`) the next time it checks: before it starts, and between
+//! the steps of operations that can take a while, such as the page copy of
+//! a Set Owner that encrypts. Checks come only where giving up leaves the
+//! file as it was, so a cancelled operation has no effect.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{BtrieveResult, StatusCode};
+
+/// Shared flag telling an operation to stop. Clones cancel together
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail with `Cancelled` if the token has been cancelled
+    pub fn check(&self) -> BtrieveResult<()> {
+        if self.is_cancelled() {
+            return Err(StatusCode::Cancelled.into());
+        }
+        Ok(())
+    }
+}
@@ -193,6 +193,10 @@ pub enum StatusCode {
     FileGone = 99,
     /// Server crash - locks lost
     ServerCrashLocksLost = 100,
+    /// Operation cancelled before it finished (Xtrieve)
+    Cancelled = 200,
+    /// Server too busy to take the request (Xtrieve)
+    ServerBusy = 201,
 
     // Status codes 101-171 are additional error conditions
     /// Unknown status code
@@ -295,6 +299,8 @@ impl StatusCode {
             97 => StatusCode::RecordPageConflict,
             99 => StatusCode::FileGone,
             100 => StatusCode::ServerCrashLocksLost,
+            200 => StatusCode::Cancelled,
+            201 => StatusCode::ServerBusy,
             _ => StatusCode::Unknown,
         }
     }
@@ -334,6 +340,8 @@ impl std::fmt::Display for StatusCode {
             StatusCode::RecordInUse => "Record in use",
             StatusCode::FileInUse => "File in use",
             StatusCode::WaitLockError => "Deadlock detected",
+            StatusCode::Cancelled => "Operation cancelled",
+            StatusCode::ServerBusy => "Server busy",
             _ => "Error",
         })
     }
@@ -353,6 +361,30 @@ pub enum BtrieveError {
 
     #[error("Internal error: {0}")]
     Internal(String),
+
+    /// A status that came back with the server's account of it
+    #[error("Btrieve status {status}: {detail}")]
+    Detailed { status: StatusCode, detail: ErrorDetail },
+}
+
+/// What went wrong behind a status, for clients that can show more than
+/// the number
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorDetail {
+    /// The error as the engine saw it, like "I/O error: disk full"
+    pub message: String,
+    /// The operation and file it happened on
+    pub context: String,
+}
+
+impl std::fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.context.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{} ({})", self.message, self.context)
+        }
+    }
 }
 
 impl BtrieveError {
@@ -363,6 +395,15 @@ impl BtrieveError {
             BtrieveError::Io(_) => StatusCode::IoError,
             BtrieveError::InvalidFormat(_) => StatusCode::NotBtrieveFile,
             BtrieveError::Internal(_) => StatusCode::UnrecoverableError,
+            BtrieveError::Detailed { status, .. } => *status,
+        }
+    }
+
+    /// The server's account of the error, when it sent one
+    pub fn detail(&self) -> Option<&ErrorDetail> {
+        match self {
+            BtrieveError::Detailed { detail, .. } => Some(detail),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,45 @@
+//! Cache coherence - how far cached pages of a file may be trusted
+//!
+//! Pages and records are cached on the assumption that this process is
+//! the only writer of its files. On a network share another engine may
+//! write a file this one has open, and the cache would hide its changes.
+//! Coherence is set per directory (`OpenFileTable::set_coherence`), like
+//! the opportunistic locks of SMB and NetWare clients:
+//! - `Cached`: trust the cache (the default)
+//! - `Validate`: before each operation, read page 0 back; if it changed
+//!   since this process last wrote or read it, reload the FCR and drop the
+//!   file's cached pages and records
+//! - `Uncached`: as `Validate`, and drop the cached pages and records
+//!   before every operation whether page 0 changed or not
+//!
+//! Under `Validate` and `Uncached` each write of the FCR bumps the usage
+//! count in its first four bytes, and the FCR is written with every change
+//! rather than deferred, so other engines see every change this one makes.
+//! Page writes aren't queued. Changes elsewhere that leave page 0 alone,
+//! like an Update in place, are only seen under `Uncached`.
+
+/// How far cached pages of a file may be trusted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Coherence {
+    #[default]
+    Cached,
+    Validate,
+    Uncached,
+}
+
+impl Coherence {
+    /// Coherence by name: cached, validate or none
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cached" => Some(Coherence::Cached),
+            "validate" => Some(Coherence::Validate),
+            "none" | "uncached" => Some(Coherence::Uncached),
+            _ => None,
+        }
+    }
+
+    /// Whether page 0 is checked before each operation
+    pub fn validates(self) -> bool {
+        self != Coherence::Cached
+    }
+}
@@ -6,10 +6,32 @@
 //! - Current key number
 //! - Navigation state
 
+use std::ops::Range;
 use std::path::PathBuf;
 
+use crate::error::{BtrieveError, BtrieveResult, StatusCode};
 use crate::storage::record::RecordAddress;
 
+/// Position block byte holding the cursor id
+pub const CURSOR_ID: usize = 19;
+/// Position block byte holding the length of the current key value
+pub const KEY_LEN: usize = 20;
+/// Position block bytes holding the current key value
+pub const KEY_VALUE: Range<usize> = 21..64;
+/// Longest key value a position block can hold
+pub const MAX_KEY_LEN: usize = KEY_VALUE.end - KEY_VALUE.start;
+/// Position block bytes holding the file path
+pub const FILE_PATH: Range<usize> = 64..114;
+/// Longest file path a position block can hold
+pub const MAX_PATH_LEN: usize = FILE_PATH.end - FILE_PATH.start;
+/// Position block bytes holding the check stamped by Open:
+/// [magic:1][version:1][nonce:4]
+pub const CHECK: Range<usize> = 114..120;
+/// First check byte of a position block made by this engine
+pub const MAGIC: u8 = 0xB7;
+/// Position block layout revision
+pub const FORMAT_VERSION: u8 = 1;
+
 /// Cursor state flags
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CursorState {
@@ -21,7 +43,8 @@ pub enum CursorState {
     AtEnd,
     /// Cursor is at beginning of file (before first record)
     AtBeginning,
-    /// Record was deleted, cursor needs repositioning
+    /// Record was deleted; the cursor sits between the keys either side
+    /// of it, so Get Next/Previous carry on from there
     Deleted,
 }
 
@@ -40,8 +63,9 @@ pub struct Cursor {
     pub key_value: Vec<u8>,
     /// Current record data (cached)
     pub record_data: Vec<u8>,
-    /// Index position within leaf node
-    pub leaf_index: usize,
+    /// Duplicate sequence of the current index entry, which places it
+    /// among entries with the same key
+    pub dup_sequence: u32,
     /// Current leaf page
     pub leaf_page: u32,
     /// Physical position (for step operations)
@@ -58,7 +82,7 @@ impl Cursor {
             key_number,
             key_value: Vec::new(),
             record_data: Vec::new(),
-            leaf_index: 0,
+            dup_sequence: 0,
             leaf_page: 0,
             physical_position: None,
         }
@@ -69,7 +93,14 @@ impl Cursor {
         matches!(self.state, CursorState::Positioned)
     }
 
-    /// Position cursor on a record
+    /// Check if cursor has a place to move on from: a record, or the gap
+    /// a deleted record left between its neighbours
+    pub fn has_currency(&self) -> bool {
+        matches!(self.state, CursorState::Positioned | CursorState::Deleted)
+    }
+
+    /// Position cursor on a record. Its address is also the physical
+    /// position Step operations go on from
     pub fn position(
         &mut self,
         address: RecordAddress,
@@ -78,22 +109,24 @@ impl Cursor {
     ) {
         self.state = CursorState::Positioned;
         self.record_address = Some(address);
+        self.physical_position = Some(address);
         self.key_value = key_value;
         self.record_data = record_data;
     }
 
-    /// Position cursor with leaf info (for efficient Next/Prev)
+    /// Position cursor on an index entry, with the leaf it was found in
+    /// and its duplicate sequence
     pub fn position_with_leaf(
         &mut self,
         address: RecordAddress,
         key_value: Vec<u8>,
         record_data: Vec<u8>,
         leaf_page: u32,
-        leaf_index: usize,
+        dup_sequence: u32,
     ) {
         self.position(address, key_value, record_data);
         self.leaf_page = leaf_page;
-        self.leaf_index = leaf_index;
+        self.dup_sequence = dup_sequence;
     }
 
     /// Mark cursor as at end of file
@@ -108,7 +141,9 @@ impl Cursor {
         self.record_address = None;
     }
 
-    /// Invalidate cursor (e.g., after delete)
+    /// Invalidate cursor after its record is deleted, keeping the key,
+    /// duplicate sequence and address as the place between keys to move
+    /// on from
     pub fn invalidate(&mut self) {
         self.state = CursorState::Deleted;
     }
@@ -117,9 +152,10 @@ impl Cursor {
     pub fn reset(&mut self) {
         self.state = CursorState::Unpositioned;
         self.record_address = None;
+        self.physical_position = None;
         self.key_value.clear();
         self.record_data.clear();
-        self.leaf_index = 0;
+        self.dup_sequence = 0;
         self.leaf_page = 0;
     }
 
@@ -178,7 +214,7 @@ impl PositionBlock {
         block.data[0] = cursor.state as u8;
 
         // Store key number
-        block.data[1..5].copy_from_slice(&(cursor.key_number as i32).to_le_bytes());
+        block.data[1..5].copy_from_slice(&cursor.key_number.to_le_bytes());
 
         // Store record address if positioned
         if let Some(addr) = cursor.record_address {
@@ -186,34 +222,34 @@ impl PositionBlock {
             block.data[9..11].copy_from_slice(&addr.slot.to_le_bytes());
         }
 
-        // Store leaf position
+        // Store leaf page and duplicate sequence
         block.data[11..15].copy_from_slice(&cursor.leaf_page.to_le_bytes());
-        block.data[15..19].copy_from_slice(&(cursor.leaf_index as u32).to_le_bytes());
+        block.data[15..19].copy_from_slice(&cursor.dup_sequence.to_le_bytes());
 
         // Store key value (truncated if too long) - but leave room for file path at 64
-        let key_len = cursor.key_value.len().min(43); // Max 43 bytes for key (21..64)
-        block.data[20] = key_len as u8;
-        if key_len > 0 {
-            block.data[21..21 + key_len].copy_from_slice(&cursor.key_value[..key_len]);
-        }
+        let key_len = cursor.key_value.len().min(MAX_KEY_LEN);
+        block.data[KEY_LEN] = key_len as u8;
+        block.data[KEY_VALUE.start..KEY_VALUE.start + key_len]
+            .copy_from_slice(&cursor.key_value[..key_len]);
 
-        // Store file path at offset 64 (up to 64 bytes)
-        let path_str = cursor.file_path.to_string_lossy();
-        let path_bytes = path_str.as_bytes();
-        let path_len = path_bytes.len().min(64);
-        block.data[64..64 + path_len].copy_from_slice(&path_bytes[..path_len]);
+        // Store file path at offset 64
+        block.set_file_path(&cursor.file_path.to_string_lossy());
 
         block
     }
 
     /// Restore cursor state from position block
-    pub fn to_cursor(&self, file_path: PathBuf) -> Cursor {
+    ///
+    /// The block comes back from the client, so a state or key length this
+    /// engine never writes gives status 8 rather than being trusted
+    pub fn to_cursor(&self, file_path: PathBuf) -> BtrieveResult<Cursor> {
         let state = match self.data[0] {
+            0 => CursorState::Unpositioned,
             1 => CursorState::Positioned,
             2 => CursorState::AtEnd,
             3 => CursorState::AtBeginning,
             4 => CursorState::Deleted,
-            _ => CursorState::Unpositioned,
+            _ => return Err(BtrieveError::Status(StatusCode::InvalidPositioning)),
         };
 
         let key_number = i32::from_le_bytes([
@@ -223,7 +259,7 @@ impl PositionBlock {
             self.data[4],
         ]);
 
-        let record_address = if state == CursorState::Positioned {
+        let record_address = if matches!(state, CursorState::Positioned | CursorState::Deleted) {
             let page = u32::from_le_bytes([
                 self.data[5],
                 self.data[6],
@@ -243,31 +279,30 @@ impl PositionBlock {
             self.data[14],
         ]);
 
-        let leaf_index = u32::from_le_bytes([
+        let dup_sequence = u32::from_le_bytes([
             self.data[15],
             self.data[16],
             self.data[17],
             self.data[18],
-        ]) as usize;
+        ]);
 
-        let key_len = self.data[20] as usize;
-        let key_value = if key_len > 0 {
-            self.data[21..21 + key_len].to_vec()
-        } else {
-            Vec::new()
-        };
+        let key_len = self.data[KEY_LEN] as usize;
+        if key_len > MAX_KEY_LEN {
+            return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
+        }
+        let key_value = self.data[KEY_VALUE.start..KEY_VALUE.start + key_len].to_vec();
 
-        Cursor {
+        Ok(Cursor {
             file_path,
             state,
             record_address,
             key_number,
             key_value,
             record_data: Vec::new(), // Not stored in position block
-            leaf_index,
+            dup_sequence,
             leaf_page,
-            physical_position: None,
-        }
+            physical_position: record_address,
+        })
     }
 
     /// Get raw bytes
@@ -283,6 +318,62 @@ impl PositionBlock {
         block
     }
 
+    /// Store the file path at offset 64. Returns false, storing nothing,
+    /// if it doesn't fit
+    pub fn set_file_path(&mut self, path: &str) -> bool {
+        let bytes = path.as_bytes();
+        if bytes.len() > MAX_PATH_LEN {
+            return false;
+        }
+        self.data[FILE_PATH].fill(0);
+        self.data[FILE_PATH.start..FILE_PATH.start + bytes.len()].copy_from_slice(bytes);
+        true
+    }
+
+    /// Get the file path stored at offset 64
+    pub fn file_path(&self) -> Option<PathBuf> {
+        let path_area = &self.data[FILE_PATH];
+        let end = path_area.iter().position(|&b| b == 0).unwrap_or(path_area.len());
+        if end == 0 {
+            return None;
+        }
+        Some(PathBuf::from(String::from_utf8_lossy(&path_area[..end]).as_ref()))
+    }
+
+    /// Set the id telling apart opens of one file by the same session
+    pub fn set_cursor_id(&mut self, cursor_id: u8) {
+        self.data[CURSOR_ID] = cursor_id;
+    }
+
+    /// Get the cursor id, 0 if the block has none
+    pub fn get_cursor_id(&self) -> u8 {
+        self.data[CURSOR_ID]
+    }
+
+    /// Stamp the check an Open hands out with the block
+    pub fn set_check(&mut self, nonce: u32) {
+        self.data[CHECK.start] = MAGIC;
+        self.data[CHECK.start + 1] = FORMAT_VERSION;
+        self.data[CHECK.start + 2..CHECK.end].copy_from_slice(&nonce.to_le_bytes());
+    }
+
+    /// The nonce of a block made by this engine in the current layout,
+    /// `None` for a hand-made or foreign block
+    pub fn check_nonce(&self) -> Option<u32> {
+        let check = &self.data[CHECK];
+        if check[0] != MAGIC || check[1] != FORMAT_VERSION {
+            return None;
+        }
+        Some(u32::from_le_bytes([check[2], check[3], check[4], check[5]]))
+    }
+
+    /// Carry the identity of an open (cursor id and check) over from the
+    /// block the client sent, onto a block rebuilt from the cursor
+    pub fn keep_identity(&mut self, sent: &PositionBlock) {
+        self.data[CURSOR_ID] = sent.data[CURSOR_ID];
+        self.data[CHECK].copy_from_slice(&sent.data[CHECK]);
+    }
+
     /// Set session/client ID in position block (bytes 120-127)
     pub fn set_session_id(&mut self, session_id: u64) {
         self.data[120..128].copy_from_slice(&session_id.to_le_bytes());
@@ -317,25 +408,60 @@ mod tests {
     }
 
     #[test]
-    fn test_position_block_roundtrip() {
-        let mut cursor = Cursor::new(PathBuf::from("test.dat"), 2);
-        let addr = RecordAddress::new(100, 5);
-        cursor.position_with_leaf(
-            addr,
-            b"mykey".to_vec(),
-            b"record data".to_vec(),
-            50,
-            3,
-        );
-
-        let block = PositionBlock::from_cursor(&cursor);
-        let restored = block.to_cursor(PathBuf::from("test.dat"));
-
-        assert!(restored.is_positioned());
-        assert_eq!(restored.key_number, 2);
+    fn test_deleted_cursor_keeps_its_place() {
+        let mut cursor = Cursor::new(PathBuf::from("test.dat"), 1);
+        let addr = RecordAddress::new(2048, 0);
+        cursor.position(addr, b"SMITH".to_vec(), b"record data".to_vec());
+        cursor.dup_sequence = 3;
+        cursor.invalidate();
+
+        let restored = PositionBlock::from_cursor(&cursor)
+            .to_cursor(PathBuf::from("test.dat"))
+            .unwrap();
+        assert_eq!(restored.state, CursorState::Deleted);
+        assert!(!restored.is_positioned());
+        assert!(restored.has_currency());
+        assert_eq!(restored.current_record(), None);
         assert_eq!(restored.record_address, Some(addr));
-        assert_eq!(restored.leaf_page, 50);
-        assert_eq!(restored.leaf_index, 3);
-        assert_eq!(restored.key_value, b"mykey".to_vec());
+        assert_eq!(restored.key_value, b"SMITH".to_vec());
+        assert_eq!(restored.dup_sequence, 3);
+
+        cursor.reset();
+        assert!(!cursor.has_currency());
+    }
+
+    #[test]
+    fn test_position_block_file_path() {
+        let cursor = Cursor::new(PathBuf::from("/data/test.dat"), 0);
+        let mut block = PositionBlock::from_cursor(&cursor);
+        block.set_session_id(42);
+
+        assert_eq!(block.file_path(), Some(PathBuf::from("/data/test.dat")));
+        assert_eq!(PositionBlock::new().file_path(), None);
+
+        // A path that fills the area doesn't run into the check
+        let long = "/".repeat(MAX_PATH_LEN);
+        block.set_check(7);
+        assert!(block.set_file_path(&long));
+        assert_eq!(block.file_path(), Some(PathBuf::from(&long)));
+        assert_eq!(block.check_nonce(), Some(7));
+        assert!(!block.set_file_path(&format!("{}x", long)));
+    }
+
+    #[test]
+    fn test_position_block_check() {
+        let mut sent = PositionBlock::new();
+        assert_eq!(sent.check_nonce(), None);
+        sent.set_check(0xDEAD_BEEF);
+        sent.set_cursor_id(2);
+        assert_eq!(sent.check_nonce(), Some(0xDEAD_BEEF));
+
+        let mut rebuilt = PositionBlock::from_cursor(&Cursor::new(PathBuf::from("/data/test.dat"), 0));
+        rebuilt.keep_identity(&sent);
+        assert_eq!((rebuilt.check_nonce(), rebuilt.get_cursor_id()), (Some(0xDEAD_BEEF), 2));
+
+        // Another layout revision is not trusted
+        sent.data[CHECK.start + 1] = FORMAT_VERSION + 1;
+        assert_eq!(sent.check_nonce(), None);
     }
 }
@@ -0,0 +1,114 @@
+//! Group commit - one fsync for the transactions ending together
+//!
+//! Each End Transaction has to sync the file before its pre-images go.
+//! Sessions ending at about the same time share a sync: the first to come
+//! waits out the commit window, then syncs for everyone who has arrived
+//! by then, and those arriving during the sync wait for the next one.
+
+use parking_lot::{Condvar, Mutex};
+use std::io;
+use std::time::Duration;
+
+#[derive(Default)]
+struct State {
+    /// Tickets handed out, one per commit asking for a sync
+    requested: u64,
+    /// Tickets the last good sync covered
+    synced: u64,
+    /// A sync is under way
+    syncing: bool,
+}
+
+/// Shares syncs of one file between concurrent commits
+#[derive(Default)]
+pub struct GroupCommit {
+    state: Mutex<State>,
+    done: Condvar,
+}
+
+impl GroupCommit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Have `sync` run for this commit, or wait for a run that covers it.
+    /// The session leading a sync first waits `window` for others to
+    /// join. A failed sync covers no one: the sessions that were waiting
+    /// on it try again themselves
+    pub fn sync(&self, window: Duration, sync: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+        let mut state = self.state.lock();
+        state.requested += 1;
+        let ticket = state.requested;
+        while state.syncing {
+            self.done.wait(&mut state);
+            if state.synced >= ticket {
+                return Ok(());
+            }
+        }
+        state.syncing = true;
+        drop(state);
+
+        if !window.is_zero() {
+            std::thread::sleep(window);
+        }
+        let batch = self.state.lock().requested;
+        let result = sync();
+
+        let mut state = self.state.lock();
+        state.syncing = false;
+        if result.is_ok() {
+            state.synced = state.synced.max(batch);
+        }
+        self.done.notify_all();
+        result
+    }
+
+    /// Commits that asked for a sync, and those a sync covered
+    #[cfg(test)]
+    fn tickets(&self) -> (u64, u64) {
+        let state = self.state.lock();
+        (state.requested, state.synced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_commits_share_syncs() {
+        let group = Arc::new(GroupCommit::new());
+        let syncs = Arc::new(AtomicU32::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (group, syncs) = (group.clone(), syncs.clone());
+                std::thread::spawn(move || {
+                    group.sync(Duration::from_millis(20), || {
+                        syncs.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(20));
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+
+        assert!(syncs.load(Ordering::SeqCst) < 8);
+        assert_eq!(group.tickets(), (8, 8));
+    }
+
+    #[test]
+    fn test_failed_sync_covers_no_one() {
+        let group = GroupCommit::new();
+        let failed = group.sync(Duration::ZERO, || Err(io::Error::other("disk gone")));
+        assert!(failed.is_err());
+        assert_eq!(group.tickets(), (1, 0));
+
+        group.sync(Duration::ZERO, || Ok(())).unwrap();
+        assert_eq!(group.tickets(), (2, 2));
+    }
+}
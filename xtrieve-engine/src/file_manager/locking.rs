@@ -1,13 +1,17 @@
 //! Locking mechanisms for concurrent access to Btrieve files
 //!
 //! Supports file-level and record-level locking with Btrieve's lock modes.
+//! Keeps count of the conflicts between sessions, and who is waiting on
+//! whom, for the lock report (see `LockReport`).
 
 use parking_lot::{Mutex, RwLock};
 use std::collections::{HashMap, HashSet};
+use std::io;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::error::{BtrieveError, BtrieveResult, StatusCode};
+use crate::error::{BtrieveResult, StatusCode};
+use crate::metrics::Metrics;
 use crate::storage::record::RecordAddress;
 
 /// Lock types matching Btrieve's lock modes
@@ -68,10 +72,29 @@ struct RecordLock {
     session: SessionId,
     lock_type: LockType,
     acquired_at: Instant,
+    /// Requests the lock has turned away, and the last session refused
+    refused: u32,
+    last_refused: SessionId,
+}
+
+impl RecordLock {
+    fn refuse(&mut self, session: SessionId) {
+        self.refused = self.refused.saturating_add(1);
+        self.last_refused = session;
+    }
+}
+
+/// A session waiting in `lock_record`
+#[derive(Debug, Clone)]
+struct Waiting {
+    file: String,
+    address: RecordAddress,
+    blocker: SessionId,
+    since: Instant,
 }
 
 /// File lock state
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct FileLockState {
     /// Exclusive file lock holder (if any)
     exclusive_holder: Option<SessionId>,
@@ -81,22 +104,18 @@ struct FileLockState {
     record_locks: HashMap<RecordAddress, RecordLock>,
 }
 
-impl Default for FileLockState {
-    fn default() -> Self {
-        FileLockState {
-            exclusive_holder: None,
-            shared_holders: HashSet::new(),
-            record_locks: HashMap::new(),
-        }
-    }
-}
-
 /// Lock manager for Btrieve files
 pub struct LockManager {
     /// Lock state per file
     files: RwLock<HashMap<String, Arc<Mutex<FileLockState>>>>,
     /// Lock timeout for waiting locks
     timeout: Duration,
+    /// Sessions waiting for a record lock
+    waiting: Mutex<HashMap<SessionId, Waiting>>,
+    /// Conflict and wait counts since the engine started
+    stats: Mutex<LockStats>,
+    /// Told of each wait as it ends
+    metrics: RwLock<Vec<Arc<dyn Metrics>>>,
 }
 
 impl LockManager {
@@ -105,6 +124,9 @@ impl LockManager {
         LockManager {
             files: RwLock::new(HashMap::new()),
             timeout,
+            waiting: Mutex::new(HashMap::new()),
+            stats: Mutex::new(LockStats::default()),
+            metrics: RwLock::new(Vec::new()),
         }
     }
 
@@ -189,20 +211,35 @@ impl LockManager {
             let mut lock_state = state.lock();
 
             // Check for existing lock
-            if let Some(existing) = lock_state.record_locks.get(&address) {
+            if let Some(existing) = lock_state.record_locks.get_mut(&address) {
                 if existing.session != session {
                     // Conflict with another session
+                    let blocker = existing.session;
                     if !lock_type.waits() {
+                        existing.refuse(session);
+                        self.stats.lock().conflicts += 1;
                         return Err(StatusCode::RecordInUse.into());
                     }
 
                     // Check timeout
                     if Instant::now() >= deadline {
+                        existing.refuse(session);
+                        self.end_wait(session, true);
                         return Err(StatusCode::WaitLockError.into());
                     }
 
                     // Drop lock and wait
                     drop(lock_state);
+                    self.waiting
+                        .lock()
+                        .entry(session)
+                        .or_insert_with(|| Waiting {
+                            file: file_path.to_string(),
+                            address,
+                            blocker,
+                            since: Instant::now(),
+                        })
+                        .blocker = blocker;
                     std::thread::sleep(Duration::from_millis(10));
                     continue;
                 } else if !lock_type.is_multi() {
@@ -217,13 +254,44 @@ impl LockManager {
                     session,
                     lock_type,
                     acquired_at: Instant::now(),
+                    refused: 0,
+                    last_refused: 0,
                 },
             );
+            drop(lock_state);
+            self.end_wait(session, false);
 
             return Ok(());
         }
     }
 
+    /// Tell `metrics` of each wait as it ends
+    pub fn add_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.metrics.write().push(metrics);
+    }
+
+    /// Conflict and wait counts since the engine started
+    pub fn stats(&self) -> LockStats {
+        self.stats.lock().clone()
+    }
+
+    /// Count the wait of a session that got its lock or gave up
+    fn end_wait(&self, session: SessionId, timed_out: bool) {
+        let Some(waiting) = self.waiting.lock().remove(&session) else {
+            return;
+        };
+        let waited = waiting.since.elapsed();
+        let mut stats = self.stats.lock();
+        stats.waits += 1;
+        stats.timeouts += timed_out as u64;
+        stats.total_wait += waited;
+        stats.longest_wait = stats.longest_wait.max(waited);
+        drop(stats);
+        for metrics in self.metrics.read().iter() {
+            metrics.lock_wait(waited, timed_out);
+        }
+    }
+
     /// Release a record lock
     pub fn unlock_record(
         &self,
@@ -241,6 +309,17 @@ impl LockManager {
         }
     }
 
+    /// Release a session's single record locks on a file, or its multiple
+    /// record locks
+    pub fn unlock_records(&self, file_path: &str, session: SessionId, multi: bool) {
+        let state = self.get_file_state(file_path);
+        let mut lock_state = state.lock();
+
+        lock_state
+            .record_locks
+            .retain(|_, lock| lock.session != session || lock.lock_type.is_multi() != multi);
+    }
+
     /// Release all record locks for a session
     pub fn unlock_all_records(&self, file_path: &str, session: SessionId) {
         let state = self.get_file_state(file_path);
@@ -267,7 +346,8 @@ impl LockManager {
         }
     }
 
-    /// Check if a record is locked by another session
+    /// Check if a record is locked by another session. Operations turn the
+    /// session away if it is, so this counts as a conflict in the report
     pub fn is_record_locked(
         &self,
         file_path: &str,
@@ -275,14 +355,66 @@ impl LockManager {
         session: SessionId,
     ) -> bool {
         let state = self.get_file_state(file_path);
-        let lock_state = state.lock();
+        let mut lock_state = state.lock();
 
-        if let Some(lock) = lock_state.record_locks.get(&address) {
-            return lock.session != session;
+        if let Some(lock) = lock_state.record_locks.get_mut(&address) {
+            if lock.session != session {
+                lock.refuse(session);
+                self.stats.lock().conflicts += 1;
+                return true;
+            }
         }
         false
     }
 
+    /// Check if a record is locked by any session
+    pub fn is_record_locked_by_any(&self, file_path: &str, address: RecordAddress) -> bool {
+        let state = self.get_file_state(file_path);
+        let lock_state = state.lock();
+        lock_state.record_locks.contains_key(&address)
+    }
+
+    /// Who is waiting on whom, the record locks that have turned sessions
+    /// away, and conflict counts since the engine started
+    pub fn report(&self) -> LockReport {
+        let waiters: Vec<LockWaiter> = self
+            .waiting
+            .lock()
+            .iter()
+            .map(|(&session, w)| LockWaiter {
+                session,
+                blocker: w.blocker,
+                file: w.file.clone(),
+                address: w.address,
+                waited: w.since.elapsed(),
+            })
+            .collect();
+
+        let mut blockers = Vec::new();
+        for (file, state) in self.files.read().iter() {
+            for (&address, lock) in state.lock().record_locks.iter() {
+                let waited_on = waiters
+                    .iter()
+                    .any(|w| w.blocker == lock.session && w.address == address && &w.file == file);
+                if lock.refused > 0 || waited_on {
+                    blockers.push(LockBlocker {
+                        session: lock.session,
+                        file: file.clone(),
+                        address,
+                        held: lock.acquired_at.elapsed(),
+                        refused: lock.refused,
+                        last_refused: lock.last_refused,
+                    });
+                }
+            }
+        }
+
+        let mut report = LockReport { stats: self.stats.lock().clone(), waiters, blockers };
+        report.waiters.sort_by_key(|w| w.session);
+        report.blockers.sort_by(|a, b| (&a.file, a.session).cmp(&(&b.file, b.session)));
+        report
+    }
+
     /// Clean up lock state for a closed file
     pub fn cleanup_file(&self, file_path: &str) {
         let mut files = self.files.write();
@@ -290,6 +422,145 @@ impl LockManager {
     }
 }
 
+/// Conflict and wait counts since the engine started
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// Requests turned away with Record In Use
+    pub conflicts: u64,
+    /// Waits for a record lock, ended
+    pub waits: u64,
+    /// Waits that gave up
+    pub timeouts: u64,
+    pub total_wait: Duration,
+    pub longest_wait: Duration,
+}
+
+/// A session waiting for a record lock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockWaiter {
+    pub session: SessionId,
+    /// Session holding the lock
+    pub blocker: SessionId,
+    pub file: String,
+    pub address: RecordAddress,
+    pub waited: Duration,
+}
+
+/// A record lock that has turned sessions away or is waited on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockBlocker {
+    pub session: SessionId,
+    pub file: String,
+    pub address: RecordAddress,
+    pub held: Duration,
+    /// Requests turned away while it was held
+    pub refused: u32,
+    pub last_refused: SessionId,
+}
+
+/// Lock diagnostics returned in the data buffer of LockReport (90)
+///
+/// Layout, durations in microseconds:
+///   [conflicts:8][waits:8][timeouts:8][total_wait:8][longest_wait:8]
+///   [waiters:2] { [session:8][blocker:8][page:4][slot:2][waited:8][path_len:2][path:N] }
+///   [blockers:2] { [session:8][page:4][slot:2][held:8][refused:4][last_refused:8][path_len:2][path:N] }
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockReport {
+    pub stats: LockStats,
+    pub waiters: Vec<LockWaiter>,
+    pub blockers: Vec<LockBlocker>,
+}
+
+impl LockReport {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let micros = |d: Duration| (d.as_micros() as u64).to_le_bytes();
+        let path = |buf: &mut Vec<u8>, path: &str| {
+            let path = &path.as_bytes()[..path.len().min(u16::MAX as usize)];
+            buf.extend_from_slice(&(path.len() as u16).to_le_bytes());
+            buf.extend_from_slice(path);
+        };
+
+        buf.extend_from_slice(&self.stats.conflicts.to_le_bytes());
+        buf.extend_from_slice(&self.stats.waits.to_le_bytes());
+        buf.extend_from_slice(&self.stats.timeouts.to_le_bytes());
+        buf.extend_from_slice(&micros(self.stats.total_wait));
+        buf.extend_from_slice(&micros(self.stats.longest_wait));
+
+        let waiters = &self.waiters[..self.waiters.len().min(u16::MAX as usize)];
+        buf.extend_from_slice(&(waiters.len() as u16).to_le_bytes());
+        for w in waiters {
+            buf.extend_from_slice(&w.session.to_le_bytes());
+            buf.extend_from_slice(&w.blocker.to_le_bytes());
+            buf.extend_from_slice(&w.address.to_bytes());
+            buf.extend_from_slice(&micros(w.waited));
+            path(&mut buf, &w.file);
+        }
+
+        let blockers = &self.blockers[..self.blockers.len().min(u16::MAX as usize)];
+        buf.extend_from_slice(&(blockers.len() as u16).to_le_bytes());
+        for b in blockers {
+            buf.extend_from_slice(&b.session.to_le_bytes());
+            buf.extend_from_slice(&b.address.to_bytes());
+            buf.extend_from_slice(&micros(b.held));
+            buf.extend_from_slice(&b.refused.to_le_bytes());
+            buf.extend_from_slice(&b.last_refused.to_le_bytes());
+            path(&mut buf, &b.file);
+        }
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut rest = data;
+        let mut take = |n: usize| -> io::Result<&[u8]> {
+            if rest.len() < n {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Lock report truncated"));
+            }
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            Ok(head)
+        };
+        macro_rules! int {
+            ($t:ty) => {
+                <$t>::from_le_bytes(take(std::mem::size_of::<$t>())?.try_into().unwrap())
+            };
+        }
+
+        let stats = LockStats {
+            conflicts: int!(u64),
+            waits: int!(u64),
+            timeouts: int!(u64),
+            total_wait: Duration::from_micros(int!(u64)),
+            longest_wait: Duration::from_micros(int!(u64)),
+        };
+
+        let mut waiters = Vec::new();
+        for _ in 0..int!(u16) {
+            let session = int!(u64);
+            let blocker = int!(u64);
+            let address = RecordAddress::new(int!(u32), int!(u16));
+            let waited = Duration::from_micros(int!(u64));
+            let len = int!(u16) as usize;
+            let file = String::from_utf8_lossy(take(len)?).to_string();
+            waiters.push(LockWaiter { session, blocker, file, address, waited });
+        }
+
+        let mut blockers = Vec::new();
+        for _ in 0..int!(u16) {
+            let session = int!(u64);
+            let address = RecordAddress::new(int!(u32), int!(u16));
+            let held = Duration::from_micros(int!(u64));
+            let refused = int!(u32);
+            let last_refused = int!(u64);
+            let len = int!(u16) as usize;
+            let file = String::from_utf8_lossy(take(len)?).to_string();
+            blockers.push(LockBlocker { session, file, address, held, refused, last_refused });
+        }
+
+        Ok(LockReport { stats, waiters, blockers })
+    }
+}
+
 impl Default for LockManager {
     fn default() -> Self {
         Self::new(Duration::from_secs(30))
@@ -349,4 +620,34 @@ mod tests {
             .lock_record("test.dat", addr, 2, LockType::SingleNoWait)
             .unwrap();
     }
+
+    #[test]
+    fn test_lock_report() {
+        let manager = Arc::new(LockManager::default());
+        let addr = RecordAddress::new(1, 0);
+        manager.lock_record("test.dat", addr, 1, LockType::SingleNoWait).unwrap();
+        assert!(manager.lock_record("test.dat", addr, 2, LockType::SingleNoWait).is_err());
+
+        // Session 3 waits on session 1
+        let waiter = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.lock_record("test.dat", addr, 3, LockType::SingleWait))
+        };
+        while manager.report().waiters.is_empty() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let report = manager.report();
+        assert_eq!(report.stats.conflicts, 1);
+        assert_eq!((report.waiters[0].session, report.waiters[0].blocker), (3, 1));
+        assert_eq!(report.blockers.len(), 1);
+        assert_eq!((report.blockers[0].session, report.blockers[0].refused, report.blockers[0].last_refused), (1, 1, 2));
+        let bytes = report.to_bytes();
+        assert_eq!(LockReport::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+
+        manager.unlock_record("test.dat", addr, 1);
+        waiter.join().unwrap().unwrap();
+        let report = manager.report();
+        assert!(report.waiters.is_empty() && report.blockers.is_empty());
+        assert_eq!((report.stats.waits, report.stats.timeouts), (1, 0));
+    }
 }
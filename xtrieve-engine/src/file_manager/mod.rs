@@ -6,8 +6,18 @@ pub mod open_files;
 pub mod page_cache;
 pub mod locking;
 pub mod cursor;
+pub mod sessions;
+pub mod group_commit;
+pub mod record_cache;
+pub mod write_queue;
+pub mod coherence;
 
 pub use open_files::{OpenFile, OpenFileTable};
 pub use page_cache::PageCache;
 pub use locking::{LockManager, LockType};
 pub use cursor::{Cursor, CursorState};
+pub use sessions::SessionRegistry;
+pub use group_commit::GroupCommit;
+pub use record_cache::{RecordCache, RecordCacheStats};
+pub use write_queue::WriteQueue;
+pub use coherence::Coherence;
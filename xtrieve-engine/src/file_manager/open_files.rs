@@ -1,21 +1,43 @@
 //! Open file table - manages Btrieve files that are currently open
 //!
 //! Each open file has associated metadata, page cache entries, and cursors.
-//! Supports pre-imaging for transaction rollback.
-
-use parking_lot::RwLock;
+//! Supports pre-imaging for transaction rollback, pages encrypted at rest
+//! (see `storage::crypt`), compressed pages (see `storage::compress`),
+//! recycle bins of deleted records (see `storage::recycle`), roll-forward
+//! logs of committed changes (see `storage::rollfwd`), index pages kept
+//! in a file of their own (see `storage::files`) and page writes queued
+//! to go out in page order (see `write_queue`), lock files claiming
+//! the files for this process (see `storage::marker`) and page 0 checked
+//! for other engines' writes (see `coherence`).
+
+use parking_lot::{Mutex, RwLock};
 use std::collections::{HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::error::{BtrieveError, BtrieveResult, StatusCode};
+use crate::cancel::CancelToken;
+use crate::error::{BtrieveError, BtrieveResult, ErrorDetail, StatusCode};
+use crate::storage::compress::{self, Compression, FrameIndex, FRAME_HEADER};
+use crate::storage::crypt::{self, Encryption, PageCipher, KEY_LEN, SEAL_OVERHEAD};
 use crate::storage::fcr::FileControlRecord;
+use crate::storage::files::{self, IndexFile, Layout};
+use crate::storage::marker::{Marker, MarkerError};
 use crate::storage::page::Page;
+use crate::storage::record::RecordAddress;
+use crate::storage::recycle::RecycleBin;
+use crate::storage::rollfwd::RollForwardLog;
+
+use super::coherence::Coherence;
+use super::group_commit::GroupCommit;
+use super::record_cache::{RecordCache, RecordCacheStats, RECORD_CACHE_ENTRIES};
+use super::write_queue::WriteQueue;
 
 /// Open mode flags (match Btrieve)
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OpenMode {
     /// Read-only mode
     pub read_only: bool,
@@ -23,14 +45,21 @@ pub struct OpenMode {
     pub exclusive: bool,
     /// Accelerated mode (fewer flushes)
     pub accelerated: bool,
+    /// Verify mode (every page read back after it is written)
+    pub verify: bool,
 }
 
 impl OpenMode {
+    /// Decode an Open's mode: 0 normal, -1 accelerated, -2 read-only,
+    /// -3 verify, -4 exclusive. Other values open normally
     pub fn from_raw(mode: i32) -> Self {
-        OpenMode {
-            read_only: (mode & 0x01) != 0,        // -1 = normal, -2 = read-only
-            exclusive: (mode & 0x04) != 0,       // -4 = exclusive
-            accelerated: (mode & 0x10) != 0,     // Accelerated mode
+        let normal = Self::read_write();
+        match mode {
+            -1 => OpenMode { accelerated: true, ..normal },
+            -2 => OpenMode { read_only: true, ..normal },
+            -3 => OpenMode { verify: true, ..normal },
+            -4 => OpenMode { exclusive: true, ..normal },
+            _ => normal,
         }
     }
 
@@ -39,18 +68,21 @@ impl OpenMode {
             read_only: false,
             exclusive: false,
             accelerated: false,
+            verify: false,
         }
     }
 
     pub fn read_only() -> Self {
         OpenMode {
             read_only: true,
-            exclusive: false,
-            accelerated: false,
+            ..Self::read_write()
         }
     }
 }
 
+/// Counter changes kept in memory before the FCR is written out anyway
+pub const FCR_FLUSH_INTERVAL: u32 = 64;
+
 /// Per-session pre-image for transaction rollback (Btrieve 5.1 style)
 /// Stores OLD page data before modification - for restore on abort
 struct SessionPreImage {
@@ -75,6 +107,26 @@ pub struct OpenFile {
     /// Per-session pre-image files for transaction rollback
     /// Key: session_id, Value: pre-image file storing OLD data
     session_preimages: RwLock<HashMap<u64, SessionPreImage>>,
+    /// Seals pages on write and opens them on read, if the file is encrypted
+    cipher: Option<PageCipher>,
+    /// Where each page's latest frame is, if the file is compressed
+    frames: Option<Mutex<FrameIndex>>,
+    /// The index files, if index pages are kept apart from the .DAT
+    index: Option<Mutex<IndexFile>>,
+    /// Counter changes to the FCR not yet written to page 0
+    fcr_pending: AtomicU32,
+    /// Syncs shared by the transactions ending together
+    commits: GroupCommit,
+    /// Records recently read by address
+    records: Mutex<RecordCache>,
+    /// Page writes not yet in the file
+    writes: Mutex<WriteQueue>,
+    /// The file's lock file, held while it is open, if the table keeps them
+    marker: Option<Marker>,
+    /// How far cached pages may be trusted (see `coherence`)
+    coherence: Coherence,
+    /// Page 0 as this process last wrote or read it
+    fcr_seen: Mutex<Vec<u8>>,
 }
 
 impl OpenFile {
@@ -117,6 +169,26 @@ impl OpenFile {
 
         // Parse FCR
         let fcr = FileControlRecord::from_bytes(&page_data)?;
+        let fcr_seen = Mutex::new(page_data);
+
+        // Find the frames of a compressed file, cutting off one a crash tore
+        let frames = match fcr.compression {
+            Compression::None => None,
+            Compression::Lz4 => {
+                let index = FrameIndex::scan(&mut file, page_size as u64)?;
+                if !mode.read_only {
+                    file.set_len(index.end())?;
+                }
+                Some(Mutex::new(index))
+            }
+        };
+        let index = open_index(path, &fcr, mode.read_only).map_err(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                BtrieveError::InvalidFormat(format!("an index file of {} is missing", path.display()))
+            } else {
+                BtrieveError::Io(e)
+            }
+        })?;
 
         Ok(OpenFile {
             path: path.to_path_buf(),
@@ -125,27 +197,46 @@ impl OpenFile {
             file: RwLock::new(file),
             ref_count: 1,
             session_preimages: RwLock::new(HashMap::new()),
+            cipher: None,
+            frames,
+            index,
+            fcr_pending: AtomicU32::new(0),
+            commits: GroupCommit::new(),
+            records: Mutex::new(RecordCache::new(RECORD_CACHE_ENTRIES)),
+            writes: Mutex::new(WriteQueue::new(0)),
+            marker: None,
+            coherence: Coherence::Cached,
+            fcr_seen,
         })
     }
 
     /// Create a new Btrieve file
     pub fn create(path: &Path, fcr: FileControlRecord) -> BtrieveResult<Self> {
-        // Check if file exists
-        if path.exists() {
-            return Err(BtrieveError::Status(StatusCode::FileAlreadyExists));
-        }
-
-        let file = OpenOptions::new()
+        // Never write over an existing file: create_new checks and creates
+        // in one step, so one made meanwhile isn't truncated either
+        let file = match OpenOptions::new()
             .read(true)
             .write(true)
-            .create(true)
-            .open(path)?;
+            .create_new(true)
+            .open(path)
+        {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(BtrieveError::Status(StatusCode::FileAlreadyExists));
+            }
+            file => file?,
+        };
 
         // Write FCR to page 0
         let fcr_data = fcr.to_bytes();
         let mut file = file;
         file.write_all(&fcr_data)?;
         file.flush()?;
+        let frames = (fcr.compression != Compression::None)
+            .then(|| Mutex::new(FrameIndex::new(fcr.page_size as u64)));
+        let index = match fcr.layout {
+            Layout::Single => None,
+            layout => Some(Mutex::new(IndexFile::create(&files::index_paths(path, layout, fcr.keys.len()))?)),
+        };
 
         Ok(OpenFile {
             path: path.to_path_buf(),
@@ -154,19 +245,221 @@ impl OpenFile {
             file: RwLock::new(file),
             ref_count: 1,
             session_preimages: RwLock::new(HashMap::new()),
+            cipher: None,
+            frames,
+            index,
+            fcr_pending: AtomicU32::new(0),
+            commits: GroupCommit::new(),
+            records: Mutex::new(RecordCache::new(RECORD_CACHE_ENTRIES)),
+            writes: Mutex::new(WriteQueue::new(0)),
+            marker: None,
+            coherence: Coherence::Cached,
+            fcr_seen: Mutex::new(fcr_data),
         })
     }
 
-    /// Read a page from the file
-    pub fn read_page(&self, page_number: u32) -> BtrieveResult<Page> {
-        let mut file = self.file.write();
-        let offset = (page_number as u64) * (self.fcr.page_size as u64);
-        file.seek(SeekFrom::Start(offset))?;
+    /// Hold an OS lock on the data file itself while a lock file claims
+    /// it, for other tools that honour advisory locks. Unix only: a
+    /// Windows lock bars every other handle, this process's own among them
+    fn lock_data(&self) -> BtrieveResult<()> {
+        #[cfg(unix)]
+        if self.marker.is_some() {
+            match self.file.read().try_lock() {
+                Ok(()) => {}
+                Err(fs::TryLockError::WouldBlock) => return Err(BtrieveError::Status(StatusCode::FileInUse)),
+                Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
 
-        let mut data = vec![0u8; self.fcr.page_size as usize];
+    /// Check the owner name given at an Open. Without it, a file with an
+    /// owner can only be opened read-only, and only if its owner allows that
+    pub fn check_owner(&self, owner: Option<&[u8]>, mode: OpenMode) -> BtrieveResult<()> {
+        let Some(set) = &self.fcr.owner else {
+            return Ok(());
+        };
+        match owner {
+            Some(name) if crypt::owner_check(name, &self.fcr.salt) == set.check => Ok(()),
+            None if set.read_only_without && mode.read_only => Ok(()),
+            _ => Err(BtrieveError::Status(StatusCode::InvalidOwner)),
+        }
+    }
+
+    /// Set the cipher for an encrypted file, from the owner name (already
+    /// checked) or the daemon key
+    pub fn unlock(&mut self, owner: Option<&[u8]>, daemon_key: Option<&[u8; KEY_LEN]>) -> BtrieveResult<()> {
+        self.cipher = match (self.fcr.encryption, &self.fcr.owner) {
+            (Encryption::None, _) => None,
+            (Encryption::Owner, Some(_)) => {
+                let owner = owner.ok_or(BtrieveError::Status(StatusCode::InvalidOwner))?;
+                Some(PageCipher::for_owner(owner, &self.fcr.salt))
+            }
+            (Encryption::Owner, None) => {
+                return Err(BtrieveError::InvalidFormat("owner encryption without an owner".to_string()));
+            }
+            (Encryption::DaemonKey, _) => {
+                let key = daemon_key.ok_or(BtrieveError::Status(StatusCode::AccessDenied))?;
+                Some(PageCipher::new(key, &self.fcr.salt))
+            }
+        };
+        // Nothing in the FCR tells the daemon key, so try it on a page
+        if self.fcr.encryption == Encryption::DaemonKey && self.page_count()? > 1 {
+            self.read_page(1).map_err(|_| BtrieveError::Status(StatusCode::AccessDenied))?;
+        }
+        Ok(())
+    }
+
+    /// Bytes a page takes in its slot, for files not compressed
+    fn slot_size(&self) -> u64 {
+        self.fcr.page_size as u64 + if self.cipher.is_some() { SEAL_OVERHEAD as u64 } else { 0 }
+    }
+
+    /// Where a page's slot starts, for files not compressed. The FCR is
+    /// never sealed, so sealed pages start after it
+    fn slot_offset(&self, page_number: u32) -> u64 {
+        match page_number {
+            0 => 0,
+            n => self.fcr.page_size as u64 + (n as u64 - 1) * self.slot_size(),
+        }
+    }
+
+    /// A page as it goes on disk: compressed, then sealed
+    fn encode(&self, page: &Page) -> Vec<u8> {
+        if page.page_number == 0 {
+            return page.data.clone();
+        }
+        let data = match self.fcr.compression {
+            Compression::None => page.data.clone(),
+            Compression::Lz4 => compress::compress_page(&page.data),
+        };
+        match &self.cipher {
+            Some(cipher) => cipher.seal(page.page_number, &data),
+            None => data,
+        }
+    }
+
+    /// A page as read from disk, opened and decompressed
+    fn decode(&self, page_number: u32, mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+        if page_number == 0 {
+            return Ok(data);
+        }
+        if let Some(cipher) = &self.cipher {
+            data = cipher.open(page_number, &data)?;
+        }
+        match self.fcr.compression {
+            Compression::None => Ok(data),
+            Compression::Lz4 => compress::decompress_page(&data, self.fcr.page_size as usize),
+        }
+    }
+
+    /// Read a page as stored, or `None` if it is past the end of the file
+    fn read_stored(&self, file: &mut File, page_number: u32) -> io::Result<Option<Vec<u8>>> {
+        if let Some(data) = self.writes.lock().get(page_number) {
+            return Ok(Some(data.to_vec()));
+        }
+        let (offset, len) = match &self.frames {
+            Some(frames) if page_number > 0 => match frames.lock().get(page_number) {
+                Some((offset, len)) => (offset, len as u64),
+                None => return Ok(None),
+            },
+            _ => {
+                let len = if page_number == 0 { self.fcr.page_size as u64 } else { self.slot_size() };
+                (self.slot_offset(page_number), len)
+            }
+        };
+        if offset + len > file.seek(SeekFrom::End(0))? {
+            return Ok(None);
+        }
+        if let Some(index) = &self.index {
+            let mut index = index.lock();
+            if index.contains(page_number) {
+                return index.read(page_number, offset, len as usize).map(Some);
+            }
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0u8; len as usize];
         file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Write a page as stored: in its slot, or as a new frame
+    fn write_stored(&self, file: &mut File, page_number: u32, data: &[u8]) -> io::Result<()> {
+        match &self.frames {
+            Some(frames) if page_number > 0 => {
+                let at = frames.lock().record(page_number, data.len() as u32);
+                let mut frame = Vec::with_capacity(FRAME_HEADER as usize + data.len());
+                frame.extend_from_slice(&page_number.to_le_bytes());
+                frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                frame.extend_from_slice(data);
+                file.seek(SeekFrom::Start(at))?;
+                file.write_all(&frame)
+            }
+            _ => {
+                file.seek(SeekFrom::Start(self.slot_offset(page_number)))?;
+                file.write_all(data)
+            }
+        }
+    }
+
+    /// Write a page as stored to the file it belongs in: an index file if
+    /// `to_index`, the .DAT otherwise. The .DAT is
+    /// given a blank slot for an index page past its end, since it is
+    /// what tells how many pages there are
+    fn store(&self, file: &mut File, page_number: u32, data: &[u8], to_index: bool) -> io::Result<()> {
+        let Some(index) = &self.index else {
+            return self.write_stored(file, page_number, data);
+        };
+        let mut index = index.lock();
+        let offset = self.slot_offset(page_number);
+        if to_index {
+            if file.seek(SeekFrom::End(0))? < offset + data.len() as u64 {
+                self.write_stored(file, page_number, &vec![0u8; data.len()])?;
+            }
+            index.write(page_number, offset, data)
+        } else {
+            index.clear(page_number, offset, self.slot_size() as usize)?;
+            self.write_stored(file, page_number, data)
+        }
+    }
+
+    /// Have a new index page of key `key_number` go to that key's index
+    /// file, for a file with one per key
+    pub fn claim_index_page(&self, page_number: u32, key_number: usize) {
+        if let Some(index) = &self.index {
+            index.lock().claim(page_number, self.fcr.layout.index_file_of(key_number));
+        }
+    }
+
+    /// Sync the .DAT and any index files to disk, queued pages first
+    fn sync_files(&self) -> io::Result<()> {
+        self.write_queued()?;
+        self.file.read().sync_all()?;
+        if let Some(index) = &self.index {
+            index.lock().sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Number of pages in the file, the FCR included
+    fn stored_pages(&self, file: &mut File) -> io::Result<u32> {
+        if let Some(frames) = &self.frames {
+            return Ok(frames.lock().page_count());
+        }
+        let len = file.seek(SeekFrom::End(0))?;
+        Ok(match len.checked_sub(self.fcr.page_size as u64) {
+            Some(rest) => 1 + (rest / self.slot_size()) as u32,
+            None => 0,
+        })
+    }
 
-        Ok(Page::from_data(page_number, data))
+    /// Read a page from the file
+    pub fn read_page(&self, page_number: u32) -> BtrieveResult<Page> {
+        let mut file = self.file.write();
+        let data = self
+            .read_stored(&mut file, page_number)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, format!("no page {}", page_number)))?;
+        Ok(Page::from_data(page_number, self.decode(page_number, data)?))
     }
 
     /// Write a page - Btrieve 5.1 style
@@ -196,16 +489,11 @@ impl OpenFile {
                 // Only save pre-image once per page (first modification wins)
                 if !preimage.pages.contains(&page.page_number) {
                     // Read current (old) page data from main file
+                    // (as stored, so a sealed page stays sealed in the PRE)
                     let mut file = self.file.write();
-                    let offset = (page.page_number as u64) * (self.fcr.page_size as u64);
 
                     // Check if page exists (might be new allocation)
-                    let file_len = file.seek(SeekFrom::End(0))?;
-                    if offset < file_len {
-                        file.seek(SeekFrom::Start(offset))?;
-                        let mut old_data = vec![0u8; self.fcr.page_size as usize];
-                        file.read_exact(&mut old_data)?;
-
+                    if let Some(old_data) = self.read_stored(&mut file, page.page_number)? {
                         // Write old data to PRE file
                         preimage.file.seek(SeekFrom::End(0))?;
                         preimage.file.write_all(&page.page_number.to_le_bytes())?;
@@ -218,20 +506,126 @@ impl OpenFile {
             }
         }
 
-        // Write new data directly to main file (Btrieve 5.1 style)
+        // Write new data directly to main file (Btrieve 5.1 style), or
+        // queue it. A transaction's pages and pages read back in verify
+        // mode are written through
+        self.records.lock().invalidate_page(page.page_number);
+        let data = self.encode(page);
+        let to_index = self.index.is_some() && files::is_index_page(page.page_number, &page.data);
+        if !has_preimage && !self.mode.verify {
+            let mut writes = self.writes.lock();
+            if writes.is_enabled() {
+                let full = writes.push(page.page_number, data, to_index);
+                drop(writes);
+                if full {
+                    self.write_queued()?;
+                }
+                return Ok(());
+            }
+        }
         let mut file = self.file.write();
-        let offset = (page.page_number as u64) * (self.fcr.page_size as u64);
+        self.writes.lock().remove(page.page_number);
+        self.store(&mut file, page.page_number, &data, to_index)?;
 
-        file.seek(SeekFrom::Start(offset))?;
-        file.write_all(&page.data)?;
+        if !self.mode.accelerated {
+            file.flush()?;
+        }
+        self.verify_stored(&mut file, page.page_number, &data)
+    }
+
+    /// Have the page writes a queue holds back wait for no more than
+    /// `limit` pages (0 writes every page through). Pages already waiting
+    /// go out first
+    pub fn set_write_queue(&self, limit: usize) -> BtrieveResult<()> {
+        self.write_queued()?;
+        *self.writes.lock() = WriteQueue::new(limit);
+        Ok(())
+    }
+
+    /// Pages waiting in the write queue
+    pub fn queued_pages(&self) -> usize {
+        self.writes.lock().len()
+    }
 
+    /// Write out the queued pages in page order, a run of adjacent pages
+    /// in one write where its slots are next to each other in the .DAT
+    pub fn write_queued(&self) -> io::Result<()> {
+        let mut file = self.file.write();
+        let runs = self.writes.lock().take();
+        let contiguous = self.frames.is_none() && self.index.is_none();
+        for run in runs {
+            if contiguous {
+                file.seek(SeekFrom::Start(self.slot_offset(run.first)))?;
+                file.write_all(&run.pages.concat())?;
+                continue;
+            }
+            for (page_number, data) in (run.first..).zip(&run.pages) {
+                self.store(&mut file, page_number, data, run.to_index)?;
+            }
+        }
         if !self.mode.accelerated {
             file.flush()?;
         }
+        Ok(())
+    }
 
+    /// In verify mode, read a page back after writing it and fail with an
+    /// I/O error unless it reads as written
+    fn verify_stored(&self, file: &mut File, page_number: u32, data: &[u8]) -> BtrieveResult<()> {
+        if self.mode.verify && self.read_stored(file, page_number)?.as_deref() != Some(data) {
+            return Err(BtrieveError::Status(StatusCode::IoError));
+        }
         Ok(())
     }
 
+    /// Record starting at `address` (its page, and its offset in the page
+    /// as the slot), from the record cache or by `read`, which is kept for
+    /// next time
+    pub fn cached_record(
+        &self,
+        address: RecordAddress,
+        read: impl FnOnce() -> BtrieveResult<Vec<u8>>,
+    ) -> BtrieveResult<Vec<u8>> {
+        let generation = {
+            let mut records = self.records.lock();
+            if let Some(data) = records.get(address) {
+                return Ok(data);
+            }
+            records.generation()
+        };
+        let data = read()?;
+        self.records.lock().put(generation, address, &data);
+        Ok(data)
+    }
+
+    /// Page holding the record at `file_offset`, a Btrieve 5.1 record
+    /// address, and where in the page it starts. Addresses come back from
+    /// clients, so one in the FCR or whose record would run off its page
+    /// gives status 42
+    pub fn record_location(&self, file_offset: u32) -> BtrieveResult<(u32, usize)> {
+        let page_size = self.fcr.page_size as u32;
+        let page_number = file_offset / page_size;
+        let offset_in_page = (file_offset % page_size) as usize;
+        if page_number == 0 || offset_in_page + self.fcr.record_length as usize > page_size as usize {
+            return Err(BtrieveError::Status(StatusCode::InvalidRecordAddress));
+        }
+        Ok((page_number, offset_in_page))
+    }
+
+    /// Read the page a record address leads to, as `read_page` but with
+    /// status 42 for a page past the end of the file
+    pub fn read_record_page(&self, page_number: u32) -> BtrieveResult<Page> {
+        let mut file = self.file.write();
+        let data = self
+            .read_stored(&mut file, page_number)?
+            .ok_or(BtrieveError::Status(StatusCode::InvalidRecordAddress))?;
+        Ok(Page::from_data(page_number, self.decode(page_number, data)?))
+    }
+
+    pub fn record_cache_stats(&self) -> RecordCacheStats {
+        self.records.lock().stats()
+    }
+
     /// Allocate a new page
     pub fn allocate_page(&self) -> BtrieveResult<Page> {
         if self.mode.read_only {
@@ -239,27 +633,27 @@ impl OpenFile {
         }
 
         let mut file = self.file.write();
-        let end = file.seek(SeekFrom::End(0))?;
-        let page_number = (end / self.fcr.page_size as u64) as u32;
+        let page_number = self.stored_pages(&mut file)?;
 
         let page = Page::new(page_number, self.fcr.page_size);
-        file.write_all(&page.data)?;
+        let data = self.encode(&page);
+        self.write_stored(&mut file, page_number, &data)?;
+        self.verify_stored(&mut file, page_number, &data)?;
 
         Ok(page)
     }
 
-    /// Flush all writes to disk
+    /// Flush all writes to disk, with any counters not yet written
     pub fn flush(&self) -> BtrieveResult<()> {
-        let file = self.file.write();
-        file.sync_all()?;
+        self.flush_fcr()?;
+        self.sync_files()?;
         Ok(())
     }
 
     /// Get the number of pages in the file
     pub fn page_count(&self) -> BtrieveResult<u32> {
         let mut file = self.file.write();
-        let end = file.seek(SeekFrom::End(0))?;
-        Ok((end / self.fcr.page_size as u64) as u32)
+        Ok(self.stored_pages(&mut file)?)
     }
 
     /// Update FCR and write to page 0
@@ -268,9 +662,242 @@ impl OpenFile {
             return Err(BtrieveError::Status(StatusCode::AccessDenied));
         }
 
-        let fcr_data = self.fcr.to_bytes();
-        let page = Page::from_data(0, fcr_data);
-        self.write_page(&page)
+        self.write_fcr()
+    }
+
+    /// Note a change to the FCR's counters alone (record count, unique
+    /// key counts, autoincrement values) without writing page 0 for it.
+    /// The FCR goes out with the next structural change, every
+    /// `FCR_FLUSH_INTERVAL` counter changes, at a transaction's End and
+    /// when the file is flushed or closed
+    pub fn defer_fcr(&mut self) -> BtrieveResult<()> {
+        if self.mode.read_only {
+            return Err(BtrieveError::Status(StatusCode::AccessDenied));
+        }
+
+        // Other engines checking page 0 see every change at once
+        if self.coherence.validates() || self.fcr_pending.fetch_add(1, Ordering::Relaxed) + 1 >= FCR_FLUSH_INTERVAL {
+            self.write_fcr()?;
+        }
+        Ok(())
+    }
+
+    /// Write the FCR if counter changes are waiting for it
+    pub fn flush_fcr(&self) -> BtrieveResult<()> {
+        if self.fcr_pending.load(Ordering::Relaxed) > 0 {
+            self.write_fcr()?;
+        }
+        Ok(())
+    }
+
+    /// Write the FCR to page 0, with its usage count bumped if other
+    /// engines check it
+    fn write_fcr(&self) -> BtrieveResult<()> {
+        let mut data = self.fcr.to_bytes();
+        if self.coherence.validates() {
+            let seen = self.fcr_seen.lock();
+            let usage = u32::from_le_bytes([seen[0], seen[1], seen[2], seen[3]]).wrapping_add(1);
+            data[..4].copy_from_slice(&usage.to_le_bytes());
+        }
+        let page = Page::from_data(0, data);
+        self.write_page(&page)?;
+        *self.fcr_seen.lock() = page.data;
+        self.fcr_pending.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn coherence(&self) -> Coherence {
+        self.coherence
+    }
+
+    /// Trust cached pages only so far (see `coherence`). Checking files
+    /// don't queue page writes
+    pub fn set_coherence(&mut self, coherence: Coherence) -> BtrieveResult<()> {
+        self.coherence = coherence;
+        if coherence.validates() {
+            self.set_write_queue(0)?;
+        }
+        Ok(())
+    }
+
+    /// Read page 0 back and, if another engine has written it since this
+    /// process last did, reload the FCR and what hangs off it and drop the
+    /// cached records. Whether it had changed (see `coherence`)
+    pub fn revalidate(&mut self) -> BtrieveResult<bool> {
+        if self.coherence == Coherence::Uncached {
+            self.records.lock().clear();
+        }
+        let mut on_disk = vec![0u8; self.fcr.page_size as usize];
+        {
+            let mut file = self.file.write();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut on_disk)?;
+        }
+        if on_disk == *self.fcr_seen.lock() {
+            return Ok(false);
+        }
+
+        let fcr = FileControlRecord::from_bytes(&on_disk)?;
+        self.frames = match fcr.compression {
+            Compression::None => None,
+            Compression::Lz4 => Some(Mutex::new(FrameIndex::scan(&mut *self.file.write(), fcr.page_size as u64)?)),
+        };
+        self.index = open_index(&self.path, &fcr, self.mode.read_only)?;
+        self.fcr = fcr;
+        self.records.lock().clear();
+        *self.fcr_seen.lock() = on_disk;
+        Ok(true)
+    }
+
+    /// Rewrite the file with a new FCR, its pages sealed with `cipher`:
+    /// to change its owner, encryption or compression, or to drop the
+    /// frames a compressed file's pages have left behind. The file is
+    /// written beside itself and renamed into place, so a failure leaves
+    /// it as it was. Refused while a transaction has pre-images of the old
+    /// pages. A recycle bin and a roll-forward log are sealed again with
+    /// the new key. `cancel` is checked before each page is read
+    pub fn rewrite(&mut self, fcr: FileControlRecord, cipher: Option<PageCipher>, cancel: &CancelToken) -> BtrieveResult<()> {
+        if self.mode.read_only {
+            return Err(BtrieveError::Status(StatusCode::AccessDenied));
+        }
+        if self.has_active_transactions() {
+            return Err(BtrieveError::Status(StatusCode::TransactionActive));
+        }
+
+        let pages = (1..self.page_count()?)
+            .map(|n| {
+                cancel.check()?;
+                self.read_page(n)
+            })
+            .collect::<BtrieveResult<Vec<_>>>()?;
+        self.replace(fcr, cipher, &pages)
+    }
+
+    /// Empty the file: every page after the FCR goes, and its counts and
+    /// index roots start over, keeping its keys, flags and owner. Deleted
+    /// records aren't put in the recycle bin. Refused like `rewrite`
+    pub fn truncate(&mut self) -> BtrieveResult<()> {
+        if self.mode.read_only {
+            return Err(BtrieveError::Status(StatusCode::AccessDenied));
+        }
+        if self.has_active_transactions() {
+            return Err(BtrieveError::Status(StatusCode::TransactionActive));
+        }
+
+        let mut fcr = FileControlRecord::new(self.fcr.record_length, self.fcr.page_size, self.fcr.keys.clone());
+        fcr.flags = self.fcr.flags;
+        fcr.encryption = self.fcr.encryption;
+        fcr.owner = self.fcr.owner;
+        fcr.salt = self.fcr.salt;
+        fcr.compression = self.fcr.compression;
+        fcr.recycle = self.fcr.recycle;
+        fcr.layout = self.fcr.layout;
+        fcr.roll_forward = self.fcr.roll_forward;
+        let cipher = self.cipher.clone();
+        self.replace(fcr, cipher, &[])
+    }
+
+    /// Write the file again as `fcr` and `pages`, beside itself, and
+    /// rename it into place. Index files are written and renamed first
+    fn replace(&mut self, fcr: FileControlRecord, cipher: Option<PageCipher>, pages: &[Page]) -> BtrieveResult<()> {
+        let recycled = self.recycle_bin().map(|bin| bin.entries()).transpose()?;
+        let logged = self.roll_forward_log().map(|log| log.entries()).transpose()?;
+
+        self.records.lock().clear();
+        self.write_queued()?;
+        let frames = (fcr.compression != Compression::None)
+            .then(|| Mutex::new(FrameIndex::new(fcr.page_size as u64)));
+        let previous = (
+            std::mem::replace(&mut self.fcr, fcr),
+            std::mem::replace(&mut self.cipher, cipher),
+            std::mem::replace(&mut self.frames, frames),
+            self.index.take(),
+        );
+        let rewritten = self.path.with_extension("XT~");
+        let index_paths = files::index_paths(&self.path, self.fcr.layout, self.fcr.keys.len());
+        let index_rewritten: Vec<PathBuf> = index_paths
+            .iter()
+            .map(|path| {
+                let mut name = path.as_os_str().to_owned();
+                name.push("~");
+                PathBuf::from(name)
+            })
+            .collect();
+        let written = (|| -> io::Result<()> {
+            let mut out = File::create(&rewritten)?;
+            let mut index = match self.fcr.layout {
+                Layout::Single => None,
+                _ => Some(IndexFile::create(&index_rewritten)?),
+            };
+            out.write_all(&self.fcr.to_bytes())?;
+            for page in pages {
+                let data = self.encode(page);
+                match &mut index {
+                    Some(index) if files::is_index_page(page.page_number, &page.data) => {
+                        // The .DAT keeps a blank slot for it, and the page
+                        // stays in the index file it was in
+                        let n = previous.3.as_ref().and_then(|old| old.lock().file_of(page.page_number));
+                        self.write_stored(&mut out, page.page_number, &vec![0u8; data.len()])?;
+                        index.write_to(n.unwrap_or(0), page.page_number, self.slot_offset(page.page_number), &data)?;
+                    }
+                    _ => self.write_stored(&mut out, page.page_number, &data)?,
+                }
+            }
+            out.sync_all()?;
+            if let Some(index) = index {
+                index.sync_all()?;
+                for (from, to) in index_rewritten.iter().zip(&index_paths) {
+                    fs::rename(from, to)?;
+                }
+            }
+            fs::rename(&rewritten, &self.path)
+        })();
+        if let Err(e) = written {
+            (self.fcr, self.cipher, self.frames, self.index) = previous;
+            let _ = fs::remove_file(&rewritten);
+            for path in &index_rewritten {
+                let _ = fs::remove_file(path);
+            }
+            return Err(e.into());
+        }
+
+        *self.file.write() = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.lock_data()?;
+        *self.fcr_seen.lock() = self.fcr.to_bytes();
+        self.index = open_index(&self.path, &self.fcr, false)?;
+        self.fcr_pending.store(0, Ordering::Relaxed);
+        // Deleted records follow the file to its new key
+        if let (Some(bin), Some(entries)) = (self.recycle_bin(), recycled) {
+            if !entries.is_empty() {
+                bin.rewrite(&entries)?;
+            }
+        }
+        if let (Some(log), Some(entries)) = (self.roll_forward_log(), logged) {
+            if !entries.is_empty() {
+                log.rewrite(&entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The file's recycle bin, if deleted records are kept
+    pub fn recycle_bin(&self) -> Option<RecycleBin> {
+        self.fcr.recycle.then(|| RecycleBin::new(&self.path, self.cipher.clone()))
+    }
+
+    /// The file's roll-forward log, if committed changes are logged
+    pub fn roll_forward_log(&self) -> Option<RollForwardLog> {
+        self.fcr.roll_forward.then(|| RollForwardLog::new(&self.path, self.cipher.clone()))
+    }
+
+    /// Whether a compressed file has left enough old frames behind to be
+    /// worth rewriting
+    pub fn worth_compacting(&self) -> bool {
+        !self.mode.read_only
+            && self
+                .frames
+                .as_ref()
+                .is_some_and(|frames| frames.lock().worth_compacting(self.fcr.page_size as u64))
     }
 
     /// Get pre-image file path for a session
@@ -309,15 +936,20 @@ impl OpenFile {
     }
 
     /// Commit transaction - just delete PRE file
-    /// Btrieve 5.1: changes already written to main file, PRE no longer needed
-    pub fn commit_transaction(&self, session_id: u64) -> BtrieveResult<()> {
-        let mut preimages = self.session_preimages.write();
+    /// Btrieve 5.1: changes already written to main file, PRE no longer needed.
+    /// Sessions committing within `window` of each other share one sync
+    /// of the main file (see `group_commit`)
+    pub fn commit_transaction(&self, session_id: u64, window: Duration) -> BtrieveResult<()> {
+        // Counters the transaction changed are part of what it commits
+        if self.is_in_transaction(session_id) {
+            self.flush_fcr()?;
+        }
 
         // Remove session's pre-image
-        if preimages.remove(&session_id).is_some() {
-            // Sync main file
-            let file = self.file.write();
-            file.sync_all()?;
+        let removed = self.session_preimages.write().remove(&session_id);
+        if removed.is_some() {
+            // Sync main file, without holding up other sessions' writes
+            self.commits.sync(window, || self.sync_files())?;
 
             // Delete PRE file - changes are committed
             let pre_path = self.preimage_path(session_id);
@@ -340,7 +972,8 @@ impl OpenFile {
 
         let SessionPreImage { mut file, pages: _ } = preimage;
 
-        // Restore all pages from PRE to main file
+        // Restore all pages from PRE to main file, over any queued since
+        self.write_queued()?;
         file.seek(SeekFrom::Start(0))?;
         let mut main_file = self.file.write();
 
@@ -365,14 +998,17 @@ impl OpenFile {
                 break;
             }
 
-            // Restore original page to main file
-            let offset = (page_number as u64) * (self.fcr.page_size as u64);
-            main_file.seek(SeekFrom::Start(offset))?;
-            main_file.write_all(&old_data)?;
+            // Restore original page to the file it was read from; a page
+            // blank before the transaction was in the .DAT
+            let to_index = old_data.iter().any(|&b| b != 0)
+                && self.index.as_ref().is_some_and(|index| index.lock().contains(page_number));
+            self.records.lock().invalidate_page(page_number);
+            self.store(&mut main_file, page_number, &old_data, to_index)?;
+            self.verify_stored(&mut main_file, page_number, &old_data)?;
         }
 
-        main_file.sync_all()?;
         drop(main_file);
+        self.sync_files()?;
 
         // Delete PRE file
         let pre_path = self.preimage_path(session_id);
@@ -394,34 +1030,212 @@ impl OpenFile {
     }
 }
 
+/// The index files of a file laid out apart, with the pages in them found
+/// again
+fn open_index(path: &Path, fcr: &FileControlRecord, read_only: bool) -> io::Result<Option<Mutex<IndexFile>>> {
+    if fcr.layout == Layout::Single {
+        return Ok(None);
+    }
+    let page_size = fcr.page_size as u64;
+    let sealed = if fcr.encryption != Encryption::None { SEAL_OVERHEAD as u64 } else { 0 };
+    let paths = files::index_paths(path, fcr.layout, fcr.keys.len());
+    let index = IndexFile::open(&paths, read_only, page_size, page_size + sealed)?;
+    Ok(Some(Mutex::new(index)))
+}
+
 /// Table of all open files
 pub struct OpenFileTable {
     files: RwLock<HashMap<PathBuf, Arc<RwLock<OpenFile>>>>,
+    /// Key sealing the pages of files created here, from the daemon's
+    /// configuration
+    page_key: Option<[u8; KEY_LEN]>,
+    /// How long, in microseconds, a transaction's End waits for others
+    /// to share its sync
+    commit_window: AtomicU64,
+    /// Most page writes each file queues (see `write_queue`), 0 for none
+    write_queue: AtomicU32,
+    /// Files the startup self-check failed, with the status their opens
+    /// get (see `storage::selfcheck`)
+    refused: RwLock<HashMap<PathBuf, StatusCode>>,
+    /// Take the lock file of each file opened, refusing files another
+    /// process holds (see `storage::marker`)
+    lock_files: AtomicBool,
+    /// Coherence of the files under each directory, for those not cached
+    /// as usual (see `coherence`)
+    coherence: RwLock<Vec<(PathBuf, Coherence)>>,
 }
 
 impl OpenFileTable {
     pub fn new() -> Self {
         OpenFileTable {
             files: RwLock::new(HashMap::new()),
+            page_key: None,
+            commit_window: AtomicU64::new(0),
+            write_queue: AtomicU32::new(0),
+            refused: RwLock::new(HashMap::new()),
+            lock_files: AtomicBool::new(false),
+            coherence: RwLock::new(Vec::new()),
         }
     }
 
-    /// Open a file (or increment ref count if already open)
-    pub fn open(&self, path: &Path, mode: OpenMode) -> BtrieveResult<Arc<RwLock<OpenFile>>> {
+    /// Table whose new files have their pages sealed with `key`, and which
+    /// can open files sealed with it
+    pub fn with_page_key(key: [u8; KEY_LEN]) -> Self {
+        OpenFileTable {
+            page_key: Some(key),
+            ..Self::new()
+        }
+    }
+
+    /// How long a transaction's End waits for others ending at the same
+    /// time, so that one sync does for all of them. None by default:
+    /// commits arriving during a sync still share the next one
+    pub fn set_commit_window(&self, window: Duration) {
+        self.commit_window.store(window.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn commit_window(&self) -> Duration {
+        Duration::from_micros(self.commit_window.load(Ordering::Relaxed))
+    }
+
+    /// Queue up to `pages` page writes per file, written out in page
+    /// order when the queue fills, the file is synced or `write_queued`
+    /// is called. Files open already keep writing through
+    pub fn set_write_queue(&self, pages: u32) {
+        self.write_queue.store(pages, Ordering::Relaxed);
+    }
+
+    pub fn write_queue(&self) -> u32 {
+        self.write_queue.load(Ordering::Relaxed)
+    }
+
+    /// Claim each file with a lock file while it is open, so a second
+    /// daemon on the same directory refuses it
+    pub fn set_lock_files(&self, on: bool) {
+        self.lock_files.store(on, Ordering::Relaxed);
+    }
+
+    pub fn lock_files(&self) -> bool {
+        self.lock_files.load(Ordering::Relaxed)
+    }
+
+    /// Take the lock file of the file at `path`, if lock files are kept.
+    /// Status 80, with the holder, if another process has it. None where
+    /// it can't be written, for files only ever read
+    fn take_marker(&self, path: &Path) -> BtrieveResult<Option<Marker>> {
+        if !self.lock_files() {
+            return Ok(None);
+        }
+        match Marker::take(path) {
+            Ok(marker) => Ok(Some(marker)),
+            Err(MarkerError::Held(holder)) => Err(BtrieveError::Detailed {
+                status: StatusCode::FileInUse,
+                detail: ErrorDetail {
+                    message: format!("served by another process ({})", holder),
+                    context: path.display().to_string(),
+                },
+            }),
+            Err(MarkerError::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied => Ok(None),
+            Err(MarkerError::Io(e)) => Err(e.into()),
+        }
+    }
+
+    /// Trust cached pages of files under `dir`, once opened, only so far
+    pub fn set_coherence(&self, dir: &Path, coherence: Coherence) {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let mut rules = self.coherence.write();
+        rules.retain(|(other, _)| *other != dir);
+        if coherence != Coherence::Cached {
+            rules.push((dir, coherence));
+        }
+    }
+
+    /// Whether any directory's files are checked for other engines' writes
+    pub fn checks_coherence(&self) -> bool {
+        !self.coherence.read().is_empty()
+    }
+
+    /// Coherence of a file, by the deepest directory given one it is in
+    fn coherence_of(&self, canonical: &Path) -> Coherence {
+        self.coherence
+            .read()
+            .iter()
+            .filter(|(dir, _)| canonical.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map_or(Coherence::Cached, |(_, coherence)| *coherence)
+    }
+
+    /// Refuse opens of the file at `path` with `status` from now on
+    pub fn refuse(&self, path: &Path, status: StatusCode) {
         let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.refused.write().insert(canonical, status);
+    }
+
+    /// Write out the pages every open file has queued
+    pub fn write_queued(&self) -> BtrieveResult<()> {
+        for file in self.list() {
+            file.read().write_queued()?;
+        }
+        Ok(())
+    }
+
+    /// How the pages of the file with `salt` are protected when no owner
+    /// name is: with the daemon key if there is one
+    pub fn default_protection(&self, salt: &[u8; 16]) -> (Encryption, Option<PageCipher>) {
+        match &self.page_key {
+            Some(key) => (Encryption::DaemonKey, Some(PageCipher::new(key, salt))),
+            None => (Encryption::None, None),
+        }
+    }
+
+    /// Whether files created here have their pages sealed
+    pub fn seals_pages(&self) -> bool {
+        self.page_key.is_some()
+    }
+
+    /// Open a file (or increment ref count if already open), giving the
+    /// owner name if it has one
+    pub fn open(
+        &self,
+        path: &Path,
+        mode: OpenMode,
+        owner: Option<&[u8]>,
+    ) -> BtrieveResult<Arc<RwLock<OpenFile>>> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(status) = self.refused.read().get(&canonical) {
+            return Err(BtrieveError::Status(*status));
+        }
 
         // Check if already open
         {
             let files = self.files.read();
             if let Some(file) = files.get(&canonical) {
                 let mut f = file.write();
+                f.check_owner(owner, mode)?;
                 f.ref_count += 1;
+                // Pages are written as carefully as the most careful open asks
+                f.mode.accelerated &= mode.accelerated;
+                f.mode.verify |= mode.verify;
                 return Ok(file.clone());
             }
         }
 
-        // Open new file
-        let open_file = OpenFile::open(path, mode)?;
+        // Open new file. Opens share it, so it is opened for writing even
+        // for a read-only open (which the session registry holds to reads)
+        // unless the file can only be read
+        let marker = self.take_marker(path)?;
+        let mut open_file = match OpenFile::open(path, OpenMode { read_only: false, ..mode }) {
+            Err(BtrieveError::Io(e)) if mode.read_only && e.kind() == io::ErrorKind::PermissionDenied => {
+                OpenFile::open(path, mode)?
+            }
+            opened => opened?,
+        };
+        open_file.check_owner(owner, mode)?;
+        open_file.unlock(owner, self.page_key.as_ref())?;
+        open_file.set_write_queue(self.write_queue() as usize)?;
+        open_file.set_coherence(self.coherence_of(&canonical))?;
+        open_file.marker = marker;
+        open_file.lock_data()?;
         let open_file = Arc::new(RwLock::new(open_file));
 
         let mut files = self.files.write();
@@ -450,8 +1264,18 @@ impl OpenFileTable {
             }
         }
 
-        // Create new file
-        let open_file = OpenFile::create(path, fcr)?;
+        // Create new file, sealed with the daemon key if there is one
+        let mut fcr = fcr;
+        fcr.salt = crypt::new_salt();
+        let (encryption, cipher) = self.default_protection(&fcr.salt);
+        fcr.encryption = encryption;
+        let marker = self.take_marker(path)?;
+        let mut open_file = OpenFile::create(path, fcr)?;
+        open_file.cipher = cipher;
+        open_file.marker = marker;
+        open_file.lock_data()?;
+        open_file.set_write_queue(self.write_queue() as usize)?;
+        open_file.set_coherence(self.coherence_of(&canonical))?;
         let open_file = Arc::new(RwLock::new(open_file));
 
         let mut files = self.files.write();
@@ -470,7 +1294,13 @@ impl OpenFileTable {
             f.ref_count = f.ref_count.saturating_sub(1);
 
             if f.ref_count == 0 {
-                // Flush before closing
+                // Drop stale frames, and flush before closing
+                if f.worth_compacting() {
+                    let (fcr, cipher) = (f.fcr.clone(), f.cipher.clone());
+                    if let Err(e) = f.rewrite(fcr, cipher, &CancelToken::new()) {
+                        tracing::warn!("Could not compact {}: {}", f.path.display(), e);
+                    }
+                }
                 let _ = f.flush();
                 drop(f);
                 files.remove(&canonical);
@@ -481,6 +1311,116 @@ impl OpenFileTable {
         Ok(false)
     }
 
+    /// Delete a file no session has open, with its recycle bin, roll-forward
+    /// log and index files. A file
+    /// with an owner needs the name, even one others may read without it.
+    /// The caller checks no session has the file open; an entry left in
+    /// the table by Create is dropped
+    pub fn delete(&self, path: &Path, owner: Option<&[u8]>) -> BtrieveResult<()> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        // Holding the table keeps the file from being opened meanwhile
+        let mut files = self.files.write();
+        Self::check_unused(files.get(&canonical), path, owner)?;
+        let _marker = match files.get(&canonical) {
+            None => self.take_marker(path)?,
+            Some(_) => None,
+        };
+
+        fs::remove_file(path)?;
+        files.remove(&canonical);
+        for companion in Self::companions(path) {
+            match fs::remove_file(companion) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Rename a file no session has open, taking its recycle bin,
+    /// roll-forward log and index files along.
+    /// The new name must be free, and so must the names its companions
+    /// take. Either everything moves or, when a rename fails part way,
+    /// what had moved is put back; anything that couldn't be is named in
+    /// the error. An entry left in the table by Create follows the file
+    pub fn rename(&self, from: &Path, to: &Path, owner: Option<&[u8]>) -> BtrieveResult<()> {
+        let canonical = from.canonicalize().unwrap_or_else(|_| from.to_path_buf());
+
+        let mut files = self.files.write();
+        Self::check_unused(files.get(&canonical), from, owner)?;
+        let _marker = match files.get(&canonical) {
+            None => self.take_marker(from)?,
+            Some(_) => None,
+        };
+        let moves: Vec<(PathBuf, PathBuf)> = std::iter::once((from.to_path_buf(), to.to_path_buf()))
+            .chain(
+                Self::companions(from)
+                    .into_iter()
+                    .zip(Self::companions(to))
+                    .filter(|(companion, _)| companion.exists()),
+            )
+            .collect();
+        if moves.iter().any(|(_, renamed)| renamed.exists()) {
+            return Err(BtrieveError::Status(StatusCode::FileAlreadyExists));
+        }
+
+        for (done, (source, target)) in moves.iter().enumerate() {
+            let Err(e) = fs::rename(source, target) else {
+                continue;
+            };
+            let stranded: Vec<String> = moves[..done]
+                .iter()
+                .rev()
+                .filter(|(source, target)| fs::rename(target, source).is_err())
+                .map(|(_, target)| target.display().to_string())
+                .collect();
+            if stranded.is_empty() {
+                return Err(e.into());
+            }
+            let message = format!(
+                "renaming {} to {}: {}; left under the new name: {}",
+                source.display(),
+                target.display(),
+                e,
+                stranded.join(", ")
+            );
+            return Err(io::Error::new(e.kind(), message).into());
+        }
+        if let Some(file) = files.remove(&canonical) {
+            file.write().path = to.to_path_buf();
+            files.insert(to.canonicalize().unwrap_or_else(|_| to.to_path_buf()), file);
+        }
+        Ok(())
+    }
+
+    /// Files that may be kept beside the file at `path`, which go where
+    /// it goes
+    fn companions(path: &Path) -> Vec<PathBuf> {
+        let mut companions = vec![
+            RecycleBin::new(path, None).path().to_path_buf(),
+            RollForwardLog::new(path, None).path().to_path_buf(),
+        ];
+        companions.extend(files::index_paths(path, Layout::Split, 0));
+        companions.extend(files::index_paths(path, Layout::PerKey, FileControlRecord::MAX_KEYS));
+        companions
+    }
+
+    /// Refuse to delete or rename a file an Open has just taken, or
+    /// without its owner name
+    fn check_unused(entry: Option<&Arc<RwLock<OpenFile>>>, path: &Path, owner: Option<&[u8]>) -> BtrieveResult<()> {
+        match entry {
+            Some(file) => {
+                let f = file.read();
+                if f.ref_count > 1 {
+                    return Err(BtrieveError::Status(StatusCode::FileInUse));
+                }
+                f.check_owner(owner, OpenMode::read_write())
+            }
+            None => OpenFile::open(path, OpenMode::read_only())?.check_owner(owner, OpenMode::read_write()),
+        }
+    }
+
     /// Get an open file
     pub fn get(&self, path: &Path) -> Option<Arc<RwLock<OpenFile>>> {
         let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
@@ -488,6 +1428,11 @@ impl OpenFileTable {
         files.get(&canonical).cloned()
     }
 
+    /// Snapshot of all open files
+    pub fn list(&self) -> Vec<Arc<RwLock<OpenFile>>> {
+        self.files.read().values().cloned().collect()
+    }
+
     /// Get number of open files
     pub fn len(&self) -> usize {
         self.files.read().len()
@@ -537,13 +1482,217 @@ mod tests {
         };
 
         let fcr = FileControlRecord::new(100, 4096, vec![key]);
-        let _file = OpenFile::create(&path, fcr).unwrap();
+        let _file = OpenFile::create(&path, fcr.clone()).unwrap();
         drop(_file);
 
+        // A second create leaves the file alone
+        assert!(matches!(
+            OpenFile::create(&path, fcr),
+            Err(BtrieveError::Status(StatusCode::FileAlreadyExists))
+        ));
+
         // Reopen
         let file = OpenFile::open(&path, OpenMode::read_only()).unwrap();
         assert_eq!(file.fcr.record_length, 100);
         assert_eq!(file.fcr.page_size, 4096);
         assert_eq!(file.fcr.num_keys, 1);
     }
+
+    #[test]
+    fn test_rename_moves_companions_or_nothing() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT");
+        let key = KeySpec {
+            position: 0,
+            length: 4,
+            flags: KeyFlags::empty(),
+            key_type: KeyType::UnsignedBinary,
+            null_value: 0,
+            acs_number: 0,
+            unique_count: 0,
+        };
+        drop(OpenFile::create(&path, FileControlRecord::new(8, 1024, vec![key])).unwrap());
+        fs::write(path.with_extension("RCY"), b"bin").unwrap();
+        let table = OpenFileTable::new();
+
+        // A stale companion under the new name stops the rename before
+        // anything moves
+        let to = dir.path().join("CLIENTS.DAT");
+        fs::write(to.with_extension("RCY"), b"stale").unwrap();
+        assert!(matches!(
+            table.rename(&path, &to, None),
+            Err(BtrieveError::Status(StatusCode::FileAlreadyExists))
+        ));
+        assert!(path.exists() && !to.exists());
+        fs::remove_file(to.with_extension("RCY")).unwrap();
+
+        // The name of the last index file is too long to make, so the
+        // rename fails after the data file and recycle bin have moved,
+        // and they go back
+        fs::write(path.with_extension("IX12"), b"index").unwrap();
+        let long = dir.path().join(format!("{}.DAT", "A".repeat(251)));
+        assert!(matches!(table.rename(&path, &long, None), Err(BtrieveError::Io(_))));
+        assert!(path.exists() && path.with_extension("RCY").exists() && path.with_extension("IX12").exists());
+        assert!(!long.exists() && !long.with_extension("RCY").exists());
+
+        fs::remove_file(path.with_extension("IX12")).unwrap();
+        table.rename(&path, &to, None).unwrap();
+        assert!(to.exists() && to.with_extension("RCY").exists());
+        assert!(!path.exists() && !path.with_extension("RCY").exists());
+    }
+
+    #[test]
+    fn test_deferred_fcr() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        let key = KeySpec {
+            position: 0,
+            length: 4,
+            flags: KeyFlags::empty(),
+            key_type: KeyType::UnsignedBinary,
+            null_value: 0,
+            acs_number: 0,
+            unique_count: 0,
+        };
+        let mut file = OpenFile::create(&path, FileControlRecord::new(16, 1024, vec![key])).unwrap();
+        let on_disk = || OpenFile::open(&path, OpenMode::read_only()).unwrap().fcr.num_records as u32;
+
+        // Counter changes stay in memory until there are enough of them
+        for _ in 0..FCR_FLUSH_INTERVAL - 1 {
+            file.fcr.num_records += 1;
+            file.defer_fcr().unwrap();
+        }
+        assert_eq!(on_disk(), 0);
+        file.fcr.num_records += 1;
+        file.defer_fcr().unwrap();
+        assert_eq!(on_disk(), FCR_FLUSH_INTERVAL);
+
+        // ...or the file is flushed
+        file.fcr.num_records += 1;
+        file.defer_fcr().unwrap();
+        assert_eq!(on_disk(), FCR_FLUSH_INTERVAL);
+        file.flush().unwrap();
+        assert_eq!(on_disk(), FCR_FLUSH_INTERVAL + 1);
+    }
+
+    #[test]
+    fn test_verify_mode() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        drop(OpenFile::create(&path, FileControlRecord::new(16, 1024, Vec::new())).unwrap());
+        let file = OpenFile::open(&path, OpenMode::from_raw(-3)).unwrap();
+
+        let mut page = file.allocate_page().unwrap();
+        page.data[100] = 7;
+        file.write_page(&page).unwrap();
+        assert_eq!(file.read_page(page.page_number).unwrap().data[100], 7);
+
+        // A page that doesn't read back as written fails the write
+        let mut handle = file.file.write();
+        let mut garbled = file.encode(&page);
+        garbled[100] = 8;
+        assert!(file.verify_stored(&mut handle, page.page_number, &file.encode(&page)).is_ok());
+        let failed = file.verify_stored(&mut handle, page.page_number, &garbled);
+        assert!(matches!(failed, Err(BtrieveError::Status(StatusCode::IoError))));
+    }
+
+    #[test]
+    fn test_split_layout() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        let mut fcr = FileControlRecord::new(16, 512, Vec::new());
+        fcr.layout = Layout::Split;
+        let mut file = OpenFile::create(&path, fcr).unwrap();
+
+        let mut data_page = Page::new(1, 512);
+        data_page.data[0] = 0x02;
+        let mut index_page = Page::new(2, 512);
+        index_page.data[2] = 2;
+        file.write_page(&data_page).unwrap();
+        file.write_page(&index_page).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * 512);
+        assert_eq!(fs::read(path.with_extension("IX")).unwrap()[1024..], index_page.data[..]);
+
+        // Rewritten, then opened again, each page is found where it went
+        let fcr = file.fcr.clone();
+        file.rewrite(fcr, None, &CancelToken::new()).unwrap();
+        drop(file);
+        let file = OpenFile::open(&path, OpenMode::read_only()).unwrap();
+        assert_eq!(file.page_count().unwrap(), 3);
+        assert_eq!(file.read_page(1).unwrap().data, data_page.data);
+        assert_eq!(file.read_page(2).unwrap().data, index_page.data);
+    }
+
+    #[test]
+    fn test_refused_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        OpenFile::create(&path, FileControlRecord::new(16, 512, Vec::new())).unwrap();
+        let table = OpenFileTable::new();
+        table.refuse(&path, StatusCode::PreImageOpenError);
+        let status = table.open(&path, OpenMode::read_write(), None).err().map(|e| e.status_code());
+        assert_eq!(status, Some(StatusCode::PreImageOpenError));
+    }
+
+    #[test]
+    fn test_lock_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        let (first, second) = (OpenFileTable::new(), OpenFileTable::new());
+        first.set_lock_files(true);
+        second.set_lock_files(true);
+        first.create(&path, FileControlRecord::new(16, 512, Vec::new())).unwrap();
+
+        // The other table is refused until the first closes the file
+        let refused = second.open(&path, OpenMode::read_write(), None).err().unwrap();
+        assert_eq!(refused.status_code(), StatusCode::FileInUse);
+        assert_eq!(second.delete(&path, None).unwrap_err().status_code(), StatusCode::FileInUse);
+        #[cfg(unix)]
+        assert!(File::open(&path).unwrap().try_lock().is_err());
+
+        // Closing gives up the claim and removes the lock file
+        first.close(&path).unwrap();
+        assert!(!path.with_extension("LCK").exists());
+        second.open(&path, OpenMode::read_write(), None).unwrap();
+    }
+
+    #[test]
+    fn test_write_queue() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.dat");
+        let key = KeySpec {
+            position: 0,
+            length: 4,
+            flags: KeyFlags::empty(),
+            key_type: KeyType::UnsignedBinary,
+            null_value: 0,
+            acs_number: 0,
+            unique_count: 0,
+        };
+        let file = OpenFile::create(&path, FileControlRecord::new(16, 512, vec![key])).unwrap();
+        file.set_write_queue(4).unwrap();
+        let on_disk = |n: usize| fs::read(&path).unwrap()[n * 512 + 100];
+
+        let mut pages: Vec<Page> = (0..3).map(|_| file.allocate_page().unwrap()).collect();
+        for page in &mut pages {
+            page.data[100] = page.page_number as u8;
+            file.write_page(page).unwrap();
+        }
+        assert_eq!(file.queued_pages(), 3);
+        assert_eq!(file.read_page(2).unwrap().data[100], 2);
+        assert_eq!(on_disk(2), 0);
+
+        // A fourth page fills the queue, and all four go out
+        let mut page = file.allocate_page().unwrap();
+        page.data[100] = 4;
+        file.write_page(&page).unwrap();
+        assert_eq!(file.queued_pages(), 0);
+        assert_eq!((1..5).map(on_disk).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+        pages[0].data[100] = 9;
+        file.write_page(&pages[0]).unwrap();
+        file.flush().unwrap();
+        assert_eq!(on_disk(1), 9);
+    }
+
 }
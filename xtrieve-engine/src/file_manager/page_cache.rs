@@ -5,7 +5,6 @@
 use lru::LruCache;
 use parking_lot::RwLock;
 use std::num::NonZeroUsize;
-use std::sync::Arc;
 
 use crate::storage::page::Page;
 
@@ -21,7 +20,6 @@ struct CacheKey {
 struct CachedPage {
     page: Page,
     dirty: bool,
-    pin_count: u32,
 }
 
 /// Thread-safe LRU page cache
@@ -80,7 +78,6 @@ impl PageCache {
         let cached = CachedPage {
             page,
             dirty,
-            pin_count: 0,
         };
 
         let mut cache = self.cache.write();
@@ -0,0 +1,117 @@
+//! Per-file cache of records read by address
+//!
+//! Lookups that keep coming back to the same records would otherwise take
+//! the page from the page cache and copy the record out of it each time.
+//! The cache holds the bytes read at an address, the page and the offset
+//! in it they start at, and a write of that page drops them. A record
+//! read while a write was dropping entries isn't kept, since its page may
+//! be the old one.
+
+use lru::LruCache;
+use std::num::NonZeroUsize;
+
+use crate::storage::record::RecordAddress;
+
+/// Records kept per open file
+pub const RECORD_CACHE_ENTRIES: usize = 256;
+
+/// Cache statistics
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecordCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// LRU map of record address (page and offset in it) to record bytes
+pub struct RecordCache {
+    records: LruCache<RecordAddress, Vec<u8>>,
+    /// Bumped by every invalidation
+    generation: u64,
+    stats: RecordCacheStats,
+}
+
+impl RecordCache {
+    pub fn new(capacity: usize) -> Self {
+        RecordCache {
+            records: LruCache::new(NonZeroUsize::new(capacity.max(1)).unwrap()),
+            generation: 0,
+            stats: RecordCacheStats::default(),
+        }
+    }
+
+    /// Record read at `address`, if cached
+    pub fn get(&mut self, address: RecordAddress) -> Option<Vec<u8>> {
+        let found = self.records.get(&address).cloned();
+        match found {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        found
+    }
+
+    /// Generation to pass to `put` for a record about to be read
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Keep a record, unless a write has dropped entries since
+    /// `generation` was taken
+    pub fn put(&mut self, generation: u64, address: RecordAddress, data: &[u8]) {
+        if generation == self.generation {
+            self.records.put(address, data.to_vec());
+        }
+    }
+
+    /// Drop the records read from a page about to change
+    pub fn invalidate_page(&mut self, page_number: u32) {
+        self.generation += 1;
+        let stale: Vec<RecordAddress> = self
+            .records
+            .iter()
+            .filter(|(address, _)| address.page == page_number)
+            .map(|(address, _)| *address)
+            .collect();
+        for address in stale {
+            self.records.pop(&address);
+        }
+    }
+
+    /// Drop every record, for a change to the whole file
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        self.records.clear();
+    }
+
+    pub fn stats(&self) -> RecordCacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_cache() {
+        let mut cache = RecordCache::new(2);
+        let at = |page, slot| RecordAddress { page, slot };
+
+        let generation = cache.generation();
+        cache.put(generation, at(1, 0), b"ONE");
+        cache.put(generation, at(1, 1), b"TWO");
+        assert_eq!(cache.get(at(1, 0)).as_deref(), Some(&b"ONE"[..]));
+        assert_eq!(cache.get(at(2, 0)), None);
+        assert_eq!(cache.stats(), RecordCacheStats { hits: 1, misses: 1 });
+
+        // The least recently used record makes way
+        cache.put(generation, at(2, 0), b"THREE");
+        assert_eq!(cache.get(at(1, 1)), None);
+
+        // A write drops the page's records, and any read begun before it
+        cache.invalidate_page(1);
+        assert_eq!(cache.get(at(1, 0)), None);
+        assert!(cache.get(at(2, 0)).is_some());
+        cache.put(generation, at(1, 0), b"OLD");
+        assert_eq!(cache.get(at(1, 0)), None);
+    }
+}
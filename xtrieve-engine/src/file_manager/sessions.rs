@@ -0,0 +1,214 @@
+//! Session registry - what each session holds open
+//!
+//! Sessions come and go with client connections. The registry remembers
+//! the files a session opened, with an id for each open so two cursors on
+//! one file stay apart, a nonce that position blocks must present and
+//! whether the open was read-only, and
+//! how many connections are using it, so a
+//! session whose last connection drops can be torn down: transaction
+//! aborted, locks released and its opens taken off the file ref counts.
+
+use parking_lot::Mutex;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::locking::SessionId;
+
+/// One open of a file
+#[derive(Debug, Clone, Copy)]
+struct Open {
+    nonce: u32,
+    read_only: bool,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    /// Cursor ids of the opens per file not yet closed
+    open_files: HashMap<PathBuf, BTreeMap<u8, Open>>,
+    /// Connections running requests on this session
+    connections: u32,
+}
+
+/// Sessions known to the engine
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<SessionId, SessionState>>,
+    /// Keys the nonces, so they can't be guessed from one another
+    nonce_keys: RandomState,
+    opens: AtomicU64,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A connection starts using a session
+    pub fn attach(&self, session: SessionId) {
+        self.sessions.lock().entry(session).or_default().connections += 1;
+    }
+
+    /// A connection stops using a session. Returns true when it was the
+    /// last one, so the session should be ended
+    pub fn detach(&self, session: SessionId) -> bool {
+        let mut sessions = self.sessions.lock();
+        match sessions.get_mut(&session) {
+            Some(state) => {
+                state.connections = state.connections.saturating_sub(1);
+                state.connections == 0
+            }
+            None => true,
+        }
+    }
+
+    /// Record an Open, returning the id of its cursor (the lowest one the
+    /// session isn't using on this file) and its nonce, or `None` when all
+    /// ids are taken
+    pub fn opened(&self, session: SessionId, path: &Path, read_only: bool) -> Option<(u8, u32)> {
+        let mut sessions = self.sessions.lock();
+        let state = sessions.entry(session).or_default();
+        let cursors = state.open_files.entry(path.to_path_buf()).or_default();
+        let id = (1..=u8::MAX).find(|id| !cursors.contains_key(id))?;
+        let nonce = self.nonce_keys.hash_one(self.opens.fetch_add(1, Ordering::Relaxed)) as u32;
+        cursors.insert(id, Open { nonce, read_only });
+        Some((id, nonce))
+    }
+
+    /// Check that a position block names an open the session still has
+    pub fn is_open(&self, session: SessionId, path: &Path, cursor: u8, nonce: u32) -> bool {
+        let sessions = self.sessions.lock();
+        sessions
+            .get(&session)
+            .and_then(|state| state.open_files.get(path))
+            .and_then(|cursors| cursors.get(&cursor))
+            .is_some_and(|open| open.nonce == nonce)
+    }
+
+    /// Check whether a cursor was opened read-only
+    pub fn is_read_only(&self, session: SessionId, path: &Path, cursor: u8) -> bool {
+        let sessions = self.sessions.lock();
+        sessions
+            .get(&session)
+            .and_then(|state| state.open_files.get(path))
+            .and_then(|cursors| cursors.get(&cursor))
+            .is_some_and(|open| open.read_only)
+    }
+
+    /// Record a Close of one cursor. An id the session doesn't know (a
+    /// position block from an older server) closes its lowest one. Returns
+    /// true if the session still has the file open through another cursor
+    pub fn closed(&self, session: SessionId, path: &Path, cursor: u8) -> bool {
+        let mut sessions = self.sessions.lock();
+        let Some(state) = sessions.get_mut(&session) else { return false };
+        let Some(cursors) = state.open_files.get_mut(path) else { return false };
+        if cursors.remove(&cursor).is_none() {
+            cursors.pop_first();
+        }
+        if cursors.is_empty() {
+            state.open_files.remove(path);
+            return false;
+        }
+        true
+    }
+
+    /// Sessions that have a file open
+    pub fn holders(&self, path: &Path) -> Vec<SessionId> {
+        let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+        let path = canonical(path);
+        self.sessions
+            .lock()
+            .iter()
+            .filter(|(_, state)| state.open_files.keys().any(|open| canonical(open) == path))
+            .map(|(&session, _)| session)
+            .collect()
+    }
+
+    /// Forget a session, returning the files it still had open and how
+    /// many times each
+    pub fn remove(&self, session: SessionId) -> Vec<(PathBuf, u32)> {
+        self.sessions
+            .lock()
+            .remove(&session)
+            .map(|state| {
+                state
+                    .open_files
+                    .into_iter()
+                    .map(|(path, cursors)| (path, cursors.len() as u32))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of sessions known
+    pub fn len(&self) -> usize {
+        self.sessions.lock().len()
+    }
+
+    /// Check if no sessions are known
+    pub fn is_empty(&self) -> bool {
+        self.sessions.lock().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_registry() {
+        let registry = SessionRegistry::new();
+        let path = Path::new("/data/CUST.DAT");
+
+        registry.attach(1);
+        registry.attach(1);
+        assert_eq!(registry.opened(1, path, false).unwrap().0, 1);
+        assert_eq!(registry.opened(1, path, false).unwrap().0, 2);
+        assert_eq!(registry.opened(1, Path::new("/data/ORDERS.DAT"), true).unwrap().0, 1);
+        assert!(registry.is_read_only(1, Path::new("/data/ORDERS.DAT"), 1));
+        assert!(!registry.is_read_only(1, path, 1));
+        assert!(!registry.closed(1, Path::new("/data/ORDERS.DAT"), 1));
+        assert_eq!(registry.holders(path), [1]);
+        assert!(registry.holders(Path::new("/data/ORDERS.DAT")).is_empty());
+
+        // Still used by the second connection
+        assert!(!registry.detach(1));
+        assert!(registry.detach(1));
+
+        assert_eq!(registry.remove(1), vec![(path.to_path_buf(), 2)]);
+        assert!(registry.is_empty());
+        assert!(registry.remove(1).is_empty());
+    }
+
+    #[test]
+    fn test_cursor_ids() {
+        let registry = SessionRegistry::new();
+        let path = Path::new("/data/CUST.DAT");
+
+        let (first, nonce) = registry.opened(1, path, false).unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(registry.opened(1, path, false).unwrap().0, 2);
+        assert_eq!(registry.opened(2, path, false).unwrap().0, 1);
+        assert!(registry.is_open(1, path, 1, nonce));
+        assert!(!registry.is_open(2, path, 1, nonce));
+
+        // Closing the first cursor leaves the second open, and frees its id
+        // for an open with a new nonce
+        assert!(registry.closed(1, path, 1));
+        assert!(!registry.is_open(1, path, 1, nonce));
+        let (reused, renewed) = registry.opened(1, path, false).unwrap();
+        assert_eq!(reused, 1);
+        assert_ne!(renewed, nonce);
+        // An unknown id closes the lowest
+        assert!(registry.closed(1, path, 0));
+        assert!(!registry.closed(1, path, 2));
+        assert!(!registry.closed(1, path, 2));
+
+        for _ in 1..=u8::MAX {
+            registry.opened(3, path, false).unwrap();
+        }
+        assert_eq!(registry.opened(3, path, false), None);
+    }
+}
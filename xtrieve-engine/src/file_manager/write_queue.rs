@@ -0,0 +1,116 @@
+//! Write queue - page writes held back and written in page order
+//!
+//! With a queue, a page write returns once the page is queued. Pages are
+//! kept in page order, a later write of a page replacing the one waiting,
+//! and go out when the queue fills, when the file is synced (a flush,
+//! a transaction's End, a close) or when the daemon's writer thread comes
+//! round. Pages next to each other go out as one run, in one write.
+//! Reads look in the queue first, so a queued page is never read stale.
+
+use std::collections::BTreeMap;
+
+/// A page waiting to be written, as stored
+struct Queued {
+    data: Vec<u8>,
+    /// Goes to an index file rather than the .DAT
+    to_index: bool,
+}
+
+/// Pages next to each other, in page order, going to the same file
+pub struct Run {
+    pub first: u32,
+    pub pages: Vec<Vec<u8>>,
+    pub to_index: bool,
+}
+
+/// Page writes of one file waiting to go out
+pub struct WriteQueue {
+    pages: BTreeMap<u32, Queued>,
+    /// Most pages kept before the writer drains the queue itself; 0 writes
+    /// every page through
+    limit: usize,
+}
+
+impl WriteQueue {
+    pub fn new(limit: usize) -> Self {
+        WriteQueue {
+            pages: BTreeMap::new(),
+            limit,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    /// Queue a page, replacing a write of it still waiting. Whether the
+    /// queue is now full
+    pub fn push(&mut self, page_number: u32, data: Vec<u8>, to_index: bool) -> bool {
+        self.pages.insert(page_number, Queued { data, to_index });
+        self.pages.len() >= self.limit
+    }
+
+    /// A queued page, as stored
+    pub fn get(&self, page_number: u32) -> Option<&[u8]> {
+        self.pages.get(&page_number).map(|queued| queued.data.as_slice())
+    }
+
+    /// Drop a queued write of a page about to be written through
+    pub fn remove(&mut self, page_number: u32) {
+        self.pages.remove(&page_number);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Empty the queue into runs of adjacent pages, in page order
+    pub fn take(&mut self) -> Vec<Run> {
+        let mut runs: Vec<Run> = Vec::new();
+        for (page_number, queued) in std::mem::take(&mut self.pages) {
+            match runs.last_mut() {
+                Some(run) if run.to_index == queued.to_index && run.first + run.pages.len() as u32 == page_number => {
+                    run.pages.push(queued.data);
+                }
+                _ => runs.push(Run {
+                    first: page_number,
+                    pages: vec![queued.data],
+                    to_index: queued.to_index,
+                }),
+            }
+        }
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs_of_adjacent_pages() {
+        let mut queue = WriteQueue::new(8);
+        assert!(!queue.push(5, vec![5], false));
+        assert!(!queue.push(3, vec![3], false));
+        assert!(!queue.push(4, vec![4], false));
+        assert!(!queue.push(4, vec![44], false));
+        assert!(!queue.push(9, vec![9], false));
+        assert!(!queue.push(10, vec![10], true));
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.get(4), Some(&[44u8][..]));
+
+        let runs = queue.take();
+        let shape: Vec<_> = runs.iter().map(|run| (run.first, run.pages.len(), run.to_index)).collect();
+        assert_eq!(shape, vec![(3, 3, false), (9, 1, false), (10, 1, true)]);
+        assert_eq!(runs[0].pages, vec![vec![3], vec![44], vec![5]]);
+        assert!(queue.is_empty());
+
+        let mut small = WriteQueue::new(2);
+        assert!(!small.push(1, vec![1], false));
+        assert!(small.push(2, vec![2], false));
+    }
+}
@@ -0,0 +1,224 @@
+//! Golden-file compatibility checks against real Btrieve files
+//!
+//! Runs a fixed series of operations over each `.DAT` file of a corpus,
+//! by default `data/fixtures`, and compares what comes back with the
+//! transcript recorded beside it in `<file>.golden`: Open, Stat, every
+//! record by Step, then every record by Get along each key. A change in
+//! what the storage layer makes of a file shows up as the first line that
+//! differs.
+//!
+//! ```text
+//! cargo test -p xtrieve-engine --features golden
+//! XTRIEVE_GOLDEN_DIR=/path/to/corpus cargo test -p xtrieve-engine --features golden
+//! XTRIEVE_GOLDEN_RECORD=1 cargo test -p xtrieve-engine --features golden
+//! ```
+//!
+//! Recording writes the transcripts instead of checking them; do it only
+//! when a change in results is meant. Each line holds an operation, its
+//! status, and for records their length and a SHA-256 prefix (the full
+//! data would make transcripts as large as the files). The files are
+//! worked on as copies, so the corpus is never written to.
+//!
+//! The transcripts record what Xtrieve does, not what Btrieve did: there
+//! is no Btrieve to record from. A line known to differ from Btrieve is
+//! marked with a leading `!` and says why after ` # `, for example
+//! `!get_next 0 42 # Btrieve reads all 5 records along key 0`. Marked
+//! lines are still checked, so a change in them fails like any other;
+//! once one matches Btrieve, record again and drop the mark. Recording
+//! keeps the marks of lines that come out the same.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::operations::{Engine, OperationCode, OperationRequest, OperationResponse};
+use crate::StatusCode;
+
+/// Directory holding the corpus unless `XTRIEVE_GOLDEN_DIR` names another
+const DEFAULT_CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../data/fixtures");
+
+/// Records read by one Step or Get run before giving up on reaching the end
+const MAX_RECORDS: usize = 100_000;
+
+/// The `.DAT` files of a corpus, in name order
+fn corpus(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("reading corpus {}: {}", dir.display(), e))
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("dat")))
+        .collect();
+    files.sort();
+    files
+}
+
+fn golden_path(fixture: &Path) -> PathBuf {
+    let mut name = fixture.as_os_str().to_owned();
+    name.push(".golden");
+    PathBuf::from(name)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Length and SHA-256 prefix of a record
+fn digest(data: &[u8]) -> String {
+    format!("{} {}", data.len(), hex(&Sha256::digest(data)[..8]))
+}
+
+/// A line of a recorded transcript: the operation and result, and why it
+/// is known to differ from Btrieve if it is marked
+struct Expected<'a> {
+    line: &'a str,
+    known_bad: Option<&'a str>,
+}
+
+impl<'a> Expected<'a> {
+    fn parse(text: &'a str) -> Self {
+        match text.strip_prefix('!') {
+            Some(marked) => {
+                let (line, why) = marked.split_once(" # ").unwrap_or((marked, ""));
+                Expected { line, known_bad: Some(why) }
+            }
+            None => Expected { line: text, known_bad: None },
+        }
+    }
+}
+
+/// The transcript to write for `actual`, keeping the marks of `previous`
+/// lines that are unchanged
+fn record(actual: &[String], previous: &[Expected]) -> String {
+    actual
+        .iter()
+        .enumerate()
+        .map(|(i, line)| match previous.get(i) {
+            Some(Expected { line: same, known_bad: Some(why) }) if same == line => format!("!{} # {}\n", line, why),
+            _ => format!("{}\n", line),
+        })
+        .collect()
+}
+
+/// What the engine makes of a file: one line per operation
+fn transcript(fixture: &Path) -> Vec<String> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(fixture.file_name().unwrap());
+    fs::copy(fixture, &path).unwrap();
+
+    let engine = Engine::new(256);
+    let run = |operation, position_block: &[u8], key_number: i32| -> OperationResponse {
+        engine.execute(1, OperationRequest {
+            operation,
+            file_path: Some(path.to_string_lossy().to_string()),
+            position_block: position_block.to_vec(),
+            key_number,
+            ..Default::default()
+        })
+    };
+    let mut lines = Vec::new();
+
+    let open = run(OperationCode::Open, &[], 0);
+    lines.push(format!("open {}", open.status.as_raw()));
+    if open.status != StatusCode::Success {
+        return lines;
+    }
+    let block = open.position_block;
+
+    let stat = run(OperationCode::Stat, &block, 0);
+    lines.push(format!("stat {} {}", stat.status.as_raw(), hex(&stat.data_buffer)));
+    let num_keys = match stat.data_buffer.get(4..6) {
+        Some(n) => u16::from_le_bytes([n[0], n[1]]) as i32,
+        None => 0,
+    };
+
+    // Every record in physical order, then along each key
+    let mut walk = |first: OperationCode, next: OperationCode, name: &str, key_number: i32| {
+        let mut response = run(first, &block, key_number);
+        for n in 0..MAX_RECORDS {
+            let op = if n == 0 { "first" } else { "next" };
+            let status = response.status.as_raw();
+            if response.status != StatusCode::Success {
+                lines.push(format!("{}_{} {} {}", name, op, key_number, status));
+                return;
+            }
+            lines.push(format!(
+                "{}_{} {} {} {} {}",
+                name,
+                op,
+                key_number,
+                status,
+                digest(&response.data_buffer),
+                hex(&response.key_buffer)
+            ));
+            response = run(next, &response.position_block, key_number);
+        }
+        lines.push(format!("{} {} stopped after {} records", name, key_number, MAX_RECORDS));
+    };
+    walk(OperationCode::StepFirst, OperationCode::StepNext, "step", 0);
+    for key in 0..num_keys {
+        walk(OperationCode::GetFirst, OperationCode::GetNext, "get", key);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_golden_files() {
+        let dir = std::env::var_os("XTRIEVE_GOLDEN_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CORPUS));
+        let recording = std::env::var_os("XTRIEVE_GOLDEN_RECORD").is_some();
+        let fixtures = corpus(&dir);
+        assert!(!fixtures.is_empty(), "no .DAT files in {}", dir.display());
+
+        let mut failures = Vec::new();
+        for fixture in &fixtures {
+            let actual = transcript(fixture);
+            let golden = golden_path(fixture);
+            let recorded = fs::read_to_string(&golden);
+            let expected: Vec<Expected> = recorded.iter().flat_map(|text| text.lines()).map(Expected::parse).collect();
+            if recording {
+                fs::write(&golden, record(&actual, &expected)).unwrap();
+                continue;
+            }
+            if recorded.is_err() {
+                failures.push(format!("{}: no transcript, record one with XTRIEVE_GOLDEN_RECORD=1", fixture.display()));
+                continue;
+            }
+            let differs = (0..expected.len().max(actual.len()))
+                .find(|&i| expected.get(i).map(|e| e.line) != actual.get(i).map(String::as_str));
+            if let Some(i) = differs {
+                let known_bad = match expected.get(i).and_then(|e| e.known_bad) {
+                    Some(why) => format!("\n  (marked as differing from Btrieve: {}; if it now matches, drop the mark)", why),
+                    None => String::new(),
+                };
+                failures.push(format!(
+                    "{}: line {} differs\n  expected: {}\n  actual:   {}{}",
+                    fixture.display(),
+                    i + 1,
+                    expected.get(i).map(|e| e.line).unwrap_or("(end)"),
+                    actual.get(i).map(String::as_str).unwrap_or("(end)"),
+                    known_bad
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_known_bad_marks() {
+        let recorded = ["open 0", "!get_next 0 42 # Btrieve reads all 5 records"];
+        let expected: Vec<Expected> = recorded.iter().map(|line| Expected::parse(line)).collect();
+        assert_eq!((expected[0].line, expected[0].known_bad), ("open 0", None));
+        assert_eq!((expected[1].line, expected[1].known_bad), ("get_next 0 42", Some("Btrieve reads all 5 records")));
+
+        // Recording keeps a mark only while its line stays the same
+        let same = ["open 0".to_string(), "get_next 0 42".to_string()];
+        assert_eq!(record(&same, &expected), "open 0\n!get_next 0 42 # Btrieve reads all 5 records\n");
+        let changed = ["open 0".to_string(), "get_next 0 9".to_string()];
+        assert_eq!(record(&changed, &expected), "open 0\nget_next 0 9\n");
+    }
+}
@@ -3,11 +3,21 @@
 //! This crate provides the core storage engine for reading and writing
 //! Btrieve 5.1 compatible database files.
 
+pub mod cancel;
 pub mod error;
 pub mod storage;
 pub mod file_manager;
+pub mod metrics;
 pub mod operations;
 pub mod protocol;
+pub mod trace;
+#[cfg(all(test, feature = "golden"))]
+mod golden;
+#[cfg(all(test, feature = "model"))]
+mod model;
 
-pub use error::{BtrieveError, BtrieveResult, StatusCode};
-pub use protocol::{Request, Response, POSITION_BLOCK_SIZE, DEFAULT_PORT};
+pub use cancel::CancelToken;
+pub use error::{BtrieveError, BtrieveResult, ErrorDetail, StatusCode};
+pub use file_manager::locking::LockReport;
+pub use metrics::{EngineStats, Metrics};
+pub use protocol::{Request, Response, ServerInfo, POSITION_BLOCK_SIZE, DEFAULT_PORT};
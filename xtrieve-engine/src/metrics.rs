@@ -0,0 +1,37 @@
+//! Metrics - what the engine does, for an embedder's telemetry
+//!
+//! A `Metrics` registered with `Engine::add_metrics` is told of each
+//! operation as it finishes, with its status and how long it took, and of
+//! each wait for a record lock as it ends. The counters the engine keeps
+//! itself (page cache, record caches, lock conflicts) are read when wanted
+//! with `Engine::stats`. Calls come on the thread that ran the operation,
+//! so implementations should be quick and do their own locking.
+
+use std::time::Duration;
+
+use crate::error::StatusCode;
+use crate::file_manager::locking::LockStats;
+use crate::file_manager::page_cache::CacheStats;
+use crate::file_manager::record_cache::RecordCacheStats;
+use crate::operations::OperationCode;
+
+/// Receiver of the engine's events, for counting and timing them
+pub trait Metrics: Send + Sync {
+    /// An operation finished, or was refused, with this status
+    fn operation(&self, _operation: OperationCode, _status: StatusCode, _elapsed: Duration) {}
+
+    /// A wait for a record lock ended, with the lock or after timing out
+    fn lock_wait(&self, _waited: Duration, _timed_out: bool) {}
+}
+
+/// The engine's own counters at one moment
+#[derive(Debug, Clone, Default)]
+pub struct EngineStats {
+    pub page_cache: CacheStats,
+    /// Pages in the page cache
+    pub cached_pages: usize,
+    /// Summed over the open files
+    pub record_cache: RecordCacheStats,
+    pub locks: LockStats,
+    pub open_files: usize,
+}
@@ -0,0 +1,283 @@
+//! Differential tests of the engine against a model
+//!
+//! proptest generates sequences of record operations on a file with one
+//! unique 4-byte unsigned key and applies each to the engine and to a
+//! `BTreeMap` holding what the file should contain. Every status must
+//! match the model's, every key returned must be the one the model names,
+//! and a full GetFirst/GetNext walk and Step walk after the sequence must
+//! see exactly the model's keys. A failure shrinks to a short sequence,
+//! which proptest saves under `proptest-regressions/` to be run first
+//! from then on.
+//!
+//! ```text
+//! cargo test -p xtrieve-engine --features model model
+//! PROPTEST_CASES=2000 cargo test -p xtrieve-engine --features model model
+//! ```
+//!
+//! Keys are drawn from a small range so sequences revisit them, and the
+//! page size is the smallest so a few dozen inserts split index pages.
+//!
+//! The tests are behind the `model` feature, like the golden files, as
+//! they take longer than the rest. After a Delete the model's cursor stays
+//! on the deleted key, so Next and Previous must reach its neighbours.
+
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+
+use crate::operations::{Engine, OperationCode, OperationRequest, OperationResponse};
+use crate::StatusCode;
+
+/// Record length: the key, then a payload
+const RECORD_LEN: usize = 8;
+
+/// Keys are drawn from `0..KEY_SPACE`
+const KEY_SPACE: u32 = 200;
+
+/// Keys walked before a scan is taken to be stuck
+const MAX_WALK: usize = 10_000;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(u32, u32),
+    GetEqual(u32),
+    GetGreaterOrEqual(u32),
+    GetLessOrEqual(u32),
+    /// GetEqual, then Delete the record found
+    Delete(u32),
+    GetFirst,
+    GetLast,
+    /// GetNext `n` times from the current record
+    Next(u8),
+    /// GetPrevious `n` times from the current record
+    Previous(u8),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let key = 0..KEY_SPACE;
+    prop_oneof![
+        4 => (key.clone(), any::<u32>()).prop_map(|(k, v)| Op::Insert(k, v)),
+        2 => key.clone().prop_map(Op::GetEqual),
+        1 => key.clone().prop_map(Op::GetGreaterOrEqual),
+        1 => key.clone().prop_map(Op::GetLessOrEqual),
+        2 => key.prop_map(Op::Delete),
+        1 => Just(Op::GetFirst),
+        1 => Just(Op::GetLast),
+        1 => (1..5u8).prop_map(Op::Next),
+        1 => (1..5u8).prop_map(Op::Previous),
+    ]
+}
+
+/// The engine side: one open file and its position block
+struct Subject {
+    engine: Engine,
+    position_block: Vec<u8>,
+    _dir: tempfile::TempDir,
+}
+
+impl Subject {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("MODEL.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(64);
+
+        // 512-byte pages, key 0: 4-byte unsigned binary at the start
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&(RECORD_LEN as u16).to_le_bytes());
+        spec[2..4].copy_from_slice(&512u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[26] = 14;
+        let create = engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.clone()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+        let open = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        });
+        assert_eq!(open.status, StatusCode::Success);
+        Subject { engine, position_block: open.position_block, _dir: dir }
+    }
+
+    fn run(&mut self, operation: OperationCode, key: Option<u32>, data: Vec<u8>) -> OperationResponse {
+        let response = self.engine.execute(1, OperationRequest {
+            operation,
+            position_block: self.position_block.clone(),
+            data_buffer: data,
+            key_buffer: key.map(|k| k.to_le_bytes().to_vec()).unwrap_or_default(),
+            ..Default::default()
+        });
+        if !response.position_block.is_empty() {
+            self.position_block = response.position_block.clone();
+        }
+        response
+    }
+
+    /// Keys of a whole walk: `first` then `next` until end of file
+    fn walk(&mut self, first: OperationCode, next: OperationCode) -> Result<Vec<u32>, String> {
+        let mut keys = Vec::new();
+        let mut response = self.run(first, None, Vec::new());
+        while response.status == StatusCode::Success {
+            keys.push(match first {
+                OperationCode::StepFirst => key_of(&response.data_buffer),
+                _ => key_of(&response.key_buffer),
+            });
+            if keys.len() > MAX_WALK {
+                return Err(format!("{:?} walk doesn't end", first));
+            }
+            response = self.run(next, None, Vec::new());
+        }
+        match response.status {
+            StatusCode::EndOfFile => Ok(keys),
+            status => Err(format!("{:?} walk ended with {:?}", first, status)),
+        }
+    }
+}
+
+fn key_of(buffer: &[u8]) -> u32 {
+    buffer.get(..4).map_or(u32::MAX, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// What the file should hold, and where the model's cursor is
+#[derive(Default)]
+struct Model {
+    records: BTreeMap<u32, u32>,
+    current: Option<u32>,
+}
+
+impl Model {
+    /// Status and key the engine should answer `op` with
+    fn apply(&mut self, op: &Op) -> (StatusCode, Option<u32>) {
+        let found = |key: Option<u32>| match key {
+            Some(key) => (StatusCode::Success, Some(key)),
+            None => (StatusCode::KeyNotFound, None),
+        };
+        let (status, key) = match *op {
+            Op::Insert(key, value) => {
+                if self.records.contains_key(&key) {
+                    return (StatusCode::DuplicateKey, None);
+                }
+                self.records.insert(key, value);
+                (StatusCode::Success, Some(key))
+            }
+            Op::GetEqual(key) => found(self.records.contains_key(&key).then_some(key)),
+            Op::GetGreaterOrEqual(key) => found(self.records.range(key..).next().map(|(&k, _)| k)),
+            Op::GetLessOrEqual(key) => found(self.records.range(..=key).next_back().map(|(&k, _)| k)),
+            Op::Delete(key) => {
+                if self.records.remove(&key).is_none() {
+                    return (StatusCode::KeyNotFound, None);
+                }
+                // The cursor stays where the deleted key was, between its
+                // neighbours, so Next and Previous reach them
+                self.current = Some(key);
+                return (StatusCode::Success, None);
+            }
+            Op::GetFirst => (StatusCode::EndOfFile, self.records.keys().next().copied()),
+            Op::GetLast => (StatusCode::EndOfFile, self.records.keys().next_back().copied()),
+            Op::Next(_) | Op::Previous(_) => unreachable!("walked one step at a time"),
+        };
+        let status = match (status, key) {
+            (StatusCode::EndOfFile, Some(_)) => StatusCode::Success,
+            (status, _) => status,
+        };
+        if status == StatusCode::Success {
+            self.current = key;
+        }
+        (status, key)
+    }
+
+    /// Key one GetNext (or GetPrevious) from the current record reaches
+    fn step(&mut self, forward: bool) -> Option<(StatusCode, Option<u32>)> {
+        let current = self.current?;
+        let key = if forward {
+            self.records.range(current + 1..).next().map(|(&k, _)| k)
+        } else {
+            self.records.range(..current).next_back().map(|(&k, _)| k)
+        };
+        Some(match key {
+            Some(key) => {
+                self.current = Some(key);
+                (StatusCode::Success, Some(key))
+            }
+            None => (StatusCode::EndOfFile, None),
+        })
+    }
+}
+
+/// Run `ops` on both sides, failing at the first difference
+fn check(ops: &[Op]) -> Result<(), TestCaseError> {
+    let mut subject = Subject::new();
+    let mut model = Model::default();
+
+    for (n, op) in ops.iter().enumerate() {
+        let steps = match *op {
+            Op::Next(count) => Some((count, true)),
+            Op::Previous(count) => Some((count, false)),
+            _ => None,
+        };
+        if let Some((count, forward)) = steps {
+            for _ in 0..count {
+                // Without a current record the engine's answer isn't pinned down
+                let Some((status, key)) = model.step(forward) else { break };
+                let operation = if forward { OperationCode::GetNext } else { OperationCode::GetPrevious };
+                let response = subject.run(operation, None, Vec::new());
+                prop_assert_eq!(response.status, status, "op {} {:?}", n, op);
+                if let Some(key) = key {
+                    prop_assert_eq!(key_of(&response.key_buffer), key, "op {} {:?}", n, op);
+                }
+                if status != StatusCode::Success {
+                    model.current = None;
+                    break;
+                }
+            }
+            continue;
+        }
+
+        let response = match *op {
+            Op::Insert(key, value) => {
+                subject.run(OperationCode::Insert, None, [key.to_le_bytes(), value.to_le_bytes()].concat())
+            }
+            Op::GetEqual(key) => subject.run(OperationCode::GetEqual, Some(key), Vec::new()),
+            Op::GetGreaterOrEqual(key) => subject.run(OperationCode::GetGreaterOrEqual, Some(key), Vec::new()),
+            Op::GetLessOrEqual(key) => subject.run(OperationCode::GetLessOrEqual, Some(key), Vec::new()),
+            Op::Delete(key) => match subject.run(OperationCode::GetEqual, Some(key), Vec::new()) {
+                found if found.status == StatusCode::Success => subject.run(OperationCode::Delete, None, Vec::new()),
+                missing => missing,
+            },
+            Op::GetFirst => subject.run(OperationCode::GetFirst, None, Vec::new()),
+            Op::GetLast => subject.run(OperationCode::GetLast, None, Vec::new()),
+            Op::Next(_) | Op::Previous(_) => unreachable!(),
+        };
+        let (status, key) = model.apply(op);
+        prop_assert_eq!(response.status, status, "op {} {:?}", n, op);
+        if let (Some(key), false) = (key, matches!(op, Op::Insert(..))) {
+            prop_assert_eq!(key_of(&response.key_buffer), key, "op {} {:?}", n, op);
+        }
+        if matches!(op, Op::Insert(..)) && status == StatusCode::Success {
+            // Insert leaves the cursor on the new record
+            model.current = key;
+        }
+    }
+
+    let expected: Vec<u32> = model.records.keys().copied().collect();
+    let walked = subject.walk(OperationCode::GetFirst, OperationCode::GetNext).map_err(TestCaseError::fail)?;
+    prop_assert_eq!(&walked, &expected, "GetFirst/GetNext walk");
+    let mut stepped = subject.walk(OperationCode::StepFirst, OperationCode::StepNext).map_err(TestCaseError::fail)?;
+    stepped.sort_unstable();
+    prop_assert_eq!(&stepped, &expected, "StepFirst/StepNext walk");
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn model_matches_engine(ops in prop::collection::vec(op(), 1..300)) {
+        check(&ops)?;
+    }
+}
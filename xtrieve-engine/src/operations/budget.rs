@@ -0,0 +1,59 @@
+//! Memory budget - a bound on what one operation may gather in memory
+//!
+//! Some operations read a whole structure of a file before they answer:
+//! every index entry for a read in key order, every page for the rewrite
+//! of Set and Clear Owner, the whole recycle bin for Get Deleted and
+//! Undelete. On a big enough file that is more memory than the daemon has.
+//! With a budget (`Engine::with_memory_budget`) each such operation counts
+//! what it is about to hold and fails with status 61 once it would pass
+//! the budget, before allocating it.
+
+use crate::error::{BtrieveError, BtrieveResult, StatusCode};
+
+/// What one operation has gathered so far, against its budget
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBudget {
+    /// Bytes an operation may hold; `None` for no limit
+    limit: Option<usize>,
+    used: usize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        MemoryBudget { limit, used: 0 }
+    }
+
+    /// Count `bytes` more; status 61 if that passes the budget
+    pub fn charge(&mut self, bytes: usize) -> BtrieveResult<()> {
+        let used = self.used.saturating_add(bytes);
+        if self.limit.is_some_and(|limit| used > limit) {
+            return Err(BtrieveError::Status(StatusCode::WorkSpaceTooSmall));
+        }
+        self.used = used;
+        Ok(())
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charge() {
+        let mut budget = MemoryBudget::new(Some(100));
+        assert!(budget.charge(60).is_ok());
+        assert!(budget.charge(40).is_ok());
+        assert_eq!(
+            budget.charge(1).unwrap_err().status_code(),
+            StatusCode::WorkSpaceTooSmall
+        );
+        assert_eq!(budget.used(), 100);
+
+        let mut unlimited = MemoryBudget::new(None);
+        assert!(unlimited.charge(usize::MAX).is_ok());
+    }
+}
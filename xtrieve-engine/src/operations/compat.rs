@@ -0,0 +1,99 @@
+//! Strict Btrieve 5.1 compatibility
+//!
+//! An engine made with `Engine::with_strict_compat` behaves as the original
+//! record manager does where Xtrieve would otherwise be more lenient or
+//! offer more, for checking an application against it:
+//!
+//! - the Xtrieve extension operations (90-99) answer status 1
+//! - Create refuses records under 4 bytes (status 28) and key lengths the
+//!   key type doesn't allow (status 29), and files whose pages the original
+//!   couldn't read: compressed, with a recycle bin, roll-forward log or
+//!   index files, or sealed with the daemon's page key (status 40)
+//! - Set Owner refuses to encrypt (status 40), the pages being sealed in a
+//!   format of Xtrieve's own
+//! - Insert and Update refuse a data buffer shorter than the record length
+//!   of a fixed-length file instead of padding it (status 22)
+//! - Stat leaves Xtrieve's own file flags out
+
+use crate::error::{BtrieveError, BtrieveResult, StatusCode};
+use crate::file_manager::cursor::PositionBlock;
+use crate::storage::fcr::FileFlags;
+use crate::storage::key::KeyType;
+
+use super::dispatcher::{Engine, OperationCode, OperationRequest};
+use super::file_ops::CreateSpec;
+
+/// Smallest record Btrieve 5.1 creates a file for
+const MIN_RECORD_LENGTH: u16 = 4;
+
+/// File flags Btrieve 5.1 doesn't know
+pub const XTRIEVE_FILE_FLAGS: FileFlags = FileFlags::RECYCLE
+    .union(FileFlags::ROLL_FORWARD)
+    .union(FileFlags::SPLIT_INDEX)
+    .union(FileFlags::INDEX_PER_KEY);
+
+/// Refuse a request the original engine would have refused
+pub fn check(engine: &Engine, req: &OperationRequest) -> BtrieveResult<()> {
+    if req.operation.is_extension() {
+        return Err(BtrieveError::Status(StatusCode::InvalidOperation));
+    }
+    match req.operation {
+        OperationCode::Create => check_create(engine, &req.data_buffer),
+        OperationCode::SetOwner if req.key_number & 2 != 0 => {
+            Err(BtrieveError::Status(StatusCode::OperationNotAllowed))
+        }
+        OperationCode::Insert | OperationCode::Update => check_record_length(engine, req),
+        _ => Ok(()),
+    }
+}
+
+/// The file specification of a Create. A buffer Create can't parse is
+/// left to it to refuse
+fn check_create(engine: &Engine, buf: &[u8]) -> BtrieveResult<()> {
+    let Ok(spec) = CreateSpec::parse(buf, engine.create_limits().layout) else {
+        return Ok(());
+    };
+    // Pages sealed with the daemon's key are as unreadable as compressed ones
+    if engine.files.seals_pages() {
+        return Err(BtrieveError::Status(StatusCode::OperationNotAllowed));
+    }
+    if spec.record_length < MIN_RECORD_LENGTH {
+        return Err(BtrieveError::Status(StatusCode::InvalidRecordLength));
+    }
+    if spec.flags.intersects(FileFlags::COMPRESSED | XTRIEVE_FILE_FLAGS) {
+        return Err(BtrieveError::Status(StatusCode::OperationNotAllowed));
+    }
+
+    for key in spec.keys {
+        if !key_length_allowed(key.key_type, key.length) {
+            return Err(BtrieveError::Status(StatusCode::InvalidKeyLength));
+        }
+    }
+    Ok(())
+}
+
+/// Lengths Btrieve 5.1 takes for each key type
+fn key_length_allowed(key_type: KeyType, length: u16) -> bool {
+    match key_type {
+        KeyType::Integer | KeyType::AutoIncrement => matches!(length, 2 | 4),
+        KeyType::Float | KeyType::BFloat => matches!(length, 4 | 8),
+        KeyType::Date | KeyType::Time => length == 4,
+        KeyType::Logical => matches!(length, 1 | 2),
+        _ => true,
+    }
+}
+
+/// Records of a fixed-length file are given whole
+fn check_record_length(engine: &Engine, req: &OperationRequest) -> BtrieveResult<()> {
+    let Some(path) = PositionBlock::from_bytes(&req.position_block).file_path() else {
+        return Ok(());
+    };
+    let Some(file) = engine.files.get(&path) else {
+        return Ok(());
+    };
+    let f = file.read();
+    if !f.fcr.flags.contains(FileFlags::VARIABLE_LENGTH) && req.data_buffer.len() < f.fcr.record_length as usize {
+        return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
+    }
+    Ok(())
+}
@@ -2,18 +2,33 @@
 //!
 //! This is the main entry point for all Btrieve operations.
 
-use std::path::PathBuf;
+use parking_lot::RwLock;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::error::{BtrieveError, BtrieveResult, StatusCode};
+use crate::cancel::CancelToken;
+use crate::error::{BtrieveError, BtrieveResult, ErrorDetail, StatusCode};
 use crate::file_manager::{
-    cursor::{Cursor, PositionBlock},
-    locking::{LockManager, LockType, SessionId},
-    open_files::{OpenFileTable, OpenMode},
+    coherence::Coherence,
+    record_cache::RecordCacheStats,
+    cursor::PositionBlock,
+    locking::{LockManager, SessionId},
+    open_files::OpenFileTable,
     page_cache::PageCache,
+    sessions::SessionRegistry,
 };
+use crate::metrics::{EngineStats, Metrics};
+use crate::protocol::{ServerInfo, POSITION_BLOCK_SIZE, PROTOCOL_VERSION};
+use crate::storage::crypt::KEY_LEN;
 use crate::storage::fcr::FileControlRecord;
-use crate::storage::key::KeySpec;
+use crate::storage::page::MAX_PAGE_SIZE;
+use crate::storage::record::RecordAddress;
+
+use super::budget::MemoryBudget;
+use super::file_ops::{CreateLimits, FileInfo};
+use super::hooks::EngineHook;
+use super::key_ops::Scan;
 
 /// Btrieve operation codes
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -54,7 +69,7 @@ pub enum OperationCode {
     GetPosition = 22,
     GetDirect = 23,
     GetByPercentage = 26,
-    FindPercentage = 27,
+    FindPercentage = 45,
 
     // Transaction operations
     BeginTransaction = 19,
@@ -75,10 +90,32 @@ pub enum OperationCode {
 
     // Utility operations
     Stop = 25,
+    Unlock = 27,
     Reset = 28,
-    Unlock = 53,
     Version = 54,
 
+    // Xtrieve extensions
+    /// Report lock conflicts and who is waiting on whom
+    LockReport = 90,
+    /// Remove every record of an open file
+    TruncateFile = 91,
+    /// Create an empty file shaped like an open one
+    CloneFile = 92,
+    /// Delete a file no session has open
+    DeleteFile = 93,
+    /// Rename a file no session has open
+    RenameFile = 94,
+    /// Read a record from a file's recycle bin
+    GetDeleted = 95,
+    /// Restore a record from a file's recycle bin
+    Undelete = 96,
+    /// Cancel a running request by the id it was sent with, run by xtrieved
+    Cancel = 89,
+    /// SQL SELECT over a data dictionary, run by xtrieved rather than the engine
+    Query = 97,
+    ServerInfo = 98,
+    Ping = 99,
+
     // Unknown/invalid
     Unknown = 255,
 }
@@ -111,7 +148,7 @@ impl OperationCode {
             24 => OperationCode::StepNext,
             25 => OperationCode::Stop,
             26 => OperationCode::GetByPercentage,
-            27 => OperationCode::FindPercentage,
+            27 => OperationCode::Unlock,
             28 => OperationCode::Reset,
             29 => OperationCode::SetOwner,
             30 => OperationCode::ClearOwner,
@@ -125,11 +162,112 @@ impl OperationCode {
             38 => OperationCode::StepNextExtended,
             39 => OperationCode::StepPreviousExtended,
             40 => OperationCode::InsertExtended,
+            45 => OperationCode::FindPercentage,
             50 => OperationCode::GetKey,
+            89 => OperationCode::Cancel,
+            90 => OperationCode::LockReport,
+            91 => OperationCode::TruncateFile,
+            92 => OperationCode::CloneFile,
+            93 => OperationCode::DeleteFile,
+            94 => OperationCode::RenameFile,
+            95 => OperationCode::GetDeleted,
+            96 => OperationCode::Undelete,
+            97 => OperationCode::Query,
+            98 => OperationCode::ServerInfo,
+            99 => OperationCode::Ping,
             _ => OperationCode::Unknown,
         }
     }
 
+    /// Check if the engine implements this operation (mirrors `Engine::execute`)
+    pub fn is_supported(&self) -> bool {
+        matches!(
+            self,
+            OperationCode::Open
+                | OperationCode::Close
+                | OperationCode::Create
+                | OperationCode::Stat
+                | OperationCode::Insert
+                | OperationCode::Update
+                | OperationCode::Delete
+                | OperationCode::GetEqual
+                | OperationCode::GetNext
+                | OperationCode::GetPrevious
+                | OperationCode::GetGreater
+                | OperationCode::GetGreaterOrEqual
+                | OperationCode::GetLessThan
+                | OperationCode::GetLessOrEqual
+                | OperationCode::GetFirst
+                | OperationCode::GetLast
+                | OperationCode::GetPosition
+                | OperationCode::GetDirect
+                | OperationCode::StepFirst
+                | OperationCode::StepLast
+                | OperationCode::StepNext
+                | OperationCode::StepPrevious
+                | OperationCode::BeginTransaction
+                | OperationCode::EndTransaction
+                | OperationCode::AbortTransaction
+                | OperationCode::Unlock
+                | OperationCode::Reset
+                | OperationCode::SetOwner
+                | OperationCode::ClearOwner
+                | OperationCode::LockReport
+                | OperationCode::TruncateFile
+                | OperationCode::CloneFile
+                | OperationCode::DeleteFile
+                | OperationCode::RenameFile
+                | OperationCode::GetDeleted
+                | OperationCode::Undelete
+                | OperationCode::GetByPercentage
+                | OperationCode::ServerInfo
+                | OperationCode::Ping
+        )
+    }
+
+    /// Check if this operation works on a file opened earlier, named by
+    /// the position block the client sends
+    pub fn uses_open_file(&self) -> bool {
+        !matches!(
+            self,
+            OperationCode::Open
+                | OperationCode::Create
+                | OperationCode::DeleteFile
+                | OperationCode::RenameFile
+                | OperationCode::BeginTransaction
+                | OperationCode::EndTransaction
+                | OperationCode::AbortTransaction
+                | OperationCode::Reset
+                | OperationCode::Stop
+                | OperationCode::Version
+                | OperationCode::GetByPercentage
+                | OperationCode::Query
+                | OperationCode::Cancel
+                | OperationCode::LockReport
+                | OperationCode::ServerInfo
+                | OperationCode::Ping
+                | OperationCode::Unknown
+        )
+    }
+
+    /// Check if this is one of Xtrieve's own operations, not Btrieve's
+    pub fn is_extension(&self) -> bool {
+        matches!(
+            self,
+            OperationCode::Cancel
+                | OperationCode::LockReport
+                | OperationCode::TruncateFile
+                | OperationCode::CloneFile
+                | OperationCode::DeleteFile
+                | OperationCode::RenameFile
+                | OperationCode::GetDeleted
+                | OperationCode::Undelete
+                | OperationCode::Query
+                | OperationCode::ServerInfo
+                | OperationCode::Ping
+        )
+    }
+
     /// Check if this operation requires a positioned cursor
     pub fn requires_position(&self) -> bool {
         matches!(
@@ -170,9 +308,16 @@ impl OperationCode {
     pub fn is_write(&self) -> bool {
         matches!(
             self,
-            OperationCode::Insert | OperationCode::Update | OperationCode::Delete
+            OperationCode::Insert | OperationCode::Update | OperationCode::Delete | OperationCode::Undelete
         )
     }
+
+    /// Check if this operation changes the open file: its records, or the
+    /// file as a whole (truncating it, or setting or clearing its owner)
+    pub fn modifies_file(&self) -> bool {
+        self.is_write()
+            || matches!(self, OperationCode::TruncateFile | OperationCode::SetOwner | OperationCode::ClearOwner)
+    }
 }
 
 /// Request structure for operations
@@ -216,6 +361,8 @@ pub struct OperationResponse {
     pub key_buffer: Vec<u8>,
     pub data_length: u32,
     pub key_length: u32,
+    /// What went wrong, for an error the status alone undersells
+    pub detail: Option<ErrorDetail>,
 }
 
 impl OperationResponse {
@@ -227,6 +374,7 @@ impl OperationResponse {
             key_buffer: Vec::new(),
             data_length: 0,
             key_length: 0,
+            detail: None,
         }
     }
 
@@ -238,6 +386,7 @@ impl OperationResponse {
             key_buffer: Vec::new(),
             data_length: 0,
             key_length: 0,
+            detail: None,
         }
     }
 
@@ -257,6 +406,32 @@ impl OperationResponse {
         self.position_block = position;
         self
     }
+
+    pub fn with_detail(mut self, detail: ErrorDetail) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+}
+
+/// Response for an operation that failed. A bare status says all there
+/// is to say; other errors keep their message and where they happened
+fn error_response(request: &OperationRequest, error: BtrieveError) -> OperationResponse {
+    let response = OperationResponse::error(error.status_code());
+    let message = match error {
+        BtrieveError::Status(_) => return response,
+        BtrieveError::Detailed { detail, .. } => return response.with_detail(detail),
+        other => other.to_string(),
+    };
+    let path = request
+        .file_path
+        .clone()
+        .map(PathBuf::from)
+        .or_else(|| PositionBlock::from_bytes(&request.position_block).file_path());
+    let context = match path {
+        Some(path) => format!("{:?} on {}", request.operation, path.display()),
+        None => format!("{:?}", request.operation),
+    };
+    response.with_detail(ErrorDetail { message, context })
 }
 
 /// The Xtrieve engine - main coordinator for all operations
@@ -267,6 +442,29 @@ pub struct Engine {
     pub cache: Arc<PageCache>,
     /// Lock manager
     pub locks: Arc<LockManager>,
+    /// Files each session holds open
+    pub sessions: Arc<SessionRegistry>,
+    /// Embedder hooks run around each operation
+    hooks: RwLock<Vec<Arc<dyn EngineHook>>>,
+    /// Told of each operation as it finishes (see `metrics`)
+    metrics: RwLock<Vec<Arc<dyn Metrics>>>,
+    /// Behave as Btrieve 5.1 exactly (see `compat`)
+    strict: bool,
+    /// Defaults and limits for Create
+    create_limits: CreateLimits,
+    /// Bytes one operation may gather in memory (see `budget`)
+    memory_budget: Option<usize>,
+    /// Serve Delete File (93) and Rename File (94)
+    file_admin: bool,
+}
+
+/// What ending a session released
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionCleanup {
+    /// A transaction was still open and has been aborted
+    pub transaction_aborted: bool,
+    /// Opens closed on the session's behalf
+    pub files_closed: u32,
 }
 
 impl Engine {
@@ -276,6 +474,89 @@ impl Engine {
             files: Arc::new(OpenFileTable::new()),
             cache: Arc::new(PageCache::new(cache_size)),
             locks: Arc::new(LockManager::default()),
+            sessions: Arc::new(SessionRegistry::new()),
+            hooks: RwLock::new(Vec::new()),
+            metrics: RwLock::new(Vec::new()),
+            strict: false,
+            create_limits: CreateLimits::default(),
+            memory_budget: None,
+            file_admin: true,
+        }
+    }
+
+    /// Create an engine that encrypts the pages of the files it creates
+    /// with `page_key`, and can open files encrypted with it
+    pub fn with_page_key(cache_size: usize, page_key: [u8; KEY_LEN]) -> Self {
+        Engine {
+            files: Arc::new(OpenFileTable::with_page_key(page_key)),
+            ..Self::new(cache_size)
+        }
+    }
+
+    /// Turn off the Xtrieve extensions and hold requests to Btrieve 5.1's
+    /// limits, to check an application against the original engine (see
+    /// `compat`)
+    pub fn with_strict_compat(self) -> Self {
+        Engine { strict: true, ..self }
+    }
+
+    /// Whether the engine is in strict Btrieve 5.1 mode
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Fill in a page size Create requests leave out and cap the record
+    /// length they may ask for
+    pub fn with_create_limits(self, create_limits: CreateLimits) -> Self {
+        Engine { create_limits, ..self }
+    }
+
+    pub fn create_limits(&self) -> CreateLimits {
+        self.create_limits
+    }
+
+    /// Fail operations that would gather more than `bytes` in memory with
+    /// status 61 rather than let them grow with the file
+    pub fn with_memory_budget(self, bytes: usize) -> Self {
+        Engine { memory_budget: Some(bytes), ..self }
+    }
+
+    /// Refuse Delete File (93) and Rename File (94) with status 45, for a
+    /// server whose clients shouldn't remove or move files
+    pub fn without_file_admin(self) -> Self {
+        Engine { file_admin: false, ..self }
+    }
+
+    /// A fresh budget for one operation
+    pub fn memory_budget(&self) -> MemoryBudget {
+        MemoryBudget::new(self.memory_budget)
+    }
+
+    /// Register a hook, run around every operation after those already
+    /// registered
+    pub fn add_hook(&self, hook: Arc<dyn EngineHook>) {
+        self.hooks.write().push(hook);
+    }
+
+    /// Tell `metrics` of each operation and lock wait from now on
+    pub fn add_metrics(&self, metrics: Arc<dyn Metrics>) {
+        self.locks.add_metrics(metrics.clone());
+        self.metrics.write().push(metrics);
+    }
+
+    /// The engine's own counters: page and record caches, lock conflicts
+    /// and waits, files open
+    pub fn stats(&self) -> EngineStats {
+        let record_cache = self.files.list().iter().fold(RecordCacheStats::default(), |sum, file| {
+            let stats = file.read().record_cache_stats();
+            RecordCacheStats { hits: sum.hits + stats.hits, misses: sum.misses + stats.misses }
+        });
+        EngineStats {
+            page_cache: self.cache.stats(),
+            cached_pages: self.cache.len(),
+            record_cache,
+            locks: self.locks.stats(),
+            open_files: self.files.len(),
         }
     }
 
@@ -285,42 +566,223 @@ impl Engine {
         session: SessionId,
         request: OperationRequest,
     ) -> OperationResponse {
+        self.execute_cancellable(session, request, &CancelToken::new())
+    }
+
+    /// Execute an operation that gives up with status 200 once `cancel`
+    /// is cancelled (see `cancel`)
+    pub fn execute_cancellable(
+        &self,
+        session: SessionId,
+        request: OperationRequest,
+        cancel: &CancelToken,
+    ) -> OperationResponse {
+        let started = Instant::now();
+        let hooks = self.hooks.read().clone();
+        let response = match hooks.iter().find_map(|hook| hook.before_operation(session, &request).err()) {
+            Some(status) => OperationResponse::error(status),
+            None => self.dispatch(session, &request, cancel),
+        };
+
+        if response.status == StatusCode::Success {
+            match (request.operation, &request.file_path) {
+                (OperationCode::Open, Some(path)) => {
+                    hooks.iter().for_each(|hook| hook.on_open(session, Path::new(path)));
+                }
+                (OperationCode::EndTransaction, _) => hooks.iter().for_each(|hook| hook.on_commit(session)),
+                _ => {}
+            }
+        }
+        for hook in &hooks {
+            hook.after_operation(session, &request, &response);
+        }
+        for metrics in self.metrics.read().iter() {
+            metrics.operation(request.operation, response.status, started.elapsed());
+        }
+        response
+    }
+
+    /// Run one operation
+    fn dispatch(&self, session: SessionId, request: &OperationRequest, cancel: &CancelToken) -> OperationResponse {
+        if cancel.is_cancelled() {
+            return OperationResponse::error(StatusCode::Cancelled);
+        }
+        if request.operation.uses_open_file() {
+            if let Err(status) = self.check_position_block(session, request) {
+                return OperationResponse::error(status);
+            }
+            if let Err(e) = self.revalidate(request) {
+                return error_response(request, e);
+            }
+        }
+        if self.strict {
+            if let Err(e) = super::compat::check(self, request) {
+                return error_response(request, e);
+            }
+        }
+
         let result = match request.operation {
-            OperationCode::Open => self.op_open(session, &request),
-            OperationCode::Close => self.op_close(session, &request),
-            OperationCode::Create => self.op_create(session, &request),
-            OperationCode::Stat => self.op_stat(session, &request),
-            OperationCode::Insert => self.op_insert(session, &request),
-            OperationCode::Update => self.op_update(session, &request),
-            OperationCode::Delete => self.op_delete(session, &request),
-            OperationCode::GetEqual => self.op_get_equal(session, &request),
-            OperationCode::GetNext => self.op_get_next(session, &request),
-            OperationCode::GetPrevious => self.op_get_previous(session, &request),
-            OperationCode::GetGreater => self.op_get_greater(session, &request),
-            OperationCode::GetGreaterOrEqual => self.op_get_greater_or_equal(session, &request),
-            OperationCode::GetLessThan => self.op_get_less_than(session, &request),
-            OperationCode::GetLessOrEqual => self.op_get_less_or_equal(session, &request),
-            OperationCode::GetFirst => self.op_get_first(session, &request),
-            OperationCode::GetLast => self.op_get_last(session, &request),
-            OperationCode::GetPosition => self.op_get_position(session, &request),
-            OperationCode::GetDirect => self.op_get_direct(session, &request),
-            OperationCode::StepFirst => self.op_step_first(session, &request),
-            OperationCode::StepLast => self.op_step_last(session, &request),
-            OperationCode::StepNext => self.op_step_next(session, &request),
-            OperationCode::StepPrevious => self.op_step_previous(session, &request),
-            OperationCode::BeginTransaction => self.op_begin_transaction(session, &request),
-            OperationCode::EndTransaction => self.op_end_transaction(session, &request),
-            OperationCode::AbortTransaction => self.op_abort_transaction(session, &request),
-            OperationCode::Reset => self.op_reset(session, &request),
-            OperationCode::GetByPercentage => self.op_version(session, &request), // Op 26 is Version
+            OperationCode::Open => self.op_open(session, request),
+            OperationCode::Close => self.op_close(session, request),
+            OperationCode::Create => self.op_create(session, request),
+            OperationCode::Stat => self.op_stat(session, request),
+            OperationCode::Insert => self.op_insert(session, request),
+            OperationCode::Update => self.op_update(session, request),
+            OperationCode::Delete => self.op_delete(session, request),
+            OperationCode::GetEqual => self.op_get_equal(session, request),
+            OperationCode::GetNext => self.op_get_next(session, request),
+            OperationCode::GetPrevious => self.op_get_previous(session, request),
+            OperationCode::GetGreater => self.op_get_greater(session, request),
+            OperationCode::GetGreaterOrEqual => self.op_get_greater_or_equal(session, request),
+            OperationCode::GetLessThan => self.op_get_less_than(session, request),
+            OperationCode::GetLessOrEqual => self.op_get_less_or_equal(session, request),
+            OperationCode::GetFirst => self.op_get_first(session, request),
+            OperationCode::GetLast => self.op_get_last(session, request),
+            OperationCode::GetPosition => self.op_get_position(session, request),
+            OperationCode::GetDirect => self.op_get_direct(session, request),
+            OperationCode::StepFirst => self.op_step_first(session, request),
+            OperationCode::StepLast => self.op_step_last(session, request),
+            OperationCode::StepNext => self.op_step_next(session, request),
+            OperationCode::StepPrevious => self.op_step_previous(session, request),
+            OperationCode::BeginTransaction => self.op_begin_transaction(session, request),
+            OperationCode::EndTransaction => self.op_end_transaction(session, request),
+            OperationCode::AbortTransaction => self.op_abort_transaction(session, request),
+            OperationCode::Unlock => self.op_unlock(session, request),
+            OperationCode::Reset => self.op_reset(session, request),
+            OperationCode::SetOwner => self.op_set_owner(session, request, cancel),
+            OperationCode::ClearOwner => self.op_clear_owner(session, request, cancel),
+            OperationCode::TruncateFile => self.op_truncate_file(session, request),
+            OperationCode::CloneFile => self.op_clone_file(session, request),
+            OperationCode::DeleteFile => self.op_delete_file(session, request),
+            OperationCode::RenameFile => self.op_rename_file(session, request),
+            OperationCode::GetDeleted => self.op_get_deleted(session, request),
+            OperationCode::Undelete => self.op_undelete(session, request),
+            OperationCode::LockReport => self.op_lock_report(session, request),
+            OperationCode::ServerInfo => self.op_server_info(session, request),
+            OperationCode::Ping => self.op_ping(session, request),
+            OperationCode::GetByPercentage => self.op_version(session, request), // Op 26 is Version
             OperationCode::Unknown => Err(BtrieveError::Status(StatusCode::InvalidOperation)),
             _ => Err(BtrieveError::Status(StatusCode::InvalidOperation)),
         };
 
         match result {
-            Ok(response) => response,
-            Err(e) => OperationResponse::error(e.status_code()),
+            Ok(mut response) => {
+                // Operations rebuild the position block from the cursor;
+                // it keeps the cursor id and check Open gave it
+                if request.operation != OperationCode::Open
+                    && response.position_block.len() == POSITION_BLOCK_SIZE
+                {
+                    let mut block = PositionBlock::from_bytes(&response.position_block);
+                    block.keep_identity(&PositionBlock::from_bytes(&request.position_block));
+                    response.position_block = block.data.to_vec();
+                }
+                response
+            }
+            Err(e) => error_response(request, e),
+        }
+    }
+
+    /// Refuse a position block that doesn't name an open the session still
+    /// has: hand-made, from another engine or layout revision, or left
+    /// over from a file since closed. Blocks naming no file are left to the
+    /// operation, which may take a file path instead. A change to the file
+    /// through a read-only open is refused too
+    fn check_position_block(&self, session: SessionId, request: &OperationRequest) -> Result<(), StatusCode> {
+        let block = PositionBlock::from_bytes(&request.position_block);
+        let Some(path) = block.file_path() else {
+            return Ok(());
+        };
+        let cursor = block.get_cursor_id();
+        match block.check_nonce() {
+            Some(nonce) if self.sessions.is_open(session, &path, cursor, nonce) => {}
+            _ => return Err(StatusCode::FileNotOpen),
+        }
+        if request.operation.modifies_file() && self.sessions.is_read_only(session, &path, cursor) {
+            return Err(StatusCode::AccessDenied);
+        }
+        Ok(())
+    }
+
+    /// Make sure cached pages of the file an operation works on are still
+    /// good, if its directory doesn't trust them (see `coherence`)
+    fn revalidate(&self, request: &OperationRequest) -> BtrieveResult<()> {
+        if !self.files.checks_coherence() {
+            return Ok(());
+        }
+        let Some(path) = PositionBlock::from_bytes(&request.position_block).file_path() else {
+            return Ok(());
+        };
+        let Some(file) = self.files.get(&path) else {
+            return Ok(());
+        };
+        let mut f = file.write();
+        if !f.coherence().validates() {
+            return Ok(());
+        }
+        if f.revalidate()? || f.coherence() == Coherence::Uncached {
+            self.cache.invalidate_file(&path.to_string_lossy());
+        }
+        Ok(())
+    }
+
+    /// Capabilities of this engine, as returned by the ServerInfo operation
+    pub fn server_info() -> ServerInfo {
+        let mut info = ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            max_keys: FileControlRecord::MAX_KEYS as u16,
+            max_page_size: MAX_PAGE_SIZE,
+            max_record_length: MAX_PAGE_SIZE - 20,
+            position_block_size: POSITION_BLOCK_SIZE as u16,
+            operations: [0u8; 32],
+        };
+        for code in 0..256u32 {
+            if OperationCode::from_raw(code).is_supported() {
+                info.set_supported(code as u16);
+            }
         }
+        info
+    }
+
+    /// End a session whose client is gone: abort its transaction, close
+    /// the files it left open and release every lock it holds
+    pub fn end_session(&self, session: SessionId) -> SessionCleanup {
+        let mut cleanup = SessionCleanup::default();
+        if super::transaction_ops::has_transaction(session) {
+            cleanup.transaction_aborted =
+                super::transaction_ops::abort_transaction(self, session, &OperationRequest::default()).is_ok();
+        }
+
+        for (path, count) in self.sessions.remove(session) {
+            let close = OperationRequest {
+                operation: OperationCode::Close,
+                file_path: Some(path.to_string_lossy().to_string()),
+                ..Default::default()
+            };
+            for _ in 0..count {
+                if super::file_ops::close(self, session, &close).is_ok() {
+                    cleanup.files_closed += 1;
+                }
+            }
+        }
+
+        self.locks.release_session(session);
+        cleanup
+    }
+
+    /// Records of an open file in `key_number` order, each with its key,
+    /// for code in the engine's process (exporters, change capture) that
+    /// would otherwise walk pages itself. The index is read when the scan
+    /// starts and each record as it is reached; one locked by any session
+    /// comes back as `RecordInUse` and the scan goes on past it
+    pub fn scan(&self, path: &Path, key_number: usize) -> BtrieveResult<Scan<'_>> {
+        super::key_ops::scan(self, path, key_number)
+    }
+
+    /// The Btrieve files in `dir` with their record length, page size, key
+    /// and record counts, for tools that browse a data directory
+    pub fn list_files(&self, dir: &Path) -> BtrieveResult<Vec<FileInfo>> {
+        super::file_ops::list_files(self, dir)
     }
 
     /// Shutdown the engine gracefully
@@ -356,6 +818,36 @@ impl Engine {
         super::file_ops::stat(self, session, req)
     }
 
+    fn op_set_owner(&self, session: SessionId, req: &OperationRequest, cancel: &CancelToken) -> BtrieveResult<OperationResponse> {
+        super::file_ops::set_owner(self, session, req, cancel)
+    }
+
+    fn op_clear_owner(&self, session: SessionId, req: &OperationRequest, cancel: &CancelToken) -> BtrieveResult<OperationResponse> {
+        super::file_ops::clear_owner(self, session, req, cancel)
+    }
+
+    fn op_truncate_file(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        super::file_ops::truncate_file(self, session, req)
+    }
+
+    fn op_clone_file(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        super::file_ops::clone_file(self, session, req)
+    }
+
+    fn op_delete_file(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        if !self.file_admin {
+            return Err(BtrieveError::Status(StatusCode::AccessDenied));
+        }
+        super::file_ops::delete_file(self, session, req)
+    }
+
+    fn op_rename_file(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        if !self.file_admin {
+            return Err(BtrieveError::Status(StatusCode::AccessDenied));
+        }
+        super::file_ops::rename_file(self, session, req)
+    }
+
     fn op_insert(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
         super::record_ops::insert(self, session, req)
     }
@@ -368,6 +860,14 @@ impl Engine {
         super::record_ops::delete(self, session, req)
     }
 
+    fn op_get_deleted(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        super::record_ops::get_deleted(self, session, req)
+    }
+
+    fn op_undelete(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        super::record_ops::undelete(self, session, req)
+    }
+
     fn op_get_equal(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
         super::key_ops::get_equal(self, session, req)
     }
@@ -440,11 +940,55 @@ impl Engine {
         super::transaction_ops::abort_transaction(self, session, req)
     }
 
+    fn op_unlock(&self, session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        // Unlock (27) - key number -1 releases the multiple record lock on
+        // the record at the position in the data buffer, -2 every multiple
+        // record lock, anything else the single record lock. Locks taken
+        // in a transaction last until it ends
+        let block = PositionBlock::from_bytes(&req.position_block);
+        let path = block.file_path().ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+        if super::transaction_ops::has_transaction(session) {
+            return Ok(OperationResponse::success().with_position(req.position_block.clone()));
+        }
+        let file = path.to_string_lossy();
+        match req.key_number {
+            -1 => {
+                let position = req.data_buffer.get(..4)
+                    .ok_or(BtrieveError::Status(StatusCode::DataBufferTooShort))?;
+                let address = RecordAddress::from_position(u32::from_le_bytes([
+                    position[0], position[1], position[2], position[3],
+                ]));
+                self.locks.unlock_record(&file, address, session);
+            }
+            -2 => self.locks.unlock_records(&file, session, true),
+            _ => self.locks.unlock_records(&file, session, false),
+        }
+        Ok(OperationResponse::success().with_position(req.position_block.clone()))
+    }
+
     fn op_reset(&self, _session: SessionId, _req: &OperationRequest) -> BtrieveResult<OperationResponse> {
         // Reset operation - typically does nothing in modern implementations
         Ok(OperationResponse::success())
     }
 
+    fn op_ping(&self, _session: SessionId, req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        // Ping (99) - Xtrieve extension, echoes the data buffer back so
+        // clients and bridges can check the backend is alive
+        Ok(OperationResponse::success().with_data(req.data_buffer.clone()))
+    }
+
+    fn op_server_info(&self, _session: SessionId, _req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        // Server info (98) - Xtrieve extension, reports engine version,
+        // limits and the supported operation codes
+        Ok(OperationResponse::success().with_data(Self::server_info().to_bytes()))
+    }
+
+    fn op_lock_report(&self, _session: SessionId, _req: &OperationRequest) -> BtrieveResult<OperationResponse> {
+        // Lock report (90) - Xtrieve extension, for tracking down storms
+        // of Record In Use: the locks turning sessions away and who waits
+        Ok(OperationResponse::success().with_data(self.locks.report().to_bytes()))
+    }
+
     fn op_version(&self, _session: SessionId, _req: &OperationRequest) -> BtrieveResult<OperationResponse> {
         // Version operation (26) - return Btrieve version info
         // Format: major (2 bytes), minor (2 bytes), revision (1 byte), type (1 byte)
@@ -463,3 +1007,1976 @@ impl Default for Engine {
         Self::new(1000)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::cursor::MAX_PATH_LEN;
+    use crate::storage::fcr::FileFlags;
+    use crate::storage::key::{KeyFlags, KeySpec, KeyType, SpecLayout};
+    use std::path::Path;
+
+    #[test]
+    fn test_ping_echoes_data() {
+        let engine = Engine::new(16);
+        let response = engine.execute(1, OperationRequest {
+            operation: OperationCode::from_raw(99),
+            data_buffer: b"hello".to_vec(),
+            ..Default::default()
+        });
+
+        assert_eq!(response.status, StatusCode::Success);
+        assert_eq!(response.data_buffer, b"hello".to_vec());
+    }
+
+    #[test]
+    fn test_server_info_lists_supported_ops() {
+        let engine = Engine::new(16);
+        let response = engine.execute(1, OperationRequest {
+            operation: OperationCode::from_raw(98),
+            ..Default::default()
+        });
+
+        assert_eq!(response.status, StatusCode::Success);
+        let info = ServerInfo::from_bytes(&response.data_buffer).unwrap();
+        assert_eq!(info.engine_version, env!("CARGO_PKG_VERSION"));
+        assert!(info.supports(OperationCode::GetEqual as u16));
+        assert!(info.supports(OperationCode::Ping as u16));
+        assert!(!info.supports(OperationCode::GetNextExtended as u16));
+    }
+
+    #[test]
+    fn test_lock_report_op() {
+        use crate::file_manager::locking::{LockReport, LockType};
+        use crate::storage::record::RecordAddress;
+
+        let engine = Engine::new(16);
+        let addr = RecordAddress::new(3, 1);
+        engine.locks.lock_record("A.DAT", addr, 1, LockType::SingleNoWait).unwrap();
+        assert!(engine.locks.is_record_locked("A.DAT", addr, 2));
+
+        let response = engine.execute(2, OperationRequest {
+            operation: OperationCode::from_raw(90),
+            ..Default::default()
+        });
+        assert_eq!(response.status, StatusCode::Success);
+        let report = LockReport::from_bytes(&response.data_buffer).unwrap();
+        assert_eq!(report.stats.conflicts, 1);
+        assert_eq!(report.blockers[0].file, "A.DAT");
+        assert_eq!((report.blockers[0].session, report.blockers[0].last_refused), (1, 2));
+    }
+
+    /// Create an empty file with no keys, returning an Open request for it
+    fn create_file(engine: &Engine, path: &str) -> OperationRequest {
+        let mut spec = vec![0u8; 16];
+        spec[0..2].copy_from_slice(&100u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        let create = engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.to_string()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+
+        OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_end_session_releases_what_it_held() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let open = create_file(&engine, &path);
+        assert_eq!(engine.execute(7, open.clone()).status, StatusCode::Success);
+        assert_eq!(engine.execute(7, open).status, StatusCode::Success);
+        let begin = OperationRequest { operation: OperationCode::BeginTransaction, ..Default::default() };
+        assert_eq!(engine.execute(7, begin.clone()).status, StatusCode::Success);
+
+        let ref_count = || engine.files.get(Path::new(&path)).unwrap().read().ref_count;
+        assert_eq!(ref_count(), 3);
+
+        let cleanup = engine.end_session(7);
+        assert_eq!(cleanup, SessionCleanup { transaction_aborted: true, files_closed: 2 });
+        assert_eq!(ref_count(), 1);
+        assert!(engine.sessions.is_empty());
+        // The session can start over
+        assert_eq!(engine.execute(7, begin).status, StatusCode::Success);
+        assert_eq!(engine.end_session(7), SessionCleanup { transaction_aborted: true, files_closed: 0 });
+    }
+
+    #[test]
+    fn test_cursors_on_one_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let open = create_file(&engine, &path);
+        let exclusive = OperationRequest { open_mode: -4, ..open.clone() };
+
+        let first = engine.execute(7, open.clone()).position_block;
+        let second = engine.execute(7, open).position_block;
+        assert_eq!(PositionBlock::from_bytes(&first).get_cursor_id(), 1);
+        assert_eq!(PositionBlock::from_bytes(&second).get_cursor_id(), 2);
+
+        // Calls hand back the cursor id they were given
+        let insert = engine.execute(7, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: second.clone(),
+            data_buffer: vec![b'A'; 100],
+            ..Default::default()
+        });
+        assert_eq!(insert.status, StatusCode::Success);
+        assert_eq!(PositionBlock::from_bytes(&insert.position_block).get_cursor_id(), 2);
+
+        // Closing one cursor keeps the session's hold through the other
+        let close = |block: Vec<u8>| OperationRequest {
+            operation: OperationCode::Close,
+            position_block: block,
+            ..Default::default()
+        };
+        assert_eq!(engine.execute(7, close(first)).status, StatusCode::Success);
+        assert_eq!(engine.execute(8, exclusive.clone()).status, StatusCode::FileInUse);
+        assert_eq!(engine.execute(7, close(second)).status, StatusCode::Success);
+        assert_eq!(engine.execute(8, exclusive).status, StatusCode::Success);
+    }
+
+    #[test]
+    fn test_stale_position_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let open = create_file(&engine, &path);
+        let insert = |block: &[u8]| OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block.to_vec(),
+            data_buffer: vec![b'A'; 100],
+            ..Default::default()
+        };
+
+        let block = engine.execute(7, open.clone()).position_block;
+        assert_eq!(engine.execute(7, insert(&block)).status, StatusCode::Success);
+        // Only the session that opened it can use it
+        assert_eq!(engine.execute(8, insert(&block)).status, StatusCode::FileNotOpen);
+
+        // A hand-made block naming the file is refused
+        let mut forged = PositionBlock::new();
+        forged.set_file_path(&path);
+        assert_eq!(engine.execute(7, insert(&forged.data)).status, StatusCode::FileNotOpen);
+
+        // After Close, a reopen gets the same cursor id but the old block is stale
+        let close = OperationRequest {
+            operation: OperationCode::Close,
+            position_block: block.clone(),
+            ..Default::default()
+        };
+        assert_eq!(engine.execute(7, close).status, StatusCode::Success);
+        let reopened = engine.execute(7, open).position_block;
+        assert_eq!(PositionBlock::from_bytes(&reopened).get_cursor_id(), PositionBlock::from_bytes(&block).get_cursor_id());
+        assert_eq!(engine.execute(7, insert(&block)).status, StatusCode::FileNotOpen);
+        assert_eq!(engine.execute(7, insert(&reopened)).status, StatusCode::Success);
+
+        // Paths the block can't hold are refused at Open
+        let long = OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(format!("{}/{}", dir.path().display(), "X".repeat(MAX_PATH_LEN))),
+            ..Default::default()
+        };
+        assert_eq!(engine.execute(7, long).status, StatusCode::InvalidFileName);
+    }
+
+    #[test]
+    fn test_tampered_position_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], data_buffer| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer,
+            ..Default::default()
+        }).status;
+        let record = [10u32.to_le_bytes(), *b"PART"].concat();
+        let current = engine.execute(1, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block.clone(),
+            data_buffer: record.clone(),
+            ..Default::default()
+        }).position_block;
+        let tampered = |at: usize, bytes: &[u8]| {
+            let mut block = current.clone();
+            block[at..at + bytes.len()].copy_from_slice(bytes);
+            block
+        };
+
+        // A key longer than the block holds, or a state the engine never writes
+        assert_eq!(run(OperationCode::GetNext, &tampered(20, &[200]), Vec::new()), StatusCode::InvalidPositioning);
+        assert_eq!(run(OperationCode::GetNext, &tampered(0, &[9]), Vec::new()), StatusCode::InvalidPositioning);
+
+        // Addresses in the FCR, past the end of the file or running off
+        // their page are refused before use
+        let address = u32::from_le_bytes(current[5..9].try_into().unwrap());
+        for forged in [0, 1_000_000, (address / 1024) * 1024 + 1020] {
+            let at = forged.to_le_bytes();
+            assert_eq!(run(OperationCode::Update, &tampered(5, &at), record.clone()), StatusCode::InvalidRecordAddress);
+            assert_eq!(run(OperationCode::Delete, &tampered(5, &at), Vec::new()), StatusCode::InvalidRecordAddress);
+            assert_eq!(run(OperationCode::GetDirect, &block, at.to_vec()), StatusCode::InvalidRecordAddress);
+        }
+        // and changes only go to pages holding records
+        for page in (1..4).filter(|&page| page != address / 1024) {
+            let at = (page * 1024 + 18).to_le_bytes();
+            assert_eq!(run(OperationCode::Update, &tampered(5, &at), record.clone()), StatusCode::InvalidRecordAddress);
+            assert_eq!(run(OperationCode::Delete, &tampered(5, &at), Vec::new()), StatusCode::InvalidRecordAddress);
+        }
+        assert_eq!(run(OperationCode::GetDirect, &block, address.to_le_bytes().to_vec()), StatusCode::Success);
+    }
+
+    /// Create a file of 8-byte records keyed on their first 4 bytes
+    fn create_parts(engine: &Engine, path: &str) {
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[26] = 14;
+        let create = engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.to_string()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+    }
+
+    #[test]
+    fn test_step_goes_on_from_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], key: u32| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: [key.to_le_bytes(), *b"PART"].concat(),
+            key_buffer: key.to_le_bytes().to_vec(),
+            ..Default::default()
+        });
+        let key_of = |response: &OperationResponse| u32::from_le_bytes(response.data_buffer[0..4].try_into().unwrap());
+
+        // Enough records out of key order to fill several data pages, with
+        // index pages in between
+        let inserted: Vec<u32> = (0..200).map(|i| i * 37 % 200).collect();
+        for &key in &inserted {
+            assert_eq!(run(OperationCode::Insert, &block, key).status, StatusCode::Success);
+        }
+        let walk = |first, next| {
+            let mut keys = Vec::new();
+            let mut response = run(first, &block, 0);
+            while response.status == StatusCode::Success {
+                keys.push(key_of(&response));
+                response = run(next, &response.position_block, 0);
+            }
+            assert_eq!(response.status, StatusCode::EndOfFile);
+            keys
+        };
+        assert_eq!(walk(OperationCode::StepFirst, OperationCode::StepNext), inserted);
+        let mut backwards = inserted.clone();
+        backwards.reverse();
+        assert_eq!(walk(OperationCode::StepLast, OperationCode::StepPrevious), backwards);
+
+        // A Step goes on from the record a Get found, in physical order
+        for i in [0, 76, 77, 150] {
+            let found = run(OperationCode::GetEqual, &block, inserted[i]);
+            assert_eq!(found.status, StatusCode::Success);
+            let next = run(OperationCode::StepNext, &found.position_block, 0);
+            assert_eq!(key_of(&next), inserted[i + 1]);
+            let previous = run(OperationCode::StepPrevious, &found.position_block, 0);
+            match i {
+                0 => assert_eq!(previous.status, StatusCode::EndOfFile),
+                _ => assert_eq!(key_of(&previous), inserted[i - 1]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_refused_insert_leaves_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], key: u32| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: [key.to_le_bytes(), *b"PART"].concat(),
+            ..Default::default()
+        });
+
+        assert_eq!(run(OperationCode::Insert, &block, 10).status, StatusCode::Success);
+        let inserted = run(OperationCode::Insert, &block, 20);
+        assert_eq!(run(OperationCode::Insert, &block, 10).status, StatusCode::DuplicateKey);
+
+        // The refused record wasn't written, and Insert left the cursor on
+        // its record's key
+        let first = run(OperationCode::StepFirst, &block, 0);
+        let second = run(OperationCode::StepNext, &first.position_block, 0);
+        assert_eq!(second.status, StatusCode::Success);
+        assert_eq!(run(OperationCode::StepNext, &second.position_block, 0).status, StatusCode::EndOfFile);
+        assert_eq!(run(OperationCode::GetNext, &inserted.position_block, 0).status, StatusCode::EndOfFile);
+        let previous = run(OperationCode::GetPrevious, &inserted.position_block, 0);
+        assert_eq!(previous.key_buffer, 10u32.to_le_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_hooks() {
+        #[derive(Default)]
+        struct Audit {
+            log: parking_lot::Mutex<Vec<String>>,
+        }
+        impl EngineHook for Audit {
+            fn before_operation(&self, _session: SessionId, request: &OperationRequest) -> Result<(), StatusCode> {
+                match request.operation {
+                    OperationCode::Insert if request.data_buffer.starts_with(b"X") => Err(StatusCode::AccessDenied),
+                    _ => Ok(()),
+                }
+            }
+            fn after_operation(&self, session: SessionId, request: &OperationRequest, response: &OperationResponse) {
+                self.log.lock().push(format!("{} {:?} {:?}", session, request.operation, response.status));
+            }
+            fn on_open(&self, _session: SessionId, path: &Path) {
+                self.log.lock().push(format!("open {}", path.file_name().unwrap().to_string_lossy()));
+            }
+            fn on_commit(&self, session: SessionId) {
+                self.log.lock().push(format!("commit {}", session));
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("AUDIT.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let audit = Arc::new(Audit::default());
+        engine.add_hook(audit.clone());
+
+        let block = engine.execute(3, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, data_buffer: &[u8]| engine.execute(3, OperationRequest {
+            operation,
+            position_block: block.clone(),
+            data_buffer: data_buffer.to_vec(),
+            ..Default::default()
+        });
+        run(OperationCode::BeginTransaction, b"");
+        assert_eq!(run(OperationCode::Insert, b"XXXXXXXX").status, StatusCode::AccessDenied);
+        assert_eq!(run(OperationCode::Insert, b"\x01\0\0\0PART").status, StatusCode::Success);
+        run(OperationCode::EndTransaction, b"");
+
+        assert_eq!(*audit.log.lock(), [
+            "open AUDIT.DAT",
+            "3 Open Success",
+            "3 BeginTransaction Success",
+            "3 Insert AccessDenied",
+            "3 Insert Success",
+            "commit 3",
+            "3 EndTransaction Success",
+        ]);
+    }
+
+    #[test]
+    fn test_record_validation() {
+        use crate::operations::validation::{FieldRule, FileRules, RecordValidator};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let mut validator = RecordValidator::new(engine.files.clone());
+        validator.add("parts.dat", FileRules {
+            fields: vec![FieldRule { name: "QTY".to_string(), offset: 4, length: 4, signed: false, min: None, max: Some(100) }],
+            required_keys: vec![0],
+            rejection: StatusCode::DataBufferTooShort,
+        });
+        engine.add_hook(Arc::new(validator));
+
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let insert = |record: &[u8]| engine.execute(1, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block.clone(),
+            data_buffer: record.to_vec(),
+            ..Default::default()
+        }).status;
+        assert_eq!(insert(&[1, 0, 0, 0, 100, 0, 0, 0]), StatusCode::Success);
+        assert_eq!(insert(&[2, 0, 0, 0, 101, 0, 0, 0]), StatusCode::DataBufferTooShort);
+        assert_eq!(insert(&[0, 0, 0, 0, 5, 0, 0, 0]), StatusCode::DataBufferTooShort);
+    }
+
+    #[test]
+    fn test_recycle_bin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[8..10].copy_from_slice(&FileFlags::RECYCLE.bits().to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[20] = 0x01; // duplicates
+        spec[26] = 14;
+        engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.clone()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+
+        let open = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], data_buffer: &[u8], key_number| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            key_number,
+            ..Default::default()
+        });
+        let records = || {
+            let stat = run(OperationCode::Stat, &open, b"", 0).data_buffer;
+            u32::from_le_bytes([stat[6], stat[7], stat[8], stat[9]])
+        };
+
+        // A delete rolled back leaves nothing in the bin
+        run(OperationCode::BeginTransaction, &open, b"", 0);
+        let inserted = run(OperationCode::Insert, &open, b"\x01\0\0\0GONE", 0).position_block;
+        assert_eq!(run(OperationCode::Delete, &inserted, b"", 0).status, StatusCode::Success);
+        run(OperationCode::AbortTransaction, &open, b"", 0);
+        assert_eq!(run(OperationCode::GetDeleted, &open, b"", 0).status, StatusCode::EndOfFile);
+
+        let inserted = run(OperationCode::Insert, &open, b"\x02\0\0\0ACME", 0).position_block;
+        assert_eq!(run(OperationCode::Delete, &inserted, b"", 0).status, StatusCode::Success);
+        assert_eq!(records(), 0);
+
+        let deleted = run(OperationCode::GetDeleted, &open, b"", 0);
+        assert_eq!(deleted.status, StatusCode::Success);
+        assert_eq!(deleted.data_buffer, b"\x02\0\0\0ACME");
+        assert_eq!(deleted.key_buffer.len(), 8);
+        assert_eq!(run(OperationCode::GetDeleted, &open, b"", 1).status, StatusCode::EndOfFile);
+
+        let restored = run(OperationCode::Undelete, &open, b"", 0);
+        assert_eq!(restored.status, StatusCode::Success);
+        assert_eq!(restored.data_buffer, b"\x02\0\0\0ACME");
+        assert_eq!(records(), 1);
+        assert_eq!(run(OperationCode::Undelete, &open, b"", 0).status, StatusCode::EndOfFile);
+    }
+
+    #[test]
+    fn test_roll_forward_log() {
+        use crate::storage::rollfwd::{Change, RollForwardLog};
+
+        let dir = tempfile::tempdir().unwrap();
+        let dat = dir.path().join("CUST.DAT");
+        let engine = Engine::new(16);
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[8..10].copy_from_slice(&FileFlags::ROLL_FORWARD.bits().to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[20] = 0x01 | 0x02; // duplicates, modifiable
+        spec[26] = 14;
+        engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(dat.to_string_lossy().to_string()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+
+        let open = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(dat.to_string_lossy().to_string()),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], data_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            ..Default::default()
+        });
+        let log = RollForwardLog::new(&dat, None);
+
+        // Nothing is logged before the commit, or at all if rolled back
+        run(OperationCode::BeginTransaction, &open, b"");
+        run(OperationCode::Insert, &open, b"\x01\0\0\0GONE");
+        run(OperationCode::AbortTransaction, &open, b"");
+        run(OperationCode::BeginTransaction, &open, b"");
+        run(OperationCode::Insert, &open, b"\x02\0\0\0KEPT");
+        assert!(log.entries().unwrap().is_empty());
+        run(OperationCode::EndTransaction, &open, b"");
+
+        let inserted = run(OperationCode::Insert, &open, b"\x03\0\0\0ACME").position_block;
+        let updated = run(OperationCode::Update, &inserted, b"\x03\0\0\0ACE").position_block;
+        assert_eq!(run(OperationCode::Delete, &updated, b"").status, StatusCode::Success);
+        assert_eq!(run(OperationCode::TruncateFile, &open, b"").status, StatusCode::Success);
+
+        let changes: Vec<Change> = log.entries().unwrap().into_iter().map(|e| e.change).collect();
+        assert_eq!(changes, [
+            Change::Insert(b"\x02\0\0\0KEPT".to_vec()),
+            Change::Insert(b"\x03\0\0\0ACME".to_vec()),
+            Change::Update { before: b"\x03\0\0\0ACME".to_vec(), after: b"\x03\0\0\0ACE\0".to_vec() },
+            Change::Delete(b"\x03\0\0\0ACE\0".to_vec()),
+            Change::Truncate,
+        ]);
+    }
+
+    #[test]
+    fn test_split_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let dat = dir.path().join("CUST.DAT");
+        let path = dat.to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let create = |flags: FileFlags| {
+            let mut spec = vec![0u8; 32];
+            spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+            spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+            spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+            spec[8..10].copy_from_slice(&flags.bits().to_le_bytes());
+            spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+            spec[26] = 14;
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::Create,
+                file_path: Some(path.clone()),
+                data_buffer: spec,
+                ..Default::default()
+            }).status
+        };
+        assert_eq!(create(FileFlags::SPLIT_INDEX | FileFlags::COMPRESSED), StatusCode::OperationNotAllowed);
+        assert_eq!(create(FileFlags::SPLIT_INDEX), StatusCode::Success);
+
+        let open = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, data_buffer: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: open.clone(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+
+        for id in 1u8..=3 {
+            assert_eq!(run(OperationCode::Insert, &[id, 0, 0, 0, b'A', b'C', b'M', b'E'], b"").status, StatusCode::Success);
+        }
+
+        // Index pages a rolled back insert changed go back to the index file
+        run(OperationCode::BeginTransaction, b"", b"");
+        assert_eq!(run(OperationCode::Insert, b"\x09\0\0\0GONE", b"").status, StatusCode::Success);
+        assert_eq!(run(OperationCode::AbortTransaction, b"", b"").status, StatusCode::Success);
+        let found = run(OperationCode::GetEqual, b"", b"\x02\0\0\0");
+        assert_eq!(found.status, StatusCode::Success);
+        assert_eq!(found.key_buffer, b"\x02\0\0\0");
+        let stat = run(OperationCode::Stat, b"", b"").data_buffer;
+        let flags = FileFlags::from_bits_truncate(u16::from_le_bytes([stat[10], stat[11]]));
+        assert!(flags.contains(FileFlags::SPLIT_INDEX));
+
+        // Every page the index file holds is blank in the .DAT
+        engine.files.get(&dat).unwrap().read().flush().unwrap();
+        let (data, index) = (std::fs::read(&dat).unwrap(), std::fs::read(dir.path().join("CUST.IX")).unwrap());
+        let in_index: Vec<usize> = (1..index.len() / 1024)
+            .filter(|n| index[n * 1024..(n + 1) * 1024].iter().any(|&b| b != 0))
+            .collect();
+        assert!(!in_index.is_empty());
+        assert!(in_index.iter().all(|n| data[n * 1024..(n + 1) * 1024].iter().all(|&b| b == 0)));
+    }
+
+    #[test]
+    fn test_index_per_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let dat = dir.path().join("CUST.DAT");
+        let path = dat.to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let create = |flags: FileFlags| {
+            let mut spec = vec![0u8; 48];
+            spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+            spec[2..4].copy_from_slice(&512u16.to_le_bytes());
+            spec[4..6].copy_from_slice(&2u16.to_le_bytes());
+            spec[8..10].copy_from_slice(&flags.bits().to_le_bytes());
+            for (key, position) in [(0usize, 0u16), (1, 4)] {
+                let at = 16 + key * 16;
+                spec[at..at + 2].copy_from_slice(&position.to_le_bytes());
+                spec[at + 2..at + 4].copy_from_slice(&4u16.to_le_bytes());
+                spec[at + 4] = 0x01; // duplicates
+                spec[at + 10] = 14;
+            }
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::Create,
+                file_path: Some(path.clone()),
+                data_buffer: spec,
+                ..Default::default()
+            }).status
+        };
+        assert_eq!(create(FileFlags::INDEX_PER_KEY | FileFlags::SPLIT_INDEX), StatusCode::OperationNotAllowed);
+        assert_eq!(create(FileFlags::INDEX_PER_KEY), StatusCode::Success);
+
+        let open = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, data_buffer: &[u8], key_buffer: &[u8], key_number| engine.execute(1, OperationRequest {
+            operation,
+            position_block: open.clone(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            key_number,
+            ..Default::default()
+        });
+        // Enough records to split both keys' leaves
+        for id in 0u32..100 {
+            let mut record = id.to_le_bytes().to_vec();
+            record.extend_from_slice(&(id % 3).to_le_bytes());
+            assert_eq!(run(OperationCode::Insert, &record, b"", 0).status, StatusCode::Success);
+        }
+        assert_eq!(run(OperationCode::GetEqual, b"", &99u32.to_le_bytes(), 0).status, StatusCode::Success);
+        assert_eq!(run(OperationCode::GetEqual, b"", &2u32.to_le_bytes(), 1).status, StatusCode::Success);
+
+        // Each key's pages are in its own file, and in no other
+        engine.files.get(&dat).unwrap().read().flush().unwrap();
+        let pages = |name: &str| -> std::collections::HashSet<usize> {
+            let file = std::fs::read(dir.path().join(name)).unwrap();
+            (1..file.len() / 512).filter(|n| file[n * 512..(n + 1) * 512].iter().any(|&b| b != 0)).collect()
+        };
+        let (key0, key1) = (pages("CUST.IX0"), pages("CUST.IX1"));
+        assert!(key0.len() > 1 && key1.len() > 1);
+        assert!(key0.is_disjoint(&key1));
+        assert!(pages("CUST.DAT").is_disjoint(&key0) && pages("CUST.DAT").is_disjoint(&key1));
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        }).position_block;
+        for key in [3u32, 1, 2] {
+            let insert = engine.execute(1, OperationRequest {
+                operation: OperationCode::Insert,
+                position_block: block.clone(),
+                data_buffer: [key.to_le_bytes(), *b"PART"].concat(),
+                ..Default::default()
+            });
+            assert_eq!(insert.status, StatusCode::Success);
+        }
+
+        let keys: Vec<Vec<u8>> = engine.scan(Path::new(&path), 0).unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!(keys, [1u32, 2, 3].map(|k| k.to_le_bytes().to_vec()));
+        assert_eq!(engine.scan(Path::new(&path), 1).err().map(|e| e.status_code()), Some(StatusCode::InvalidKeyNumber));
+        let elsewhere = dir.path().join("OTHER.DAT");
+        assert_eq!(engine.scan(&elsewhere, 0).err().map(|e| e.status_code()), Some(StatusCode::FileNotOpen));
+
+        // A record another session holds is reported, and the scan goes on
+        let other = engine.execute(2, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        }).position_block;
+        let locked = engine.execute(2, OperationRequest {
+            operation: OperationCode::GetEqual,
+            position_block: other,
+            key_buffer: 2u32.to_le_bytes().to_vec(),
+            lock_bias: 200,
+            ..Default::default()
+        });
+        assert_eq!(locked.status, StatusCode::Success);
+        let statuses = || -> Vec<StatusCode> {
+            engine
+                .scan(Path::new(&path), 0)
+                .unwrap()
+                .map(|r| r.map_or_else(|e| e.status_code(), |_| StatusCode::Success))
+                .collect()
+        };
+        assert_eq!(statuses().len(), 3);
+        assert!(statuses().contains(&StatusCode::RecordInUse));
+        engine.end_session(2);
+        assert_eq!(statuses(), [StatusCode::Success; 3]);
+    }
+
+    #[test]
+    fn test_unlock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let open = |session| engine.execute(session, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        }).position_block;
+        let (mine, theirs) = (open(1), open(2));
+        for key in [1u32, 2] {
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::Insert,
+                position_block: mine.clone(),
+                data_buffer: [key.to_le_bytes(), *b"PART"].concat(),
+                ..Default::default()
+            });
+        }
+        let get = |session, block: &[u8], key: u32, lock_bias| engine.execute(session, OperationRequest {
+            operation: OperationCode::GetEqual,
+            position_block: block.to_vec(),
+            key_buffer: key.to_le_bytes().to_vec(),
+            lock_bias,
+            ..Default::default()
+        }).status;
+        let unlock = |key_number| engine.execute(1, OperationRequest {
+            operation: OperationCode::Unlock,
+            position_block: mine.clone(),
+            key_number,
+            ..Default::default()
+        }).status;
+        assert_eq!(OperationCode::from_raw(27), OperationCode::Unlock);
+
+        assert_eq!(get(1, &mine, 1, 200), StatusCode::Success);
+        assert_eq!(get(2, &theirs, 1, 200), StatusCode::RecordInUse);
+        // Unlocking multiple record locks leaves the single one
+        assert_eq!(unlock(-2), StatusCode::Success);
+        assert_eq!(get(2, &theirs, 1, 200), StatusCode::RecordInUse);
+        assert_eq!(unlock(0), StatusCode::Success);
+        assert_eq!(get(2, &theirs, 1, 0), StatusCode::Success);
+
+        assert_eq!(get(1, &mine, 1, 400), StatusCode::Success);
+        assert_eq!(get(1, &mine, 2, 400), StatusCode::Success);
+        assert_eq!(unlock(0), StatusCode::Success);
+        assert_eq!(get(2, &theirs, 2, 200), StatusCode::RecordInUse);
+        assert_eq!(unlock(-2), StatusCode::Success);
+        assert_eq!(get(2, &theirs, 1, 200), StatusCode::Success);
+        assert_eq!(get(2, &theirs, 2, 200), StatusCode::Success);
+    }
+
+    #[test]
+    fn test_open_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let open = |session, open_mode| engine.execute(session, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            open_mode,
+            ..Default::default()
+        }).position_block;
+        let insert = |session, block: &[u8], key: u32| engine.execute(session, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block.to_vec(),
+            data_buffer: [key.to_le_bytes(), *b"PART"].concat(),
+            ..Default::default()
+        }).status;
+
+        // A read-only open can't write, and doesn't stop another open writing
+        let reader = open(1, -2);
+        assert_eq!(insert(1, &reader, 1), StatusCode::AccessDenied);
+        let writer = open(2, 0);
+        assert_eq!(insert(2, &writer, 1), StatusCode::Success);
+        let first = engine.execute(1, OperationRequest {
+            operation: OperationCode::GetFirst,
+            position_block: reader.clone(),
+            ..Default::default()
+        });
+        assert_eq!(first.status, StatusCode::Success);
+
+        // Nor change the file as a whole
+        for operation in [OperationCode::TruncateFile, OperationCode::SetOwner, OperationCode::ClearOwner] {
+            let refused = engine.execute(1, OperationRequest {
+                operation,
+                position_block: reader.clone(),
+                data_buffer: b"BOSS".to_vec(),
+                key_buffer: b"BOSS".to_vec(),
+                ..Default::default()
+            });
+            assert_eq!(refused.status, StatusCode::AccessDenied, "{:?}", operation);
+        }
+
+        let accelerated = open(3, -1);
+        assert_eq!(insert(3, &accelerated, 2), StatusCode::Success);
+        let verified = open(4, -3);
+        assert_eq!(insert(4, &verified, 3), StatusCode::Success);
+        let file = engine.files.get(Path::new(&path)).unwrap();
+        assert!(file.read().mode.verify && !file.read().mode.accelerated);
+    }
+
+    #[test]
+    fn test_record_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        }).position_block;
+        let insert = |key: u32| engine.execute(1, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block.clone(),
+            data_buffer: [key.to_le_bytes(), *b"PART"].concat(),
+            ..Default::default()
+        }).status;
+        let get = |key: u32| engine.execute(1, OperationRequest {
+            operation: OperationCode::GetEqual,
+            position_block: block.clone(),
+            key_buffer: key.to_le_bytes().to_vec(),
+            ..Default::default()
+        });
+        let stats = || engine.files.get(Path::new(&path)).unwrap().read().record_cache_stats();
+
+        assert_eq!(insert(1), StatusCode::Success);
+        let first = get(1);
+        assert_eq!(first.status, StatusCode::Success);
+        let hits = stats().hits;
+        assert_eq!(get(1).data_buffer, first.data_buffer);
+        assert_eq!(stats().hits, hits + 1);
+
+        // A write to the record's page sends the next read to the page
+        assert_eq!(insert(2), StatusCode::Success);
+        let misses = stats().misses;
+        assert_eq!(get(1).status, StatusCode::Success);
+        assert_eq!(stats().misses, misses + 1);
+    }
+
+    #[test]
+    fn test_create_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let create = |engine: &Engine, name: &str, record_length: u16, page_size: u16| {
+            let mut spec = vec![0u8; 32];
+            spec[0..2].copy_from_slice(&record_length.to_le_bytes());
+            spec[2..4].copy_from_slice(&page_size.to_le_bytes());
+            spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+            spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+            spec[26] = 14;
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::Create,
+                file_path: Some(dir.path().join(name).to_string_lossy().to_string()),
+                data_buffer: spec,
+                ..Default::default()
+            }).status
+        };
+
+        let engine = Engine::new(16);
+        assert_eq!(create(&engine, "A.DAT", 8, 0), StatusCode::PageSizeError);
+        assert_eq!(create(&engine, "A.DAT", 8, 1000), StatusCode::PageSizeError);
+        assert_eq!(create(&engine, "A.DAT", 1000, 1024), StatusCode::Success);
+
+        let engine = Engine::new(16).with_create_limits(CreateLimits {
+            default_page_size: Some(2048),
+            max_record_length: Some(512),
+            ..Default::default()
+        });
+        assert_eq!(create(&engine, "B.DAT", 8, 0), StatusCode::Success);
+        let stat = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(dir.path().join("B.DAT").to_string_lossy().to_string()),
+            ..Default::default()
+        });
+        let stat = engine.execute(1, OperationRequest {
+            operation: OperationCode::Stat,
+            position_block: stat.position_block,
+            ..Default::default()
+        });
+        assert_eq!(u16::from_le_bytes([stat.data_buffer[2], stat.data_buffer[3]]), 2048);
+        // A default doesn't stand in for a page size that is wrong
+        assert_eq!(create(&engine, "C.DAT", 8, 1000), StatusCode::PageSizeError);
+        assert_eq!(create(&engine, "C.DAT", 513, 1024), StatusCode::InvalidRecordLength);
+        assert_eq!(create(&engine, "C.DAT", 512, 1024), StatusCode::Success);
+    }
+
+    #[test]
+    fn test_create_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = KeySpec {
+            position: 0,
+            length: 4,
+            flags: KeyFlags::DUPLICATES,
+            key_type: KeyType::UnsignedBinary,
+            null_value: 0,
+            acs_number: 0,
+            unique_count: 0,
+        };
+        let spec = |layout: SpecLayout| {
+            let mut spec = vec![0u8; layout.file_spec_size()];
+            spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+            spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+            spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+            spec.extend_from_slice(&key.to_layout(layout));
+            spec
+        };
+        let create = |engine: &Engine, name: &str, data_buffer: Vec<u8>| {
+            let path = dir.path().join(name).to_string_lossy().to_string();
+            let status = engine.execute(1, OperationRequest {
+                operation: OperationCode::Create,
+                file_path: Some(path.clone()),
+                data_buffer,
+                ..Default::default()
+            }).status;
+            if status != StatusCode::Success {
+                return Err(status);
+            }
+            let open = engine.execute(1, OperationRequest {
+                operation: OperationCode::Open,
+                file_path: Some(path),
+                ..Default::default()
+            });
+            let stat = engine.execute(1, OperationRequest {
+                operation: OperationCode::Stat,
+                position_block: open.position_block,
+                ..Default::default()
+            });
+            Ok(stat.data_buffer[14..].to_vec())
+        };
+
+        // The SDKs' compact buffers are told from their length
+        let engine = Engine::new(16);
+        assert_eq!(create(&engine, "A.DAT", spec(SpecLayout::Xtrieve)), Ok(key.to_bytes()));
+        assert_eq!(create(&engine, "B.DAT", spec(SpecLayout::Compact)), Ok(key.to_bytes()));
+
+        // Btrieve's layout is taken only when the daemon asks for it
+        let engine = Engine::new(16).with_create_limits(CreateLimits {
+            layout: Some(SpecLayout::Classic),
+            ..Default::default()
+        });
+        assert_eq!(create(&engine, "C.DAT", spec(SpecLayout::Classic)), Ok(key.to_bytes()));
+        assert_eq!(create(&engine, "D.DAT", spec(SpecLayout::Xtrieve)), Err(StatusCode::InvalidKeyPosition));
+    }
+
+    #[test]
+    fn test_truncate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let open = |session, owner: &[u8]| engine.execute(session, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            key_buffer: owner.to_vec(),
+            ..Default::default()
+        });
+        let run = |session, operation, block: &[u8], buffer: &[u8]| engine.execute(session, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: buffer.to_vec(),
+            key_buffer: buffer.to_vec(),
+            ..Default::default()
+        });
+        let records = |block: &[u8]| {
+            let stat = run(1, OperationCode::Stat, block, b"").data_buffer;
+            u32::from_le_bytes([stat[6], stat[7], stat[8], stat[9]])
+        };
+
+        let block = open(1, b"").position_block;
+        assert_eq!(run(1, OperationCode::SetOwner, &block, b"BOSS").status, StatusCode::Success);
+        for key in [1u32, 2, 3] {
+            let insert = run(1, OperationCode::Insert, &block, &[key.to_le_bytes(), *b"ACME"].concat());
+            assert_eq!(insert.status, StatusCode::Success);
+        }
+        let keys = run(1, OperationCode::Stat, &block, b"").data_buffer[10..].to_vec();
+
+        // Not while another session has the file, or in a transaction
+        let other = open(2, b"BOSS").position_block;
+        assert_eq!(run(1, OperationCode::TruncateFile, &block, b"").status, StatusCode::FileInUse);
+        assert_eq!(run(2, OperationCode::Close, &other, b"").status, StatusCode::Success);
+        run(1, OperationCode::BeginTransaction, &block, b"");
+        assert_eq!(run(1, OperationCode::Insert, &block, b"\x04\0\0\0ACME").status, StatusCode::Success);
+        assert_eq!(run(1, OperationCode::TruncateFile, &block, b"").status, StatusCode::TransactionActive);
+        run(1, OperationCode::AbortTransaction, &block, b"");
+
+        assert_eq!(run(1, OperationCode::TruncateFile, &block, b"").status, StatusCode::Success);
+        assert_eq!(records(&block), 0);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1024);
+        assert_eq!(run(1, OperationCode::Stat, &block, b"").data_buffer[10..], keys);
+        assert_eq!(run(1, OperationCode::Insert, &block, b"\x05\0\0\0ACME").status, StatusCode::Success);
+        assert_eq!(records(&block), 1);
+
+        assert_eq!(run(1, OperationCode::Close, &block, b"").status, StatusCode::Success);
+        assert_eq!(open(1, b"").status, StatusCode::InvalidOwner);
+    }
+
+    #[test]
+    fn test_clone_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[8..10].copy_from_slice(&(FileFlags::COMPRESSED | FileFlags::RECYCLE).bits().to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[20] = 0x21; // duplicates, alternate collating sequence
+        spec[26] = 14;
+        spec[28] = 3;
+        engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path("CUST.DAT")),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        let open = |name: &str| engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path(name)),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], data_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            ..Default::default()
+        });
+
+        let block = open("CUST.DAT");
+        assert_eq!(run(OperationCode::Insert, &block, b"\x01\0\0\0ACME").status, StatusCode::Success);
+        assert_eq!(run(OperationCode::CloneFile, &block, path("EMPTY.DAT").as_bytes()).status, StatusCode::Success);
+        assert_eq!(run(OperationCode::CloneFile, &block, path("EMPTY.DAT").as_bytes()).status, StatusCode::FileInUse);
+        assert_eq!(run(OperationCode::CloneFile, &block, b"").status, StatusCode::InvalidFileName);
+
+        let original = run(OperationCode::Stat, &block, b"").data_buffer;
+        let clone = run(OperationCode::Stat, &open("EMPTY.DAT"), b"").data_buffer;
+        assert_eq!(&clone[6..10], &0u32.to_le_bytes());
+        assert_eq!(clone[..6], original[..6]);
+        assert_eq!(clone[10..], original[10..]);
+    }
+
+    #[test]
+    fn test_no_case_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("NAMES.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[20..22].copy_from_slice(&KeyFlags::NO_CASE.bits().to_le_bytes());
+        let create = engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.clone()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+        let open = || engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], data_buffer: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+
+        let block = open();
+        assert_eq!(run(OperationCode::Insert, &block, b"Kent0001", b"").status, StatusCode::Success);
+        assert_eq!(run(OperationCode::GetEqual, &block, b"", b"kent").status, StatusCode::Success);
+
+        // Get First/Next, Greater and Less follow the collation, not the bytes
+        for record in [b"carl0002", b"DAVE0003", b"adam0004", b"Bert0005"] {
+            assert_eq!(run(OperationCode::Insert, &block, record, b"").status, StatusCode::Success);
+        }
+        let mut walk = Vec::new();
+        let mut response = run(OperationCode::GetFirst, &block, b"", b"");
+        while response.status == StatusCode::Success {
+            walk.push(response.key_buffer.clone());
+            response = run(OperationCode::GetNext, &response.position_block, b"", b"");
+        }
+        assert_eq!(response.status, StatusCode::EndOfFile);
+        assert_eq!(walk, [b"adam", b"Bert", b"carl", b"DAVE", b"Kent"]);
+        assert_eq!(run(OperationCode::GetGreater, &block, b"", b"BERT").key_buffer, b"carl");
+        assert_eq!(run(OperationCode::GetLessThan, &block, b"", b"CARL").key_buffer, b"Bert");
+
+        // The collation outlives the open file
+        run(OperationCode::Close, &block, b"", b"");
+        let block = open();
+        assert_eq!(run(OperationCode::GetEqual, &block, b"", b"KENT").status, StatusCode::Success);
+        assert_eq!(run(OperationCode::GetEqual, &block, b"", b"KANT").status, StatusCode::KeyNotFound);
+    }
+
+    #[test]
+    fn test_utf8_key_walk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("WORDS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        // 16-byte records, an 8-byte UTF-8 key taking duplicates up front
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&16u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&8u16.to_le_bytes());
+        spec[20..22].copy_from_slice(&KeyFlags::DUPLICATES.bits().to_le_bytes());
+        spec[26] = KeyType::Utf8 as u8;
+        let create = engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.clone()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], data_buffer: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+
+        // "café" composed (NFC) and decomposed (NFD) differ past the fourth
+        // byte, and from "cafe" only there
+        let words = ["cafe", "caf\u{e9}", "cafz", "cafe\u{301}", "cafa"];
+        for (sequence, word) in words.iter().enumerate() {
+            let mut record = vec![0u8; 16];
+            record[..word.len()].copy_from_slice(word.as_bytes());
+            record[12..].copy_from_slice(&(sequence as u32).to_le_bytes());
+            assert_eq!(run(OperationCode::Insert, &block, &record, b"").status, StatusCode::Success);
+        }
+
+        // The two forms are one key, past "z", in the order inserted
+        let sequence = |response: &OperationResponse| u32::from_le_bytes(response.data_buffer[12..16].try_into().unwrap());
+        let mut walk = Vec::new();
+        let mut response = run(OperationCode::GetFirst, &block, b"", b"");
+        while response.status == StatusCode::Success {
+            walk.push(sequence(&response));
+            response = run(OperationCode::GetNext, &response.position_block, b"", b"");
+        }
+        assert_eq!(response.status, StatusCode::EndOfFile);
+        assert_eq!(walk, [4, 0, 2, 1, 3]);
+        let found = run(OperationCode::GetEqual, &block, b"", "cafe\u{301}".as_bytes());
+        assert_eq!(sequence(&found), 1);
+        assert_eq!(sequence(&run(OperationCode::GetNext, &found.position_block, b"", b"")), 3);
+    }
+
+    /// Create and open a file of 8-byte records keyed on their first 4
+    /// bytes, duplicates allowed
+    fn open_duplicates(engine: &Engine, path: &str) -> Vec<u8> {
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[20..22].copy_from_slice(&(KeyFlags::DUPLICATES | KeyFlags::MODIFIABLE).bits().to_le_bytes());
+        spec[26] = 14;
+        let create = engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.to_string()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+        engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.to_string()),
+            ..Default::default()
+        }).position_block
+    }
+
+    #[test]
+    fn test_duplicates_in_insertion_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("DUPS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let block = open_duplicates(&engine, &path);
+        let run = |operation, block: &[u8], data_buffer: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+
+        // 84 entries fill a leaf, which splits in half between the
+        // duplicates of 40; the last of them is inserted after the split
+        let mut keys: Vec<u32> = (0..=40).collect();
+        keys.extend([40, 40, 40]);
+        keys.extend(41..120);
+        keys.push(40);
+        for (sequence, &key) in keys.iter().enumerate() {
+            let mut record = key.to_le_bytes().to_vec();
+            record.extend((sequence as u32).to_le_bytes());
+            assert_eq!(run(OperationCode::Insert, &block, &record, b"").status, StatusCode::Success);
+        }
+
+        let mut sequences = Vec::new();
+        let mut response = run(OperationCode::GetEqual, &block, b"", &40u32.to_le_bytes());
+        while response.status == StatusCode::Success && response.key_buffer == 40u32.to_le_bytes() {
+            sequences.push(u32::from_le_bytes(response.data_buffer[4..8].try_into().unwrap()));
+            response = run(OperationCode::GetNext, &response.position_block, b"", b"");
+        }
+        assert_eq!(sequences, [40, 41, 42, 43, 123]);
+        assert_eq!(response.key_buffer, 41u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_update_onto_duplicate_goes_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("DUPS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let block = open_duplicates(&engine, &path);
+        let run = |operation, block: &[u8], data_buffer: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+        let record = |key: u32, sequence: u32| [key.to_le_bytes(), sequence.to_le_bytes()].concat();
+        let sequence = |response: &OperationResponse| u32::from_le_bytes(response.data_buffer[4..8].try_into().unwrap());
+
+        // The record moved onto 40 has the lowest address of the three
+        for (sequence, key) in [10u32, 40, 40].into_iter().enumerate() {
+            assert_eq!(run(OperationCode::Insert, &block, &record(key, sequence as u32), b"").status, StatusCode::Success);
+        }
+        let moving = run(OperationCode::GetEqual, &block, b"", &10u32.to_le_bytes());
+        let updated = run(OperationCode::Update, &moving.position_block, &record(40, 0), b"");
+        assert_eq!(updated.status, StatusCode::Success);
+
+        let mut sequences = Vec::new();
+        let mut response = run(OperationCode::GetEqual, &block, b"", &40u32.to_le_bytes());
+        while response.status == StatusCode::Success {
+            sequences.push(sequence(&response));
+            response = run(OperationCode::GetNext, &response.position_block, b"", b"");
+        }
+        assert_eq!(sequences, [1, 2, 0]);
+
+        // The updated cursor sits on it as the last duplicate
+        assert_eq!(run(OperationCode::GetNext, &updated.position_block, b"", b"").status, StatusCode::EndOfFile);
+        assert_eq!(sequence(&run(OperationCode::GetPrevious, &updated.position_block, b"", b"")), 2);
+
+        // and a new duplicate still goes after it
+        assert_eq!(run(OperationCode::Insert, &block, &record(40, 3), b"").status, StatusCode::Success);
+        let last = run(OperationCode::GetLast, &block, b"", b"");
+        assert_eq!(sequence(&last), 3);
+        assert_eq!(sequence(&run(OperationCode::GetPrevious, &last.position_block, b"", b"")), 0);
+    }
+
+    #[test]
+    fn test_next_and_previous_after_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, block: &[u8], key: u32| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: [key.to_le_bytes(), *b"PART"].concat(),
+            key_buffer: key.to_le_bytes().to_vec(),
+            ..Default::default()
+        });
+        for key in [10, 20, 30, 40, 50] {
+            assert_eq!(run(OperationCode::Insert, &block, key).status, StatusCode::Success);
+        }
+        let delete = |key| {
+            let found = run(OperationCode::GetEqual, &block, key);
+            let deleted = run(OperationCode::Delete, &found.position_block, 0);
+            assert_eq!(deleted.status, StatusCode::Success);
+            deleted.position_block
+        };
+        let key = |response: OperationResponse| (response.status, u32::from_le_bytes(response.key_buffer[..4].try_into().unwrap_or([0; 4])));
+
+        // From the middle, both ways go to the neighbours
+        let deleted = delete(30);
+        assert_eq!(key(run(OperationCode::GetNext, &deleted, 0)), (StatusCode::Success, 40));
+        assert_eq!(key(run(OperationCode::GetPrevious, &deleted, 0)), (StatusCode::Success, 20));
+
+        // and on past a neighbour deleted since
+        delete(40);
+        assert_eq!(key(run(OperationCode::GetNext, &deleted, 0)), (StatusCode::Success, 50));
+
+        // From either end, one way runs out
+        let first = delete(10);
+        assert_eq!(key(run(OperationCode::GetNext, &first, 0)), (StatusCode::Success, 20));
+        assert_eq!(run(OperationCode::GetPrevious, &first, 0).status, StatusCode::EndOfFile);
+        let last = delete(50);
+        assert_eq!(run(OperationCode::GetNext, &last, 0).status, StatusCode::EndOfFile);
+        assert_eq!(key(run(OperationCode::GetPrevious, &last, 0)), (StatusCode::Success, 20));
+
+        // and from the last record of all, both do
+        let only = delete(20);
+        assert_eq!(run(OperationCode::GetNext, &only, 0).status, StatusCode::EndOfFile);
+        assert_eq!(run(OperationCode::GetPrevious, &only, 0).status, StatusCode::EndOfFile);
+    }
+
+    #[test]
+    fn test_next_and_previous_after_deleting_a_duplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("DUPS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let block = open_duplicates(&engine, &path);
+        let run = |operation, block: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+        for (sequence, key) in [4u32, 5, 5, 5, 5, 6].into_iter().enumerate() {
+            let mut record = key.to_le_bytes().to_vec();
+            record.extend((sequence as u32).to_le_bytes());
+            let insert = engine.execute(1, OperationRequest {
+                operation: OperationCode::Insert,
+                position_block: block.clone(),
+                data_buffer: record,
+                ..Default::default()
+            });
+            assert_eq!(insert.status, StatusCode::Success);
+        }
+        let sequence = |response: &OperationResponse| u32::from_le_bytes(response.data_buffer[4..8].try_into().unwrap());
+        // The duplicate of 5 `steps` on from the first
+        let duplicate = |steps| {
+            let mut response = run(OperationCode::GetEqual, &block, &5u32.to_le_bytes());
+            for _ in 0..steps {
+                response = run(OperationCode::GetNext, &response.position_block, b"");
+            }
+            response
+        };
+
+        // From a deleted duplicate, Get Next goes on to the one after it
+        let middle = duplicate(1);
+        assert_eq!(sequence(&middle), 2);
+        let deleted = run(OperationCode::Delete, &middle.position_block, b"");
+        assert_eq!(deleted.status, StatusCode::Success);
+        assert_eq!(sequence(&run(OperationCode::GetNext, &deleted.position_block, b"")), 3);
+
+        // and Get Previous back to the one before
+        let middle = duplicate(1);
+        assert_eq!(sequence(&middle), 3);
+        let deleted = run(OperationCode::Delete, &middle.position_block, b"");
+        assert_eq!(deleted.status, StatusCode::Success);
+        assert_eq!(sequence(&run(OperationCode::GetPrevious, &deleted.position_block, b"")), 1);
+        assert_eq!(sequence(&run(OperationCode::GetNext, &deleted.position_block, b"")), 4);
+
+        // A record updated onto 5 is the last duplicate, though its
+        // address is the lowest; deleting the one before it leaves Get
+        // Next on it and Get Previous on the first
+        let moved = run(OperationCode::GetEqual, &block, &4u32.to_le_bytes());
+        let update = engine.execute(1, OperationRequest {
+            operation: OperationCode::Update,
+            position_block: moved.position_block,
+            data_buffer: [5u32.to_le_bytes(), 0u32.to_le_bytes()].concat(),
+            ..Default::default()
+        });
+        assert_eq!(update.status, StatusCode::Success);
+        let middle = duplicate(1);
+        assert_eq!(sequence(&middle), 4);
+        let deleted = run(OperationCode::Delete, &middle.position_block, b"");
+        assert_eq!(deleted.status, StatusCode::Success);
+        assert_eq!(sequence(&run(OperationCode::GetNext, &deleted.position_block, b"")), 0);
+        assert_eq!(sequence(&run(OperationCode::GetPrevious, &deleted.position_block, b"")), 1);
+
+        // Deleting that last duplicate leaves Get Next on the next key
+        let last = duplicate(1);
+        assert_eq!(sequence(&last), 0);
+        let deleted = run(OperationCode::Delete, &last.position_block, b"");
+        assert_eq!(sequence(&run(OperationCode::GetNext, &deleted.position_block, b"")), 5);
+        assert_eq!(sequence(&run(OperationCode::GetPrevious, &deleted.position_block, b"")), 1);
+    }
+
+    #[test]
+    fn test_delete_from_duplicates_across_leaves() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("DUPS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        let block = open_duplicates(&engine, &path);
+        let run = |operation, block: &[u8], data_buffer: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+        let sequence = |response: &OperationResponse| u32::from_le_bytes(response.data_buffer[4..8].try_into().unwrap());
+
+        // 84 entries fill a leaf, which splits in half: duplicates of 40
+        // with sequences 40 and 41 stay, 42 to 79 go to the new leaf
+        let keys: Vec<u32> = (0..40).chain([40; 40]).chain(80..90).collect();
+        for (sequence, &key) in keys.iter().enumerate() {
+            let record = [key.to_le_bytes(), (sequence as u32).to_le_bytes()].concat();
+            assert_eq!(run(OperationCode::Insert, &block, &record, b"").status, StatusCode::Success);
+        }
+        let mut duplicate = run(OperationCode::GetEqual, &block, b"", &40u32.to_le_bytes());
+        let mut chain = Vec::new();
+        while duplicate.status == StatusCode::Success && duplicate.key_buffer == 40u32.to_le_bytes() {
+            chain.push(duplicate.clone());
+            duplicate = run(OperationCode::GetNext, &duplicate.position_block, b"", b"");
+        }
+        assert_eq!(chain.iter().map(sequence).collect::<Vec<_>>(), (40..80).collect::<Vec<_>>());
+
+        // Each of the duplicates either side of the split, deleted, leaves
+        // Get Next and Get Previous on its neighbours in the chain
+        let mut gone = Vec::new();
+        for at in [1, 2, 20, 21, 38] {
+            let deleted = run(OperationCode::Delete, &chain[at].position_block, b"", b"");
+            assert_eq!(deleted.status, StatusCode::Success);
+            gone.push(at);
+            let next = run(OperationCode::GetNext, &deleted.position_block, b"", b"");
+            let previous = run(OperationCode::GetPrevious, &deleted.position_block, b"", b"");
+            let after = (at + 1..).find(|i| !gone.contains(i)).unwrap();
+            let before = (0..at).rev().find(|i| !gone.contains(i)).unwrap();
+            assert_eq!(sequence(&next), 40 + after as u32, "after deleting {}", at);
+            assert_eq!(sequence(&previous), 40 + before as u32, "before deleting {}", at);
+        }
+    }
+
+    #[test]
+    fn test_delete_and_rename_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path("CUST.DAT"));
+        create_parts(&engine, &path("ORDERS.DAT"));
+        let run = |operation, name: &str, data_buffer: &[u8], key_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            file_path: Some(path(name)),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: key_buffer.to_vec(),
+            ..Default::default()
+        });
+        let on_block = |operation, block: &[u8], buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer: buffer.to_vec(),
+            key_buffer: buffer.to_vec(),
+            ..Default::default()
+        });
+
+        let block = run(OperationCode::Open, "CUST.DAT", b"", b"").position_block;
+        assert_eq!(on_block(OperationCode::Insert, &block, b"\x01\0\0\0ACME").status, StatusCode::Success);
+        assert_eq!(on_block(OperationCode::SetOwner, &block, b"BOSS").status, StatusCode::Success);
+        assert_eq!(run(OperationCode::DeleteFile, "CUST.DAT", b"", b"BOSS").status, StatusCode::FileInUse);
+        assert_eq!(on_block(OperationCode::Close, &block, b"").status, StatusCode::Success);
+
+        let rename = |to: &str, owner: &[u8]| run(OperationCode::RenameFile, "CUST.DAT", path(to).as_bytes(), owner);
+        assert_eq!(rename("CLIENTS.DAT", b"").status, StatusCode::InvalidOwner);
+        assert_eq!(rename("ORDERS.DAT", b"BOSS").status, StatusCode::FileAlreadyExists);
+        assert_eq!(rename("CLIENTS.DAT", b"BOSS").status, StatusCode::Success);
+        assert!(!Path::new(&path("CUST.DAT")).exists());
+
+        let block = run(OperationCode::Open, "CLIENTS.DAT", b"", b"BOSS").position_block;
+        assert_eq!(&on_block(OperationCode::Stat, &block, b"").data_buffer[6..10], &1u32.to_le_bytes());
+        assert_eq!(on_block(OperationCode::Close, &block, b"").status, StatusCode::Success);
+
+        assert_eq!(run(OperationCode::DeleteFile, "CLIENTS.DAT", b"", b"WORKER").status, StatusCode::InvalidOwner);
+        assert_eq!(run(OperationCode::DeleteFile, "CLIENTS.DAT", b"", b"BOSS").status, StatusCode::Success);
+        assert_eq!(run(OperationCode::Open, "CLIENTS.DAT", b"", b"BOSS").status, StatusCode::FileNotFound);
+        assert_eq!(run(OperationCode::DeleteFile, "CLIENTS.DAT", b"", b"").status, StatusCode::FileNotFound);
+
+        // A server may keep both from its clients
+        let engine = Engine::new(16).without_file_admin();
+        create_parts(&engine, &path("CUST.DAT"));
+        let run = |operation, data_buffer: &[u8]| engine.execute(1, OperationRequest {
+            operation,
+            file_path: Some(path("CUST.DAT")),
+            data_buffer: data_buffer.to_vec(),
+            ..Default::default()
+        }).status;
+        assert_eq!(run(OperationCode::RenameFile, path("CLIENTS.DAT").as_bytes()), StatusCode::AccessDenied);
+        assert_eq!(run(OperationCode::DeleteFile, b""), StatusCode::AccessDenied);
+        assert!(Path::new(&path("CUST.DAT")).exists());
+    }
+
+    #[test]
+    fn test_compressed_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::new(16);
+        let fill = |name: &str, flags: u16| {
+            let path = dir.path().join(name).to_string_lossy().to_string();
+            let mut spec = vec![0u8; 32];
+            spec[0..2].copy_from_slice(&64u16.to_le_bytes());
+            spec[2..4].copy_from_slice(&4096u16.to_le_bytes());
+            spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+            spec[8..10].copy_from_slice(&flags.to_le_bytes());
+            spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+            spec[26] = 14;
+            let create = engine.execute(1, OperationRequest {
+                operation: OperationCode::Create,
+                file_path: Some(path.clone()),
+                data_buffer: spec,
+                ..Default::default()
+            });
+            assert_eq!(create.status, StatusCode::Success);
+            let block = engine.execute(1, OperationRequest {
+                operation: OperationCode::Open,
+                file_path: Some(path.clone()),
+                ..Default::default()
+            }).position_block;
+            for key in 0..200u32 {
+                let mut record = vec![b' '; 64];
+                record[..4].copy_from_slice(&key.to_le_bytes());
+                record[4..18].copy_from_slice(b"ARCHIVED ENTRY");
+                let insert = engine.execute(1, OperationRequest {
+                    operation: OperationCode::Insert,
+                    position_block: block.clone(),
+                    data_buffer: record,
+                    ..Default::default()
+                });
+                assert_eq!(insert.status, StatusCode::Success);
+            }
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::Close,
+                position_block: block,
+                ..Default::default()
+            });
+            path
+        };
+
+        let plain = std::fs::metadata(fill("PLAIN.DAT", 0)).unwrap().len();
+        let path = fill("PACKED.DAT", FileFlags::COMPRESSED.bits());
+        // Closing dropped the frames each insert left behind
+        let packed = std::fs::metadata(&path).unwrap().len();
+        assert!(packed * 2 < plain, "{} compressed, {} plain", packed, plain);
+
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let stat = engine.execute(1, OperationRequest {
+            operation: OperationCode::Stat,
+            position_block: block.clone(),
+            ..Default::default()
+        });
+        assert_eq!(&stat.data_buffer[6..10], &200u32.to_le_bytes());
+        assert_ne!(u16::from_le_bytes([stat.data_buffer[10], stat.data_buffer[11]]) & FileFlags::COMPRESSED.bits(), 0);
+        let first = engine.execute(1, OperationRequest {
+            operation: OperationCode::StepFirst,
+            position_block: block,
+            ..Default::default()
+        });
+        assert_eq!(first.status, StatusCode::Success);
+    }
+
+    #[test]
+    fn test_owner_encrypts_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PAYROLL.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let open = |owner: &[u8]| engine.execute(2, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            key_buffer: owner.to_vec(),
+            ..Default::default()
+        });
+        let run = |operation, block: &[u8], data_buffer: &[u8], key_buffer: &[u8], key_number| {
+            engine.execute(2, OperationRequest {
+                operation,
+                position_block: block.to_vec(),
+                data_buffer: data_buffer.to_vec(),
+                key_buffer: key_buffer.to_vec(),
+                key_number,
+                ..Default::default()
+            })
+        };
+
+        let block = open(b"").position_block;
+        for key in [1u32, 2, 3] {
+            let insert = run(OperationCode::Insert, &block, &[key.to_le_bytes(), *b"WAGE"].concat(), b"", 0);
+            assert_eq!(insert.status, StatusCode::Success);
+        }
+        assert!(std::fs::read(&path).unwrap().windows(4).any(|w| w == b"WAGE"));
+
+        // Access code 3 can't be honoured; names must match
+        assert_eq!(run(OperationCode::SetOwner, &block, b"BOSS", b"BOSS", 3).status, StatusCode::InvalidOwner);
+        assert_eq!(run(OperationCode::SetOwner, &block, b"BOSS", b"BOS", 2).status, StatusCode::InvalidOwner);
+        assert_eq!(run(OperationCode::SetOwner, &block, b"BOSS", b"BOSS\0", 2).status, StatusCode::Success);
+        assert_eq!(run(OperationCode::SetOwner, &block, b"BOSS", b"BOSS", 2).status, StatusCode::OwnerAlreadySet);
+        assert!(!std::fs::read(&path).unwrap().windows(4).any(|w| w == b"WAGE"));
+        assert_eq!(run(OperationCode::GetFirst, &block, b"", b"", 0).status, StatusCode::Success);
+        assert_eq!(run(OperationCode::Close, &block, b"", b"", 0).status, StatusCode::Success);
+
+        assert_eq!(open(b"").status, StatusCode::InvalidOwner);
+        assert_eq!(open(b"WORKER").status, StatusCode::InvalidOwner);
+        let block = open(b"BOSS").position_block;
+        let stat = run(OperationCode::Stat, &block, b"", b"", 0);
+        assert_eq!(&stat.data_buffer[6..10], &3u32.to_le_bytes());
+        let insert = run(OperationCode::Insert, &block, &[4u32.to_le_bytes(), *b"WAGE"].concat(), b"", 0);
+        assert_eq!(insert.status, StatusCode::Success);
+
+        // Clearing the owner leaves the pages readable again
+        assert_eq!(run(OperationCode::ClearOwner, &block, b"", b"WORKER", 0).status, StatusCode::InvalidOwner);
+        assert_eq!(run(OperationCode::ClearOwner, &block, b"", b"BOSS", 0).status, StatusCode::Success);
+        assert_eq!(std::fs::read(&path).unwrap().windows(4).filter(|w| w == b"WAGE").count(), 4);
+    }
+
+    #[test]
+    fn test_cancelled_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PAYROLL.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        }).position_block;
+        let insert = engine.execute(1, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block.clone(),
+            data_buffer: [1u32.to_le_bytes(), *b"WAGE"].concat(),
+            ..Default::default()
+        });
+        assert_eq!(insert.status, StatusCode::Success);
+        let set_owner = || OperationRequest {
+            operation: OperationCode::SetOwner,
+            position_block: block.clone(),
+            data_buffer: b"BOSS".to_vec(),
+            key_buffer: b"BOSS".to_vec(),
+            key_number: 2,
+            ..Default::default()
+        };
+
+        // A cancelled Set Owner leaves the file as it was
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert_eq!(engine.execute_cancellable(1, set_owner(), &cancel).status, StatusCode::Cancelled);
+        assert!(std::fs::read(&path).unwrap().windows(4).any(|w| w == b"WAGE"));
+
+        assert_eq!(engine.execute_cancellable(1, set_owner(), &CancelToken::new()).status, StatusCode::Success);
+        assert!(!std::fs::read(&path).unwrap().windows(4).any(|w| w == b"WAGE"));
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PAYROLL.DAT").to_string_lossy().to_string();
+        create_parts(&Engine::new(16), &path);
+        let set_owner = |engine: &Engine| {
+            let block = engine.execute(1, OperationRequest {
+                operation: OperationCode::Open,
+                file_path: Some(path.clone()),
+                ..Default::default()
+            }).position_block;
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::SetOwner,
+                position_block: block,
+                data_buffer: b"BOSS".to_vec(),
+                key_buffer: b"BOSS".to_vec(),
+                key_number: 2,
+                ..Default::default()
+            }).status
+        };
+
+        // The rewrite would hold every page of the file at once
+        assert_eq!(set_owner(&Engine::new(16).with_memory_budget(512)), StatusCode::WorkSpaceTooSmall);
+        assert_eq!(set_owner(&Engine::new(16).with_memory_budget(1 << 20)), StatusCode::Success);
+    }
+
+    #[test]
+    fn test_metrics() {
+        #[derive(Default)]
+        struct Counts {
+            operations: parking_lot::Mutex<Vec<(OperationCode, StatusCode)>>,
+        }
+        impl Metrics for Counts {
+            fn operation(&self, operation: OperationCode, status: StatusCode, _elapsed: std::time::Duration) {
+                self.operations.lock().push((operation, status));
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        create_parts(&engine, &path);
+        let counts = Arc::new(Counts::default());
+        engine.add_metrics(counts.clone());
+
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        engine.execute(1, OperationRequest {
+            operation: OperationCode::GetEqual,
+            position_block: block,
+            key_buffer: b"NONE".to_vec(),
+            ..Default::default()
+        });
+        assert_eq!(
+            *counts.operations.lock(),
+            vec![
+                (OperationCode::Open, StatusCode::Success),
+                (OperationCode::GetEqual, StatusCode::KeyNotFound),
+            ]
+        );
+        assert_eq!(engine.stats().open_files, 1);
+    }
+
+    #[test]
+    fn test_list_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT");
+        let engine = Engine::new(16);
+        create_parts(&engine, &path.to_string_lossy());
+        std::fs::write(dir.path().join("NOTES.TXT"), b"not a btrieve file").unwrap();
+        std::fs::write(dir.path().join("PARTS.LCK"), b"pid 1").unwrap();
+
+        // Open, the record count is the engine's, whether written yet or not
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        }).position_block;
+        let insert = engine.execute(1, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block,
+            data_buffer: b"BOLT0001".to_vec(),
+            ..Default::default()
+        });
+        assert_eq!(insert.status, StatusCode::Success);
+
+        assert_eq!(
+            engine.list_files(dir.path()).unwrap(),
+            vec![FileInfo { path, record_length: 8, page_size: 1024, keys: 1, records: 1 }]
+        );
+    }
+
+    #[test]
+    fn test_coherence_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let (here, there) = (Engine::new(16), Engine::new(16));
+        for engine in [&here, &there] {
+            engine.files.set_coherence(dir.path(), Coherence::Validate);
+        }
+        create_parts(&here, &path);
+        let open = |engine: &Engine| {
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::Open,
+                file_path: Some(path.clone()),
+                ..Default::default()
+            }).position_block
+        };
+        let records = |engine: &Engine, block: &Vec<u8>| {
+            let stat = engine.execute(1, OperationRequest {
+                operation: OperationCode::Stat,
+                position_block: block.clone(),
+                ..Default::default()
+            });
+            u32::from_le_bytes(stat.data_buffer[6..10].try_into().unwrap())
+        };
+        let (block_here, block_there) = (open(&here), open(&there));
+        assert_eq!(records(&there, &block_there), 0);
+
+        // An insert by the other engine shows once page 0 is read back
+        let insert = here.execute(1, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block_here,
+            data_buffer: b"BOLT0001".to_vec(),
+            ..Default::default()
+        });
+        assert_eq!(insert.status, StatusCode::Success);
+        assert_eq!(records(&there, &block_there), 1);
+    }
+
+    #[test]
+    fn test_page_key_encrypts_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("LEDGER.DAT").to_string_lossy().to_string();
+        let engine = Engine::with_page_key(16, [9u8; KEY_LEN]);
+        create_parts(&engine, &path);
+        let open = |engine: &Engine| engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        });
+        let block = open(&engine).position_block;
+        let insert = engine.execute(1, OperationRequest {
+            operation: OperationCode::Insert,
+            position_block: block.clone(),
+            data_buffer: [7u32.to_le_bytes(), *b"CASH"].concat(),
+            ..Default::default()
+        });
+        assert_eq!(insert.status, StatusCode::Success);
+        engine.shutdown();
+
+        assert!(!std::fs::read(&path).unwrap().windows(4).any(|w| w == b"CASH"));
+        assert_eq!(open(&Engine::new(16)).status, StatusCode::AccessDenied);
+        assert_eq!(open(&Engine::with_page_key(16, [8u8; KEY_LEN])).status, StatusCode::AccessDenied);
+        assert_eq!(open(&Engine::with_page_key(16, [9u8; KEY_LEN])).status, StatusCode::Success);
+    }
+
+    #[test]
+    fn test_strict_compat() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16).with_strict_compat();
+        let ping = OperationRequest { operation: OperationCode::Ping, ..Default::default() };
+        assert_eq!(engine.execute(1, ping).status, StatusCode::InvalidOperation);
+
+        let create = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut spec = vec![0u8; 32];
+            spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+            spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+            spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+            spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+            spec[26] = 1;
+            edit(&mut spec);
+            engine.execute(1, OperationRequest {
+                operation: OperationCode::Create,
+                file_path: Some(path.clone()),
+                data_buffer: spec,
+                ..Default::default()
+            }).status
+        };
+        assert_eq!(create(&|spec| spec[0..2].copy_from_slice(&2u16.to_le_bytes())), StatusCode::InvalidRecordLength);
+        assert_eq!(create(&|spec| spec[18..20].copy_from_slice(&3u16.to_le_bytes())), StatusCode::InvalidKeyLength);
+        let recycle = FileFlags::RECYCLE.bits().to_le_bytes();
+        assert_eq!(create(&|spec| spec[8..10].copy_from_slice(&recycle)), StatusCode::OperationNotAllowed);
+        assert_eq!(create(&|_| {}), StatusCode::Success);
+
+        let open = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path.clone()),
+            ..Default::default()
+        }).position_block;
+        let run = |operation, data_buffer: &[u8], key_number| engine.execute(1, OperationRequest {
+            operation,
+            position_block: open.clone(),
+            data_buffer: data_buffer.to_vec(),
+            key_buffer: data_buffer.to_vec(),
+            key_number,
+            ..Default::default()
+        }).status;
+        // Short records aren't padded
+        assert_eq!(run(OperationCode::Insert, b"\x01\0\0\0", 0), StatusCode::DataBufferTooShort);
+        assert_eq!(run(OperationCode::Insert, b"\x01\0\0\0BOLT", 0), StatusCode::Success);
+        assert_eq!(run(OperationCode::SetOwner, b"SECRET\0", 2), StatusCode::OperationNotAllowed);
+        assert_eq!(run(OperationCode::SetOwner, b"SECRET\0", 0), StatusCode::Success);
+    }
+
+    #[test]
+    fn test_update_moves_cursor_with_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("PARTS.DAT").to_string_lossy().to_string();
+        let engine = Engine::new(16);
+        // 8-byte records with a modifiable unsigned key in the first 4 bytes
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[20..22].copy_from_slice(&0x0002u16.to_le_bytes());
+        spec[26] = 14;
+        let create = engine.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.clone()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+        let block = engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        }).position_block;
+        let record = |key: u32| [key.to_le_bytes(), *b"PART"].concat();
+        let run = |operation, block: &[u8], data_buffer| engine.execute(1, OperationRequest {
+            operation,
+            position_block: block.to_vec(),
+            data_buffer,
+            ..Default::default()
+        });
+
+        let mut current = Vec::new();
+        for key in [10, 20, 30, 40] {
+            current = run(OperationCode::Insert, &block, record(key)).position_block;
+        }
+        // Move the record from 40 to 15: Get Next carries on after 15
+        let update = run(OperationCode::Update, &current, record(15));
+        assert_eq!(update.status, StatusCode::Success);
+        assert_eq!(PositionBlock::from_bytes(&update.position_block).get_cursor_id(), 1);
+        let next = run(OperationCode::GetNext, &update.position_block, Vec::new());
+        assert_eq!(next.status, StatusCode::Success);
+        assert_eq!(next.key_buffer, 20u32.to_le_bytes().to_vec());
+        let previous = run(OperationCode::GetPrevious, &update.position_block, Vec::new());
+        assert_eq!(previous.key_buffer, 10u32.to_le_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_error_detail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT").to_string_lossy().to_string();
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&8u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[8..10].copy_from_slice(&FileFlags::SPLIT_INDEX.bits().to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[26] = 14;
+        let create = Engine::new(16).execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some(path.clone()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(create.status, StatusCode::Success);
+        std::fs::remove_file(dir.path().join("CUST.IX")).unwrap();
+
+        let engine = Engine::new(16);
+        let open = |path: String| engine.execute(1, OperationRequest {
+            operation: OperationCode::Open,
+            file_path: Some(path),
+            ..Default::default()
+        });
+        // A bare status has nothing to add
+        let missing = open(dir.path().join("NONE.DAT").to_string_lossy().to_string());
+        assert_eq!(missing.status, StatusCode::FileNotFound);
+        assert_eq!(missing.detail, None);
+
+        let open = open(path.clone());
+        assert_eq!(open.status, StatusCode::NotBtrieveFile);
+        let detail = open.detail.unwrap();
+        assert!(detail.message.contains("index file"), "{}", detail.message);
+        assert_eq!(detail.context, format!("Open on {}", path));
+    }
+}
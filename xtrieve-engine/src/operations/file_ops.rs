@@ -1,13 +1,22 @@
-//! File operations: Open, Close, Create, Stat
+//! File operations: Open, Close, Create, Stat, Set Owner, Clear Owner,
+//! Clone File, Delete File, Rename File, Truncate File
 
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::cancel::CancelToken;
 use crate::error::{BtrieveError, BtrieveResult, StatusCode};
 use crate::file_manager::cursor::PositionBlock;
 use crate::file_manager::locking::SessionId;
 use crate::file_manager::open_files::OpenMode;
-use crate::storage::fcr::FileControlRecord;
-use crate::storage::key::{KeySpec, KeyFlags, KeyType};
+use crate::storage::compress::Compression;
+use crate::storage::crypt::{self, Encryption, PageCipher};
+use crate::storage::fcr::{FileControlRecord, FileFlags, Owner};
+use crate::storage::files::Layout;
+use crate::storage::key::{KeySpec, SpecLayout};
+use crate::storage::check::check_fcr_file;
+use crate::storage::rollfwd::Change;
+use crate::storage::selfcheck;
 
 use super::dispatcher::{Engine, OperationRequest, OperationResponse};
 
@@ -21,25 +30,30 @@ pub fn open(
         .ok_or(BtrieveError::Status(StatusCode::InvalidFileName))?;
 
     let mode = OpenMode::from_raw(req.open_mode);
+
+    // Create position block for this file, which must be able to name it
+    let mut position = PositionBlock::new();
+    if !position.set_file_path(path) {
+        return Err(BtrieveError::Status(StatusCode::InvalidFileName));
+    }
     let path = PathBuf::from(path);
 
-    // Open the file
-    let file = engine.files.open(&path, mode)?;
+    // Open the file, with the owner name from the key buffer if it has one
+    let _file = engine.files.open(&path, mode, owner_name(&req.key_buffer))?;
 
-    // Create position block for this file
-    let mut position = PositionBlock::new();
-    // Store a reference to the file path in the position block
-    let path_str = path.to_string_lossy();
-    let path_bytes = path_str.as_bytes();
-    let len = path_bytes.len().min(64);
-    position.data[64..64 + len].copy_from_slice(&path_bytes[..len]);
-
-    // Acquire file lock
-    engine.locks.lock_file(
-        &path.to_string_lossy(),
-        session,
-        mode.exclusive,
-    )?;
+    // Acquire file lock, giving the open back if that fails
+    if let Err(e) = engine.locks.lock_file(&path.to_string_lossy(), session, mode.exclusive) {
+        let _ = engine.files.close(&path);
+        return Err(e);
+    }
+
+    // Each open is its own cursor, so a session can hold several on a file
+    let Some((cursor_id, nonce)) = engine.sessions.opened(session, &path, mode.read_only) else {
+        let _ = engine.files.close(&path);
+        return Err(BtrieveError::Status(StatusCode::HandleTableFull));
+    };
+    position.set_cursor_id(cursor_id);
+    position.set_check(nonce);
 
     Ok(OperationResponse::success()
         .with_position(position.data.to_vec()))
@@ -54,20 +68,19 @@ pub fn close(
     // Get file path from position block or request
     let path = if let Some(ref p) = req.file_path {
         PathBuf::from(p)
-    } else if !req.position_block.is_empty() {
-        // Extract path from position block (stored at offset 64)
-        let end = req.position_block[64..].iter()
-            .position(|&b| b == 0)
-            .unwrap_or(64);
-        let path_str = String::from_utf8_lossy(&req.position_block[64..64 + end]);
-        PathBuf::from(path_str.as_ref())
+    } else if let Some(path) = PositionBlock::from_bytes(&req.position_block).file_path() {
+        path
     } else {
         return Err(BtrieveError::Status(StatusCode::FileNotOpen));
     };
 
-    // Release locks
-    engine.locks.unlock_all_records(&path.to_string_lossy(), session);
-    engine.locks.unlock_file(&path.to_string_lossy(), session);
+    // Release locks, unless the session still has the file open through
+    // another cursor
+    let cursor_id = PositionBlock::from_bytes(&req.position_block).get_cursor_id();
+    if !engine.sessions.closed(session, &path, cursor_id) {
+        engine.locks.unlock_all_records(&path.to_string_lossy(), session);
+        engine.locks.unlock_file(&path.to_string_lossy(), session);
+    }
 
     // Flush and close
     if let Some(file) = engine.files.get(&path) {
@@ -86,6 +99,183 @@ pub fn close(
     Ok(OperationResponse::success())
 }
 
+/// An owner name as a client passes it: up to 8 bytes, ended by a NUL if
+/// shorter. `None` if there is none
+fn owner_name(buffer: &[u8]) -> Option<&[u8]> {
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Some(&buffer[..end.min(8)]).filter(|name| !name.is_empty())
+}
+
+/// Path of the file a position block names
+fn open_path(req: &OperationRequest) -> BtrieveResult<PathBuf> {
+    PositionBlock::from_bytes(&req.position_block)
+        .file_path()
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))
+}
+
+/// Write the file's dirty pages out of the cache, before it is rewritten
+fn flush_file(engine: &Engine, path: &std::path::Path) -> BtrieveResult<()> {
+    let file = engine.files.get(path).ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let dirty = engine.cache.invalidate_file(&path.to_string_lossy());
+    let f = file.read();
+    for page in dirty {
+        f.write_page(&page)?;
+    }
+    Ok(())
+}
+
+/// Operation 29: Set an owner name on an open file
+///
+/// The data and key buffers both hold the name. The key number is the
+/// access code: bit 0 lets the file be opened read-only without the name,
+/// bit 1 encrypts its pages with a key derived from the name. Both
+/// together (code 3) are refused: an encrypted file can't be read without
+/// the key. Rewriting the file for its new protection can be cancelled
+/// while its pages are being read.
+pub fn set_owner(
+    engine: &Engine,
+    _session: SessionId,
+    req: &OperationRequest,
+    cancel: &CancelToken,
+) -> BtrieveResult<OperationResponse> {
+    let path = open_path(req)?;
+    let name = owner_name(&req.key_buffer).ok_or(BtrieveError::Status(StatusCode::InvalidOwner))?;
+    if owner_name(&req.data_buffer) != Some(name) || !(0..=2).contains(&req.key_number) {
+        return Err(BtrieveError::Status(StatusCode::InvalidOwner));
+    }
+
+    flush_file(engine, &path)?;
+    let file = engine.files.get(&path).ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let mut f = file.write();
+    if f.fcr.owner.is_some() {
+        return Err(BtrieveError::Status(StatusCode::OwnerAlreadySet));
+    }
+
+    let salt = crypt::new_salt();
+    let owner = Owner {
+        read_only_without: req.key_number & 1 != 0,
+        check: crypt::owner_check(name, &salt),
+    };
+    let (encryption, cipher) = if req.key_number & 2 != 0 {
+        (Encryption::Owner, Some(PageCipher::for_owner(name, &salt)))
+    } else {
+        engine.files.default_protection(&salt)
+    };
+    let mut fcr = f.fcr.clone();
+    fcr.owner = Some(owner);
+    fcr.salt = salt;
+    fcr.encryption = encryption;
+    charge_rewrite(engine, &f.fcr)?;
+    f.rewrite(fcr, cipher, cancel)?;
+
+    Ok(OperationResponse::success())
+}
+
+/// Count the pages a rewrite holds at once against the memory budget
+fn charge_rewrite(engine: &Engine, fcr: &FileControlRecord) -> BtrieveResult<()> {
+    engine.memory_budget().charge(fcr.num_pages as usize * fcr.page_size as usize)
+}
+
+/// Operation 30: Clear the owner name of an open file, given in the key
+/// buffer. Pages encrypted with the name are decrypted, or sealed with the
+/// daemon key if there is one. Cancellable like `set_owner`
+pub fn clear_owner(
+    engine: &Engine,
+    _session: SessionId,
+    req: &OperationRequest,
+    cancel: &CancelToken,
+) -> BtrieveResult<OperationResponse> {
+    let path = open_path(req)?;
+
+    flush_file(engine, &path)?;
+    let file = engine.files.get(&path).ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let mut f = file.write();
+    let Some(owner) = &f.fcr.owner else {
+        return Ok(OperationResponse::success());
+    };
+    match owner_name(&req.key_buffer) {
+        Some(name) if crypt::owner_check(name, &f.fcr.salt) == owner.check => {}
+        _ => return Err(BtrieveError::Status(StatusCode::InvalidOwner)),
+    }
+
+    let salt = crypt::new_salt();
+    let (encryption, cipher) = engine.files.default_protection(&salt);
+    let mut fcr = f.fcr.clone();
+    fcr.owner = None;
+    fcr.salt = salt;
+    fcr.encryption = encryption;
+    charge_rewrite(engine, &f.fcr)?;
+    f.rewrite(fcr, cipher, cancel)?;
+
+    Ok(OperationResponse::success())
+}
+
+/// Limits a daemon puts on Create requests (see `Engine::with_create_limits`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CreateLimits {
+    /// Page size for a Create that leaves it 0. Without one, such a
+    /// Create fails with status 24 as any other invalid page size does
+    pub default_page_size: Option<u16>,
+    /// Longest record a file may be created for; longer fail with status 28
+    pub max_record_length: Option<u16>,
+    /// Layout Create buffers must have. Without one a buffer exactly the
+    /// length of a compact spec is taken as one, and any other as Xtrieve's
+    pub layout: Option<SpecLayout>,
+}
+
+/// File and key specifications of a Create buffer
+pub(crate) struct CreateSpec {
+    pub record_length: u16,
+    pub page_size: u16,
+    pub flags: FileFlags,
+    pub keys: Vec<KeySpec>,
+}
+
+impl CreateSpec {
+    /// Parse `buf` laid out as `layout`, or as the layout its length
+    /// suggests
+    pub(crate) fn parse(buf: &[u8], layout: Option<SpecLayout>) -> BtrieveResult<Self> {
+        if buf.len() < 6 {
+            return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
+        }
+        let u16_at = |at: usize| u16::from_le_bytes([buf[at], buf[at + 1]]);
+        let num_keys = u16_at(4) as usize;
+        let layout = layout.unwrap_or_else(|| {
+            let compact = SpecLayout::Compact.file_spec_size();
+            let exact = buf.len() >= compact && (buf.len() - compact).is_multiple_of(KeySpec::SIZE);
+            if exact && buf.len() - compact >= num_keys * KeySpec::SIZE {
+                SpecLayout::Compact
+            } else {
+                SpecLayout::Xtrieve
+            }
+        });
+        let header = layout.file_spec_size();
+        if buf.len() < header + num_keys * KeySpec::SIZE {
+            return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
+        }
+
+        let flags = match layout {
+            SpecLayout::Xtrieve => u16_at(8),
+            SpecLayout::Classic => u16_at(10),
+            SpecLayout::Compact => 0,
+        };
+        let keys = buf[header..]
+            .chunks_exact(KeySpec::SIZE)
+            .take(num_keys)
+            .map(|spec| {
+                KeySpec::from_layout(spec, layout)
+                    .map_err(|_| BtrieveError::Status(StatusCode::InvalidKeyPosition))
+            })
+            .collect::<BtrieveResult<_>>()?;
+        Ok(CreateSpec {
+            record_length: u16_at(0),
+            page_size: u16_at(2),
+            flags: FileFlags::from_bits_truncate(flags),
+            keys,
+        })
+    }
+}
+
 /// Operation 14: Create a new Btrieve file
 pub fn create(
     engine: &Engine,
@@ -95,23 +285,15 @@ pub fn create(
     let path = req.file_path.as_ref()
         .ok_or(BtrieveError::Status(StatusCode::InvalidFileName))?;
 
-    // Parse file specification from data buffer
-    // Btrieve 5.x format:
-    //   0-1:   record_length
-    //   2-3:   page_size
-    //   4-5:   num_keys
-    //   6-7:   unused
-    //   8-11:  file_flags
-    //   12-13: reserved
-    //   14-15: preallocation
-    //   16+:   key specs (16 bytes each)
-    if req.data_buffer.len() < 16 {
-        return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
-    }
-
-    let record_length = u16::from_le_bytes([req.data_buffer[0], req.data_buffer[1]]);
-    let page_size = u16::from_le_bytes([req.data_buffer[2], req.data_buffer[3]]);
-    let num_keys = u16::from_le_bytes([req.data_buffer[4], req.data_buffer[5]]);
+    // Parse file specification from data buffer: Xtrieve's layout, the
+    // compact one of the SDKs or Btrieve's (see `SpecLayout`)
+    let limits = engine.create_limits();
+    let spec = CreateSpec::parse(&req.data_buffer, limits.layout)?;
+    let record_length = spec.record_length;
+    let page_size = match spec.page_size {
+        0 => limits.default_page_size.unwrap_or(0),
+        page_size => page_size,
+    };
 
     // Validate page size
     if !crate::storage::page::PAGE_SIZES.contains(&page_size) {
@@ -119,35 +301,57 @@ pub fn create(
     }
 
     // Validate record length
-    if record_length == 0 || record_length > page_size - 20 {
+    if record_length == 0
+        || record_length > page_size - 20
+        || limits.max_record_length.is_some_and(|max| record_length > max)
+    {
         return Err(BtrieveError::Status(StatusCode::InvalidRecordLength));
     }
 
-    // Parse key specifications (start at offset 16 in Btrieve 5.x)
-    let mut keys = Vec::with_capacity(num_keys as usize);
-    let mut offset = 16;
-
-    for _ in 0..num_keys {
-        if offset + 16 > req.data_buffer.len() {
-            return Err(BtrieveError::Status(StatusCode::DataBufferTooShort));
-        }
-
-        let key = KeySpec::from_bytes(&req.data_buffer[offset..])?;
-
-        // Validate key
+    // Validate keys
+    let keys = spec.keys;
+    for key in &keys {
         if key.position + key.length > record_length {
             return Err(BtrieveError::Status(StatusCode::InvalidKeyPosition));
         }
         if key.length == 0 || key.length > 255 {
             return Err(BtrieveError::Status(StatusCode::InvalidKeyLength));
         }
-
-        keys.push(key);
-        offset += 16;
     }
 
-    // Create FCR
-    let fcr = FileControlRecord::new(record_length, page_size, keys);
+    // Create FCR; the data compression flag compresses its pages, the
+    // recycle flag keeps deleted records, the roll-forward flag logs
+    // committed changes, the split index flag keeps the index pages in a
+    // file of their own and the index per key flag each key's in its own.
+    // Compressed pages aren't in slots an index file could mirror, so
+    // those don't go with compression
+    let mut fcr = FileControlRecord::new(record_length, page_size, keys);
+    let file_flags = spec.flags;
+    if file_flags.contains(FileFlags::COMPRESSED) {
+        fcr.flags |= FileFlags::COMPRESSED;
+        fcr.compression = Compression::Lz4;
+    }
+    if file_flags.contains(FileFlags::RECYCLE) {
+        fcr.flags |= FileFlags::RECYCLE;
+        fcr.recycle = true;
+    }
+    if file_flags.contains(FileFlags::ROLL_FORWARD) {
+        fcr.flags |= FileFlags::ROLL_FORWARD;
+        fcr.roll_forward = true;
+    }
+    let layout = match (file_flags.contains(FileFlags::SPLIT_INDEX), file_flags.contains(FileFlags::INDEX_PER_KEY)) {
+        (false, false) => Layout::Single,
+        (true, false) => Layout::Split,
+        (false, true) => Layout::PerKey,
+        (true, true) => return Err(BtrieveError::Status(StatusCode::OperationNotAllowed)),
+    };
+    if layout != Layout::Single {
+        if fcr.compression != Compression::None {
+            return Err(BtrieveError::Status(StatusCode::OperationNotAllowed));
+        }
+        fcr.flags |= file_flags & (FileFlags::SPLIT_INDEX | FileFlags::INDEX_PER_KEY);
+        fcr.layout = layout;
+    }
 
     // Create the file
     let path = PathBuf::from(path);
@@ -156,6 +360,119 @@ pub fn create(
     Ok(OperationResponse::success())
 }
 
+/// The path in the data buffer of Clone File and Rename File, ended by a
+/// NUL if shorter. It must fit in a position block to be opened later
+fn new_path(req: &OperationRequest) -> BtrieveResult<&str> {
+    let end = req.data_buffer.iter().position(|&b| b == 0).unwrap_or(req.data_buffer.len());
+    let to = std::str::from_utf8(&req.data_buffer[..end])
+        .map_err(|_| BtrieveError::Status(StatusCode::InvalidFileName))?;
+    if to.is_empty() || !PositionBlock::new().set_file_path(to) {
+        return Err(BtrieveError::Status(StatusCode::InvalidFileName));
+    }
+    Ok(to)
+}
+
+/// Operation 92: Create an empty file at the path in the data buffer with
+/// the record length, page size, flags and keys of an open file, their
+/// alternate collating sequences included: BUTIL -CLONE. Like BUTIL, the
+/// owner name is left behind. An Xtrieve extension
+pub fn clone_file(
+    engine: &Engine,
+    _session: SessionId,
+    req: &OperationRequest,
+) -> BtrieveResult<OperationResponse> {
+    let path = open_path(req)?;
+    let to = new_path(req)?;
+
+    let file = engine.files.get(&path).ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let fcr = {
+        let f = file.read();
+        let mut fcr = FileControlRecord::new(f.fcr.record_length, f.fcr.page_size, f.fcr.keys.clone());
+        fcr.flags = f.fcr.flags;
+        fcr.compression = f.fcr.compression;
+        fcr.recycle = f.fcr.recycle;
+        fcr.layout = f.fcr.layout;
+        fcr.roll_forward = f.fcr.roll_forward;
+        fcr
+    };
+    engine.files.create(Path::new(to), fcr)?;
+
+    Ok(OperationResponse::success())
+}
+
+/// Path of a file to delete or rename, which no session may have open.
+/// Pages Create left in the cache are written out first
+fn unused_path(engine: &Engine, req: &OperationRequest) -> BtrieveResult<PathBuf> {
+    let path = req.file_path.as_ref()
+        .ok_or(BtrieveError::Status(StatusCode::InvalidFileName))?;
+    let path = PathBuf::from(path);
+    if !engine.sessions.holders(&path).is_empty() {
+        return Err(BtrieveError::Status(StatusCode::FileInUse));
+    }
+    if engine.files.get(&path).is_some() {
+        flush_file(engine, &path)?;
+    }
+    Ok(path)
+}
+
+/// Operation 93: Delete a file no session has open, with its recycle bin.
+/// The key buffer holds the owner name of a file that has one. An Xtrieve
+/// extension
+pub fn delete_file(
+    engine: &Engine,
+    _session: SessionId,
+    req: &OperationRequest,
+) -> BtrieveResult<OperationResponse> {
+    let path = unused_path(engine, req)?;
+    engine.files.delete(&path, owner_name(&req.key_buffer))?;
+    engine.locks.cleanup_file(&path.to_string_lossy());
+
+    Ok(OperationResponse::success())
+}
+
+/// Operation 94: Rename a file no session has open to the path in the data
+/// buffer, ended by a NUL if shorter. The key buffer holds the owner name
+/// of a file that has one. An Xtrieve extension
+pub fn rename_file(
+    engine: &Engine,
+    _session: SessionId,
+    req: &OperationRequest,
+) -> BtrieveResult<OperationResponse> {
+    let to = new_path(req)?;
+    let path = unused_path(engine, req)?;
+    engine.files.rename(&path, Path::new(to), owner_name(&req.key_buffer))?;
+    engine.locks.cleanup_file(&path.to_string_lossy());
+
+    Ok(OperationResponse::success())
+}
+
+/// Operation 91: Remove every record of an open file at once, keeping its
+/// keys, flags and owner. The session must be the only one with the file
+/// open. A roll-forward log gets the truncate as one change. An Xtrieve
+/// extension
+pub fn truncate_file(
+    engine: &Engine,
+    session: SessionId,
+    req: &OperationRequest,
+) -> BtrieveResult<OperationResponse> {
+    let path = open_path(req)?;
+    if engine.sessions.holders(&path).iter().any(|&holder| holder != session) {
+        return Err(BtrieveError::Status(StatusCode::FileInUse));
+    }
+
+    flush_file(engine, &path)?;
+    let file = engine.files.get(&path).ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let mut f = file.write();
+    f.truncate()?;
+    if let Some(log) = f.roll_forward_log() {
+        log.append(&[Change::Truncate])?;
+    }
+    drop(f);
+    engine.locks.unlock_all_records(&path.to_string_lossy(), session);
+
+    Ok(OperationResponse::success())
+}
+
 /// Operation 15: Get file statistics
 pub fn stat(
     engine: &Engine,
@@ -165,12 +482,8 @@ pub fn stat(
     // Get file from position block
     let path = if let Some(ref p) = req.file_path {
         PathBuf::from(p)
-    } else if !req.position_block.is_empty() {
-        let end = req.position_block[64..].iter()
-            .position(|&b| b == 0)
-            .unwrap_or(64);
-        let path_str = String::from_utf8_lossy(&req.position_block[64..64 + end]);
-        PathBuf::from(path_str.as_ref())
+    } else if let Some(path) = PositionBlock::from_bytes(&req.position_block).file_path() {
+        path
     } else {
         return Err(BtrieveError::Status(StatusCode::FileNotOpen));
     };
@@ -190,8 +503,12 @@ pub fn stat(
     buffer.extend_from_slice(&fcr.record_length.to_le_bytes());
     buffer.extend_from_slice(&fcr.page_size.to_le_bytes());
     buffer.extend_from_slice(&fcr.num_keys.to_le_bytes());
-    buffer.extend_from_slice(&fcr.num_records.to_le_bytes());
-    buffer.extend_from_slice(&fcr.flags.bits().to_le_bytes());
+    buffer.extend_from_slice(&fcr.reported_records().to_le_bytes());
+    let flags = match engine.is_strict() {
+        true => fcr.flags - super::compat::XTRIEVE_FILE_FLAGS,
+        false => fcr.flags,
+    };
+    buffer.extend_from_slice(&flags.bits().to_le_bytes());
     buffer.extend_from_slice(&fcr.unused_pages.to_le_bytes());
 
     // Add key specifications
@@ -202,17 +519,71 @@ pub fn stat(
     Ok(OperationResponse::success().with_data(buffer))
 }
 
+/// A Btrieve file found by `Engine::list_files`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub record_length: u16,
+    pub page_size: u16,
+    pub keys: usize,
+    pub records: u64,
+}
+
+/// The Btrieve files in `dir`, by name. Files whose FCR fails the quick
+/// check are left out, as are the companions of data files; those the
+/// engine has open are described as it holds them, not as on disk
+pub fn list_files(engine: &Engine, dir: &Path) -> BtrieveResult<Vec<FileInfo>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file() && !selfcheck::is_companion(&name) {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    let mut files = Vec::new();
+    for path in paths {
+        let fcr = match engine.files.get(&path) {
+            Some(file) => file.read().fcr.clone(),
+            None => match check_fcr_file(&path) {
+                Ok(report) if report.is_clean() => selfcheck::read_fcr(&path)?,
+                // Not a Btrieve file, or one too damaged to describe
+                _ => continue,
+            },
+        };
+        files.push(FileInfo {
+            path,
+            record_length: fcr.record_length,
+            page_size: fcr.page_size,
+            keys: fcr.keys.len(),
+            records: fcr.num_records,
+        });
+    }
+    Ok(files)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_open_mode_parsing() {
-        let mode = OpenMode::from_raw(0);
-        assert!(!mode.read_only);
-        assert!(!mode.exclusive);
+        assert_eq!(OpenMode::from_raw(0), OpenMode::read_write());
+        assert!(OpenMode::from_raw(-1).accelerated);
+        assert_eq!(OpenMode::from_raw(-2), OpenMode::read_only());
+        assert!(OpenMode::from_raw(-3).verify);
+        let exclusive = OpenMode::from_raw(-4);
+        assert!(exclusive.exclusive && !exclusive.read_only && !exclusive.accelerated);
+        assert_eq!(OpenMode::from_raw(-9), OpenMode::read_write());
+    }
 
-        let mode = OpenMode::from_raw(-2i32 as i32);
-        // Note: This test depends on exact bit patterns
+    #[test]
+    fn test_owner_name() {
+        assert_eq!(owner_name(b"SECRET\0\0"), Some(&b"SECRET"[..]));
+        assert_eq!(owner_name(b"LONGERTHAN8"), Some(&b"LONGERTH"[..]));
+        assert_eq!(owner_name(b"\0SECRET"), None);
+        assert_eq!(owner_name(b""), None);
     }
 }
@@ -0,0 +1,32 @@
+//! Engine hooks - embedder code run around operations
+//!
+//! An embedder registers hooks with `Engine::add_hook` to audit, validate
+//! or warm caches without changing the dispatcher. Every method has a
+//! default that does nothing, so a hook implements only what it needs.
+//! Hooks run on the thread executing the operation, in the order they were
+//! added, so they should be quick.
+
+use std::path::Path;
+
+use crate::error::StatusCode;
+use crate::file_manager::locking::SessionId;
+
+use super::dispatcher::{OperationRequest, OperationResponse};
+
+/// Code run around the operations an engine executes
+pub trait EngineHook: Send + Sync {
+    /// Before an operation runs. Returning a status refuses the operation
+    /// with it, and later hooks aren't asked
+    fn before_operation(&self, _session: SessionId, _request: &OperationRequest) -> Result<(), StatusCode> {
+        Ok(())
+    }
+
+    /// After an operation ran, or was refused, with the response going back
+    fn after_operation(&self, _session: SessionId, _request: &OperationRequest, _response: &OperationResponse) {}
+
+    /// After a file was opened
+    fn on_open(&self, _session: SessionId, _path: &Path) {}
+
+    /// After a session's transaction was committed
+    fn on_commit(&self, _session: SessionId) {}
+}
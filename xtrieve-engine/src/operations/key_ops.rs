@@ -6,13 +6,15 @@
 //! - Index pages are identified by: prev_sibling=0xFFFFFFFF, next_sibling=0xFFFFFFFF
 //! - For sorted access (GetFirst, GetNext), we must scan all index pages
 
-use std::path::PathBuf;
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
 
 use crate::error::{BtrieveError, BtrieveResult, StatusCode};
 use crate::file_manager::cursor::{Cursor, PositionBlock};
 use crate::file_manager::locking::{LockType, SessionId};
-use crate::storage::btree::{IndexNode, LeafEntry, SearchResult};
+use crate::storage::btree::{find_first_equal, IndexNode, LeafEntry, SearchResult};
 use crate::storage::key::KeySpec;
+use crate::storage::page::Page;
 use crate::storage::record::RecordAddress;
 
 use super::dispatcher::{Engine, OperationRequest, OperationResponse};
@@ -22,14 +24,7 @@ fn get_file_path(position_block: &[u8]) -> Option<PathBuf> {
     if position_block.len() < 128 {
         return None;
     }
-    let end = position_block[64..].iter()
-        .position(|&b| b == 0)
-        .unwrap_or(64);
-    if end == 0 {
-        return None;
-    }
-    let path_str = String::from_utf8_lossy(&position_block[64..64 + end]);
-    Some(PathBuf::from(path_str.as_ref()))
+    PositionBlock::from_bytes(position_block).file_path()
 }
 
 /// Helper to read a record given its address
@@ -37,7 +32,7 @@ fn get_file_path(position_block: &[u8]) -> Option<PathBuf> {
 /// (slot=0 indicates file offset mode)
 fn read_record(
     engine: &Engine,
-    file_path: &PathBuf,
+    file_path: &Path,
     address: RecordAddress,
 ) -> BtrieveResult<Vec<u8>> {
     let file = engine.files.get(file_path)
@@ -46,31 +41,29 @@ fn read_record(
     let f = file.read();
 
     // Btrieve 5.1: address.page contains absolute file offset to record data
-    // Calculate which page contains this offset
-    let file_offset = address.page as u64;
-    let page_size = f.fcr.page_size as u64;
-    let page_number = (file_offset / page_size) as u32;
-    let offset_in_page = (file_offset % page_size) as usize;
-
-    // Read the page containing the record
-    let page = if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_number) {
-        cached
-    } else {
-        let page = f.read_page(page_number)?;
-        engine.cache.put(&file_path.to_string_lossy(), page.clone(), false);
-        page
-    };
+    let (page_number, offset_in_page) = f.record_location(address.page)?;
 
-    // Extract record data from the page at the calculated offset
-    // Record format in Btrieve 5.1: record data starts at file_offset
-    let record_length = f.fcr.record_length as usize;
+    let at = RecordAddress { page: page_number, slot: offset_in_page as u16 };
+    f.cached_record(at, || {
+        // Read the page containing the record
+        let page = if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_number) {
+            cached
+        } else {
+            let page = f.read_record_page(page_number)?;
+            engine.cache.put(&file_path.to_string_lossy(), page.clone(), false);
+            page
+        };
 
-    if offset_in_page + record_length > page.data.len() {
-        return Err(BtrieveError::Status(StatusCode::InvalidRecordAddress));
-    }
+        // Extract record data from the page at the calculated offset
+        // Record format in Btrieve 5.1: record data starts at file_offset
+        let record_length = f.fcr.record_length as usize;
 
-    let record_data = page.data[offset_in_page..offset_in_page + record_length].to_vec();
-    Ok(record_data)
+        if offset_in_page + record_length > page.data.len() {
+            return Err(BtrieveError::Status(StatusCode::InvalidRecordAddress));
+        }
+
+        Ok(page.data[offset_in_page..offset_in_page + record_length].to_vec())
+    })
 }
 
 /// Check if a page is an index page (Btrieve 5.1 hash index format)
@@ -87,10 +80,16 @@ fn is_index_page(page_data: &[u8]) -> bool {
 }
 
 /// Collect all index entries from all index pages in the file
-/// Returns entries sorted by key value for ordered access
+/// Returns entries sorted by key value for ordered access, or status 61
+/// once they pass the engine's memory budget
+///
+/// A tree of Xtrieve's that has split is read along its chain of leaves;
+/// otherwise every page is scanned for index pages, as Btrieve 5.1
+/// scatters its own through the file
 fn collect_all_index_entries(
     engine: &Engine,
-    file_path: &PathBuf,
+    file_path: &Path,
+    key_number: usize,
     key_spec: &KeySpec,
 ) -> BtrieveResult<Vec<(LeafEntry, u32, usize)>> {
     let file = engine.files.get(file_path)
@@ -98,87 +97,109 @@ fn collect_all_index_entries(
 
     let f = file.read();
     let num_pages = f.fcr.num_pages;
+    let root_page = f.fcr.index_roots.get(key_number).copied().unwrap_or(0);
     let mut all_entries: Vec<(LeafEntry, u32, usize)> = Vec::new();
+    let mut budget = engine.memory_budget();
 
-    // Scan all pages to find index pages
-    for page_num in 1..=num_pages {
-        let page = if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_num) {
-            cached
-        } else {
-            match f.read_page(page_num) {
-                Ok(p) => {
-                    engine.cache.put(&file_path.to_string_lossy(), p.clone(), false);
-                    p
+    let read_page = |page_num: u32| -> BtrieveResult<Page> {
+        if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_num) {
+            return Ok(cached);
+        }
+        let page = f.read_page(page_num)?;
+        engine.cache.put(&file_path.to_string_lossy(), page.clone(), false);
+        Ok(page)
+    };
+    let read_node = |page_num: u32| -> BtrieveResult<IndexNode> {
+        Ok(IndexNode::from_bytes(page_num, &read_page(page_num)?.data, key_spec.clone())?)
+    };
+    let mut take = |node: IndexNode| -> BtrieveResult<()> {
+        for (idx, entry) in node.leaf_entries.into_iter().enumerate() {
+            budget.charge(std::mem::size_of::<(LeafEntry, u32, usize)>() + entry.key.len())?;
+            all_entries.push((entry, node.page_number, idx));
+        }
+        Ok(())
+    };
+
+    let root = if root_page == 0 { None } else { Some(read_node(root_page)?) };
+    match root {
+        Some(mut node) if !node.is_leaf() => {
+            // Down the leftmost children, then along the leaves; a chain
+            // longer than the file is a loop
+            let mut visited = 0;
+            while !node.is_leaf() && visited <= num_pages {
+                visited += 1;
+                node = read_node(node.leftmost_child)?;
+            }
+            loop {
+                visited += 1;
+                let next = node.next_sibling;
+                take(node)?;
+                if next == 0 || visited > num_pages {
+                    break;
                 }
-                Err(_) => continue,
+                node = read_node(next)?;
             }
-        };
-
-        if !is_index_page(&page.data) {
-            continue;
         }
+        _ => {
+            // Scan all pages to find index pages
+            for page_num in 1..=num_pages {
+                let Ok(page) = read_page(page_num) else {
+                    continue;
+                };
+
+                if !is_index_page(&page.data) {
+                    continue;
+                }
 
-        // Parse index page and collect entries
-        if let Ok(node) = IndexNode::from_bytes(page_num, &page.data, key_spec.clone()) {
-            for (idx, entry) in node.leaf_entries.into_iter().enumerate() {
-                all_entries.push((entry, page_num, idx));
+                // Parse index page and collect entries
+                if let Ok(node) = IndexNode::from_bytes(page_num, &page.data, key_spec.clone()) {
+                    take(node)?;
+                }
             }
         }
     }
 
-    // Sort entries by key value
-    all_entries.sort_by(|a, b| a.0.key.cmp(&b.0.key));
+    // Sort entries in the key's order, duplicates by sequence
+    all_entries.sort_by(|a, b| a.0.order(&b.0, key_spec));
 
     Ok(all_entries)
 }
 
-/// Find index entry by exact key match using hash bucket optimization
-fn find_entry_by_key(
-    engine: &Engine,
-    file_path: &PathBuf,
-    key_spec: &KeySpec,
-    search_key: &[u8],
-) -> BtrieveResult<Option<(LeafEntry, u32, usize)>> {
-    let file = engine.files.get(file_path)
-        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
-
-    let f = file.read();
-    let num_pages = f.fcr.num_pages;
-
-    // Scan all index pages looking for exact match
-    for page_num in 1..=num_pages {
-        let page = if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_num) {
-            cached
-        } else {
-            match f.read_page(page_num) {
-                Ok(p) => {
-                    engine.cache.put(&file_path.to_string_lossy(), p.clone(), false);
-                    p
-                }
-                Err(_) => continue,
-            }
-        };
+/// Records of an open file in key order, each with its key (see
+/// `Engine::scan`)
+pub struct Scan<'a> {
+    engine: &'a Engine,
+    path: PathBuf,
+    entries: std::vec::IntoIter<(LeafEntry, u32, usize)>,
+}
 
-        if !is_index_page(&page.data) {
-            continue;
-        }
+impl Iterator for Scan<'_> {
+    type Item = BtrieveResult<(Vec<u8>, Vec<u8>)>;
 
-        if let Ok(node) = IndexNode::from_bytes(page_num, &page.data, key_spec.clone()) {
-            for (idx, entry) in node.leaf_entries.iter().enumerate() {
-                if entry.key == search_key {
-                    return Ok(Some((entry.clone(), page_num, idx)));
-                }
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        let (entry, _, _) = self.entries.next()?;
+        if self.engine.locks.is_record_locked_by_any(&self.path.to_string_lossy(), entry.record_address) {
+            return Some(Err(BtrieveError::Status(StatusCode::RecordInUse)));
         }
+        Some(read_record(self.engine, &self.path, entry.record_address).map(|record| (entry.key, record)))
     }
+}
+
+/// Start a scan of an open file in `key_number` order
+pub fn scan<'a>(engine: &'a Engine, path: &Path, key_number: usize) -> BtrieveResult<Scan<'a>> {
+    let file = engine.files.get(path)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let key_spec = file.read().fcr.keys.get(key_number).cloned()
+        .ok_or(BtrieveError::Status(StatusCode::InvalidKeyNumber))?;
 
-    Ok(None)
+    let entries = collect_all_index_entries(engine, path, key_number, &key_spec)?;
+    Ok(Scan { engine, path: path.to_path_buf(), entries: entries.into_iter() })
 }
 
 /// Search the B+ tree for a key
 fn search_btree(
     engine: &Engine,
-    file_path: &PathBuf,
+    file_path: &Path,
     key_number: usize,
     search_key: &[u8],
 ) -> BtrieveResult<SearchResult> {
@@ -215,13 +236,17 @@ fn search_btree(
         let node = IndexNode::from_bytes(current_page, &page.data, key_spec.clone())?;
 
         if node.is_leaf() {
-            // Search leaf node
-            if let Some(entry) = node.find_exact(search_key) {
-                let index = node.find_index(search_key).unwrap_or(0);
-                return Ok(SearchResult::found(current_page, index, entry.clone()));
-            } else {
-                return Ok(SearchResult::not_found(current_page));
-            }
+            // Settle on the first duplicate, which may sit in a sibling leaf
+            let read_leaf = |page_num: u32| {
+                let page = f.read_page(page_num)?;
+                Ok::<_, BtrieveError>(IndexNode::from_bytes(page_num, &page.data, key_spec.clone())?)
+            };
+            return Ok(match find_first_equal(node, search_key, read_leaf)? {
+                Some((leaf, index)) => {
+                    SearchResult::found(leaf.page_number, index, leaf.leaf_entries[index].clone())
+                }
+                None => SearchResult::not_found(current_page),
+            });
         } else {
             // Internal node - find child to descend into
             current_page = node.find_child(search_key);
@@ -277,7 +302,7 @@ pub fn get_equal(
         entry.key.clone(),
         record_data.clone(),
         result.leaf_page,
-        result.entry_index as usize,
+        entry.dup_sequence,
     );
     let position = PositionBlock::from_cursor(&cursor);
 
@@ -299,18 +324,19 @@ pub fn get_next(
 
     // Restore cursor
     let position = PositionBlock::from_bytes(&req.position_block);
-    let cursor = position.to_cursor(path.clone());
+    let cursor = position.to_cursor(path.clone())?;
 
-    if !cursor.is_positioned() {
+    // After a Delete the cursor is between keys and moves on from there
+    if !cursor.has_currency() {
         return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
     }
 
     let file = engine.files.get(&path)
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
 
+    let key_number = cursor.key_number as usize;
     let key_spec = {
         let f = file.read();
-        let key_number = cursor.key_number as usize;
         if key_number >= f.fcr.keys.len() {
             return Err(BtrieveError::Status(StatusCode::InvalidKeyNumber));
         }
@@ -318,7 +344,7 @@ pub fn get_next(
     };
 
     // Collect all index entries sorted by key
-    let entries = collect_all_index_entries(engine, &path, &key_spec)?;
+    let entries = collect_all_index_entries(engine, &path, key_number, &key_spec)?;
 
     if entries.is_empty() {
         return Err(BtrieveError::Status(StatusCode::EndOfFile));
@@ -331,14 +357,20 @@ pub fn get_next(
         .ok_or(BtrieveError::Status(StatusCode::InvalidPositioning))?;
 
     let current_idx = entries.iter().position(|(e, _, _)| {
-        e.key == *current_key && e.record_address == current_addr
+        key_spec.compare(&e.key, current_key) == Ordering::Equal && e.record_address == current_addr
     });
 
     let next_idx = match current_idx {
         Some(idx) => idx + 1,
         None => {
-            // Current key not found - find first key greater than current
-            entries.iter().position(|(e, _, _)| e.key > *current_key)
+            // Current key not found (deleted) - find first entry after it,
+            // duplicates of its key ordered by sequence
+            let deleted = LeafEntry {
+                key: current_key.clone(),
+                record_address: current_addr,
+                dup_sequence: cursor.dup_sequence,
+            };
+            entries.iter().position(|(e, _, _)| e.order(&deleted, &key_spec) == Ordering::Greater)
                 .ok_or(BtrieveError::Status(StatusCode::EndOfFile))?
         }
     };
@@ -347,7 +379,7 @@ pub fn get_next(
         return Err(BtrieveError::Status(StatusCode::EndOfFile));
     }
 
-    let (entry, leaf_page, leaf_index) = &entries[next_idx];
+    let (entry, leaf_page, _) = &entries[next_idx];
 
     // Check if record is locked
     if engine.locks.is_record_locked(&path.to_string_lossy(), entry.record_address, session) {
@@ -362,7 +394,7 @@ pub fn get_next(
         entry.key.clone(),
         record_data.clone(),
         *leaf_page,
-        *leaf_index,
+        entry.dup_sequence,
     );
     let new_position = PositionBlock::from_cursor(&new_cursor);
 
@@ -383,18 +415,19 @@ pub fn get_previous(
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
 
     let position = PositionBlock::from_bytes(&req.position_block);
-    let cursor = position.to_cursor(path.clone());
+    let cursor = position.to_cursor(path.clone())?;
 
-    if !cursor.is_positioned() {
+    // After a Delete the cursor is between keys and moves on from there
+    if !cursor.has_currency() {
         return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
     }
 
     let file = engine.files.get(&path)
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
 
+    let key_number = cursor.key_number as usize;
     let key_spec = {
         let f = file.read();
-        let key_number = cursor.key_number as usize;
         if key_number >= f.fcr.keys.len() {
             return Err(BtrieveError::Status(StatusCode::InvalidKeyNumber));
         }
@@ -402,7 +435,7 @@ pub fn get_previous(
     };
 
     // Collect all index entries sorted by key
-    let entries = collect_all_index_entries(engine, &path, &key_spec)?;
+    let entries = collect_all_index_entries(engine, &path, key_number, &key_spec)?;
 
     if entries.is_empty() {
         return Err(BtrieveError::Status(StatusCode::EndOfFile));
@@ -415,20 +448,26 @@ pub fn get_previous(
         .ok_or(BtrieveError::Status(StatusCode::InvalidPositioning))?;
 
     let current_idx = entries.iter().position(|(e, _, _)| {
-        e.key == *current_key && e.record_address == current_addr
+        key_spec.compare(&e.key, current_key) == Ordering::Equal && e.record_address == current_addr
     });
 
     let prev_idx = match current_idx {
         Some(0) => return Err(BtrieveError::Status(StatusCode::EndOfFile)),
         Some(idx) => idx - 1,
         None => {
-            // Current key not found - find last key smaller than current
-            entries.iter().rposition(|(e, _, _)| e.key < *current_key)
+            // Current key not found (deleted) - find last entry before it,
+            // duplicates of its key ordered by sequence
+            let deleted = LeafEntry {
+                key: current_key.clone(),
+                record_address: current_addr,
+                dup_sequence: cursor.dup_sequence,
+            };
+            entries.iter().rposition(|(e, _, _)| e.order(&deleted, &key_spec) == Ordering::Less)
                 .ok_or(BtrieveError::Status(StatusCode::EndOfFile))?
         }
     };
 
-    let (entry, leaf_page, leaf_index) = &entries[prev_idx];
+    let (entry, leaf_page, _) = &entries[prev_idx];
 
     // Check if record is locked
     if engine.locks.is_record_locked(&path.to_string_lossy(), entry.record_address, session) {
@@ -443,7 +482,7 @@ pub fn get_previous(
         entry.key.clone(),
         record_data.clone(),
         *leaf_page,
-        *leaf_index,
+        entry.dup_sequence,
     );
     let new_position = PositionBlock::from_cursor(&new_cursor);
 
@@ -497,8 +536,8 @@ pub fn get_greater(
 
         if node.is_leaf() {
             // Find first entry > search_key
-            for (idx, entry) in node.leaf_entries.iter().enumerate() {
-                if entry.key.as_slice() > search_key.as_slice() {
+            for entry in &node.leaf_entries {
+                if key_spec.compare(&entry.key, search_key) == Ordering::Greater {
                     // Btrieve 5.1: Check if record is locked by another session's transaction
                     if engine.locks.is_record_locked(&path.to_string_lossy(), entry.record_address, session) {
                         return Err(BtrieveError::Status(StatusCode::RecordInUse));
@@ -513,7 +552,7 @@ pub fn get_greater(
                         entry.key.clone(),
                         record_data.clone(),
                         current_page,
-                        idx,
+                        entry.dup_sequence,
                     );
                     let position = PositionBlock::from_cursor(&cursor);
 
@@ -594,7 +633,7 @@ pub fn get_less_than(
         if node.is_leaf() {
             // Find last entry < search_key
             for (idx, entry) in node.leaf_entries.iter().enumerate().rev() {
-                if entry.key.as_slice() < search_key.as_slice() {
+                if key_spec.compare(&entry.key, search_key) == Ordering::Less {
                     best_entry = Some((entry.clone(), current_page, idx));
                     break;
                 }
@@ -615,7 +654,7 @@ pub fn get_less_than(
         }
     }
 
-    if let Some((entry, leaf_page, idx)) = best_entry {
+    if let Some((entry, leaf_page, _)) = best_entry {
         // Btrieve 5.1: Check if record is locked by another session's transaction
         if engine.locks.is_record_locked(&path.to_string_lossy(), entry.record_address, session) {
             return Err(BtrieveError::Status(StatusCode::RecordInUse));
@@ -630,7 +669,7 @@ pub fn get_less_than(
             entry.key.clone(),
             record_data.clone(),
             leaf_page,
-            idx,
+            entry.dup_sequence,
         );
         let position = PositionBlock::from_cursor(&cursor);
 
@@ -680,14 +719,14 @@ pub fn get_first(
     };
 
     // Collect all index entries sorted by key
-    let entries = collect_all_index_entries(engine, &path, &key_spec)?;
+    let entries = collect_all_index_entries(engine, &path, key_number, &key_spec)?;
 
     if entries.is_empty() {
         return Err(BtrieveError::Status(StatusCode::EndOfFile));
     }
 
     // First entry (minimum key) is at index 0 after sorting
-    let (entry, leaf_page, leaf_index) = &entries[0];
+    let (entry, leaf_page, _) = &entries[0];
 
     // Check if record is locked
     if engine.locks.is_record_locked(&path.to_string_lossy(), entry.record_address, session) {
@@ -702,7 +741,7 @@ pub fn get_first(
         entry.key.clone(),
         record_data.clone(),
         *leaf_page,
-        *leaf_index,
+        entry.dup_sequence,
     );
     let position = PositionBlock::from_cursor(&cursor);
 
@@ -736,14 +775,14 @@ pub fn get_last(
     };
 
     // Collect all index entries sorted by key
-    let entries = collect_all_index_entries(engine, &path, &key_spec)?;
+    let entries = collect_all_index_entries(engine, &path, key_number, &key_spec)?;
 
     if entries.is_empty() {
         return Err(BtrieveError::Status(StatusCode::EndOfFile));
     }
 
     // Last entry (maximum key) is at the end after sorting
-    let (entry, leaf_page, leaf_index) = &entries[entries.len() - 1];
+    let (entry, leaf_page, _) = &entries[entries.len() - 1];
 
     // Check if record is locked
     if engine.locks.is_record_locked(&path.to_string_lossy(), entry.record_address, session) {
@@ -758,7 +797,7 @@ pub fn get_last(
         entry.key.clone(),
         record_data.clone(),
         *leaf_page,
-        *leaf_index,
+        entry.dup_sequence,
     );
     let position = PositionBlock::from_cursor(&cursor);
 
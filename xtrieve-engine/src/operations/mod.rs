@@ -3,11 +3,19 @@
 //! This module implements all Btrieve operation codes (0-50+).
 
 pub mod dispatcher;
+pub mod budget;
+pub mod compat;
 pub mod file_ops;
 pub mod record_ops;
 pub mod key_ops;
 pub mod step_ops;
 pub mod position_ops;
 pub mod transaction_ops;
+pub mod hooks;
+pub mod validation;
 
-pub use dispatcher::{Engine, OperationCode, OperationRequest, OperationResponse};
+pub use budget::MemoryBudget;
+pub use dispatcher::{Engine, OperationCode, OperationRequest, OperationResponse, SessionCleanup};
+pub use file_ops::{CreateLimits, FileInfo};
+pub use hooks::EngineHook;
+pub use key_ops::Scan;
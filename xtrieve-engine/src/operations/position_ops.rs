@@ -1,6 +1,6 @@
 //! Position operations: Get Position, Get Direct, Get By Percentage
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::error::{BtrieveError, BtrieveResult, StatusCode};
 use crate::file_manager::cursor::{Cursor, PositionBlock};
@@ -14,21 +14,14 @@ fn get_file_path(position_block: &[u8]) -> Option<PathBuf> {
     if position_block.len() < 128 {
         return None;
     }
-    let end = position_block[64..].iter()
-        .position(|&b| b == 0)
-        .unwrap_or(64);
-    if end == 0 {
-        return None;
-    }
-    let path_str = String::from_utf8_lossy(&position_block[64..64 + end]);
-    Some(PathBuf::from(path_str.as_ref()))
+    PositionBlock::from_bytes(position_block).file_path()
 }
 
 /// Helper to read a record given its address
-/// In Btrieve 5.1 format, address.slot contains the absolute file offset
+/// In Btrieve 5.1 format, address.page contains the absolute file offset
 fn read_record(
     engine: &Engine,
-    file_path: &PathBuf,
+    file_path: &Path,
     address: RecordAddress,
 ) -> BtrieveResult<Vec<u8>> {
     let file = engine.files.get(file_path)
@@ -36,28 +29,27 @@ fn read_record(
 
     let f = file.read();
 
-    // Btrieve 5.1: address.slot contains absolute file offset to record data
-    let file_offset = address.slot as u64;
-    let page_size = f.fcr.page_size as u64;
-    let page_number = (file_offset / page_size) as u32;
-    let offset_in_page = (file_offset % page_size) as usize;
+    // Btrieve 5.1: address.page contains absolute file offset to record data
+    let (page_number, offset_in_page) = f.record_location(address.page)?;
 
-    let page = if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_number) {
-        cached
-    } else {
-        let page = f.read_page(page_number)?;
-        engine.cache.put(&file_path.to_string_lossy(), page.clone(), false);
-        page
-    };
+    let at = RecordAddress { page: page_number, slot: offset_in_page as u16 };
+    f.cached_record(at, || {
+        let page = if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_number) {
+            cached
+        } else {
+            let page = f.read_record_page(page_number)?;
+            engine.cache.put(&file_path.to_string_lossy(), page.clone(), false);
+            page
+        };
 
-    let record_length = f.fcr.record_length as usize;
+        let record_length = f.fcr.record_length as usize;
 
-    if offset_in_page + record_length > page.data.len() {
-        return Err(BtrieveError::Status(StatusCode::InvalidRecordAddress));
-    }
+        if offset_in_page + record_length > page.data.len() {
+            return Err(BtrieveError::Status(StatusCode::InvalidRecordAddress));
+        }
 
-    let record_data = page.data[offset_in_page..offset_in_page + record_length].to_vec();
-    Ok(record_data)
+        Ok(page.data[offset_in_page..offset_in_page + record_length].to_vec())
+    })
 }
 
 /// Operation 22: Get Position - get physical address of current record
@@ -71,7 +63,7 @@ pub fn get_position(
 
     // Restore cursor
     let position_block = PositionBlock::from_bytes(&req.position_block);
-    let cursor = position_block.to_cursor(path);
+    let cursor = position_block.to_cursor(path)?;
 
     if !cursor.is_positioned() {
         return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
@@ -122,7 +114,7 @@ pub fn get_direct(
 
     {
         let f = file.read();
-        if record_addr.page >= f.fcr.num_pages {
+        if record_addr.page / f.fcr.page_size as u32 >= f.fcr.num_pages {
             return Err(BtrieveError::Status(StatusCode::InvalidRecordAddress));
         }
     }
@@ -172,7 +164,7 @@ pub fn get_by_percentage(
     }
 
     // Calculate approximate record number
-    let target_record = ((percentage as u64 * total_records as u64) / 10000) as u32;
+    let target_record = (percentage as u128 * total_records as u128 / 10000) as u64;
 
     // For now, use step operations to find the record
     // TODO: Implement more efficient positioning
@@ -203,7 +195,7 @@ pub fn get_by_percentage(
 
     // Re-read current record
     let position = PositionBlock::from_bytes(&modified_req.position_block);
-    let cursor = position.to_cursor(path.clone());
+    let cursor = position.to_cursor(path.clone())?;
 
     if let Some(addr) = cursor.record_address {
         let record_data = read_record(engine, &path, addr)?;
@@ -225,7 +217,7 @@ pub fn find_percentage(
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
 
     let position_block = PositionBlock::from_bytes(&req.position_block);
-    let cursor = position_block.to_cursor(path.clone());
+    let cursor = position_block.to_cursor(path.clone())?;
 
     if !cursor.is_positioned() {
         return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
@@ -1,55 +1,62 @@
-//! Record operations: Insert, Update, Delete
+//! Record operations: Insert, Update, Delete, and the recycle bin's
+//! Get Deleted and Undelete
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::error::{BtrieveError, BtrieveResult, StatusCode};
 use crate::file_manager::cursor::{Cursor, PositionBlock};
-use crate::file_manager::locking::{LockType, SessionId};
-use crate::storage::btree::{IndexNode, InternalEntry, LeafEntry};
-use crate::storage::page::Page;
+use crate::file_manager::locking::SessionId;
+use crate::storage::btree::{find_first_equal, IndexNode, InternalEntry, LeafEntry};
+use crate::storage::page::{Page, PageType};
 use crate::storage::record::{DataPage, RecordAddress};
+use crate::storage::recycle::{RecycleBin, Tombstone};
+use crate::storage::rollfwd::Change;
 
-use super::dispatcher::{Engine, OperationRequest, OperationResponse};
+use super::dispatcher::{Engine, OperationCode, OperationRequest, OperationResponse};
 
 /// Extract file path from position block
 fn get_file_path(position_block: &[u8]) -> Option<PathBuf> {
     if position_block.len() < 128 {
         return None;
     }
-    let end = position_block[64..]
-        .iter()
-        .position(|&b| b == 0)
-        .unwrap_or(64);
-    if end == 0 {
-        return None;
+    PositionBlock::from_bytes(position_block).file_path()
+}
+
+/// Log a change to a file that keeps a roll-forward log: now, or when the
+/// session's transaction commits
+fn log_change(engine: &Engine, session: SessionId, path: &Path, change: impl FnOnce() -> Change) -> BtrieveResult<()> {
+    let log = engine.files.get(path).and_then(|file| file.read().roll_forward_log());
+    if let Some(log) = log {
+        if let Some(change) = super::transaction_ops::defer_change(session, path, change()) {
+            log.append(&[change])?;
+        }
     }
-    let path_str = String::from_utf8_lossy(&position_block[64..64 + end]);
-    Some(PathBuf::from(path_str.as_ref()))
+    Ok(())
 }
 
-/// Convert file offset (stored in RecordAddress.slot) to actual page number and slot index
-/// Returns (page_number, slot_index) or None if not found
+/// Convert file offset (stored in RecordAddress.page) to actual page number and slot index
+/// Returns (page_number, slot_index), or status 42 if no record starts there
 fn file_offset_to_page_slot(
     engine: &Engine,
-    file_path: &PathBuf,
-    file_offset: u16,
-    page_size: u16,
+    file_path: &Path,
+    file_offset: u32,
 ) -> BtrieveResult<(u32, u16)> {
-    let page_number = (file_offset as u32 * 1) / page_size as u32;
-    let offset_in_page = (file_offset as usize) % (page_size as usize);
-
     let file = engine.files.get(file_path)
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
     let f = file.read();
+    let (page_number, offset_in_page) = f.record_location(file_offset)?;
 
     let page = if let Some(cached) = engine.cache.get(&file_path.to_string_lossy(), page_number) {
         cached
     } else {
-        let page = f.read_page(page_number)?;
+        let page = f.read_record_page(page_number)?;
         engine.cache.put(&file_path.to_string_lossy(), page.clone(), false);
         page
     };
 
+    if page.page_type() != PageType::Data {
+        return Err(BtrieveError::Status(StatusCode::InvalidRecordAddress));
+    }
     let data_page = DataPage::from_bytes(page_number, page.data)?;
 
     // Find slot with matching offset
@@ -62,22 +69,114 @@ fn file_offset_to_page_slot(
     Err(BtrieveError::Status(StatusCode::InvalidRecordAddress))
 }
 
-/// Insert a key into the B+ tree, handling splits as needed
+/// Whether key `key_number`'s index holds `key_value`, so a duplicate of
+/// a unique key is refused before anything is written
+fn key_exists(
+    engine: &Engine,
+    file_path: &Path,
+    key_number: usize,
+    key_value: &[u8],
+) -> BtrieveResult<bool> {
+    let file = engine
+        .files
+        .get(file_path)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+
+    let (root_page, key_spec) = {
+        let f = file.read();
+        (f.fcr.index_roots[key_number], f.fcr.keys[key_number].clone())
+    };
+    if root_page == 0 {
+        return Ok(false);
+    }
+
+    let read_node = |page_num: u32| {
+        let page = file.read().read_page(page_num)?;
+        Ok::<_, BtrieveError>(IndexNode::from_bytes(page_num, &page.data, key_spec.clone())?)
+    };
+    let mut node = read_node(root_page)?;
+    while !node.is_leaf() {
+        node = read_node(node.find_child(key_value))?;
+    }
+    Ok(find_first_equal(node, key_value, read_node)?.is_some())
+}
+
+/// Sequence number for a new duplicate of `key_value` in key
+/// `key_number`'s index: one past the highest its duplicates have, so it
+/// goes after every one of them
+fn next_dup_sequence(
+    engine: &Engine,
+    file_path: &Path,
+    key_number: usize,
+    key_value: &[u8],
+) -> BtrieveResult<u32> {
+    let file = engine
+        .files
+        .get(file_path)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+
+    let (root_page, key_spec) = {
+        let f = file.read();
+        (f.fcr.index_roots[key_number], f.fcr.keys[key_number].clone())
+    };
+    if root_page == 0 {
+        return Ok(0);
+    }
+
+    let read_node = |page_num: u32| {
+        let page = file.read().read_page(page_num)?;
+        Ok::<_, BtrieveError>(IndexNode::from_bytes(page_num, &page.data, key_spec.clone())?)
+    };
+    let mut node = read_node(root_page)?;
+    while !node.is_leaf() {
+        node = read_node(node.find_child(key_value))?;
+    }
+    let Some((mut leaf, mut at)) = find_first_equal(node, key_value, read_node)? else {
+        return Ok(0);
+    };
+
+    // The duplicates run on from the first along the leaves
+    let mut next = 0;
+    loop {
+        for entry in &leaf.leaf_entries[at..] {
+            if key_spec.compare(&entry.key, key_value) != std::cmp::Ordering::Equal {
+                return Ok(next);
+            }
+            next = next.max(entry.dup_sequence.saturating_add(1));
+        }
+        if leaf.next_sibling == 0 {
+            return Ok(next);
+        }
+        leaf = read_node(leaf.next_sibling)?;
+        at = 0;
+    }
+}
+
+/// Insert a key into the B+ tree, handling splits as needed. Returns the
+/// duplicate sequence the entry was given
+#[allow(clippy::too_many_arguments)]
 fn btree_insert(
     engine: &Engine,
-    file_path: &PathBuf,
+    file_path: &Path,
     key_number: usize,
     key_value: Vec<u8>,
     record_address: RecordAddress,
     allow_duplicates: bool,
     page_size: u16,
     session: SessionId,
-) -> BtrieveResult<()> {
+) -> BtrieveResult<u32> {
     let file = engine
         .files
         .get(file_path)
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
 
+    // A duplicate goes after those already there, whatever its address
+    let dup_seq = if allow_duplicates {
+        next_dup_sequence(engine, file_path, key_number, &key_value)?
+    } else {
+        0
+    };
+
     // Read root_page and key_spec with a short-lived read lock to avoid deadlock
     let (root_page, key_spec) = {
         let f = file.read();
@@ -96,15 +195,9 @@ fn btree_insert(
         }
 
         let new_page_num = f.fcr.num_pages;
+        f.claim_index_page(new_page_num, key_number);
         let mut leaf = IndexNode::new_leaf(new_page_num, key_spec.clone(), page_size);
 
-        // Get next dup sequence if duplicates allowed
-        let dup_seq = if allow_duplicates {
-            key_spec.unique_count
-        } else {
-            0
-        };
-
         leaf.insert_leaf_entry(
             LeafEntry {
                 key: key_value.clone(),
@@ -132,7 +225,7 @@ fn btree_insert(
         let path_str = file_path.to_string_lossy();
         engine.cache.put(&path_str, page, false);
 
-        return Ok(());
+        return Ok(dup_seq);
     }
 
     // Traverse tree to find insertion point (no locks held here)
@@ -140,10 +233,12 @@ fn btree_insert(
     let result = btree_insert_recursive(
         engine,
         file_path,
+        key_number,
         root_page,
         &key_spec,
         key_value.clone(),
         record_address,
+        dup_seq,
         allow_duplicates,
         page_size,
         session,
@@ -155,6 +250,7 @@ fn btree_insert(
         let mut f = file.write();
 
         let new_root_num = f.fcr.num_pages;
+        f.claim_index_page(new_root_num, key_number);
         let mut new_root = IndexNode::new_internal(new_root_num, key_spec.clone(), root_page);
         new_root.insert_internal_entry(InternalEntry {
             key: separator,
@@ -173,17 +269,20 @@ fn btree_insert(
         engine.cache.put(&file_path.to_string_lossy(), page, false);
     }
 
-    Ok(())
+    Ok(dup_seq)
 }
 
 /// Recursive B+ tree insertion, returns Some((separator, right_page)) if split occurred
+#[allow(clippy::too_many_arguments, clippy::only_used_in_recursion)]
 fn btree_insert_recursive(
     engine: &Engine,
-    file_path: &PathBuf,
+    file_path: &Path,
+    key_number: usize,
     page_num: u32,
     key_spec: &crate::storage::key::KeySpec,
     key_value: Vec<u8>,
     record_address: RecordAddress,
+    dup_seq: u32,
     allow_duplicates: bool,
     page_size: u16,
     session: SessionId,
@@ -203,16 +302,6 @@ fn btree_insert_recursive(
 
     if node.is_leaf() {
         // Insert into leaf
-        let dup_seq = if allow_duplicates {
-            // Count existing entries with same key
-            node.leaf_entries
-                .iter()
-                .filter(|e| key_spec.compare(&e.key, &key_value) == std::cmp::Ordering::Equal)
-                .count() as u32
-        } else {
-            0
-        };
-
         let entry = LeafEntry {
             key: key_value.clone(),
             record_address,
@@ -230,6 +319,7 @@ fn btree_insert_recursive(
             let mut f = file.write();
             let new_page_num = f.fcr.num_pages;
             f.fcr.num_pages += 1;
+            f.claim_index_page(new_page_num, key_number);
             f.update_fcr()?;
             drop(f);
 
@@ -251,7 +341,7 @@ fn btree_insert_recursive(
             engine.cache.put(&path_str, left_page, false);
             engine.cache.put(&path_str, right_page, false);
 
-            return Ok(Some((separator, new_page_num)));
+            Ok(Some((separator, new_page_num)))
         } else {
             // Write updated node
             let f = file.read();
@@ -262,7 +352,7 @@ fn btree_insert_recursive(
             // Update cache
             engine.cache.put(&file_path.to_string_lossy(), page, false);
 
-            return Ok(None);
+            Ok(None)
         }
     } else {
         // Internal node - find child and recurse
@@ -271,10 +361,12 @@ fn btree_insert_recursive(
         let result = btree_insert_recursive(
             engine,
             file_path,
+            key_number,
             child_page,
             key_spec,
             key_value,
             record_address,
+            dup_seq,
             allow_duplicates,
             page_size,
             session,
@@ -293,6 +385,7 @@ fn btree_insert_recursive(
                 let mut f = file.write();
                 let new_page_num = f.fcr.num_pages;
                 f.fcr.num_pages += 1;
+                f.claim_index_page(new_page_num, key_number);
                 f.update_fcr()?;
                 drop(f);
 
@@ -354,7 +447,7 @@ pub fn insert(
     }
 
     // Get file info
-    let (page_size, record_length, num_keys, first_data_page, last_data_page) = {
+    let (page_size, record_length, _num_keys, first_data_page, last_data_page) = {
         let f = file.read();
         (
             f.fcr.page_size,
@@ -374,6 +467,15 @@ pub fn insert(
     let mut record = record_data.to_vec();
     record.resize(record_length as usize, 0);
 
+    // Refuse a duplicate of a unique key before the record is written, so
+    // a refused Insert leaves nothing behind
+    let keys = file.read().fcr.keys.clone();
+    for (key_num, key_spec) in keys.iter().enumerate() {
+        if !key_spec.allows_duplicates() && key_exists(engine, &path, key_num, &key_spec.extract_key(&record))? {
+            return Err(BtrieveError::Status(StatusCode::DuplicateKey));
+        }
+    }
+
     // Find or create a data page with space
     let record_addr: RecordAddress;
 
@@ -389,8 +491,8 @@ pub fn insert(
 
         // Btrieve 5.1 compatibility: store absolute file offset in record address
         let slot_entry = &data_page.slots[slot as usize];
-        let file_offset = (new_page_num as u32 * page_size as u32) + slot_entry.offset as u32;
-        record_addr = RecordAddress::new(0, file_offset as u16);
+        let file_offset = (new_page_num * page_size as u32) + slot_entry.offset as u32;
+        record_addr = RecordAddress::new(file_offset, 0);
 
         // Write data page
         let page = Page::from_data(new_page_num, data_page.to_bytes());
@@ -417,8 +519,8 @@ pub fn insert(
         if let Some(slot) = data_page.insert_record(&record) {
             // Btrieve 5.1 compatibility: store absolute file offset
             let slot_entry = &data_page.slots[slot as usize];
-            let file_offset = (last_data_page as u32 * page_size as u32) + slot_entry.offset as u32;
-            record_addr = RecordAddress::new(0, file_offset as u16);
+            let file_offset = (last_data_page * page_size as u32) + slot_entry.offset as u32;
+            record_addr = RecordAddress::new(file_offset, 0);
 
             let f = file.read();
             let page = Page::from_data(last_data_page, data_page.to_bytes());
@@ -430,10 +532,10 @@ pub fn insert(
 
             let mut f = file.write();
             f.fcr.num_records += 1;
-            f.update_fcr()?;
+            f.defer_fcr()?;
         } else {
             // Need to allocate new page
-            let mut f = file.write();
+            let f = file.write();
             let new_page_num = f.fcr.num_pages;
 
             let mut new_data_page = DataPage::new(new_page_num, page_size);
@@ -443,8 +545,8 @@ pub fn insert(
 
             // Btrieve 5.1 compatibility: store absolute file offset
             let slot_entry = &new_data_page.slots[slot as usize];
-            let file_offset = (new_page_num as u32 * page_size as u32) + slot_entry.offset as u32;
-            record_addr = RecordAddress::new(0, file_offset as u16);
+            let file_offset = (new_page_num * page_size as u32) + slot_entry.offset as u32;
+            record_addr = RecordAddress::new(file_offset, 0);
 
             // Link pages
             new_data_page.set_prev_page(last_data_page);
@@ -480,26 +582,24 @@ pub fn insert(
         }
     }
 
-    // Insert into all indexes
-    {
-        let f = file.read();
-        let keys = f.fcr.keys.clone();
-        drop(f);
-
-        for (key_num, key_spec) in keys.iter().enumerate() {
-            let key_value = key_spec.extract_key(&record);
-            let allow_dups = key_spec.allows_duplicates();
+    // Insert into all indexes, keeping the current key's duplicate sequence
+    let mut dup_sequence = 0;
+    for (key_num, key_spec) in keys.iter().enumerate() {
+        let key_value = key_spec.extract_key(&record);
+        let allow_dups = key_spec.allows_duplicates();
 
-            btree_insert(
-                engine,
-                &path,
-                key_num,
-                key_value,
-                record_addr,
-                allow_dups,
-                page_size,
-                session,
-            )?;
+        let sequence = btree_insert(
+            engine,
+            &path,
+            key_num,
+            key_value,
+            record_addr,
+            allow_dups,
+            page_size,
+            session,
+        )?;
+        if key_num as i32 == req.key_number {
+            dup_sequence = sequence;
         }
     }
 
@@ -514,9 +614,18 @@ pub fn insert(
         )?;
     }
 
-    // Build position block with new record position
+    log_change(engine, session, &path, || Change::Insert(record.clone()))?;
+
+    // Build position block with new record position, on its key's value
+    // so Get Next and Get Previous go on from it
+    let key_value = usize::try_from(req.key_number)
+        .ok()
+        .and_then(|key_number| keys.get(key_number))
+        .map(|key_spec| key_spec.extract_key(&record))
+        .unwrap_or_default();
     let mut cursor = Cursor::new(path.clone(), req.key_number);
-    cursor.position(record_addr, Vec::new(), record);
+    cursor.position(record_addr, key_value, record);
+    cursor.dup_sequence = dup_sequence;
     let position = PositionBlock::from_cursor(&cursor);
 
     Ok(OperationResponse::success().with_position(position.data.to_vec()))
@@ -536,7 +645,7 @@ pub fn update(
 
     // Restore cursor from position block
     let position = PositionBlock::from_bytes(&req.position_block);
-    let cursor = position.to_cursor(path.clone());
+    let cursor = position.to_cursor(path.clone())?;
 
     if !cursor.is_positioned() {
         return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
@@ -574,12 +683,11 @@ pub fn update(
     let mut padded_record = new_record.to_vec();
     padded_record.resize(record_length as usize, 0);
 
-    // Convert file offset to page/slot (Btrieve 5.1: record_addr.slot contains file offset)
+    // Convert file offset to page/slot (Btrieve 5.1: record_addr.page contains file offset)
     let (actual_page, actual_slot) = file_offset_to_page_slot(
         engine,
         &path,
-        record_addr.slot,
-        page_size,
+        record_addr.page,
     )?;
 
     // Read old record
@@ -592,19 +700,32 @@ pub fn update(
         .ok_or(BtrieveError::Status(StatusCode::InvalidRecordAddress))?
         .to_vec();
 
-    // Check modifiable key constraints and update indexes
+    // Check modifiable and unique key constraints before any index changes,
+    // so a refused Update leaves every key as it was
     for (key_num, key_spec) in keys.iter().enumerate() {
         let old_key = key_spec.extract_key(&old_record);
         let new_key = key_spec.extract_key(&padded_record);
+        if old_key == new_key {
+            continue;
+        }
+        if !key_spec.is_modifiable() {
+            return Err(BtrieveError::Status(StatusCode::ModifiableKeyChanged));
+        }
+        if !key_spec.allows_duplicates() && key_exists(engine, &path, key_num, &new_key)? {
+            return Err(BtrieveError::Status(StatusCode::DuplicateKey));
+        }
+    }
 
-        if old_key != new_key {
-            if !key_spec.is_modifiable() {
-                return Err(BtrieveError::Status(StatusCode::ModifiableKeyChanged));
-            }
+    // Update indexes; a changed key goes after any duplicates of its new value
+    let mut dup_sequence = cursor.dup_sequence;
+    for (key_num, key_spec) in keys.iter().enumerate() {
+        let old_key = key_spec.extract_key(&old_record);
+        let new_key = key_spec.extract_key(&padded_record);
 
+        if old_key != new_key {
             // Remove old key from index, add new key
             btree_remove(engine, &path, key_num, &old_key, record_addr, page_size, session)?;
-            btree_insert(
+            let sequence = btree_insert(
                 engine,
                 &path,
                 key_num,
@@ -614,6 +735,9 @@ pub fn update(
                 page_size,
                 session,
             )?;
+            if key_num as i32 == cursor.key_number {
+                dup_sequence = sequence;
+            }
         }
     }
 
@@ -647,19 +771,33 @@ pub fn update(
         )?;
     }
 
-    Ok(OperationResponse::success().with_position(req.position_block.clone()))
+    log_change(engine, session, &path, || Change::Update { before: old_record, after: padded_record.clone() })?;
+
+    // The cursor follows the record: if the update changed its value for
+    // the current key, Get Next/Previous go on from the new value
+    let key_spec = usize::try_from(cursor.key_number).ok().and_then(|k| keys.get(k));
+    let Some(key_spec) = key_spec else {
+        return Ok(OperationResponse::success().with_position(req.position_block.clone()));
+    };
+    let mut moved = Cursor::new(path, cursor.key_number);
+    moved.position(record_addr, key_spec.extract_key(&padded_record), padded_record);
+    moved.dup_sequence = dup_sequence;
+    let position = PositionBlock::from_cursor(&moved);
+
+    Ok(OperationResponse::success().with_position(position.data.to_vec()))
 }
 
-/// Remove a key from the B+ tree
+/// Remove a key from the B+ tree, returning the removed entry's
+/// duplicate sequence
 fn btree_remove(
     engine: &Engine,
-    file_path: &PathBuf,
+    file_path: &Path,
     key_number: usize,
     key_value: &[u8],
     record_address: RecordAddress,
     page_size: u16,
     session: SessionId,
-) -> BtrieveResult<()> {
+) -> BtrieveResult<Option<u32>> {
     let file = engine
         .files
         .get(file_path)
@@ -671,27 +809,48 @@ fn btree_remove(
     drop(f);
 
     if root_page == 0 {
-        return Ok(()); // Empty tree
+        return Ok(None); // Empty tree
     }
 
     // Find leaf containing the key
+    let mut removed = None;
     let mut current_page = root_page;
     loop {
         let f = file.read();
         let page = f.read_page(current_page)?;
         drop(f);
 
-        let mut node = IndexNode::from_bytes(current_page, &page.data, key_spec.clone())?;
+        let node = IndexNode::from_bytes(current_page, &page.data, key_spec.clone())?;
 
         if node.is_leaf() {
-            // Remove entry
-            if node.remove_leaf_entry(key_value, record_address) {
-                let f = file.read();
-                let page = Page::from_data(current_page, node.to_bytes(page_size));
-                f.write_page_for_session(&page, session)?;
-
-                // Update cache with modified page
-                engine.cache.put(&file_path.to_string_lossy(), page, false);
+            // Duplicates of the key can start in an earlier leaf: go from
+            // the first of them along the leaves until the entry turns up
+            let read_leaf = |page_num: u32| {
+                let page = file.read().read_page(page_num)?;
+                Ok::<_, BtrieveError>(IndexNode::from_bytes(page_num, &page.data, key_spec.clone())?)
+            };
+            let Some((mut leaf, _)) = find_first_equal(node, key_value, &read_leaf)? else {
+                break;
+            };
+            loop {
+                // Remove entry
+                if let Some(entry) = leaf.remove_leaf_entry(key_value, record_address) {
+                    removed = Some(entry.dup_sequence);
+                    let f = file.read();
+                    let page = Page::from_data(leaf.page_number, leaf.to_bytes(page_size));
+                    f.write_page_for_session(&page, session)?;
+
+                    // Update cache with modified page
+                    engine.cache.put(&file_path.to_string_lossy(), page, false);
+                    break;
+                }
+                let past = leaf
+                    .last_entry()
+                    .is_none_or(|e| key_spec.compare(&e.key, key_value) == std::cmp::Ordering::Greater);
+                if past || leaf.next_sibling == 0 {
+                    break;
+                }
+                leaf = read_leaf(leaf.next_sibling)?;
             }
             break;
         } else {
@@ -702,7 +861,7 @@ fn btree_remove(
     // Note: Full B+ tree deletion with rebalancing is complex
     // This simplified version just removes from leaf without rebalancing
 
-    Ok(())
+    Ok(removed)
 }
 
 /// Operation 4: Delete the current record
@@ -719,7 +878,7 @@ pub fn delete(
 
     // Restore cursor from position block
     let position = PositionBlock::from_bytes(&req.position_block);
-    let mut cursor = position.to_cursor(path.clone());
+    let mut cursor = position.to_cursor(path.clone())?;
 
     if !cursor.is_positioned() {
         return Err(BtrieveError::Status(StatusCode::InvalidPositioning));
@@ -747,12 +906,11 @@ pub fn delete(
     let keys = f.fcr.keys.clone();
     drop(f);
 
-    // Convert file offset to page/slot (Btrieve 5.1: record_addr.slot contains file offset)
+    // Convert file offset to page/slot (Btrieve 5.1: record_addr.page contains file offset)
     let (actual_page, actual_slot) = file_offset_to_page_slot(
         engine,
         &path,
-        record_addr.slot,
-        page_size,
+        record_addr.page,
     )?;
 
     // Read the record to get key values
@@ -766,10 +924,23 @@ pub fn delete(
         .ok_or(BtrieveError::Status(StatusCode::InvalidRecordAddress))?
         .to_vec();
 
-    // Remove from all indexes
+    // Keep the record in the recycle bin: now, or when the transaction
+    // deleting it commits
+    let bin = file.read().recycle_bin();
+    if let Some(bin) = bin {
+        if let Some(record) = super::transaction_ops::defer_tombstone(session, &path, record.clone()) {
+            bin.append(&[record])?;
+        }
+    }
+
+    // Remove from all indexes, noting where among its duplicates the
+    // current key's entry was
     for (key_num, key_spec) in keys.iter().enumerate() {
         let key_value = key_spec.extract_key(&record);
-        btree_remove(engine, &path, key_num, &key_value, record_addr, page_size, session)?;
+        let removed = btree_remove(engine, &path, key_num, &key_value, record_addr, page_size, session)?;
+        if key_num as i32 == cursor.key_number {
+            cursor.dup_sequence = removed.unwrap_or(0);
+        }
     }
 
     // Mark record as deleted
@@ -786,11 +957,93 @@ pub fn delete(
     // Update FCR
     let mut f = file.write();
     f.fcr.num_records = f.fcr.num_records.saturating_sub(1);
-    f.update_fcr()?;
+    f.defer_fcr()?;
+    drop(f);
+
+    log_change(engine, session, &path, || Change::Delete(record))?;
 
-    // Invalidate cursor
+    // Leave the cursor between the keys around the deleted record
     cursor.invalidate();
     let position = PositionBlock::from_cursor(&cursor);
 
     Ok(OperationResponse::success().with_position(position.data.to_vec()))
 }
+
+/// The recycle bin of the file a position block names, and the file
+fn recycle_bin(engine: &Engine, req: &OperationRequest) -> BtrieveResult<(PathBuf, RecycleBin, bool)> {
+    let path = get_file_path(&req.position_block)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let file = engine.files.get(&path)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let f = file.read();
+    let bin = f.recycle_bin()
+        .ok_or(BtrieveError::Status(StatusCode::OperationNotAllowed))?;
+    Ok((path, bin, f.mode.read_only))
+}
+
+/// The entry `key_number` places back from the most recently deleted.
+/// The whole bin is read, so it counts against the memory budget
+fn tombstone(engine: &Engine, bin: &RecycleBin, key_number: i32) -> BtrieveResult<Tombstone> {
+    let back = usize::try_from(key_number)
+        .map_err(|_| BtrieveError::Status(StatusCode::InvalidKeyNumber))?;
+    let size = std::fs::metadata(bin.path()).map(|meta| meta.len()).unwrap_or(0);
+    engine.memory_budget().charge(usize::try_from(size).unwrap_or(usize::MAX))?;
+    let mut entries = bin.entries()?;
+    if back >= entries.len() {
+        return Err(BtrieveError::Status(StatusCode::EndOfFile));
+    }
+    Ok(entries.swap_remove(entries.len() - 1 - back))
+}
+
+/// Operation 95: Get Deleted (Xtrieve extension)
+///
+/// Returns a record from the recycle bin without restoring it: the one
+/// `key_number` places back from the most recently deleted, with the time
+/// it was deleted in the key buffer
+pub fn get_deleted(
+    engine: &Engine,
+    _session: SessionId,
+    req: &OperationRequest,
+) -> BtrieveResult<OperationResponse> {
+    let (_, bin, _) = recycle_bin(engine, req)?;
+    let entry = tombstone(engine, &bin, req.key_number)?;
+    Ok(OperationResponse::success()
+        .with_data(entry.record)
+        .with_key(entry.deleted_at.to_le_bytes().to_vec()))
+}
+
+/// Operation 96: Undelete (Xtrieve extension)
+///
+/// Inserts the record `key_number` places back from the most recently
+/// deleted again and takes it out of the recycle bin. A record whose key is
+/// now taken stays in the bin. Refused inside a transaction, which couldn't
+/// take the bin back on abort
+pub fn undelete(
+    engine: &Engine,
+    session: SessionId,
+    req: &OperationRequest,
+) -> BtrieveResult<OperationResponse> {
+    if super::transaction_ops::has_transaction(session) {
+        return Err(BtrieveError::Status(StatusCode::TransactionActive));
+    }
+    let (path, bin, read_only) = recycle_bin(engine, req)?;
+    if read_only {
+        return Err(BtrieveError::Status(StatusCode::AccessDenied));
+    }
+    let entry = tombstone(engine, &bin, req.key_number)?;
+
+    let response = insert(engine, session, &OperationRequest {
+        operation: OperationCode::Insert,
+        position_block: req.position_block.clone(),
+        data_buffer: entry.record.clone(),
+        data_length: entry.record.len() as u32,
+        ..Default::default()
+    })?;
+
+    // Hold the file while the bin is rewritten, so no delete appends to it
+    let file = engine.files.get(&path)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+    let _held = file.write();
+    bin.remove(&entry)?;
+    Ok(response.with_data(entry.record))
+}
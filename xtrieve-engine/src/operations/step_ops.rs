@@ -1,120 +1,195 @@
 //! Step operations: Physical record traversal (not using indexes)
 //!
-//! Btrieve 5.1 data page format:
-//! - 6-byte header (prev_page:2, page_num:2, usage:2)
-//! - Fixed-length records at consecutive offsets
-//! - Deleted records marked by key=0xFFFFFFFF or first 2 bytes=0x0000
+//! A Step walks the pages after the FCR in file order, taking records from:
+//! - data pages the engine writes (see [`DataPage`]), in the order of
+//!   their offsets in the page
+//! - Btrieve 5.1 data pages: a 6-byte header (prev_page:2, page_num:2,
+//!   usage:2, bit 15 of usage set) and records every physical record
+//!   length bytes, deleted ones starting with the free list's link
+//!
+//! Index, PAT and any other pages hold no records and are passed over.
+//!
+//! Records are addressed by the file offset of their data, as key
+//! operations do, so a Step can go on from the record a Get found.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::error::{BtrieveError, BtrieveResult, StatusCode};
 use crate::file_manager::cursor::{Cursor, PositionBlock};
 use crate::file_manager::locking::SessionId;
-use crate::storage::record::RecordAddress;
+use crate::file_manager::open_files::OpenFile;
+use crate::storage::fcr::FileControlRecord;
+use crate::storage::page::{Page, PageType};
+use crate::storage::record::{DataPage, RecordAddress};
 
 use super::dispatcher::{Engine, OperationRequest, OperationResponse};
 
 /// Btrieve 5.1 data page header size
-const HEADER_SIZE: usize = 6;
+const LEGACY_HEADER_SIZE: usize = 6;
+/// Bit of a Btrieve 5.1 page's usage word marking a data page
+const LEGACY_DATA_PAGE: u16 = 0x8000;
+
+/// Which way a Step goes through the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
 
-/// Check if a record slot is deleted in Btrieve 5.1 format
-fn is_deleted(record_data: &[u8]) -> bool {
+/// Check if a record slot is deleted in Btrieve 5.1 format. A deleted
+/// record starts with the free list's link: 0xFFFFFFFF at the end of the
+/// list, else the address of the next free record, high word first. Slots
+/// never used are zero
+fn is_deleted(record_data: &[u8], fcr: &FileControlRecord) -> bool {
     if record_data.len() < 4 {
         return true;
     }
-    // Deleted records have:
-    // - First 4 bytes = 0xFFFFFFFF (end of free list)
-    // - First 2 bytes = 0x0000 with next pointer in bytes 2-3 (free list link)
-    let first_four = u32::from_le_bytes([record_data[0], record_data[1], record_data[2], record_data[3]]);
-    let first_two = u16::from_le_bytes([record_data[0], record_data[1]]);
-    first_four == 0xFFFFFFFF || first_two == 0x0000
+    let high = u16::from_le_bytes([record_data[0], record_data[1]]) as u32;
+    let low = u16::from_le_bytes([record_data[2], record_data[3]]) as u32;
+    let link = (high << 16) | low;
+    if link == 0 || link == 0xFFFFFFFF {
+        return true;
+    }
+    let page_size = fcr.page_size as u32;
+    let in_page = (link % page_size) as usize;
+    (1..=fcr.num_pages).contains(&(link / page_size))
+        && in_page >= LEGACY_HEADER_SIZE
+        && (in_page - LEGACY_HEADER_SIZE).is_multiple_of(fcr.physical_record_length.max(1) as usize)
 }
 
-/// Get record at given slot from page data
-fn get_record(page_data: &[u8], record_length: u16, slot: u16) -> Option<Vec<u8>> {
-    let record_len = record_length as usize;
-    let offset = HEADER_SIZE + (slot as usize * record_len);
-    if offset + record_len <= page_data.len() {
-        let record_data = &page_data[offset..offset + record_len];
-        if !is_deleted(record_data) {
-            return Some(record_data.to_vec());
-        }
+/// Whether a page is a Btrieve 5.1 data page: its header names the page
+/// and marks it as holding records
+fn is_legacy_data_page(page_number: u32, page_data: &[u8]) -> bool {
+    if page_data.len() < LEGACY_HEADER_SIZE {
+        return false;
     }
-    None
+    let number = u16::from_le_bytes([page_data[2], page_data[3]]);
+    let usage = u16::from_le_bytes([page_data[4], page_data[5]]);
+    number == page_number as u16 && usage & LEGACY_DATA_PAGE != 0
 }
 
-/// Find first valid record in a page
-fn first_record(page_data: &[u8], record_length: u16) -> Option<(u16, Vec<u8>)> {
-    let record_len = record_length as usize;
-    let mut offset = HEADER_SIZE;
-    let mut slot: u16 = 0;
-
-    while offset + record_len <= page_data.len() {
-        let record_data = &page_data[offset..offset + record_len];
-        if !is_deleted(record_data) {
-            return Some((slot, record_data.to_vec()));
+/// The live records of a page in physical order, with the file offset of
+/// their data. Pages that hold no records give none
+fn page_records(page: &Page, fcr: &FileControlRecord) -> Vec<(u32, Vec<u8>)> {
+    let base = page.page_number * fcr.page_size as u32;
+    if page.page_number == 0 {
+        return Vec::new();
+    }
+    if is_legacy_data_page(page.page_number, &page.data) {
+        let (record_length, physical_length) = (fcr.record_length as usize, fcr.physical_record_length as usize);
+        if record_length == 0 || physical_length < record_length {
+            return Vec::new();
         }
-        offset += record_len;
-        slot += 1;
+        return page.data[LEGACY_HEADER_SIZE..]
+            .chunks_exact(physical_length)
+            .enumerate()
+            .map(|(i, slot)| (i, &slot[..record_length]))
+            .filter(|(_, record)| !is_deleted(record, fcr))
+            .map(|(i, record)| {
+                let in_page = LEGACY_HEADER_SIZE + i * physical_length;
+                (base + in_page as u32, record.to_vec())
+            })
+            .collect();
     }
-    None
+    if page.page_type() != PageType::Data {
+        return Vec::new();
+    }
+    let Ok(data_page) = DataPage::from_bytes(page.page_number, page.data.clone()) else {
+        return Vec::new();
+    };
+    let mut records: Vec<_> = (0..data_page.slot_count)
+        .filter_map(|slot| {
+            let record = data_page.get_record(slot)?;
+            Some((base + data_page.slots[slot as usize].offset as u32, record.to_vec()))
+        })
+        .collect();
+    records.sort_by_key(|(address, _)| *address);
+    records
 }
 
-/// Find last valid record in a page
-fn last_record(page_data: &[u8], record_length: u16) -> Option<(u16, Vec<u8>)> {
-    let record_len = record_length as usize;
-    let usable_space = page_data.len().saturating_sub(HEADER_SIZE);
-    let max_slots = usable_space / record_len;
-
-    for slot in (0..max_slots as u16).rev() {
-        let offset = HEADER_SIZE + (slot as usize * record_len);
-        if offset + record_len <= page_data.len() {
-            let record_data = &page_data[offset..offset + record_len];
-            if !is_deleted(record_data) {
-                return Some((slot, record_data.to_vec()));
-            }
-        }
+/// A page through the page cache, or None if the file can't give it
+fn load_page(engine: &Engine, path: &Path, f: &OpenFile, page_num: u32) -> Option<Page> {
+    if let Some(cached) = engine.cache.get(&path.to_string_lossy(), page_num) {
+        return Some(cached);
     }
-    None
+    let page = f.read_page(page_num).ok()?;
+    engine.cache.put(&path.to_string_lossy(), page.clone(), false);
+    Some(page)
 }
 
-/// Find next valid record after given slot
-fn next_record(page_data: &[u8], record_length: u16, after_slot: u16) -> Option<(u16, Vec<u8>)> {
-    let record_len = record_length as usize;
-    let usable_space = page_data.len().saturating_sub(HEADER_SIZE);
-    let max_slots = usable_space / record_len;
-    let mut slot = after_slot + 1;
+/// The first live record going `direction` from the record at file
+/// offset `from`, or from the start or end of the file if None
+fn find_record(
+    engine: &Engine,
+    path: &Path,
+    direction: Direction,
+    from: Option<u32>,
+) -> BtrieveResult<(RecordAddress, Vec<u8>)> {
+    let file = engine.files.get(path)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+
+    let f = file.read();
+    let last_page = f.fcr.num_pages;
+    let from_page = from.map(|at| at / f.fcr.page_size as u32);
 
-    while (slot as usize) < max_slots {
-        let offset = HEADER_SIZE + (slot as usize * record_len);
-        if offset + record_len <= page_data.len() {
-            let record_data = &page_data[offset..offset + record_len];
-            if !is_deleted(record_data) {
-                return Some((slot, record_data.to_vec()));
-            }
+    let pages: Vec<u32> = match direction {
+        Direction::Forward => (from_page.unwrap_or(1).max(1)..=last_page).collect(),
+        Direction::Backward => (1..=from_page.unwrap_or(last_page).min(last_page)).rev().collect(),
+    };
+    for page_num in pages {
+        let Some(page) = load_page(engine, path, &f, page_num) else {
+            continue;
+        };
+        let records = page_records(&page, &f.fcr);
+        let found = match direction {
+            Direction::Forward => records
+                .into_iter()
+                .find(|(address, _)| from.is_none_or(|at| *address > at)),
+            Direction::Backward => records
+                .into_iter()
+                .rev()
+                .find(|(address, _)| from.is_none_or(|at| *address < at)),
+        };
+        if let Some((address, record_data)) = found {
+            return Ok((RecordAddress::new(address, 0), record_data));
         }
-        slot += 1;
     }
-    None
+
+    Err(BtrieveError::Status(StatusCode::EndOfFile))
 }
 
-/// Find previous valid record before given slot
-fn prev_record(page_data: &[u8], record_length: u16, before_slot: u16) -> Option<(u16, Vec<u8>)> {
-    if before_slot == 0 {
-        return None;
-    }
-    let record_len = record_length as usize;
+/// Answer a Step with the record it found, the cursor left on it
+fn stepped_to(path: PathBuf, (record_addr, record_data): (RecordAddress, Vec<u8>)) -> OperationResponse {
+    let mut cursor = Cursor::new(path, -1);
+    cursor.position(record_addr, Vec::new(), record_data.clone());
+    cursor.physical_position = Some(record_addr);
+    let position = PositionBlock::from_cursor(&cursor);
 
-    for slot in (0..before_slot).rev() {
-        let offset = HEADER_SIZE + (slot as usize * record_len);
-        if offset + record_len <= page_data.len() {
-            let record_data = &page_data[offset..offset + record_len];
-            if !is_deleted(record_data) {
-                return Some((slot, record_data.to_vec()));
-            }
-        }
-    }
-    None
+    OperationResponse::success()
+        .with_data(record_data)
+        .with_position(position.data.to_vec())
+}
+
+/// Go on `direction` from wherever the last Get or Step left the cursor,
+/// or from the start or end of the file if it has no place
+fn step(engine: &Engine, req: &OperationRequest, direction: Direction) -> BtrieveResult<OperationResponse> {
+    let path = get_file_path(&req.position_block)
+        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
+
+    // Restore cursor
+    let position = PositionBlock::from_bytes(&req.position_block);
+    let cursor = position.to_cursor(path.clone())?;
+
+    let from = if cursor.has_currency() {
+        let current_addr = cursor.physical_position
+            .ok_or(BtrieveError::Status(StatusCode::InvalidPositioning))?;
+        Some(current_addr.page)
+    } else {
+        None
+    };
+
+    let found = find_record(engine, &path, direction, from)?;
+    Ok(stepped_to(path, found))
 }
 
 /// Extract file path from position block
@@ -122,14 +197,7 @@ fn get_file_path(position_block: &[u8]) -> Option<PathBuf> {
     if position_block.len() < 128 {
         return None;
     }
-    let end = position_block[64..].iter()
-        .position(|&b| b == 0)
-        .unwrap_or(64);
-    if end == 0 {
-        return None;
-    }
-    let path_str = String::from_utf8_lossy(&position_block[64..64 + end]);
-    Some(PathBuf::from(path_str.as_ref()))
+    PositionBlock::from_bytes(position_block).file_path()
 }
 
 /// Operation 33: Step First - get first record physically
@@ -141,48 +209,8 @@ pub fn step_first(
     let path = get_file_path(&req.position_block)
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
 
-    let file = engine.files.get(&path)
-        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
-
-    let f = file.read();
-    let record_length = f.fcr.record_length;
-    let num_pages = f.fcr.num_pages;
-    let first_data_page = f.fcr.first_data_page;
-
-    if first_data_page == 0 {
-        return Err(BtrieveError::Status(StatusCode::EndOfFile));
-    }
-
-    // Scan data pages looking for first valid record
-    for page_num in first_data_page..=num_pages {
-        let page = if let Some(cached) = engine.cache.get(&path.to_string_lossy(), page_num) {
-            cached
-        } else {
-            match f.read_page(page_num) {
-                Ok(p) => {
-                    engine.cache.put(&path.to_string_lossy(), p.clone(), false);
-                    p
-                }
-                Err(_) => continue,
-            }
-        };
-
-        if let Some((slot, record_data)) = first_record(&page.data, record_length) {
-            let record_addr = RecordAddress::new(page_num, slot);
-            drop(f);
-
-            let mut cursor = Cursor::new(path, -1);
-            cursor.position(record_addr, Vec::new(), record_data.clone());
-            cursor.physical_position = Some(record_addr);
-            let position = PositionBlock::from_cursor(&cursor);
-
-            return Ok(OperationResponse::success()
-                .with_data(record_data)
-                .with_position(position.data.to_vec()));
-        }
-    }
-
-    Err(BtrieveError::Status(StatusCode::EndOfFile))
+    let found = find_record(engine, &path, Direction::Forward, None)?;
+    Ok(stepped_to(path, found))
 }
 
 /// Operation 34: Step Last - get last record physically
@@ -194,44 +222,8 @@ pub fn step_last(
     let path = get_file_path(&req.position_block)
         .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
 
-    let file = engine.files.get(&path)
-        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
-
-    let f = file.read();
-    let record_length = f.fcr.record_length;
-    let num_pages = f.fcr.num_pages;
-    let first_data_page = f.fcr.first_data_page;
-
-    // Scan data pages from last to first looking for last valid record
-    for page_num in (first_data_page..=num_pages).rev() {
-        let page = if let Some(cached) = engine.cache.get(&path.to_string_lossy(), page_num) {
-            cached
-        } else {
-            match f.read_page(page_num) {
-                Ok(p) => {
-                    engine.cache.put(&path.to_string_lossy(), p.clone(), false);
-                    p
-                }
-                Err(_) => continue,
-            }
-        };
-
-        if let Some((slot, record_data)) = last_record(&page.data, record_length) {
-            let record_addr = RecordAddress::new(page_num, slot);
-            drop(f);
-
-            let mut cursor = Cursor::new(path, -1);
-            cursor.position(record_addr, Vec::new(), record_data.clone());
-            cursor.physical_position = Some(record_addr);
-            let position = PositionBlock::from_cursor(&cursor);
-
-            return Ok(OperationResponse::success()
-                .with_data(record_data)
-                .with_position(position.data.to_vec()));
-        }
-    }
-
-    Err(BtrieveError::Status(StatusCode::EndOfFile))
+    let found = find_record(engine, &path, Direction::Backward, None)?;
+    Ok(stepped_to(path, found))
 }
 
 /// Operation 24: Step Next - get next record physically
@@ -240,81 +232,7 @@ pub fn step_next(
     _session: SessionId,
     req: &OperationRequest,
 ) -> BtrieveResult<OperationResponse> {
-    let path = get_file_path(&req.position_block)
-        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
-
-    // Restore cursor
-    let position = PositionBlock::from_bytes(&req.position_block);
-    let cursor = position.to_cursor(path.clone());
-
-    if !cursor.is_positioned() {
-        return step_first(engine, _session, req);
-    }
-
-    let current_addr = cursor.physical_position
-        .or(cursor.record_address)
-        .ok_or(BtrieveError::Status(StatusCode::InvalidPositioning))?;
-
-    let file = engine.files.get(&path)
-        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
-
-    let f = file.read();
-    let record_length = f.fcr.record_length;
-    let num_pages = f.fcr.num_pages;
-
-    // Try next slot in current page
-    let page = if let Some(cached) = engine.cache.get(&path.to_string_lossy(), current_addr.page) {
-        cached
-    } else {
-        let page = f.read_page(current_addr.page)?;
-        engine.cache.put(&path.to_string_lossy(), page.clone(), false);
-        page
-    };
-
-    if let Some((next_slot, record_data)) = next_record(&page.data, record_length, current_addr.slot) {
-        let record_addr = RecordAddress::new(current_addr.page, next_slot);
-        drop(f);
-
-        let mut new_cursor = Cursor::new(path, -1);
-        new_cursor.position(record_addr, Vec::new(), record_data.clone());
-        new_cursor.physical_position = Some(record_addr);
-        let new_position = PositionBlock::from_cursor(&new_cursor);
-
-        return Ok(OperationResponse::success()
-            .with_data(record_data)
-            .with_position(new_position.data.to_vec()));
-    }
-
-    // Try subsequent pages
-    for page_num in (current_addr.page + 1)..=num_pages {
-        let page = if let Some(cached) = engine.cache.get(&path.to_string_lossy(), page_num) {
-            cached
-        } else {
-            match f.read_page(page_num) {
-                Ok(p) => {
-                    engine.cache.put(&path.to_string_lossy(), p.clone(), false);
-                    p
-                }
-                Err(_) => continue,
-            }
-        };
-
-        if let Some((slot, record_data)) = first_record(&page.data, record_length) {
-            let record_addr = RecordAddress::new(page_num, slot);
-            drop(f);
-
-            let mut new_cursor = Cursor::new(path, -1);
-            new_cursor.position(record_addr, Vec::new(), record_data.clone());
-            new_cursor.physical_position = Some(record_addr);
-            let new_position = PositionBlock::from_cursor(&new_cursor);
-
-            return Ok(OperationResponse::success()
-                .with_data(record_data)
-                .with_position(new_position.data.to_vec()));
-        }
-    }
-
-    Err(BtrieveError::Status(StatusCode::EndOfFile))
+    step(engine, req, Direction::Forward)
 }
 
 /// Operation 35: Step Previous - get previous record physically
@@ -323,81 +241,51 @@ pub fn step_previous(
     _session: SessionId,
     req: &OperationRequest,
 ) -> BtrieveResult<OperationResponse> {
-    let path = get_file_path(&req.position_block)
-        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
-
-    // Restore cursor
-    let position = PositionBlock::from_bytes(&req.position_block);
-    let cursor = position.to_cursor(path.clone());
-
-    if !cursor.is_positioned() {
-        return step_last(engine, _session, req);
-    }
-
-    let current_addr = cursor.physical_position
-        .or(cursor.record_address)
-        .ok_or(BtrieveError::Status(StatusCode::InvalidPositioning))?;
-
-    let file = engine.files.get(&path)
-        .ok_or(BtrieveError::Status(StatusCode::FileNotOpen))?;
-
-    let f = file.read();
-    let record_length = f.fcr.record_length;
-    let first_data_page = f.fcr.first_data_page;
-
-    // Try previous slot in current page
-    let page = if let Some(cached) = engine.cache.get(&path.to_string_lossy(), current_addr.page) {
-        cached
-    } else {
-        let page = f.read_page(current_addr.page)?;
-        engine.cache.put(&path.to_string_lossy(), page.clone(), false);
-        page
-    };
-
-    if let Some((prev_slot, record_data)) = prev_record(&page.data, record_length, current_addr.slot) {
-        let record_addr = RecordAddress::new(current_addr.page, prev_slot);
-        drop(f);
-
-        let mut new_cursor = Cursor::new(path, -1);
-        new_cursor.position(record_addr, Vec::new(), record_data.clone());
-        new_cursor.physical_position = Some(record_addr);
-        let new_position = PositionBlock::from_cursor(&new_cursor);
-
-        return Ok(OperationResponse::success()
-            .with_data(record_data)
-            .with_position(new_position.data.to_vec()));
-    }
-
-    // Try previous pages
-    if current_addr.page > first_data_page {
-        for page_num in (first_data_page..current_addr.page).rev() {
-            let page = if let Some(cached) = engine.cache.get(&path.to_string_lossy(), page_num) {
-                cached
-            } else {
-                match f.read_page(page_num) {
-                    Ok(p) => {
-                        engine.cache.put(&path.to_string_lossy(), p.clone(), false);
-                        p
-                    }
-                    Err(_) => continue,
-                }
-            };
-
-            if let Some((slot, record_data)) = last_record(&page.data, record_length) {
-                let record_addr = RecordAddress::new(page_num, slot);
-                drop(f);
-
-                let mut new_cursor = Cursor::new(path, -1);
-                new_cursor.position(record_addr, Vec::new(), record_data.clone());
-                new_cursor.physical_position = Some(record_addr);
-                let new_position = PositionBlock::from_cursor(&new_cursor);
+    step(engine, req, Direction::Backward)
+}
 
-                return Ok(OperationResponse::success()
-                    .with_data(record_data)
-                    .with_position(new_position.data.to_vec()));
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_records() {
+        // A Btrieve 5.1 file of 8-byte records with a duplicate key
+        // pointer after each
+        let mut fcr = FileControlRecord::new(8, 64, Vec::new());
+        fcr.physical_record_length = 12;
+        fcr.num_pages = 5;
+        // A data page of it, the second record deleted at the end of the
+        // free list and the fourth linking to the second
+        let mut legacy = vec![0u8; 64];
+        legacy[2..4].copy_from_slice(&3u16.to_le_bytes());
+        legacy[4..6].copy_from_slice(&(LEGACY_DATA_PAGE | 2).to_le_bytes());
+        legacy[6..14].copy_from_slice(b"RECORD01");
+        legacy[18..22].copy_from_slice(&[0xFF; 4]);
+        legacy[30..38].copy_from_slice(b"RECORD03");
+        legacy[42..46].copy_from_slice(&[0, 0, 3 * 64 + 18, 0]);
+        let records = page_records(&Page::from_data(3, legacy.clone()), &fcr);
+        assert_eq!(records, vec![(3 * 64 + 6, b"RECORD01".to_vec()), (3 * 64 + 30, b"RECORD03".to_vec())]);
+
+        // The same bytes as another page, or without the data bit, aren't a data page
+        assert!(page_records(&Page::from_data(4, legacy.clone()), &fcr).is_empty());
+        legacy[5] = 0;
+        assert!(page_records(&Page::from_data(3, legacy), &fcr).is_empty());
+
+        // A data page of the engine's, in the order of the records' offsets
+        let fcr = FileControlRecord::new(8, 512, Vec::new());
+        let mut data_page = DataPage::new(2, 512);
+        for record in [b"AAAAAAAA", b"BBBBBBBB", b"CCCCCCCC"] {
+            data_page.insert_record(record).unwrap();
         }
+        data_page.delete_record(1);
+        let records = page_records(&Page::from_data(2, data_page.to_bytes()), &fcr);
+        let offsets: Vec<u32> = data_page.slots.iter().map(|slot| 2 * 512 + slot.offset as u32).collect();
+        assert_eq!(records, vec![(offsets[0], b"AAAAAAAA".to_vec()), (offsets[2], b"CCCCCCCC".to_vec())]);
+
+        // Index pages hold no records
+        let mut index = Page::new(5, 512);
+        index.data[0] = PageType::Index as u8;
+        assert!(page_records(&index, &fcr).is_empty());
     }
-
-    Err(BtrieveError::Status(StatusCode::EndOfFile))
 }
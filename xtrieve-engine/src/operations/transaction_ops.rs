@@ -1,13 +1,14 @@
 //! Transaction operations: Begin, End, Abort
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use parking_lot::RwLock;
 
 use crate::error::{BtrieveError, BtrieveResult, StatusCode};
 use crate::file_manager::locking::SessionId;
+use crate::storage::rollfwd::Change;
 
 use super::dispatcher::{Engine, OperationRequest, OperationResponse};
 
@@ -21,6 +22,10 @@ pub struct Transaction {
     pub session: SessionId,
     pub files: Vec<PathBuf>,
     pub mode: TransactionMode,
+    /// Records deleted from recycling files, binned on commit
+    pub tombstones: Vec<(PathBuf, Vec<u8>)>,
+    /// Changes to logging files, logged on commit
+    pub changes: Vec<(PathBuf, Change)>,
 }
 
 /// Transaction mode (from lock bias)
@@ -42,15 +47,15 @@ impl TransactionMode {
     }
 }
 
-/// Global transaction table
-/// In a full implementation, this would be part of the Engine
+// Global transaction table
+// In a full implementation, this would be part of the Engine
 lazy_static::lazy_static! {
     static ref TRANSACTIONS: RwLock<HashMap<SessionId, Transaction>> = RwLock::new(HashMap::new());
 }
 
 /// Operation 19: Begin Transaction
 pub fn begin_transaction(
-    engine: &Engine,
+    _engine: &Engine,
     session: SessionId,
     req: &OperationRequest,
 ) -> BtrieveResult<OperationResponse> {
@@ -70,6 +75,8 @@ pub fn begin_transaction(
         session,
         files: Vec::new(),
         mode,
+        tombstones: Vec::new(),
+        changes: Vec::new(),
     };
 
     // Register transaction
@@ -95,10 +102,11 @@ pub fn end_transaction(
     };
 
     // Commit transaction on all files (applies WAL to main file)
+    let window = engine.files.commit_window();
     for file_path in &transaction.files {
         if let Some(file) = engine.files.get(file_path) {
             let f = file.read();
-            f.commit_transaction(session)?;
+            f.commit_transaction(session, window)?;
         }
     }
 
@@ -107,6 +115,33 @@ pub fn end_transaction(
         engine.cache.invalidate_file(&file_path.to_string_lossy());
     }
 
+    // The deletes are in for good: bin their records. A bin that can't be
+    // written doesn't undo the commit
+    for file_path in &transaction.files {
+        let records: Vec<Vec<u8>> = transaction.tombstones.iter()
+            .filter(|(path, _)| path == file_path)
+            .map(|(_, record)| record.clone())
+            .collect();
+        let bin = engine.files.get(file_path).and_then(|file| file.read().recycle_bin());
+        if let (false, Some(bin)) = (records.is_empty(), bin) {
+            if let Err(e) = bin.append(&records) {
+                tracing::warn!("Could not bin {} deleted records of {}: {}", records.len(), file_path.display(), e);
+            }
+        }
+
+        // Likewise the changes, in the order they were made
+        let changes: Vec<Change> = transaction.changes.iter()
+            .filter(|(path, _)| path == file_path)
+            .map(|(_, change)| change.clone())
+            .collect();
+        let log = engine.files.get(file_path).and_then(|file| file.read().roll_forward_log());
+        if let (false, Some(log)) = (changes.is_empty(), log) {
+            if let Err(e) = log.append(&changes) {
+                tracing::warn!("Could not log {} changes to {}: {}", changes.len(), file_path.display(), e);
+            }
+        }
+    }
+
     // Release all locks held by session
     engine.locks.release_session(session);
 
@@ -159,12 +194,45 @@ pub fn add_file_to_transaction(engine: &Engine, session: SessionId, file_path: P
     }
 }
 
+/// Helper: Hold a record deleted from a recycling file until the session's
+/// transaction commits, so an abort doesn't leave it in the bin. Hands the
+/// record back if there is no transaction
+pub fn defer_tombstone(session: SessionId, file_path: &Path, record: Vec<u8>) -> Option<Vec<u8>> {
+    let mut transactions = TRANSACTIONS.write();
+    match transactions.get_mut(&session) {
+        Some(transaction) => {
+            transaction.tombstones.push((file_path.to_path_buf(), record));
+            None
+        }
+        None => Some(record),
+    }
+}
+
+/// Helper: Hold a change to a logging file until the session's transaction
+/// commits, so only committed changes reach the roll-forward log. Hands the
+/// change back if there is no transaction
+pub fn defer_change(session: SessionId, file_path: &Path, change: Change) -> Option<Change> {
+    let mut transactions = TRANSACTIONS.write();
+    match transactions.get_mut(&session) {
+        Some(transaction) => {
+            transaction.changes.push((file_path.to_path_buf(), change));
+            None
+        }
+        None => Some(change),
+    }
+}
+
 /// Helper: Check if session has active transaction
 pub fn has_transaction(session: SessionId) -> bool {
     let transactions = TRANSACTIONS.read();
     transactions.contains_key(&session)
 }
 
+/// Helper: Number of sessions with an active transaction
+pub fn active_transaction_count() -> usize {
+    TRANSACTIONS.read().len()
+}
+
 /// Helper: Get transaction mode for session
 pub fn get_transaction_mode(session: SessionId) -> Option<TransactionMode> {
     let transactions = TRANSACTIONS.read();
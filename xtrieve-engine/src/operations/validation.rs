@@ -0,0 +1,171 @@
+//! Record validation rules
+//!
+//! Rules declared per file, checked on every Insert and Update before the
+//! record is written, so legacy clients that can't be changed still can't
+//! store out-of-range values or records with empty keys:
+//! - Field ranges: an integer field must lie within a minimum and maximum
+//! - Required keys: a key must not be all zero bytes
+//!
+//! A record breaking a rule is refused with the file's rejection status.
+//! `RecordValidator` is an `EngineHook`; the daemon builds it from its
+//! rules file, resolving field names through the data dictionary.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::StatusCode;
+use crate::file_manager::cursor::PositionBlock;
+use crate::file_manager::locking::SessionId;
+use crate::file_manager::open_files::OpenFileTable;
+
+use super::dispatcher::{OperationCode, OperationRequest};
+use super::hooks::EngineHook;
+
+/// Status a record breaking a rule is refused with, unless a file names
+/// another
+pub const DEFAULT_REJECTION: StatusCode = StatusCode::OperationNotAllowed;
+
+/// An integer field and the values it may hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldRule {
+    /// Name for messages
+    pub name: String,
+    /// 0-based offset in the record
+    pub offset: u16,
+    /// 1, 2, 4 or 8 bytes, little-endian
+    pub length: u16,
+    /// Two's complement rather than unsigned
+    pub signed: bool,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl FieldRule {
+    /// The field's value in a record, if the record holds the field
+    fn value(&self, record: &[u8]) -> Option<i64> {
+        let start = self.offset as usize;
+        let bytes = record.get(start..start + self.length as usize)?;
+        let mut raw = [0u8; 8];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        let unsigned = u64::from_le_bytes(raw);
+        Some(match (self.signed, self.length) {
+            (true, 1) => unsigned as u8 as i8 as i64,
+            (true, 2) => unsigned as u16 as i16 as i64,
+            (true, 4) => unsigned as u32 as i32 as i64,
+            (false, 8) => unsigned.min(i64::MAX as u64) as i64,
+            _ => unsigned as i64,
+        })
+    }
+
+    /// Why a record breaks this rule, if it does. A record too short to
+    /// hold the field breaks it
+    pub fn check(&self, record: &[u8]) -> Option<String> {
+        let Some(value) = self.value(record) else {
+            return Some(format!("{} is missing", self.name));
+        };
+        match (self.min, self.max) {
+            (Some(min), _) if value < min => Some(format!("{} is {}, below {}", self.name, value, min)),
+            (_, Some(max)) if value > max => Some(format!("{} is {}, above {}", self.name, value, max)),
+            _ => None,
+        }
+    }
+}
+
+/// The rules of one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRules {
+    pub fields: Vec<FieldRule>,
+    /// Keys, by number, that must not be all zero bytes
+    pub required_keys: Vec<usize>,
+    pub rejection: StatusCode,
+}
+
+impl Default for FileRules {
+    fn default() -> Self {
+        FileRules { fields: Vec::new(), required_keys: Vec::new(), rejection: DEFAULT_REJECTION }
+    }
+}
+
+/// Checks inserted and updated records against their file's rules
+pub struct RecordValidator {
+    files: Arc<OpenFileTable>,
+    /// Rules by file name, upper case, without its directory
+    rules: HashMap<String, FileRules>,
+}
+
+impl RecordValidator {
+    /// Validator reading key definitions from an engine's open files
+    pub fn new(files: Arc<OpenFileTable>) -> Self {
+        RecordValidator { files, rules: HashMap::new() }
+    }
+
+    /// Set the rules of the files named `file_name`, in any directory and
+    /// matched ignoring case as DOS did
+    pub fn add(&mut self, file_name: &str, rules: FileRules) {
+        self.rules.insert(file_name.to_ascii_uppercase(), rules);
+    }
+
+    /// Number of files with rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Check if no file has rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Why a record for the file at `path` breaks its rules, if it does
+    pub fn check(&self, path: &Path, record: &[u8]) -> Option<(StatusCode, String)> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_uppercase();
+        let rules = self.rules.get(&name)?;
+        let broken = rules.fields.iter().find_map(|field| field.check(record)).or_else(|| {
+            let file = self.files.get(path)?;
+            let f = file.read();
+            rules.required_keys.iter().find_map(|&number| {
+                let key = f.fcr.keys.get(number)?.extract_key(record);
+                key.iter().all(|&b| b == 0).then(|| format!("key {} is empty", number))
+            })
+        })?;
+        Some((rules.rejection, broken))
+    }
+}
+
+impl EngineHook for RecordValidator {
+    fn before_operation(&self, session: SessionId, request: &OperationRequest) -> Result<(), StatusCode> {
+        if !matches!(request.operation, OperationCode::Insert | OperationCode::Update) {
+            return Ok(());
+        }
+        let Some(path) = PositionBlock::from_bytes(&request.position_block).file_path() else {
+            return Ok(());
+        };
+        match self.check(&path, &request.data_buffer) {
+            Some((status, reason)) => {
+                tracing::debug!("Session {} record for {} refused: {}", session, path.display(), reason);
+                Err(status)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_rule() {
+        let age = FieldRule { name: "AGE".to_string(), offset: 2, length: 2, signed: true, min: Some(0), max: Some(130) };
+        let record = |age: i16| [&[0u8, 0][..], &age.to_le_bytes()].concat();
+
+        assert_eq!(age.check(&record(42)), None);
+        assert_eq!(age.check(&record(-1)).unwrap(), "AGE is -1, below 0");
+        assert_eq!(age.check(&record(200)).unwrap(), "AGE is 200, above 130");
+        assert_eq!(age.check(&[0u8; 3]).unwrap(), "AGE is missing");
+
+        let count = FieldRule { signed: false, min: Some(1), max: None, ..age };
+        assert_eq!(count.check(&record(-1)), None);
+        assert!(count.check(&record(0)).is_some());
+    }
+}
@@ -5,14 +5,68 @@
 //! Request format:
 //!   [op:2][pos_block:128][data_len:4][data:N][key_len:2][key:N][key_num:2][path_len:2][path:N][lock:2]
 //!
+//! A request with bit 15 of op set (`CLIENT_ID_FLAG`) is followed by
+//! [client_id:8], the session the client asks the call to run on. Requests
+//! without it, like those from the DOS requester, keep the plain layout.
+//!
 //! Response format:
 //!   [status:2][pos_block:128][data_len:4][data:N][key_len:2][key:N]
+//!
+//! A request with bit 13 of op set (`REQUEST_ID_FLAG`) is followed, after
+//! any client id, by [request_id:8], an id the client gives the call so
+//! that a Cancel (89) from another connection can stop it.
+//!
+//! A request with bit 14 of op set (`ERROR_DETAIL_FLAG`) asks for the
+//! error behind the status. Its response has bit 15 of status set
+//! (`DETAIL_FOLLOWS`) and ends with
+//! [message_len:2][message:N][context_len:2][context:N], both empty when
+//! there is nothing to add. A server from before the flag refuses the
+//! request with status 1 and no bit 15, and the client asks again without
+//! it.
+//!
+//! A request with bit 12 of op set (`HELLO_FLAG`) asks for the server's
+//! capabilities. Its response has bit 14 of status set
+//! (`CAPABILITIES_FOLLOW`) and ends, after any error detail, with
+//! [info_len:2][info:N] laid out as `ServerInfo`. Clients send it with
+//! their first request, so they learn the server's version and limits
+//! without another round trip; a server from before the flag refuses the
+//! request with status 1, and the client asks again without it.
 
 use std::io::{self, Read, Write};
 
+use crate::error::ErrorDetail;
+
 pub const POSITION_BLOCK_SIZE: usize = 128;
 pub const DEFAULT_PORT: u16 = 7419;
 
+/// Wire protocol revision reported by the ServerInfo operation
+pub const PROTOCOL_VERSION: u16 = 6;
+
+/// First protocol revision numbering operations as Btrieve does: Unlock
+/// is 27 and Find Percentage 45. Before it 27 was Find Percentage and
+/// Unlock wasn't served
+pub const BTRIEVE_OPCODES_VERSION: u16 = 6;
+
+/// Set in the operation code of a request that ends with a client id
+pub const CLIENT_ID_FLAG: u16 = 0x8000;
+
+/// Set in the operation code of a request whose response should carry
+/// the error detail frame
+pub const ERROR_DETAIL_FLAG: u16 = 0x4000;
+
+/// Set in the operation code of a request that ends with a request id
+pub const REQUEST_ID_FLAG: u16 = 0x2000;
+
+/// Set in the operation code of a request whose response should carry
+/// the capabilities frame
+pub const HELLO_FLAG: u16 = 0x1000;
+
+/// Set in the status of a response that ends with the error detail frame
+pub const DETAIL_FOLLOWS: u16 = 0x8000;
+
+/// Set in the status of a response that ends with the capabilities frame
+pub const CAPABILITIES_FOLLOW: u16 = 0x4000;
+
 /// Request from client to server
 #[derive(Debug, Clone)]
 pub struct Request {
@@ -23,6 +77,15 @@ pub struct Request {
     pub key_number: i16,
     pub file_path: String,
     pub lock_bias: u16,
+    /// Session named by the client, scoped to its connection; 0 to let
+    /// the server pick one
+    pub client_id: u64,
+    /// Ask for the error detail frame in the response
+    pub error_detail: bool,
+    /// Id a Cancel can stop the call by, 0 for none
+    pub request_id: u64,
+    /// Ask for the capabilities frame in the response
+    pub hello: bool,
 }
 
 impl Default for Request {
@@ -35,6 +98,10 @@ impl Default for Request {
             key_number: 0,
             file_path: String::new(),
             lock_bias: 0,
+            client_id: 0,
+            error_detail: false,
+            request_id: 0,
+            hello: false,
         }
     }
 }
@@ -43,8 +110,21 @@ impl Request {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::new();
 
-        // Operation code (2 bytes)
-        buf.extend_from_slice(&self.operation_code.to_le_bytes());
+        // Operation code (2 bytes), flagged when a client id follows
+        let mut operation_code = self.operation_code;
+        if self.client_id != 0 {
+            operation_code |= CLIENT_ID_FLAG;
+        }
+        if self.error_detail {
+            operation_code |= ERROR_DETAIL_FLAG;
+        }
+        if self.request_id != 0 {
+            operation_code |= REQUEST_ID_FLAG;
+        }
+        if self.hello {
+            operation_code |= HELLO_FLAG;
+        }
+        buf.extend_from_slice(&operation_code.to_le_bytes());
 
         // Position block (128 bytes, padded)
         let mut pos_block = [0u8; POSITION_BLOCK_SIZE];
@@ -71,6 +151,16 @@ impl Request {
         // Lock bias (2 bytes)
         buf.extend_from_slice(&self.lock_bias.to_le_bytes());
 
+        // Client id (8 bytes, only when flagged)
+        if self.client_id != 0 {
+            buf.extend_from_slice(&self.client_id.to_le_bytes());
+        }
+
+        // Request id (8 bytes, only when flagged)
+        if self.request_id != 0 {
+            buf.extend_from_slice(&self.request_id.to_le_bytes());
+        }
+
         buf
     }
 
@@ -80,7 +170,8 @@ impl Request {
 
         // Operation code
         reader.read_exact(&mut buf2)?;
-        let operation_code = u16::from_le_bytes(buf2);
+        let raw_operation = u16::from_le_bytes(buf2);
+        let operation_code = raw_operation & !(CLIENT_ID_FLAG | ERROR_DETAIL_FLAG | REQUEST_ID_FLAG | HELLO_FLAG);
 
         // Position block
         let mut position_block = vec![0u8; POSITION_BLOCK_SIZE];
@@ -119,6 +210,23 @@ impl Request {
         reader.read_exact(&mut buf2)?;
         let lock_bias = u16::from_le_bytes(buf2);
 
+        // Client id
+        let mut buf8 = [0u8; 8];
+        let client_id = if raw_operation & CLIENT_ID_FLAG != 0 {
+            reader.read_exact(&mut buf8)?;
+            u64::from_le_bytes(buf8)
+        } else {
+            0
+        };
+
+        // Request id
+        let request_id = if raw_operation & REQUEST_ID_FLAG != 0 {
+            reader.read_exact(&mut buf8)?;
+            u64::from_le_bytes(buf8)
+        } else {
+            0
+        };
+
         Ok(Request {
             operation_code,
             position_block,
@@ -127,6 +235,10 @@ impl Request {
             key_number,
             file_path,
             lock_bias,
+            client_id,
+            error_detail: raw_operation & ERROR_DETAIL_FLAG != 0,
+            request_id,
+            hello: raw_operation & HELLO_FLAG != 0,
         })
     }
 }
@@ -138,6 +250,11 @@ pub struct Response {
     pub position_block: Vec<u8>,
     pub data_buffer: Vec<u8>,
     pub key_buffer: Vec<u8>,
+    /// The error detail frame, for a request that asked for it; empty
+    /// when there was nothing to add
+    pub detail: Option<ErrorDetail>,
+    /// The capabilities frame, for a request that asked for it
+    pub capabilities: Option<ServerInfo>,
 }
 
 impl Default for Response {
@@ -147,6 +264,8 @@ impl Default for Response {
             position_block: vec![0u8; POSITION_BLOCK_SIZE],
             data_buffer: Vec::new(),
             key_buffer: Vec::new(),
+            detail: None,
+            capabilities: None,
         }
     }
 }
@@ -155,8 +274,16 @@ impl Response {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::new();
 
-        // Status code (2 bytes)
-        buf.extend_from_slice(&self.status_code.to_le_bytes());
+        // Status code (2 bytes), flagged when the detail or capabilities
+        // frame follows
+        let mut status_code = self.status_code;
+        if self.detail.is_some() {
+            status_code |= DETAIL_FOLLOWS;
+        }
+        if self.capabilities.is_some() {
+            status_code |= CAPABILITIES_FOLLOW;
+        }
+        buf.extend_from_slice(&status_code.to_le_bytes());
 
         // Position block (128 bytes, padded)
         let mut pos_block = [0u8; POSITION_BLOCK_SIZE];
@@ -172,6 +299,22 @@ impl Response {
         buf.extend_from_slice(&(self.key_buffer.len() as u16).to_le_bytes());
         buf.extend_from_slice(&self.key_buffer);
 
+        // Error detail (2 byte length + message, 2 byte length + context)
+        if let Some(detail) = &self.detail {
+            for text in [&detail.message, &detail.context] {
+                let bytes = &text.as_bytes()[..text.len().min(u16::MAX as usize)];
+                buf.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+                buf.extend_from_slice(bytes);
+            }
+        }
+
+        // Capabilities (2 byte length + server info)
+        if let Some(info) = &self.capabilities {
+            let bytes = info.to_bytes();
+            buf.extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&bytes);
+        }
+
         buf
     }
 
@@ -181,7 +324,8 @@ impl Response {
 
         // Status code
         reader.read_exact(&mut buf2)?;
-        let status_code = u16::from_le_bytes(buf2);
+        let raw_status = u16::from_le_bytes(buf2);
+        let status_code = raw_status & !(DETAIL_FOLLOWS | CAPABILITIES_FOLLOW);
 
         // Position block
         let mut position_block = vec![0u8; POSITION_BLOCK_SIZE];
@@ -203,11 +347,36 @@ impl Response {
             reader.read_exact(&mut key_buffer)?;
         }
 
+        // Error detail
+        let detail = if raw_status & DETAIL_FOLLOWS != 0 {
+            let mut text = || -> io::Result<String> {
+                reader.read_exact(&mut buf2)?;
+                let mut bytes = vec![0u8; u16::from_le_bytes(buf2) as usize];
+                reader.read_exact(&mut bytes)?;
+                Ok(String::from_utf8_lossy(&bytes).to_string())
+            };
+            Some(ErrorDetail { message: text()?, context: text()? })
+        } else {
+            None
+        };
+
+        // Capabilities
+        let capabilities = if raw_status & CAPABILITIES_FOLLOW != 0 {
+            reader.read_exact(&mut buf2)?;
+            let mut bytes = vec![0u8; u16::from_le_bytes(buf2) as usize];
+            reader.read_exact(&mut bytes)?;
+            Some(ServerInfo::from_bytes(&bytes)?)
+        } else {
+            None
+        };
+
         Ok(Response {
             status_code,
             position_block,
             data_buffer,
             key_buffer,
+            detail,
+            capabilities,
         })
     }
 
@@ -215,3 +384,204 @@ impl Response {
         writer.write_all(&self.to_bytes())
     }
 }
+
+/// Capabilities frame returned in the data buffer of ServerInfo (98),
+/// and after a response to a request with `HELLO_FLAG`
+///
+/// Layout:
+///   [protocol:2][max_keys:2][max_page:2][max_record:2][pos_block:2][ops:32][ver_len:1][version:N]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub protocol_version: u16,
+    pub engine_version: String,
+    pub max_keys: u16,
+    pub max_page_size: u16,
+    pub max_record_length: u16,
+    pub position_block_size: u16,
+    /// Bitmap of supported operation codes (bit n set = op n supported)
+    pub operations: [u8; 32],
+}
+
+impl ServerInfo {
+    const FIXED_SIZE: usize = 10 + 32 + 1;
+
+    /// Check whether the server implements an operation code
+    pub fn supports(&self, operation_code: u16) -> bool {
+        let code = operation_code as usize;
+        code < 256 && self.operations[code / 8] & (1 << (code % 8)) != 0
+    }
+
+    /// Mark an operation code as supported
+    pub fn set_supported(&mut self, operation_code: u16) {
+        let code = operation_code as usize;
+        if code < 256 {
+            self.operations[code / 8] |= 1 << (code % 8);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::FIXED_SIZE + self.engine_version.len());
+        buf.extend_from_slice(&self.protocol_version.to_le_bytes());
+        buf.extend_from_slice(&self.max_keys.to_le_bytes());
+        buf.extend_from_slice(&self.max_page_size.to_le_bytes());
+        buf.extend_from_slice(&self.max_record_length.to_le_bytes());
+        buf.extend_from_slice(&self.position_block_size.to_le_bytes());
+        buf.extend_from_slice(&self.operations);
+
+        let version = self.engine_version.as_bytes();
+        let version_len = version.len().min(255);
+        buf.push(version_len as u8);
+        buf.extend_from_slice(&version[..version_len]);
+        buf
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        if data.len() < Self::FIXED_SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Server info too short"));
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+
+        let mut operations = [0u8; 32];
+        operations.copy_from_slice(&data[10..42]);
+
+        let version_len = data[42] as usize;
+        let version = data.get(43..43 + version_len).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "Server info version truncated")
+        })?;
+
+        Ok(ServerInfo {
+            protocol_version: u16_at(0),
+            max_keys: u16_at(2),
+            max_page_size: u16_at(4),
+            max_record_length: u16_at(6),
+            position_block_size: u16_at(8),
+            operations,
+            engine_version: String::from_utf8_lossy(version).to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_info_roundtrip() {
+        let mut info = ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            engine_version: "0.1.0".to_string(),
+            max_keys: 24,
+            max_page_size: 4096,
+            max_record_length: 4076,
+            position_block_size: POSITION_BLOCK_SIZE as u16,
+            operations: [0u8; 32],
+        };
+        info.set_supported(0);
+        info.set_supported(99);
+
+        let restored = ServerInfo::from_bytes(&info.to_bytes()).unwrap();
+        assert_eq!(restored, info);
+        assert!(restored.supports(0));
+        assert!(restored.supports(99));
+        assert!(!restored.supports(98));
+        assert!(!restored.supports(1000));
+    }
+
+    #[test]
+    fn test_request_client_id() {
+        let plain = Request { operation_code: 5, key_buffer: b"A100".to_vec(), ..Default::default() };
+        let bytes = plain.to_bytes();
+        assert_eq!(&bytes[..2], &5u16.to_le_bytes());
+        assert_eq!(Request::from_reader(&mut &bytes[..]).unwrap().client_id, 0);
+
+        let tagged = Request { client_id: 100, ..plain.clone() };
+        let bytes = tagged.to_bytes();
+        assert_eq!(&bytes[..2], &(5 | CLIENT_ID_FLAG).to_le_bytes());
+        assert_eq!(bytes.len(), plain.to_bytes().len() + 8);
+
+        // Two requests back to back stay framed
+        let mut stream = bytes.clone();
+        stream.extend_from_slice(&plain.to_bytes());
+        let mut reader = &stream[..];
+        let first = Request::from_reader(&mut reader).unwrap();
+        assert_eq!((first.operation_code, first.client_id), (5, 100));
+        assert_eq!(first.key_buffer, b"A100");
+        let second = Request::from_reader(&mut reader).unwrap();
+        assert_eq!((second.operation_code, second.client_id), (5, 0));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_request_id() {
+        let request = Request { operation_code: 97, client_id: 100, request_id: 7, ..Default::default() };
+        let bytes = request.to_bytes();
+        assert_eq!(&bytes[..2], &(97 | CLIENT_ID_FLAG | REQUEST_ID_FLAG).to_le_bytes());
+        assert_eq!(&bytes[bytes.len() - 16..], &[100u64.to_le_bytes(), 7u64.to_le_bytes()].concat());
+        let read = Request::from_reader(&mut &bytes[..]).unwrap();
+        assert_eq!((read.operation_code, read.client_id, read.request_id), (97, 100, 7));
+
+        let untagged = Request { client_id: 0, ..request };
+        let read = Request::from_reader(&mut &untagged.to_bytes()[..]).unwrap();
+        assert_eq!((read.client_id, read.request_id), (0, 7));
+    }
+
+    #[test]
+    fn test_error_detail_frame() {
+        let request = Request { operation_code: 2, client_id: 100, error_detail: true, ..Default::default() };
+        let bytes = request.to_bytes();
+        assert_eq!(&bytes[..2], &(2 | CLIENT_ID_FLAG | ERROR_DETAIL_FLAG).to_le_bytes());
+        let read = Request::from_reader(&mut &bytes[..]).unwrap();
+        assert_eq!((read.operation_code, read.client_id, read.error_detail), (2, 100, true));
+
+        // Without the frame the response is laid out as it always was
+        let plain = Response { status_code: 2, ..Default::default() };
+        let detail = ErrorDetail { message: "I/O error: disk full".to_string(), context: "Insert on CUST.DAT".to_string() };
+        let detailed = Response { detail: Some(detail.clone()), ..plain.clone() };
+        let bytes = detailed.to_bytes();
+        assert_eq!(&bytes[..2], &(2 | DETAIL_FOLLOWS).to_le_bytes());
+        assert_eq!(bytes.len(), plain.to_bytes().len() + 4 + 20 + 18);
+
+        let mut stream = bytes;
+        stream.extend_from_slice(&plain.to_bytes());
+        let mut reader = &stream[..];
+        let first = Response::from_reader(&mut reader).unwrap();
+        assert_eq!((first.status_code, first.detail), (2, Some(detail)));
+        let second = Response::from_reader(&mut reader).unwrap();
+        assert_eq!((second.status_code, second.detail), (2, None));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_capabilities_frame() {
+        let request = Request { operation_code: 0, error_detail: true, hello: true, ..Default::default() };
+        let bytes = request.to_bytes();
+        assert_eq!(&bytes[..2], &(ERROR_DETAIL_FLAG | HELLO_FLAG).to_le_bytes());
+        let read = Request::from_reader(&mut &bytes[..]).unwrap();
+        assert_eq!((read.operation_code, read.error_detail, read.hello), (0, true, true));
+
+        // The frame comes after the error detail, and only when asked for
+        let info = ServerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            engine_version: "0.1.0".to_string(),
+            max_keys: 24,
+            max_page_size: 4096,
+            max_record_length: 4076,
+            position_block_size: POSITION_BLOCK_SIZE as u16,
+            operations: [0u8; 32],
+        };
+        let plain = Response { status_code: 0, detail: Some(ErrorDetail::default()), ..Default::default() };
+        let greeted = Response { capabilities: Some(info.clone()), ..plain.clone() };
+        let bytes = greeted.to_bytes();
+        assert_eq!(&bytes[..2], &(DETAIL_FOLLOWS | CAPABILITIES_FOLLOW).to_le_bytes());
+        assert_eq!(bytes.len(), plain.to_bytes().len() + 2 + info.to_bytes().len());
+
+        let mut stream = bytes;
+        stream.extend_from_slice(&plain.to_bytes());
+        let mut reader = &stream[..];
+        let first = Response::from_reader(&mut reader).unwrap();
+        assert_eq!((first.status_code, first.capabilities), (0, Some(info)));
+        let second = Response::from_reader(&mut reader).unwrap();
+        assert_eq!((second.status_code, second.capabilities), (0, None));
+        assert!(reader.is_empty());
+    }
+}
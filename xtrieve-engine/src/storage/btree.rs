@@ -11,19 +11,29 @@
 //!   - bytes 6-7: entry count (u16 LE)
 //!   - bytes 8-11: prev sibling page (u32 LE, 0xFFFFFFFF = none)
 //!   - bytes 12-15: next sibling page (u32 LE, 0xFFFFFFFF = none)
-//! - Entries (16 bytes each):
-//!   - bytes 0-3: key value (4 bytes for our test file)
-//!   - bytes 4-5: unused
+//! - Entries (12 bytes each with a 4-byte key):
+//!   - bytes 0-3: key value
+//!   - bytes 4-5: record offset high (u16 LE)
 //!   - bytes 6-7: record offset low (u16 LE)
-//!   - bytes 8-9: unused
-//!   - bytes 10-11: duplicate record offset (u16 LE)
-//!   - bytes 12-15: link pointer
+//!   - bytes 8-11: duplicate/link pointer
+//!
+//! Xtrieve keeps a duplicate's sequence number in the link pointer, so
+//! duplicates of a key stay in the order they were added; 0xFFFFFFFF is
+//! sequence 0.
+//!
+//! A longer key is stored whole, the rest of the entry moving up past it;
+//! a shorter one still takes 4 bytes.
+//!
+//! Xtrieve's own trees add internal nodes once a leaf splits, in the same
+//! header with page type 03, the leftmost child in place of the previous
+//! sibling and no next sibling. Their entries are the separator key, the
+//! child page holding keys from it up (u32 LE) and 4 bytes unused.
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::cmp::Ordering;
-use std::io::{self, Cursor};
+use std::io;
 
 use super::key::KeySpec;
+use super::page::PageType;
 use super::record::RecordAddress;
 
 /// B+ tree node types
@@ -55,6 +65,17 @@ pub struct LeafEntry {
     pub dup_sequence: u32,
 }
 
+impl LeafEntry {
+    /// Order of two entries in an index: by key, duplicates by sequence
+    /// and then by record address
+    pub fn order(&self, other: &LeafEntry, key_spec: &KeySpec) -> Ordering {
+        key_spec
+            .compare(&self.key, &other.key)
+            .then(self.dup_sequence.cmp(&other.dup_sequence))
+            .then(self.record_address.cmp(&other.record_address))
+    }
+}
+
 /// B+ tree index node
 #[derive(Debug, Clone)]
 pub struct IndexNode {
@@ -82,9 +103,12 @@ impl IndexNode {
     /// Header size for Btrieve 5.1 index nodes
     pub const HEADER_SIZE: usize = 16;
 
-    /// Entry size in Btrieve 5.1 index pages (12 bytes per entry)
+    /// Entry size in Btrieve 5.1 index pages with a 4-byte key
     pub const ENTRY_SIZE: usize = 12;
 
+    /// Page type of an internal node; leaves keep Btrieve 5.1's 00
+    pub const INTERNAL_PAGE_TYPE: u8 = PageType::Index as u8;
+
     /// Parse an index node from page data (Btrieve 5.1 format)
     pub fn from_bytes(
         page_number: u32,
@@ -109,36 +133,35 @@ impl IndexNode {
         let prev_sibling = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
         let next_sibling = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
 
+        if data[0] == Self::INTERNAL_PAGE_TYPE {
+            return Ok(Self::internal_from_bytes(page_number, data, key_spec, entry_count, prev_sibling));
+        }
+
         // For Btrieve 5.1, assume leaf node (combined index+data pages)
         let node_type = NodeType::Leaf;
 
         let key_length = key_spec.length as usize;
+        let key_width = Self::key_width(&key_spec);
+        let entry_size = key_width + 8;
         let mut leaf_entries = Vec::with_capacity(entry_count as usize);
 
-        // Parse Btrieve 5.1 index entries (12 bytes each, starting at offset 16)
-        // Entry format: key(4) + offset_high(2) + offset_low(2) + dup_ptr(4) = 12 bytes
+        // Parse Btrieve 5.1 index entries (starting at offset 16)
+        // Entry format: key(4 or more) + offset_high(2) + offset_low(2) + dup_ptr(4)
         for i in 0..entry_count as usize {
-            let entry_offset = Self::HEADER_SIZE + (i * Self::ENTRY_SIZE);
-            if entry_offset + Self::ENTRY_SIZE > data.len() {
+            let entry_offset = Self::HEADER_SIZE + (i * entry_size);
+            if entry_offset + entry_size > data.len() {
                 break;
             }
 
-            // Extract key (first 4 bytes for u32 key, or key_length bytes)
-            let key_end = entry_offset + key_length.min(4);
-            let key = data[entry_offset..key_end].to_vec();
+            let key = data[entry_offset..entry_offset + key_length].to_vec();
 
-            // Extract record file offset (4 bytes total):
-            // - bytes 4-5: high word of offset
-            // - bytes 6-7: low word of offset
+            // Extract record file offset (4 bytes total), after the key:
+            // - high word of offset
+            // - low word of offset
             // Full offset = (high << 16) | low
-            let offset_high = u16::from_le_bytes([
-                data[entry_offset + 4],
-                data[entry_offset + 5],
-            ]) as u32;
-            let offset_low = u16::from_le_bytes([
-                data[entry_offset + 6],
-                data[entry_offset + 7],
-            ]) as u32;
+            let at = entry_offset + key_width;
+            let offset_high = u16::from_le_bytes([data[at], data[at + 1]]) as u32;
+            let offset_low = u16::from_le_bytes([data[at + 2], data[at + 3]]) as u32;
             let file_offset = (offset_high << 16) | offset_low;
 
             // Store file offset in RecordAddress.page, with slot=0 to indicate file offset mode
@@ -147,10 +170,13 @@ impl IndexNode {
                 slot: 0,
             };
 
+            let link = u32::from_le_bytes([data[at + 4], data[at + 5], data[at + 6], data[at + 7]]);
+            let dup_sequence = if link == 0xFFFFFFFF { 0 } else { link };
+
             leaf_entries.push(LeafEntry {
                 key,
                 record_address,
-                dup_sequence: 0,
+                dup_sequence,
             });
         }
 
@@ -167,6 +193,41 @@ impl IndexNode {
         })
     }
 
+    /// Parse the entries of an internal node, its header already read
+    fn internal_from_bytes(
+        page_number: u32,
+        data: &[u8],
+        key_spec: KeySpec,
+        entry_count: u16,
+        leftmost_child: u32,
+    ) -> Self {
+        let key_length = key_spec.length as usize;
+        let key_width = Self::key_width(&key_spec);
+        let internal_entries = (0..entry_count as usize)
+            .map(|i| Self::HEADER_SIZE + i * (key_width + 8))
+            .take_while(|&at| at + key_width + 8 <= data.len())
+            .map(|at| {
+                let child = at + key_width;
+                InternalEntry {
+                    key: data[at..at + key_length].to_vec(),
+                    child_page: u32::from_le_bytes([data[child], data[child + 1], data[child + 2], data[child + 3]]),
+                }
+            })
+            .collect();
+
+        IndexNode {
+            page_number,
+            node_type: NodeType::Internal,
+            key_spec,
+            entry_count,
+            leftmost_child,
+            prev_sibling: 0,
+            next_sibling: 0,
+            internal_entries,
+            leaf_entries: Vec::new(),
+        }
+    }
+
     /// Check if this is a leaf node
     pub fn is_leaf(&self) -> bool {
         self.node_type == NodeType::Leaf
@@ -178,21 +239,19 @@ impl IndexNode {
             return self.leftmost_child;
         }
 
+        // The child of the last separator at or below the key
+        let mut child = self.leftmost_child;
         for entry in &self.internal_entries {
             match self.key_spec.compare(key, &entry.key) {
-                Ordering::Less => return self.leftmost_child,
-                Ordering::Equal | Ordering::Greater => continue,
+                Ordering::Less => break,
+                Ordering::Equal | Ordering::Greater => child = entry.child_page,
             }
         }
-
-        // Key is greater than all entries, return rightmost child
-        self.internal_entries
-            .last()
-            .map(|e| e.child_page)
-            .unwrap_or(self.leftmost_child)
+        child
     }
 
-    /// Search for exact key match in leaf node
+    /// Search for exact key match in leaf node, the first of any
+    /// duplicates it holds
     pub fn find_exact(&self, key: &[u8]) -> Option<&LeafEntry> {
         self.leaf_entries
             .iter()
@@ -286,9 +345,14 @@ impl IndexNode {
         }
     }
 
+    /// Bytes an entry gives a key of this spec: the whole key, at least 4
+    pub fn key_width(key_spec: &KeySpec) -> usize {
+        (key_spec.length as usize).max(4)
+    }
+
     /// Calculate the size of an entry in bytes
     pub fn entry_size(&self) -> usize {
-        Self::ENTRY_SIZE
+        Self::key_width(&self.key_spec) + 8
     }
 
     /// Calculate how many entries can fit in a page
@@ -306,13 +370,11 @@ impl IndexNode {
         current >= self.max_entries(page_size)
     }
 
-    /// Insert a leaf entry in sorted order
+    /// Insert a leaf entry in sorted order, duplicates by sequence (see
+    /// `LeafEntry::order`)
     pub fn insert_leaf_entry(&mut self, entry: LeafEntry, allow_duplicates: bool) -> bool {
         let pos = self.leaf_entries.iter()
-            .position(|e| {
-                let cmp = self.key_spec.compare(&entry.key, &e.key);
-                cmp == Ordering::Less || (cmp == Ordering::Equal && entry.dup_sequence < e.dup_sequence)
-            })
+            .position(|e| entry.order(e, &self.key_spec) == Ordering::Less)
             .unwrap_or(self.leaf_entries.len());
 
         if !allow_duplicates {
@@ -391,19 +453,36 @@ impl IndexNode {
         data[4..6].copy_from_slice(&0u16.to_le_bytes()); // Capacity
         data[6..8].copy_from_slice(&self.entry_count.to_le_bytes());
 
+        if !self.is_leaf() {
+            data[0] = Self::INTERNAL_PAGE_TYPE;
+            data[8..12].copy_from_slice(&self.leftmost_child.to_le_bytes());
+            data[12..16].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+            let key_width = Self::key_width(&self.key_spec);
+            for (i, entry) in self.internal_entries.iter().enumerate() {
+                let at = Self::HEADER_SIZE + i * self.entry_size();
+                let key_len = entry.key.len().min(key_width);
+                data[at..at + key_len].copy_from_slice(&entry.key[..key_len]);
+                let child = at + key_width;
+                data[child..child + 4].copy_from_slice(&entry.child_page.to_le_bytes());
+                data[child + 4..child + 8].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+            }
+            return data;
+        }
+
         let prev = if self.prev_sibling == 0 { 0xFFFFFFFFu32 } else { self.prev_sibling };
         let next = if self.next_sibling == 0 { 0xFFFFFFFFu32 } else { self.next_sibling };
         data[8..12].copy_from_slice(&prev.to_le_bytes());
         data[12..16].copy_from_slice(&next.to_le_bytes());
 
-        // Entries (12 bytes each)
+        // Entries (12 bytes each with a 4-byte key)
+        let key_width = Self::key_width(&self.key_spec);
         let mut offset = Self::HEADER_SIZE;
 
         for entry in &self.leaf_entries {
-            // Write key (4 bytes)
-            let key_len = entry.key.len().min(4);
+            // Write key (4 bytes or the key's length)
+            let key_len = entry.key.len().min(key_width);
             data[offset..offset + key_len].copy_from_slice(&entry.key[..key_len]);
-            offset += 4;
+            offset += key_width;
 
             // File offset stored in RecordAddress.page (4 bytes as high:2 + low:2)
             let file_offset = entry.record_address.page;
@@ -416,25 +495,94 @@ impl IndexNode {
             data[offset..offset + 2].copy_from_slice(&offset_low.to_le_bytes());
             offset += 2;
 
-            // Duplicate/link pointer (4 bytes)
-            data[offset..offset + 4].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+            // Duplicate/link pointer (4 bytes): the duplicate's sequence
+            let link = if entry.dup_sequence == 0 { 0xFFFFFFFFu32 } else { entry.dup_sequence };
+            data[offset..offset + 4].copy_from_slice(&link.to_le_bytes());
             offset += 4;
         }
 
         data
     }
 
-    /// Remove a leaf entry by key and record address
-    pub fn remove_leaf_entry(&mut self, key: &[u8], record_address: RecordAddress) -> bool {
-        if let Some(pos) = self.leaf_entries.iter().position(|e| {
+    /// Remove a leaf entry by key and record address, returning it
+    pub fn remove_leaf_entry(&mut self, key: &[u8], record_address: RecordAddress) -> Option<LeafEntry> {
+        let pos = self.leaf_entries.iter().position(|e| {
             self.key_spec.compare(&e.key, key) == Ordering::Equal
                 && e.record_address == record_address
-        }) {
-            self.leaf_entries.remove(pos);
-            self.entry_count = self.leaf_entries.len() as u16;
-            return true;
+        })?;
+        let entry = self.leaf_entries.remove(pos);
+        self.entry_count = self.leaf_entries.len() as u16;
+        Some(entry)
+    }
+}
+
+/// Build the leaf level of an index from unsorted entries in one pass
+///
+/// Entries are sorted by key, duplicates by sequence and then by record
+/// address so they keep a stable order, and packed one short of the split threshold so the
+/// next insert into a leaf does not split it. `allocate` hands out the
+/// page number of each new leaf; leaves are linked as siblings.
+pub fn bulk_load(
+    key_spec: &KeySpec,
+    mut entries: Vec<LeafEntry>,
+    page_size: u16,
+    mut allocate: impl FnMut() -> u32,
+) -> Vec<IndexNode> {
+    entries.sort_by(|a, b| a.order(b, key_spec));
+
+    let per_leaf = IndexNode::new_leaf(0, key_spec.clone(), page_size)
+        .max_entries(page_size)
+        .saturating_sub(1)
+        .max(1);
+    let mut leaves: Vec<IndexNode> = Vec::new();
+    let mut entries = entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        let mut leaf = IndexNode::new_leaf(allocate(), key_spec.clone(), page_size);
+        leaf.leaf_entries = entries.by_ref().take(per_leaf).collect();
+        leaf.entry_count = leaf.leaf_entries.len() as u16;
+        if let Some(previous) = leaves.last_mut() {
+            previous.next_sibling = leaf.page_number;
+            leaf.prev_sibling = previous.page_number;
         }
-        false
+        leaves.push(leaf);
+    }
+    leaves
+}
+
+/// Find the earliest entry equal to `key`, starting from the leaf a
+/// search descended to
+///
+/// Duplicates of a key can run across several leaves, and a search lands
+/// in whichever leaf its separators pick. This walks back through the
+/// previous siblings while they end in the key, and forward while the
+/// leaf holds nothing at or past it, so the result is the first duplicate
+/// in chain order. `read_leaf` loads a sibling by page number. Returns the
+/// leaf holding the entry and its index there.
+pub fn find_first_equal<E>(
+    mut leaf: IndexNode,
+    key: &[u8],
+    mut read_leaf: impl FnMut(u32) -> Result<IndexNode, E>,
+) -> Result<Option<(IndexNode, usize)>, E> {
+    let spec = leaf.key_spec.clone();
+    let at_or_past = |e: &LeafEntry| spec.compare(&e.key, key) != Ordering::Less;
+
+    loop {
+        let first = leaf.leaf_entries.iter().position(at_or_past);
+        if first == Some(0) && leaf.prev_sibling != 0 {
+            let prev = read_leaf(leaf.prev_sibling)?;
+            if prev.last_entry().is_some_and(|e| spec.compare(&e.key, key) == Ordering::Equal) {
+                leaf = prev;
+                continue;
+            }
+        }
+        if first.is_none() && leaf.next_sibling != 0 {
+            leaf = read_leaf(leaf.next_sibling)?;
+            continue;
+        }
+
+        return Ok(first
+            .filter(|&i| spec.compare(&leaf.leaf_entries[i].key, key) == Ordering::Equal)
+            .map(|i| (leaf, i)));
     }
 }
 
@@ -559,4 +707,91 @@ mod tests {
         assert_eq!(node.leaf_entries[0].record_address.page, 0x0806);
         assert_eq!(node.leaf_entries[1].record_address.page, 0x0001084E); // (1 << 16) | 0x084E
     }
+
+    #[test]
+    fn test_bulk_load() {
+        let entries = (0..200u32)
+            .rev()
+            .map(|i| LeafEntry {
+                key: (i / 2).to_le_bytes().to_vec(),
+                record_address: RecordAddress::new(1000 + i, 0),
+                dup_sequence: 0,
+            })
+            .collect();
+        let mut next_page = 5;
+        let leaves = bulk_load(&test_key_spec(), entries, 512, || {
+            next_page += 1;
+            next_page
+        });
+
+        // (512 - 16) / 12 = 41 entries fit, 40 are loaded per leaf
+        assert_eq!(leaves.len(), 5);
+        assert_eq!(leaves[0].leaf_entries.len(), 40);
+        assert_eq!((leaves[0].prev_sibling, leaves[0].next_sibling), (0, 7));
+        assert_eq!((leaves[4].page_number, leaves[4].prev_sibling, leaves[4].next_sibling), (10, 9, 0));
+
+        let all: Vec<_> = leaves.iter().flat_map(|l| &l.leaf_entries).collect();
+        let spec = test_key_spec();
+        assert!(all.windows(2).all(|w| spec.compare(&w[0].key, &w[1].key) != Ordering::Greater));
+        assert_eq!(all[0].record_address.page, 1000);
+        assert_eq!(all[1].record_address.page, 1001);
+    }
+
+    #[test]
+    fn test_find_first_equal_across_leaves() {
+        // Keys 1 1 | 1 2 | 2 2 | 3, the duplicates of 1 and 2 straddling leaves
+        let spec = test_key_spec();
+        let keys = [1u32, 1, 1, 2, 2, 2, 3];
+        let mut leaves: Vec<IndexNode> = keys
+            .chunks(2)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut leaf = IndexNode::new_leaf(i as u32 + 1, spec.clone(), 512);
+                for (j, key) in chunk.iter().enumerate() {
+                    let entry = LeafEntry {
+                        key: key.to_le_bytes().to_vec(),
+                        record_address: RecordAddress::new(100 * (i as u32 + 1) + j as u32, 0),
+                        dup_sequence: 0,
+                    };
+                    assert!(leaf.insert_leaf_entry(entry, true));
+                }
+                leaf
+            })
+            .collect();
+        for i in 0..leaves.len() {
+            leaves[i].prev_sibling = i as u32;
+            leaves[i].next_sibling = if i + 1 < leaves.len() { i as u32 + 2 } else { 0 };
+        }
+        let read = |page: u32| Ok::<_, io::Error>(leaves[page as usize - 1].clone());
+        let first = |from: u32, key: u32| {
+            find_first_equal(read(from).unwrap(), &key.to_le_bytes(), read)
+                .unwrap()
+                .map(|(leaf, i)| leaf.leaf_entries[i].record_address.page)
+        };
+
+        // Wherever the search lands among the duplicates, it backs up to the first
+        assert_eq!(first(2, 1), Some(100));
+        assert_eq!(first(3, 2), Some(201));
+        assert_eq!(first(2, 2), Some(201));
+        // Landing short of the key walks forward to it
+        assert_eq!(first(1, 3), Some(400));
+        assert_eq!(first(1, 4), None);
+        assert_eq!(first(4, 0), None);
+    }
+
+    #[test]
+    fn test_duplicates_keep_insertion_order() {
+        let spec = test_key_spec();
+        let mut leaf = IndexNode::new_leaf(1, spec.clone(), 512);
+        for (dup_sequence, page) in [(0, 10), (1, 11), (2, 12)] {
+            let entry = LeafEntry {
+                key: 7u32.to_le_bytes().to_vec(),
+                record_address: RecordAddress::new(page, 0),
+                dup_sequence,
+            };
+            assert!(leaf.insert_leaf_entry(entry, true));
+        }
+        assert_eq!(leaf.find_exact(&7u32.to_le_bytes()).unwrap().record_address.page, 10);
+        assert_eq!(leaf.find_index(&7u32.to_le_bytes()), Some(0));
+    }
 }
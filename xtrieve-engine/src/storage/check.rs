@@ -0,0 +1,630 @@
+//! Offline integrity check of a Btrieve file
+//!
+//! Reads every page of a file without opening it through the engine and
+//! reports what is inconsistent:
+//! - FCR: page size, page count, record length and key definitions
+//! - Data pages: the page chain from the FCR, slot directories and free lists
+//! - Index pages: entry counts and sibling links
+//! - Cross-references: every index entry must point at a live record whose
+//!   key matches, and every key must index every record
+//!
+//! The checks follow the page layouts this engine writes.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::btree::IndexNode;
+use super::compress::Compression;
+use super::crypt::Encryption;
+use super::fcr::FileControlRecord;
+use super::files::Image;
+use super::page::{PageType, PAGE_SIZES};
+use super::record::{DataPage, SlotEntry};
+
+/// Part of the file a problem was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Area {
+    Fcr,
+    Data,
+    Index,
+    CrossReference,
+}
+
+impl fmt::Display for Area {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Area::Fcr => "FCR",
+            Area::Data => "data",
+            Area::Index => "index",
+            Area::CrossReference => "cross-reference",
+        })
+    }
+}
+
+/// One inconsistency
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub area: Area,
+    /// Page the problem was found on, if it is about one page
+    pub page: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.page {
+            Some(page) => write!(f, "[{}] page {}: {}", self.area, page, self.message),
+            None => write!(f, "[{}] {}", self.area, self.message),
+        }
+    }
+}
+
+/// What the check found
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub page_size: u16,
+    /// Whole pages in the file, FCR included
+    pub pages: u32,
+    pub data_pages: u32,
+    pub index_pages: u32,
+    /// Index pages with the key their entries belong to, when that could
+    /// be told from the records they point at
+    pub index_page_keys: Vec<(u32, Option<usize>)>,
+    /// Pages that are all zeros
+    pub unused_pages: Vec<u32>,
+    /// Records counted by the FCR
+    pub fcr_records: u64,
+    /// Live records found on the data pages
+    pub records: u64,
+    /// Index entries attributed to each key
+    pub key_entries: Vec<u64>,
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    /// True when no problem was found
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Problems found in one area
+    pub fn problems_in(&self, area: Area) -> impl Iterator<Item = &Problem> {
+        self.problems.iter().filter(move |p| p.area == area)
+    }
+
+    fn problem(&mut self, area: Area, page: Option<u32>, message: impl Into<String>) {
+        self.problems.push(Problem { area, page, message: message.into() });
+    }
+}
+
+/// Check the file at `path`, opened read-only, with any index files
+pub fn check_file(path: &Path) -> io::Result<CheckReport> {
+    check(Image::open(path, false)?)
+}
+
+/// Check a file image
+pub fn check<F: Read + Seek>(file: F) -> io::Result<CheckReport> {
+    let mut report = CheckReport::default();
+    let Some((mut pages, fcr_page, fcr, length)) = read_fcr(file, &mut report)? else {
+        return Ok(report);
+    };
+    // Sealed pages only open with a key the check doesn't have, and
+    // compressed ones aren't in fixed slots
+    if fcr.encryption != Encryption::None {
+        report.problem(Area::Data, None, "pages are encrypted and can only be read through the engine");
+        return Ok(report);
+    }
+    if fcr.compression != Compression::None {
+        report.problem(Area::Data, None, "pages are compressed and can only be read through the engine");
+        return Ok(report);
+    }
+    if length % report.page_size as u64 != 0 {
+        report.problem(
+            Area::Fcr,
+            None,
+            format!("file length {} is not a multiple of the page size", length),
+        );
+    }
+    check_fcr(&fcr, &mut report);
+
+    // Taken as written rather than through the FCR parser's guess at real
+    // Btrieve 5.1 layouts; 0 means no record was ever inserted
+    let first_data_page = match u32::from_le_bytes([fcr_page[0x24], fcr_page[0x25], fcr_page[0x26], fcr_page[0x27]]) {
+        0 => None,
+        page => Some(page),
+    };
+    let records = check_data_pages(&mut pages, &fcr, first_data_page, &mut report)?;
+    check_indexes(&mut pages, &fcr, &records, &mut report)?;
+    Ok(report)
+}
+
+/// Check only the FCR of the file at `path`, as the daemon's startup
+/// self-check does. The FCR is stored plain, so sealed and compressed
+/// files are checked too
+pub fn check_fcr_file(path: &Path) -> io::Result<CheckReport> {
+    let mut report = CheckReport::default();
+    let Some((_, _, fcr, length)) = read_fcr(Image::open(path, false)?, &mut report)? else {
+        return Ok(report);
+    };
+    if fcr.compression != Compression::None {
+        // Frames aren't page sized, so the length says nothing of the count
+        report.pages = fcr.num_pages;
+    } else if length % fcr.page_size as u64 != 0 {
+        report.problem(Area::Fcr, None, format!("file length {} is not a multiple of the page size", length));
+    }
+    check_fcr(&fcr, &mut report);
+    Ok(report)
+}
+
+/// Read and parse the FCR, with the file's length. `None`, with the
+/// problem reported, when there is no FCR to parse
+#[allow(clippy::type_complexity)]
+fn read_fcr<F: Read + Seek>(
+    mut file: F,
+    report: &mut CheckReport,
+) -> io::Result<Option<(PageReader<F>, Vec<u8>, FileControlRecord, u64)>> {
+    let length = file.seek(SeekFrom::End(0))?;
+
+    let mut header = [0u8; 0x30];
+    file.seek(SeekFrom::Start(0))?;
+    if length < header.len() as u64 || file.read_exact(&mut header).is_err() {
+        report.problem(Area::Fcr, Some(0), "file is too short to hold an FCR");
+        return Ok(None);
+    }
+    let page_size = u16::from_le_bytes([header[0x08], header[0x09]]);
+    if !PAGE_SIZES.contains(&page_size) {
+        report.problem(Area::Fcr, Some(0), format!("invalid page size {}", page_size));
+        return Ok(None);
+    }
+    report.page_size = page_size;
+    report.pages = (length / page_size as u64) as u32;
+    if report.pages == 0 {
+        report.problem(Area::Fcr, Some(0), "FCR page is truncated");
+        return Ok(None);
+    }
+
+    let mut pages = PageReader { file, page_size };
+    let fcr_page = pages.read(0)?;
+    let fcr = FileControlRecord::from_bytes(&fcr_page)?;
+    Ok(Some((pages, fcr_page, fcr, length)))
+}
+
+struct PageReader<F> {
+    file: F,
+    page_size: u16,
+}
+
+impl<F: Read + Seek> PageReader<F> {
+    fn read(&mut self, page: u32) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; self.page_size as usize];
+        self.file.seek(SeekFrom::Start(page as u64 * self.page_size as u64))?;
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Data pages on the chain, and the key prefix each key keeps in the index
+/// for every live record, by file offset
+struct Records {
+    data_pages: HashSet<u32>,
+    keys: HashMap<u32, Vec<Vec<u8>>>,
+}
+
+fn check_fcr(fcr: &FileControlRecord, report: &mut CheckReport) {
+    report.fcr_records = fcr.num_records;
+    if fcr.num_pages > report.pages {
+        report.problem(
+            Area::Fcr,
+            None,
+            format!("FCR counts {} pages but the file holds {}", fcr.num_pages, report.pages),
+        );
+    }
+    let max_record = fcr.page_size as usize - DataPage::HEADER_SIZE - SlotEntry::SIZE;
+    if fcr.record_length == 0 || fcr.record_length as usize > max_record {
+        report.problem(
+            Area::Fcr,
+            None,
+            format!("record length {} does not fit a {} byte page", fcr.record_length, fcr.page_size),
+        );
+    }
+    if fcr.num_keys as usize > FileControlRecord::MAX_KEYS {
+        report.problem(Area::Fcr, None, format!("{} keys defined, at most {}", fcr.num_keys, FileControlRecord::MAX_KEYS));
+    }
+    for (number, key) in fcr.keys.iter().enumerate() {
+        if key.length == 0 || key.position as usize + key.length as usize > fcr.record_length as usize {
+            report.problem(
+                Area::Fcr,
+                None,
+                format!(
+                    "key {} at position {} length {} lies outside the {} byte record",
+                    number,
+                    key.position + 1,
+                    key.length,
+                    fcr.record_length
+                ),
+            );
+        }
+    }
+}
+
+fn check_data_pages<F: Read + Seek>(
+    pages: &mut PageReader<F>,
+    fcr: &FileControlRecord,
+    first_data_page: Option<u32>,
+    report: &mut CheckReport,
+) -> io::Result<Records> {
+    let page_size = fcr.page_size as usize;
+    let mut records = Records { data_pages: HashSet::new(), keys: HashMap::new() };
+    let mut previous = 0;
+    let mut next = first_data_page;
+
+    while let Some(number) = next {
+        if number == 0 || number >= report.pages {
+            report.problem(Area::Data, Some(previous), format!("chain points at page {}, outside the file", number));
+            break;
+        }
+        if !records.data_pages.insert(number) {
+            report.problem(Area::Data, Some(previous), format!("chain loops back to page {}", number));
+            break;
+        }
+        let data = pages.read(number)?;
+        if PageType::from(data[0]) != PageType::Data {
+            report.problem(Area::Data, Some(number), format!("on the data chain but has page type {:#04x}", data[0]));
+            break;
+        }
+        let page = DataPage::from_bytes(number, data.clone())?;
+        report.data_pages += 1;
+        if page.prev_page != previous {
+            report.problem(
+                Area::Data,
+                Some(number),
+                format!("previous page link is {}, expected {}", page.prev_page, previous),
+            );
+        }
+
+        let directory = page.slot_count as usize * SlotEntry::SIZE;
+        if DataPage::HEADER_SIZE + directory > page_size {
+            report.problem(Area::Data, Some(number), format!("{} slots do not fit the page", page.slot_count));
+        } else {
+            check_slots(&page, &data, fcr, page_size - directory, &mut records, report);
+        }
+
+        previous = number;
+        next = (page.next_page != 0).then_some(page.next_page);
+    }
+
+    if report.records != fcr.num_records {
+        report.problem(
+            Area::Fcr,
+            None,
+            format!("FCR counts {} records but the data pages hold {}", fcr.num_records, report.records),
+        );
+    }
+    Ok(records)
+}
+
+fn check_slots(
+    page: &DataPage,
+    data: &[u8],
+    fcr: &FileControlRecord,
+    directory_start: usize,
+    records: &mut Records,
+    report: &mut CheckReport,
+) {
+    let number = page.page_number;
+    // Slot n is the nth entry back from the end of the page, the order
+    // `DataPage::insert_record` writes them in
+    let slots: Vec<SlotEntry> = (0..page.slot_count as usize)
+        .filter_map(|n| SlotEntry::from_bytes(&data[data.len() - (n + 1) * SlotEntry::SIZE..]).ok())
+        .collect();
+    let mut extents = Vec::new();
+    for (slot, entry) in slots.iter().enumerate() {
+        if !entry.is_in_use() {
+            continue;
+        }
+        let start = entry.offset as usize;
+        let end = start + entry.length as usize;
+        if start < DataPage::HEADER_SIZE || end > directory_start {
+            report.problem(
+                Area::Data,
+                Some(number),
+                format!("slot {} spans bytes {}..{}, outside the record area", slot, start, end),
+            );
+            continue;
+        }
+        extents.push((start, end, slot));
+        if entry.is_deleted() {
+            continue;
+        }
+        if !fcr.is_variable_length() && entry.length != fcr.record_length {
+            report.problem(
+                Area::Data,
+                Some(number),
+                format!("slot {} holds {} bytes, records are {}", slot, entry.length, fcr.record_length),
+            );
+        }
+        report.records += 1;
+        let record = &data[start..end];
+        let keys = fcr
+            .keys
+            .iter()
+            .map(|key| {
+                let mut value = key.extract_key(record);
+                value.truncate(4);
+                value
+            })
+            .collect();
+        records.keys.insert(number * fcr.page_size as u32 + entry.offset as u32, keys);
+    }
+
+    extents.sort();
+    for pair in extents.windows(2) {
+        if pair[1].0 < pair[0].1 {
+            report.problem(Area::Data, Some(number), format!("slots {} and {} overlap", pair[0].2, pair[1].2));
+        }
+    }
+
+    // Deleted slots form a list through the first two bytes of their data.
+    // The head is read where `DataPage` writes it, at offset 16
+    let mut seen = HashSet::new();
+    let mut free = u16::from_le_bytes([data[16], data[17]]);
+    while free != DataPage::NO_FREE_SLOT {
+        let Some(entry) = slots.get(free as usize).filter(|e| e.is_deleted()) else {
+            report.problem(Area::Data, Some(number), format!("free list reaches slot {}, which is not deleted", free));
+            break;
+        };
+        if !seen.insert(free) {
+            report.problem(Area::Data, Some(number), format!("free list loops at slot {}", free));
+            break;
+        }
+        let at = entry.offset as usize;
+        free = if entry.length >= 2 && at + 2 <= data.len() {
+            u16::from_le_bytes([data[at], data[at + 1]])
+        } else {
+            DataPage::NO_FREE_SLOT
+        };
+    }
+}
+
+fn check_indexes<F: Read + Seek>(
+    pages: &mut PageReader<F>,
+    fcr: &FileControlRecord,
+    records: &Records,
+    report: &mut CheckReport,
+) -> io::Result<()> {
+    // Every page off the data chain is an index node or unused. Leaves are
+    // checked; internal nodes only counted
+    let mut nodes = HashMap::new();
+    let mut internal_nodes = 0;
+    for number in 1..report.pages {
+        if records.data_pages.contains(&number) {
+            continue;
+        }
+        let data = pages.read(number)?;
+        if data.iter().all(|&b| b == 0) {
+            report.unused_pages.push(number);
+        } else if data[0] == 0 && data[1] == 0 && u16::from_le_bytes([data[2], data[3]]) == number as u16 {
+            let count = u16::from_le_bytes([data[6], data[7]]) as usize;
+            if IndexNode::HEADER_SIZE + count * IndexNode::ENTRY_SIZE > data.len() {
+                report.problem(Area::Index, Some(number), format!("{} entries do not fit the page", count));
+            }
+            nodes.insert(number, data);
+        } else if data[0] == IndexNode::INTERNAL_PAGE_TYPE && u16::from_le_bytes([data[2], data[3]]) == number as u16 {
+            internal_nodes += 1;
+        } else if PageType::from(data[0]) == PageType::Data {
+            report.problem(Area::Data, Some(number), "data page is not on the data page chain");
+        } else {
+            report.problem(Area::Index, Some(number), format!("unknown page type {:#04x}", data[0]));
+        }
+    }
+    report.index_pages = nodes.len() as u32 + internal_nodes;
+
+    let mut key_entries = vec![0u64; fcr.keys.len()];
+    let mut unique: Vec<HashSet<Vec<u8>>> = vec![HashSet::new(); fcr.keys.len()];
+    let mut numbers: Vec<_> = nodes.keys().copied().collect();
+    numbers.sort();
+    for number in numbers {
+        let data = &nodes[&number];
+        let link = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        for (at, name, back_at) in [(8, "previous", 12), (12, "next", 8)] {
+            let sibling = link(at);
+            if sibling == u32::MAX {
+                continue;
+            }
+            match nodes.get(&sibling) {
+                None => report.problem(
+                    Area::Index,
+                    Some(number),
+                    format!("{} sibling {} is not an index page", name, sibling),
+                ),
+                Some(other) => {
+                    let back = u32::from_le_bytes([other[back_at], other[back_at + 1], other[back_at + 2], other[back_at + 3]]);
+                    if back != number {
+                        report.problem(
+                            Area::Index,
+                            Some(number),
+                            format!("{} sibling {} links back to {}", name, sibling, back),
+                        );
+                    }
+                }
+            }
+        }
+
+        // Entries are as wide as their key, so the page is read as each
+        // key's and belongs to the first whose entries match their records
+        if fcr.keys.is_empty() {
+            report.problem(Area::Index, Some(number), "index page in a file without keys");
+            report.index_page_keys.push((number, None));
+            continue;
+        }
+        let nodes = fcr
+            .keys
+            .iter()
+            .map(|key| IndexNode::from_bytes(number, data, key.clone()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let matched = nodes.iter().enumerate().position(|(key_number, node)| {
+            node.leaf_entries.iter().all(|entry| {
+                records.keys.get(&entry.record_address.page).is_none_or(|keys| keys[key_number] == entry.key)
+            })
+        });
+        let node = &nodes[matched.unwrap_or(0)];
+        let dangling: Vec<u32> = node
+            .leaf_entries
+            .iter()
+            .map(|entry| entry.record_address.page)
+            .filter(|offset| !records.keys.contains_key(offset))
+            .collect();
+        if let Some(&offset) = dangling.first() {
+            report.problem(
+                Area::CrossReference,
+                Some(number),
+                format!(
+                    "{} of {} entries point at no record (first at file offset {})",
+                    dangling.len(),
+                    node.leaf_entries.len(),
+                    offset
+                ),
+            );
+        }
+        if dangling.len() == node.leaf_entries.len() {
+            report.index_page_keys.push((number, None));
+            continue;
+        }
+        let Some(key_number) = matched else {
+            report.problem(Area::CrossReference, Some(number), "entry keys do not match the records they point at");
+            report.index_page_keys.push((number, None));
+            continue;
+        };
+        report.index_page_keys.push((number, Some(key_number)));
+        let key = &fcr.keys[key_number];
+        key_entries[key_number] += (node.leaf_entries.len() - dangling.len()) as u64;
+
+        for (i, pair) in node.leaf_entries.windows(2).enumerate() {
+            if key.compare(&pair[0].key, &pair[1].key) == std::cmp::Ordering::Greater {
+                report.problem(
+                    Area::Index,
+                    Some(number),
+                    format!("key {} entries {} and {} are out of order", key_number, i, i + 1),
+                );
+            }
+        }
+        if !key.allows_duplicates() {
+            for entry in &node.leaf_entries {
+                if records.keys.contains_key(&entry.record_address.page) && !unique[key_number].insert(entry.key.clone()) {
+                    report.problem(
+                        Area::Index,
+                        Some(number),
+                        format!("key {} does not allow duplicates but repeats a value", key_number),
+                    );
+                }
+            }
+        }
+    }
+
+    for (key_number, &entries) in key_entries.iter().enumerate() {
+        if entries != report.records && !fcr.keys[key_number].allows_null() {
+            report.problem(
+                Area::CrossReference,
+                None,
+                format!("key {} indexes {} of {} records", key_number, entries, report.records),
+            );
+        }
+    }
+    report.key_entries = key_entries;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::btree::LeafEntry;
+    use crate::storage::key::{KeyFlags, KeySpec, KeyType};
+    use crate::storage::record::RecordAddress;
+    use std::io::Cursor;
+
+    const PAGE: u16 = 512;
+
+    /// FCR, one data page with three records, one index leaf for key 0
+    fn sample() -> Vec<u8> {
+        let key = KeySpec {
+            position: 0,
+            length: 4,
+            flags: KeyFlags::empty(),
+            key_type: KeyType::UnsignedBinary,
+            null_value: 0,
+            acs_number: 0,
+            unique_count: 0,
+        };
+        let mut fcr = FileControlRecord::new(16, PAGE, vec![key.clone()]);
+        fcr.num_records = 3;
+        fcr.num_pages = 3;
+        fcr.first_data_page = 1;
+
+        let mut data = DataPage::new(1, PAGE);
+        let mut leaf = IndexNode::new_leaf(2, key, PAGE);
+        for id in [7u32, 3, 5] {
+            let mut record = vec![0u8; 16];
+            record[..4].copy_from_slice(&id.to_le_bytes());
+            let slot = data.insert_record(&record).unwrap();
+            let offset = PAGE as u32 + data.slots[slot as usize].offset as u32;
+            leaf.insert_leaf_entry(
+                LeafEntry {
+                    key: id.to_le_bytes().to_vec(),
+                    record_address: RecordAddress::new(offset, 0),
+                    dup_sequence: 0,
+                },
+                false,
+            );
+        }
+
+        // A deleted record on the free list
+        let gone = data.insert_record(&[9u8; 16]).unwrap();
+        data.delete_record(gone);
+
+        let mut file = fcr.to_bytes();
+        file.extend(data.to_bytes());
+        file.extend(leaf.to_bytes(PAGE));
+        file
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let report = check(Cursor::new(sample())).unwrap();
+        assert!(report.is_clean(), "{:?}", report.problems);
+        assert_eq!((report.pages, report.data_pages, report.index_pages), (3, 1, 1));
+        assert_eq!(report.records, 3);
+        assert_eq!(report.key_entries, [3]);
+        assert_eq!(report.index_page_keys, [(2, Some(0))]);
+    }
+
+    #[test]
+    fn test_corruption_found() {
+        let mut file = sample();
+        // Point the second index entry at the FCR
+        let entry = PAGE as usize * 2 + IndexNode::HEADER_SIZE + IndexNode::ENTRY_SIZE;
+        file[entry + 4..entry + 8].fill(0);
+        // FCR claims one record too many
+        file[0x1C] = 4;
+
+        let report = check(Cursor::new(file.clone())).unwrap();
+        assert_eq!(report.problems_in(Area::Fcr).count(), 1);
+        let cross: Vec<_> = report.problems_in(Area::CrossReference).map(|p| p.to_string()).collect();
+        assert_eq!(
+            cross,
+            [
+                "[cross-reference] page 2: 1 of 3 entries point at no record (first at file offset 0)",
+                "[cross-reference] key 0 indexes 2 of 3 records",
+            ]
+        );
+
+        // A data chain pointing past the end of the file
+        file[PAGE as usize + 4] = 9;
+        let report = check(Cursor::new(file)).unwrap();
+        assert!(report.problems_in(Area::Data).any(|p| p.message.contains("outside the file")));
+
+        let report = check(Cursor::new(vec![0u8; 100])).unwrap();
+        assert_eq!(report.problems[0].message, "invalid page size 0");
+    }
+}
@@ -0,0 +1,209 @@
+//! Collations for string keys
+//!
+//! String keys compare byte by byte unless they name a collation. The
+//! built-in ones fold case through an OEM code page, so that "SMITH",
+//! "Smith" and "smith" are one key value, the way Btrieve's UPPER.ALT
+//! sequence sorts them. A key picks one with an alternate collating
+//! sequence number, or with the NO_CASE flag (an Xtrieve extension),
+//! which folds through code page 437 unless the ACS number names 850.
+//!
+//! UTF-8 keys (an Xtrieve key type) compare as text instead: trailing
+//! NULs and blanks are padding, both sides are put in Unicode NFC so
+//! precomposed and combining accents match, and NO_CASE applies full
+//! Unicode case folding. Bytes that aren't valid UTF-8 compare as U+FFFD.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// ACS number of the code page 437 upper-case sequence
+pub const ACS_UPPER_437: u8 = 1;
+/// ACS number of the code page 850 upper-case sequence
+pub const ACS_UPPER_850: u8 = 2;
+
+/// OEM code page a collation folds case through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodePage {
+    Cp437,
+    Cp850,
+}
+
+/// How a string key's bytes compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collation {
+    /// Byte by byte
+    Binary,
+    /// Byte by byte after upper-casing through a code page
+    NoCase(CodePage),
+}
+
+impl Collation {
+    /// Built-in collation for an ACS number, if there is one
+    pub fn from_acs(number: u8) -> Option<Self> {
+        match number {
+            ACS_UPPER_437 => Some(Collation::NoCase(CodePage::Cp437)),
+            ACS_UPPER_850 => Some(Collation::NoCase(CodePage::Cp850)),
+            _ => None,
+        }
+    }
+
+    /// A byte as it compares
+    pub fn fold(self, byte: u8) -> u8 {
+        match self {
+            Collation::Binary => byte,
+            Collation::NoCase(CodePage::Cp437) => UPPER_437[byte as usize],
+            Collation::NoCase(CodePage::Cp850) => UPPER_850[byte as usize],
+        }
+    }
+
+    pub fn compare(self, a: &[u8], b: &[u8]) -> Ordering {
+        match self {
+            Collation::Binary => a.cmp(b),
+            _ => a.iter().map(|&c| self.fold(c)).cmp(b.iter().map(|&c| self.fold(c))),
+        }
+    }
+}
+
+/// Compare two UTF-8 key values, case folded if `no_case`
+pub fn compare_utf8(a: &[u8], b: &[u8], no_case: bool) -> Ordering {
+    let (a, b) = (utf8_text(a), utf8_text(b));
+    if a.is_ascii() && b.is_ascii() && !no_case {
+        // Already NFC
+        return a.cmp(&b);
+    }
+    utf8_normal(&a, no_case).cmp(&utf8_normal(&b, no_case))
+}
+
+/// Key bytes as text, without padding
+fn utf8_text(key: &[u8]) -> Cow<'_, str> {
+    let end = key.iter().rposition(|&c| c != 0 && c != b' ').map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&key[..end])
+}
+
+/// NFC form of `text`, case folded if `no_case`
+fn utf8_normal(text: &str, no_case: bool) -> String {
+    if no_case {
+        caseless::default_case_fold_str(text).nfc().collect()
+    } else {
+        text.nfc().collect()
+    }
+}
+
+/// Identity table with ASCII a-z upper-cased and `pairs` mapped
+const fn upper(pairs: &[(u8, u8)]) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = if i >= b'a' as usize && i <= b'z' as usize { i as u8 - 32 } else { i as u8 };
+        i += 1;
+    }
+    let mut i = 0;
+    while i < pairs.len() {
+        table[pairs[i].0 as usize] = pairs[i].1;
+        i += 1;
+    }
+    table
+}
+
+/// Code page 437: accented letters it has a capital for fold to it, the
+/// rest to the unaccented capital
+static UPPER_437: [u8; 256] = upper(&[
+    (0x81, 0x9A), // ü
+    (0x82, 0x90), // é
+    (0x83, b'A'), // â
+    (0x84, 0x8E), // ä
+    (0x85, b'A'), // à
+    (0x86, 0x8F), // å
+    (0x87, 0x80), // ç
+    (0x88, b'E'), // ê
+    (0x89, b'E'), // ë
+    (0x8A, b'E'), // è
+    (0x8B, b'I'), // ï
+    (0x8C, b'I'), // î
+    (0x8D, b'I'), // ì
+    (0x91, 0x92), // æ
+    (0x93, b'O'), // ô
+    (0x94, 0x99), // ö
+    (0x95, b'O'), // ò
+    (0x96, b'U'), // û
+    (0x97, b'U'), // ù
+    (0x98, b'Y'), // ÿ
+    (0xA0, b'A'), // á
+    (0xA1, b'I'), // í
+    (0xA2, b'O'), // ó
+    (0xA3, b'U'), // ú
+    (0xA4, 0xA5), // ñ
+]);
+
+/// Code page 850: every accented letter has its capital
+static UPPER_850: [u8; 256] = upper(&[
+    (0x81, 0x9A), // ü
+    (0x82, 0x90), // é
+    (0x83, 0xB6), // â
+    (0x84, 0x8E), // ä
+    (0x85, 0xB7), // à
+    (0x86, 0x8F), // å
+    (0x87, 0x80), // ç
+    (0x88, 0xD2), // ê
+    (0x89, 0xD3), // ë
+    (0x8A, 0xD4), // è
+    (0x8B, 0xD8), // ï
+    (0x8C, 0xD7), // î
+    (0x8D, 0xDE), // ì
+    (0x91, 0x92), // æ
+    (0x93, 0xE2), // ô
+    (0x94, 0x99), // ö
+    (0x95, 0xE3), // ò
+    (0x96, 0xEA), // û
+    (0x97, 0xEB), // ù
+    (0x98, b'Y'), // ÿ
+    (0x9B, 0x9D), // ø
+    (0xA0, 0xB5), // á
+    (0xA1, 0xD6), // í
+    (0xA2, 0xE0), // ó
+    (0xA3, 0xE9), // ú
+    (0xA4, 0xA5), // ñ
+    (0xC6, 0xC7), // ã
+    (0xD0, 0xD1), // ð
+    (0xE4, 0xE5), // õ
+    (0xE7, 0xE8), // þ
+    (0xEC, 0xED), // ý
+]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_page_folding() {
+        let cp437 = Collation::NoCase(CodePage::Cp437);
+        let cp850 = Collation::NoCase(CodePage::Cp850);
+
+        assert_eq!(cp437.compare(b"Smith", b"SMITH"), Ordering::Equal);
+        assert_eq!(cp437.compare(b"smith", b"SMYTH"), Ordering::Less);
+        assert_eq!(Collation::Binary.compare(b"smith", b"SMITH"), Ordering::Greater);
+
+        // "café" against "CAFÉ": both code pages have É
+        assert_eq!(cp437.compare(b"caf\x82", b"CAF\x90"), Ordering::Equal);
+        assert_eq!(cp850.compare(b"caf\x82", b"CAF\x90"), Ordering::Equal);
+
+        // "à" has a capital only in 850; 437 folds it to plain A
+        assert_eq!(cp437.fold(0x85), b'A');
+        assert_eq!(cp850.fold(0x85), 0xB7);
+        assert_eq!(cp850.fold(0xB7), 0xB7);
+
+        assert_eq!(Collation::from_acs(ACS_UPPER_850), Some(cp850));
+        assert_eq!(Collation::from_acs(3), None);
+    }
+
+    #[test]
+    fn test_utf8_compare() {
+        // "é" precomposed against "e" and a combining acute, NUL padded
+        assert_eq!(compare_utf8("caf\u{e9}".as_bytes(), b"cafe\xCC\x81\0\0", false), Ordering::Equal);
+        assert_eq!(compare_utf8("Stra\u{df}e".as_bytes(), b"STRASSE", false), Ordering::Greater);
+        assert_eq!(compare_utf8("Stra\u{df}e".as_bytes(), b"STRASSE  ", true), Ordering::Equal);
+        assert_eq!(compare_utf8("\u{3a3}\u{3b1}".as_bytes(), "\u{3c3}\u{391}".as_bytes(), true), Ordering::Equal);
+        assert_eq!(compare_utf8(b"abc", b"abd", true), Ordering::Less);
+    }
+}
@@ -0,0 +1,196 @@
+//! Page compression
+//!
+//! A file created with the data compression flag keeps every page but the
+//! FCR compressed with LZ4. Compressed pages vary in size, so they can't be
+//! rewritten in fixed slots; instead each write appends a frame after the
+//! FCR:
+//!
+//! ```text
+//! [page number:4][length:4][stored page:length]
+//! ```
+//!
+//! The latest frame of a page holds it. Opening the file scans the frames
+//! into a `FrameIndex`, so opens cost a read of the whole file: this suits
+//! large archival files read far more than written. A frame as long as the
+//! page holds it uncompressed, for pages LZ4 can't shrink. Frames a page
+//! leaves behind are reclaimed by rewriting the file, which the engine does
+//! on the last close once they outweigh the live ones.
+//!
+//! Compressed files are an Xtrieve extension: DOS Btrieve can't read them.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// How a file's pages are compressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Pages are stored in fixed slots as they are
+    #[default]
+    None,
+    /// LZ4 block compression, pages stored as frames
+    Lz4,
+}
+
+impl Compression {
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+
+    pub fn to_raw(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+        }
+    }
+}
+
+/// Bytes of a frame header
+pub const FRAME_HEADER: u64 = 8;
+
+/// Compress a page, or keep it as it is if that is no larger
+pub fn compress_page(data: &[u8]) -> Vec<u8> {
+    let compressed = lz4_flex::block::compress(data);
+    if compressed.len() < data.len() {
+        compressed
+    } else {
+        data.to_vec()
+    }
+}
+
+/// Restore a page of `page_size` bytes from what `compress_page` made of it
+pub fn decompress_page(stored: &[u8], page_size: usize) -> io::Result<Vec<u8>> {
+    if stored.len() == page_size {
+        return Ok(stored.to_vec());
+    }
+    match lz4_flex::block::decompress(stored, page_size) {
+        Ok(data) if data.len() == page_size => Ok(data),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "compressed page is damaged")),
+    }
+}
+
+/// Where the latest frame of each page is
+#[derive(Debug, Clone, Default)]
+pub struct FrameIndex {
+    /// Offset and length of each page's stored bytes, by page number
+    frames: Vec<Option<(u64, u32)>>,
+    /// Where the next frame goes
+    end: u64,
+    /// Bytes taken by frames no longer the latest of their page
+    stale: u64,
+}
+
+impl FrameIndex {
+    /// Index of a file with no frames yet, which start at `start`
+    pub fn new(start: u64) -> Self {
+        FrameIndex { frames: Vec::new(), end: start, stale: 0 }
+    }
+
+    /// Read the frames from `start` on. A frame cut short by a crash ends
+    /// the scan, and is overwritten by the next one appended
+    pub fn scan<F: Read + Seek>(file: &mut F, start: u64) -> io::Result<Self> {
+        let length = file.seek(SeekFrom::End(0))?;
+        let mut index = Self::new(start);
+        file.seek(SeekFrom::Start(start))?;
+        let mut header = [0u8; FRAME_HEADER as usize];
+        while index.end + FRAME_HEADER <= length {
+            file.read_exact(&mut header)?;
+            let page = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            if page == 0 || index.end + FRAME_HEADER + len as u64 > length {
+                break;
+            }
+            index.record(page, len);
+            file.seek(SeekFrom::Current(len as i64))?;
+        }
+        Ok(index)
+    }
+
+    /// Offset and length of a page's stored bytes, if it has been written
+    pub fn get(&self, page: u32) -> Option<(u64, u32)> {
+        self.frames.get(page as usize).copied().flatten()
+    }
+
+    /// Pages in the file, the FCR included
+    pub fn page_count(&self) -> u32 {
+        self.frames.len().max(1) as u32
+    }
+
+    /// Where the next frame goes
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Take note of a frame of `len` bytes for `page` appended at the end,
+    /// returning where its header goes
+    pub fn record(&mut self, page: u32, len: u32) -> u64 {
+        let at = self.end;
+        if self.frames.len() <= page as usize {
+            self.frames.resize(page as usize + 1, None);
+        }
+        if let Some((_, old)) = self.frames[page as usize].replace((at + FRAME_HEADER, len)) {
+            self.stale += FRAME_HEADER + old as u64;
+        }
+        self.end += FRAME_HEADER + len as u64;
+        at
+    }
+
+    /// Whether rewriting the file would at least halve the frames
+    pub fn worth_compacting(&self, start: u64) -> bool {
+        self.stale > 0 && self.stale >= self.end - start - self.stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn test_page_roundtrip() {
+        let mut page = vec![0u8; 1024];
+        page[..11].copy_from_slice(b"ACME WIDGET");
+        let stored = compress_page(&page);
+        assert!(stored.len() < 100);
+        assert_eq!(decompress_page(&stored, 1024).unwrap(), page);
+
+        // Pages that don't shrink are kept as they are
+        let mut state = 0x2545_F491u32;
+        let noise: Vec<u8> = (0..1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        assert_eq!(compress_page(&noise), noise);
+        assert_eq!(decompress_page(&noise, 1024).unwrap(), noise);
+        assert!(decompress_page(&stored[..stored.len() - 1], 1024).is_err());
+    }
+
+    #[test]
+    fn test_frame_scan() {
+        let mut index = FrameIndex::new(512);
+        let mut file = Cursor::new(vec![0u8; 512]);
+        for (page, stored) in [(1u32, &b"abc"[..]), (2, b"defgh"), (1, b"ij")] {
+            file.seek(SeekFrom::Start(index.record(page, stored.len() as u32))).unwrap();
+            file.write_all(&page.to_le_bytes()).unwrap();
+            file.write_all(&(stored.len() as u32).to_le_bytes()).unwrap();
+            file.write_all(stored).unwrap();
+        }
+        // A frame torn by a crash
+        file.write_all(&3u32.to_le_bytes()).unwrap();
+        file.write_all(&100u32.to_le_bytes()).unwrap();
+
+        let scanned = FrameIndex::scan(&mut file, 512).unwrap();
+        assert_eq!(scanned.page_count(), 3);
+        assert_eq!(scanned.get(1), Some((512 + 11 + 13 + 8, 2)));
+        assert_eq!(scanned.get(2), Some((512 + 11 + 8, 5)));
+        assert_eq!(scanned.get(3), None);
+        assert_eq!(scanned.end(), index.end());
+        assert!(!scanned.worth_compacting(512));
+    }
+}
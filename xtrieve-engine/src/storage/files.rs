@@ -1,617 +1,353 @@
-//! Btrieve-style file set management
+//! File layouts - where a file's pages are kept
 //!
-//! Manages the complete file set for a Btrieve database:
-//! - .DAT - Main data file (FCR + data pages)
-//! - .IX# - Index files (one per key: .IX0, .IX1, etc.)
-//! - .PRE - Pre-image file (for transaction rollback)
+//! A file is created with one of three layouts:
+//! - Single: every page in the .DAT, as Btrieve 5.1 keeps them
+//! - Split: the FCR and data pages in the .DAT, the index pages in a
+//!   companion file beside it with the extension IX
+//! - Per key: like split, but each key's index pages in a file of its own,
+//!   .IX0 for key 0, .IX1 for key 1 and so on
 //!
-//! This follows the original Btrieve 5.1 architecture.
+//! The last two are Xtrieve extensions. Pages keep their numbers in them:
+//! an index page sits in its index file at the offset it would have had in
+//! the .DAT, whose slot for it stays blank, so the .DAT still tells how
+//! many pages there are and the index files have holes where the other
+//! pages are. Which file a page goes to follows from what it holds, and
+//! for a new index page of a per-key file from the key it is claimed for;
+//! opening the file finds its index pages again as the slots of its index
+//! files that aren't blank.
+//!
+//! Every layout takes the same per-session pre-images for transactions
+//! (see `OpenFile`): a page is restored to the file it was read from.
 
-use std::fs::{self, File, OpenOptions};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-
 use super::fcr::FileControlRecord;
-use super::key::KeySpec;
-use super::page::Page;
-
-/// File extensions
-pub const DATA_EXT: &str = "DAT";
-pub const INDEX_EXT_PREFIX: &str = "IX";
-pub const PREIMAGE_EXT: &str = "PRE";
-
-/// Index file header (stored in page 0 of each .IX# file)
-#[derive(Debug, Clone)]
-pub struct IndexFileHeader {
-    /// Signature to identify as Xtrieve index file
-    pub signature: [u8; 4],
-    /// Page size (must match data file)
-    pub page_size: u16,
-    /// Key number this index represents
-    pub key_number: u16,
-    /// Copy of key specification
-    pub key_spec: KeySpec,
-    /// Root page of B+ tree (0 if empty)
-    pub root_page: u32,
-    /// Number of pages in this index file
-    pub num_pages: u32,
-    /// Number of entries in the index
-    pub num_entries: u32,
-    /// Unique key counter (for duplicate key handling)
-    pub unique_count: u32,
+use super::page::{PageType, MAX_PAGE_SIZE, PAGE_SIZES};
+
+/// Extension of a split file's index file, and the start of a per-key
+/// file's
+pub const INDEX_EXT: &str = "IX";
+
+/// Where a file's pages are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Every page in the .DAT
+    #[default]
+    Single,
+    /// Index pages in a companion .IX file
+    Split,
+    /// Each key's index pages in a .IX# file of its own
+    PerKey,
 }
 
-impl IndexFileHeader {
-    pub const SIZE: usize = 64;
-    pub const SIGNATURE: [u8; 4] = [b'X', b'I', b'D', b'X']; // "XIDX"
-
-    pub fn new(page_size: u16, key_number: u16, key_spec: KeySpec) -> Self {
-        IndexFileHeader {
-            signature: Self::SIGNATURE,
-            page_size,
-            key_number,
-            key_spec,
-            root_page: 0,
-            num_pages: 1, // Just header page initially
-            num_entries: 0,
-            unique_count: 0,
+impl Layout {
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Layout::Single),
+            1 => Some(Layout::Split),
+            2 => Some(Layout::PerKey),
+            _ => None,
         }
     }
 
-    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
-        if data.len() < Self::SIZE {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Index header too short"));
-        }
-
-        let mut cursor = std::io::Cursor::new(data);
-
-        let mut signature = [0u8; 4];
-        cursor.read_exact(&mut signature)?;
-
-        if signature != Self::SIGNATURE {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid index signature"));
+    pub fn to_raw(self) -> u8 {
+        match self {
+            Layout::Single => 0,
+            Layout::Split => 1,
+            Layout::PerKey => 2,
         }
-
-        let page_size = cursor.read_u16::<LittleEndian>()?;
-        let key_number = cursor.read_u16::<LittleEndian>()?;
-
-        // Read key spec (16 bytes)
-        let mut key_bytes = [0u8; 16];
-        cursor.read_exact(&mut key_bytes)?;
-        let key_spec = KeySpec::from_bytes(&key_bytes)?;
-
-        let root_page = cursor.read_u32::<LittleEndian>()?;
-        let num_pages = cursor.read_u32::<LittleEndian>()?;
-        let num_entries = cursor.read_u32::<LittleEndian>()?;
-        let unique_count = cursor.read_u32::<LittleEndian>()?;
-
-        Ok(IndexFileHeader {
-            signature,
-            page_size,
-            key_number,
-            key_spec,
-            root_page,
-            num_pages,
-            num_entries,
-            unique_count,
-        })
     }
 
-    pub fn to_bytes(&self, page_size: u16) -> Vec<u8> {
-        let mut buf = vec![0u8; page_size as usize];
-
-        buf[0..4].copy_from_slice(&self.signature);
-
-        let mut cursor = std::io::Cursor::new(&mut buf[4..]);
-        cursor.write_u16::<LittleEndian>(self.page_size).unwrap();
-        cursor.write_u16::<LittleEndian>(self.key_number).unwrap();
-
-        // Key spec (16 bytes)
-        let key_bytes = self.key_spec.to_bytes();
-        cursor.write_all(&key_bytes).unwrap();
-
-        cursor.write_u32::<LittleEndian>(self.root_page).unwrap();
-        cursor.write_u32::<LittleEndian>(self.num_pages).unwrap();
-        cursor.write_u32::<LittleEndian>(self.num_entries).unwrap();
-        cursor.write_u32::<LittleEndian>(self.unique_count).unwrap();
-
-        buf
+    /// Which of the index files a key's pages go to
+    pub fn index_file_of(self, key_number: usize) -> usize {
+        match self {
+            Layout::PerKey => key_number,
+            Layout::Single | Layout::Split => 0,
+        }
     }
 }
 
-/// Pre-image record (one per modified page)
-#[derive(Debug, Clone)]
-pub struct PreImageRecord {
-    /// Source: 0 = data file, 1+ = index file number
-    pub source: u8,
-    /// Page number in the source file
-    pub page_number: u32,
-    /// Original page data before modification
-    pub original_data: Vec<u8>,
-}
-
-impl PreImageRecord {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(5 + self.original_data.len());
-        buf.push(self.source);
-        buf.extend_from_slice(&self.page_number.to_le_bytes());
-        buf.extend_from_slice(&(self.original_data.len() as u32).to_le_bytes());
-        buf.extend_from_slice(&self.original_data);
-        buf
-    }
-
-    pub fn from_bytes(data: &[u8]) -> io::Result<(Self, usize)> {
-        if data.len() < 9 {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Pre-image record too short"));
-        }
-
-        let source = data[0];
-        let page_number = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
-        let data_len = u32::from_le_bytes([data[5], data[6], data[7], data[8]]) as usize;
-
-        if data.len() < 9 + data_len {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Pre-image data truncated"));
-        }
-
-        let original_data = data[9..9 + data_len].to_vec();
-        let total_len = 9 + data_len;
-
-        Ok((PreImageRecord { source, page_number, original_data }, total_len))
+/// Paths of the index files of the file at `data_path`
+pub fn index_paths(data_path: &Path, layout: Layout, num_keys: usize) -> Vec<PathBuf> {
+    match layout {
+        Layout::Single => Vec::new(),
+        Layout::Split => vec![data_path.with_extension(INDEX_EXT)],
+        Layout::PerKey => (0..num_keys)
+            .map(|k| data_path.with_extension(format!("{}{}", INDEX_EXT, k)))
+            .collect(),
     }
 }
 
-/// Pre-image file header
-#[derive(Debug, Clone)]
-pub struct PreImageHeader {
-    /// Signature
-    pub signature: [u8; 4],
-    /// Transaction ID
-    pub transaction_id: u64,
-    /// Session ID that owns this pre-image
-    pub session_id: u64,
-    /// Base file name (without extension)
-    pub base_name: String,
+/// Whether a page, as the engine writes it, goes to an index file of a
+/// file laid out apart: anything but the FCR, data pages and blank pages
+pub fn is_index_page(page_number: u32, data: &[u8]) -> bool {
+    page_number > 0 && PageType::from(data[0]) != PageType::Data && data.iter().any(|&b| b != 0)
 }
 
-impl PreImageHeader {
-    pub const SIGNATURE: [u8; 4] = [b'X', b'P', b'R', b'E']; // "XPRE"
-
-    pub fn new(transaction_id: u64, session_id: u64, base_name: &str) -> Self {
-        PreImageHeader {
-            signature: Self::SIGNATURE,
-            transaction_id,
-            session_id,
-            base_name: base_name.to_string(),
-        }
-    }
-
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(128);
-        buf.extend_from_slice(&self.signature);
-        buf.extend_from_slice(&self.transaction_id.to_le_bytes());
-        buf.extend_from_slice(&self.session_id.to_le_bytes());
-
-        let name_bytes = self.base_name.as_bytes();
-        buf.extend_from_slice(&(name_bytes.len() as u32).to_le_bytes());
-        buf.extend_from_slice(name_bytes);
-
-        buf
-    }
-
-    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
-        if data.len() < 24 {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Pre-image header too short"));
-        }
-
-        let mut signature = [0u8; 4];
-        signature.copy_from_slice(&data[0..4]);
-
-        if signature != Self::SIGNATURE {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid pre-image signature"));
-        }
-
-        let transaction_id = u64::from_le_bytes([
-            data[4], data[5], data[6], data[7],
-            data[8], data[9], data[10], data[11],
-        ]);
-        let session_id = u64::from_le_bytes([
-            data[12], data[13], data[14], data[15],
-            data[16], data[17], data[18], data[19],
-        ]);
-        let name_len = u32::from_le_bytes([data[20], data[21], data[22], data[23]]) as usize;
-
-        if data.len() < 24 + name_len {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Pre-image name truncated"));
+/// Pages of each index file, as the slots that aren't blank
+fn scan(files: &mut [File], first: u64, slot_size: u64) -> io::Result<HashMap<u32, usize>> {
+    let mut pages = HashMap::new();
+    let mut slot = vec![0u8; slot_size as usize];
+    for (n, file) in files.iter_mut().enumerate() {
+        let len = file.seek(SeekFrom::End(0))?;
+        let mut offset = first;
+        let mut page_number = 1;
+        file.seek(SeekFrom::Start(first))?;
+        while offset + slot_size <= len {
+            file.read_exact(&mut slot)?;
+            if slot.iter().any(|&b| b != 0) {
+                pages.insert(page_number, n);
+            }
+            offset += slot_size;
+            page_number += 1;
         }
-
-        let base_name = String::from_utf8_lossy(&data[24..24 + name_len]).to_string();
-
-        Ok(PreImageHeader {
-            signature,
-            transaction_id,
-            session_id,
-            base_name,
-        })
     }
+    Ok(pages)
 }
 
-/// Manages a complete Btrieve file set
-pub struct BtrieveFileSet {
-    /// Base path (without extension)
-    pub base_path: PathBuf,
-    /// Data file handle
-    pub data_file: Option<File>,
-    /// Index file handles
-    pub index_files: Vec<Option<File>>,
-    /// Pre-image file handle (only during transactions)
-    pub preimage_file: Option<File>,
-    /// Cached FCR
-    pub fcr: Option<FileControlRecord>,
-    /// Cached index headers
-    pub index_headers: Vec<Option<IndexFileHeader>>,
-    /// Page size
-    pub page_size: u16,
+/// The index files of a file laid out apart
+pub struct IndexFile {
+    files: Vec<File>,
+    /// Pages kept in the index files rather than the .DAT, and in which
+    pages: HashMap<u32, usize>,
+    /// Index files new pages are claimed for, until first written
+    claims: HashMap<u32, usize>,
 }
 
-impl BtrieveFileSet {
-    /// Get the data file path
-    pub fn data_path(base: &Path) -> PathBuf {
-        base.with_extension(DATA_EXT)
+impl IndexFile {
+    /// Create empty index files at `paths`, replacing any there
+    pub fn create(paths: &[PathBuf]) -> io::Result<Self> {
+        let files = paths
+            .iter()
+            .map(|path| OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path))
+            .collect::<io::Result<_>>()?;
+        Ok(IndexFile { files, pages: HashMap::new(), claims: HashMap::new() })
     }
 
-    /// Get an index file path
-    pub fn index_path(base: &Path, key_num: usize) -> PathBuf {
-        base.with_extension(format!("{}{}", INDEX_EXT_PREFIX, key_num))
+    /// Open the index files at `paths`, whose slots start at `first` and
+    /// take `slot_size` bytes each from page 1 on
+    pub fn open(paths: &[PathBuf], read_only: bool, first: u64, slot_size: u64) -> io::Result<Self> {
+        let mut files = paths
+            .iter()
+            .map(|path| OpenOptions::new().read(true).write(!read_only).open(path))
+            .collect::<io::Result<Vec<_>>>()?;
+        let pages = scan(&mut files, first, slot_size)?;
+        Ok(IndexFile { files, pages, claims: HashMap::new() })
     }
 
-    /// Get the pre-image file path
-    pub fn preimage_path(base: &Path) -> PathBuf {
-        base.with_extension(PREIMAGE_EXT)
+    /// Whether a page is kept in an index file
+    pub fn contains(&self, page_number: u32) -> bool {
+        self.pages.contains_key(&page_number)
     }
 
-    /// Create a new file set
-    pub fn create(
-        base_path: PathBuf,
-        record_length: u16,
-        page_size: u16,
-        keys: Vec<KeySpec>,
-    ) -> io::Result<Self> {
-        let num_keys = keys.len();
-
-        // Create FCR
-        let fcr = FileControlRecord::new(record_length, page_size, keys.clone());
-
-        // Create and write data file
-        let data_path = Self::data_path(&base_path);
-        let mut data_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&data_path)?;
-
-        let fcr_bytes = fcr.to_bytes();
-        data_file.write_all(&fcr_bytes)?;
-        data_file.sync_all()?;
-
-        // Create index files
-        let mut index_files = Vec::with_capacity(num_keys);
-        let mut index_headers = Vec::with_capacity(num_keys);
-
-        for (i, key_spec) in keys.iter().enumerate() {
-            let index_path = Self::index_path(&base_path, i);
-            let mut index_file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&index_path)?;
-
-            let header = IndexFileHeader::new(page_size, i as u16, key_spec.clone());
-            let header_bytes = header.to_bytes(page_size);
-            index_file.write_all(&header_bytes)?;
-            index_file.sync_all()?;
-
-            index_files.push(Some(index_file));
-            index_headers.push(Some(header));
-        }
-
-        Ok(BtrieveFileSet {
-            base_path,
-            data_file: Some(data_file),
-            index_files,
-            preimage_file: None,
-            fcr: Some(fcr),
-            index_headers,
-            page_size,
-        })
+    /// The index file a page is kept in
+    pub fn file_of(&self, page_number: u32) -> Option<usize> {
+        self.pages.get(&page_number).copied()
     }
 
-    /// Open an existing file set
-    pub fn open(base_path: PathBuf) -> io::Result<Self> {
-        // Open data file
-        let data_path = Self::data_path(&base_path);
-        let mut data_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&data_path)?;
-
-        // Read FCR
-        let mut fcr_buf = vec![0u8; 4096]; // Read first page
-        data_file.read_exact(&mut fcr_buf)?;
-        let fcr = FileControlRecord::from_bytes(&fcr_buf)?;
-        let page_size = fcr.page_size;
-        let num_keys = fcr.num_keys as usize;
-
-        // Open index files
-        let mut index_files = Vec::with_capacity(num_keys);
-        let mut index_headers = Vec::with_capacity(num_keys);
-
-        for i in 0..num_keys {
-            let index_path = Self::index_path(&base_path, i);
-            if index_path.exists() {
-                let mut index_file = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(&index_path)?;
-
-                // Read index header
-                let mut header_buf = vec![0u8; page_size as usize];
-                index_file.read_exact(&mut header_buf)?;
-                let header = IndexFileHeader::from_bytes(&header_buf)?;
-
-                index_files.push(Some(index_file));
-                index_headers.push(Some(header));
-            } else {
-                index_files.push(None);
-                index_headers.push(None);
-            }
-        }
-
-        // Check for orphaned pre-image file (crash recovery)
-        let preimage_path = Self::preimage_path(&base_path);
-        if preimage_path.exists() {
-            // TODO: Perform crash recovery by rolling back
-            tracing::warn!("Found orphaned pre-image file, should recover: {:?}", preimage_path);
+    /// Have a new page go to index file `file` when first written
+    pub fn claim(&mut self, page_number: u32, file: usize) {
+        if file < self.files.len() {
+            self.claims.insert(page_number, file);
         }
-
-        Ok(BtrieveFileSet {
-            base_path,
-            data_file: Some(data_file),
-            index_files,
-            preimage_file: None,
-            fcr: Some(fcr),
-            index_headers,
-            page_size,
-        })
     }
 
-    /// Start a transaction (create pre-image file)
-    pub fn begin_transaction(&mut self, transaction_id: u64, session_id: u64) -> io::Result<()> {
-        let preimage_path = Self::preimage_path(&self.base_path);
-        let mut preimage_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&preimage_path)?;
-
-        let base_name = self.base_path.file_name()
-            .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or_default();
-
-        let header = PreImageHeader::new(transaction_id, session_id, &base_name);
-        preimage_file.write_all(&header.to_bytes())?;
-        preimage_file.sync_all()?;
-
-        self.preimage_file = Some(preimage_file);
-        Ok(())
+    /// Read a page's slot as stored
+    pub fn read(&mut self, page_number: u32, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let file = &mut self.files[self.pages.get(&page_number).copied().unwrap_or(0)];
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0u8; len];
+        file.read_exact(&mut data)?;
+        Ok(data)
     }
 
-    /// Save a pre-image before modifying a page
-    pub fn save_preimage(&mut self, source: u8, page_number: u32, original_data: &[u8]) -> io::Result<()> {
-        if let Some(ref mut preimage) = self.preimage_file {
-            let record = PreImageRecord {
-                source,
-                page_number,
-                original_data: original_data.to_vec(),
-            };
-            preimage.seek(SeekFrom::End(0))?;
-            preimage.write_all(&record.to_bytes())?;
-            preimage.sync_all()?;
-        }
-        Ok(())
+    /// Write a page, as stored, to its slot: in the index file it is
+    /// already in, the one it was claimed for, or the first
+    pub fn write(&mut self, page_number: u32, offset: u64, data: &[u8]) -> io::Result<()> {
+        let claimed = self.claims.remove(&page_number);
+        let n = self.pages.get(&page_number).copied().or(claimed).unwrap_or(0);
+        self.write_to(n, page_number, offset, data)
     }
 
-    /// Commit transaction (delete pre-image file)
-    pub fn commit_transaction(&mut self) -> io::Result<()> {
-        if self.preimage_file.take().is_some() {
-            let preimage_path = Self::preimage_path(&self.base_path);
-            fs::remove_file(preimage_path)?;
-        }
-
-        // Sync all files
-        if let Some(ref mut data) = self.data_file {
-            data.sync_all()?;
-        }
-        for index_file in &mut self.index_files {
-            if let Some(ref mut f) = index_file {
-                f.sync_all()?;
-            }
-        }
-
+    /// Write a page, as stored, to its slot in index file `n`
+    pub fn write_to(&mut self, n: usize, page_number: u32, offset: u64, data: &[u8]) -> io::Result<()> {
+        let file = &mut self.files[n];
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        self.pages.insert(page_number, n);
         Ok(())
     }
 
-    /// Abort transaction (restore from pre-image)
-    pub fn abort_transaction(&mut self) -> io::Result<()> {
-        let preimage_path = Self::preimage_path(&self.base_path);
-
-        if let Some(mut preimage) = self.preimage_file.take() {
-            // Read all pre-image records and restore
-            preimage.seek(SeekFrom::Start(0))?;
-
-            // Read header
-            let mut header_buf = vec![0u8; 256];
-            preimage.read_exact(&mut header_buf)?;
-            let header = PreImageHeader::from_bytes(&header_buf)?;
-
-            // Read pre-image records
-            let mut all_data = Vec::new();
-            preimage.read_to_end(&mut all_data)?;
-
-            let mut offset = 0;
-            while offset < all_data.len() {
-                if let Ok((record, len)) = PreImageRecord::from_bytes(&all_data[offset..]) {
-                    // Restore the original page
-                    self.write_page_raw(record.source, record.page_number, &record.original_data)?;
-                    offset += len;
-                } else {
-                    break;
-                }
-            }
-
-            // Delete pre-image file
-            drop(preimage);
-            fs::remove_file(preimage_path)?;
+    /// Blank a page's slot, if it is kept in an index file, for one going
+    /// to the .DAT
+    pub fn clear(&mut self, page_number: u32, offset: u64, len: usize) -> io::Result<()> {
+        if let Some(n) = self.pages.remove(&page_number) {
+            let file = &mut self.files[n];
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&vec![0u8; len])?;
         }
-
         Ok(())
     }
 
-    /// Write a page to the specified file (0 = data, 1+ = index)
-    fn write_page_raw(&mut self, source: u8, page_number: u32, data: &[u8]) -> io::Result<()> {
-        let offset = (page_number as u64) * (self.page_size as u64);
-
-        if source == 0 {
-            if let Some(ref mut f) = self.data_file {
-                f.seek(SeekFrom::Start(offset))?;
-                f.write_all(data)?;
-            }
-        } else {
-            let index = (source - 1) as usize;
-            if let Some(Some(ref mut f)) = self.index_files.get_mut(index) {
-                f.seek(SeekFrom::Start(offset))?;
-                f.write_all(data)?;
-            }
+    pub fn sync_all(&self) -> io::Result<()> {
+        for file in &self.files {
+            file.sync_all()?;
         }
-
         Ok(())
     }
+}
 
-    /// Read a data page
-    pub fn read_data_page(&mut self, page_number: u32) -> io::Result<Vec<u8>> {
-        let offset = (page_number as u64) * (self.page_size as u64);
-        let mut buf = vec![0u8; self.page_size as usize];
-
-        if let Some(ref mut f) = self.data_file {
-            f.seek(SeekFrom::Start(offset))?;
-            f.read_exact(&mut buf)?;
-        }
+/// A file's pages read as one image, wherever its layout keeps them, for
+/// the tools that work on files offline. Pages are taken as not sealed or
+/// compressed; the FCR says if they are
+pub struct Image {
+    data: File,
+    index: Vec<File>,
+    pages: HashMap<u32, usize>,
+    page_size: u64,
+    layout: Layout,
+    pos: u64,
+}
 
-        Ok(buf)
+impl Image {
+    /// Image of the file at `data_path`. One whose FCR can't be read is
+    /// taken as a single file, for the caller to find what is wrong
+    pub fn open(data_path: &Path, writable: bool) -> io::Result<Self> {
+        let mut data = OpenOptions::new().read(true).write(writable).open(data_path)?;
+        let mut page = vec![0u8; MAX_PAGE_SIZE as usize];
+        let read = data.read(&mut page)?;
+        let page_size = if read >= 0x0A { u16::from_le_bytes([page[0x08], page[0x09]]) } else { 0 };
+        let fcr = (PAGE_SIZES.contains(&page_size) && read >= page_size as usize)
+            .then(|| FileControlRecord::from_bytes(&page[..page_size as usize]).ok())
+            .flatten();
+        let (layout, page_size, num_keys) = match &fcr {
+            Some(fcr) => (fcr.layout, fcr.page_size as u64, fcr.num_keys as usize),
+            None => (Layout::Single, 512, 0),
+        };
+        let mut index = index_paths(data_path, layout, num_keys)
+            .iter()
+            .map(|path| OpenOptions::new().read(true).write(writable).open(path))
+            .collect::<io::Result<Vec<_>>>()?;
+        let pages = scan(&mut index, page_size, page_size)?;
+        Ok(Image { data, index, pages, page_size, layout, pos: 0 })
     }
 
-    /// Write a data page (with pre-imaging if transaction active)
-    pub fn write_data_page(&mut self, page_number: u32, data: &[u8]) -> io::Result<()> {
-        // Save pre-image if transaction active
-        if self.preimage_file.is_some() {
-            let original = self.read_data_page(page_number)?;
-            self.save_preimage(0, page_number, &original)?;
-        }
-
-        let offset = (page_number as u64) * (self.page_size as u64);
-        if let Some(ref mut f) = self.data_file {
-            f.seek(SeekFrom::Start(offset))?;
-            f.write_all(data)?;
-        }
-
-        Ok(())
+    pub fn layout(&self) -> Layout {
+        self.layout
     }
 
-    /// Read an index page
-    pub fn read_index_page(&mut self, key_number: usize, page_number: u32) -> io::Result<Vec<u8>> {
-        let offset = (page_number as u64) * (self.page_size as u64);
-        let mut buf = vec![0u8; self.page_size as usize];
-
-        if let Some(Some(ref mut f)) = self.index_files.get_mut(key_number) {
-            f.seek(SeekFrom::Start(offset))?;
-            f.read_exact(&mut buf)?;
-        }
-
-        Ok(buf)
+    /// The index file a page is kept in, if not the .DAT
+    pub fn file_of(&self, page_number: u32) -> Option<usize> {
+        self.pages.get(&page_number).copied()
     }
 
-    /// Write an index page (with pre-imaging if transaction active)
-    pub fn write_index_page(&mut self, key_number: usize, page_number: u32, data: &[u8]) -> io::Result<()> {
-        // Save pre-image if transaction active
-        if self.preimage_file.is_some() {
-            let original = self.read_index_page(key_number, page_number)?;
-            self.save_preimage((key_number + 1) as u8, page_number, &original)?;
+    /// Write a page where it belongs: an index page of a file laid out
+    /// apart to index file `index_file`, leaving its slot in the .DAT
+    /// blank, any other page to the .DAT
+    pub fn write_page(&mut self, page_number: u32, index_file: usize, data: &[u8]) -> io::Result<()> {
+        let offset = page_number as u64 * self.page_size;
+        let apart = self.layout != Layout::Single && is_index_page(page_number, data);
+        if let Some(n) = self.pages.get(&page_number).copied().filter(|&n| !apart || n != index_file) {
+            self.index[n].seek(SeekFrom::Start(offset))?;
+            self.index[n].write_all(&vec![0u8; data.len()])?;
+            self.pages.remove(&page_number);
         }
-
-        let offset = (page_number as u64) * (self.page_size as u64);
-        if let Some(Some(ref mut f)) = self.index_files.get_mut(key_number) {
-            f.seek(SeekFrom::Start(offset))?;
-            f.write_all(data)?;
+        self.data.seek(SeekFrom::Start(offset))?;
+        if !apart {
+            return self.data.write_all(data);
         }
-
+        self.data.write_all(&vec![0u8; data.len()])?;
+        self.index[index_file].seek(SeekFrom::Start(offset))?;
+        self.index[index_file].write_all(data)?;
+        self.pages.insert(page_number, index_file);
         Ok(())
     }
 
-    /// Update FCR and write to disk
-    pub fn update_fcr(&mut self) -> io::Result<()> {
-        if let Some(ref fcr) = self.fcr {
-            let fcr_bytes = fcr.to_bytes();
-            if let Some(ref mut f) = self.data_file {
-                f.seek(SeekFrom::Start(0))?;
-                f.write_all(&fcr_bytes)?;
+    /// Cut the file to `pages` pages
+    pub fn set_pages(&mut self, pages: u32) -> io::Result<()> {
+        let len = pages as u64 * self.page_size;
+        self.data.set_len(len)?;
+        for file in &mut self.index {
+            if file.seek(SeekFrom::End(0))? > len {
+                file.set_len(len)?;
             }
         }
+        self.pages.retain(|&page, _| page < pages);
         Ok(())
     }
 
-    /// Update index header and write to disk
-    pub fn update_index_header(&mut self, key_number: usize) -> io::Result<()> {
-        if let Some(Some(ref header)) = self.index_headers.get(key_number) {
-            let header_bytes = header.to_bytes(self.page_size);
-            if let Some(Some(ref mut f)) = self.index_files.get_mut(key_number) {
-                f.seek(SeekFrom::Start(0))?;
-                f.write_all(&header_bytes)?;
-            }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.data.flush()?;
+        for file in &mut self.index {
+            file.flush()?;
         }
         Ok(())
     }
+}
 
-    /// Close all files
-    pub fn close(&mut self) -> io::Result<()> {
-        // Sync and close data file
-        if let Some(ref mut f) = self.data_file {
-            f.sync_all()?;
-        }
-        self.data_file = None;
-
-        // Sync and close index files
-        for index_file in &mut self.index_files {
-            if let Some(ref mut f) = index_file {
-                f.sync_all()?;
-            }
-            *index_file = None;
-        }
-
-        // If there's an active transaction, abort it
-        if self.preimage_file.is_some() {
-            self.abort_transaction()?;
-        }
+impl Read for Image {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let page = (self.pos / self.page_size) as u32;
+        let len = buf.len().min((self.page_size - self.pos % self.page_size) as usize);
+        let file = match self.pages.get(&page) {
+            Some(&n) => &mut self.index[n],
+            None => &mut self.data,
+        };
+        file.seek(SeekFrom::Start(self.pos))?;
+        let read = file.read(&mut buf[..len])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
 
-        Ok(())
+impl Seek for Image {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(at) => at,
+            SeekFrom::End(by) => self.data.seek(SeekFrom::End(0))?.saturating_add_signed(by),
+            SeekFrom::Current(by) => self.pos.saturating_add_signed(by),
+        };
+        Ok(self.pos)
     }
 }
 
-impl Drop for BtrieveFileSet {
-    fn drop(&mut self) {
-        let _ = self.close();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_index_pages_found_again() {
+        let dir = tempdir().unwrap();
+        let paths = index_paths(&dir.path().join("TEST.DAT"), Layout::PerKey, 2);
+        let mut index = IndexFile::create(&paths).unwrap();
+        index.claim(2, 1);
+        index.write(2, 512 + 512, &[7u8; 512]).unwrap();
+        index.write(4, 512 + 3 * 512, &[9u8; 512]).unwrap();
+        index.write(3, 512 + 2 * 512, &[1u8; 512]).unwrap();
+        index.clear(3, 512 + 2 * 512, 512).unwrap();
+        drop(index);
+
+        let mut index = IndexFile::open(&paths, true, 512, 512).unwrap();
+        assert_eq!(
+            (1..=4).map(|n| index.file_of(n)).collect::<Vec<_>>(),
+            [None, Some(1), None, Some(0)]
+        );
+        assert_eq!(index.read(4, 512 + 3 * 512, 512).unwrap(), vec![9u8; 512]);
+    }
+
+    #[test]
+    fn test_page_routing() {
+        let mut data = vec![0u8; 512];
+        assert!(!is_index_page(5, &data));
+        data[0] = PageType::Data as u8;
+        assert!(!is_index_page(5, &data));
+        data[0] = 0;
+        data[2] = 5;
+        assert!(is_index_page(5, &data));
+        assert!(!is_index_page(0, &data));
     }
 }
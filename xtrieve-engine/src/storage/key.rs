@@ -3,15 +3,17 @@
 //! Btrieve supports up to 24 keys (indexes) per file, each with specific
 //! type information and flags. Keys can be simple or segmented (compound).
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt};
 use std::cmp::Ordering;
 use std::io::{self, Cursor};
 
+use super::collate::{self, CodePage, Collation, ACS_UPPER_850};
+
 /// Key data types supported by Btrieve 5.1
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum KeyType {
-    /// ASCII string, case-sensitive binary comparison
+    /// ASCII string, compared byte by byte unless the key names a collation
     String = 0,
     /// Signed integer (1, 2, 4, or 8 bytes)
     Integer = 1,
@@ -39,6 +41,8 @@ pub enum KeyType {
     UnsignedBinary = 14,
     /// Auto-incrementing integer
     AutoIncrement = 15,
+    /// UTF-8 string, compared after NFC normalization (Xtrieve extension)
+    Utf8 = 64,
 }
 
 impl KeyType {
@@ -58,6 +62,7 @@ impl KeyType {
             11 => KeyType::ZString,
             14 => KeyType::UnsignedBinary,
             15 => KeyType::AutoIncrement,
+            64 => KeyType::Utf8,
             _ => KeyType::String, // Default to string for unknown types
         }
     }
@@ -87,6 +92,44 @@ bitflags::bitflags! {
         const EXTENDED_TYPE = 0x0100;
         /// Manual key number assignment
         const MANUAL = 0x0200;
+        /// Case-insensitive string compare (Xtrieve extension)
+        const NO_CASE = 0x0400;
+    }
+}
+
+/// How a Create buffer lays out its file and key specifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecLayout {
+    /// Xtrieve's own, as `KeySpec::to_bytes` writes it and Stat returns
+    /// it: a 16-byte file spec with the flags at byte 8, 0-based key
+    /// positions and the ACS number at byte 12 of a key spec
+    Xtrieve,
+    /// Btrieve's: a 16-byte file spec with the flags at byte 10, 1-based
+    /// key positions and the ACS number at byte 15
+    Classic,
+    /// A 10-byte file spec without flags, and the key type and null value
+    /// at bytes 6 and 7 of a key spec: what the C, Go, JavaScript and PHP
+    /// SDKs send
+    Compact,
+}
+
+impl SpecLayout {
+    /// Layout named `xtrieve`, `classic` or `compact`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "xtrieve" => Some(SpecLayout::Xtrieve),
+            "classic" | "btrieve" => Some(SpecLayout::Classic),
+            "compact" => Some(SpecLayout::Compact),
+            _ => None,
+        }
+    }
+
+    /// Bytes of the file spec before the first key spec
+    pub fn file_spec_size(self) -> usize {
+        match self {
+            SpecLayout::Compact => 10,
+            _ => 16,
+        }
     }
 }
 
@@ -145,6 +188,61 @@ impl KeySpec {
         })
     }
 
+    /// Parse a key specification laid out as `layout`. A Btrieve spec
+    /// can't give position 0, its positions being 1-based
+    pub fn from_layout(data: &[u8], layout: SpecLayout) -> io::Result<Self> {
+        if data.len() < Self::SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Key spec too short"));
+        }
+        let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        let flags = KeyFlags::from_bits_truncate(u16_at(4));
+        match layout {
+            SpecLayout::Xtrieve => Self::from_bytes(data),
+            SpecLayout::Classic => Ok(KeySpec {
+                position: u16_at(0).checked_sub(1).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Key position 0 in a Btrieve key spec")
+                })?,
+                length: u16_at(2),
+                flags,
+                key_type: KeyType::from_raw(data[10]),
+                null_value: data[11],
+                acs_number: data[15],
+                unique_count: u32::from_le_bytes([data[6], data[7], data[8], data[9]]),
+            }),
+            SpecLayout::Compact => Ok(KeySpec {
+                position: u16_at(0),
+                length: u16_at(2),
+                flags,
+                key_type: KeyType::from_raw(data[6]),
+                null_value: data[7],
+                acs_number: 0,
+                unique_count: 0,
+            }),
+        }
+    }
+
+    /// Serialize key specification laid out as `layout`
+    pub fn to_layout(&self, layout: SpecLayout) -> Vec<u8> {
+        let mut buf = match layout {
+            SpecLayout::Xtrieve => return self.to_bytes(),
+            _ => vec![0u8; Self::SIZE],
+        };
+        buf[2..4].copy_from_slice(&self.length.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.flags.bits().to_le_bytes());
+        if layout == SpecLayout::Classic {
+            buf[0..2].copy_from_slice(&(self.position + 1).to_le_bytes());
+            buf[6..10].copy_from_slice(&self.unique_count.to_le_bytes());
+            buf[10] = self.key_type as u8;
+            buf[11] = self.null_value;
+            buf[15] = self.acs_number;
+        } else {
+            buf[0..2].copy_from_slice(&self.position.to_le_bytes());
+            buf[6] = self.key_type as u8;
+            buf[7] = self.null_value;
+        }
+        buf
+    }
+
     /// Serialize key specification to bytes
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = vec![0u8; Self::SIZE];
@@ -185,6 +283,24 @@ impl KeySpec {
         self.flags.contains(KeyFlags::NULL)
     }
 
+    /// How string values of this key compare: a built-in ACS, case folded
+    /// for NO_CASE (through code page 850 if the ACS number names it), or
+    /// byte by byte
+    pub fn collation(&self) -> Collation {
+        if self.flags.contains(KeyFlags::ALT_SEQUENCE) {
+            if let Some(collation) = Collation::from_acs(self.acs_number) {
+                return collation;
+            }
+        }
+        if self.flags.contains(KeyFlags::NO_CASE) {
+            return Collation::NoCase(match self.acs_number {
+                ACS_UPPER_850 => CodePage::Cp850,
+                _ => CodePage::Cp437,
+            });
+        }
+        Collation::Binary
+    }
+
     /// Extract key value from a record
     pub fn extract_key(&self, record: &[u8]) -> Vec<u8> {
         let start = self.position as usize;
@@ -206,10 +322,8 @@ impl KeySpec {
     /// Compare two key values according to key type
     pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
         let result = match self.key_type {
-            KeyType::String | KeyType::ZString => {
-                // Binary comparison for strings
-                a.cmp(b)
-            }
+            KeyType::String | KeyType::ZString => self.collation().compare(a, b),
+            KeyType::Utf8 => collate::compare_utf8(a, b, self.flags.contains(KeyFlags::NO_CASE)),
             KeyType::Integer => self.compare_integer(a, b),
             KeyType::UnsignedBinary | KeyType::AutoIncrement => self.compare_unsigned(a, b),
             KeyType::Float => self.compare_float(a, b),
@@ -219,7 +333,7 @@ impl KeySpec {
                 let len_b = b.first().copied().unwrap_or(0) as usize;
                 let a_data = a.get(1..=len_a).unwrap_or(&[]);
                 let b_data = b.get(1..=len_b).unwrap_or(&[]);
-                a_data.cmp(b_data)
+                self.collation().compare(a_data, b_data)
             }
             _ => a.cmp(b), // Default binary comparison
         };
@@ -444,4 +558,54 @@ mod tests {
         let key = spec.extract_key(record);
         assert_eq!(&key, b" WO");
     }
+
+    #[test]
+    fn test_no_case_key() {
+        let mut spec = KeySpec {
+            position: 0,
+            length: 5,
+            flags: KeyFlags::NO_CASE,
+            key_type: KeyType::String,
+            null_value: 0,
+            acs_number: 0,
+            unique_count: 0,
+        };
+        assert_eq!(spec.compare(b"smith", b"SMITH"), Ordering::Equal);
+        assert_eq!(spec.compare(b"jones", b"SMITH"), Ordering::Less);
+
+        // ACS 2 folds through code page 850, where "à" has a capital
+        spec.acs_number = 2;
+        assert_eq!(spec.compare(b"\x85", b"\xB7"), Ordering::Equal);
+
+        // Numbers aren't folded
+        spec.key_type = KeyType::UnsignedBinary;
+        assert_eq!(spec.compare(b"a", b"A"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_spec_layouts() {
+        let spec = KeySpec {
+            position: 4,
+            length: 10,
+            flags: KeyFlags::DUPLICATES | KeyFlags::ALT_SEQUENCE,
+            key_type: KeyType::ZString,
+            null_value: b' ',
+            acs_number: 2,
+            unique_count: 0,
+        };
+        for layout in [SpecLayout::Xtrieve, SpecLayout::Classic] {
+            let parsed = KeySpec::from_layout(&spec.to_layout(layout), layout).unwrap();
+            assert_eq!(parsed.to_bytes(), spec.to_bytes(), "{:?}", layout);
+        }
+        let compact = KeySpec::from_layout(&spec.to_layout(SpecLayout::Compact), SpecLayout::Compact).unwrap();
+        assert_eq!((compact.position, compact.key_type, compact.null_value), (4, KeyType::ZString, b' '));
+
+        // Btrieve's positions start at 1
+        let classic = spec.to_layout(SpecLayout::Classic);
+        assert_eq!(&classic[0..2], &5u16.to_le_bytes());
+        assert_eq!((classic[10], classic[15]), (11, 2));
+        let mut first = classic.clone();
+        first[0..2].copy_from_slice(&0u16.to_le_bytes());
+        assert!(KeySpec::from_layout(&first, SpecLayout::Classic).is_err());
+    }
 }
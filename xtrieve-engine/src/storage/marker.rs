@@ -0,0 +1,127 @@
+//! Lock files - a process's claim on the data files it serves
+//!
+//! Beside each data file it has open, a process holding lock files keeps
+//! `<name>.LCK` with an exclusive OS lock on it and its process id inside.
+//! Another daemon pointed at the same directory finds the lock taken and
+//! refuses the file, so daemons sharing a directory (an NFS or SMB share
+//! among them) each serve files the others don't. The OS drops the lock
+//! when its holder exits, crashed or not, so a lock file left behind
+//! claims nothing: the next process takes it over, and the startup
+//! self-check removes it.
+//!
+//! Claims are taken and given up under the directory's arbitration file,
+//! `XTRIEVE.ARB`, locked for the moment it takes. A lock file is removed
+//! when its claim ends, and without the arbitration no process could tell
+//! the lock file it is about to lock from one just removed.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Extension of a data file's lock file
+pub const MARKER_EXT: &str = "LCK";
+
+/// Name of the arbitration file in each directory with lock files
+pub const ARBITER_NAME: &str = "XTRIEVE.ARB";
+
+/// A data file's lock file, held: the claim lasts as long as this does,
+/// and the lock file is removed with it
+#[derive(Debug)]
+pub struct Marker {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+/// Why a lock file couldn't be taken
+#[derive(Debug)]
+pub enum MarkerError {
+    /// Another process holds it; what it wrote there, like "pid 1234"
+    Held(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for MarkerError {
+    fn from(e: io::Error) -> Self {
+        MarkerError::Io(e)
+    }
+}
+
+impl Marker {
+    /// Lock file of the data file at `data_path`
+    pub fn path_of(data_path: &Path) -> PathBuf {
+        data_path.with_extension(MARKER_EXT)
+    }
+
+    /// Take the lock file of the data file at `data_path`, creating it if
+    /// there is none
+    pub fn take(data_path: &Path) -> Result<Self, MarkerError> {
+        let path = Self::path_of(data_path);
+        let _arbiter = arbitrate(&path)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)?;
+                return Err(MarkerError::Held(holder.trim().to_string()));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "pid {}", std::process::id())?;
+        Ok(Marker { path, file: Some(file) })
+    }
+}
+
+impl Drop for Marker {
+    fn drop(&mut self) {
+        // Removed while still locked, so no one takes the file going away
+        let _arbiter = arbitrate(&self.path);
+        let _ = fs::remove_file(&self.path);
+        self.file = None;
+    }
+}
+
+/// Lock the arbitration file of the directory `path` is in, until the
+/// file returned is dropped
+fn arbitrate(path: &Path) -> io::Result<File> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(ARBITER_NAME))?;
+    file.lock()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_held_until_dropped() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT");
+        let marker = Marker::take(&path).unwrap();
+        match Marker::take(&path) {
+            Err(MarkerError::Held(holder)) => assert_eq!(holder, format!("pid {}", std::process::id())),
+            other => panic!("taken twice: {:?}", other),
+        }
+        drop(marker);
+        assert!(!dir.path().join("CUST.LCK").exists());
+        let _marker = Marker::take(&path).unwrap();
+        assert!(dir.path().join("CUST.LCK").exists());
+    }
+}
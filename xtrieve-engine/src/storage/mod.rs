@@ -4,20 +4,40 @@
 //! - Page I/O
 //! - FCR (File Control Record) parsing
 //! - Key specifications
+//! - String key collations
 //! - B+ tree index structures
 //! - Record management
-//! - Separated file management (.DAT, .IX#, .PRE)
+//! - File layouts (index pages in the .DAT or in a companion .IX)
+//! - Page encryption at rest
+//! - Page compression
+//! - Recycle bins of deleted records
+//! - Roll-forward logs of committed changes
+//! - The daemon's startup self-check
+//! - Lock files claiming the data files a process serves
 
 pub mod page;
 pub mod fcr;
 pub mod key;
+pub mod collate;
 pub mod record;
 pub mod btree;
 pub mod files;
+pub mod check;
+pub mod selfcheck;
+pub mod marker;
+pub mod rebuild;
+pub mod crypt;
+pub mod compress;
+pub mod recycle;
+pub mod rollfwd;
 
 pub use page::{Page, PageType, PAGE_SIZES};
 pub use fcr::FileControlRecord;
-pub use key::{KeySpec, KeyType, KeyFlags};
+pub use key::{KeySpec, KeyType, KeyFlags, SpecLayout};
+pub use collate::{CodePage, Collation};
 pub use record::Record;
 pub use btree::{BTree, LeafEntry};
-pub use files::{BtrieveFileSet, IndexFileHeader, PreImageRecord, PreImageHeader};
+pub use files::{IndexFile, Layout};
+pub use check::{check_file, check_fcr_file, CheckReport};
+pub use selfcheck::{check_dir, FileCheck, Finding};
+pub use rebuild::{rebuild_file, RebuildReport};
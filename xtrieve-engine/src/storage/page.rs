@@ -3,7 +3,7 @@
 //! Btrieve files are organized into fixed-size pages. The page size is
 //! set at file creation and stored in the FCR (page 0).
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt};
 use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 
 /// Valid page sizes in Btrieve 5.1
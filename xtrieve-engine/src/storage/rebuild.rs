@@ -0,0 +1,294 @@
+//! Offline index rebuild
+//!
+//! Drops the index pages of one key, or of every key, and builds them
+//! again from the records on the data pages with `btree::bulk_load`.
+//! Freed pages are reused before the file grows, and free pages left at
+//! the end of the file are cut off. The file must not be open elsewhere.
+//!
+//! A file laid out apart gets its rebuilt pages in its index files; with
+//! an index file per key, rebuilding one key rewrites only that key's
+//! file and the FCR.
+
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::error::{BtrieveError, BtrieveResult, StatusCode};
+
+use super::btree::{bulk_load, LeafEntry};
+use super::check::{check, Area};
+use super::fcr::FileControlRecord;
+use super::files::{Image, Layout};
+use super::record::{DataPage, RecordAddress};
+
+/// What a rebuild did
+#[derive(Debug, Clone, Default)]
+pub struct RebuildReport {
+    /// Per rebuilt key: key number, entries built, leaf pages written
+    pub keys: Vec<(usize, u64, u32)>,
+    /// Index pages dropped
+    pub dropped_pages: u32,
+    /// File size in bytes before and after
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Rebuild the index of `key_number`, or of every key when `None`
+///
+/// Refuses files whose data pages fail the integrity check, since the
+/// index would be built from damaged records. A key that must be unique
+/// but repeats a value fails with `DuplicateKey` before anything is
+/// written.
+pub fn rebuild_file(path: &Path, key_number: Option<usize>) -> BtrieveResult<RebuildReport> {
+    let mut image = Image::open(path, true).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            BtrieveError::Status(StatusCode::FileNotFound)
+        } else {
+            BtrieveError::Io(e)
+        }
+    })?;
+    rebuild(&mut image, key_number)
+}
+
+/// Rebuild indexes in an open file
+pub fn rebuild(file: &mut Image, key_number: Option<usize>) -> BtrieveResult<RebuildReport> {
+    let checked = check(&mut *file)?;
+    if checked.page_size == 0 {
+        return Err(BtrieveError::InvalidFormat(checked.problems[0].message.clone()));
+    }
+    if let Some(problem) = checked.problems_in(Area::Data).next() {
+        return Err(BtrieveError::InvalidFormat(format!("cannot rebuild from damaged data pages: {}", problem)));
+    }
+
+    let page_size = checked.page_size;
+    let mut fcr_page = vec![0u8; page_size as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut fcr_page)?;
+    let fcr = FileControlRecord::from_bytes(&fcr_page)?;
+    let keys: Vec<usize> = match key_number {
+        Some(k) if k < fcr.keys.len() => vec![k],
+        Some(_) => return Err(BtrieveError::Status(StatusCode::InvalidKeyNumber)),
+        None => (0..fcr.keys.len()).collect(),
+    };
+
+    // Build every key in memory first so a failure leaves the file alone
+    let records = read_records(file, page_size, &fcr_page)?;
+    let mut built = Vec::new();
+    for &k in &keys {
+        let spec = &fcr.keys[k];
+        let mut entries = Vec::with_capacity(records.len());
+        let mut seen = HashSet::new();
+        for (offset, record) in &records {
+            let key = spec.extract_key(record);
+            if spec.is_null_key(&key) {
+                continue;
+            }
+            if !spec.allows_duplicates() && !seen.insert(key.clone()) {
+                return Err(BtrieveError::Status(StatusCode::DuplicateKey));
+            }
+            // The records don't say which duplicate came first, so
+            // rebuilt duplicates fall back to address order
+            entries.push(LeafEntry { key, record_address: RecordAddress::new(*offset, 0), dup_sequence: 0 });
+        }
+        built.push((k, entries));
+    }
+
+    // Pages whose key can't be told are dropped only when every key is rebuilt
+    let mut free: Vec<u32> = checked.unused_pages.clone();
+    let mut dropped = 0;
+    for &(page, owner) in &checked.index_page_keys {
+        // Which key a page of a per-key file belongs to is known
+        let owner = match file.layout() {
+            Layout::PerKey => file.file_of(page),
+            Layout::Single | Layout::Split => owner,
+        };
+        let drop = match (owner, key_number) {
+            (_, None) => true,
+            (Some(owner), Some(k)) => owner == k,
+            (None, Some(_)) => false,
+        };
+        if drop {
+            file.write_page(page, 0, &vec![0u8; page_size as usize])?;
+            free.push(page);
+            dropped += 1;
+        }
+    }
+    free.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut pages = checked.pages;
+    let mut report = RebuildReport {
+        dropped_pages: dropped,
+        size_before: checked.pages as u64 * page_size as u64,
+        ..Default::default()
+    };
+    for (k, entries) in built {
+        let count = entries.len() as u64;
+        let leaves = bulk_load(&fcr.keys[k], entries, page_size, || {
+            free.pop().unwrap_or_else(|| {
+                pages += 1;
+                pages - 1
+            })
+        });
+        for leaf in &leaves {
+            file.write_page(leaf.page_number, file.layout().index_file_of(k), &leaf.to_bytes(page_size))?;
+        }
+        report.keys.push((k, count, leaves.len() as u32));
+    }
+
+    // Cut free pages off the end, then record the page count in the FCR
+    let mut free: HashSet<u32> = free.into_iter().collect();
+    while pages > 1 && free.remove(&(pages - 1)) {
+        pages -= 1;
+    }
+    file.set_pages(pages)?;
+    fcr_page[0x20..0x24].copy_from_slice(&pages.to_le_bytes());
+    file.write_page(0, 0, &fcr_page)?;
+    file.flush()?;
+    report.size_after = pages as u64 * page_size as u64;
+    Ok(report)
+}
+
+/// Live records by file offset, following the data page chain the check
+/// has already verified
+fn read_records<F: Read + Seek>(file: &mut F, page_size: u16, fcr_page: &[u8]) -> BtrieveResult<Vec<(u32, Vec<u8>)>> {
+    let mut records = Vec::new();
+    let mut next = u32::from_le_bytes([fcr_page[0x24], fcr_page[0x25], fcr_page[0x26], fcr_page[0x27]]);
+    while next != 0 {
+        let mut data = vec![0u8; page_size as usize];
+        file.seek(SeekFrom::Start(next as u64 * page_size as u64))?;
+        file.read_exact(&mut data)?;
+        let page = DataPage::from_bytes(next, data)?;
+        for slot in 0..page.slot_count {
+            if let Some(record) = page.get_record(slot) {
+                let offset = next * page_size as u32 + page.slots[slot as usize].offset as u32;
+                records.push((offset, record.to_vec()));
+            }
+        }
+        next = page.next_page;
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::check::check_file;
+    use crate::storage::files::index_paths;
+    use crate::storage::key::{KeyFlags, KeySpec, KeyType};
+
+    const PAGE: u16 = 512;
+
+    fn key(position: u16, flags: KeyFlags) -> KeySpec {
+        KeySpec {
+            position,
+            length: 4,
+            flags,
+            key_type: KeyType::UnsignedBinary,
+            null_value: 0,
+            acs_number: 0,
+            unique_count: 0,
+        }
+    }
+
+    /// 60 records on three data pages, two index pages left over from
+    /// inserts that wrote record offset 0, and a free page at the end. With
+    /// an index file per key, the index pages are key 0's
+    fn broken_file(path: &Path, layout: Layout) {
+        let mut fcr = FileControlRecord::new(16, PAGE, vec![key(0, KeyFlags::empty()), key(4, KeyFlags::DUPLICATES)]);
+        fcr.layout = layout;
+        fcr.num_records = 60;
+        fcr.num_pages = 7;
+        fcr.first_data_page = 1;
+
+        let mut pages: Vec<_> = (1..=3).map(|n| DataPage::new(n, PAGE)).collect();
+        for n in 0..2 {
+            pages[n].set_next_page(n as u32 + 2);
+            pages[n + 1].set_prev_page(n as u32 + 1);
+        }
+        for id in 0..60u32 {
+            let mut record = vec![0u8; 16];
+            record[..4].copy_from_slice(&(1000 - id).to_le_bytes());
+            record[4..8].copy_from_slice(&(id % 7).to_le_bytes());
+            pages[(id / 20) as usize].insert_record(&record).unwrap();
+        }
+
+        let mut stale = crate::storage::btree::IndexNode::new_leaf(4, key(0, KeyFlags::empty()), PAGE);
+        stale.insert_leaf_entry(
+            LeafEntry { key: vec![1, 0, 0, 0], record_address: RecordAddress::new(0, 0), dup_sequence: 0 },
+            false,
+        );
+
+        let mut image = fcr.to_bytes();
+        for page in &pages {
+            image.extend(page.to_bytes());
+        }
+        let mut index = stale.to_bytes(PAGE);
+        stale.page_number = 5;
+        index.extend(stale.to_bytes(PAGE));
+        match layout {
+            Layout::Single => image.extend(index),
+            _ => {
+                let paths = index_paths(path, layout, 2);
+                let mut key0 = vec![0u8; image.len()];
+                key0.extend(index);
+                std::fs::write(&paths[0], key0).unwrap();
+                std::fs::write(&paths[1], b"").unwrap();
+                image.extend(vec![0u8; 2 * PAGE as usize]);
+            }
+        }
+        image.extend(vec![0u8; PAGE as usize]);
+        std::fs::write(path, image).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild_all_keys() {
+        let path = std::env::temp_dir().join(format!("xtrieve_rebuild_{}.dat", std::process::id()));
+        broken_file(&path, Layout::Single);
+        assert!(!check_file(&path).unwrap().is_clean());
+
+        let report = rebuild_file(&path, None).unwrap();
+        assert_eq!(report.dropped_pages, 2);
+        // (512 - 16) / 12 = 41 entries fit, 40 are loaded per leaf
+        assert_eq!(report.keys, [(0, 60, 2), (1, 60, 2)]);
+        assert_eq!((report.size_before, report.size_after), (7 * 512, 8 * 512));
+
+        let checked = check_file(&path).unwrap();
+        assert!(checked.is_clean(), "{:?}", checked.problems);
+        assert_eq!(checked.key_entries, [60, 60]);
+
+        // One key again: its two pages are reused, nothing else moves
+        let report = rebuild_file(&path, Some(1)).unwrap();
+        assert_eq!((report.dropped_pages, report.size_after), (2, 8 * 512));
+        assert!(check_file(&path).unwrap().is_clean());
+
+        assert!(matches!(
+            rebuild_file(&path, Some(2)),
+            Err(BtrieveError::Status(StatusCode::InvalidKeyNumber))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT");
+        broken_file(&path, Layout::PerKey);
+        let paths = index_paths(&path, Layout::PerKey, 2);
+        let read = |path: &Path| std::fs::read(path).unwrap();
+        let (data, key0) = (read(&path), read(&paths[0]));
+
+        // Key 1's file is written; the data pages and key 0's file aren't
+        let report = rebuild_file(&path, Some(1)).unwrap();
+        assert_eq!((report.keys.as_slice(), report.dropped_pages), (&[(1, 60, 2)][..], 0));
+        assert_eq!(read(&paths[0]), key0);
+        assert_eq!(read(&path)[PAGE as usize..4 * PAGE as usize], data[PAGE as usize..4 * PAGE as usize]);
+        assert_eq!(check_file(&path).unwrap().key_entries[1], 60);
+
+        // Key 0's stale pages were in its file alone
+        let report = rebuild_file(&path, Some(0)).unwrap();
+        assert_eq!(report.dropped_pages, 2);
+        let checked = check_file(&path).unwrap();
+        assert!(checked.is_clean(), "{:?}", checked.problems);
+        assert_eq!(checked.key_entries, [60, 60]);
+    }
+}
@@ -4,10 +4,11 @@
 //! that tracks the position and status of records within the page.
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::io::{self, Cursor, Write};
+use std::io::{self, Cursor};
 
-/// Physical address of a record (page number + slot)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Physical address of a record (page number + slot), ordered by page
+/// then slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RecordAddress {
     /// Page number containing the record
     pub page: u32,
@@ -43,17 +44,16 @@ impl RecordAddress {
     }
 
     /// Convert to a 4-byte position (as used by Get Position operation)
-    /// With Btrieve 5.1 format, slot contains the absolute file offset
+    /// With Btrieve 5.1 format, page contains the absolute file offset
     pub fn to_position(&self, _page_size: u16) -> u32 {
-        // In Btrieve 5.1 format, slot contains the file offset
-        self.slot as u32
+        self.page
     }
 
     /// Convert from a 4-byte position
     /// With Btrieve 5.1 format, position is the absolute file offset
     pub fn from_position(position: u32) -> Self {
-        // Store file offset in slot field (page=0 indicates file offset format)
-        RecordAddress { page: 0, slot: position as u16 }
+        // Store file offset in page field (slot=0 indicates file offset format)
+        RecordAddress { page: position, slot: 0 }
     }
 }
 
@@ -203,8 +203,14 @@ impl DataPage {
         let first_free_slot = cursor.read_u16::<LittleEndian>()?;
 
         // Read slot directory from end of page
+        let slot_dir_start = (page_size as usize)
+            .checked_sub(slot_count as usize * SlotEntry::SIZE)
+            .filter(|&start| start >= Self::HEADER_SIZE)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Slot directory larger than the page",
+            ))?;
         let mut slots = Vec::with_capacity(slot_count as usize);
-        let slot_dir_start = page_size as usize - (slot_count as usize * SlotEntry::SIZE);
 
         for i in 0..slot_count as usize {
             let slot_offset = slot_dir_start + (i * SlotEntry::SIZE);
@@ -500,17 +506,18 @@ impl DataPage {
     /// Update record in place (must be same length or smaller)
     pub fn update_record(&mut self, slot: u16, record_data: &[u8]) -> bool {
         if let Some(entry) = self.slots.get(slot as usize) {
-            if entry.is_in_use() && !entry.is_deleted() {
-                if record_data.len() <= entry.length as usize {
-                    let start = entry.offset as usize;
-                    self.data[start..start + record_data.len()].copy_from_slice(record_data);
-                    // Pad with zeros if new record is shorter
-                    if record_data.len() < entry.length as usize {
-                        let end = start + entry.length as usize;
-                        self.data[start + record_data.len()..end].fill(0);
-                    }
-                    return true;
+            if entry.is_in_use()
+                && !entry.is_deleted()
+                && record_data.len() <= entry.length as usize
+            {
+                let start = entry.offset as usize;
+                self.data[start..start + record_data.len()].copy_from_slice(record_data);
+                // Pad with zeros if new record is shorter
+                if record_data.len() < entry.length as usize {
+                    let end = start + entry.length as usize;
+                    self.data[start + record_data.len()..end].fill(0);
                 }
+                return true;
             }
         }
         false
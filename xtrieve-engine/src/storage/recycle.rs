@@ -0,0 +1,176 @@
+//! Recycle bin for deleted records
+//!
+//! A file created with the recycle flag keeps the records Delete removes in
+//! a companion file beside it, named like the file with the extension RCY,
+//! so an operator can bring back a record deleted by mistake. Entries are
+//! appended in the order records were deleted:
+//!
+//! ```text
+//! [length:4][deleted at:8][record]
+//! ```
+//!
+//! The deletion time is in seconds since the Unix epoch. For an encrypted
+//! file everything after the length is sealed with the file's key, so the
+//! bin doesn't give away what the file protects. The bin only grows until
+//! records are undeleted or it is removed by hand.
+//!
+//! Recycle bins are an Xtrieve extension: DOS Btrieve ignores them.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::crypt::PageCipher;
+
+/// Page number bound into sealed entries, which no page has
+const ENTRY_SEAL: u32 = u32::MAX;
+
+/// A deleted record in a recycle bin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tombstone {
+    /// Seconds since the Unix epoch
+    pub deleted_at: u64,
+    pub record: Vec<u8>,
+}
+
+/// The recycle bin of one file
+#[derive(Debug, Clone)]
+pub struct RecycleBin {
+    path: PathBuf,
+    cipher: Option<PageCipher>,
+}
+
+impl RecycleBin {
+    /// Bin of the file at `data_path`, its entries sealed with `cipher`
+    pub fn new(data_path: &Path, cipher: Option<PageCipher>) -> Self {
+        RecycleBin { path: data_path.with_extension("RCY"), cipher }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn encode(&self, tombstone: &Tombstone) -> Vec<u8> {
+        let mut payload = tombstone.deleted_at.to_le_bytes().to_vec();
+        payload.extend_from_slice(&tombstone.record);
+        if let Some(cipher) = &self.cipher {
+            payload = cipher.seal(ENTRY_SEAL, &payload);
+        }
+        let mut entry = (payload.len() as u32).to_le_bytes().to_vec();
+        entry.extend_from_slice(&payload);
+        entry
+    }
+
+    fn decode(&self, payload: &[u8]) -> io::Result<Tombstone> {
+        let payload = match &self.cipher {
+            Some(cipher) => cipher.open(ENTRY_SEAL, payload)?,
+            None => payload.to_vec(),
+        };
+        if payload.len() < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "recycle bin entry too short"));
+        }
+        let mut deleted_at = [0u8; 8];
+        deleted_at.copy_from_slice(&payload[..8]);
+        Ok(Tombstone { deleted_at: u64::from_le_bytes(deleted_at), record: payload[8..].to_vec() })
+    }
+
+    /// Add records deleted just now
+    pub fn append(&self, records: &[Vec<u8>]) -> io::Result<()> {
+        let deleted_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let entries: Vec<u8> = records
+            .iter()
+            .flat_map(|record| self.encode(&Tombstone { deleted_at, record: record.clone() }))
+            .collect();
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(&entries)?;
+        file.sync_data()
+    }
+
+    /// Every record in the bin, oldest first. An entry cut short by a crash
+    /// ends the list
+    pub fn entries(&self) -> io::Result<Vec<Tombstone>> {
+        let mut data = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut file) => file.read_to_end(&mut data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while at + 4 <= data.len() {
+            let len = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize;
+            let Some(payload) = data.get(at + 4..at + 4 + len) else {
+                break;
+            };
+            entries.push(self.decode(payload)?);
+            at += 4 + len;
+        }
+        Ok(entries)
+    }
+
+    /// Take one record out of the bin. Returns false if the bin doesn't
+    /// hold it
+    pub fn remove(&self, tombstone: &Tombstone) -> io::Result<bool> {
+        let mut entries = self.entries()?;
+        let Some(index) = entries.iter().rposition(|e| e == tombstone) else {
+            return Ok(false);
+        };
+        entries.remove(index);
+        self.rewrite(&entries)?;
+        Ok(true)
+    }
+
+    /// Replace the bin's entries, sealed with this bin's cipher. The bin is
+    /// written beside itself and renamed into place
+    pub fn rewrite(&self, entries: &[Tombstone]) -> io::Result<()> {
+        let rewritten = self.path.with_extension("RC~");
+        let mut out = File::create(&rewritten)?;
+        for entry in entries {
+            out.write_all(&self.encode(entry))?;
+        }
+        out.sync_all()?;
+        fs::rename(&rewritten, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::crypt::KEY_LEN;
+
+    #[test]
+    fn test_recycle_bin() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = RecycleBin::new(&dir.path().join("CUST.DAT"), None);
+        assert_eq!(bin.path(), dir.path().join("CUST.RCY"));
+        assert!(bin.entries().unwrap().is_empty());
+
+        bin.append(&[b"first".to_vec(), b"second".to_vec()]).unwrap();
+        bin.append(&[b"third".to_vec()]).unwrap();
+        let entries = bin.entries().unwrap();
+        let records: Vec<&[u8]> = entries.iter().map(|e| &e.record[..]).collect();
+        assert_eq!(records, [&b"first"[..], b"second", b"third"]);
+        assert!(entries[0].deleted_at > 0);
+
+        assert!(bin.remove(&entries[1]).unwrap());
+        assert!(!bin.remove(&entries[1]).unwrap());
+        assert_eq!(bin.entries().unwrap().len(), 2);
+
+        // A torn entry at the end is left out
+        OpenOptions::new().append(true).open(bin.path()).unwrap().write_all(&[50, 0, 0, 0, 1]).unwrap();
+        assert_eq!(bin.entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_sealed_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT");
+        let bin = RecycleBin::new(&path, Some(PageCipher::new(&[5u8; KEY_LEN], &[0u8; 16])));
+        bin.append(&[b"ACME WIDGET".to_vec()]).unwrap();
+
+        assert!(!fs::read(bin.path()).unwrap().windows(11).any(|w| w == b"ACME WIDGET"));
+        assert_eq!(bin.entries().unwrap()[0].record, b"ACME WIDGET");
+        assert!(RecycleBin::new(&path, Some(PageCipher::new(&[6u8; KEY_LEN], &[0u8; 16]))).entries().is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Roll-forward log of committed changes
+//!
+//! A file created with the roll-forward flag logs every change that
+//! commits to it in a companion file beside it, named like the file with
+//! the extension LOG, as Btrieve's BLOG did. After a crash or a bad disk
+//! the last backup is restored and `xtutil rollfwd` applies the log to it,
+//! bringing it up to the last commit. Entries are appended in commit order:
+//!
+//! ```text
+//! [length:4][logged at:8][change:1][change data]
+//! ```
+//!
+//! The logging time is in seconds since the Unix epoch. An insert or a
+//! delete carries the record; an update carries `[length:4]` and the
+//! record before it, then the record after it; a truncate carries nothing.
+//! Records are logged whole, so a change can be found again in a restored
+//! file without record addresses. For an encrypted file everything after
+//! the length is sealed with the file's key, as in the recycle bin.
+//!
+//! The log only grows: start a new one, by removing it, when a backup is
+//! taken. Roll-forward logs are an Xtrieve extension: DOS Btrieve ignores
+//! them.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::crypt::PageCipher;
+
+/// Page number bound into sealed entries, which neither a page nor a
+/// recycle bin entry has
+const ENTRY_SEAL: u32 = u32::MAX - 1;
+
+/// A change committed to a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Insert(Vec<u8>),
+    Update { before: Vec<u8>, after: Vec<u8> },
+    Delete(Vec<u8>),
+    /// Every record removed
+    Truncate,
+}
+
+impl Change {
+    fn to_raw(&self) -> u8 {
+        match self {
+            Change::Insert(_) => 1,
+            Change::Update { .. } => 2,
+            Change::Delete(_) => 3,
+            Change::Truncate => 4,
+        }
+    }
+}
+
+/// A change in a roll-forward log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedChange {
+    /// Seconds since the Unix epoch
+    pub logged_at: u64,
+    pub change: Change,
+}
+
+/// The roll-forward log of one file
+#[derive(Debug, Clone)]
+pub struct RollForwardLog {
+    path: PathBuf,
+    cipher: Option<PageCipher>,
+}
+
+impl RollForwardLog {
+    /// Log of the file at `data_path`, its entries sealed with `cipher`
+    pub fn new(data_path: &Path, cipher: Option<PageCipher>) -> Self {
+        RollForwardLog { path: data_path.with_extension("LOG"), cipher }
+    }
+
+    /// Log kept somewhere else than beside its file, such as a copy
+    /// taken off a failed disk
+    pub fn at(path: &Path, cipher: Option<PageCipher>) -> Self {
+        RollForwardLog { path: path.to_path_buf(), cipher }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn encode(&self, logged_at: u64, change: &Change) -> Vec<u8> {
+        let mut payload = logged_at.to_le_bytes().to_vec();
+        payload.push(change.to_raw());
+        match change {
+            Change::Insert(record) | Change::Delete(record) => payload.extend_from_slice(record),
+            Change::Update { before, after } => {
+                payload.extend_from_slice(&(before.len() as u32).to_le_bytes());
+                payload.extend_from_slice(before);
+                payload.extend_from_slice(after);
+            }
+            Change::Truncate => {}
+        }
+        if let Some(cipher) = &self.cipher {
+            payload = cipher.seal(ENTRY_SEAL, &payload);
+        }
+        let mut entry = (payload.len() as u32).to_le_bytes().to_vec();
+        entry.extend_from_slice(&payload);
+        entry
+    }
+
+    fn decode(&self, payload: &[u8]) -> io::Result<LoggedChange> {
+        let payload = match &self.cipher {
+            Some(cipher) => cipher.open(ENTRY_SEAL, payload)?,
+            None => payload.to_vec(),
+        };
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("roll-forward log entry {}", what));
+        if payload.len() < 9 {
+            return Err(invalid("too short"));
+        }
+        let mut logged_at = [0u8; 8];
+        logged_at.copy_from_slice(&payload[..8]);
+        let data = &payload[9..];
+        let change = match payload[8] {
+            1 => Change::Insert(data.to_vec()),
+            2 => {
+                let split = data
+                    .get(..4)
+                    .map(|n| 4 + u32::from_le_bytes([n[0], n[1], n[2], n[3]]) as usize)
+                    .filter(|&split| split <= data.len())
+                    .ok_or_else(|| invalid("too short"))?;
+                Change::Update { before: data[4..split].to_vec(), after: data[split..].to_vec() }
+            }
+            3 => Change::Delete(data.to_vec()),
+            4 => Change::Truncate,
+            other => return Err(invalid(&format!("of unknown change {}", other))),
+        };
+        Ok(LoggedChange { logged_at: u64::from_le_bytes(logged_at), change })
+    }
+
+    /// Add changes committed just now
+    pub fn append(&self, changes: &[Change]) -> io::Result<()> {
+        let logged_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let entries: Vec<u8> = changes.iter().flat_map(|change| self.encode(logged_at, change)).collect();
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(&entries)?;
+        file.sync_data()
+    }
+
+    /// Every change in the log, oldest first. An entry cut short by a
+    /// crash ends the list
+    pub fn entries(&self) -> io::Result<Vec<LoggedChange>> {
+        let mut data = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut file) => file.read_to_end(&mut data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while at + 4 <= data.len() {
+            let len = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]) as usize;
+            let Some(payload) = data.get(at + 4..at + 4 + len) else {
+                break;
+            };
+            entries.push(self.decode(payload)?);
+            at += 4 + len;
+        }
+        Ok(entries)
+    }
+
+    /// Replace the log's entries, sealed with this log's cipher. The log
+    /// is written beside itself and renamed into place
+    pub fn rewrite(&self, entries: &[LoggedChange]) -> io::Result<()> {
+        let rewritten = self.path.with_extension("LO~");
+        let mut out = File::create(&rewritten)?;
+        for entry in entries {
+            out.write_all(&self.encode(entry.logged_at, &entry.change))?;
+        }
+        out.sync_all()?;
+        fs::rename(&rewritten, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::crypt::KEY_LEN;
+
+    #[test]
+    fn test_roll_forward_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = RollForwardLog::new(&dir.path().join("CUST.DAT"), None);
+        assert_eq!(log.path(), dir.path().join("CUST.LOG"));
+        assert!(log.entries().unwrap().is_empty());
+
+        let changes = vec![
+            Change::Insert(b"A100".to_vec()),
+            Change::Update { before: b"A100".to_vec(), after: b"A100 PAID".to_vec() },
+            Change::Delete(b"A100 PAID".to_vec()),
+            Change::Truncate,
+        ];
+        log.append(&changes[..2]).unwrap();
+        log.append(&changes[2..]).unwrap();
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.iter().map(|e| e.change.clone()).collect::<Vec<_>>(), changes);
+        assert!(entries[0].logged_at > 0);
+
+        // A torn entry at the end is left out
+        OpenOptions::new().append(true).open(log.path()).unwrap().write_all(&[50, 0, 0, 0, 1]).unwrap();
+        assert_eq!(log.entries().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_sealed_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CUST.DAT");
+        let log = RollForwardLog::new(&path, Some(PageCipher::new(&[5u8; KEY_LEN], &[0u8; 16])));
+        log.append(&[Change::Insert(b"ACME WIDGET".to_vec())]).unwrap();
+
+        assert!(!fs::read(log.path()).unwrap().windows(11).any(|w| w == b"ACME WIDGET"));
+        assert_eq!(log.entries().unwrap()[0].change, Change::Insert(b"ACME WIDGET".to_vec()));
+        assert!(RollForwardLog::at(log.path(), Some(PageCipher::new(&[6u8; KEY_LEN], &[0u8; 16]))).entries().is_err());
+    }
+}
@@ -0,0 +1,235 @@
+//! Startup self-check of a data directory
+//!
+//! Run by the daemon before it serves anything, so every file is checked
+//! while no client holds it. Each data file gets the quick FCR check of
+//! `check::check_fcr_file`, and its companions are looked at for what a
+//! crash leaves behind:
+//! - a pre-image (`.PRE.<session>`): the transaction it belonged to never
+//!   ended, so the file may hold half of it
+//! - the work files of a rewrite (`.XT~`, `.IX~`, `.RC~`, `.LO~`): if the
+//!   rewrite never renamed any of them into place the file is as it was
+//!   and they are removed; if it renamed some, the file is torn
+//! - a lock file (`.LCK`, see `marker`) no process holds: removed. One
+//!   another daemon holds is reported, and refuses opens by itself
+//!
+//! Files with a bad FCR, an orphaned pre-image or a torn rewrite are
+//! refused (see `OpenFileTable::refuse`) until an operator sees to them.
+//! Every file that isn't a companion is taken for a data file.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::error::StatusCode;
+
+use super::check::check_fcr_file;
+use super::files::{self, INDEX_EXT};
+use super::fcr::FileControlRecord;
+use super::marker::{Marker, MarkerError, ARBITER_NAME, MARKER_EXT};
+use super::page::MAX_PAGE_SIZE;
+
+/// Something the self-check found about one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    /// The FCR failed the quick check
+    BadFcr(String),
+    /// A pre-image no transaction is left to end
+    OrphanedPreImage(PathBuf),
+    /// A rewrite stopped after renaming some of its files into place;
+    /// the work file still there
+    TornRewrite(PathBuf),
+    /// A work file of a rewrite that replaced nothing, removed
+    StaleWorkFile(PathBuf),
+    /// A lock file no process holds, removed
+    StaleLockFile(PathBuf),
+    /// The lock file is held by another process, which wrote this in it
+    ServedElsewhere(String),
+}
+
+impl Finding {
+    /// Short name for the report
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Finding::BadFcr(_) => "bad-fcr",
+            Finding::OrphanedPreImage(_) => "orphaned-pre-image",
+            Finding::TornRewrite(_) => "torn-rewrite",
+            Finding::StaleWorkFile(_) => "stale-work-file",
+            Finding::StaleLockFile(_) => "stale-lock-file",
+            Finding::ServedElsewhere(_) => "served-elsewhere",
+        }
+    }
+
+    /// Status opens of the file are refused with, if it is refused
+    pub fn refusal(&self) -> Option<StatusCode> {
+        match self {
+            Finding::BadFcr(_) | Finding::TornRewrite(_) => Some(StatusCode::NotBtrieveFile),
+            Finding::OrphanedPreImage(_) => Some(StatusCode::PreImageOpenError),
+            Finding::StaleWorkFile(_) | Finding::StaleLockFile(_) | Finding::ServedElsewhere(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::BadFcr(problem) | Finding::ServedElsewhere(problem) => f.write_str(problem),
+            Finding::OrphanedPreImage(path)
+            | Finding::TornRewrite(path)
+            | Finding::StaleWorkFile(path)
+            | Finding::StaleLockFile(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// What the self-check found about one data file
+#[derive(Debug, Clone)]
+pub struct FileCheck {
+    pub path: PathBuf,
+    pub findings: Vec<Finding>,
+}
+
+impl FileCheck {
+    /// Status to refuse opens of the file with, if it failed
+    pub fn refusal(&self) -> Option<StatusCode> {
+        self.findings.iter().find_map(Finding::refusal)
+    }
+}
+
+/// Check every data file under `dir`, removing stale work files as they
+/// are found
+pub fn check_dir(dir: &Path) -> io::Result<Vec<FileCheck>> {
+    let mut names = Vec::new();
+    let mut checks = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            checks.extend(check_dir(&entry.path())?);
+        } else {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+
+    for name in names.iter().filter(|name| !is_companion(name)) {
+        let path = dir.join(name);
+        let stem = Path::new(name).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let companion = |suffix: &str| dir.join(format!("{}.{}", stem, suffix));
+        let mut findings = Vec::new();
+
+        let fcr = match check_fcr_file(&path) {
+            Ok(report) if report.is_clean() => read_fcr(&path).ok(),
+            Ok(report) => {
+                findings.extend(report.problems.iter().map(|problem| Finding::BadFcr(problem.to_string())));
+                None
+            }
+            Err(e) => {
+                findings.push(Finding::BadFcr(e.to_string()));
+                None
+            }
+        };
+
+        let lock_file = Marker::path_of(&path);
+        if lock_file.exists() {
+            match Marker::take(&path) {
+                // Given up, and so removed, straight away
+                Ok(_) => findings.push(Finding::StaleLockFile(lock_file)),
+                Err(MarkerError::Held(holder)) => findings.push(Finding::ServedElsewhere(holder)),
+                Err(MarkerError::Io(e)) => return Err(e),
+            }
+        }
+
+        let pre_image = format!("{}.PRE.", stem);
+        for other in names.iter().filter(|other| other.starts_with(&pre_image)) {
+            findings.push(Finding::OrphanedPreImage(dir.join(other)));
+        }
+
+        // A file whose FCR can't be read can't say which work files are its
+        let Some(fcr) = fcr else {
+            checks.push(FileCheck { path, findings });
+            continue;
+        };
+        // The index work files are renamed into place before the .DAT's
+        let work_file = companion("XT~");
+        let index_work: Vec<PathBuf> = files::index_paths(&path, fcr.layout, fcr.keys.len())
+            .into_iter()
+            .map(|index| PathBuf::from(format!("{}~", index.display())))
+            .collect();
+        let index_left: Vec<&PathBuf> = index_work.iter().filter(|work| work.exists()).collect();
+        if work_file.exists() && index_left.len() < index_work.len() {
+            findings.push(Finding::TornRewrite(work_file));
+        } else {
+            let stale = [work_file, companion("RC~"), companion("LO~")];
+            for work in stale.iter().chain(index_left) {
+                if work.exists() {
+                    fs::remove_file(work)?;
+                    findings.push(Finding::StaleWorkFile(work.clone()));
+                }
+            }
+        }
+
+        checks.push(FileCheck { path, findings });
+    }
+    Ok(checks)
+}
+
+/// Whether a file name is one of a data file's companions rather than a
+/// data file
+pub(crate) fn is_companion(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    let extension = Path::new(&upper).extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+    let index = extension
+        .strip_prefix(INDEX_EXT)
+        .is_some_and(|rest| rest.chars().all(|c| c.is_ascii_digit()));
+    upper.ends_with('~')
+        || upper.contains(".PRE.")
+        || index
+        || extension == "RCY"
+        || extension == "LOG"
+        || extension == MARKER_EXT
+        || upper == ARBITER_NAME
+}
+
+pub(crate) fn read_fcr(path: &Path) -> io::Result<FileControlRecord> {
+    let mut data = Vec::new();
+    File::open(path)?.take(MAX_PAGE_SIZE as u64).read_to_end(&mut data)?;
+    FileControlRecord::from_bytes(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_dir() {
+        let dir = tempdir().unwrap();
+        let good = FileControlRecord::new(16, 512, Vec::new()).to_bytes();
+        fs::write(dir.path().join("CUST.DAT"), &good).unwrap();
+        fs::write(dir.path().join("CUST.XT~"), &good).unwrap();
+        fs::write(dir.path().join("CUST.LCK"), b"pid 1").unwrap();
+        fs::write(dir.path().join("ORDERS.DAT"), &good).unwrap();
+        fs::write(dir.path().join("ORDERS.PRE.7"), b"").unwrap();
+        fs::write(dir.path().join("NOTES.DAT"), b"not a btrieve file").unwrap();
+
+        let checks = check_dir(dir.path()).unwrap();
+        let summary: Vec<_> = checks
+            .iter()
+            .map(|check| {
+                let name = check.path.file_name().unwrap().to_string_lossy().into_owned();
+                let kinds: Vec<_> = check.findings.iter().map(Finding::kind).collect();
+                (name, kinds, check.refusal())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("CUST.DAT".to_string(), vec!["stale-lock-file", "stale-work-file"], None),
+                ("NOTES.DAT".to_string(), vec!["bad-fcr"], Some(StatusCode::NotBtrieveFile)),
+                ("ORDERS.DAT".to_string(), vec!["orphaned-pre-image"], Some(StatusCode::PreImageOpenError)),
+            ]
+        );
+        assert!(!dir.path().join("CUST.XT~").exists());
+        assert!(!dir.path().join("CUST.LCK").exists());
+    }
+}
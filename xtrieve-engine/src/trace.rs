@@ -0,0 +1,127 @@
+//! Request traces
+//!
+//! A trace is the stream of requests a server executed, each with the
+//! response it gave, so a workload captured from one build can be played
+//! against another and the answers compared.
+//!
+//! File format:
+//!   ["XTTRACE1"] then per request:
+//!   [elapsed_us:8][session:8][open_mode:4][request][response]
+//!
+//! Requests and responses use the wire framing of `protocol`. The open
+//! mode is carried separately because the binary protocol has no field
+//! for it.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::protocol::{Request, Response};
+
+/// Leading bytes of every trace file
+pub const TRACE_MAGIC: &[u8; 8] = b"XTTRACE1";
+
+/// One executed request
+#[derive(Debug, Clone)]
+pub struct TraceRecord {
+    /// Time since the trace started
+    pub elapsed: Duration,
+    /// Server session the request ran on
+    pub session: u64,
+    pub open_mode: i32,
+    /// The request as the client sent it (file path not resolved)
+    pub request: Request,
+    pub response: Response,
+}
+
+impl TraceRecord {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&(self.elapsed.as_micros() as u64).to_le_bytes())?;
+        writer.write_all(&self.session.to_le_bytes())?;
+        writer.write_all(&self.open_mode.to_le_bytes())?;
+        writer.write_all(&self.request.to_bytes())?;
+        self.response.write_to(writer)
+    }
+
+    /// Read the next record, `None` at a clean end of the trace
+    pub fn from_reader<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut buf8 = [0u8; 8];
+        match reader.read(&mut buf8[..1])? {
+            0 => return Ok(None),
+            _ => reader.read_exact(&mut buf8[1..])?,
+        }
+        let elapsed = Duration::from_micros(u64::from_le_bytes(buf8));
+        reader.read_exact(&mut buf8)?;
+        let session = u64::from_le_bytes(buf8);
+        let mut buf4 = [0u8; 4];
+        reader.read_exact(&mut buf4)?;
+        let open_mode = i32::from_le_bytes(buf4);
+
+        Ok(Some(TraceRecord {
+            elapsed,
+            session,
+            open_mode,
+            request: Request::from_reader(reader)?,
+            response: Response::from_reader(reader)?,
+        }))
+    }
+}
+
+/// Write the trace header
+pub fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(TRACE_MAGIC)
+}
+
+/// Check the trace header
+pub fn read_header<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != TRACE_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an Xtrieve trace file"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_roundtrip() {
+        let record = TraceRecord {
+            elapsed: Duration::from_micros(1_500),
+            session: 7,
+            open_mode: -2,
+            request: Request {
+                operation_code: 5,
+                data_buffer: vec![0; 16],
+                key_buffer: b"A100".to_vec(),
+                key_number: 1,
+                file_path: "CUST.DAT".into(),
+                ..Default::default()
+            },
+            response: Response { status_code: 4, ..Default::default() },
+        };
+
+        let mut trace = Vec::new();
+        write_header(&mut trace).unwrap();
+        record.write_to(&mut trace).unwrap();
+        record.write_to(&mut trace).unwrap();
+
+        let mut reader = &trace[..];
+        read_header(&mut reader).unwrap();
+        for _ in 0..2 {
+            let read = TraceRecord::from_reader(&mut reader).unwrap().unwrap();
+            assert_eq!((read.elapsed, read.session, read.open_mode), (record.elapsed, 7, -2));
+            assert_eq!(read.request.key_buffer, b"A100");
+            assert_eq!(read.request.file_path, "CUST.DAT");
+            assert_eq!(read.response.status_code, 4);
+        }
+        assert!(TraceRecord::from_reader(&mut reader).unwrap().is_none());
+
+        // A record cut short is an error, not the end
+        let mut cut = &trace[8..trace.len() - 3];
+        TraceRecord::from_reader(&mut cut).unwrap();
+        assert!(TraceRecord::from_reader(&mut cut).is_err());
+        assert!(read_header(&mut &b"XTTRACE0"[..]).is_err());
+    }
+}
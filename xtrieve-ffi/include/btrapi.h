@@ -0,0 +1,43 @@
+/**
+ * Btrieve-compatible entry points exported by libxtrieve (xtrieve-ffi).
+ *
+ * Link against libxtrieve instead of the Btrieve requester. Calls go to the
+ * backend named by XTRIEVE_SERVER:
+ *
+ *   XTRIEVE_SERVER=file:///srv/data   in-process engine, no daemon
+ *   XTRIEVE_SERVER=192.168.1.10:7419  remote xtrieved
+ *   (unset)                           xtrieved on 127.0.0.1:7419
+ */
+
+#ifndef XTRIEVE_BTRAPI_H
+#define XTRIEVE_BTRAPI_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#if defined(_WIN32)
+#define XTRIEVE_API __stdcall
+#else
+#define XTRIEVE_API
+#endif
+
+/* Btrieve 6.x call; data_length is the buffer size in, bytes returned out */
+int XTRIEVE_API BTRCALL(uint16_t operation, void *pos_block, void *data_buffer,
+                        uint32_t *data_length, void *key_buffer,
+                        uint8_t key_length, char key_number);
+
+/* Btrieve 5.x call; key_buffer must hold 255 bytes */
+int XTRIEVE_API BTRV(uint16_t operation, void *pos_block, void *data_buffer,
+                     uint16_t *data_length, void *key_buffer, int key_number);
+
+/* Select the backend explicitly (NULL for the default); returns a status */
+int XTRIEVE_API XtrieveConnect(const char *address);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* XTRIEVE_BTRAPI_H */
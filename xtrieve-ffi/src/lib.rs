@@ -0,0 +1,432 @@
+//! Btrieve-compatible C entry points
+//!
+//! Exports `BTRV` and `BTRCALL` with the classic Btrieve signatures so
+//! existing C, Pascal and Clarion programs can relink against Xtrieve
+//! instead of the Btrieve requester. Every call is forwarded through one
+//! process-wide `XtrieveClient`:
+//!
+//! - `XTRIEVE_SERVER=file:///path/to/data` runs the engine in-process
+//! - `XTRIEVE_SERVER=host:port` (or `unix://`, `grpc://`) talks to xtrieved
+//! - unset, the library connects to xtrieved on `127.0.0.1:7419`
+//!
+//! `XtrieveConnect` picks the backend explicitly instead of the variable.
+
+#![allow(non_snake_case)]
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::slice;
+use std::sync::Mutex;
+
+use xtrieve_client::btrieve::op;
+use xtrieve_client::{BtrieveError, BtrieveRequest, BtrieveResponse, StatusCode, XtrieveClient};
+use xtrieve_engine::file_manager::cursor::PositionBlock;
+use xtrieve_engine::POSITION_BLOCK_SIZE;
+
+/// Environment variable naming the backend address
+pub const SERVER_ENV: &str = "XTRIEVE_SERVER";
+
+/// Backend used when `XTRIEVE_SERVER` is unset (xtrieved's default listener)
+const DEFAULT_SERVER: &str = "127.0.0.1:7419";
+
+/// Key buffer length `BTRV` assumes, as the Btrieve requester does
+const BTRV_KEY_LENGTH: u8 = 255;
+
+/// Stop (25): release the backend; handled here rather than forwarded
+const STOP: u32 = 25;
+
+/// Connection shared by every call in the process
+static CLIENT: Mutex<Option<XtrieveClient>> = Mutex::new(None);
+
+/// The caller's buffers for one call
+struct Call<'a> {
+    operation: u32,
+    lock_bias: u32,
+    position_block: &'a mut [u8],
+    data_buffer: &'a mut [u8],
+    /// Bytes of `data_buffer` holding input; the whole buffer receives output
+    data_length: usize,
+    key_buffer: &'a mut [u8],
+    key_number: i32,
+}
+
+impl Call<'_> {
+    /// Translate to an Xtrieve request.
+    ///
+    /// Btrieve passes Open/Create file names in the key buffer and the
+    /// owner name in the data buffer; Xtrieve wants the path in
+    /// `file_path` and the owner, null-terminated, in the key buffer.
+    fn request(&self) -> BtrieveRequest {
+        let data_buffer = self.data_buffer[..self.data_length].to_vec();
+        let mut request = BtrieveRequest {
+            operation_code: self.operation,
+            position_block: self.position_block.to_vec(),
+            key_number: self.key_number,
+            lock_bias: self.lock_bias,
+            ..Default::default()
+        };
+
+        match self.operation {
+            op::OPEN => {
+                request.file_path = c_string(self.key_buffer);
+                request.open_mode = self.key_number;
+                let owner = c_string(&data_buffer);
+                if !owner.is_empty() {
+                    request.key_buffer = [owner.as_bytes(), &[0]].concat();
+                }
+            }
+            op::CREATE => {
+                request.file_path = c_string(self.key_buffer);
+                request.data_buffer = data_buffer;
+            }
+            _ => {
+                request.data_buffer = data_buffer;
+                request.key_buffer = self.key_buffer.to_vec();
+            }
+        }
+        request.data_buffer_length = request.data_buffer.len() as u32;
+        request.key_buffer_length = request.key_buffer.len() as u32;
+        request
+    }
+
+    /// Copy a response back into the caller's buffers, returning the status
+    /// and the data length to report
+    fn respond(&mut self, response: &BtrieveResponse) -> (c_int, usize) {
+        let mut status = response.status_code as c_int;
+
+        // Operations that leave the cursor alone (Stat, transactions)
+        // answer with a block naming no file; keep the caller's
+        if PositionBlock::from_bytes(&response.position_block).file_path().is_some() {
+            copy_prefix(self.position_block, &response.position_block);
+        }
+        copy_prefix(self.key_buffer, &response.key_buffer);
+
+        // Operations that return no data leave the caller's length alone
+        if response.data_buffer.is_empty() {
+            return (status, self.data_length);
+        }
+        let copied = copy_prefix(self.data_buffer, &response.data_buffer);
+        if copied < response.data_buffer.len() && status == 0 {
+            status = StatusCode::DataBufferTooShort.as_raw() as c_int;
+        }
+        (status, copied)
+    }
+}
+
+/// Text up to the first NUL, without the trailing blanks Pascal callers pad with
+fn c_string(buf: &[u8]) -> String {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).trim_end().to_string()
+}
+
+fn copy_prefix(dst: &mut [u8], src: &[u8]) -> usize {
+    let n = dst.len().min(src.len());
+    dst[..n].copy_from_slice(&src[..n]);
+    n
+}
+
+fn status_of(err: &BtrieveError) -> c_int {
+    match err {
+        BtrieveError::Status(status) | BtrieveError::Detailed { status, .. } => status.as_raw() as c_int,
+        _ => StatusCode::RecordManagerInactive.as_raw() as c_int,
+    }
+}
+
+/// Split a Btrieve operation into its code and lock bias (+100..+400)
+fn split_operation(operation: u32) -> (u32, u32) {
+    (operation % 100, operation / 100 * 100)
+}
+
+/// Run one call on the shared client, connecting on first use
+fn dispatch(call: &mut Call) -> (c_int, usize) {
+    let mut client = CLIENT.lock().unwrap_or_else(|e| e.into_inner());
+
+    if call.operation == STOP {
+        *client = None;
+        return (0, call.data_length);
+    }
+    if client.is_none() {
+        let addr = std::env::var(SERVER_ENV).unwrap_or_else(|_| DEFAULT_SERVER.to_string());
+        match XtrieveClient::connect(&addr) {
+            Ok(connected) => *client = Some(connected),
+            Err(e) => return (status_of(&e), call.data_length),
+        }
+    }
+
+    let result = client.as_mut().map(|c| c.execute(call.request()));
+    match result {
+        Some(Ok(response)) => call.respond(&response),
+        Some(Err(e)) => {
+            // Drop a broken connection so the next call reconnects
+            if !matches!(e, BtrieveError::Status(_)) {
+                *client = None;
+            }
+            (status_of(&e), call.data_length)
+        }
+        None => (
+            StatusCode::RecordManagerInactive.as_raw() as c_int,
+            call.data_length,
+        ),
+    }
+}
+
+/// View a caller buffer, treating null as empty
+unsafe fn buffer<'a>(ptr: *mut c_void, len: usize) -> &'a mut [u8] {
+    if ptr.is_null() || len == 0 {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(ptr as *mut u8, len)
+    }
+}
+
+unsafe fn call(
+    operation: u32,
+    pos_block: *mut c_void,
+    data_buffer: *mut c_void,
+    data_length: usize,
+    key_buffer: *mut c_void,
+    key_length: u8,
+    key_number: i32,
+) -> (c_int, usize) {
+    let (operation, lock_bias) = split_operation(operation);
+    let mut call = Call {
+        operation,
+        lock_bias,
+        position_block: buffer(pos_block, POSITION_BLOCK_SIZE),
+        data_buffer: buffer(data_buffer, data_length),
+        data_length: if data_buffer.is_null() {
+            0
+        } else {
+            data_length
+        },
+        key_buffer: buffer(key_buffer, key_length as usize),
+        key_number,
+    };
+    dispatch(&mut call)
+}
+
+/// Btrieve 6.x entry point.
+///
+/// # Safety
+///
+/// `pos_block` must point to 128 bytes, `data_buffer` to `*data_length`
+/// bytes and `key_buffer` to `key_length` bytes, each valid for reads and
+/// writes or null. `data_length` must be valid or null.
+#[no_mangle]
+pub unsafe extern "system" fn BTRCALL(
+    operation: u16,
+    pos_block: *mut c_void,
+    data_buffer: *mut c_void,
+    data_length: *mut u32,
+    key_buffer: *mut c_void,
+    key_length: u8,
+    key_number: c_char,
+) -> c_int {
+    let length = if data_length.is_null() {
+        0
+    } else {
+        *data_length as usize
+    };
+    let (status, returned) = call(
+        operation as u32,
+        pos_block,
+        data_buffer,
+        length,
+        key_buffer,
+        key_length,
+        key_number as i32,
+    );
+    if !data_length.is_null() {
+        *data_length = returned as u32;
+    }
+    status
+}
+
+/// Btrieve 5.x entry point; the key buffer is taken to be 255 bytes.
+///
+/// # Safety
+///
+/// As for `BTRCALL`, with `key_buffer` valid for 255 bytes.
+#[no_mangle]
+pub unsafe extern "system" fn BTRV(
+    operation: u16,
+    pos_block: *mut c_void,
+    data_buffer: *mut c_void,
+    data_length: *mut u16,
+    key_buffer: *mut c_void,
+    key_number: c_int,
+) -> c_int {
+    let length = if data_length.is_null() {
+        0
+    } else {
+        *data_length as usize
+    };
+    let (status, returned) = call(
+        operation as u32,
+        pos_block,
+        data_buffer,
+        length,
+        key_buffer,
+        BTRV_KEY_LENGTH,
+        key_number,
+    );
+    if !data_length.is_null() {
+        *data_length = returned as u16;
+    }
+    status
+}
+
+/// Connect to `address` (see the crate docs), replacing the current backend.
+/// Returns 0, or the Btrieve status the connection failed with.
+///
+/// # Safety
+///
+/// `address` must be a valid NUL-terminated string or null for the default.
+#[no_mangle]
+pub unsafe extern "system" fn XtrieveConnect(address: *const c_char) -> c_int {
+    let addr = if address.is_null() {
+        DEFAULT_SERVER.to_string()
+    } else {
+        CStr::from_ptr(address).to_string_lossy().into_owned()
+    };
+    let mut client = CLIENT.lock().unwrap_or_else(|e| e.into_inner());
+    // Release the old backend first so an embedded session flushes its files
+    *client = None;
+    match XtrieveClient::connect(&addr) {
+        Ok(connected) => {
+            *client = Some(connected);
+            0
+        }
+        Err(e) => status_of(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::ptr;
+
+    const NO_BUFFER: *mut c_void = ptr::null_mut();
+
+    #[test]
+    fn test_open_request_translation() {
+        let mut pos = [0u8; POSITION_BLOCK_SIZE];
+        let mut data = *b"SECRET\0\0";
+        let mut key = [0u8; 32];
+        key[..12].copy_from_slice(b"ORDERS.DAT  ");
+        let call = Call {
+            operation: op::OPEN,
+            lock_bias: 0,
+            position_block: &mut pos,
+            data_buffer: &mut data,
+            data_length: 8,
+            key_buffer: &mut key,
+            key_number: -2,
+        };
+
+        let request = call.request();
+        assert_eq!(request.file_path, "ORDERS.DAT");
+        assert_eq!(request.open_mode, -2);
+        assert_eq!(request.key_buffer, b"SECRET\0");
+        assert!(request.data_buffer.is_empty());
+        assert_eq!(split_operation(212), (12, 200));
+    }
+
+    #[test]
+    fn test_btrv_embedded_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let addr = CString::new(format!("file://{}", dir.path().display())).unwrap();
+        let mut pos = [0u8; POSITION_BLOCK_SIZE];
+        let mut key = [0u8; BTRV_KEY_LENGTH as usize];
+        key[..9].copy_from_slice(b"TEST.DAT\0");
+
+        // Record length 16, one 4-byte unsigned key at offset 0
+        let mut spec = [0u8; 32];
+        spec[0..2].copy_from_slice(&16u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[26] = 14;
+
+        unsafe {
+            assert_eq!(XtrieveConnect(addr.as_ptr()), 0);
+
+            let mut len = spec.len() as u16;
+            let status = BTRV(
+                14,
+                NO_BUFFER,
+                spec.as_mut_ptr().cast(),
+                &mut len,
+                key.as_mut_ptr().cast(),
+                0,
+            );
+            assert_eq!(status, 0);
+
+            let mut len = 0u16;
+            let status = BTRV(
+                0,
+                pos.as_mut_ptr().cast(),
+                NO_BUFFER,
+                &mut len,
+                key.as_mut_ptr().cast(),
+                0,
+            );
+            assert_eq!(status, 0);
+
+            let mut record = [0u8; 16];
+            record[0..4].copy_from_slice(&7u32.to_le_bytes());
+            let mut len = record.len() as u16;
+            let status = BTRV(
+                2,
+                pos.as_mut_ptr().cast(),
+                record.as_mut_ptr().cast(),
+                &mut len,
+                key.as_mut_ptr().cast(),
+                0,
+            );
+            assert_eq!(status, 0);
+            assert_eq!(len, 16);
+
+            // Stat output does not fit in 4 bytes
+            let mut stat = [0u8; 4];
+            let mut len = stat.len() as u16;
+            let status = BTRV(
+                15,
+                pos.as_mut_ptr().cast(),
+                stat.as_mut_ptr().cast(),
+                &mut len,
+                key.as_mut_ptr().cast(),
+                0,
+            );
+            assert_eq!(status, 22);
+            assert_eq!(len, 4);
+            assert_eq!(u16::from_le_bytes([stat[0], stat[1]]), 16);
+
+            // Stat must not clobber the position block
+            let mut len = record.len() as u16;
+            let status = BTRV(
+                12,
+                pos.as_mut_ptr().cast(),
+                record.as_mut_ptr().cast(),
+                &mut len,
+                key.as_mut_ptr().cast(),
+                0,
+            );
+            assert_eq!(status, 0);
+
+            let status = BTRV(
+                1,
+                pos.as_mut_ptr().cast(),
+                NO_BUFFER,
+                ptr::null_mut(),
+                NO_BUFFER,
+                0,
+            );
+            assert_eq!(status, 0);
+            assert_eq!(
+                BTRV(25, NO_BUFFER, NO_BUFFER, ptr::null_mut(), NO_BUFFER, 0),
+                0
+            );
+        }
+    }
+}
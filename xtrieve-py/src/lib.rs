@@ -0,0 +1,386 @@
+//! Python bindings
+//!
+//! ```python
+//! import xtrieve
+//!
+//! customers = xtrieve.open("CUSTOMER.DAT", server="file:///srv/btrieve")
+//! layout = xtrieve.Schema([
+//!     ("id", "u32", 0),
+//!     ("name", "string", 4, 30),
+//!     ("since", "date", 34),
+//!     ("balance", "bcd", 38, 6),
+//! ])
+//! for row in customers.records(key=0, schema=layout):
+//!     print(row["id"], row["name"])
+//!
+//! with customers.transaction():
+//!     customers.insert(record_bytes)
+//! ```
+//!
+//! `server` takes any client address: `file://<data dir>` for the
+//! in-process engine, or `host:port`, `unix://`, `grpc://` for xtrieved.
+//! Without it, `XTRIEVE_SERVER` is used, then `127.0.0.1:7419`.
+
+mod schema;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyIOError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyType};
+
+use xtrieve_client::btrieve::FileStatistics;
+use xtrieve_client::{BtrieveFile, BtrieveRecord, BtrieveResult, StatusCode, XtrieveClient};
+
+pub use schema::Schema;
+
+create_exception!(
+    xtrieve,
+    BtrieveError,
+    PyException,
+    "A non-zero Btrieve status; args are (status, message)"
+);
+
+/// Environment variable naming the server when `open` is not given one
+const SERVER_ENV: &str = "XTRIEVE_SERVER";
+
+/// xtrieved's default listener
+const DEFAULT_SERVER: &str = "127.0.0.1:7419";
+
+/// Btrieve statuses become `BtrieveError`, transport failures `IOError`
+pub(crate) fn to_py_err(err: xtrieve_client::BtrieveError) -> PyErr {
+    match err {
+        xtrieve_client::BtrieveError::Status(status)
+        | xtrieve_client::BtrieveError::Detailed { status, .. } => {
+            BtrieveError::new_err((status.as_raw(), err.to_string()))
+        }
+        other => PyIOError::new_err(other.to_string()),
+    }
+}
+
+fn is_end_of_file(err: &xtrieve_client::BtrieveError) -> bool {
+    matches!(
+        err,
+        xtrieve_client::BtrieveError::Status(StatusCode::EndOfFile)
+    )
+}
+
+/// An open Btrieve file
+#[pyclass(name = "BtrieveFile", module = "xtrieve")]
+struct File {
+    /// `None` once closed
+    file: Option<BtrieveFile>,
+}
+
+impl File {
+    /// Run an operation with the GIL released
+    fn call<T: Send>(
+        &mut self,
+        py: Python<'_>,
+        op: impl FnOnce(&mut BtrieveFile) -> BtrieveResult<T> + Send,
+    ) -> PyResult<T> {
+        let file = self.file.as_mut().ok_or_else(|| {
+            to_py_err(xtrieve_client::BtrieveError::Status(
+                StatusCode::FileNotOpen,
+            ))
+        })?;
+        py.allow_threads(|| op(file)).map_err(to_py_err)
+    }
+
+    fn read(
+        &mut self,
+        py: Python<'_>,
+        op: impl FnOnce(&mut BtrieveFile) -> BtrieveResult<BtrieveRecord> + Send,
+    ) -> PyResult<Py<PyBytes>> {
+        let record = self.call(py, op)?;
+        Ok(PyBytes::new_bound(py, &record.data).unbind())
+    }
+}
+
+#[pymethods]
+impl File {
+    /// Select the key used by Get operations
+    fn set_key(&mut self, key_number: i32) {
+        if let Some(file) = self.file.as_mut() {
+            file.set_key(key_number);
+        }
+    }
+
+    fn insert(&mut self, py: Python<'_>, data: Vec<u8>) -> PyResult<()> {
+        self.call(py, |f| f.insert(&data))
+    }
+
+    /// Replace the current record
+    fn update(&mut self, py: Python<'_>, data: Vec<u8>) -> PyResult<()> {
+        self.call(py, |f| f.update(&data))
+    }
+
+    /// Delete the current record
+    fn delete(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.call(py, |f| f.delete())
+    }
+
+    fn get_equal(&mut self, py: Python<'_>, key: Vec<u8>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_equal(&key))
+    }
+
+    fn get_greater(&mut self, py: Python<'_>, key: Vec<u8>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_greater(&key))
+    }
+
+    fn get_greater_or_equal(&mut self, py: Python<'_>, key: Vec<u8>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_greater_or_equal(&key))
+    }
+
+    fn get_less(&mut self, py: Python<'_>, key: Vec<u8>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_less(&key))
+    }
+
+    fn get_less_or_equal(&mut self, py: Python<'_>, key: Vec<u8>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_less_or_equal(&key))
+    }
+
+    fn get_first(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_first())
+    }
+
+    fn get_last(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_last())
+    }
+
+    fn get_next(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_next())
+    }
+
+    fn get_previous(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_previous())
+    }
+
+    fn step_first(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.step_first())
+    }
+
+    fn step_next(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.step_next())
+    }
+
+    fn step_last(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.step_last())
+    }
+
+    fn step_previous(&mut self, py: Python<'_>) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.step_previous())
+    }
+
+    /// Physical position of the current record, for `get_direct`
+    fn get_position(&mut self, py: Python<'_>) -> PyResult<u32> {
+        self.call(py, |f| f.get_position())
+    }
+
+    fn get_direct(&mut self, py: Python<'_>, position: u32) -> PyResult<Py<PyBytes>> {
+        self.read(py, |f| f.get_direct(position))
+    }
+
+    /// File statistics as a dict
+    fn stat<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stat: FileStatistics = self.call(py, |f| f.stat())?;
+        let dict = PyDict::new_bound(py);
+        dict.set_item("record_length", stat.record_length)?;
+        dict.set_item("page_size", stat.page_size)?;
+        dict.set_item("num_keys", stat.num_keys)?;
+        dict.set_item("num_records", stat.num_records)?;
+        dict.set_item("flags", stat.flags.bits())?;
+        let keys = stat
+            .keys
+            .iter()
+            .map(|key| {
+                let spec = PyDict::new_bound(py);
+                spec.set_item("position", key.position)?;
+                spec.set_item("length", key.length)?;
+                spec.set_item("type", format!("{:?}", key.key_type))?;
+                spec.set_item("flags", key.flags.bits())?;
+                Ok(spec)
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        dict.set_item("keys", keys)?;
+        Ok(dict)
+    }
+
+    /// Iterate over all records in `key` order, as bytes or, given a
+    /// schema, as dicts
+    #[pyo3(signature = (key = 0, schema = None))]
+    fn records(slf: Py<Self>, key: i32, schema: Option<Schema>) -> RecordIter {
+        RecordIter {
+            file: slf,
+            key,
+            schema,
+            started: false,
+            done: false,
+        }
+    }
+
+    fn begin_transaction(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.call(py, |f| f.begin_transaction())
+    }
+
+    fn end_transaction(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.call(py, |f| f.end_transaction())
+    }
+
+    fn abort_transaction(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.call(py, |f| f.abort_transaction())
+    }
+
+    /// Context manager committing on success and aborting on an exception
+    fn transaction(slf: Py<Self>) -> Transaction {
+        Transaction { file: slf }
+    }
+
+    fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        match self.file.take() {
+            Some(file) => py.allow_threads(|| file.close()).map_err(to_py_err),
+            None => Ok(()),
+        }
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type = None, _exc = None, _traceback = None))]
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _exc_type: Option<Bound<'_, PyType>>,
+        _exc: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<bool> {
+        self.close(py)?;
+        Ok(false)
+    }
+}
+
+/// Iterator returned by `BtrieveFile.records`
+#[pyclass(module = "xtrieve")]
+struct RecordIter {
+    file: Py<File>,
+    key: i32,
+    schema: Option<Schema>,
+    started: bool,
+    done: bool,
+}
+
+#[pymethods]
+impl RecordIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut file = self.file.bind(py).borrow_mut();
+        let (started, key) = (self.started, self.key);
+        self.started = true;
+        let result = file.call(py, |f| {
+            let record = if started {
+                f.get_next()
+            } else {
+                f.set_key(key);
+                f.get_first()
+            };
+            match record {
+                Ok(record) => Ok(Some(record)),
+                Err(e) if is_end_of_file(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        });
+
+        match result {
+            Ok(Some(record)) => match &self.schema {
+                Some(schema) => Ok(Some(schema.decode(py, &record.data)?.into_any().unbind())),
+                None => Ok(Some(
+                    PyBytes::new_bound(py, &record.data).into_any().unbind(),
+                )),
+            },
+            Ok(None) => {
+                self.done = true;
+                Ok(None)
+            }
+            Err(e) => {
+                self.done = true;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Context manager returned by `BtrieveFile.transaction`
+#[pyclass(module = "xtrieve")]
+struct Transaction {
+    file: Py<File>,
+}
+
+#[pymethods]
+impl Transaction {
+    fn __enter__(&self, py: Python<'_>) -> PyResult<Py<File>> {
+        self.file.bind(py).borrow_mut().begin_transaction(py)?;
+        Ok(self.file.clone_ref(py))
+    }
+
+    #[pyo3(signature = (exc_type = None, _exc = None, _traceback = None))]
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        exc_type: Option<Bound<'_, PyType>>,
+        _exc: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<bool> {
+        let mut file = self.file.bind(py).borrow_mut();
+        match exc_type {
+            None => file.end_transaction(py)?,
+            Some(_) => file.abort_transaction(py)?,
+        }
+        Ok(false)
+    }
+}
+
+/// Open a file; `mode` is the Btrieve open mode (0 normal, -2 read-only, ...)
+#[pyfunction]
+#[pyo3(signature = (path, server = None, mode = 0, owner = None))]
+fn open(
+    py: Python<'_>,
+    path: &str,
+    server: Option<&str>,
+    mode: i32,
+    owner: Option<&str>,
+) -> PyResult<File> {
+    let addr = match server {
+        Some(addr) => addr.to_string(),
+        None => std::env::var(SERVER_ENV).unwrap_or_else(|_| DEFAULT_SERVER.to_string()),
+    };
+    let file = py
+        .allow_threads(|| {
+            let client = XtrieveClient::connect(&addr)?;
+            match owner {
+                Some(owner) => BtrieveFile::open_with_owner(client, path, mode, owner),
+                None => BtrieveFile::open(client, path, mode),
+            }
+        })
+        .map_err(to_py_err)?;
+    Ok(File { file: Some(file) })
+}
+
+#[pymodule]
+fn xtrieve(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    m.add_class::<File>()?;
+    m.add_class::<Schema>()?;
+    m.add_class::<RecordIter>()?;
+    m.add_class::<Transaction>()?;
+    m.add("BtrieveError", m.py().get_type_bound::<BtrieveError>())?;
+    m.add("KEY_NOT_FOUND", StatusCode::KeyNotFound.as_raw())?;
+    m.add("DUPLICATE_KEY", StatusCode::DuplicateKey.as_raw())?;
+    m.add("END_OF_FILE", StatusCode::EndOfFile.as_raw())?;
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Record layouts for decoding fields into Python values
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDate, PyDict, PyTime, PyTuple};
+
+use xtrieve_client::typed::{self, BtrieveDate, BtrieveTime, Encoding, Field};
+
+use crate::to_py_err;
+
+/// Field types and how they are stored
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    /// Space padded text
+    String,
+    /// NUL terminated text
+    ZString,
+    Bytes,
+    Date,
+    Time,
+    /// Packed decimal, decoded as an unscaled integer
+    Bcd,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "i8" => Kind::I8,
+            "i16" => Kind::I16,
+            "i32" => Kind::I32,
+            "i64" => Kind::I64,
+            "u8" => Kind::U8,
+            "u16" => Kind::U16,
+            "u32" => Kind::U32,
+            "u64" => Kind::U64,
+            "f32" => Kind::F32,
+            "f64" => Kind::F64,
+            "string" => Kind::String,
+            "zstring" => Kind::ZString,
+            "bytes" => Kind::Bytes,
+            "date" => Kind::Date,
+            "time" => Kind::Time,
+            "bcd" => Kind::Bcd,
+            _ => return None,
+        })
+    }
+
+    /// Natural width, 0 when the field must give a length
+    fn width(self) -> usize {
+        match self {
+            Kind::I8 | Kind::U8 => 1,
+            Kind::I16 | Kind::U16 => 2,
+            Kind::I32 | Kind::U32 | Kind::F32 | Kind::Date | Kind::Time => 4,
+            Kind::I64 | Kind::U64 | Kind::F64 => 8,
+            Kind::String | Kind::ZString | Kind::Bytes | Kind::Bcd => 0,
+        }
+    }
+}
+
+fn read<T: Field + IntoPy<PyObject>>(
+    py: Python<'_>,
+    bytes: &[u8],
+    encoding: Encoding,
+) -> PyResult<PyObject> {
+    T::read(bytes, encoding)
+        .map(|v| v.into_py(py))
+        .map_err(to_py_err)
+}
+
+#[derive(Debug, Clone)]
+struct FieldSpec {
+    name: String,
+    kind: Kind,
+    offset: usize,
+    len: usize,
+}
+
+impl FieldSpec {
+    fn decode(&self, py: Python<'_>, data: &[u8]) -> PyResult<PyObject> {
+        let bytes = typed::slice(data, self.offset, self.len).map_err(to_py_err)?;
+        let value = match self.kind {
+            Kind::I8 => read::<i8>(py, bytes, Encoding::Native)?,
+            Kind::I16 => read::<i16>(py, bytes, Encoding::Native)?,
+            Kind::I32 => read::<i32>(py, bytes, Encoding::Native)?,
+            Kind::I64 => read::<i64>(py, bytes, Encoding::Native)?,
+            Kind::U8 => read::<u8>(py, bytes, Encoding::Native)?,
+            Kind::U16 => read::<u16>(py, bytes, Encoding::Native)?,
+            Kind::U32 => read::<u32>(py, bytes, Encoding::Native)?,
+            Kind::U64 => read::<u64>(py, bytes, Encoding::Native)?,
+            Kind::F32 => read::<f32>(py, bytes, Encoding::Native)?,
+            Kind::F64 => read::<f64>(py, bytes, Encoding::Native)?,
+            Kind::String => read::<String>(py, bytes, Encoding::Native)?,
+            Kind::ZString => read::<String>(py, bytes, Encoding::ZString)?,
+            Kind::Bytes => PyBytes::new_bound(py, bytes).into_py(py),
+            Kind::Bcd => typed::read_bcd(bytes).map_err(to_py_err)?.into_py(py),
+            Kind::Date => {
+                let date = BtrieveDate::read(bytes, Encoding::Native).map_err(to_py_err)?;
+                // Unset dates are stored as zeros
+                if date == BtrieveDate::default() {
+                    py.None()
+                } else {
+                    PyDate::new_bound(py, date.year as i32, date.month, date.day)?.into_py(py)
+                }
+            }
+            Kind::Time => {
+                let time = BtrieveTime::read(bytes, Encoding::Native).map_err(to_py_err)?;
+                let micros = time.hundredths as u32 * 10_000;
+                PyTime::new_bound(py, time.hours, time.minutes, time.seconds, micros, None)?
+                    .into_py(py)
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Layout of a fixed-length record.
+///
+/// Built from `(name, type, offset)` or `(name, type, offset, length)`
+/// tuples with 0-based offsets. Types: `i8`..`i64`, `u8`..`u64`, `f32`,
+/// `f64`, `date`, `time`, and - with a length - `string` (space padded),
+/// `zstring`, `bytes` and `bcd` (packed decimal, as an unscaled int).
+#[pyclass(module = "xtrieve")]
+#[derive(Debug, Clone)]
+pub struct Schema {
+    fields: Vec<FieldSpec>,
+}
+
+#[pymethods]
+impl Schema {
+    #[new]
+    fn new(fields: Vec<Bound<'_, PyTuple>>) -> PyResult<Self> {
+        let fields = fields
+            .iter()
+            .map(|field| {
+                let (name, kind, offset, len): (String, String, usize, Option<usize>) =
+                    match field.len() {
+                        3 => {
+                            let (name, kind, offset) = field.extract()?;
+                            (name, kind, offset, None)
+                        }
+                        _ => {
+                            let (name, kind, offset, len) = field.extract()?;
+                            (name, kind, offset, Some(len))
+                        }
+                    };
+                let parsed = Kind::parse(&kind).ok_or_else(|| {
+                    PyValueError::new_err(format!("Unknown field type '{}' for {}", kind, name))
+                })?;
+                let len = match (len, parsed.width()) {
+                    (Some(len), _) => len,
+                    (None, 0) => {
+                        return Err(PyValueError::new_err(format!(
+                            "Field {} of type '{}' needs a length",
+                            name, kind
+                        )))
+                    }
+                    (None, width) => width,
+                };
+                Ok(FieldSpec {
+                    name,
+                    kind: parsed,
+                    offset,
+                    len,
+                })
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(Schema { fields })
+    }
+
+    /// Bytes needed to hold every field
+    #[getter]
+    fn record_length(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|f| f.offset + f.len)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Field names in declaration order
+    #[getter]
+    fn names(&self) -> Vec<String> {
+        self.fields.iter().map(|f| f.name.clone()).collect()
+    }
+
+    /// Decode a record into a dict of field values
+    pub fn decode<'py>(&self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        for field in &self.fields {
+            dict.set_item(&field.name, field.decode(py, data)?)?;
+        }
+        Ok(dict)
+    }
+
+    fn __repr__(&self) -> String {
+        let fields: Vec<_> = self
+            .fields
+            .iter()
+            .map(|f| format!("{}@{}", f.name, f.offset))
+            .collect();
+        format!("Schema({})", fields.join(", "))
+    }
+}
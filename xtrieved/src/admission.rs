@@ -0,0 +1,171 @@
+//! Admission - how much work the daemon takes on at once
+//!
+//! Each connection has a thread and each operation runs on its caller's
+//! thread (or a worker's), so a flood of clients would start threads and
+//! pile operations onto the engine without bound. With limits set, at most
+//! `max_running` operations run at once and up to `max_queued` more wait
+//! their turn; past that an operation is answered at once with status 201
+//! (`ServerBusy`) for the client to retry. Connections past
+//! `max_connections` are closed as they are accepted.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// The daemon's limits, and the work it holds against them
+#[derive(Default)]
+pub struct Admission {
+    /// Operations running at once (0 = no limit)
+    max_running: usize,
+    /// Operations waiting for one to finish, past which they are refused
+    max_queued: usize,
+    /// Connections served at once (0 = no limit)
+    max_connections: usize,
+    state: Mutex<State>,
+    finished: Condvar,
+    connections: AtomicUsize,
+    /// Operations answered busy since startup
+    refused: AtomicU64,
+    /// Connections closed for being over the limit since startup
+    rejected: AtomicU64,
+}
+
+#[derive(Default)]
+struct State {
+    running: usize,
+    queued: usize,
+}
+
+/// What the admission counters read at one moment
+pub struct AdmissionStats {
+    pub running: usize,
+    pub queued: usize,
+    pub connections: usize,
+    pub refused: u64,
+    pub rejected: u64,
+}
+
+/// An operation let in to run; the next waiting one goes when it drops
+pub struct Running<'a> {
+    admission: &'a Admission,
+}
+
+/// A connection let in; its slot frees when it drops
+pub struct Connected {
+    admission: Arc<Admission>,
+}
+
+impl Admission {
+    pub fn new(max_running: usize, max_queued: usize, max_connections: usize) -> Self {
+        Admission { max_running, max_queued, max_connections, ..Default::default() }
+    }
+
+    /// Let an operation run, waiting for a turn if it must; `None` if as
+    /// many are waiting as may
+    pub fn enter(&self) -> Option<Running<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if self.max_running > 0 && state.running >= self.max_running {
+            if state.queued >= self.max_queued {
+                self.refused.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            state.queued += 1;
+            while state.running >= self.max_running {
+                state = self.finished.wait(state).unwrap();
+            }
+            state.queued -= 1;
+        }
+        state.running += 1;
+        Some(Running { admission: self })
+    }
+
+    /// Let a connection in; `None` if as many are served as may be
+    pub fn connect(self: &Arc<Self>) -> Option<Connected> {
+        let taken = self.connections.fetch_add(1, Ordering::AcqRel);
+        if self.max_connections > 0 && taken >= self.max_connections {
+            self.connections.fetch_sub(1, Ordering::AcqRel);
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(Connected { admission: self.clone() })
+    }
+
+    pub fn stats(&self) -> AdmissionStats {
+        let state = self.state.lock().unwrap();
+        AdmissionStats {
+            running: state.running,
+            queued: state.queued,
+            connections: self.connections.load(Ordering::Acquire),
+            refused: self.refused.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.admission.state.lock().unwrap().running -= 1;
+        self.admission.finished.notify_one();
+    }
+}
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        self.admission.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_running_limit() {
+        let admission = Admission::new(2, 0, 0);
+        let first = admission.enter().unwrap();
+        let _second = admission.enter().unwrap();
+        assert!(admission.enter().is_none());
+        assert_eq!((admission.stats().running, admission.stats().refused), (2, 1));
+
+        // Finishing one lets the next in
+        drop(first);
+        assert!(admission.enter().is_some());
+        assert_eq!(admission.stats().refused, 1);
+    }
+
+    #[test]
+    fn test_queued_wait_their_turn() {
+        let admission = Arc::new(Admission::new(1, 1, 0));
+        let running = admission.enter().unwrap();
+        let waiter = {
+            let admission = admission.clone();
+            thread::spawn(move || admission.enter().is_some())
+        };
+        while admission.stats().queued == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // The queue is full too
+        assert!(admission.enter().is_none());
+
+        drop(running);
+        assert!(waiter.join().unwrap());
+        let stats = admission.stats();
+        assert_eq!((stats.running, stats.queued, stats.refused), (0, 0, 1));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let admission = Arc::new(Admission::new(0, 0, 1));
+        let connected = admission.connect().unwrap();
+        assert!(admission.connect().is_none());
+        assert_eq!((admission.stats().connections, admission.stats().rejected), (1, 1));
+        drop(connected);
+        assert!(admission.connect().is_some());
+
+        // No limit set
+        let unlimited = Admission::default();
+        let _held: Vec<_> = (0..8).map(|_| unlimited.enter().unwrap()).collect();
+        assert_eq!(unlimited.stats().running, 8);
+    }
+}
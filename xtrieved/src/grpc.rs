@@ -0,0 +1,419 @@
+//! gRPC service - the same operations as the binary protocol over tonic
+//!
+//! Besides unary `Execute`, the service offers server-streaming scans
+//! (`ExecuteExtended`), a change feed (`Watch`) and a bidirectional
+//! `Session` stream that pins one server-side session for its lifetime.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, error, info, warn};
+
+use xtrieve_engine::operations::{transaction_ops, OperationCode, OperationRequest, OperationResponse};
+use xtrieve_engine::StatusCode;
+
+use crate::server::{self, Change, Shared};
+
+pub mod proto {
+    tonic::include_proto!("xtrieve");
+}
+
+use proto::xtrieve_server::{Xtrieve, XtrieveServer};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Start the gRPC service on its own runtime thread
+pub fn spawn(addr: SocketAddr, shared: Arc<Shared>) -> std::io::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    thread::Builder::new()
+        .name("grpc".to_string())
+        .spawn(move || {
+            let service = XtrieveService { shared };
+            let result = runtime.block_on(
+                tonic::transport::Server::builder()
+                    .add_service(XtrieveServer::new(service))
+                    .serve(addr),
+            );
+            if let Err(e) = result {
+                error!("gRPC server failed: {}", e);
+            }
+        })?;
+    Ok(())
+}
+
+/// Convert a gRPC request into an engine request
+fn to_operation_request(req: proto::BtrieveRequest) -> OperationRequest {
+    let operation = OperationCode::from_raw(req.operation_code);
+    let data_buffer = match (&req.file_spec, operation) {
+        (Some(spec), OperationCode::Create) if req.data_buffer.is_empty() => encode_file_spec(spec),
+        _ => req.data_buffer,
+    };
+
+    OperationRequest {
+        operation,
+        file_path: if req.file_path.is_empty() {
+            None
+        } else {
+            Some(req.file_path)
+        },
+        position_block: req.position_block,
+        data_buffer,
+        key_buffer: req.key_buffer,
+        key_number: req.key_number,
+        data_length: req.data_buffer_length,
+        key_length: req.key_buffer_length,
+        open_mode: req.open_mode,
+        lock_bias: req.lock_bias,
+    }
+}
+
+/// Encode a FileSpec as a Btrieve 5.x Create data buffer
+fn encode_file_spec(spec: &proto::FileSpec) -> Vec<u8> {
+    let mut buf = vec![0u8; 16];
+    buf[0..2].copy_from_slice(&(spec.record_length as u16).to_le_bytes());
+    buf[2..4].copy_from_slice(&(spec.page_size as u16).to_le_bytes());
+    buf[4..6].copy_from_slice(&(spec.keys.len() as u16).to_le_bytes());
+    buf[8..12].copy_from_slice(&spec.file_flags.to_le_bytes());
+    buf[14..16].copy_from_slice(&(spec.pre_allocation as u16).to_le_bytes());
+
+    for key in &spec.keys {
+        let mut key_buf = [0u8; 16];
+        key_buf[0..2].copy_from_slice(&(key.position as u16).to_le_bytes());
+        key_buf[2..4].copy_from_slice(&(key.length as u16).to_le_bytes());
+        key_buf[4..6].copy_from_slice(&(key.flags as u16).to_le_bytes());
+        key_buf[10] = key.key_type as u8;
+        key_buf[11] = key.null_value as u8;
+        key_buf[12] = key.acs_number as u8;
+        buf.extend_from_slice(&key_buf);
+    }
+
+    buf
+}
+
+fn to_proto_response(resp: OperationResponse) -> proto::BtrieveResponse {
+    let detail = resp.detail.unwrap_or_default();
+    proto::BtrieveResponse {
+        status_code: resp.status.as_raw() as u32,
+        position_block: resp.position_block,
+        data_length: resp.data_buffer.len() as u32,
+        data_buffer: resp.data_buffer,
+        key_length: resp.key_buffer.len() as u32,
+        key_buffer: resp.key_buffer,
+        error_message: detail.message,
+        error_context: detail.context,
+    }
+}
+
+fn to_change_event(change: Change, feed_id: u64) -> proto::ChangeEvent {
+    let kind = match change.operation {
+        OperationCode::Update => proto::ChangeKind::Update,
+        OperationCode::Delete => proto::ChangeKind::Delete,
+        OperationCode::TruncateFile => proto::ChangeKind::Truncate,
+        _ => proto::ChangeKind::Insert,
+    };
+    proto::ChangeEvent {
+        file_path: change.file_path,
+        kind: kind as i32,
+        session_id: change.session_id,
+        position_block: change.position_block,
+        data_buffer: change.data_buffer,
+        sequence: change.sequence,
+        feed_id,
+    }
+}
+
+/// Operation repeated after a positioning operation to continue a scan
+fn scan_continuation(op: OperationCode) -> Option<OperationCode> {
+    match op {
+        OperationCode::GetFirst
+        | OperationCode::GetEqual
+        | OperationCode::GetGreater
+        | OperationCode::GetGreaterOrEqual
+        | OperationCode::GetNext => Some(OperationCode::GetNext),
+        OperationCode::GetLast
+        | OperationCode::GetLessThan
+        | OperationCode::GetLessOrEqual
+        | OperationCode::GetPrevious => Some(OperationCode::GetPrevious),
+        OperationCode::StepFirst | OperationCode::StepNext => Some(OperationCode::StepNext),
+        OperationCode::StepLast | OperationCode::StepPrevious => Some(OperationCode::StepPrevious),
+        _ => None,
+    }
+}
+
+pub struct XtrieveService {
+    shared: Arc<Shared>,
+}
+
+impl XtrieveService {
+    /// Session for a standalone request: explicit client_id, then the
+    /// session stored in the position block if a standalone call started
+    /// it, then a fresh one
+    fn session_for(&self, req: &proto::BtrieveRequest) -> u64 {
+        self.shared.session_for(req.client_id, &req.position_block)
+    }
+
+    /// Run a unary call's operation, keeping its session alive
+    async fn execute_blocking(&self, session: u64, req: OperationRequest) -> Result<OperationResponse, Status> {
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || {
+            shared.touch(session);
+            let result = shared.execute(session, req);
+            shared.touch(session);
+            result
+        })
+            .await
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+#[tonic::async_trait]
+impl Xtrieve for XtrieveService {
+    async fn execute(
+        &self,
+        request: Request<proto::BtrieveRequest>,
+    ) -> Result<Response<proto::BtrieveResponse>, Status> {
+        let req = request.into_inner();
+        let session = self.session_for(&req);
+        debug!("gRPC op {} from session {}", req.operation_code, session);
+
+        let result = self.execute_blocking(session, to_operation_request(req)).await?;
+        Ok(Response::new(to_proto_response(result)))
+    }
+
+    type ExecuteExtendedStream = ResponseStream<proto::BtrieveResponse>;
+
+    async fn execute_extended(
+        &self,
+        request: Request<proto::BtrieveRequest>,
+    ) -> Result<Response<Self::ExecuteExtendedStream>, Status> {
+        let req = request.into_inner();
+        let session = self.session_for(&req);
+        let mut op_req = to_operation_request(req);
+        let next_op = scan_continuation(op_req.operation);
+
+        let shared = self.shared.clone();
+        let (tx, rx) = mpsc::channel(32);
+
+        tokio::task::spawn_blocking(move || loop {
+            shared.touch(session);
+            let result = shared.execute(session, op_req.clone());
+            shared.touch(session);
+            let status = result.status;
+            if status == StatusCode::EndOfFile {
+                break;
+            }
+            // A continuation that leaves the cursor where it was would never end
+            if Some(op_req.operation) == next_op && result.position_block == op_req.position_block {
+                break;
+            }
+
+            op_req.position_block = result.position_block.clone();
+            op_req.key_buffer = result.key_buffer.clone();
+
+            if tx.blocking_send(Ok(to_proto_response(result))).is_err() {
+                // Client went away
+                break;
+            }
+            match next_op {
+                Some(op) if status == StatusCode::Success => op_req.operation = op,
+                _ => break,
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    type WatchStream = ResponseStream<proto::ChangeEvent>;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let request = request.into_inner();
+        let filter = if request.file_path.is_empty() {
+            None
+        } else {
+            let resolved = self.shared.roots.resolve(&request.file_path);
+            Some(resolved.ok_or_else(|| Status::invalid_argument("the path leaves its data root"))?)
+        };
+
+        let feed_id = self.shared.changes.id;
+        let after = match request.resume_after {
+            0 => None,
+            _ if request.feed_id != feed_id => {
+                return Err(Status::data_loss("the server restarted since the resume point"));
+            }
+            after => Some(after),
+        };
+        let (replay, mut changes) = self.shared.changes.subscribe(after).ok_or_else(|| {
+            Status::data_loss(format!("changes after {} are no longer held", request.resume_after))
+        })?;
+        let shared = self.shared.clone();
+        let (tx, rx) = mpsc::channel(256);
+
+        tokio::spawn(async move {
+            let mut pending: VecDeque<Change> = replay.into();
+            let mut last = after.unwrap_or(0);
+            loop {
+                let change = match pending.pop_front() {
+                    Some(change) => change,
+                    None => match changes.recv().await {
+                        Ok(change) => change,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            // Catch up from the held changes if they still reach back
+                            warn!("Change feed subscriber lagged, {} events dropped", missed);
+                            match shared.changes.since(last) {
+                                Some(held) => pending = held.into(),
+                                None => {
+                                    let lost = Status::data_loss(format!("changes after {} are no longer held", last));
+                                    let _ = tx.send(Err(lost)).await;
+                                    break;
+                                }
+                            }
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                };
+                // Caught-up changes come round again on the live channel
+                if change.sequence <= last {
+                    continue;
+                }
+                last = change.sequence;
+                if let Some(filter) = &filter {
+                    if std::path::Path::new(&change.file_path) != filter.as_path() {
+                        continue;
+                    }
+                }
+                if tx.send(Ok(to_change_event(change, feed_id))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    type SessionStream = ResponseStream<proto::BtrieveResponse>;
+
+    async fn session(
+        &self,
+        request: Request<Streaming<proto::BtrieveRequest>>,
+    ) -> Result<Response<Self::SessionStream>, Status> {
+        let mut incoming = request.into_inner();
+        let mut connection = server::Connection::new();
+        let session = connection.session;
+        let shared = self.shared.clone();
+        shared.attach(&mut connection, session);
+        let (tx, rx) = mpsc::channel(32);
+        debug!("gRPC session {} started", session);
+
+        tokio::spawn(async move {
+            while let Some(req) = incoming.next().await {
+                let req = match req {
+                    Ok(req) => req,
+                    Err(status) => {
+                        debug!("gRPC session {} stream error: {}", session, status);
+                        break;
+                    }
+                };
+
+                let shared = shared.clone();
+                let op_req = to_operation_request(req);
+                let result = tokio::task::spawn_blocking(move || shared.execute(session, op_req))
+                    .await
+                    .map(to_proto_response)
+                    .map_err(|e| Status::internal(e.to_string()));
+
+                if tx.send(result).await.is_err() {
+                    break;
+                }
+            }
+            debug!("gRPC session {} ended", session);
+            let _ = tokio::task::spawn_blocking(move || shared.disconnect(connection)).await;
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn get_status(
+        &self,
+        request: Request<proto::StatusRequest>,
+    ) -> Result<Response<proto::StatusResponse>, Status> {
+        let req = request.into_inner();
+        let engine = &self.shared.engine;
+
+        let open_file_list = if req.include_open_files {
+            engine
+                .files
+                .list()
+                .iter()
+                .map(|file| {
+                    let f = file.read();
+                    proto::OpenFileInfo {
+                        file_path: f.path.to_string_lossy().to_string(),
+                        open_count: f.ref_count,
+                        record_count: f.fcr.reported_records(),
+                        has_locks: f.has_active_transactions(),
+                        total_records: f.fcr.num_records,
+                        file_size: f.fcr.file_size(),
+                    }
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let statistics = if req.include_statistics {
+            let stats = &self.shared.stats;
+            let cache = engine.cache.stats();
+            Some(proto::ServerStatistics {
+                total_operations: stats.total_operations.load(Ordering::Relaxed),
+                total_reads: stats.total_reads.load(Ordering::Relaxed),
+                total_writes: stats.total_writes.load(Ordering::Relaxed),
+                cache_hits: cache.hits,
+                cache_misses: cache.misses,
+            })
+        } else {
+            None
+        };
+
+        Ok(Response::new(proto::StatusResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_seconds: self.shared.started_at.elapsed().as_secs(),
+            open_files: engine.files.len() as u32,
+            active_transactions: transaction_ops::active_transaction_count() as u32,
+            open_file_list,
+            statistics,
+        }))
+    }
+
+    async fn shutdown(
+        &self,
+        request: Request<proto::ShutdownRequest>,
+    ) -> Result<Response<proto::ShutdownResponse>, Status> {
+        let graceful = request.into_inner().graceful;
+        info!("Shutdown requested over gRPC (graceful: {})", graceful);
+
+        let shared = self.shared.clone();
+        thread::spawn(move || {
+            // Give the response a moment to reach the client
+            thread::sleep(std::time::Duration::from_millis(100));
+            shared.engine.shutdown();
+            std::process::exit(0);
+        });
+
+        Ok(Response::new(proto::ShutdownResponse {
+            accepted: true,
+            message: "Server shutting down".to_string(),
+        }))
+    }
+}
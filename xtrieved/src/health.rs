@@ -0,0 +1,108 @@
+//! Health and readiness, for container orchestration
+//!
+//! Live: the engine answers an operation. Ready: live, startup (the
+//! self-check among it) has finished and every listener is bound, and
+//! every data directory takes a new file.
+
+use std::fs::{self, OpenOptions};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use xtrieve_engine::operations::{Engine, OperationCode, OperationRequest};
+use xtrieve_engine::StatusCode;
+
+use crate::server::DataRoots;
+
+/// Name of the file written to each data directory to see it takes one
+const PROBE_NAME: &str = "XTRIEVE.RDY";
+
+/// What the daemon knows of its own state
+pub struct Health {
+    engine: Arc<Engine>,
+    roots: DataRoots,
+    /// Set once startup has finished
+    started: AtomicBool,
+}
+
+impl Health {
+    pub fn new(engine: Arc<Engine>, roots: DataRoots) -> Self {
+        Health { engine, roots, started: AtomicBool::new(false) }
+    }
+
+    /// Mark startup finished
+    pub fn set_started(&self) {
+        self.started.store(true, Ordering::Release);
+    }
+
+    /// Whether the engine answers operations; why not if it doesn't
+    pub fn live(&self) -> Result<(), String> {
+        // Op 26 is Version, which even a strict engine answers
+        let response = self.engine.execute(0, OperationRequest {
+            operation: OperationCode::GetByPercentage,
+            ..Default::default()
+        });
+        match response.status {
+            StatusCode::Success => Ok(()),
+            status => Err(format!("engine answered status {}", status.as_raw())),
+        }
+    }
+
+    /// Whether the daemon is ready to serve; every reason it isn't
+    pub fn ready(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        if let Err(problem) = self.live() {
+            problems.push(problem);
+        }
+        if !self.started.load(Ordering::Acquire) {
+            problems.push("starting up".to_string());
+        }
+        for dir in self.roots.dirs() {
+            let probe = dir.join(PROBE_NAME);
+            let written = OpenOptions::new().write(true).create(true).truncate(true).open(&probe);
+            match written.and_then(|_| fs::remove_file(&probe)) {
+                Ok(()) => {}
+                Err(e) => problems.push(format!("{} is not writable: {}", dir.display(), e)),
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(dirs: &[String]) -> Health {
+        Health::new(Arc::new(Engine::new(64)), DataRoots::parse(dirs).unwrap())
+    }
+
+    #[test]
+    fn test_ready_once_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let health = health(&[dir.path().to_string_lossy().to_string()]);
+        assert_eq!(health.live(), Ok(()));
+        assert_eq!(health.ready(), Err(vec!["starting up".to_string()]));
+
+        health.set_started();
+        assert_eq!(health.ready(), Ok(()));
+        // The probe file doesn't stay behind
+        assert!(!dir.path().join(PROBE_NAME).exists());
+    }
+
+    #[test]
+    fn test_not_ready_without_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("gone");
+        let health = health(&[dir.path().to_string_lossy().to_string(), format!("ACCT={}", missing.display())]);
+        health.set_started();
+        assert_eq!(health.live(), Ok(()));
+
+        let problems = health.ready().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with(&format!("{} is not writable", missing.display())));
+    }
+}
@@ -0,0 +1,503 @@
+//! HTTP/JSON gateway - a REST face on the record operations
+//!
+//! Every endpoint takes and returns JSON. Binary fields (position block,
+//! record data, key values) travel as base64 strings, and the position
+//! block returned by one call is passed back on the next, exactly as a
+//! binary-protocol client would.
+//!
+//! ```text
+//! POST /api/open          {"path": "CUSTOMER.DAT", "mode": 0}
+//! POST /api/close         {"position_block": "..."}
+//! POST /api/get/{op}      {"position_block": "...", "key_number": 0, "key": "..."}
+//! POST /api/insert        {"position_block": "...", "data": "..."}
+//! POST /api/update        {"position_block": "...", "data": "..."}
+//! POST /api/delete        {"position_block": "..."}
+//! ```
+//!
+//! `{op}` is one of `first`, `last`, `next`, `previous`, `equal`, `greater`,
+//! `greater-or-equal`, `less`, `less-or-equal`, `step-first`, `step-last`,
+//! `step-next` or `step-previous`.
+//!
+//! A request naming a `dictionary` (the directory of a FILE/FIELD/INDEX.DDF
+//! set, relative to the data directory) has its file described by the
+//! dictionary's table for it. Records read come back decoded as well, as
+//! `"fields": {"Name": "Ada", "Balance": "12.50", ...}`, and an Insert or
+//! Update may give `fields` to store over `data` (or over blanks, without
+//! it), each written the way it reads back:
+//!
+//! ```text
+//! POST /api/insert        {"position_block": "...", "dictionary": "ddf",
+//!                          "fields": {"Id": 7, "Name": "Ada"}}
+//! ```
+
+use std::net::SocketAddr;
+use std::path::{Component, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode as HttpStatus;
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{debug, error};
+
+use xtrieve_client::ddf::{self, Dictionary, Table};
+use xtrieve_client::XtrieveClient;
+use xtrieve_engine::file_manager::cursor::PositionBlock;
+use xtrieve_engine::operations::{OperationCode, OperationRequest, OperationResponse};
+use xtrieve_engine::{CancelToken, StatusCode};
+
+use crate::server::{self, EngineTransport, Shared};
+
+/// Start the HTTP gateway on its own runtime thread
+pub fn spawn(addr: SocketAddr, shared: Arc<Shared>) -> std::io::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(addr))?;
+    thread::Builder::new()
+        .name("http".to_string())
+        .spawn(move || {
+            if let Err(e) = runtime.block_on(async { axum::serve(listener, router(shared)).await }) {
+                error!("HTTP gateway failed: {}", e);
+            }
+        })?;
+    Ok(())
+}
+
+fn router(shared: Arc<Shared>) -> Router {
+    Router::new()
+        .route("/api/open", post(open))
+        .route("/api/close", post(close))
+        .route("/api/get/:op", post(get))
+        .route("/api/insert", post(insert))
+        .route("/api/update", post(update))
+        .route("/api/delete", post(delete))
+        .with_state(shared)
+}
+
+/// JSON request body (all fields optional)
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ApiRequest {
+    path: String,
+    mode: i32,
+    position_block: String,
+    data: String,
+    key: String,
+    key_number: i32,
+    lock_bias: i32,
+    client_id: u64,
+    /// Directory of the data dictionary describing the file
+    dictionary: Option<String>,
+    /// Column values to store, by name
+    fields: Map<String, Value>,
+}
+
+/// JSON response body
+#[derive(Debug, Serialize)]
+struct ApiResponse {
+    status: u16,
+    message: String,
+    position_block: String,
+    data: String,
+    key: String,
+    /// The record's columns, with a dictionary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<Map<String, Value>>,
+}
+
+type ApiResult = (HttpStatus, Json<ApiResponse>);
+
+fn api_error(status: StatusCode) -> ApiResult {
+    let http = match status {
+        StatusCode::KeyNotFound | StatusCode::EndOfFile | StatusCode::FileNotFound => HttpStatus::NOT_FOUND,
+        StatusCode::DuplicateKey | StatusCode::RecordInUse | StatusCode::FileInUse => HttpStatus::CONFLICT,
+        _ => HttpStatus::BAD_REQUEST,
+    };
+    (
+        http,
+        Json(ApiResponse {
+            status: status.as_raw(),
+            message: status.to_string(),
+            position_block: String::new(),
+            data: String::new(),
+            key: String::new(),
+            fields: None,
+        }),
+    )
+}
+
+/// A request the gateway can't make sense of, and why
+fn bad_request(message: String) -> ApiResult {
+    let (http, mut body) = api_error(StatusCode::InvalidOperation);
+    body.message = message;
+    (http, body)
+}
+
+fn decode(name: &str, field: &str) -> Result<Vec<u8>, String> {
+    BASE64.decode(field).map_err(|_| format!("invalid base64 in {}", name))
+}
+
+/// Map a URL operation name to its Btrieve operation code
+fn get_operation(name: &str) -> Option<OperationCode> {
+    Some(match name {
+        "first" => OperationCode::GetFirst,
+        "last" => OperationCode::GetLast,
+        "next" => OperationCode::GetNext,
+        "previous" => OperationCode::GetPrevious,
+        "equal" => OperationCode::GetEqual,
+        "greater" => OperationCode::GetGreater,
+        "greater-or-equal" => OperationCode::GetGreaterOrEqual,
+        "less" => OperationCode::GetLessThan,
+        "less-or-equal" => OperationCode::GetLessOrEqual,
+        "step-first" => OperationCode::StepFirst,
+        "step-last" => OperationCode::StepLast,
+        "step-next" => OperationCode::StepNext,
+        "step-previous" => OperationCode::StepPrevious,
+        _ => return None,
+    })
+}
+
+async fn run(shared: Arc<Shared>, operation: OperationCode, mut body: ApiRequest) -> ApiResult {
+    let client_id = body.client_id;
+    let dictionary = body.dictionary.take();
+    let fields = std::mem::take(&mut body.fields);
+    let mut request = match build_request(operation, body) {
+        Ok(r) => r,
+        Err(message) => return bad_request(message),
+    };
+    let session = shared.session_for(client_id, &request.position_block);
+    debug!("HTTP op {:?} from session {}", operation, session);
+
+    let execute = move || {
+        let table = match &dictionary {
+            Some(dir) => Some(table_for(&shared, dir, &request)?),
+            None if fields.is_empty() => None,
+            None => return Err("fields need a dictionary".to_string()),
+        };
+        if let Some(table) = table.as_ref().filter(|_| !fields.is_empty()) {
+            store_fields(table, &fields, &mut request.data_buffer)?;
+        }
+        shared.touch(session);
+        let response = shared.execute(session, request);
+        shared.touch(session);
+        Ok(to_api_result(response, table.as_ref().filter(|_| operation.is_read())))
+    };
+    match tokio::task::spawn_blocking(execute).await {
+        Ok(result) => result.unwrap_or_else(bad_request),
+        Err(_) => api_error(StatusCode::Unknown),
+    }
+}
+
+/// The table of the dictionary in `dir` describing the file a request is
+/// for: the one it opens, or the one its position block names
+fn table_for(shared: &Shared, dir: &str, request: &OperationRequest) -> Result<Table, String> {
+    let file = match &request.file_path {
+        Some(path) => Some(shared.roots.resolve(path).ok_or("the path leaves its data root")?),
+        None => PositionBlock::from_bytes(&request.position_block).file_path(),
+    };
+    let Some(file) = file else {
+        return Err("no file to look up in the dictionary".to_string());
+    };
+
+    let session = server::next_session_id();
+    let mut client = XtrieveClient::with_transport(Box::new(EngineTransport {
+        engine: shared.engine.clone(),
+        roots: shared.roots.clone(),
+        session_id: session,
+        cancel: CancelToken::new(),
+    }));
+    let loaded = Dictionary::load(&mut client, dir);
+    shared.engine.end_session(session);
+    let dictionary = loaded.map_err(|e| format!("loading the dictionary in {}: {}", dir, e))?;
+
+    // Dictionaries name files in any case, often from beside the data
+    let file = lexical(&file);
+    dictionary
+        .tables
+        .into_iter()
+        .find(|table| shared.roots.resolve(&table.file_path(dir)).is_some_and(|path| lexical(&path).eq_ignore_ascii_case(&file)))
+        .ok_or_else(|| format!("no table in the dictionary for {}", file))
+}
+
+/// A path with `.` and `..` worked out, without going to the disk
+fn lexical(path: &std::path::Path) -> String {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    normal.to_string_lossy().to_string()
+}
+
+/// Write column values over a record, widened to hold every column
+fn store_fields(table: &Table, fields: &Map<String, Value>, record: &mut Vec<u8>) -> Result<(), String> {
+    if record.len() < table.record_length() {
+        record.resize(table.record_length(), 0);
+    }
+    for (name, value) in fields {
+        let column = table
+            .column(name)
+            .ok_or_else(|| format!("no column {} in {}", name, table.name))?;
+        let text = match value {
+            Value::String(text) => text.clone(),
+            Value::Null => String::new(),
+            other => other.to_string(),
+        };
+        column.encode(&text, record).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// A record's columns as JSON
+fn read_fields(table: &Table, record: &[u8]) -> Option<Map<String, Value>> {
+    let columns = table.decode(record).ok()?;
+    Some(columns.into_iter().map(|(name, value)| (name.to_string(), json_value(value))).collect())
+}
+
+/// Numbers, text and truth values as themselves; decimals (to keep their
+/// digits), dates, times and raw bytes as they display
+fn json_value(value: ddf::Value) -> Value {
+    match value {
+        ddf::Value::Null => Value::Null,
+        ddf::Value::Int(v) => v.into(),
+        ddf::Value::UInt(v) => v.into(),
+        ddf::Value::Float(v) => serde_json::Number::from_f64(v).map_or(Value::Null, Value::Number),
+        ddf::Value::Bool(v) => v.into(),
+        ddf::Value::Text(v) => v.into(),
+        other => other.to_string().into(),
+    }
+}
+
+fn build_request(operation: OperationCode, body: ApiRequest) -> Result<OperationRequest, String> {
+    Ok(OperationRequest {
+        operation,
+        file_path: if body.path.is_empty() { None } else { Some(body.path) },
+        position_block: decode("position_block", &body.position_block)?,
+        data_buffer: decode("data", &body.data)?,
+        key_buffer: decode("key", &body.key)?,
+        key_number: body.key_number,
+        open_mode: body.mode,
+        lock_bias: body.lock_bias,
+        ..Default::default()
+    })
+}
+
+fn to_api_result(response: OperationResponse, table: Option<&Table>) -> ApiResult {
+    if response.status != StatusCode::Success {
+        let (http, mut body) = api_error(response.status);
+        if let Some(detail) = response.detail {
+            body.message = format!("{}: {}", response.status, detail);
+        }
+        return (http, body);
+    }
+    (
+        HttpStatus::OK,
+        Json(ApiResponse {
+            status: 0,
+            message: response.status.to_string(),
+            position_block: BASE64.encode(&response.position_block),
+            data: BASE64.encode(&response.data_buffer),
+            key: BASE64.encode(&response.key_buffer),
+            fields: table.and_then(|table| read_fields(table, &response.data_buffer)),
+        }),
+    )
+}
+
+async fn open(State(shared): State<Arc<Shared>>, Json(body): Json<ApiRequest>) -> ApiResult {
+    run(shared, OperationCode::Open, body).await
+}
+
+async fn close(State(shared): State<Arc<Shared>>, Json(body): Json<ApiRequest>) -> ApiResult {
+    run(shared, OperationCode::Close, body).await
+}
+
+async fn get(
+    State(shared): State<Arc<Shared>>,
+    Path(op): Path<String>,
+    Json(body): Json<ApiRequest>,
+) -> ApiResult {
+    match get_operation(&op) {
+        Some(operation) => run(shared, operation, body).await,
+        None => api_error(StatusCode::InvalidOperation),
+    }
+}
+
+async fn insert(State(shared): State<Arc<Shared>>, Json(body): Json<ApiRequest>) -> ApiResult {
+    run(shared, OperationCode::Insert, body).await
+}
+
+async fn update(State(shared): State<Arc<Shared>>, Json(body): Json<ApiRequest>) -> ApiResult {
+    run(shared, OperationCode::Update, body).await
+}
+
+async fn delete(State(shared): State<Arc<Shared>>, Json(body): Json<ApiRequest>) -> ApiResult {
+    run(shared, OperationCode::Delete, body).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtrieve_engine::operations::Engine;
+    use xtrieve_engine::storage::fcr::FileControlRecord;
+
+    use crate::server::DataRoots;
+
+    fn serve(dir: &std::path::Path) -> Arc<Shared> {
+        let roots = DataRoots::parse(&[dir.to_string_lossy().to_string()]).unwrap();
+        Arc::new(Shared::new(Arc::new(Engine::new(64)), roots))
+    }
+
+    /// Create PARTS.DAT: 18-byte records keyed on their first 4 bytes
+    fn create_parts(shared: &Shared) {
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&18u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&1024u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[26] = 14;
+        let created = shared.execute(1, OperationRequest {
+            operation: OperationCode::Create,
+            file_path: Some("PARTS.DAT".to_string()),
+            data_buffer: spec,
+            ..Default::default()
+        });
+        assert_eq!(created.status, StatusCode::Success);
+    }
+
+    /// Write `records` to `path` the way Btrieve 5.1 lays out a file
+    /// without keys, as dictionaries come: the FCR, then one data page
+    fn write_v51(path: &std::path::Path, record_length: u16, records: &[Vec<u8>]) {
+        let mut fcr = FileControlRecord::new(record_length, 1024, Vec::new());
+        fcr.num_pages = 3;
+        fcr.num_records = records.len() as u64;
+        let mut file = fcr.to_bytes();
+        // Page 2 holds the records, its header marking it a data page
+        file.resize(2 * 1024, 0);
+        file.extend_from_slice(&[0, 0, 2, 0]);
+        file.extend_from_slice(&(0x8000 | records.len() as u16).to_le_bytes());
+        records.iter().for_each(|record| file.extend_from_slice(record));
+        file.resize(3 * 1024, 0);
+        std::fs::write(path, file).unwrap();
+    }
+
+    /// A dictionary in `ddf/` describing PARTS.DAT beside it: Id (integer),
+    /// Name (string) and Price (money)
+    fn describe_parts(shared: &Shared) {
+        let dir = shared.roots.default_dir().join("ddf");
+        std::fs::create_dir(&dir).unwrap();
+        let mut table = vec![b' '; 87];
+        table[0..2].copy_from_slice(&1u16.to_le_bytes());
+        table[2..7].copy_from_slice(b"Parts");
+        table[22..34].copy_from_slice(b"..\\PARTS.DAT");
+        table[86] = 0;
+        let column = |id: u16, name: &str, data_type: u8, offset: u16, size: u16, decimals: u8| {
+            let mut field = vec![b' '; 32];
+            field[0..2].copy_from_slice(&id.to_le_bytes());
+            field[2..4].copy_from_slice(&1u16.to_le_bytes());
+            field[4..4 + name.len()].copy_from_slice(name.as_bytes());
+            field[24] = data_type;
+            field[25..27].copy_from_slice(&offset.to_le_bytes());
+            field[27..29].copy_from_slice(&size.to_le_bytes());
+            field[29] = decimals;
+            field[30..32].fill(0);
+            field
+        };
+        let fields = [column(1, "Id", 1, 0, 4, 0), column(2, "Name", 0, 4, 10, 0), column(3, "Price", 6, 14, 4, 2)];
+        let index = [1u16, 1, 0, 0, 0].iter().flat_map(|word| word.to_le_bytes()).collect();
+        write_v51(&dir.join("FILE.DDF"), 87, &[table]);
+        write_v51(&dir.join("FIELD.DDF"), 32, &fields);
+        write_v51(&dir.join("INDEX.DDF"), 10, &[index]);
+    }
+
+    fn request(body: serde_json::Value) -> Json<ApiRequest> {
+        Json(serde_json::from_value(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_records_as_base64() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = serve(dir.path());
+        create_parts(&shared);
+
+        let (http, Json(opened)) = open(State(shared.clone()), request(serde_json::json!({"path": "PARTS.DAT"}))).await;
+        assert_eq!(http, HttpStatus::OK);
+        let record = [7u32.to_le_bytes().as_slice(), b"Ada       ", &[0, 0, 0x12, 0x5c]].concat();
+        let body = serde_json::json!({"position_block": opened.position_block, "data": BASE64.encode(&record)});
+        let (http, Json(inserted)) = insert(State(shared.clone()), request(body)).await;
+        assert_eq!((http, inserted.status), (HttpStatus::OK, 0));
+
+        let body = serde_json::json!({"position_block": inserted.position_block});
+        let (http, Json(first)) = get(State(shared.clone()), Path("first".to_string()), request(body)).await;
+        assert_eq!(http, HttpStatus::OK);
+        assert_eq!(BASE64.decode(&first.data).unwrap(), record);
+        assert!(first.fields.is_none());
+        let body = serde_json::json!({"position_block": first.position_block});
+        let (http, Json(next)) = get(State(shared.clone()), Path("next".to_string()), request(body)).await;
+        assert_eq!((http, next.status), (HttpStatus::NOT_FOUND, StatusCode::EndOfFile.as_raw()));
+
+        let body = serde_json::json!({"position_block": first.position_block});
+        let (http, _) = get(State(shared.clone()), Path("sideways".to_string()), request(body)).await;
+        assert_eq!(http, HttpStatus::BAD_REQUEST);
+        let (http, Json(refused)) = insert(State(shared), request(serde_json::json!({"data": "not base64!"}))).await;
+        assert_eq!((http, refused.message.as_str()), (HttpStatus::BAD_REQUEST, "invalid base64 in data"));
+    }
+
+    #[tokio::test]
+    async fn test_fields_through_a_dictionary() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = serve(dir.path());
+        create_parts(&shared);
+        describe_parts(&shared);
+
+        let body = serde_json::json!({"path": "PARTS.DAT", "dictionary": "ddf"});
+        let (http, Json(opened)) = open(State(shared.clone()), request(body)).await;
+        assert_eq!(http, HttpStatus::OK, "{}", opened.message);
+        let block = opened.position_block;
+
+        // Fields are stored over blanks and read back decoded
+        let body = serde_json::json!({
+            "position_block": block,
+            "dictionary": "ddf",
+            "fields": {"Id": 7, "name": "Ada", "Price": "12.50"},
+        });
+        let (http, Json(inserted)) = insert(State(shared.clone()), request(body)).await;
+        assert_eq!((http, inserted.status), (HttpStatus::OK, 0));
+        let body = serde_json::json!({"position_block": block, "dictionary": "ddf"});
+        let (_, Json(first)) = get(State(shared.clone()), Path("first".to_string()), request(body)).await;
+        let fields = serde_json::Value::Object(first.fields.unwrap());
+        assert_eq!(fields, serde_json::json!({"Id": 7, "Name": "Ada", "Price": "12.50"}));
+        assert_eq!(BASE64.decode(&first.data).unwrap()[..4], 7u32.to_le_bytes());
+
+        // Fields change only themselves on Update
+        let body = serde_json::json!({
+            "position_block": first.position_block,
+            "dictionary": "ddf",
+            "data": first.data,
+            "fields": {"Price": 9.5},
+        });
+        let (http, Json(updated)) = update(State(shared.clone()), request(body)).await;
+        assert_eq!(http, HttpStatus::OK, "{}", updated.message);
+        let body = serde_json::json!({"position_block": updated.position_block, "dictionary": "ddf"});
+        let (_, Json(first)) = get(State(shared.clone()), Path("first".to_string()), request(body)).await;
+        assert_eq!(first.fields.unwrap()["Price"], "9.50");
+
+        for (body, message) in [
+            (serde_json::json!({"position_block": block, "fields": {"Id": 8}}), "fields need a dictionary"),
+            (serde_json::json!({"position_block": block, "dictionary": "ddf", "fields": {"Colour": "red"}}), "no column Colour in Parts"),
+            (serde_json::json!({"position_block": block, "dictionary": "ddf", "fields": {"Id": "eight"}}), "Id: cannot store 'eight' as Integer"),
+        ] {
+            let (http, Json(refused)) = insert(State(shared.clone()), request(body)).await;
+            assert_eq!(http, HttpStatus::BAD_REQUEST);
+            assert!(refused.message.contains(message), "{}", refused.message);
+        }
+    }
+}
@@ -3,23 +3,39 @@
 //! This daemon provides TCP access to Btrieve file operations using a
 //! simple binary protocol similar to original Btrieve.
 
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::net::{TcpListener, TcpStream, SocketAddr};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Parser;
 use tracing::{info, warn, error, debug, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use xtrieve_engine::operations::{Engine, OperationCode, OperationRequest};
-use xtrieve_engine::file_manager::cursor::PositionBlock;
-use xtrieve_engine::protocol::{Request, Response};
-
+use xtrieve_engine::file_manager::Coherence;
+use xtrieve_engine::operations::{CreateLimits, Engine};
+use xtrieve_engine::protocol::Request;
+use xtrieve_engine::storage::{check_dir, SpecLayout, PAGE_SIZES};
+
+#[cfg(feature = "grpc")]
+mod grpc;
+mod admission;
+mod health;
+#[cfg(feature = "http")]
+mod http;
+mod logging;
+mod metrics;
+mod rules;
 mod server;
+mod workers;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+use admission::Admission;
+use server::{ConnectionStats, DataRoots, Shared, TcpTuning};
 
 /// Xtrieve daemon - Btrieve 5.1 compatible database server
 #[derive(Parser, Debug)]
@@ -34,39 +50,194 @@ struct Args {
     #[arg(short, long, default_value_t = 10000)]
     cache_size: usize,
 
-    /// Data directory for relative paths
+    /// Data directory for relative paths. Repeat as NAME=DIR to serve
+    /// DIR under the logical prefix NAME (NAME:CUST.DAT)
     #[arg(short, long, default_value = "./data")]
-    data_dir: PathBuf,
+    data_dir: Vec<String>,
+
+    /// Seconds of idle time before TCP keepalive probes start (0 = off)
+    #[arg(long, default_value_t = 60)]
+    keepalive: u64,
+
+    /// Seconds between TCP keepalive probes (0 = the same as --keepalive)
+    #[arg(long, default_value_t = 0)]
+    keepalive_interval: u64,
+
+    /// Unanswered keepalive probes before a connection is dropped
+    /// (0 = the OS's count)
+    #[arg(long, default_value_t = 0)]
+    keepalive_retries: u32,
+
+    /// Set TCP_NODELAY, so small responses go out at once instead of
+    /// waiting on Nagle's algorithm (`--tcp-nodelay false` to wait)
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    tcp_nodelay: bool,
+
+    /// Socket send buffer size in bytes (0 = the OS's)
+    #[arg(long, default_value_t = 0)]
+    send_buffer: usize,
+
+    /// Socket receive buffer size in bytes (0 = the OS's)
+    #[arg(long, default_value_t = 0)]
+    recv_buffer: usize,
+
+    /// Address for the gRPC service (disabled if not given)
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc_listen: Option<String>,
+
+    /// Address for the HTTP/JSON gateway (disabled if not given)
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    http_listen: Option<String>,
+
+    /// Seconds a session of HTTP requests or unary gRPC calls lives after
+    /// its last call, as they have no connection whose close ends it; its
+    /// transaction is then aborted and its files and locks released
+    /// (0 = never)
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    #[arg(long, default_value_t = 600)]
+    session_idle: u64,
+
+    /// Address for the WebSocket transport (disabled if not given)
+    #[cfg(feature = "websocket")]
+    #[arg(long)]
+    ws_listen: Option<String>,
+
+    /// Unix socket path for local clients (disabled if not given)
+    #[cfg(unix)]
+    #[arg(long)]
+    unix_listen: Option<PathBuf>,
+
+    /// Address to serve Prometheus metrics on, at /metrics, and health
+    /// checks at /healthz and /readyz (disabled if not given)
+    #[arg(long)]
+    metrics_listen: Option<String>,
+
+    /// Record every request and its response to this file (see xtreplay)
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// File holding a 256-bit key, as 64 hex digits, that encrypts the
+    /// pages of every file created (files without it stay readable)
+    #[arg(long)]
+    page_key_file: Option<PathBuf>,
+
+    /// Microseconds a transaction's End waits for others ending at the
+    /// same time to share its fsync (0 = share only syncs already queued)
+    #[arg(long, default_value_t = 0)]
+    commit_window: u64,
+
+    /// Page writes each file may hold back in its write queue, written
+    /// out in page order with adjacent pages together (0 = write through)
+    #[arg(long, default_value_t = 0)]
+    write_queue: u32,
+
+    /// Milliseconds between the writer thread's passes over the write
+    /// queues
+    #[arg(long, default_value_t = 50)]
+    write_queue_interval: u64,
+
+    /// Threads running file operations, each file always on the same one
+    /// (0 = every connection runs its own)
+    #[arg(long, default_value_t = 0)]
+    workers: usize,
+
+    /// Operations running at once, over every transport (0 = no limit)
+    #[arg(long, default_value_t = 0)]
+    max_operations: usize,
+
+    /// Operations that may wait for --max-operations to free up; past it
+    /// they are answered with status 201 (Server Busy)
+    #[arg(long, default_value_t = 64)]
+    queue_depth: usize,
+
+    /// Binary protocol and WebSocket connections served at once; past it
+    /// new ones are closed (0 = no limit)
+    #[arg(long, default_value_t = 0)]
+    max_connections: usize,
+
+    /// TOML file of per-file record validation rules, checked on every
+    /// Insert and Update
+    #[arg(long)]
+    rules: Option<PathBuf>,
+
+    /// Behave exactly as Btrieve 5.1: no Xtrieve operations, and requests
+    /// held to the original's limits
+    #[arg(long)]
+    strict: bool,
+
+    /// Page size (512, 1024, 2048 or 4096) for Create requests that give 0;
+    /// without it they fail with status 24
+    #[arg(long)]
+    default_page_size: Option<u16>,
+
+    /// Longest record length a Create may ask for
+    #[arg(long)]
+    max_record_length: Option<u16>,
+
+    /// Most memory (MiB) one operation may gather, like every index entry
+    /// of a read in key order; past it the operation fails with status 61
+    #[arg(long)]
+    memory_budget: Option<usize>,
+
+    /// How far cached pages may be trusted, for files on a share other
+    /// engines write: cached, validate (read page 0 back before each
+    /// operation) or none. MODE for the default directory, NAME=MODE for
+    /// the one with that prefix; repeatable
+    #[arg(long)]
+    cache_coherence: Vec<String>,
+
+    /// Let clients delete and rename files (operations 93 and 94); without
+    /// it they are refused with status 45
+    #[arg(long)]
+    file_admin: bool,
+
+    /// Keep a lock file (NAME.LCK) beside each open file, so daemons
+    /// sharing a directory each serve files the others don't
+    #[arg(long)]
+    lock_files: bool,
+
+    /// Check every file in the data directories before serving: refuse
+    /// those with a bad FCR or left mid-transaction by a crash, and remove
+    /// stale work files. Findings are logged one per line under the
+    /// `self_check` target
+    #[arg(long)]
+    self_check: bool,
+
+    /// Layout every Create buffer must have: xtrieve, classic (Btrieve's,
+    /// with 1-based key positions) or compact (the SDKs'). Without it a
+    /// buffer is read as compact or Xtrieve's by its length
+    #[arg(long)]
+    create_layout: Option<String>,
 
     /// Log level (trace, debug, info, warn, error)
     #[arg(long, default_value = "info")]
     log_level: String,
-}
 
-/// Session ID counter
-static SESSION_COUNTER: AtomicU64 = AtomicU64::new(1);
-
-fn resolve_path(data_dir: &PathBuf, path: &str) -> PathBuf {
-    let path = PathBuf::from(path);
-    if path.is_absolute() {
-        path
-    } else {
-        data_dir.join(path)
-    }
+    /// Log format: text, or json for one JSON object per line with each
+    /// event's fields. Every operation is logged (session, op, file,
+    /// status, duration) at debug level
+    #[arg(long, default_value = "text")]
+    log_format: String,
 }
 
-fn handle_client(
-    stream: TcpStream,
-    engine: Arc<Engine>,
-    data_dir: PathBuf,
-) {
+fn handle_client(stream: TcpStream, shared: Arc<Shared>) {
     let peer = stream.peer_addr().ok();
-    debug!("Client connected: {:?}", peer);
+    let reader = stream.try_clone().expect("Failed to clone stream");
+    serve(reader, stream, format!("{:?}", peer), shared);
+}
 
-    let session_id = SESSION_COUNTER.fetch_add(1, Ordering::SeqCst);
+/// Answer binary protocol requests on one connection until it closes
+fn serve(reader: impl Read, writer: impl Write, peer: String, shared: Arc<Shared>) {
+    debug!("Client connected: {}", peer);
 
-    let mut reader = BufReader::new(stream.try_clone().expect("Failed to clone stream"));
-    let mut writer = BufWriter::new(stream);
+    let mut connection = server::Connection::new();
+    let session_id = connection.session;
+    let mut stats = ConnectionStats::new();
+
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
 
     loop {
         // Read request
@@ -74,7 +245,7 @@ fn handle_client(
             Ok(r) => r,
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                    debug!("Client disconnected: {:?}", peer);
+                    debug!("Client disconnected: {}", peer);
                 } else {
                     warn!("Error reading request: {}", e);
                 }
@@ -84,47 +255,8 @@ fn handle_client(
 
         debug!("Op {} from session {}", req.operation_code, session_id);
 
-        // Extract session from position block if available
-        let pos_block = PositionBlock::from_bytes(&req.position_block);
-        let stored_session = pos_block.get_session_id();
-        let effective_session = if stored_session > 0 {
-            stored_session
-        } else {
-            session_id
-        };
-
-        // Convert to engine request
-        let engine_req = OperationRequest {
-            operation: OperationCode::from_raw(req.operation_code as u32),
-            file_path: if req.file_path.is_empty() {
-                None
-            } else {
-                Some(resolve_path(&data_dir, &req.file_path).to_string_lossy().to_string())
-            },
-            position_block: req.position_block,
-            data_buffer: req.data_buffer,
-            key_buffer: req.key_buffer,
-            key_number: req.key_number as i32,
-            data_length: 0,
-            key_length: 0,
-            open_mode: 0,
-            lock_bias: req.lock_bias as i32,
-        };
-
-        // Execute
-        let result = engine.execute(effective_session, engine_req);
-
-        // Store session in position block
-        let mut result_pos_block = PositionBlock::from_bytes(&result.position_block);
-        result_pos_block.set_session_id(effective_session);
-
-        // Build response
-        let response = Response {
-            status_code: result.status.as_raw() as u16,
-            position_block: result_pos_block.data.to_vec(),
-            data_buffer: result.data_buffer,
-            key_buffer: result.key_buffer,
-        };
+        let response = shared.execute_wire(&mut connection, req);
+        stats.record_request(response.status_code == 0);
 
         // Send response
         if let Err(e) = writer.write_all(&response.to_bytes()) {
@@ -136,6 +268,58 @@ fn handle_client(
             break;
         }
     }
+
+    debug!(
+        "Session {} closed: {} requests, {} errors in {:?}",
+        session_id,
+        stats.total_requests,
+        stats.total_errors,
+        stats.uptime()
+    );
+    shared.disconnect(connection);
+}
+
+/// Check the files of every data directory (see `storage::selfcheck`),
+/// refusing those that fail. Each finding is logged with its fields, for
+/// scripts reading the log
+fn self_check(engine: &Engine, roots: &DataRoots) -> Result<()> {
+    let (mut files, mut refused) = (0, 0);
+    for dir in roots.dirs() {
+        for check in check_dir(dir)? {
+            files += 1;
+            let refusal = check.refusal();
+            for finding in &check.findings {
+                warn!(
+                    target: "self_check",
+                    file = %check.path.display(),
+                    finding = finding.kind(),
+                    detail = %finding,
+                    refused = refusal.map(|status| status as i32),
+                    "Self-check finding"
+                );
+            }
+            if let Some(status) = refusal {
+                engine.files.refuse(&check.path, status);
+                refused += 1;
+            }
+        }
+    }
+    info!(target: "self_check", files, refused, "Self-check done");
+    Ok(())
+}
+
+/// Read a page key: 64 hex digits, surrounding whitespace ignored
+fn read_page_key(path: &std::path::Path) -> Result<[u8; 32]> {
+    let text = std::fs::read_to_string(path)?;
+    let hex = text.trim();
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        anyhow::bail!("{} must hold a key of 64 hex digits", path.display());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(key)
 }
 
 fn main() -> Result<()> {
@@ -151,22 +335,59 @@ fn main() -> Result<()> {
         _ => Level::INFO,
     };
 
-    let subscriber = FmtSubscriber::builder()
+    let builder = FmtSubscriber::builder()
         .with_max_level(log_level)
         .with_target(false)
-        .with_thread_ids(false)
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber)?;
+        .with_thread_ids(false);
+    match args.log_format.to_lowercase().as_str() {
+        "text" => tracing::subscriber::set_global_default(builder.finish())?,
+        "json" => tracing::subscriber::set_global_default(builder.event_format(logging::Json).finish())?,
+        _ => bail!("--log-format must be text or json"),
+    }
 
-    // Create data directory if needed
-    std::fs::create_dir_all(&args.data_dir)?;
+    // Create data directories if needed
+    let roots = DataRoots::parse(&args.data_dir)?;
+    for dir in roots.dirs() {
+        std::fs::create_dir_all(dir)?;
+    }
 
     // Parse listen address
     let addr: SocketAddr = args.listen.parse()?;
 
     // Create engine
-    let engine = Arc::new(Engine::new(args.cache_size));
+    let engine = match &args.page_key_file {
+        Some(path) => Engine::with_page_key(args.cache_size, read_page_key(path)?),
+        None => Engine::new(args.cache_size),
+    };
+    let engine = match args.strict {
+        true if args.page_key_file.is_some() => bail!("--strict can't create files sealed with --page-key-file"),
+        true => engine.with_strict_compat(),
+        false => engine,
+    };
+    if let Some(size) = args.default_page_size {
+        if !PAGE_SIZES.contains(&size) {
+            bail!("--default-page-size must be one of {:?}", PAGE_SIZES);
+        }
+    }
+    let create_layout = match args.create_layout.as_deref().map(SpecLayout::from_name) {
+        Some(None) => bail!("--create-layout must be xtrieve, classic or compact"),
+        layout => layout.flatten(),
+    };
+    let engine = engine.with_create_limits(CreateLimits {
+        default_page_size: args.default_page_size,
+        max_record_length: args.max_record_length,
+        layout: create_layout,
+    });
+    let engine = match args.memory_budget {
+        Some(0) => bail!("--memory-budget must be at least 1 MiB"),
+        Some(mib) => engine.with_memory_budget(mib.saturating_mul(1 << 20)),
+        None => engine,
+    };
+    let engine = match args.file_admin {
+        true => engine,
+        false => engine.without_file_admin(),
+    };
+    let engine = Arc::new(engine);
 
     // Classic Btrieve-style startup banner
     println!();
@@ -175,20 +396,185 @@ fn main() -> Result<()> {
     println!();
 
     info!("Listening on {}", addr);
-    info!("Data directory: {}", args.data_dir.display());
+    info!("Data directory: {}", roots.default_dir().display());
+    for (name, dir) in roots.prefixed() {
+        info!("Data directory {}: {}", name, dir.display());
+    }
     info!("Cache size: {} pages", args.cache_size);
+    if args.strict {
+        info!("Strict Btrieve 5.1 mode: Xtrieve extensions off");
+    }
+    if args.file_admin {
+        info!("Clients may delete and rename files");
+    }
+    if let Some(size) = args.default_page_size {
+        info!("Default page size for Create: {}", size);
+    }
+    if let Some(length) = args.max_record_length {
+        info!("Longest record length for Create: {}", length);
+    }
+    if let Some(layout) = create_layout {
+        info!("Create buffer layout: {:?}", layout);
+    }
+    if let Some(mib) = args.memory_budget {
+        info!("Memory budget: {} MiB per operation", mib);
+    }
+    if args.commit_window > 0 {
+        engine.files.set_commit_window(Duration::from_micros(args.commit_window));
+        info!("Group commit window: {} us", args.commit_window);
+    }
+    if args.write_queue > 0 {
+        if args.write_queue_interval == 0 {
+            bail!("--write-queue-interval must be at least 1 ms");
+        }
+        engine.files.set_write_queue(args.write_queue);
+        info!("Write queue: {} pages per file, written every {} ms", args.write_queue, args.write_queue_interval);
+        let (engine, interval) = (engine.clone(), Duration::from_millis(args.write_queue_interval));
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if let Err(e) = engine.files.write_queued() {
+                error!("Writing queued pages failed: {}", e);
+            }
+        });
+    }
+    if let Some(path) = &args.page_key_file {
+        info!("Encrypting new files with the key in {}", path.display());
+    }
+    if let Some(path) = &args.rules {
+        let validator = rules::load(path, &engine, &roots)?;
+        info!("Validating records of {} files with the rules in {}", validator.len(), path.display());
+        engine.add_hook(Arc::new(validator));
+    }
+
+    let admission = Arc::new(Admission::new(args.max_operations, args.queue_depth, args.max_connections));
+    if args.max_operations > 0 {
+        info!("Running at most {} operations at once, {} more waiting", args.max_operations, args.queue_depth);
+    }
+    if args.max_connections > 0 {
+        info!("Serving at most {} connections", args.max_connections);
+    }
+
+    // Up before the self-check, so readiness says the daemon is starting
+    let health = Arc::new(health::Health::new(engine.clone(), roots.clone()));
+    if let Some(metrics_listen) = &args.metrics_listen {
+        let metrics_addr: SocketAddr = metrics_listen.parse()?;
+        let prometheus = Arc::new(metrics::Prometheus::new(admission.clone()));
+        engine.add_metrics(prometheus.clone());
+        info!("Metrics and health checks listening on {}", metrics_addr);
+        metrics::spawn(metrics_addr, engine.clone(), prometheus, health.clone())?;
+    }
+
+    if args.self_check {
+        self_check(&engine, &roots)?;
+    }
+    for setting in &args.cache_coherence {
+        let (dir, name) = match setting.split_once('=') {
+            Some((prefix, name)) => match roots.prefixed().iter().find(|(p, _)| p.eq_ignore_ascii_case(prefix)) {
+                Some((_, dir)) => (dir.as_path(), name),
+                None => bail!("--cache-coherence names no data directory {}", prefix),
+            },
+            None => (roots.default_dir(), setting.as_str()),
+        };
+        let Some(coherence) = Coherence::from_name(name) else {
+            bail!("--cache-coherence must be cached, validate or none");
+        };
+        engine.files.set_coherence(dir, coherence);
+        info!("Cache coherence for {}: {:?}", dir.display(), coherence);
+    }
+    if args.lock_files {
+        engine.files.set_lock_files(true);
+        info!("Claiming open files with lock files");
+    }
+
+    let mut shared = Shared::new(engine, roots);
+    shared.admission = admission;
+    if let Some(path) = &args.trace {
+        info!("Tracing requests to {}", path.display());
+        shared.trace = Some(server::Tracer::create(path)?);
+    }
+    if args.workers > 0 {
+        let pool = workers::WorkerPool::new(args.workers)?;
+        info!("Running file operations on {} workers", pool.len());
+        shared.workers = Some(pool);
+    }
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    {
+        shared.session_idle = Duration::from_secs(args.session_idle);
+    }
+    let shared = Arc::new(shared);
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    if args.session_idle > 0 {
+        let (shared, every) = (shared.clone(), Duration::from_secs(args.session_idle.clamp(1, 60)));
+        thread::spawn(move || loop {
+            thread::sleep(every);
+            let ended = shared.end_idle();
+            if ended > 0 {
+                debug!("Ended {} idle sessions", ended);
+            }
+        });
+    }
+
+    let tuning = TcpTuning {
+        nodelay: args.tcp_nodelay,
+        keepalive: args.keepalive,
+        keepalive_interval: args.keepalive_interval,
+        keepalive_retries: args.keepalive_retries,
+        send_buffer: args.send_buffer,
+        recv_buffer: args.recv_buffer,
+    };
+    if !tuning.nodelay {
+        info!("TCP_NODELAY off: small responses wait on Nagle's algorithm");
+    }
+    if tuning.send_buffer > 0 || tuning.recv_buffer > 0 {
+        info!("Socket buffers: send {} bytes, receive {} bytes (0 = the OS's)", tuning.send_buffer, tuning.recv_buffer);
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_listen) = &args.grpc_listen {
+        let grpc_addr: SocketAddr = grpc_listen.parse()?;
+        info!("gRPC listening on {}", grpc_addr);
+        grpc::spawn(grpc_addr, shared.clone())?;
+    }
+
+    #[cfg(feature = "http")]
+    if let Some(http_listen) = &args.http_listen {
+        let http_addr: SocketAddr = http_listen.parse()?;
+        info!("HTTP gateway listening on {}", http_addr);
+        http::spawn(http_addr, shared.clone())?;
+    }
+
+    #[cfg(feature = "websocket")]
+    if let Some(ws_listen) = &args.ws_listen {
+        let ws_addr: SocketAddr = ws_listen.parse()?;
+        info!("WebSocket listening on {}", ws_addr);
+        websocket::spawn(ws_addr, shared.clone(), tuning)?;
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = &args.unix_listen {
+        info!("Unix socket listening on {}", path.display());
+        spawn_unix(path, shared.clone())?;
+    }
 
     // Bind TCP listener
     let listener = TcpListener::bind(addr)?;
+    health.set_started();
 
     // Accept connections
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
-                let engine = engine.clone();
-                let data_dir = args.data_dir.clone();
+                let Some(connected) = shared.admission.connect() else {
+                    warn!("Closing connection: --max-connections reached");
+                    continue;
+                };
+                if let Err(e) = tuning.apply(&stream) {
+                    warn!("Could not tune connection: {}", e);
+                }
+                let shared = shared.clone();
                 thread::spawn(move || {
-                    handle_client(stream, engine, data_dir);
+                    handle_client(stream, shared);
+                    drop(connected);
                 });
             }
             Err(e) => {
@@ -199,3 +585,38 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Serve the binary protocol on a Unix socket from a background thread
+#[cfg(unix)]
+fn spawn_unix(path: &std::path::Path, shared: Arc<Shared>) -> Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    // A socket file left by an earlier run would make bind fail
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let Some(connected) = shared.admission.connect() else {
+                        warn!("Closing unix connection: --max-connections reached");
+                        continue;
+                    };
+                    let shared = shared.clone();
+                    thread::spawn(move || {
+                        match stream.try_clone() {
+                            Ok(reader) => serve(reader, stream, "unix socket".to_string(), shared),
+                            Err(e) => warn!("Failed to clone unix stream: {}", e),
+                        }
+                        drop(connected);
+                    });
+                }
+                Err(e) => error!("Unix accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
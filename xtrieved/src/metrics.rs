@@ -0,0 +1,170 @@
+//! Prometheus metrics endpoint
+//!
+//! Counts operations by code and status and times them into a histogram,
+//! through the engine's `Metrics` trait, and serves them with the engine's
+//! own counters in the Prometheus text format on `GET /metrics`. The same
+//! listener answers `GET /healthz` and `GET /readyz` (see `health`): 200
+//! and "ok", or 503 and why not, a reason a line.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::admission::Admission;
+use crate::health::Health;
+
+use xtrieve_engine::error::StatusCode;
+use xtrieve_engine::operations::{Engine, OperationCode};
+use xtrieve_engine::{EngineStats, Metrics};
+
+/// Upper bounds (seconds) of the operation latency buckets
+const BUCKETS: [f64; 10] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.25, 1.0];
+
+/// Latency histogram of one operation
+#[derive(Default)]
+struct Latency {
+    /// Operations at or under each bucket's bound
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// By operation name and status code
+    operations: BTreeMap<(String, i32), u64>,
+    latency: BTreeMap<String, Latency>,
+}
+
+/// The daemon's `Metrics`, rendered for Prometheus
+pub struct Prometheus {
+    counters: Mutex<Counters>,
+    admission: Arc<Admission>,
+}
+
+impl Metrics for Prometheus {
+    fn operation(&self, operation: OperationCode, status: StatusCode, elapsed: Duration) {
+        let name = format!("{:?}", operation);
+        let seconds = elapsed.as_secs_f64();
+        let mut counters = self.counters.lock().unwrap();
+        *counters.operations.entry((name.clone(), status as i32)).or_default() += 1;
+        let latency = counters.latency.entry(name).or_default();
+        for (bound, count) in BUCKETS.iter().zip(latency.buckets.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        latency.count += 1;
+        latency.sum += seconds;
+    }
+}
+
+impl Prometheus {
+    pub fn new(admission: Arc<Admission>) -> Self {
+        Prometheus { counters: Mutex::new(Counters::default()), admission }
+    }
+
+    /// Everything counted, with the engine's counters, in the text format
+    pub fn render(&self, stats: &EngineStats) -> String {
+        let mut out = String::new();
+        let counters = self.counters.lock().unwrap();
+
+        out.push_str("# HELP xtrieve_operations_total Operations finished, by code and status\n");
+        out.push_str("# TYPE xtrieve_operations_total counter\n");
+        for ((operation, status), count) in &counters.operations {
+            let _ = writeln!(out, "xtrieve_operations_total{{operation=\"{}\",status=\"{}\"}} {}", operation, status, count);
+        }
+
+        out.push_str("# HELP xtrieve_operation_seconds Time operations took\n");
+        out.push_str("# TYPE xtrieve_operation_seconds histogram\n");
+        for (operation, latency) in &counters.latency {
+            for (bound, count) in BUCKETS.iter().zip(latency.buckets.iter()) {
+                let _ = writeln!(out, "xtrieve_operation_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}", operation, bound, count);
+            }
+            let _ = writeln!(out, "xtrieve_operation_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}", operation, latency.count);
+            let _ = writeln!(out, "xtrieve_operation_seconds_sum{{operation=\"{}\"}} {}", operation, latency.sum);
+            let _ = writeln!(out, "xtrieve_operation_seconds_count{{operation=\"{}\"}} {}", operation, latency.count);
+        }
+
+        let metric = |out: &mut String, name: &str, kind: &str, help: &str, value: &dyn fmt::Display| {
+            let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+        };
+        let page_cache = &stats.page_cache;
+        metric(&mut out, "xtrieve_page_cache_hits_total", "counter", "Page reads found in the cache", &page_cache.hits);
+        metric(&mut out, "xtrieve_page_cache_misses_total", "counter", "Page reads that went to disk", &page_cache.misses);
+        metric(&mut out, "xtrieve_page_cache_evictions_total", "counter", "Pages evicted from the cache", &page_cache.evictions);
+        metric(&mut out, "xtrieve_page_cache_pages", "gauge", "Pages in the cache", &stats.cached_pages);
+        let record_cache = &stats.record_cache;
+        metric(&mut out, "xtrieve_record_cache_hits_total", "counter", "Record reads found in a record cache", &record_cache.hits);
+        metric(&mut out, "xtrieve_record_cache_misses_total", "counter", "Record reads not in a record cache", &record_cache.misses);
+        let locks = &stats.locks;
+        metric(&mut out, "xtrieve_lock_conflicts_total", "counter", "Requests turned away with Record In Use", &locks.conflicts);
+        metric(&mut out, "xtrieve_lock_waits_total", "counter", "Waits for a record lock", &locks.waits);
+        metric(&mut out, "xtrieve_lock_timeouts_total", "counter", "Waits for a record lock that gave up", &locks.timeouts);
+        metric(&mut out, "xtrieve_lock_wait_seconds_total", "counter", "Time spent waiting for record locks", &locks.total_wait.as_secs_f64());
+        metric(&mut out, "xtrieve_open_files", "gauge", "Files open", &stats.open_files);
+        let admission = self.admission.stats();
+        metric(&mut out, "xtrieve_operations_running", "gauge", "Operations running", &admission.running);
+        metric(&mut out, "xtrieve_operations_queued", "gauge", "Operations waiting to run", &admission.queued);
+        metric(&mut out, "xtrieve_operations_busy_total", "counter", "Operations answered Server Busy", &admission.refused);
+        metric(&mut out, "xtrieve_connections", "gauge", "Connections served", &admission.connections);
+        metric(&mut out, "xtrieve_connections_rejected_total", "counter", "Connections closed over the limit", &admission.rejected);
+        out
+    }
+}
+
+/// Serve metrics and health on `addr` from a background thread
+pub fn spawn(addr: SocketAddr, engine: Arc<Engine>, metrics: Arc<Prometheus>, health: Arc<Health>) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = answer(stream, &engine, &metrics, &health) {
+                warn!("Metrics request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer one HTTP request: metrics or health, 404 for anything else
+fn answer(stream: TcpStream, engine: &Engine, metrics: &Prometheus, health: &Health) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers are read and ignored
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, content_type, body) = match request_line.split(' ').take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", "text/plain; version=0.0.4", metrics.render(&engine.stats())),
+        ["GET", "/healthz"] => checked(health.live().map_err(|problem| vec![problem])),
+        ["GET", "/readyz"] => checked(health.ready()),
+        _ => ("404 Not Found", "text/plain", String::new()),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Status line, content type and body answering a health check
+fn checked(result: Result<(), Vec<String>>) -> (&'static str, &'static str, String) {
+    match result {
+        Ok(()) => ("200 OK", "text/plain", "ok\n".to_string()),
+        Err(problems) => ("503 Service Unavailable", "text/plain", problems.join("\n") + "\n"),
+    }
+}
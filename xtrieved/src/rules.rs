@@ -0,0 +1,175 @@
+//! Record validation rules file
+//!
+//! A TOML file given with `--rules`, naming the files whose records are
+//! checked on Insert and Update:
+//!
+//! ```toml
+//! rejection = 40              # status for a broken rule (default 40)
+//!
+//! [[file]]
+//! name = "PARTS.DAT"
+//! required_keys = [0]         # keys that must not be all zero bytes
+//! dictionary = "ddf"          # resolve columns through FILE/FIELD.DDF
+//! table = "Parts"
+//!
+//! [[file.field]]
+//! column = "Quantity"
+//! min = 0
+//!
+//! [[file.field]]
+//! name = "PRICE"              # or give the field outright
+//! offset = 8                  # 0-based
+//! length = 4
+//! type = "unsigned"           # or "integer"
+//! min = 1
+//! max = 100000
+//! ```
+//!
+//! A file with a `table` but no `name` takes its name from the table's
+//! location in the dictionary.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+use xtrieve_client::ddf::{DataType, Dictionary};
+use xtrieve_client::XtrieveClient;
+use xtrieve_engine::operations::validation::{FieldRule, FileRules, RecordValidator, DEFAULT_REJECTION};
+use xtrieve_engine::operations::Engine;
+use xtrieve_engine::{CancelToken, StatusCode};
+
+use crate::server::{next_session_id, DataRoots, EngineTransport};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rejection: Option<u16>,
+    #[serde(default)]
+    file: Vec<FileEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileEntry {
+    name: Option<String>,
+    rejection: Option<u16>,
+    #[serde(default)]
+    required_keys: Vec<usize>,
+    dictionary: Option<String>,
+    table: Option<String>,
+    #[serde(default)]
+    field: Vec<FieldEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldEntry {
+    column: Option<String>,
+    name: Option<String>,
+    offset: Option<u16>,
+    length: Option<u16>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    min: Option<i64>,
+    max: Option<i64>,
+}
+
+/// Build a validator from a rules file, reading any dictionaries it names
+/// through the engine
+pub fn load(path: &Path, engine: &Arc<Engine>, roots: &DataRoots) -> Result<RecordValidator> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let rules: RulesFile = toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+    let default_rejection = match rules.rejection {
+        Some(raw) => status(raw)?,
+        None => DEFAULT_REJECTION,
+    };
+
+    let session = next_session_id();
+    let mut client = XtrieveClient::with_transport(Box::new(EngineTransport {
+        engine: engine.clone(),
+        roots: roots.clone(),
+        session_id: session,
+        cancel: CancelToken::new(),
+    }));
+    let mut validator = RecordValidator::new(engine.files.clone());
+    let loaded = rules.file.into_iter().try_for_each(|entry| {
+        let (name, file_rules) = file_rules(&mut client, entry, default_rejection)?;
+        validator.add(&name, file_rules);
+        Ok::<_, anyhow::Error>(())
+    });
+    engine.end_session(session);
+    loaded?;
+    Ok(validator)
+}
+
+/// Rules of one `[[file]]` entry and the file name they apply to
+fn file_rules(client: &mut XtrieveClient, entry: FileEntry, default_rejection: StatusCode) -> Result<(String, FileRules)> {
+    let table = match (&entry.dictionary, &entry.table) {
+        (dir, Some(table)) => {
+            let dir = dir.as_deref().unwrap_or("");
+            let dictionary = Dictionary::load(client, dir)
+                .map_err(|e| anyhow!("loading the dictionary in {:?}: {}", dir, e))?;
+            let table = dictionary.table(table).ok_or_else(|| anyhow!("no table {} in the dictionary", table))?;
+            Some((table.clone(), dir.to_string()))
+        }
+        (Some(_), None) => bail!("a file with a dictionary must name its table"),
+        (None, None) => None,
+    };
+    let name = match (entry.name, &table) {
+        (Some(name), _) => name,
+        (None, Some((table, dir))) => {
+            let location = PathBuf::from(table.file_path(dir));
+            location.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+        }
+        (None, None) => bail!("a file needs a name or a table"),
+    };
+
+    let mut fields = Vec::new();
+    for field in entry.field {
+        let rule = match &field.column {
+            Some(column) => {
+                let Some((table, _)) = &table else {
+                    bail!("{}: column {} needs a table", name, column);
+                };
+                let col = table.column(column).ok_or_else(|| anyhow!("{}: no column {}", name, column))?;
+                let signed = match col.data_type {
+                    DataType::Integer => true,
+                    DataType::UnsignedBinary | DataType::AutoIncrement => false,
+                    other => bail!("{}: column {} is {:?}, not an integer", name, column, other),
+                };
+                FieldRule { name: col.name.clone(), offset: col.offset, length: col.size, signed, min: field.min, max: field.max }
+            }
+            None => {
+                let field_name = field.name.ok_or_else(|| anyhow!("{}: a field needs a column or a name", name))?;
+                let (Some(offset), Some(length)) = (field.offset, field.length) else {
+                    bail!("{}: field {} needs an offset and a length", name, field_name);
+                };
+                let signed = match field.kind.as_deref().unwrap_or("integer") {
+                    "integer" => true,
+                    "unsigned" => false,
+                    other => bail!("{}: field {} has unknown type {}", name, field_name, other),
+                };
+                FieldRule { name: field_name, offset, length, signed, min: field.min, max: field.max }
+            }
+        };
+        if !matches!(rule.length, 1 | 2 | 4 | 8) {
+            bail!("{}: field {} is {} bytes, not 1, 2, 4 or 8", name, rule.name, rule.length);
+        }
+        fields.push(rule);
+    }
+
+    let rejection = match entry.rejection {
+        Some(raw) => status(raw)?,
+        None => default_rejection,
+    };
+    Ok((name, FileRules { fields, required_keys: entry.required_keys, rejection }))
+}
+
+fn status(raw: u16) -> Result<StatusCode> {
+    match StatusCode::from_raw(raw) {
+        StatusCode::Success => bail!("the rejection status can't be 0"),
+        status => Ok(status),
+    }
+}
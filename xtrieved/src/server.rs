@@ -1,7 +1,911 @@
 //! Server utilities and helpers
 
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+use socket2::{SockRef, TcpKeepalive};
+use tracing::debug;
+use xtrieve_engine::file_manager::cursor::PositionBlock;
+use xtrieve_engine::file_manager::LockType;
+#[cfg(feature = "grpc")]
+use xtrieve_engine::operations::transaction_ops;
+use xtrieve_engine::operations::{Engine, OperationCode, OperationRequest, OperationResponse};
+use xtrieve_engine::protocol::{Request, Response};
+use xtrieve_engine::trace::{self, TraceRecord};
+use xtrieve_engine::{BtrieveError, BtrieveResult, CancelToken, ErrorDetail, ServerInfo, StatusCode};
+use xtrieve_client::{query, BtrieveRequest, BtrieveResponse, Transport, XtrieveClient};
+
+use crate::admission::Admission;
+use crate::workers::WorkerPool;
+
+/// Session ID counter. Server sessions start above the ids clients pick
+/// for themselves (`client_id`), so the two are never mistaken for each
+/// other in logs
+static SESSION_COUNTER: AtomicU64 = AtomicU64::new(FIRST_SERVER_SESSION);
+
+/// First session ID handed out by the server
+pub const FIRST_SERVER_SESSION: u64 = 1 << 32;
+
+/// Allocate a new server-side session ID
+pub fn next_session_id() -> u64 {
+    SESSION_COUNTER.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Resolve a client supplied path against the data directory
+pub fn resolve_path(data_dir: &Path, path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_absolute() {
+        path
+    } else {
+        data_dir.join(path)
+    }
+}
+
+/// The directories client paths lead to: a default one, and any named by
+/// a logical prefix so files on several volumes keep stable paths
+/// (`ACCT:CUST.DAT` with `--data-dir ACCT=/srv/acct`)
+#[derive(Debug, Clone)]
+pub struct DataRoots {
+    default: PathBuf,
+    /// Prefix, upper case, and its directory
+    prefixed: Vec<(String, PathBuf)>,
+}
+
+impl DataRoots {
+    /// Roots from `--data-dir` values: `NAME=dir` for a prefixed root, or
+    /// a plain directory for the default. Without a plain one, the first
+    /// prefixed root is also the default
+    pub fn parse(specs: &[String]) -> anyhow::Result<Self> {
+        let mut default = None;
+        let mut prefixed: Vec<(String, PathBuf)> = Vec::new();
+        for spec in specs {
+            match spec.split_once('=') {
+                Some((name, dir)) if Self::is_prefix(name) => {
+                    let name = name.to_ascii_uppercase();
+                    if prefixed.iter().any(|(n, _)| *n == name) {
+                        anyhow::bail!("data root {} given twice", name);
+                    }
+                    prefixed.push((name, PathBuf::from(dir)));
+                }
+                _ if default.is_some() => anyhow::bail!("only one --data-dir can be without a prefix"),
+                _ => default = Some(PathBuf::from(spec)),
+            }
+        }
+        let default = match (default, prefixed.first()) {
+            (Some(dir), _) => dir,
+            (None, Some((_, dir))) => dir.clone(),
+            (None, None) => anyhow::bail!("no data directory given"),
+        };
+        Ok(DataRoots { default, prefixed })
+    }
+
+    /// Prefixes are two or more letters, digits or underscores, so DOS
+    /// drive letters aren't taken for one
+    fn is_prefix(name: &str) -> bool {
+        name.len() >= 2 && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+    }
+
+    /// Directory of paths without a prefix
+    pub fn default_dir(&self) -> &Path {
+        &self.default
+    }
+
+    /// Every directory, the default first
+    pub fn dirs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.default.as_path()).chain(self.prefixed.iter().map(|(_, dir)| dir.as_path()))
+    }
+
+    /// Prefixed roots, as given
+    pub fn prefixed(&self) -> &[(String, PathBuf)] {
+        &self.prefixed
+    }
+
+    /// Resolve a client supplied path: `NAME:rest` under the root named
+    /// NAME (any case), anything else against the default directory.
+    /// `None` for a prefixed path whose `..` climbs out of its root
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        if let Some((name, rest)) = path.split_once(':') {
+            if let Some((_, dir)) = self.prefixed.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+                let mut depth = 0usize;
+                for part in rest.split(['/', '\\']) {
+                    match part {
+                        "" | "." => {}
+                        ".." => depth = depth.checked_sub(1)?,
+                        _ => depth += 1,
+                    }
+                }
+                return Some(dir.join(rest.trim_start_matches(['/', '\\'])));
+            }
+        }
+        Some(resolve_path(&self.default, path))
+    }
+}
+
+/// Socket options set on each TCP connection accepted
+#[derive(Debug, Clone, Copy)]
+pub struct TcpTuning {
+    /// Send small responses at once rather than waiting (Nagle) to
+    /// coalesce them
+    pub nodelay: bool,
+    /// Seconds of idle time before keepalive probes start (0 = off)
+    pub keepalive: u64,
+    /// Seconds between keepalive probes (0 = the same as `keepalive`)
+    pub keepalive_interval: u64,
+    /// Unanswered probes before the connection is dropped (0 = the OS's)
+    pub keepalive_retries: u32,
+    /// Socket send and receive buffer sizes in bytes (0 = the OS's)
+    pub send_buffer: usize,
+    pub recv_buffer: usize,
+}
+
+impl TcpTuning {
+    /// Set the options on a connection
+    pub fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let socket = SockRef::from(stream);
+        socket.set_nodelay(self.nodelay)?;
+        if self.send_buffer > 0 {
+            socket.set_send_buffer_size(self.send_buffer)?;
+        }
+        if self.recv_buffer > 0 {
+            socket.set_recv_buffer_size(self.recv_buffer)?;
+        }
+        if self.keepalive == 0 {
+            return Ok(());
+        }
+        let interval = match self.keepalive_interval {
+            0 => self.keepalive,
+            secs => secs,
+        };
+        let keepalive = TcpKeepalive::new()
+            .with_time(Duration::from_secs(self.keepalive))
+            .with_interval(Duration::from_secs(interval));
+        // Retries can't be set everywhere; elsewhere the OS's count stands
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd", target_os = "netbsd"))]
+        let keepalive = match self.keepalive_retries {
+            0 => keepalive,
+            retries => keepalive.with_retries(retries),
+        };
+        socket.set_tcp_keepalive(&keepalive)
+    }
+}
+
+/// The session stamped into a position block, 0 for none. It comes back
+/// from the client, so it is only a claim until checked against the
+/// sessions the caller started
+fn block_session(position_block: &[u8]) -> u64 {
+    PositionBlock::from_bytes(position_block).get_session_id()
+}
+
+/// The sessions one client connection has run requests on
+pub struct Connection {
+    /// The connection's own session, for requests that name no other
+    pub session: u64,
+    used: HashSet<u64>,
+    /// Sessions the client named by client id, by that id
+    named: HashMap<u64, u64>,
+}
+
+impl Connection {
+    pub fn new() -> Self {
+        Connection { session: next_session_id(), used: HashSet::new(), named: HashMap::new() }
+    }
+
+    /// The session `client_id` names on this connection, started the
+    /// first time it is used. Another connection naming the same id gets
+    /// a session of its own
+    pub fn named_session(&mut self, client_id: u64) -> u64 {
+        *self.named.entry(client_id).or_insert_with(next_session_id)
+    }
+
+    /// The session a request runs on: the one its client id names, then
+    /// the one in its position block if this connection started it, else
+    /// the connection's own. A block from another connection's session
+    /// reaches nothing of it: its file isn't open on the session used
+    pub fn session_for(&mut self, client_id: u64, position_block: &[u8]) -> u64 {
+        if client_id != 0 {
+            return self.named_session(client_id);
+        }
+        let stored = block_session(position_block);
+        if stored == self.session || self.named.values().any(|&session_id| session_id == stored) {
+            stored
+        } else {
+            self.session
+        }
+    }
+}
+
+/// A committed record change (Insert, Update or Delete), or a Truncate
+/// File emptying the file
+#[cfg(feature = "grpc")]
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// Position in the feed, counting from 1
+    pub sequence: u64,
+    pub file_path: String,
+    pub operation: OperationCode,
+    pub session_id: u64,
+    pub position_block: Vec<u8>,
+    pub data_buffer: Vec<u8>,
+}
+
+/// Changes held for watchers that reconnect
+#[cfg(feature = "grpc")]
+const FEED_HISTORY: usize = 4096;
+
+/// Committed changes in commit order, with the most recent kept so a
+/// watcher that reconnects can resume after the last change it saw.
+///
+/// Changes are only recorded once something has watched the feed. Those
+/// made in a transaction are held until it ends, then published together
+/// if it committed and dropped if it didn't.
+#[cfg(feature = "grpc")]
+pub struct ChangeFeed {
+    /// Tells this run's numbering from a restarted server's
+    pub id: u64,
+    live: tokio::sync::broadcast::Sender<Change>,
+    /// Sequence of the next change, and the changes held
+    history: Mutex<(u64, std::collections::VecDeque<Change>)>,
+    watched: std::sync::atomic::AtomicBool,
+    /// Changes of transactions not yet ended, by session
+    pending: Mutex<HashMap<u64, Vec<Change>>>,
+}
+
+#[cfg(feature = "grpc")]
+impl ChangeFeed {
+    fn new() -> Self {
+        let id = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1);
+        ChangeFeed {
+            id,
+            live: tokio::sync::broadcast::channel(1024).0,
+            history: Mutex::new((1, Default::default())),
+            watched: Default::default(),
+            pending: Default::default(),
+        }
+    }
+
+    fn is_recording(&self) -> bool {
+        self.watched.load(Ordering::Relaxed)
+    }
+
+    /// A change a session made: published now, or when its transaction ends
+    fn record(&self, change: Change) {
+        if transaction_ops::has_transaction(change.session_id) {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            pending.entry(change.session_id).or_default().push(change);
+        } else {
+            self.publish(change);
+        }
+    }
+
+    /// A session's transaction ended: publish its changes if it committed
+    fn settle(&self, session_id: u64, committed: bool) {
+        let held = self.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&session_id);
+        if committed {
+            held.into_iter().flatten().for_each(|change| self.publish(change));
+        }
+    }
+
+    /// Number the change and pass it to every watcher
+    fn publish(&self, mut change: Change) {
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        change.sequence = history.0;
+        history.0 += 1;
+        if history.1.len() == FEED_HISTORY {
+            history.1.pop_front();
+        }
+        history.1.push_back(change.clone());
+        let _ = self.live.send(change);
+    }
+
+    /// Changes after `after`, or `None` once they are no longer all held
+    pub fn since(&self, after: u64) -> Option<Vec<Change>> {
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        Self::held_since(&history, after)
+    }
+
+    fn held_since(history: &(u64, std::collections::VecDeque<Change>), after: u64) -> Option<Vec<Change>> {
+        let (next, held) = history;
+        let oldest = held.front().map_or(*next, |c| c.sequence);
+        if after >= *next || after + 1 < oldest {
+            return None;
+        }
+        Some(held.iter().filter(|c| c.sequence > after).cloned().collect())
+    }
+
+    /// Start watching: the held changes after `after` (none without it)
+    /// and a receiver for those still to come. `None` if the changes
+    /// after `after` are no longer all held
+    pub fn subscribe(
+        &self,
+        after: Option<u64>,
+    ) -> Option<(Vec<Change>, tokio::sync::broadcast::Receiver<Change>)> {
+        self.watched.store(true, Ordering::Relaxed);
+        let history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        let replay = match after {
+            Some(after) => Self::held_since(&history, after)?,
+            None => Vec::new(),
+        };
+        Some((replay, self.live.subscribe()))
+    }
+}
+
+/// Operation counters across all connections
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    pub total_operations: AtomicU64,
+    pub total_reads: AtomicU64,
+    pub total_writes: AtomicU64,
+}
+
+/// Writes every executed request and its response to a trace file
+pub struct Tracer {
+    /// `None` once a write has failed
+    out: Mutex<Option<BufWriter<File>>>,
+    started: Instant,
+}
+
+impl Tracer {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        trace::write_header(&mut out)?;
+        out.flush()?;
+        Ok(Tracer { out: Mutex::new(Some(out)), started: Instant::now() })
+    }
+
+    fn record(&self, record: TraceRecord) {
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(writer) = out.as_mut() else { return };
+        // Flushed per request so a killed daemon leaves a usable trace
+        if let Err(e) = record.write_to(writer).and_then(|_| writer.flush()) {
+            tracing::warn!("Request trace stopped: {}", e);
+            *out = None;
+        }
+    }
+}
+
+/// Requests running with an id a Cancel can name. Requests sent with the
+/// same id share a token, and one Cancel stops them all
+#[derive(Default)]
+pub struct RunningRequests {
+    /// Token of each id, and how many requests run with it
+    running: Mutex<HashMap<u64, (CancelToken, usize)>>,
+}
+
+impl RunningRequests {
+    fn start(&self, request_id: u64) -> CancelToken {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = running.entry(request_id).or_default();
+        entry.1 += 1;
+        entry.0.clone()
+    }
+
+    fn finish(&self, request_id: u64) {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = running.get_mut(&request_id) {
+            entry.1 -= 1;
+            if entry.1 == 0 {
+                running.remove(&request_id);
+            }
+        }
+    }
+
+    /// Cancel the requests running with `request_id`; false if there are none
+    fn cancel(&self, request_id: u64) -> bool {
+        let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        running.get(&request_id).map(|(token, _)| token.cancel()).is_some()
+    }
+}
+
+/// State shared by every transport (binary TCP and gRPC)
+pub struct Shared {
+    pub engine: Arc<Engine>,
+    pub roots: DataRoots,
+    pub trace: Option<Tracer>,
+    /// Runs each file's operations on a thread of its own, if set
+    pub workers: Option<WorkerPool>,
+    #[cfg(feature = "grpc")]
+    pub started_at: Instant,
+    pub stats: ServerStats,
+    /// Requests a Cancel can stop
+    pub running: RunningRequests,
+    /// Bounds the operations running and waiting, and the connections
+    pub admission: Arc<Admission>,
+    #[cfg(feature = "grpc")]
+    pub changes: ChangeFeed,
+    /// Sessions of calls with no connection (HTTP, unary gRPC)
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    unconnected: Mutex<Unconnected>,
+    /// How long a session of calls with no connection lives after its
+    /// last call (zero = for ever)
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    pub session_idle: Duration,
+}
+
+/// How long a session of calls with no connection lives by default
+#[cfg(any(feature = "grpc", feature = "http"))]
+pub const DEFAULT_SESSION_IDLE: Duration = Duration::from_secs(600);
+
+/// The sessions calls with no connection run on. No connection closes to
+/// end them, so they end once idle (see [`Shared::end_idle`])
+#[cfg(any(feature = "grpc", feature = "http"))]
+#[derive(Default)]
+struct Unconnected {
+    /// Sessions named by client id, by that id
+    named: HashMap<u64, u64>,
+    /// When each session was last used
+    used: HashMap<u64, Instant>,
+}
+
+impl Shared {
+    pub fn new(engine: Arc<Engine>, roots: DataRoots) -> Self {
+        Shared {
+            engine,
+            roots,
+            trace: None,
+            workers: None,
+            #[cfg(feature = "grpc")]
+            started_at: Instant::now(),
+            stats: ServerStats::default(),
+            running: RunningRequests::default(),
+            admission: Arc::new(Admission::default()),
+            #[cfg(feature = "grpc")]
+            changes: ChangeFeed::new(),
+            #[cfg(any(feature = "grpc", feature = "http"))]
+            unconnected: Mutex::default(),
+            #[cfg(any(feature = "grpc", feature = "http"))]
+            session_idle: DEFAULT_SESSION_IDLE,
+        }
+    }
+
+    /// The session `client_id` names in calls with no connection, started
+    /// the first time it is used. Every such call naming the id shares
+    /// it, but none reaches a session a connection named
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    pub fn named_session(&self, client_id: u64) -> u64 {
+        let mut unconnected = self.unconnected.lock().unwrap_or_else(|e| e.into_inner());
+        *unconnected.named.entry(client_id).or_insert_with(next_session_id)
+    }
+
+    /// The session a call with no connection runs on: the one its client
+    /// id names, then the one in its position block if a call with no
+    /// connection started it and it hasn't ended, else a new one
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    pub fn session_for(&self, client_id: u64, position_block: &[u8]) -> u64 {
+        if client_id != 0 {
+            return self.named_session(client_id);
+        }
+        let stored = block_session(position_block);
+        let unconnected = self.unconnected.lock().unwrap_or_else(|e| e.into_inner());
+        if unconnected.used.contains_key(&stored) {
+            stored
+        } else {
+            next_session_id()
+        }
+    }
+
+    /// Note that a call with no connection runs on a session, before the
+    /// call and again after it. The session lives until no call has for
+    /// `session_idle`
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    pub fn touch(&self, session_id: u64) {
+        let mut unconnected = self.unconnected.lock().unwrap_or_else(|e| e.into_inner());
+        if unconnected.used.insert(session_id, Instant::now()).is_none() {
+            self.engine.sessions.attach(session_id);
+        }
+    }
+
+    /// End the sessions of calls with no connection that have been idle
+    /// for `session_idle` and no connection uses, as a disconnect would.
+    /// Returns how many were let go
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    pub fn end_idle(&self) -> usize {
+        if self.session_idle.is_zero() {
+            return 0;
+        }
+        // Held throughout, so a call can't take up a session being ended
+        let mut unconnected = self.unconnected.lock().unwrap_or_else(|e| e.into_inner());
+        let idle: Vec<u64> = unconnected
+            .used
+            .iter()
+            .filter(|(_, used)| used.elapsed() >= self.session_idle)
+            .map(|(&session_id, _)| session_id)
+            .collect();
+        for session_id in &idle {
+            unconnected.used.remove(session_id);
+            if self.engine.sessions.detach(*session_id) {
+                self.end_session(*session_id);
+            }
+        }
+        unconnected.named.retain(|_, session_id| !idle.contains(session_id));
+        idle.len()
+    }
+
+    /// Execute an operation on behalf of a session.
+    ///
+    /// Resolves the file path, stamps the session into the returned
+    /// position block and publishes successful writes to the change feed.
+    /// With a trace open, the request is recorded as the client sent it.
+    pub fn execute(&self, session_id: u64, req: OperationRequest) -> OperationResponse {
+        self.execute_cancellable(session_id, req, &CancelToken::new())
+    }
+
+    /// Execute an operation that gives up with status 200 once `cancel`
+    /// is cancelled
+    pub fn execute_cancellable(&self, session_id: u64, req: OperationRequest, cancel: &CancelToken) -> OperationResponse {
+        let Some(tracer) = &self.trace else {
+            return self.execute_untraced(session_id, req, cancel);
+        };
+        let elapsed = tracer.started.elapsed();
+        let request = Request {
+            operation_code: req.operation as u16,
+            position_block: req.position_block.clone(),
+            data_buffer: req.data_buffer.clone(),
+            key_buffer: req.key_buffer.clone(),
+            key_number: req.key_number as i16,
+            file_path: req.file_path.clone().unwrap_or_default(),
+            lock_bias: req.lock_bias as u16,
+            client_id: 0,
+            error_detail: false,
+            request_id: 0,
+            hello: false,
+        };
+        let open_mode = req.open_mode;
+
+        let result = self.execute_untraced(session_id, req, cancel);
+        tracer.record(TraceRecord {
+            elapsed,
+            session: session_id,
+            open_mode,
+            request,
+            response: Response {
+                status_code: result.status.as_raw(),
+                position_block: result.position_block.clone(),
+                data_buffer: result.data_buffer.clone(),
+                key_buffer: result.key_buffer.clone(),
+                detail: None,
+                capabilities: None,
+            },
+        });
+        result
+    }
+
+    fn execute_untraced(&self, session_id: u64, mut req: OperationRequest, cancel: &CancelToken) -> OperationResponse {
+        let started = Instant::now();
+        let _running = match req.operation {
+            // A Cancel frees capacity, so it never waits for any
+            OperationCode::Cancel => None,
+            _ => match self.admission.enter() {
+                Some(running) => Some(running),
+                None => return OperationResponse::error(StatusCode::ServerBusy),
+            },
+        };
+        if matches!(req.operation, OperationCode::Query | OperationCode::Cancel) {
+            self.stats.total_operations.fetch_add(1, Ordering::Relaxed);
+            if self.engine.is_strict() {
+                return OperationResponse::error(StatusCode::InvalidOperation);
+            }
+            if req.operation == OperationCode::Cancel {
+                return self.cancel_request(&req);
+            }
+            return self.execute_query(session_id, req, cancel);
+        }
+
+        if let Some(path) = req.file_path.take() {
+            if !path.is_empty() {
+                let Some(resolved) = self.roots.resolve(&path) else {
+                    return OperationResponse::error(StatusCode::InvalidFileName);
+                };
+                req.file_path = Some(resolved.to_string_lossy().to_string());
+            }
+        }
+        // Clone File and Rename File name a new path in the data buffer
+        if matches!(req.operation, OperationCode::CloneFile | OperationCode::RenameFile) {
+            let end = req.data_buffer.iter().position(|&b| b == 0).unwrap_or(req.data_buffer.len());
+            let to = String::from_utf8_lossy(&req.data_buffer[..end]).to_string();
+            if !to.is_empty() {
+                let Some(resolved) = self.roots.resolve(&to) else {
+                    return OperationResponse::error(StatusCode::InvalidFileName);
+                };
+                req.data_buffer = resolved.to_string_lossy().as_bytes().to_vec();
+            }
+        }
+
+        let operation = req.operation;
+        let requested_file = req.file_path.clone();
+        self.stats.total_operations.fetch_add(1, Ordering::Relaxed);
+        if operation.is_read() {
+            self.stats.total_reads.fetch_add(1, Ordering::Relaxed);
+        } else if operation.is_write() {
+            self.stats.total_writes.fetch_add(1, Ordering::Relaxed);
+        }
+
+        #[cfg(feature = "grpc")]
+        let written = if operation.is_write() && self.changes.is_recording() {
+            Some(req.data_buffer.clone())
+        } else if operation == OperationCode::TruncateFile && self.changes.is_recording() {
+            // Truncate answers with no position block; the request's names the file
+            Some(req.position_block.clone())
+        } else {
+            None
+        };
+
+        let mut result = match self.worker_file(&req) {
+            Some((workers, path)) => {
+                let (engine, cancel) = (self.engine.clone(), cancel.clone());
+                workers.run(&path, move || engine.execute_cancellable(session_id, req, &cancel))
+            }
+            None => self.engine.execute_cancellable(session_id, req, cancel),
+        };
+
+        // Query is answered here, not by the engine
+        if operation == OperationCode::ServerInfo && result.status == StatusCode::Success {
+            if let Ok(mut info) = ServerInfo::from_bytes(&result.data_buffer) {
+                info.set_supported(OperationCode::Query as u16);
+                info.set_supported(OperationCode::Cancel as u16);
+                result = OperationResponse::success().with_data(info.to_bytes());
+            }
+        }
+
+        // Store session in position block
+        let mut pos_block = PositionBlock::from_bytes(&result.position_block);
+        pos_block.set_session_id(session_id);
+        result.position_block = pos_block.data.to_vec();
+
+        debug!(
+            target: "operation",
+            session = session_id,
+            op = ?operation,
+            file = pos_block.file_path().map(|path| path.display().to_string()).or(requested_file),
+            status = result.status.as_raw(),
+            duration_us = started.elapsed().as_micros() as u64,
+            "Operation"
+        );
+
+        #[cfg(feature = "grpc")]
+        if let Some(data) = written {
+            if result.status == StatusCode::Success {
+                let position_block = match operation {
+                    OperationCode::TruncateFile => data.clone(),
+                    _ => result.position_block.clone(),
+                };
+                self.changes.record(Change {
+                    sequence: 0,
+                    file_path: PositionBlock::from_bytes(&position_block)
+                        .file_path()
+                        .map(|p| p.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    operation,
+                    session_id,
+                    position_block,
+                    data_buffer: match operation {
+                        OperationCode::Delete | OperationCode::TruncateFile => Vec::new(),
+                        // The restored record comes from the bin, not the request
+                        OperationCode::Undelete => result.data_buffer.clone(),
+                        _ => data,
+                    },
+                });
+            }
+        }
+        #[cfg(feature = "grpc")]
+        if matches!(operation, OperationCode::EndTransaction | OperationCode::AbortTransaction)
+            && !transaction_ops::has_transaction(session_id)
+        {
+            let committed = operation == OperationCode::EndTransaction && result.status == StatusCode::Success;
+            self.changes.settle(session_id, committed);
+        }
+
+        result
+    }
+
+    /// The workers and the file an operation runs on, with a worker pool.
+    /// Operations naming no file run on the connection's thread, and so
+    /// do those that may wait for a record lock, which only an operation
+    /// on the same file can free
+    fn worker_file(&self, req: &OperationRequest) -> Option<(&WorkerPool, PathBuf)> {
+        let workers = self.workers.as_ref()?;
+        if LockType::from_bias(req.lock_bias).waits() {
+            return None;
+        }
+        let path = PositionBlock::from_bytes(&req.position_block)
+            .file_path()
+            .or_else(|| req.file_path.as_ref().map(PathBuf::from))?;
+        Some((workers, path))
+    }
+
+    /// Run a Query (97): the data buffer holds a SELECT and the file path
+    /// the directory of its data dictionary. Rows come back as tab
+    /// separated text; failures that aren't Btrieve statuses come back as
+    /// status 19 with the message in the data buffer.
+    fn execute_query(&self, session_id: u64, req: OperationRequest, cancel: &CancelToken) -> OperationResponse {
+        let sql = String::from_utf8_lossy(&req.data_buffer);
+        let dictionary_dir = req.file_path.unwrap_or_default();
+        let mut client = XtrieveClient::with_transport(Box::new(EngineTransport {
+            engine: self.engine.clone(),
+            roots: self.roots.clone(),
+            session_id,
+            cancel: cancel.clone(),
+        }));
+
+        match query::execute(&mut client, &dictionary_dir, sql.trim_end_matches('\0')) {
+            Ok(result) => OperationResponse::success().with_data(result.to_tsv().into_bytes()),
+            Err(BtrieveError::Status(status)) => OperationResponse::error(status),
+            Err(BtrieveError::Detailed { status, detail }) => OperationResponse::error(status).with_detail(detail),
+            Err(e) => {
+                let message = match e {
+                    BtrieveError::Internal(message) => message,
+                    e => e.to_string(),
+                };
+                let detail = ErrorDetail { message: message.clone(), context: "Query".to_string() };
+                OperationResponse::error(StatusCode::UnrecoverableError)
+                    .with_data(message.into_bytes())
+                    .with_detail(detail)
+            }
+        }
+    }
+
+    /// Run a Cancel (89): the data buffer holds the 8-byte id of the
+    /// requests to stop. Status 4 if none is running with it
+    fn cancel_request(&self, req: &OperationRequest) -> OperationResponse {
+        let Some(id) = req.data_buffer.get(..8) else {
+            return OperationResponse::error(StatusCode::DataBufferTooShort);
+        };
+        let request_id = u64::from_le_bytes(id.try_into().unwrap());
+        if self.running.cancel(request_id) {
+            tracing::info!("Request {} cancelled", request_id);
+            OperationResponse::success()
+        } else {
+            OperationResponse::error(StatusCode::KeyNotFound)
+        }
+    }
+
+    /// Note that a connection runs requests on a session, so the session
+    /// lives until every connection using it has gone
+    pub fn attach(&self, connection: &mut Connection, session_id: u64) {
+        if connection.used.insert(session_id) {
+            self.engine.sessions.attach(session_id);
+        }
+    }
+
+    /// A connection closed: end the sessions no other connection uses,
+    /// aborting their transactions and releasing their files and locks
+    pub fn disconnect(&self, connection: Connection) {
+        for session_id in connection.used {
+            if self.engine.sessions.detach(session_id) {
+                self.end_session(session_id);
+            }
+        }
+    }
+
+    /// End a session nothing uses any more
+    fn end_session(&self, session_id: u64) {
+        let cleanup = self.engine.end_session(session_id);
+        #[cfg(feature = "grpc")]
+        self.changes.settle(session_id, false);
+        if cleanup.transaction_aborted || cleanup.files_closed > 0 {
+            tracing::info!(
+                "Session {} ended: transaction aborted: {}, files closed: {}",
+                session_id,
+                cleanup.transaction_aborted,
+                cleanup.files_closed
+            );
+        }
+    }
+
+    /// Execute a binary protocol request for a connection
+    pub fn execute_wire(&self, connection: &mut Connection, req: Request) -> Response {
+        let session_id = connection.session_for(req.client_id, &req.position_block);
+        self.attach(connection, session_id);
+        let (error_detail, hello) = (req.error_detail, req.hello);
+
+        let operation = OperationCode::from_raw(req.operation_code as u32);
+        let engine_req = OperationRequest {
+            operation,
+            file_path: if req.file_path.is_empty() {
+                None
+            } else {
+                Some(req.file_path)
+            },
+            position_block: req.position_block,
+            data_buffer: req.data_buffer,
+            key_buffer: req.key_buffer,
+            key_number: req.key_number as i32,
+            data_length: 0,
+            key_length: 0,
+            // Btrieve passes an Open's mode as its key number
+            open_mode: match operation {
+                OperationCode::Open => req.key_number as i32,
+                _ => 0,
+            },
+            lock_bias: req.lock_bias as i32,
+        };
+
+        let result = match req.request_id {
+            0 => self.execute(session_id, engine_req),
+            request_id => {
+                let cancel = self.running.start(request_id);
+                let result = self.execute_cancellable(session_id, engine_req, &cancel);
+                self.running.finish(request_id);
+                result
+            }
+        };
+
+        Response {
+            status_code: result.status.as_raw(),
+            position_block: result.position_block,
+            data_buffer: result.data_buffer,
+            key_buffer: result.key_buffer,
+            detail: error_detail.then(|| result.detail.unwrap_or_default()),
+            capabilities: if hello { self.capabilities(session_id) } else { None },
+        }
+    }
+
+    /// The capabilities frame answering a hello: what ServerInfo (98)
+    /// reports
+    fn capabilities(&self, session_id: u64) -> Option<ServerInfo> {
+        let result = self.execute(session_id, OperationRequest {
+            operation: OperationCode::ServerInfo,
+            ..Default::default()
+        });
+        ServerInfo::from_bytes(&result.data_buffer).ok()
+    }
+}
+
+/// Carries the Btrieve calls of a server-side query straight to the
+/// engine, on the session that issued the query
+pub(crate) struct EngineTransport {
+    pub(crate) engine: Arc<Engine>,
+    pub(crate) roots: DataRoots,
+    pub(crate) session_id: u64,
+    /// Stops the calls once the request they serve is cancelled
+    pub(crate) cancel: CancelToken,
+}
+
+impl Transport for EngineTransport {
+    fn round_trip(
+        &mut self,
+        request: &BtrieveRequest,
+        _deadline: Option<Instant>,
+    ) -> BtrieveResult<BtrieveResponse> {
+        let file_path = match request.file_path.as_str() {
+            "" => None,
+            path => match self.roots.resolve(path) {
+                Some(resolved) => Some(resolved.to_string_lossy().to_string()),
+                None => {
+                    return Ok(BtrieveResponse {
+                        status_code: StatusCode::InvalidFileName.as_raw() as u32,
+                        ..Default::default()
+                    })
+                }
+            },
+        };
+        let result = self.engine.execute_cancellable(self.session_id, OperationRequest {
+            operation: OperationCode::from_raw(request.operation_code),
+            file_path,
+            position_block: request.position_block.clone(),
+            data_buffer: request.data_buffer.clone(),
+            key_buffer: request.key_buffer.clone(),
+            key_number: request.key_number,
+            data_length: 0,
+            key_length: 0,
+            open_mode: request.open_mode,
+            lock_bias: request.lock_bias as i32,
+        }, &self.cancel);
+        Ok(BtrieveResponse {
+            status_code: result.status.as_raw() as u32,
+            position_block: result.position_block,
+            data_buffer: result.data_buffer,
+            key_buffer: result.key_buffer,
+            detail: result.detail,
+        })
+    }
+}
+
 /// Connection statistics
 #[derive(Debug, Default)]
 pub struct ConnectionStats {
@@ -32,3 +936,323 @@ impl ConnectionStats {
             .unwrap_or(Duration::ZERO)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_roots() {
+        let roots = DataRoots::parse(&["data".to_string(), "acct=ledgers".to_string()]).unwrap();
+        assert_eq!(roots.default_dir(), Path::new("data"));
+        assert_eq!(roots.resolve("CUST.DAT"), Some(Path::new("data").join("CUST.DAT")));
+        // A drive letter is not a prefix
+        assert_eq!(roots.resolve("C:CUST.DAT"), Some(Path::new("data").join("C:CUST.DAT")));
+
+        // Prefixes match in any case, with or without a leading separator
+        let ledger = Some(Path::new("ledgers").join("LEDGER.DAT"));
+        assert_eq!(roots.resolve("ACCT:LEDGER.DAT"), ledger);
+        assert_eq!(roots.resolve("Acct:\\LEDGER.DAT"), ledger);
+        assert_eq!(roots.resolve("acct:/LEDGER.DAT"), ledger);
+
+        // `..` may move about inside a root but not out of it
+        assert_eq!(roots.resolve("ACCT:2024/../LEDGER.DAT"), Some(Path::new("ledgers").join("2024/../LEDGER.DAT")));
+        assert_eq!(roots.resolve("ACCT:../../etc/passwd"), None);
+        assert_eq!(roots.resolve("ACCT:2024\\..\\..\\DATA\\CUST.DAT"), None);
+        assert_eq!(roots.resolve("ACCT:./.."), None);
+    }
+
+    #[test]
+    fn test_data_roots_parse() {
+        // Without a plain directory the first prefixed root is the default
+        let roots = DataRoots::parse(&["ACCT=ledgers".to_string(), "HR=staff".to_string()]).unwrap();
+        assert_eq!(roots.default_dir(), Path::new("ledgers"));
+        assert_eq!(roots.dirs().collect::<Vec<_>>(), [Path::new("ledgers"), Path::new("ledgers"), Path::new("staff")]);
+
+        assert!(DataRoots::parse(&[]).is_err());
+        assert!(DataRoots::parse(&["data".to_string(), "other".to_string()]).is_err());
+        assert!(DataRoots::parse(&["acct=ledgers".to_string(), "ACCT=staff".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_tcp_tuning() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let tuning = TcpTuning {
+            nodelay: true,
+            keepalive: 30,
+            keepalive_interval: 5,
+            keepalive_retries: 3,
+            send_buffer: 64 * 1024,
+            recv_buffer: 64 * 1024,
+        };
+        tuning.apply(&stream).unwrap();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        // Linux reports buffers doubled for its own bookkeeping
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+            assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(5));
+            assert_eq!(socket.keepalive_retries().unwrap(), 3);
+        }
+
+        // Without keepalive a new connection gets none
+        let plain = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        TcpTuning { nodelay: false, keepalive: 0, ..tuning }.apply(&plain).unwrap();
+        assert!(!SockRef::from(&plain).nodelay().unwrap());
+        assert!(!SockRef::from(&plain).keepalive().unwrap());
+    }
+
+    #[test]
+    fn test_server_busy() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut shared, block) = serve(dir.path());
+        shared.admission = Arc::new(Admission::new(1, 0, 0));
+        let stat = || {
+            shared.execute(1, OperationRequest {
+                operation: OperationCode::Stat,
+                position_block: block.clone(),
+                ..Default::default()
+            }).status
+        };
+
+        let running = shared.admission.enter().unwrap();
+        assert_eq!(stat(), StatusCode::ServerBusy);
+        drop(running);
+        assert_eq!(stat(), StatusCode::Success);
+        assert_eq!(shared.admission.stats().refused, 1);
+    }
+
+    #[test]
+    fn test_hello_gets_capabilities() {
+        let dir = tempfile::tempdir().unwrap();
+        let (shared, _) = serve(dir.path());
+        let mut connection = Connection::new();
+        let ping = |hello| Request { operation_code: OperationCode::Ping as u16, hello, ..Default::default() };
+
+        let greeted = shared.execute_wire(&mut connection, ping(true));
+        assert_eq!(greeted.status_code, StatusCode::Success.as_raw());
+        let info = greeted.capabilities.unwrap();
+        assert_eq!(info.protocol_version, xtrieve_engine::protocol::PROTOCOL_VERSION);
+        assert!(info.supports(OperationCode::Query as u16));
+
+        // Requests without the flag get the plain response
+        assert!(shared.execute_wire(&mut connection, ping(false)).capabilities.is_none());
+        shared.disconnect(connection);
+    }
+
+    #[test]
+    fn test_client_id_stays_with_its_connection() {
+        use xtrieve_engine::operations::transaction_ops::has_transaction;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (shared, _) = serve(dir.path());
+        let request = |operation: OperationCode| Request {
+            operation_code: operation as u16,
+            client_id: 7,
+            ..Default::default()
+        };
+        let success = StatusCode::Success.as_raw();
+        let (mut owner, mut intruder) = (Connection::new(), Connection::new());
+        assert_eq!(shared.execute_wire(&mut owner, request(OperationCode::BeginTransaction)).status_code, success);
+        let session = owner.named_session(7);
+        assert!(has_transaction(session));
+
+        // The same id on another connection names a session of its own
+        assert_ne!(shared.execute_wire(&mut intruder, request(OperationCode::EndTransaction)).status_code, success);
+        shared.disconnect(intruder);
+        assert!(has_transaction(session));
+
+        assert_eq!(shared.execute_wire(&mut owner, request(OperationCode::EndTransaction)).status_code, success);
+        shared.disconnect(owner);
+    }
+
+    #[test]
+    fn test_position_block_stays_with_its_session() {
+        use xtrieve_engine::operations::transaction_ops::has_transaction;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (shared, _) = serve(dir.path());
+        let request = |operation: OperationCode, position_block: &[u8]| Request {
+            operation_code: operation as u16,
+            position_block: position_block.to_vec(),
+            file_path: if operation == OperationCode::Open { "PARTS.DAT".to_string() } else { String::new() },
+            key_buffer: vec![0; 4],
+            ..Default::default()
+        };
+        let (success, not_open) = (StatusCode::Success.as_raw(), StatusCode::FileNotOpen.as_raw());
+        let (mut owner, mut intruder) = (Connection::new(), Connection::new());
+        let block = shared.execute_wire(&mut owner, request(OperationCode::Open, &[])).position_block;
+        let begin = shared.execute_wire(&mut owner, request(OperationCode::BeginTransaction, &block));
+        assert_eq!(begin.status_code, success);
+        assert!(has_transaction(owner.session));
+
+        // The owner's block, sent on another connection, runs on that
+        // connection's session, where the file isn't open
+        assert_eq!(shared.execute_wire(&mut intruder, request(OperationCode::GetFirst, &block)).status_code, not_open);
+        shared.execute_wire(&mut intruder, request(OperationCode::AbortTransaction, &block));
+        assert!(has_transaction(owner.session));
+        shared.disconnect(intruder);
+        assert!(has_transaction(owner.session));
+
+        let first = shared.execute_wire(&mut owner, request(OperationCode::GetFirst, &block));
+        assert_ne!(first.status_code, not_open);
+        assert_eq!(shared.execute_wire(&mut owner, request(OperationCode::AbortTransaction, &block)).status_code, success);
+        shared.disconnect(owner);
+    }
+
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    #[test]
+    fn test_client_id_without_connection() {
+        use xtrieve_engine::operations::transaction_ops::has_transaction;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (shared, _) = serve(dir.path());
+        let run = |session, operation| {
+            shared.execute(session, OperationRequest { operation, ..Default::default() }).status
+        };
+        let mut owner = Connection::new();
+        let session = owner.named_session(7);
+        assert_eq!(run(session, OperationCode::BeginTransaction), StatusCode::Success);
+
+        // Calls with no connection share their ids, apart from the
+        // connections' sessions, even one named by its own number
+        assert_eq!(shared.named_session(7), shared.named_session(7));
+        assert_ne!(shared.named_session(7), session);
+        assert_ne!(run(shared.named_session(session), OperationCode::EndTransaction), StatusCode::Success);
+        assert!(has_transaction(session));
+        // nor by a block stamped with it
+        let mut block = PositionBlock::new();
+        block.set_session_id(session);
+        assert_ne!(shared.session_for(0, &block.data), session);
+        let standalone = shared.session_for(0, &[]);
+        shared.touch(standalone);
+        block.set_session_id(standalone);
+        assert_eq!(shared.session_for(0, &block.data), standalone);
+        assert_eq!(run(session, OperationCode::EndTransaction), StatusCode::Success);
+    }
+
+    #[cfg(any(feature = "grpc", feature = "http"))]
+    #[test]
+    fn test_idle_sessions_end() {
+        use xtrieve_engine::operations::transaction_ops::has_transaction;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (mut shared, _) = serve(dir.path());
+        shared.session_idle = Duration::from_millis(200);
+        let begin = |session| {
+            shared.touch(session);
+            let response = shared.execute(session, OperationRequest {
+                operation: OperationCode::BeginTransaction,
+                ..Default::default()
+            });
+            assert_eq!(response.status, StatusCode::Success);
+        };
+        let session = shared.named_session(7);
+        begin(session);
+        // One a connection uses too outlives its calls
+        let mut connection = Connection::new();
+        let held = connection.session;
+        shared.attach(&mut connection, held);
+        begin(held);
+
+        assert_eq!(shared.end_idle(), 0);
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(shared.end_idle(), 2);
+        assert!(!has_transaction(session));
+        assert!(has_transaction(held));
+        // The id names a new session from now on
+        assert_ne!(shared.named_session(7), session);
+
+        shared.disconnect(connection);
+        assert!(!has_transaction(held));
+    }
+
+    /// A server over a scratch directory, and a file of 4-byte records
+    /// keyed on themselves opened in it
+    fn serve(dir: &Path) -> (Shared, Vec<u8>) {
+        let roots = DataRoots::parse(&[dir.to_string_lossy().to_string()]).unwrap();
+        let shared = Shared::new(Arc::new(Engine::new(64)), roots);
+        let mut spec = vec![0u8; 32];
+        spec[0..2].copy_from_slice(&4u16.to_le_bytes());
+        spec[2..4].copy_from_slice(&512u16.to_le_bytes());
+        spec[4..6].copy_from_slice(&1u16.to_le_bytes());
+        spec[18..20].copy_from_slice(&4u16.to_le_bytes());
+        spec[26] = 14;
+        for (operation, data_buffer) in [(OperationCode::Create, spec), (OperationCode::Open, Vec::new())] {
+            let response = shared.execute(1, OperationRequest {
+                operation,
+                file_path: Some("PARTS.DAT".to_string()),
+                data_buffer,
+                ..Default::default()
+            });
+            assert_eq!(response.status, StatusCode::Success);
+            if operation == OperationCode::Open {
+                return (shared, response.position_block);
+            }
+        }
+        unreachable!()
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn test_change_feed_waits_for_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let (shared, block) = serve(dir.path());
+        let (_, mut changes) = shared.changes.subscribe(None).unwrap();
+        let run = |operation, data_buffer: &[u8]| {
+            let response = shared.execute(1, OperationRequest {
+                operation,
+                position_block: block.clone(),
+                data_buffer: data_buffer.to_vec(),
+                ..Default::default()
+            });
+            assert_eq!(response.status, StatusCode::Success);
+        };
+
+        run(OperationCode::BeginTransaction, b"");
+        run(OperationCode::Insert, &1u32.to_le_bytes());
+        run(OperationCode::Insert, &2u32.to_le_bytes());
+        assert!(changes.try_recv().is_err());
+        run(OperationCode::EndTransaction, b"");
+        assert_eq!(changes.try_recv().unwrap().data_buffer, 1u32.to_le_bytes());
+        assert_eq!(changes.try_recv().unwrap().data_buffer, 2u32.to_le_bytes());
+
+        // Nothing of an aborted transaction is published
+        run(OperationCode::BeginTransaction, b"");
+        run(OperationCode::Insert, &3u32.to_le_bytes());
+        run(OperationCode::AbortTransaction, b"");
+        run(OperationCode::Insert, &4u32.to_le_bytes());
+        let change = changes.try_recv().unwrap();
+        assert_eq!((change.sequence, change.data_buffer), (3, 4u32.to_le_bytes().to_vec()));
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn test_change_feed_reports_truncate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (shared, block) = serve(dir.path());
+        let (_, mut changes) = shared.changes.subscribe(None).unwrap();
+        for (operation, data_buffer) in [(OperationCode::Insert, 1u32.to_le_bytes().to_vec()), (OperationCode::TruncateFile, Vec::new())] {
+            let response = shared.execute(1, OperationRequest {
+                operation,
+                position_block: block.clone(),
+                data_buffer,
+                ..Default::default()
+            });
+            assert_eq!(response.status, StatusCode::Success);
+        }
+
+        assert_eq!(changes.try_recv().unwrap().operation, OperationCode::Insert);
+        let truncated = changes.try_recv().unwrap();
+        assert_eq!(truncated.operation, OperationCode::TruncateFile);
+        assert!(truncated.file_path.ends_with("PARTS.DAT"));
+        assert!(truncated.data_buffer.is_empty());
+    }
+}
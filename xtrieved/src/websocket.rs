@@ -0,0 +1,104 @@
+//! WebSocket transport for the binary protocol
+//!
+//! Each binary WebSocket message carries exactly one request frame, in the
+//! same layout as the TCP protocol, and is answered by one binary message
+//! holding the response frame. This lets browser-based terminals and JS
+//! tooling reach xtrieved through proxies that only pass HTTP.
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use tracing::{debug, error, warn};
+use tungstenite::Message;
+
+use xtrieve_engine::protocol::Request;
+
+use crate::server::{self, ConnectionStats, Shared, TcpTuning};
+
+/// Start the WebSocket listener on its own accept thread
+pub fn spawn(addr: SocketAddr, shared: Arc<Shared>, tuning: TcpTuning) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::Builder::new()
+        .name("websocket".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let Some(connected) = shared.admission.connect() else {
+                            warn!("Closing WebSocket connection: --max-connections reached");
+                            continue;
+                        };
+                        if let Err(e) = tuning.apply(&stream) {
+                            warn!("Could not tune connection: {}", e);
+                        }
+                        let shared = shared.clone();
+                        thread::spawn(move || {
+                            handle_client(stream, shared);
+                            drop(connected);
+                        });
+                    }
+                    Err(e) => {
+                        error!("WebSocket accept failed: {}", e);
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, shared: Arc<Shared>) {
+    let peer = stream.peer_addr().ok();
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("WebSocket handshake with {:?} failed: {}", peer, e);
+            return;
+        }
+    };
+    debug!("WebSocket client connected: {:?}", peer);
+
+    let mut connection = server::Connection::new();
+    let session_id = connection.session;
+    let mut stats = ConnectionStats::new();
+
+    loop {
+        let frame = match socket.read() {
+            Ok(Message::Binary(frame)) => frame,
+            Ok(Message::Close(_)) => break,
+            // Pings are answered by tungstenite; text frames are not part of the protocol
+            Ok(_) => continue,
+            Err(e) => {
+                debug!("WebSocket client {:?} gone: {}", peer, e);
+                break;
+            }
+        };
+
+        let req = match Request::from_reader(&mut frame.as_slice()) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Malformed WebSocket request: {}", e);
+                break;
+            }
+        };
+
+        debug!("WebSocket op {} from session {}", req.operation_code, session_id);
+
+        let response = shared.execute_wire(&mut connection, req);
+        stats.record_request(response.status_code == 0);
+
+        if let Err(e) = socket.send(Message::Binary(response.to_bytes())) {
+            warn!("Error writing WebSocket response: {}", e);
+            break;
+        }
+    }
+
+    debug!(
+        "WebSocket session {} closed: {} requests, {} errors in {:?}",
+        session_id,
+        stats.total_requests,
+        stats.total_errors,
+        stats.uptime()
+    );
+    shared.disconnect(connection);
+}
@@ -0,0 +1,106 @@
+//! Worker pool - the operations on one file run on one thread
+//!
+//! Each file is given to a worker by a hash of its path, so requests for
+//! it from every connection queue up on one thread and find its pages and
+//! locks where the last request left them, while files on other workers
+//! run in parallel. The connection's thread waits for the answer.
+//!
+//! Files share workers, so a long operation holds up every file hashed to
+//! its worker, not only its own: a Set Owner rewriting a large file, say,
+//! stalls the requests for the others behind it until it is done. Workers
+//! aren't stolen from, to keep each file on one thread.
+//!
+//! An operation that panics takes the daemon down, as it would on the
+//! connection's thread: release builds abort on a panic.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads running the operations of the files given to them, in order
+pub struct WorkerPool {
+    workers: Vec<Sender<Job>>,
+}
+
+impl WorkerPool {
+    /// Start `size` workers (at least one)
+    pub fn new(size: usize) -> std::io::Result<Self> {
+        let workers = (0..size.max(1))
+            .map(|n| {
+                let (sender, jobs) = mpsc::channel::<Job>();
+                thread::Builder::new().name(format!("xtrieve-worker-{}", n)).spawn(move || {
+                    for job in jobs {
+                        job();
+                    }
+                })?;
+                Ok(sender)
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(WorkerPool { workers })
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Worker the file at `path` is given to
+    fn worker_of(&self, path: &Path) -> usize {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        (hasher.finish() % self.workers.len() as u64) as usize
+    }
+
+    /// Run `job` on the worker of the file at `path`, after the jobs
+    /// already queued for it, and wait for its result
+    pub fn run<T: Send + 'static>(&self, path: &Path, job: impl FnOnce() -> T + Send + 'static) -> T {
+        let (done, result) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move || {
+            let _ = done.send(job());
+        });
+        self.workers[self.worker_of(path)].send(job).expect("workers run as long as the pool");
+        result.recv().expect("worker answers each job")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_file_stays_on_one_worker() {
+        let pool = WorkerPool::new(4).unwrap();
+        let path = Path::new("PARTS.DAT");
+        let threads: Vec<_> = (0..8)
+            .map(|_| pool.run(path, || thread::current().name().unwrap().to_string()))
+            .collect();
+        assert!(threads.iter().all(|name| *name == threads[0]));
+        assert_eq!(threads[0], format!("xtrieve-worker-{}", pool.worker_of(path)));
+        assert_eq!(WorkerPool::new(0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_files_on_other_workers_go_on() {
+        let pool = Arc::new(WorkerPool::new(2).unwrap());
+        let busy = Path::new("PARTS.DAT");
+        let other = (0..)
+            .map(|n| PathBuf::from(format!("FILE{}.DAT", n)))
+            .find(|path| pool.worker_of(path) != pool.worker_of(busy))
+            .unwrap();
+
+        // Hold the first file's worker until the other file has run
+        let (release, held) = mpsc::channel::<()>();
+        let blocked = {
+            let pool = pool.clone();
+            thread::spawn(move || pool.run(busy, move || held.recv().is_ok()))
+        };
+        assert_eq!(pool.run(&other, || 7), 7);
+        release.send(()).unwrap();
+        assert!(blocked.join().unwrap());
+    }
+}
@@ -0,0 +1,516 @@
+//! BUTIL-compatible commands
+//!
+//! ```text
+//! xtutil -STAT   <file> [/O<owner>]
+//! xtutil -CREATE <file> <description file>
+//! xtutil -CLONE  <new file> <existing file> [/O<owner>]
+//! xtutil -COPY   <input file> <output file> [/O<input owner> [/O<output owner>]]
+//! xtutil -LOAD   <unformatted file> <file> [/O<owner>]
+//! xtutil -SAVE   <file> <unformatted file> [N <key number>] [/O<owner>]
+//! ```
+//!
+//! Command names are case-insensitive, as in BUTIL, so existing batch files
+//! run once `BUTIL` is replaced with `xtutil`. Unformatted files hold one
+//! `<length>,<record bytes>\r\n` entry per record and end with Ctrl-Z.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::btrieve::FileStatistics;
+use xtrieve_client::{BtrieveError, BtrieveFile, FileBuilder, KeyBuilder, KeyType, StatusCode};
+use xtrieve_engine::storage::fcr::FileFlags;
+use xtrieve_engine::storage::KeyFlags;
+
+use crate::{walk, Global};
+
+/// End-of-file marker of unformatted files (Ctrl-Z)
+const END_OF_DATA: u8 = 0x1A;
+
+/// Positional arguments and `/O<owner>` options of a command
+struct Args {
+    positional: Vec<String>,
+    owners: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Self {
+        // Owner names are up to 8 characters, which keeps absolute paths
+        // such as /opt/data/CUST.DAT positional
+        let (owners, positional): (Vec<_>, Vec<_>) = args.partition(|a| {
+            (a.starts_with("/O") || a.starts_with("/o"))
+                && (3..=10).contains(&a.len())
+                && !a[2..].contains('/')
+        });
+        Args {
+            positional,
+            owners: owners.into_iter().map(|o| o[2..].to_string()).collect(),
+        }
+    }
+
+    fn expect(&self, count: usize, usage: &str) -> Result<()> {
+        if self.positional.len() < count {
+            bail!("usage: xtutil {}", usage);
+        }
+        Ok(())
+    }
+
+    fn owner(&self, n: usize) -> Option<&str> {
+        self.owners.get(n).map(String::as_str)
+    }
+}
+
+pub fn run(global: &Global, command: &str, args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let args = Args::parse(args);
+    match command.to_ascii_lowercase().as_str() {
+        "-stat" => stat(global, &args),
+        "-create" => create(global, &args),
+        "-clone" => clone(global, &args),
+        "-copy" => copy(global, &args),
+        "-load" => load(global, &args),
+        "-save" => save(global, &args),
+        _ => bail!("unknown command {}", command),
+    }?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn open(global: &Global, path: &str, owner: Option<&str>) -> Result<BtrieveFile> {
+    let client = global.connect()?;
+    let file = match owner {
+        Some(owner) => BtrieveFile::open_with_owner(client, path, 0, owner),
+        None => BtrieveFile::open(client, path, 0),
+    };
+    file.with_context(|| format!("cannot open {}", path))
+}
+
+fn stat(global: &Global, args: &Args) -> Result<()> {
+    args.expect(1, "-STAT <file> [/O<owner>]")?;
+    let path = &args.positional[0];
+    let mut file = open(global, path, args.owner(0))?;
+    let stat = file.stat()?;
+    file.close()?;
+    print!("{}", format_stat(path, &stat));
+    Ok(())
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "Yes"
+    } else {
+        "No"
+    }
+}
+
+/// BUTIL's -STAT report; positions are 1-based
+fn format_stat(path: &str, stat: &FileStatistics) -> String {
+    let flag = |f: FileFlags| yes_no(stat.flags.contains(f));
+    let mut out = format!("File Statistics for {}\n\n", path);
+    out += &format!(
+        "Record Length = {}   Variable Records = {}   Blank Truncation = {}\n",
+        stat.record_length,
+        flag(FileFlags::VARIABLE_LENGTH),
+        flag(FileFlags::BLANK_TRUNCATION)
+    );
+    out += &format!(
+        "Page Size = {}   Compressed = {}\n",
+        stat.page_size,
+        flag(FileFlags::COMPRESSED)
+    );
+    out += &format!(
+        "Total Records = {}   Keys = {}\n",
+        stat.num_records, stat.num_keys
+    );
+    if stat.keys.is_empty() {
+        return out;
+    }
+
+    out += "\nKey  Segment  Position  Length  Duplicates  Modifiable  Type            Null  Descending\n";
+    let (mut key, mut segment) = (0, 1);
+    for spec in &stat.keys {
+        let null = match spec.flags.contains(KeyFlags::NULL) {
+            true => format!("{:02X}", spec.null_value),
+            false => "--".to_string(),
+        };
+        out += &format!(
+            "{:>3}  {:>7}  {:>8}  {:>6}  {:<10}  {:<10}  {:<14}  {:<4}  {}\n",
+            key,
+            segment,
+            spec.position + 1,
+            spec.length,
+            yes_no(spec.allows_duplicates()),
+            yes_no(spec.flags.contains(KeyFlags::MODIFIABLE)),
+            format!("{:?}", spec.key_type),
+            null,
+            yes_no(spec.is_descending())
+        );
+        if spec.is_segmented() {
+            segment += 1;
+        } else {
+            key += 1;
+            segment = 1;
+        }
+    }
+    out
+}
+
+fn create(global: &Global, args: &Args) -> Result<()> {
+    args.expect(2, "-CREATE <file> <description file>")?;
+    let (path, description) = (&args.positional[0], &args.positional[1]);
+    let text = fs::read_to_string(description)
+        .with_context(|| format!("cannot read {}", description))?;
+    let builder = parse_description(&text).with_context(|| format!("in {}", description))?;
+    builder
+        .create(&mut global.connect()?, path)
+        .with_context(|| format!("cannot create {}", path))?;
+    println!("The command completed successfully.");
+    Ok(())
+}
+
+fn clone(global: &Global, args: &Args) -> Result<()> {
+    args.expect(2, "-CLONE <new file> <existing file> [/O<owner>]")?;
+    let (new, existing) = (&args.positional[0], &args.positional[1]);
+    let mut file = open(global, existing, args.owner(0))?;
+    // Servers without Clone File (92) get the shape through Stat
+    let cloned = match file.clone_to(new) {
+        Err(BtrieveError::Status(StatusCode::InvalidOperation)) => {
+            let stat = file.stat()?;
+            FileBuilder::from_statistics(&stat).create(&mut global.connect()?, new)
+        }
+        cloned => cloned,
+    };
+    file.close()?;
+    cloned.with_context(|| format!("cannot create {}", new))?;
+    println!("The command completed successfully.");
+    Ok(())
+}
+
+fn copy(global: &Global, args: &Args) -> Result<()> {
+    args.expect(2, "-COPY <input file> <output file> [/O<owner> [/O<owner>]]")?;
+    let mut input = open(global, &args.positional[0], args.owner(0))?;
+    let mut output = open(global, &args.positional[1], args.owner(1))?;
+    let mut count = 0u64;
+    walk(&mut input, Some(0), |record| {
+        count += 1;
+        output
+            .insert(record)
+            .with_context(|| format!("record {} rejected", count))
+    })?;
+    input.close()?;
+    output.close()?;
+    println!("{} records copied.", count);
+    Ok(())
+}
+
+fn load(global: &Global, args: &Args) -> Result<()> {
+    args.expect(2, "-LOAD <unformatted file> <file> [/O<owner>]")?;
+    let (source, path) = (&args.positional[0], &args.positional[1]);
+    let input = fs::File::open(source).with_context(|| format!("cannot read {}", source))?;
+    let mut reader = UnformattedReader::new(BufReader::new(input));
+    let mut file = open(global, path, args.owner(0))?;
+    let mut count = 0u64;
+    while let Some(record) = reader
+        .next_record()
+        .with_context(|| format!("{}: bad entry after record {}", source, count))?
+    {
+        count += 1;
+        file.insert(&record)
+            .with_context(|| format!("record {} rejected", count))?;
+    }
+    file.close()?;
+    println!("{} records loaded.", count);
+    Ok(())
+}
+
+fn save(global: &Global, args: &Args) -> Result<()> {
+    let usage = "-SAVE <file> <unformatted file> [N <key number>] [/O<owner>]";
+    args.expect(2, usage)?;
+    let (path, target) = (&args.positional[0], &args.positional[1]);
+    let key_number = match args.positional.get(2).map(|s| s.to_ascii_uppercase()).as_deref() {
+        None => 0,
+        Some("N") => match args.positional.get(3).map(|k| k.parse::<i32>()) {
+            Some(Ok(key)) => key,
+            _ => bail!("usage: xtutil {}", usage),
+        },
+        Some("Y") => bail!("saving by a key from a description file is not supported"),
+        Some(_) => bail!("usage: xtutil {}", usage),
+    };
+
+    let mut file = open(global, path, args.owner(0))?;
+    let output = fs::File::create(target).with_context(|| format!("cannot create {}", target))?;
+    let mut output = BufWriter::new(output);
+    let mut count = 0u64;
+    walk(&mut file, Some(key_number), |record| {
+        count += 1;
+        write_unformatted(&mut output, record)?;
+        Ok(())
+    })?;
+    output.write_all(&[END_OF_DATA])?;
+    output.flush()?;
+    file.close()?;
+    println!("{} records saved.", count);
+    Ok(())
+}
+
+/// Append one record in unformatted layout
+fn write_unformatted(out: &mut impl Write, record: &[u8]) -> io::Result<()> {
+    write!(out, "{},", record.len())?;
+    out.write_all(record)?;
+    out.write_all(b"\r\n")
+}
+
+/// Reads records back from an unformatted file
+struct UnformattedReader<R> {
+    input: R,
+}
+
+impl<R: BufRead> UnformattedReader<R> {
+    fn new(input: R) -> Self {
+        UnformattedReader { input }
+    }
+
+    /// Next record, `None` at Ctrl-Z or the end of the file
+    fn next_record(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut length = String::new();
+        loop {
+            let mut byte = [0u8];
+            if self.input.read(&mut byte)? == 0 {
+                return match length.is_empty() {
+                    true => Ok(None),
+                    false => Err(invalid("truncated length")),
+                };
+            }
+            match byte[0] {
+                END_OF_DATA if length.is_empty() => return Ok(None),
+                b'\r' | b'\n' if length.is_empty() => continue,
+                b',' => break,
+                b if b.is_ascii_digit() => length.push(b as char),
+                _ => return Err(invalid("expected <length>,")),
+            }
+        }
+        let length: usize = length.parse().map_err(|_| invalid("bad length"))?;
+        let mut record = vec![0u8; length];
+        self.input.read_exact(&mut record)?;
+
+        // Each record ends with CR LF
+        let mut end = [0u8; 2];
+        self.input.read_exact(&mut end)?;
+        if &end != b"\r\n" {
+            return Err(invalid("record not followed by CR LF"));
+        }
+        Ok(Some(record))
+    }
+}
+
+fn parse_key_type(name: &str) -> Option<KeyType> {
+    let name: String = name.chars().filter(|c| !c.is_whitespace()).collect();
+    Some(match name.to_ascii_lowercase().as_str() {
+        "string" => KeyType::String,
+        "integer" => KeyType::Integer,
+        "float" => KeyType::Float,
+        "date" => KeyType::Date,
+        "time" => KeyType::Time,
+        "decimal" => KeyType::Decimal,
+        "money" => KeyType::Money,
+        "logical" => KeyType::Logical,
+        "numeric" => KeyType::Numeric,
+        "bfloat" => KeyType::BFloat,
+        "lstring" => KeyType::LString,
+        "zstring" => KeyType::ZString,
+        "unsignedbinary" | "unsigned" => KeyType::UnsignedBinary,
+        "autoincrement" => KeyType::AutoIncrement,
+        "utf8" | "utf-8" => KeyType::Utf8,
+        _ => return None,
+    })
+}
+
+/// Parse a BUTIL description file into a Create specification.
+///
+/// Entries are `keyword=value` separated by blanks or newlines:
+/// `record=`, `variable=`, `truncate=`, `compress=`, `key=` (number of
+/// keys), `page=`, `allocation=`, then per segment `position=` (1-based),
+/// `length=`, `duplicates=`, `modifiable=`, `type=`, `descending=`,
+/// `alternate=`, `nullkey=` with `value=` (hex), and `segment=` (`y` when
+/// another segment of the same key follows). `nocase=` (case-insensitive
+/// string compare) is an Xtrieve extension.
+pub(crate) fn parse_description(text: &str) -> Result<FileBuilder> {
+    // Values may hold blanks ("type=unsigned binary"): words without '='
+    // belong to the previous value
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for word in text.split_whitespace() {
+        match word.split_once('=') {
+            Some((keyword, value)) => entries.push((keyword.to_ascii_lowercase(), value.to_string())),
+            None => match entries.last_mut() {
+                Some((_, value)) => {
+                    value.push(' ');
+                    value.push_str(word);
+                }
+                None => bail!("expected keyword=value, found '{}'", word),
+            },
+        }
+    }
+
+    let flag = |value: &str| -> Result<bool> {
+        match value.to_ascii_lowercase().as_str() {
+            "y" | "yes" => Ok(true),
+            "n" | "no" => Ok(false),
+            _ => bail!("expected y or n, found '{}'", value),
+        }
+    };
+    let number = |keyword: &str, value: &str| -> Result<u16> {
+        value
+            .parse()
+            .with_context(|| format!("{}= needs a number, found '{}'", keyword, value))
+    };
+
+    let mut record_length = None;
+    let mut key_count = None;
+    let mut page_size = None;
+    let (mut variable, mut truncate, mut compress) = (false, false, false);
+    let mut allocation = 0;
+    let mut keys = Vec::new();
+    let mut key = KeyBuilder::new();
+    let mut segment: Option<(u16, u16, KeyType, bool)> = None;
+
+    let mut entries = entries.into_iter().peekable();
+    while let Some((keyword, value)) = entries.next() {
+        match keyword.as_str() {
+            "record" => record_length = Some(number(&keyword, &value)?),
+            "key" => key_count = Some(number(&keyword, &value)?),
+            "page" => page_size = Some(number(&keyword, &value)?),
+            "variable" => variable = flag(&value)?,
+            "truncate" => truncate = flag(&value)?,
+            "compress" => compress = flag(&value)?,
+            "allocation" => allocation = number(&keyword, &value)?,
+            "replace" => {}
+            "position" => {
+                if segment.is_some() {
+                    bail!("position={} starts a segment before the previous one ended with segment=", value);
+                }
+                let position = number(&keyword, &value)?;
+                if position == 0 {
+                    bail!("position= is 1-based");
+                }
+                segment = Some((position - 1, 0, KeyType::String, false));
+            }
+            "length" | "type" | "descending" | "duplicates" | "modifiable" | "alternate" | "nocase"
+            | "nullkey" | "value" | "name" | "segment" => {
+                let Some((_, length, key_type, descending)) = segment.as_mut() else {
+                    bail!("{}= before position=", keyword);
+                };
+                match keyword.as_str() {
+                    "length" => *length = number(&keyword, &value)?,
+                    "type" => *key_type = parse_key_type(&value).with_context(|| format!("unknown type '{}'", value))?,
+                    "descending" => *descending = flag(&value)?,
+                    "duplicates" if flag(&value)? => key = key.duplicates(),
+                    "modifiable" if flag(&value)? => key = key.modifiable(),
+                    "alternate" if flag(&value)? => key = key.acs(0),
+                    "nocase" if flag(&value)? => key = key.no_case(),
+                    "nullkey" if flag(&value)? => {
+                        let null = match entries.next_if(|(k, _)| k == "value") {
+                            Some((_, v)) => u8::from_str_radix(v.trim_start_matches("0x"), 16)
+                                .with_context(|| format!("value= needs a hex byte, found '{}'", v))?,
+                            None => 0,
+                        };
+                        key = key.null_value(null);
+                    }
+                    "segment" => {
+                        let (position, length, key_type, descending) = segment.take().unwrap();
+                        key = key.segment(position, length, key_type);
+                        if descending {
+                            key = key.descending();
+                        }
+                        if !flag(&value)? {
+                            keys.push(std::mem::take(&mut key));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => bail!("unknown keyword {}=", keyword),
+        }
+    }
+
+    if segment.is_some() {
+        bail!("last segment is missing segment=");
+    }
+    let Some(record_length) = record_length else {
+        bail!("record= is missing");
+    };
+    let key_count = key_count.context("key= is missing")?;
+    if keys.len() != key_count as usize {
+        bail!("key={} but {} keys are described", key_count, keys.len());
+    }
+
+    let mut builder = FileBuilder::new(record_length).preallocate(allocation);
+    if let Some(page_size) = page_size {
+        builder = builder.page_size(page_size);
+    }
+    if variable {
+        builder = builder.variable_records();
+    }
+    if truncate {
+        builder = builder.blank_truncation();
+    }
+    if compress {
+        builder = builder.compression();
+    }
+    Ok(keys.into_iter().fold(builder, FileBuilder::key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_description() {
+        let parsed = parse_description(
+            "record=64 variable=n key=2 page=512 allocation=0 replace=n\n\
+             position=1 length=4 duplicates=n modifiable=n type=unsigned binary\n\
+             alternate=n nullkey=n segment=n\n\
+             position=5 length=10 duplicates=y modifiable=y type=string\n\
+             alternate=n nocase=y nullkey=y value=20 segment=y\n\
+             position=15 length=4 duplicates=y modifiable=y type=date\n\
+             descending=y alternate=n nullkey=y value=20 segment=n\n",
+        )
+        .unwrap();
+        let expected = FileBuilder::new(64)
+            .page_size(512)
+            .key(KeyBuilder::unsigned(0, 4))
+            .key(
+                KeyBuilder::string(4, 10)
+                    .segment(14, 4, KeyType::Date)
+                    .descending()
+                    .duplicates()
+                    .modifiable()
+                    .null_value(b' ')
+                    .no_case(),
+            );
+        assert_eq!(parsed.to_bytes(), expected.to_bytes());
+
+        assert!(parse_description("record=10 key=1 position=1 length=2").is_err());
+        assert!(parse_description("record=10 key=2 position=1 length=2 segment=n").is_err());
+        assert!(parse_description("record=10 key=1 position=1 type=blob segment=n").is_err());
+    }
+
+    #[test]
+    fn test_unformatted_roundtrip() {
+        let records: [&[u8]; 3] = [b"abc", b"", b"line\r\nbreak,\x1a"];
+        let mut data = Vec::new();
+        for record in records {
+            write_unformatted(&mut data, record).unwrap();
+        }
+        data.push(END_OF_DATA);
+        assert!(data.starts_with(b"3,abc\r\n0,\r\n"));
+
+        let mut reader = UnformattedReader::new(&data[..]);
+        for record in records {
+            assert_eq!(reader.next_record().unwrap().as_deref(), Some(record));
+        }
+        assert_eq!(reader.next_record().unwrap(), None);
+
+        let mut bad = UnformattedReader::new(&b"5,abc"[..]);
+        assert!(bad.next_record().is_err());
+    }
+}
@@ -0,0 +1,31 @@
+//! `xtutil cancel`: stop a request running on the server
+//!
+//! ```text
+//! xtutil cancel <request id>
+//! ```
+//!
+//! The request must have been sent with that id, as `xtutil sql --id`
+//! does. It gives up with status 200 at its next check, leaving its files
+//! as they were.
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+
+use crate::Global;
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let (Some(id), None) = (args.next(), args.next()) else {
+        bail!("usage: xtutil cancel <request id>");
+    };
+    let request_id = id.parse::<u64>().with_context(|| format!("bad request id {}", id))?;
+
+    let mut client = global.connect()?;
+    if client.cancel(request_id)? {
+        println!("cancelled request {}", request_id);
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("no request {} is running", request_id);
+        Ok(ExitCode::FAILURE)
+    }
+}
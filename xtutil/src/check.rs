@@ -0,0 +1,107 @@
+//! `xtutil check`: verify a file's structure
+//!
+//! ```text
+//! xtutil check <file>... [--quiet]
+//! ```
+//!
+//! Runs the engine's integrity check on each file, reading it directly
+//! rather than through a server, and prints what was found. The exit
+//! status is 1 when any file has a problem, so the command can run from
+//! cron; `--quiet` prints only files with problems.
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_engine::storage::{check_file, CheckReport};
+
+use crate::{local_path, Global};
+
+pub fn run(global: &Global, args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut paths = Vec::new();
+    let mut quiet = false;
+    for arg in args {
+        match arg.as_str() {
+            "--quiet" => quiet = true,
+            _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        bail!("usage: xtutil check <file>... [--quiet]");
+    }
+
+    let mut clean = true;
+    for path in &paths {
+        let local = local_path(global, path);
+        let report = check_file(&local).with_context(|| format!("cannot check {}", local.display()))?;
+        clean &= report.is_clean();
+        if !(quiet && report.is_clean()) {
+            print!("{}", format_report(path, &report));
+        }
+    }
+    Ok(if clean { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn format_report(path: &str, report: &CheckReport) -> String {
+    let mut out = format!("{}\n", path);
+    if report.page_size != 0 {
+        out += &format!(
+            "  FCR          page size {}, {} pages, {} records counted\n",
+            report.page_size, report.pages, report.fcr_records
+        );
+        out += &format!("  data pages   {} pages, {} records\n", report.data_pages, report.records);
+        out += &format!(
+            "  index pages  {} pages, {} unused pages\n",
+            report.index_pages,
+            report.unused_pages.len()
+        );
+        for (key, entries) in report.key_entries.iter().enumerate() {
+            out += &format!("  key {:<8} {} entries\n", key, entries);
+        }
+    }
+    if report.is_clean() {
+        out += "  no problems found\n";
+    } else {
+        for problem in &report.problems {
+            out += &format!("  {}\n", problem);
+        }
+        out += &format!("  {} problem(s) found\n", report.problems.len());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtrieve_engine::storage::check::{Area, Problem};
+
+    #[test]
+    fn test_format_report() {
+        let mut report = CheckReport {
+            page_size: 1024,
+            pages: 4,
+            data_pages: 1,
+            index_pages: 2,
+            fcr_records: 3,
+            records: 3,
+            key_entries: vec![3, 2],
+            ..Default::default()
+        };
+        report.problems.push(Problem {
+            area: Area::CrossReference,
+            page: None,
+            message: "key 1 indexes 2 of 3 records".into(),
+        });
+        assert_eq!(
+            format_report("CUST.DAT", &report),
+            "CUST.DAT\n\
+             \x20 FCR          page size 1024, 4 pages, 3 records counted\n\
+             \x20 data pages   1 pages, 3 records\n\
+             \x20 index pages  2 pages, 0 unused pages\n\
+             \x20 key 0        3 entries\n\
+             \x20 key 1        2 entries\n\
+             \x20 [cross-reference] key 1 indexes 2 of 3 records\n\
+             \x20 1 problem(s) found\n"
+        );
+    }
+}
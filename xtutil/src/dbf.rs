@@ -0,0 +1,424 @@
+//! `xtutil import-dbf`: create a file from a dBase table
+//!
+//! ```text
+//! xtutil import-dbf <dbf> <file> [--fields <name,...>] [--key <name+...>]...
+//!                   [--unique <name+...>]... [--page-size <n>] [--map <path>]
+//!                   [--batch <n>] [--deleted]
+//! ```
+//!
+//! The chosen fields (all but memos by default) are laid out one after
+//! another in DBF order: character fields as strings of the same width,
+//! numeric fields as integers or, with decimal places, packed decimals,
+//! dates as Btrieve dates and logicals as one byte. Each `--key` makes a
+//! key allowing duplicates and each `--unique` one that doesn't, in the
+//! order given; `a+b` joins fields into segments of one key. `--map`
+//! writes the layout as an `import` mapping file. Records marked deleted
+//! are skipped unless `--deleted` is given.
+
+use std::fs;
+use std::io::{BufReader, Read};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::ddf::{Column, DataType};
+use xtrieve_client::{BtrieveFile, FileBuilder, KeyBuilder};
+
+use crate::{flag_value, Global};
+
+/// Records per InsertExtended batch unless `--batch` says otherwise
+const DEFAULT_BATCH: usize = 500;
+
+/// Marks the end of the field descriptors
+const HEADER_END: u8 = 0x0D;
+/// Written after the last record
+const END_OF_FILE: u8 = 0x1A;
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut positional = Vec::new();
+    let mut chosen: Option<Vec<String>> = None;
+    let mut keys: Vec<(Vec<String>, bool)> = Vec::new();
+    let mut page_size = None;
+    let mut map_path = None;
+    let mut batch_size = DEFAULT_BATCH;
+    let mut include_deleted = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fields" => {
+                chosen = Some(flag_value(&mut args, "--fields")?.split(',').map(|f| f.trim().to_string()).collect())
+            }
+            "--key" | "--unique" => {
+                let fields = flag_value(&mut args, &arg)?.split('+').map(|f| f.trim().to_string()).collect();
+                keys.push((fields, arg == "--unique"));
+            }
+            "--page-size" => {
+                let n = flag_value(&mut args, "--page-size")?;
+                page_size = Some(n.parse::<u16>().with_context(|| format!("bad page size {}", n))?);
+            }
+            "--map" => map_path = Some(flag_value(&mut args, "--map")?),
+            "--batch" => {
+                let n = flag_value(&mut args, "--batch")?;
+                batch_size = n.parse().ok().filter(|&n| n > 0).with_context(|| format!("bad batch size {}", n))?;
+            }
+            "--deleted" => include_deleted = true,
+            _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            _ => positional.push(arg),
+        }
+    }
+    let [source, path] = &positional[..] else {
+        bail!("usage: xtutil import-dbf <dbf> <file> [--fields <name,...>] [--key <name+...>]...");
+    };
+
+    let input = fs::File::open(source).with_context(|| format!("cannot read {}", source))?;
+    let mut dbf = DbfReader::new(BufReader::new(input)).with_context(|| format!("{} is not a DBF file", source))?;
+    let layout = Layout::new(&dbf.fields, chosen.as_deref())?;
+    let builder = layout.file_builder(&keys, page_size)?;
+    if let Some(map_path) = &map_path {
+        fs::write(map_path, layout.mapping()).with_context(|| format!("cannot write {}", map_path))?;
+    }
+
+    builder.create(&mut global.connect()?, path).with_context(|| format!("cannot create {}", path))?;
+    let mut file = BtrieveFile::open(global.connect()?, path, 0).with_context(|| format!("cannot open {}", path))?;
+
+    let (mut read, mut inserted, mut rejected, mut skipped) = (0u64, 0u64, 0u64, 0u64);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut numbers = Vec::with_capacity(batch_size);
+    loop {
+        let next = dbf.next_record().with_context(|| format!("{}: record {}", source, read + 1))?;
+        if let Some((deleted, data)) = &next {
+            read += 1;
+            if *deleted && !include_deleted {
+                skipped += 1;
+            } else {
+                match layout.convert(data) {
+                    Ok(record) => {
+                        batch.push(record);
+                        numbers.push(read);
+                    }
+                    Err(e) => {
+                        rejected += 1;
+                        eprintln!("record {}: {:#}", read, e);
+                    }
+                }
+            }
+        }
+        if batch.len() == batch_size || (next.is_none() && !batch.is_empty()) {
+            let report = file.insert_many(&batch)?;
+            inserted += report.inserted as u64;
+            for (index, e) in &report.failures {
+                rejected += 1;
+                eprintln!("record {}: {}", numbers[*index], e);
+            }
+            batch.clear();
+            numbers.clear();
+        }
+        if next.is_none() {
+            break;
+        }
+    }
+    file.close()?;
+
+    println!(
+        "{} records read, {} inserted, {} rejected, {} deleted skipped",
+        read, inserted, rejected, skipped
+    );
+    Ok(if rejected == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// A field descriptor from the DBF header
+#[derive(Debug, Clone)]
+struct DbfField {
+    name: String,
+    kind: u8,
+    length: u8,
+    decimals: u8,
+    /// Offset in the record, after the deletion flag
+    offset: usize,
+}
+
+/// Reads dBase III/IV and FoxPro tables record by record
+struct DbfReader<R> {
+    input: R,
+    fields: Vec<DbfField>,
+    record_length: usize,
+    records: u32,
+    read: u32,
+}
+
+impl<R: Read> DbfReader<R> {
+    fn new(mut input: R) -> Result<Self> {
+        let mut header = [0u8; 32];
+        input.read_exact(&mut header)?;
+        let records = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let header_length = u16::from_le_bytes([header[8], header[9]]) as usize;
+        let record_length = u16::from_le_bytes([header[10], header[11]]) as usize;
+        if header_length < 33 || record_length < 1 {
+            bail!("bad header (header length {}, record length {})", header_length, record_length);
+        }
+
+        let mut descriptors = vec![0u8; header_length - 32];
+        input.read_exact(&mut descriptors)?;
+        let mut fields = Vec::new();
+        let mut offset = 1;
+        for descriptor in descriptors.chunks_exact(32) {
+            if descriptor[0] == HEADER_END {
+                break;
+            }
+            let end = descriptor[..11].iter().position(|&b| b == 0).unwrap_or(11);
+            let field = DbfField {
+                name: String::from_utf8_lossy(&descriptor[..end]).trim().to_string(),
+                kind: descriptor[11].to_ascii_uppercase(),
+                length: descriptor[16],
+                decimals: descriptor[17],
+                offset,
+            };
+            offset += field.length as usize;
+            fields.push(field);
+        }
+        if offset > record_length {
+            bail!("fields need {} bytes but records hold {}", offset, record_length);
+        }
+        Ok(DbfReader { input, fields, record_length, records, read: 0 })
+    }
+
+    /// The next record and whether it is marked deleted
+    fn next_record(&mut self) -> Result<Option<(bool, Vec<u8>)>> {
+        if self.read == self.records {
+            return Ok(None);
+        }
+        let mut data = vec![0u8; self.record_length];
+        match self.input.read(&mut data[..1])? {
+            0 => return Ok(None),
+            _ if data[0] == END_OF_FILE => return Ok(None),
+            _ => self.input.read_exact(&mut data[1..])?,
+        }
+        self.read += 1;
+        Ok(Some((data[0] == b'*', data)))
+    }
+}
+
+/// Where each chosen DBF field goes in the Btrieve record
+struct Layout {
+    fields: Vec<(DbfField, Column)>,
+    record_length: u16,
+}
+
+impl Layout {
+    fn new(fields: &[DbfField], chosen: Option<&[String]>) -> Result<Self> {
+        let selected: Vec<&DbfField> = match chosen {
+            Some(names) => names
+                .iter()
+                .map(|name| {
+                    fields
+                        .iter()
+                        .find(|f| f.name.eq_ignore_ascii_case(name))
+                        .with_context(|| format!("no field {} in the DBF", name))
+                })
+                .collect::<Result<_>>()?,
+            None => fields.iter().collect(),
+        };
+
+        let mut layout = Layout { fields: Vec::new(), record_length: 0 };
+        for field in selected {
+            let (data_type, size) = match (field.kind, field.decimals) {
+                (b'C', _) => (DataType::String, field.length as u16),
+                (b'N' | b'F', 0) if field.length <= 9 => (DataType::Integer, 4),
+                (b'N' | b'F', 0) if field.length <= 18 => (DataType::Integer, 8),
+                // Every character could be a digit, plus the sign nibble
+                (b'N' | b'F', _) => (DataType::Decimal, field.length as u16 / 2 + 1),
+                (b'D', _) => (DataType::Date, 4),
+                (b'L', _) => (DataType::Logical, 1),
+                (b'I', _) => (DataType::Integer, 4),
+                (kind, _) => {
+                    eprintln!("field {} of type {} is not imported", field.name, kind as char);
+                    continue;
+                }
+            };
+            let column = Column {
+                id: layout.fields.len() as u16 + 1,
+                name: field.name.clone(),
+                data_type,
+                offset: layout.record_length,
+                size,
+                decimals: field.decimals,
+                flags: 0,
+            };
+            layout.record_length += size;
+            layout.fields.push((field.clone(), column));
+        }
+        if layout.fields.is_empty() {
+            bail!("no fields to import");
+        }
+        Ok(layout)
+    }
+
+    fn column(&self, name: &str) -> Result<&Column> {
+        self.fields
+            .iter()
+            .map(|(_, c)| c)
+            .find(|c| c.name.eq_ignore_ascii_case(name))
+            .with_context(|| format!("key field {} is not imported", name))
+    }
+
+    /// Create specification: the smallest page from 1024 bytes up that
+    /// holds a record, unless one is given
+    fn file_builder(&self, keys: &[(Vec<String>, bool)], page_size: Option<u16>) -> Result<FileBuilder> {
+        let page_size = match page_size {
+            Some(size) => size,
+            None => [1024, 2048, 4096]
+                .into_iter()
+                .find(|&size| self.record_length as usize + 32 <= size as usize)
+                .with_context(|| format!("records of {} bytes don't fit a 4096-byte page", self.record_length))?,
+        };
+        let mut builder = FileBuilder::new(self.record_length).page_size(page_size);
+        for (names, unique) in keys {
+            let mut key = KeyBuilder::new();
+            for name in names {
+                let column = self.column(name)?;
+                let key_type = column.data_type.key_type().context("no key type")?;
+                key = key.segment(column.offset, column.size, key_type);
+            }
+            builder = builder.key(if *unique { key } else { key.duplicates() });
+        }
+        Ok(builder)
+    }
+
+    /// The layout as `import --map` lines
+    fn mapping(&self) -> String {
+        let mut out = String::new();
+        for (_, column) in &self.fields {
+            let type_name = match column.data_type {
+                DataType::String => "string",
+                DataType::Integer => "integer",
+                DataType::Decimal => "decimal",
+                DataType::Date => "date",
+                _ => "logical",
+            };
+            out += &format!("{:<12} {:<8} {:>5} {:>4}", column.name, type_name, column.offset, column.size);
+            if column.decimals > 0 {
+                out += &format!(" {}", column.decimals);
+            }
+            out += "\n";
+        }
+        out
+    }
+
+    /// Build the Btrieve record for a DBF record
+    fn convert(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut record = vec![0u8; self.record_length as usize];
+        for (field, column) in &self.fields {
+            let raw = &data[field.offset..field.offset + field.length as usize];
+            match field.kind {
+                // Text keeps its bytes and code page, as Btrieve strings do
+                b'C' => record[column.offset as usize..][..raw.len()].copy_from_slice(raw),
+                b'I' => record[column.offset as usize..][..4].copy_from_slice(raw),
+                b'D' => {
+                    let text = String::from_utf8_lossy(raw);
+                    let text = text.trim();
+                    let date = match text.len() {
+                        8 => format!("{}-{}-{}", &text[..4], &text[4..6], &text[6..]),
+                        _ => String::new(),
+                    };
+                    column.encode(&date, &mut record)?;
+                }
+                b'L' => {
+                    let text = match raw[0] {
+                        b'?' | b' ' => "",
+                        _ => std::str::from_utf8(raw).unwrap_or("?"),
+                    };
+                    column.encode(text, &mut record)?;
+                }
+                _ => column.encode(&String::from_utf8_lossy(raw), &mut record)?,
+            }
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtrieve_client::ddf::Value;
+
+    /// dBase III table: NAME C(8), QTY N(5), PRICE N(7,2), DUE D, PAID L, NOTES M
+    fn table(records: &[&str]) -> Vec<u8> {
+        let fields: [(&str, u8, u8, u8); 6] = [
+            ("NAME", b'C', 8, 0),
+            ("QTY", b'N', 5, 0),
+            ("PRICE", b'N', 7, 2),
+            ("DUE", b'D', 8, 0),
+            ("PAID", b'L', 1, 0),
+            ("NOTES", b'M', 10, 0),
+        ];
+        let record_length = 1 + fields.iter().map(|f| f.2 as u16).sum::<u16>();
+        let header_length = 32 + 32 * fields.len() as u16 + 1;
+        let mut dbf = vec![0x03, 124, 1, 1];
+        dbf.extend((records.len() as u32).to_le_bytes());
+        dbf.extend(header_length.to_le_bytes());
+        dbf.extend(record_length.to_le_bytes());
+        dbf.resize(32, 0);
+        for (name, kind, length, decimals) in fields {
+            let mut descriptor = [0u8; 32];
+            descriptor[..name.len()].copy_from_slice(name.as_bytes());
+            descriptor[11] = kind;
+            descriptor[16] = length;
+            descriptor[17] = decimals;
+            dbf.extend(descriptor);
+        }
+        dbf.push(HEADER_END);
+        for record in records {
+            assert_eq!(record.len(), record_length as usize);
+            dbf.extend(record.as_bytes());
+        }
+        dbf.push(END_OF_FILE);
+        dbf
+    }
+
+    #[test]
+    fn test_read_and_convert() {
+        let dbf = table(&[
+            " WIDGET     12  19.9920240131T0000000001",
+            "*GADGET      3   1.05        ?          ",
+        ]);
+        let mut reader = DbfReader::new(&dbf[..]).unwrap();
+        assert_eq!(reader.fields.len(), 6);
+        assert_eq!((reader.fields[2].offset, reader.fields[2].decimals), (14, 2));
+
+        let layout = Layout::new(&reader.fields, None).unwrap();
+        assert_eq!(
+            layout.mapping(),
+            "NAME         string       0    8\n\
+             QTY          integer      8    4\n\
+             PRICE        decimal     12    4 2\n\
+             DUE          date        16    4\n\
+             PAID         logical     20    1\n"
+        );
+
+        let (deleted, data) = reader.next_record().unwrap().unwrap();
+        assert!(!deleted);
+        let record = layout.convert(&data).unwrap();
+        let values: Vec<String> = layout.fields.iter().map(|(_, c)| c.decode(&record).unwrap().to_string()).collect();
+        assert_eq!(values, ["WIDGET", "12", "19.99", "2024-01-31", "true"]);
+
+        let (deleted, data) = reader.next_record().unwrap().unwrap();
+        assert!(deleted);
+        let record = layout.convert(&data).unwrap();
+        assert_eq!(layout.fields[3].1.decode(&record).unwrap(), Value::Null);
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_keys_and_fields() {
+        let dbf = table(&[]);
+        let reader = DbfReader::new(&dbf[..]).unwrap();
+        let chosen = ["price".to_string(), "name".to_string()];
+        let layout = Layout::new(&reader.fields, Some(&chosen)).unwrap();
+        assert_eq!(layout.record_length, 12);
+
+        let keys = vec![(vec!["NAME".to_string()], true), (vec!["PRICE".to_string(), "NAME".to_string()], false)];
+        let spec = layout.file_builder(&keys, None).unwrap().to_bytes();
+        assert_eq!(u16::from_le_bytes([spec[2], spec[3]]), 1024);
+        assert_eq!(u16::from_le_bytes([spec[4], spec[5]]), 2);
+        assert!(layout.file_builder(&[(vec!["QTY".to_string()], false)], None).is_err());
+        assert!(Layout::new(&reader.fields, Some(&["NOPE".to_string()])).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! `xtutil dump`: decode one page of a file
+//!
+//! ```text
+//! xtutil dump <file> --page <n> [--as fcr|data|index|hex] [--hex]
+//! ```
+//!
+//! Reads the page directly from disk, from its index file if the file
+//! keeps index pages apart, and prints its fields as stored: the FCR on
+//! page 0, the header and slot directory of a data page, or the header
+//! and entries of an index node. The layout is guessed from the page
+//! unless `--as` names it; `--hex` adds a hex dump of the page.
+
+use std::fmt::Write as _;
+use std::io::{Read, Seek, SeekFrom};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_engine::storage::btree::IndexNode;
+use xtrieve_engine::storage::files::Image;
+use xtrieve_engine::storage::page::PAGE_SIZES;
+use xtrieve_engine::storage::record::{DataPage, SlotEntry};
+use xtrieve_engine::storage::PageType;
+
+use crate::{flag_value, local_path, Global};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    Fcr,
+    Data,
+    Index,
+    Hex,
+}
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut path = None;
+    let mut page_number = None;
+    let mut layout = None;
+    let mut hex = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--page" => {
+                let n = flag_value(&mut args, "--page")?;
+                page_number = Some(n.parse::<u32>().with_context(|| format!("bad page number {}", n))?);
+            }
+            "--as" => {
+                layout = Some(match flag_value(&mut args, "--as")?.as_str() {
+                    "fcr" => Layout::Fcr,
+                    "data" => Layout::Data,
+                    "index" => Layout::Index,
+                    "hex" => Layout::Hex,
+                    other => bail!("unknown layout {} (fcr, data, index or hex)", other),
+                })
+            }
+            "--hex" => hex = true,
+            _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            _ if path.is_none() => path = Some(arg),
+            _ => bail!("unexpected argument {}", arg),
+        }
+    }
+    let (Some(path), Some(page_number)) = (path, page_number) else {
+        bail!("usage: xtutil dump <file> --page <n> [--as fcr|data|index|hex] [--hex]");
+    };
+
+    let local = local_path(global, &path);
+    let mut file = Image::open(&local, false).with_context(|| format!("cannot open {}", local.display()))?;
+    let mut header = [0u8; 0x0A];
+    file.read_exact(&mut header).context("file is too short to hold an FCR")?;
+    let page_size = u16::from_le_bytes([header[0x08], header[0x09]]);
+    if !PAGE_SIZES.contains(&page_size) {
+        bail!("invalid page size {} in the FCR", page_size);
+    }
+    let pages = file.seek(SeekFrom::End(0))? / page_size as u64;
+    if page_number as u64 >= pages {
+        bail!("page {} is past the end of the file ({} pages)", page_number, pages);
+    }
+    let mut page = vec![0u8; page_size as usize];
+    file.seek(SeekFrom::Start(page_number as u64 * page_size as u64))?;
+    file.read_exact(&mut page)?;
+
+    let layout = layout.unwrap_or_else(|| guess_layout(page_number, &page));
+    let mut out = format!(
+        "{} page {} of {} ({} bytes, file offset {:#x})\n",
+        path,
+        page_number,
+        pages,
+        page_size,
+        page_number as u64 * page_size as u64
+    );
+    match layout {
+        Layout::Fcr => out += &format_fcr(&page),
+        Layout::Data => out += &format_data(page_number, &page),
+        Layout::Index => out += &format_index(page_number, &page),
+        Layout::Hex => {}
+    }
+    if hex || layout == Layout::Hex {
+        out += "hex\n";
+        out += &hex_dump(&page, 0);
+    }
+    print!("{}", out);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn guess_layout(page_number: u32, page: &[u8]) -> Layout {
+    if page_number == 0 {
+        Layout::Fcr
+    } else if PageType::from(page[0]) == PageType::Data {
+        Layout::Data
+    } else if page[0] == 0 && page[1] == 0 && u16::from_le_bytes([page[2], page[3]]) == page_number as u16 {
+        Layout::Index
+    } else {
+        Layout::Hex
+    }
+}
+
+fn u16_at(page: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([page[at], page[at + 1]])
+}
+
+fn u32_at(page: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([page[at], page[at + 1], page[at + 2], page[at + 3]])
+}
+
+/// Page links with 0xFFFFFFFF shown as none
+fn link(value: u32) -> String {
+    if value == u32::MAX {
+        "none".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_fcr(page: &[u8]) -> String {
+    let mut out = String::from("FCR\n");
+    let version = match page[0x04] {
+        0x0A => " (Btrieve 5.1)",
+        0x58 => " (Xtrieve)",
+        _ => "",
+    };
+    let _ = writeln!(out, "  0x04 version            {:#04x}{}", page[0x04], version);
+    let _ = writeln!(out, "  0x08 page size          {}", u16_at(page, 0x08));
+    let _ = writeln!(out, "  0x14 keys               {}", u16_at(page, 0x14));
+    let _ = writeln!(out, "  0x16 record length      {}", u16_at(page, 0x16));
+    let _ = writeln!(out, "  0x1c records            {}", u32_at(page, 0x1C));
+    let _ = writeln!(out, "  0x20 pages              {}", u32_at(page, 0x20));
+    let _ = writeln!(out, "  0x24 first data page    {}", u32_at(page, 0x24));
+
+    let keys = (u16_at(page, 0x14) as usize).min((page.len() - 0x110) / 16);
+    for key in 0..keys {
+        let at = 0x110 + key * 16;
+        let flags = u16_at(page, at + 12);
+        let mut names = Vec::new();
+        if flags & 0x0001 != 0 {
+            names.push("duplicates");
+        }
+        if flags & 0x0002 != 0 {
+            names.push("modifiable");
+        }
+        let _ = writeln!(
+            out,
+            "  {:#05x} key {:<2} position {} length {} flags {:#06x}{}",
+            at,
+            key,
+            u16_at(page, at + 8),
+            u16_at(page, at + 10),
+            flags,
+            names.iter().map(|n| format!(" {}", n)).collect::<String>()
+        );
+        let _ = writeln!(out, "        raw {}", hex_bytes(&page[at..at + 16]));
+    }
+    out
+}
+
+fn format_data(page_number: u32, page: &[u8]) -> String {
+    let page_size = page.len();
+    let slots = u16_at(page, 2) as usize;
+    let mut out = String::from("data page\n");
+    let _ = writeln!(out, "  0x00 type               {:#04x}", page[0]);
+    let _ = writeln!(out, "  0x02 slots              {}", slots);
+    let _ = writeln!(out, "  0x04 next page          {}", u32_at(page, 4));
+    let _ = writeln!(out, "  0x08 previous page      {}", u32_at(page, 8));
+    let _ = writeln!(out, "  0x0e free space         {}", u16_at(page, 14));
+    let free = u16_at(page, 16);
+    let _ = writeln!(
+        out,
+        "  0x10 first free slot    {}",
+        if free == DataPage::NO_FREE_SLOT { "none".to_string() } else { free.to_string() }
+    );
+
+    if DataPage::HEADER_SIZE + slots * SlotEntry::SIZE > page_size {
+        let _ = writeln!(out, "  slot directory does not fit the page");
+        return out;
+    }
+    // Slot n is the nth entry back from the end of the page
+    for slot in 0..slots {
+        let at = page_size - (slot + 1) * SlotEntry::SIZE;
+        let Ok(entry) = SlotEntry::from_bytes(&page[at..]) else { continue };
+        let mut state = Vec::new();
+        if entry.is_in_use() {
+            state.push("in-use");
+        }
+        if entry.is_deleted() {
+            state.push("deleted");
+        }
+        if entry.is_fragment() {
+            state.push("fragment");
+        }
+        let _ = writeln!(
+            out,
+            "  {:#05x} slot {:<3} offset {:<5} length {:<5} {:<16} file offset {:#x}",
+            at,
+            slot,
+            entry.offset,
+            entry.length,
+            state.join(","),
+            page_number as u64 * page_size as u64 + entry.offset as u64
+        );
+        let start = entry.offset as usize;
+        let end = (start + entry.length as usize).min(page_size);
+        if start < end {
+            out += &hex_dump(&page[start..end.min(start + 32)], start);
+        }
+    }
+    out
+}
+
+fn format_index(page_number: u32, page: &[u8]) -> String {
+    let count = u16_at(page, 6) as usize;
+    let mut out = String::from("index node\n");
+    let _ = writeln!(out, "  0x02 page number        {}", u16_at(page, 2));
+    let _ = writeln!(out, "  0x04 capacity           {}", u16_at(page, 4));
+    let _ = writeln!(out, "  0x06 entries            {}", count);
+    let _ = writeln!(out, "  0x08 previous sibling   {}", link(u32_at(page, 8)));
+    let _ = writeln!(out, "  0x0c next sibling       {}", link(u32_at(page, 12)));
+    if u16_at(page, 2) != page_number as u16 {
+        let _ = writeln!(out, "  page number does not match page {}", page_number);
+    }
+
+    let fits = (page.len() - IndexNode::HEADER_SIZE) / IndexNode::ENTRY_SIZE;
+    if count > fits {
+        let _ = writeln!(out, "  only {} entries fit the page", fits);
+    }
+    for entry in 0..count.min(fits) {
+        let at = IndexNode::HEADER_SIZE + entry * IndexNode::ENTRY_SIZE;
+        let offset = (u16_at(page, at + 4) as u32) << 16 | u16_at(page, at + 6) as u32;
+        let _ = writeln!(
+            out,
+            "  {:#05x} entry {:<3} key {} record {:#x} (page {}) link {}",
+            at,
+            entry,
+            hex_bytes(&page[at..at + 4]),
+            offset,
+            offset as usize / page.len(),
+            link(u32_at(page, at + 8))
+        );
+    }
+    out
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// 16 bytes a line with offsets from `base` and printable ASCII
+fn hex_dump(bytes: &[u8], base: usize) -> String {
+    let mut out = String::new();
+    for (n, line) in bytes.chunks(16).enumerate() {
+        let text: String = line
+            .iter()
+            .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+            .collect();
+        let _ = writeln!(out, "        {:04x}  {:<47}  {}", base + n * 16, hex_bytes(line), text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_page() {
+        let mut page = DataPage::new(3, 512);
+        page.insert_record(b"ABCD\x01\x02").unwrap();
+        page.insert_record(b"WXYZ\x00\x00").unwrap();
+        page.delete_record(1);
+        let page = page.to_bytes();
+        assert_eq!(guess_layout(3, &page), Layout::Data);
+        let text = format_data(3, &page);
+        assert!(text.contains("  0x0e free space         478\n"), "{}", text);
+        assert!(text.contains("  0x10 first free slot    1\n"));
+        assert!(text.contains(
+            "  0x1fb slot 0   offset 18    length 6     in-use           file offset 0x612\n\
+             \x20       0012  41 42 43 44 01 02                                ABCD..\n"
+        ));
+        assert!(text.contains("slot 1   offset 24    length 6     in-use,deleted"));
+    }
+
+    #[test]
+    fn test_index_page() {
+        let mut page = vec![0u8; 512];
+        page[2] = 5;
+        page[6] = 1;
+        page[8..16].fill(0xFF);
+        page[16..20].copy_from_slice(&7u32.to_le_bytes());
+        page[20..22].copy_from_slice(&1u16.to_le_bytes());
+        page[22..24].copy_from_slice(&0x0212u16.to_le_bytes());
+        page[24..28].fill(0xFF);
+        assert_eq!(guess_layout(5, &page), Layout::Index);
+        assert_eq!(guess_layout(6, &page), Layout::Hex);
+        let text = format_index(5, &page);
+        assert!(text.contains("  0x08 previous sibling   none\n"));
+        assert!(text.contains("  0x010 entry 0   key 07 00 00 00 record 0x10212 (page 129) link none\n"), "{}", text);
+    }
+}
@@ -0,0 +1,285 @@
+//! `xtutil export`: dump a file as CSV or JSON lines
+//!
+//! ```text
+//! xtutil export <file> [--format csv|jsonl] [--key <n>]
+//!               [--dict <dir> [--table <name>] | --map <mapping file>] [--where <filter>]
+//!               [--raw hex|latin1] [--owner <name>] [--output <path>]
+//! ```
+//!
+//! Records are read physically unless `--key` asks for key order. With a
+//! data dictionary the table describing the file supplies column names and
+//! types, as does a mapping file in the format `import` reads; otherwise
+//! each row is the whole record in one `record` column, as hex or as
+//! latin-1 text.
+//!
+//! `--where` keeps only the records passing a filter on those columns
+//! (`Status = 'X' AND Amount > 100`, see `xtrieve_client::filter`).
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::ddf::{Column, Dictionary, Table, Value};
+use xtrieve_client::filter::Filter;
+use xtrieve_client::BtrieveFile;
+
+use crate::import::parse_mapping;
+use crate::{flag_value, walk, Global};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    JsonLines,
+}
+
+/// How records without a schema are shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Raw {
+    Hex,
+    Latin1,
+}
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut path = None;
+    let mut format = Format::Csv;
+    let mut key_number = None;
+    let mut dictionary_dir = None;
+    let mut table_name = None;
+    let mut mapping = None;
+    let mut expression = None;
+    let mut raw = Raw::Hex;
+    let mut owner = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match flag_value(&mut args, "--format")?.as_str() {
+                    "csv" => Format::Csv,
+                    "jsonl" => Format::JsonLines,
+                    other => bail!("unknown format {} (csv or jsonl)", other),
+                }
+            }
+            "--key" => {
+                let key = flag_value(&mut args, "--key")?;
+                key_number = Some(key.parse().with_context(|| format!("bad key number {}", key))?);
+            }
+            "--dict" => dictionary_dir = Some(flag_value(&mut args, "--dict")?),
+            "--table" => table_name = Some(flag_value(&mut args, "--table")?),
+            "--map" => mapping = Some(flag_value(&mut args, "--map")?),
+            "--where" => expression = Some(flag_value(&mut args, "--where")?),
+            "--raw" => {
+                raw = match flag_value(&mut args, "--raw")?.as_str() {
+                    "hex" => Raw::Hex,
+                    "latin1" => Raw::Latin1,
+                    other => bail!("unknown raw encoding {} (hex or latin1)", other),
+                }
+            }
+            "--owner" => owner = Some(flag_value(&mut args, "--owner")?),
+            "--output" => output = Some(flag_value(&mut args, "--output")?),
+            _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            _ if path.is_none() => path = Some(arg),
+            _ => bail!("unexpected argument {}", arg),
+        }
+    }
+    let Some(path) = path else {
+        bail!("usage: xtutil export <file> [--format csv|jsonl] [--key <n>] [--dict <dir>]");
+    };
+
+    let dictionary = match &dictionary_dir {
+        Some(dir) => Some(Dictionary::load(&mut global.connect()?, dir).context("cannot load dictionary")?),
+        None => None,
+    };
+    let mapped;
+    let columns: Option<&[Column]> = match (&dictionary, &dictionary_dir, &mapping) {
+        (Some(_), _, Some(_)) => bail!("give either --dict or --map"),
+        (Some(dictionary), Some(dir), None) => {
+            Some(&find_table(dictionary, dir, &path, table_name.as_deref())?.columns)
+        }
+        (_, _, Some(mapping)) => {
+            let text = fs::read_to_string(mapping).with_context(|| format!("cannot read {}", mapping))?;
+            mapped = parse_mapping(&text).with_context(|| format!("in {}", mapping))?;
+            Some(&mapped)
+        }
+        _ => None,
+    };
+    let filter = match (&expression, columns) {
+        (Some(expression), Some(columns)) => Filter::parse(expression, columns).context("bad --where filter")?,
+        (Some(_), None) => bail!("--where needs the columns from --dict or --map"),
+        (None, _) => Filter::default(),
+    };
+
+    let client = global.connect()?;
+    let mut file = match &owner {
+        Some(owner) => BtrieveFile::open_with_owner(client, &path, 0, owner),
+        None => BtrieveFile::open(client, &path, 0),
+    }
+    .with_context(|| format!("cannot open {}", path))?;
+
+    let out: Box<dyn Write> = match &output {
+        Some(target) => Box::new(fs::File::create(target).with_context(|| format!("cannot create {}", target))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut writer = RowWriter {
+        out: BufWriter::new(out),
+        format,
+        columns: match columns {
+            Some(columns) => columns.iter().map(|c| c.name.clone()).collect(),
+            None => vec!["record".to_string()],
+        },
+    };
+    writer.header()?;
+
+    let mut count = 0u64;
+    walk(&mut file, key_number, |record| {
+        if !filter.matches(record) {
+            return Ok(());
+        }
+        let row = match columns {
+            Some(columns) => columns.iter().map(|c| c.decode(record)).collect::<Result<_, _>>()?,
+            None => vec![raw_value(record, raw)],
+        };
+        count += 1;
+        writer.row(&row)
+    })?;
+    writer.out.flush()?;
+    file.close()?;
+    if output.is_some() {
+        println!("{} records exported.", count);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The dictionary table stored in `path`, or the one named `--table`
+pub(crate) fn find_table<'a>(dictionary: &'a Dictionary, dir: &str, path: &str, name: Option<&str>) -> Result<&'a Table> {
+    if let Some(name) = name {
+        return dictionary
+            .table(name)
+            .with_context(|| format!("no table {} in the dictionary", name));
+    }
+    let wanted = path.trim_start_matches("./");
+    dictionary
+        .tables
+        .iter()
+        .find(|t| t.file_path(dir).trim_start_matches("./").eq_ignore_ascii_case(wanted))
+        .with_context(|| format!("no table in the dictionary is stored in {} (use --table)", path))
+}
+
+fn raw_value(record: &[u8], raw: Raw) -> Value {
+    match raw {
+        Raw::Hex => Value::Bytes(record.to_vec()),
+        // Latin-1 maps every byte to the code point of the same value
+        Raw::Latin1 => Value::Text(record.iter().map(|&b| b as char).collect()),
+    }
+}
+
+struct RowWriter<W: Write> {
+    out: W,
+    format: Format,
+    columns: Vec<String>,
+}
+
+impl<W: Write> RowWriter<W> {
+    fn header(&mut self) -> Result<()> {
+        if self.format == Format::Csv {
+            let names: Vec<_> = self.columns.iter().map(|c| csv_field(c)).collect();
+            writeln!(self.out, "{}", names.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn row(&mut self, values: &[Value]) -> Result<()> {
+        match self.format {
+            Format::Csv => {
+                let fields: Vec<_> = values.iter().map(|v| csv_field(&v.to_string())).collect();
+                writeln!(self.out, "{}", fields.join(","))?;
+            }
+            Format::JsonLines => {
+                let members: Vec<_> = self
+                    .columns
+                    .iter()
+                    .zip(values)
+                    .map(|(name, value)| format!("{}:{}", json_string(name), json_value(value)))
+                    .collect();
+                writeln!(self.out, "{{{}}}", members.join(","))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Quote a CSV field when it holds a comma, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Numbers and booleans stay JSON scalars, everything else is a string
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Int(_) | Value::UInt(_) | Value::Decimal { .. } | Value::Bool(_) => value.to_string(),
+        Value::Float(v) if v.is_finite() => value.to_string(),
+        Value::Float(_) => "null".to_string(),
+        other => json_string(&other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_row_formats() {
+        let row = [
+            Value::Int(-7),
+            Value::Text("say \"hi\", then\nleave".into()),
+            Value::Decimal { unscaled: 1250, scale: 2 },
+            Value::Null,
+        ];
+        let columns = vec!["id".to_string(), "note".to_string(), "total".to_string(), "due".to_string()];
+
+        let mut csv = RowWriter { out: Vec::new(), format: Format::Csv, columns: columns.clone() };
+        csv.header().unwrap();
+        csv.row(&row).unwrap();
+        assert_eq!(
+            String::from_utf8(csv.out).unwrap(),
+            "id,note,total,due\n-7,\"say \"\"hi\"\", then\nleave\",12.50,\n"
+        );
+
+        let mut jsonl = RowWriter { out: Vec::new(), format: Format::JsonLines, columns };
+        jsonl.header().unwrap();
+        jsonl.row(&row).unwrap();
+        assert_eq!(
+            String::from_utf8(jsonl.out).unwrap(),
+            "{\"id\":-7,\"note\":\"say \\\"hi\\\", then\\nleave\",\"total\":12.50,\"due\":null}\n"
+        );
+    }
+
+    #[test]
+    fn test_raw_values() {
+        assert_eq!(raw_value(&[0x41, 0xE9, 0x00], Raw::Hex).to_string(), "41e900");
+        assert_eq!(raw_value(&[0x41, 0xE9], Raw::Latin1), Value::Text("Aé".into()));
+    }
+}
@@ -0,0 +1,83 @@
+//! `xtutil delete`, `rename` and `truncate`: manage files on the server
+//!
+//! ```text
+//! xtutil delete <file>... [--owner <name>]
+//! xtutil rename <file> <new name> [--owner <name>]
+//! xtutil truncate <file>... [--owner <name>]
+//! ```
+//!
+//! Paths are the server's, so files can be managed without shell access
+//! to its host. A file another client has open is refused. The recycle
+//! bin, roll-forward log and index files of a file go with it when it is
+//! deleted or renamed; records truncated away don't go to the bin. An
+//! xtrieved started without `--file-admin` refuses delete and rename with
+//! status 45; a `file://` server always allows them.
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::btrieve::{delete_file, rename_file};
+use xtrieve_client::BtrieveFile;
+
+use crate::{flag_value, Global};
+
+/// File arguments and the `--owner` option
+fn paths_and_owner(mut args: impl Iterator<Item = String>) -> Result<(Vec<String>, Option<String>)> {
+    let mut paths = Vec::new();
+    let mut owner = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--owner" => owner = Some(flag_value(&mut args, "--owner")?),
+            _ => paths.push(arg),
+        }
+    }
+    Ok((paths, owner))
+}
+
+pub fn delete(global: &Global, args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let (paths, owner) = paths_and_owner(args)?;
+    if paths.is_empty() {
+        bail!("usage: xtutil delete <file>... [--owner <name>]");
+    }
+
+    let mut client = global.connect()?;
+    for path in &paths {
+        delete_file(&mut client, path, owner.as_deref()).with_context(|| format!("cannot delete {}", path))?;
+        println!("deleted {}", path);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn rename(global: &Global, args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let (paths, owner) = paths_and_owner(args)?;
+    let [from, to] = &paths[..] else {
+        bail!("usage: xtutil rename <file> <new name> [--owner <name>]");
+    };
+
+    let mut client = global.connect()?;
+    rename_file(&mut client, from, to, owner.as_deref())
+        .with_context(|| format!("cannot rename {} to {}", from, to))?;
+    println!("renamed {} to {}", from, to);
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn truncate(global: &Global, args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let (paths, owner) = paths_and_owner(args)?;
+    if paths.is_empty() {
+        bail!("usage: xtutil truncate <file>... [--owner <name>]");
+    }
+
+    for path in &paths {
+        let client = global.connect()?;
+        let mut file = match &owner {
+            Some(owner) => BtrieveFile::open_with_owner(client, path, 0, owner),
+            None => BtrieveFile::open(client, path, 0),
+        }
+        .with_context(|| format!("cannot open {}", path))?;
+        let records = file.stat()?.num_records;
+        file.truncate().with_context(|| format!("cannot truncate {}", path))?;
+        file.close()?;
+        println!("truncated {}: {} records removed", path, records);
+    }
+    Ok(ExitCode::SUCCESS)
+}
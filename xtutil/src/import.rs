@@ -0,0 +1,319 @@
+//! `xtutil import`: bulk-load a CSV file
+//!
+//! ```text
+//! xtutil import <csv> <file> (--dict <dir> [--table <name>] | --map <mapping file>)
+//!               [--create <description file>] [--batch <n>] [--owner <name>]
+//! ```
+//!
+//! The CSV header names the columns. Each one is stored at the offset and
+//! in the type given by the data dictionary table for the file, or by a
+//! mapping file with one `name type offset length [decimals]` line per
+//! column (offsets 0-based, `#` starts a comment):
+//!
+//! ```text
+//! Id       integer   0   4
+//! Name     string    4  30
+//! Balance  money    34   6  2
+//! ```
+//!
+//! `--create` first creates the file from a BUTIL description file. Rows
+//! are inserted in batches; rows that can't be encoded or are rejected by
+//! the engine are reported with their line number and skipped, and the
+//! exit status is 1 if there were any.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::ddf::{Column, DataType, Dictionary};
+use xtrieve_client::BtrieveFile;
+
+use crate::butil::parse_description;
+use crate::export::find_table;
+use crate::{flag_value, Global};
+
+/// Rows per InsertExtended batch unless `--batch` says otherwise
+const DEFAULT_BATCH: usize = 500;
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut positional = Vec::new();
+    let mut dictionary_dir = None;
+    let mut table_name = None;
+    let mut mapping = None;
+    let mut description = None;
+    let mut batch_size = DEFAULT_BATCH;
+    let mut owner = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dict" => dictionary_dir = Some(flag_value(&mut args, "--dict")?),
+            "--table" => table_name = Some(flag_value(&mut args, "--table")?),
+            "--map" => mapping = Some(flag_value(&mut args, "--map")?),
+            "--create" => description = Some(flag_value(&mut args, "--create")?),
+            "--batch" => {
+                let n = flag_value(&mut args, "--batch")?;
+                batch_size = n.parse().ok().filter(|&n| n > 0).with_context(|| format!("bad batch size {}", n))?;
+            }
+            "--owner" => owner = Some(flag_value(&mut args, "--owner")?),
+            _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            _ => positional.push(arg),
+        }
+    }
+    let [source, path] = &positional[..] else {
+        bail!("usage: xtutil import <csv> <file> (--dict <dir> | --map <mapping file>)");
+    };
+
+    let columns = match (&dictionary_dir, &mapping) {
+        (Some(dir), None) => {
+            let dictionary = Dictionary::load(&mut global.connect()?, dir).context("cannot load dictionary")?;
+            find_table(&dictionary, dir, path, table_name.as_deref())?.columns.clone()
+        }
+        (None, Some(mapping)) => {
+            let text = fs::read_to_string(mapping).with_context(|| format!("cannot read {}", mapping))?;
+            parse_mapping(&text).with_context(|| format!("in {}", mapping))?
+        }
+        _ => bail!("give either --dict or --map"),
+    };
+
+    if let Some(description) = &description {
+        let text = fs::read_to_string(description).with_context(|| format!("cannot read {}", description))?;
+        parse_description(&text)
+            .with_context(|| format!("in {}", description))?
+            .create(&mut global.connect()?, path)
+            .with_context(|| format!("cannot create {}", path))?;
+    }
+
+    let client = global.connect()?;
+    let mut file = match &owner {
+        Some(owner) => BtrieveFile::open_with_owner(client, path, 0, owner),
+        None => BtrieveFile::open(client, path, 0),
+    }
+    .with_context(|| format!("cannot open {}", path))?;
+    let record_length = columns
+        .iter()
+        .map(|c| c.offset as usize + c.size as usize)
+        .chain([file.stat()?.record_length as usize])
+        .max()
+        .unwrap_or(0);
+
+    let input = fs::File::open(source).with_context(|| format!("cannot read {}", source))?;
+    let mut reader = CsvReader::new(BufReader::new(input));
+    let Some((_, header)) = reader.next_row()? else {
+        bail!("{} is empty", source);
+    };
+    // CSV position of each mapped column
+    let mut layout = Vec::new();
+    for (index, name) in header.iter().enumerate() {
+        match columns.iter().find(|c| c.name.eq_ignore_ascii_case(name.trim())) {
+            Some(column) => layout.push((index, column)),
+            None => eprintln!("{}: column {} is not mapped, ignored", source, name),
+        }
+    }
+    if layout.is_empty() {
+        bail!("no CSV column matches the record layout");
+    }
+
+    let mut progress = Progress::default();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut lines = Vec::with_capacity(batch_size);
+    loop {
+        let row = reader.next_row().with_context(|| format!("{}: bad CSV", source))?;
+        if let Some((line, fields)) = &row {
+            progress.read += 1;
+            let mut record = vec![0u8; record_length];
+            let encoded = layout.iter().try_for_each(|&(index, column)| {
+                column.encode(fields.get(index).map_or("", String::as_str), &mut record)
+            });
+            match encoded {
+                Ok(()) => {
+                    batch.push(record);
+                    lines.push(*line);
+                }
+                Err(e) => progress.reject(*line, &e),
+            }
+        }
+        if batch.len() == batch_size || (row.is_none() && !batch.is_empty()) {
+            let report = file.insert_many(&batch)?;
+            progress.inserted += report.inserted;
+            for (index, e) in &report.failures {
+                progress.reject(lines[*index], e);
+            }
+            batch.clear();
+            lines.clear();
+        }
+        if row.is_none() {
+            break;
+        }
+        if progress.read % batch_size == 0 {
+            progress.show();
+        }
+    }
+    file.close()?;
+    progress.finish();
+
+    Ok(if progress.rejected == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+#[derive(Default)]
+struct Progress {
+    read: usize,
+    inserted: usize,
+    rejected: usize,
+}
+
+impl Progress {
+    fn reject(&mut self, line: usize, error: &dyn std::fmt::Display) {
+        self.rejected += 1;
+        eprintln!("\rline {}: {}", line, error);
+    }
+
+    fn show(&self) {
+        eprint!(
+            "\r{} rows read, {} inserted, {} rejected",
+            self.read, self.inserted, self.rejected
+        );
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        self.show();
+        eprintln!();
+    }
+}
+
+/// Columns from a mapping file
+pub(crate) fn parse_mapping(text: &str) -> Result<Vec<Column>> {
+    let mut columns: Vec<Column> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<_> = line.split_whitespace().collect();
+        // Type names may hold a blank ("unsigned binary"): the numbers are
+        // the last two or three words
+        let numbers = words.iter().rev().take_while(|w| w.parse::<u16>().is_ok()).count();
+        if words.len() < 4 || !(2..=3).contains(&numbers) || words.len() - numbers < 2 {
+            bail!("line {}: expected name type offset length [decimals]", n + 1);
+        }
+        let type_name = words[1..words.len() - numbers].join(" ");
+        let number = |i: usize| words[words.len() - numbers + i].parse::<u16>().unwrap();
+        columns.push(Column {
+            id: columns.len() as u16 + 1,
+            name: words[0].to_string(),
+            data_type: DataType::from_name(&type_name)
+                .with_context(|| format!("line {}: unknown type '{}'", n + 1, type_name))?,
+            offset: number(0),
+            size: number(1),
+            decimals: if numbers == 3 { number(2) as u8 } else { 0 },
+            flags: 0,
+        });
+    }
+    Ok(columns)
+}
+
+/// RFC 4180 reader: quoted fields may hold commas, doubled quotes and
+/// line breaks
+struct CsvReader<R> {
+    input: R,
+    line: usize,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn new(input: R) -> Self {
+        CsvReader { input, line: 0 }
+    }
+
+    /// Next row and the line it starts on, skipping blank lines
+    fn next_row(&mut self) -> io::Result<Option<(usize, Vec<String>)>> {
+        let mut text = String::new();
+        loop {
+            text.clear();
+            if self.input.read_line(&mut text)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !text.trim_end_matches(['\r', '\n']).is_empty() {
+                break;
+            }
+        }
+        let start = self.line;
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = text.chars().peekable();
+        loop {
+            let Some(c) = chars.next() else {
+                if !quoted {
+                    break;
+                }
+                // A line break inside quotes continues on the next line
+                text.clear();
+                if self.input.read_line(&mut text)? == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unterminated quote on line {}", start),
+                    ));
+                }
+                self.line += 1;
+                chars = text.chars().peekable();
+                continue;
+            };
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' if quoted => quoted = false,
+                '"' if field.is_empty() => quoted = true,
+                ',' if !quoted => fields.push(std::mem::take(&mut field)),
+                '\r' | '\n' if !quoted => {}
+                c => field.push(c),
+            }
+        }
+        fields.push(field);
+        Ok(Some((start, fields)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_reader() {
+        let data = "id,note\r\n1,\"a, \"\"b\"\"\"\n\n2,\"two\nlines\"\n3,\n";
+        let mut reader = CsvReader::new(data.as_bytes());
+        let rows: Vec<_> = std::iter::from_fn(|| reader.next_row().unwrap()).collect();
+        assert_eq!(rows[0], (1, vec!["id".to_string(), "note".to_string()]));
+        assert_eq!(rows[1].1, ["1", "a, \"b\""]);
+        assert_eq!(rows[2], (4, vec!["2".to_string(), "two\nlines".to_string()]));
+        assert_eq!(rows[3], (6, vec!["3".to_string(), String::new()]));
+
+        let mut open = CsvReader::new("1,\"never closed\n".as_bytes());
+        assert!(open.next_row().is_err());
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let columns = parse_mapping(
+            "# customer layout\n\
+             Id       unsigned binary  0  4\n\
+             Name     string           4 30   # padded\n\
+             Balance  money           34  6  2\n",
+        )
+        .unwrap();
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns[0].data_type, DataType::UnsignedBinary);
+        assert_eq!((columns[1].offset, columns[1].size), (4, 30));
+        assert_eq!((columns[2].data_type, columns[2].decimals), (DataType::Money, 2));
+
+        assert!(parse_mapping("Id blob 0 4").is_err());
+        assert!(parse_mapping("Id integer 0").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//! `xtutil locks`: show the server's lock report
+//!
+//! ```text
+//! xtutil locks [--watch <seconds>]
+//! ```
+//!
+//! Lists the record locks that have turned clients away with Record In Use
+//! or kept them waiting, the clients waiting right now and whom they wait
+//! on, and the conflict counts since the server started. With `--watch`,
+//! prints the report again every so many seconds.
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::LockReport;
+
+use crate::{flag_value, Global};
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut watch = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--watch" => {
+                let secs = flag_value(&mut args, "--watch")?;
+                watch = Some(secs.parse::<u64>().with_context(|| format!("bad interval {}", secs))?);
+            }
+            _ => bail!("usage: xtutil locks [--watch <seconds>]"),
+        }
+    }
+
+    let mut client = global.connect()?;
+    loop {
+        let report = client.lock_report().context("cannot read the lock report")?;
+        print!("{}", format_report(&report));
+        let Some(secs) = watch else {
+            return Ok(ExitCode::SUCCESS);
+        };
+        std::thread::sleep(Duration::from_secs(secs));
+        println!();
+    }
+}
+
+fn format_report(report: &LockReport) -> String {
+    let stats = &report.stats;
+    let mut out = format!(
+        "{} conflicts, {} waits ({} timed out), {:.3}s waited, longest {:.3}s\n",
+        stats.conflicts,
+        stats.waits,
+        stats.timeouts,
+        stats.total_wait.as_secs_f64(),
+        stats.longest_wait.as_secs_f64()
+    );
+    for b in &report.blockers {
+        out += &format!(
+            "session {} holds {} {}:{} for {:.1}s, refused {} (last session {})\n",
+            b.session,
+            b.file,
+            b.address.page,
+            b.address.slot,
+            b.held.as_secs_f64(),
+            b.refused,
+            b.last_refused
+        );
+    }
+    for w in &report.waiters {
+        out += &format!(
+            "session {} waits on session {} for {} {}:{}, {:.1}s\n",
+            w.session,
+            w.blocker,
+            w.file,
+            w.address.page,
+            w.address.slot,
+            w.waited.as_secs_f64()
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtrieve_engine::file_manager::locking::{LockBlocker, LockStats, LockWaiter};
+    use xtrieve_engine::storage::record::RecordAddress;
+
+    #[test]
+    fn test_format_report() {
+        let address = RecordAddress::new(0, 1024);
+        let report = LockReport {
+            stats: LockStats { conflicts: 12, waits: 3, timeouts: 1, total_wait: Duration::from_millis(1250), longest_wait: Duration::from_millis(800) },
+            waiters: vec![LockWaiter { session: 9, blocker: 7, file: "CUST.DAT".into(), address, waited: Duration::from_millis(2100) }],
+            blockers: vec![LockBlocker { session: 7, file: "CUST.DAT".into(), address, held: Duration::from_secs(12), refused: 10, last_refused: 9 }],
+        };
+        assert_eq!(
+            format_report(&report),
+            "12 conflicts, 3 waits (1 timed out), 1.250s waited, longest 0.800s\n\
+             session 7 holds CUST.DAT 0:1024 for 12.0s, refused 10 (last session 9)\n\
+             session 9 waits on session 7 for CUST.DAT 0:1024, 2.1s\n"
+        );
+    }
+}
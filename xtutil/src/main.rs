@@ -0,0 +1,196 @@
+//! xtutil - Xtrieve maintenance utility
+//!
+//! ```text
+//! xtutil [--server <addr>] <command> [args]
+//! ```
+//!
+//! `--server` takes any client address (`host:port`, `unix://`, `grpc://`,
+//! or `file://<data dir>` to work on files without a daemon). Without it,
+//! `XTRIEVE_SERVER` is used, then `127.0.0.1:7419`.
+//!
+//! Commands starting with `-` follow BUTIL's syntax (see `butil`).
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::{BtrieveError, BtrieveFile, StatusCode, XtrieveClient};
+
+mod butil;
+mod cancel;
+mod check;
+mod dbf;
+mod dump;
+mod export;
+mod files;
+mod import;
+mod locks;
+mod reindex;
+mod rollfwd;
+mod sql;
+mod sqlite;
+mod undelete;
+
+/// Environment variable naming the server when `--server` is not given
+const SERVER_ENV: &str = "XTRIEVE_SERVER";
+
+/// xtrieved's default listener
+const DEFAULT_SERVER: &str = "127.0.0.1:7419";
+
+const USAGE: &str = "\
+usage: xtutil [--server <addr>] <command> [args]
+
+BUTIL commands:
+  -STAT <file> [/O<owner>]
+  -CREATE <file> <description file>
+  -CLONE <new file> <existing file> [/O<owner>]
+  -COPY <input file> <output file> [/O<owner> [/O<owner>]]
+  -LOAD <unformatted file> <file> [/O<owner>]
+  -SAVE <file> <unformatted file> [N <key number>] [/O<owner>]
+
+commands:
+  cancel <request id>
+        stop a request sent with that id, such as an `sql --id` query
+  check <file>... [--quiet]
+        verify FCR, data pages, indexes and their cross-references
+  delete <file>... [--owner <name>]
+        delete files no client has open, with their recycle bins, logs and index files
+  dump <file> --page <n> [--as fcr|data|index|hex] [--hex]
+        decode the FCR, a data page or an index node as stored
+  export <file> [--format csv|jsonl] [--key <n>]
+         [--dict <dir> [--table <name>] | --map <mapping file>] [--where <filter>]
+         [--raw hex|latin1] [--owner <name>] [--output <path>]
+        write every record as CSV or JSON lines
+  import <csv> <file> (--dict <dir> [--table <name>] | --map <mapping file>)
+         [--create <description file>] [--batch <n>] [--owner <name>]
+        insert the rows of a CSV file, columns placed by a DDF or mapping file
+  import-dbf <dbf> <file> [--fields <name,...>] [--key <name+...>]... [--unique <name+...>]...
+             [--page-size <n>] [--map <path>] [--batch <n>] [--deleted]
+        create a file from a dBase table, chosen fields as keys
+  locks [--watch <seconds>]
+        show record locks turning clients away and who waits on whom
+  rename <file> <new name> [--owner <name>]
+        rename a file no client has open
+  reindex <file> [<key number>]
+        rebuild one or every index from the data pages (file must be closed)
+  rollfwd <file> [--log <path>] [--owner <name>]
+        apply a roll-forward log to a restored backup of the file
+  sql [--dict <dir>] [--local | --id <request id>] [--tsv] <select>
+        run a SELECT against the tables of a data dictionary
+  to-sqlite <database> --dict <dir> [<table>...] [--owner <name>] [--replace]
+        copy dictionary tables and their keys into a SQLite database
+  truncate <file>... [--owner <name>]
+        remove every record of files no other client has open
+  undelete <file> [<n>...] [--owner <name>]
+        list a file's recycle bin, or restore the records numbered
+";
+
+/// Options shared by every command
+pub struct Global {
+    pub server: String,
+}
+
+impl Global {
+    pub fn connect(&self) -> Result<XtrieveClient> {
+        XtrieveClient::connect(&self.server)
+            .with_context(|| format!("cannot connect to {}", self.server))
+    }
+}
+
+/// Take the value following a flag
+pub fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String> {
+    match args.next() {
+        Some(value) => Ok(value),
+        None => bail!("{} needs a value", flag),
+    }
+}
+
+/// Path of a file read directly rather than through the server: relative
+/// paths are under the data directory of a `file://` server
+pub fn local_path(global: &Global, path: &str) -> PathBuf {
+    match global.server.strip_prefix("file://") {
+        Some(dir) if Path::new(path).is_relative() => Path::new(dir).join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Visit every record in `key_number` order, or physically when it is
+/// `None` or the file has no keys
+pub fn walk(
+    file: &mut BtrieveFile,
+    key_number: Option<i32>,
+    mut visit: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let key_number = match key_number {
+        Some(key) if file.stat()?.num_keys > 0 => key,
+        _ => {
+            let mut record = file.step_first();
+            loop {
+                match record {
+                    Ok(r) => visit(&r.data)?,
+                    Err(BtrieveError::Status(StatusCode::EndOfFile)) => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                }
+                record = file.step_next();
+            }
+        }
+    };
+    for record in file.records(key_number) {
+        visit(&record?.data)?;
+    }
+    Ok(())
+}
+
+fn run() -> Result<ExitCode> {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut server = None;
+    while let Some(arg) = args.next_if(|a| a.starts_with("--")) {
+        match arg.as_str() {
+            "--server" => server = Some(flag_value(&mut args, "--server")?),
+            "--help" => {
+                print!("{}", USAGE);
+                return Ok(ExitCode::SUCCESS);
+            }
+            _ => bail!("unknown option {}\n\n{}", arg, USAGE),
+        }
+    }
+    let global = Global {
+        server: server
+            .or_else(|| std::env::var(SERVER_ENV).ok())
+            .unwrap_or_else(|| DEFAULT_SERVER.to_string()),
+    };
+
+    match args.next().as_deref() {
+        Some("cancel") => cancel::run(&global, args),
+        Some("check") => check::run(&global, args),
+        Some("delete") => files::delete(&global, args),
+        Some("dump") => dump::run(&global, args),
+        Some("export") => export::run(&global, args),
+        Some("import") => import::run(&global, args),
+        Some("import-dbf") => dbf::run(&global, args),
+        Some("locks") => locks::run(&global, args),
+        Some("rename") => files::rename(&global, args),
+        Some("reindex") => reindex::run(&global, args),
+        Some("rollfwd") => rollfwd::run(&global, args),
+        Some("sql") => sql::run(&global, args),
+        Some("to-sqlite") => sqlite::run(&global, args),
+        Some("truncate") => files::truncate(&global, args),
+        Some("undelete") => undelete::run(&global, args),
+        Some(command) if command.starts_with('-') => butil::run(&global, command, args),
+        Some(command) => bail!("unknown command {}\n\n{}", command, USAGE),
+        None => {
+            eprint!("{}", USAGE);
+            Ok(ExitCode::from(2))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("xtutil: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! `xtutil reindex`: rebuild indexes from the data pages
+//!
+//! ```text
+//! xtutil reindex <file> [<key number>]
+//! ```
+//!
+//! Drops the index pages of the key, or of every key, and bulk-loads them
+//! again from the records. The file is rewritten directly, so no server
+//! may have it open. A file with an index file per key has only the
+//! key's file rewritten.
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_engine::storage::{rebuild_file, RebuildReport};
+
+use crate::{local_path, Global};
+
+pub fn run(global: &Global, args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let args: Vec<String> = args.collect();
+    let (path, key_number) = match &args[..] {
+        [path] => (path, None),
+        [path, key] => (path, Some(key.parse().with_context(|| format!("bad key number {}", key))?)),
+        _ => bail!("usage: xtutil reindex <file> [<key number>]"),
+    };
+
+    let local = local_path(global, path);
+    let report = rebuild_file(&local, key_number).with_context(|| format!("cannot reindex {}", local.display()))?;
+    print!("{}", format_report(path, &report));
+    Ok(ExitCode::SUCCESS)
+}
+
+fn format_report(path: &str, report: &RebuildReport) -> String {
+    let mut out = format!("{}\n", path);
+    for &(key, entries, pages) in &report.keys {
+        out += &format!("  key {:<8} {} entries in {} pages\n", key, entries, pages);
+    }
+    out += &format!("  {} index pages dropped\n", report.dropped_pages);
+    out += &format!("  size {} -> {} bytes\n", report.size_before, report.size_after);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_format_report() {
+        let report = RebuildReport {
+            keys: vec![(0, 60, 2), (1, 58, 2)],
+            dropped_pages: 3,
+            size_before: 4096,
+            size_after: 3584,
+        };
+        assert_eq!(
+            format_report("CUST.DAT", &report),
+            "CUST.DAT\n\
+             \x20 key 0        60 entries in 2 pages\n\
+             \x20 key 1        58 entries in 2 pages\n\
+             \x20 3 index pages dropped\n\
+             \x20 size 4096 -> 3584 bytes\n"
+        );
+
+        let global = Global { server: "file:///srv/data".into() };
+        assert_eq!(local_path(&global, "CUST.DAT"), Path::new("/srv/data/CUST.DAT"));
+        assert_eq!(local_path(&global, "/tmp/X.DAT"), Path::new("/tmp/X.DAT"));
+    }
+}
@@ -0,0 +1,180 @@
+//! `xtutil rollfwd`: apply a roll-forward log to a restored backup
+//!
+//! ```text
+//! xtutil rollfwd <file> [--log <path>] [--owner <name>]
+//! ```
+//!
+//! Replays the changes in the log of a file created with the roll-forward
+//! flag, oldest first, through the server: the recovery half of Btrieve's
+//! BLOG. The file should be the backup the log was started after. Updates
+//! and deletes find their record by its whole contents, so a change whose
+//! record isn't in the file stops the run.
+//!
+//! The log is read directly, from beside the file unless `--log` names a
+//! copy. The file logs the changes again as they are applied, so its own
+//! log is moved aside to `<name>.ROLL` first and removed once every change
+//! is in. A log sealed with an owner name needs `--owner`; one sealed with
+//! the daemon's key can only be applied by the daemon's host.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::{BtrieveError, BtrieveFile, StatusCode};
+use xtrieve_engine::storage::crypt::{Encryption, PageCipher};
+use xtrieve_engine::storage::rollfwd::{Change, RollForwardLog};
+use xtrieve_engine::storage::FileControlRecord;
+
+use crate::{flag_value, local_path, Global};
+
+/// Changes applied, by kind
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Tally {
+    inserts: usize,
+    updates: usize,
+    deletes: usize,
+    truncates: usize,
+}
+
+impl Tally {
+    fn total(&self) -> usize {
+        self.inserts + self.updates + self.deletes + self.truncates
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "applied {} changes: {} inserts, {} updates, {} deletes, {} truncates",
+            self.total(),
+            self.inserts,
+            self.updates,
+            self.deletes,
+            self.truncates
+        )
+    }
+}
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut path = None;
+    let mut log_path = None;
+    let mut owner = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log" => log_path = Some(flag_value(&mut args, "--log")?),
+            "--owner" => owner = Some(flag_value(&mut args, "--owner")?),
+            _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            _ if path.is_none() => path = Some(arg),
+            _ => bail!("unexpected argument {}", arg),
+        }
+    }
+    let Some(path) = path else {
+        bail!("usage: xtutil rollfwd <file> [--log <path>] [--owner <name>]");
+    };
+
+    let local = local_path(global, &path);
+    let cipher = log_cipher(&local, owner.as_deref())?;
+    let own_log = RollForwardLog::new(&local, None).path().to_path_buf();
+    let log = match &log_path {
+        Some(log_path) => RollForwardLog::at(&local_path(global, log_path), cipher),
+        None => RollForwardLog::at(&own_log, cipher),
+    };
+    let entries = log.entries().with_context(|| format!("cannot read the log {}", log.path().display()))?;
+
+    let client = global.connect()?;
+    let mut file = match &owner {
+        Some(owner) => BtrieveFile::open_with_owner(client, &path, 0, owner),
+        None => BtrieveFile::open(client, &path, 0),
+    }
+    .with_context(|| format!("cannot open {}", path))?;
+
+    // The file's own log fills up again as the changes are applied
+    let read_from = if log.path() == own_log && own_log.exists() {
+        let aside = own_log.with_extension("ROLL");
+        fs::rename(&own_log, &aside).with_context(|| format!("cannot move {} aside", own_log.display()))?;
+        aside
+    } else {
+        log.path().to_path_buf()
+    };
+
+    let mut tally = Tally::default();
+    for (n, entry) in entries.iter().enumerate() {
+        apply(&mut file, &entry.change, &mut tally).with_context(|| {
+            format!("change {} of {} not applied; the log is kept in {}", n + 1, entries.len(), read_from.display())
+        })?;
+    }
+    file.close()?;
+    if read_from != log.path() {
+        fs::remove_file(&read_from)?;
+    }
+    println!("{}", tally.summary());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Key the file's log is sealed with, from the FCR of the file
+fn log_cipher(local: &Path, owner: Option<&str>) -> Result<Option<PageCipher>> {
+    let mut file = fs::File::open(local).with_context(|| format!("cannot open {}", local.display()))?;
+    let mut header = vec![0u8; 0x0A];
+    file.read_exact(&mut header).context("file is too short to hold an FCR")?;
+    let page_size = u16::from_le_bytes([header[0x08], header[0x09]]) as usize;
+    header.resize(page_size.max(header.len()), 0);
+    file.read_exact(&mut header[0x0A..]).context("file is too short to hold an FCR")?;
+    let fcr = FileControlRecord::from_bytes(&header).context("cannot read the FCR")?;
+
+    match (fcr.encryption, fcr.owner, owner) {
+        (Encryption::None, _, _) => Ok(None),
+        (Encryption::Owner, Some(_), Some(owner)) => Ok(Some(PageCipher::for_owner(owner.as_bytes(), &fcr.salt))),
+        (Encryption::Owner, _, _) => bail!("the log is sealed with the owner name: give it with --owner"),
+        (Encryption::DaemonKey, _, _) => bail!("the log is sealed with the daemon's key"),
+    }
+}
+
+/// Make one logged change to the file
+fn apply(file: &mut BtrieveFile, change: &Change, tally: &mut Tally) -> Result<()> {
+    match change {
+        Change::Insert(record) => {
+            file.insert(record)?;
+            tally.inserts += 1;
+        }
+        Change::Update { before, after } => {
+            find(file, before)?;
+            file.update(after)?;
+            tally.updates += 1;
+        }
+        Change::Delete(record) => {
+            find(file, record)?;
+            file.delete()?;
+            tally.deletes += 1;
+        }
+        Change::Truncate => {
+            file.truncate()?;
+            tally.truncates += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Step to the record holding exactly `record`
+fn find(file: &mut BtrieveFile, record: &[u8]) -> Result<()> {
+    let mut step = file.step_first();
+    loop {
+        match step {
+            Ok(r) if r.data == record => return Ok(()),
+            Ok(_) => {}
+            Err(BtrieveError::Status(StatusCode::EndOfFile)) => bail!("the record changed isn't in the file"),
+            Err(e) => return Err(e.into()),
+        }
+        step = file.step_next();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let tally = Tally { inserts: 5, updates: 2, deletes: 1, truncates: 0 };
+        assert_eq!(tally.summary(), "applied 8 changes: 5 inserts, 2 updates, 1 deletes, 0 truncates");
+    }
+}
@@ -0,0 +1,50 @@
+//! `xtutil sql`: ad-hoc SELECTs over DDF-described tables
+//!
+//! The statement runs on the daemon when it supports the Query operation,
+//! otherwise (or with `--local`) xtutil issues the Btrieve calls itself.
+//! With `--id`, it runs on the daemon under that request id, which
+//! `xtutil cancel` can stop it by.
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::query;
+
+use crate::{flag_value, Global};
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut dictionary_dir = String::new();
+    let mut local = false;
+    let mut tsv = false;
+    let mut request_id = None;
+    let mut sql = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dict" => dictionary_dir = flag_value(&mut args, "--dict")?,
+            "--id" => {
+                let id = flag_value(&mut args, "--id")?;
+                request_id = Some(id.parse::<u64>().ok().filter(|&id| id != 0).with_context(|| format!("bad request id {}", id))?);
+            }
+            "--local" => local = true,
+            "--tsv" => tsv = true,
+            _ => sql.push(arg),
+        }
+    }
+    if sql.is_empty() {
+        bail!("sql needs a SELECT statement");
+    }
+    let sql = sql.join(" ");
+
+    let mut client = global.connect()?;
+    let result = match (local, request_id) {
+        (true, Some(_)) => bail!("--id runs the query on the server, not with --local"),
+        (true, None) => query::execute(&mut client, &dictionary_dir, &sql)?,
+        (false, Some(id)) => query::execute_cancellable(&mut client, &dictionary_dir, &sql, id)?,
+        (false, None) => query::query(&mut client, &dictionary_dir, &sql)?,
+    };
+    match tsv {
+        true => print!("{}", result.to_tsv()),
+        false => print!("{}", result),
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,263 @@
+//! `xtutil to-sqlite`: copy tables into a SQLite database
+//!
+//! ```text
+//! xtutil to-sqlite <database> --dict <dir> [<table>...] [--owner <name>] [--replace]
+//! ```
+//!
+//! Every table of the data dictionary, or the ones named, becomes a
+//! SQLite table with a column per dictionary column and an index per
+//! Btrieve key. Indexes are unique unless the key allows duplicates.
+//! Dates and times are stored as ISO text, decimals as NUMERIC and
+//! columns without a SQL equivalent as blobs. An existing table stops
+//! the copy unless `--replace` drops it first.
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection, Statement};
+use xtrieve_client::ddf::{DataType, Dictionary, Index, Table, Value};
+use xtrieve_client::BtrieveFile;
+use xtrieve_engine::storage::KeyFlags;
+
+use crate::{flag_value, walk, Global};
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut database = None;
+    let mut dictionary_dir = None;
+    let mut names = Vec::new();
+    let mut owner = None;
+    let mut replace = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dict" => dictionary_dir = Some(flag_value(&mut args, "--dict")?),
+            "--owner" => owner = Some(flag_value(&mut args, "--owner")?),
+            "--replace" => replace = true,
+            _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            _ if database.is_none() => database = Some(arg),
+            _ => names.push(arg),
+        }
+    }
+    let (Some(database), Some(dir)) = (database, dictionary_dir) else {
+        bail!("usage: xtutil to-sqlite <database> --dict <dir> [<table>...] [--owner <name>] [--replace]");
+    };
+
+    let dictionary = Dictionary::load(&mut global.connect()?, &dir).context("cannot load dictionary")?;
+    let tables: Vec<&Table> = if names.is_empty() {
+        dictionary.tables.iter().collect()
+    } else {
+        names
+            .iter()
+            .map(|name| dictionary.table(name).with_context(|| format!("no table {} in the dictionary", name)))
+            .collect::<Result<_>>()?
+    };
+
+    let mut db = Connection::open(&database).with_context(|| format!("cannot open {}", database))?;
+    for table in tables {
+        let path = table.file_path(&dir);
+        let client = global.connect()?;
+        let mut file = match &owner {
+            Some(owner) => BtrieveFile::open_with_owner(client, &path, 0, owner),
+            None => BtrieveFile::open(client, &path, 0),
+        }
+        .with_context(|| format!("cannot open {}", path))?;
+
+        if replace {
+            db.execute(&format!("DROP TABLE IF EXISTS {}", quote(&table.name)), [])?;
+        }
+        let tx = db.transaction()?;
+        tx.execute(&create_table(table), [])
+            .with_context(|| format!("cannot create table {} (--replace drops it)", table.name))?;
+        let mut rows = Rows::new(&tx, table)?;
+        walk(&mut file, None, |record| rows.add(record))?;
+        let count = rows.count;
+        drop(rows);
+
+        let mut created = 0;
+        for index in &table.indexes {
+            for warning in create_index(&tx, table, index)? {
+                eprintln!("warning: {}", warning);
+            }
+            created += 1;
+        }
+        tx.commit()?;
+        file.close()?;
+        println!("{}: {} rows, {} indexes", table.name, count, created);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Double-quoted SQL identifier
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn column_type(data_type: DataType) -> &'static str {
+    match data_type {
+        DataType::Integer
+        | DataType::UnsignedBinary
+        | DataType::AutoIncrement
+        | DataType::Logical
+        | DataType::Bit => "INTEGER",
+        DataType::Float | DataType::BFloat => "REAL",
+        DataType::Decimal | DataType::Money | DataType::Numeric => "NUMERIC",
+        DataType::String
+        | DataType::LString
+        | DataType::ZString
+        | DataType::Note
+        | DataType::Date
+        | DataType::Time => "TEXT",
+        DataType::LVar | DataType::Other(_) => "BLOB",
+    }
+}
+
+fn create_table(table: &Table) -> String {
+    let columns: Vec<String> = table
+        .columns
+        .iter()
+        .map(|c| format!("{} {}", quote(&c.name), column_type(c.data_type)))
+        .collect();
+    format!("CREATE TABLE {} ({})", quote(&table.name), columns.join(", "))
+}
+
+/// Create the SQLite index for a key. A unique key whose values repeat
+/// (blank keys Btrieve leaves out, say) gets a plain index and a warning.
+fn create_index(db: &Connection, table: &Table, index: &Index) -> Result<Vec<String>> {
+    let mut columns = Vec::new();
+    let mut duplicates = false;
+    for segment in &index.segments {
+        let column = table
+            .column_by_id(segment.column)
+            .with_context(|| format!("key {} of {} uses unknown column {}", index.number, table.name, segment.column))?;
+        let flags = KeyFlags::from_bits_truncate(segment.flags);
+        duplicates |= flags.contains(KeyFlags::DUPLICATES);
+        let order = if flags.contains(KeyFlags::DESCENDING) { " DESC" } else { "" };
+        columns.push(format!("{}{}", quote(&column.name), order));
+    }
+
+    let name = quote(&format!("{}_key{}", table.name, index.number));
+    let on = format!("{} ON {} ({})", name, quote(&table.name), columns.join(", "));
+    if duplicates {
+        db.execute(&format!("CREATE INDEX {}", on), [])?;
+        return Ok(Vec::new());
+    }
+    match db.execute(&format!("CREATE UNIQUE INDEX {}", on), []) {
+        Ok(_) => Ok(Vec::new()),
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+            db.execute(&format!("CREATE INDEX {}", on), [])?;
+            Ok(vec![format!(
+                "key {} of {} repeats values; its index is not unique",
+                index.number, table.name
+            )])
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn sql_value(value: Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Int(v) => SqlValue::Integer(v),
+        // Above i64::MAX only as text, which SQLite can still compare
+        Value::UInt(v) => i64::try_from(v).map_or_else(|_| SqlValue::Text(v.to_string()), SqlValue::Integer),
+        Value::Float(v) => SqlValue::Real(v),
+        Value::Bool(v) => SqlValue::Integer(v as i64),
+        Value::Bytes(v) => SqlValue::Blob(v),
+        Value::Text(v) => SqlValue::Text(v),
+        value @ (Value::Decimal { .. } | Value::Date(_) | Value::Time(_)) => SqlValue::Text(value.to_string()),
+    }
+}
+
+/// Inserts decoded records into a table
+struct Rows<'a> {
+    insert: Statement<'a>,
+    table: &'a Table,
+    count: u64,
+}
+
+impl<'a> Rows<'a> {
+    fn new(db: &'a Connection, table: &'a Table) -> Result<Self> {
+        let placeholders = vec!["?"; table.columns.len()].join(", ");
+        let insert = db.prepare(&format!("INSERT INTO {} VALUES ({})", quote(&table.name), placeholders))?;
+        Ok(Rows { insert, table, count: 0 })
+    }
+
+    fn add(&mut self, record: &[u8]) -> Result<()> {
+        let values = self.table.decode(record)?;
+        self.insert.execute(params_from_iter(values.into_iter().map(|(_, v)| sql_value(v))))?;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtrieve_client::ddf::{Column, IndexSegment};
+
+    fn column(id: u16, name: &str, data_type: DataType, offset: u16, size: u16, decimals: u8) -> Column {
+        Column { id, name: name.into(), data_type, offset, size, decimals, flags: 0 }
+    }
+
+    fn table() -> Table {
+        Table {
+            id: 1,
+            name: "Cust".into(),
+            location: "CUST.DAT".into(),
+            flags: 0,
+            columns: vec![
+                column(1, "Id", DataType::Integer, 0, 4, 0),
+                column(2, "Name", DataType::String, 4, 10, 0),
+                column(3, "Balance", DataType::Money, 14, 8, 2),
+            ],
+            indexes: vec![
+                Index { number: 0, segments: vec![IndexSegment { column: 1, flags: 0 }] },
+                Index {
+                    number: 1,
+                    segments: vec![IndexSegment { column: 2, flags: (KeyFlags::DUPLICATES | KeyFlags::DESCENDING).bits() }],
+                },
+            ],
+        }
+    }
+
+    fn record(table: &Table, values: [&str; 3]) -> Vec<u8> {
+        let mut data = vec![0u8; table.record_length()];
+        for (column, value) in table.columns.iter().zip(values) {
+            column.encode(value, &mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn test_copy_table() {
+        let table = table();
+        assert_eq!(
+            create_table(&table),
+            "CREATE TABLE \"Cust\" (\"Id\" INTEGER, \"Name\" TEXT, \"Balance\" NUMERIC)"
+        );
+
+        let db = Connection::open_in_memory().unwrap();
+        db.execute(&create_table(&table), []).unwrap();
+        let mut rows = Rows::new(&db, &table).unwrap();
+        rows.add(&record(&table, ["7", "Smith", "12.50"])).unwrap();
+        rows.add(&record(&table, ["8", "Jones", "0.05"])).unwrap();
+        drop(rows);
+        for index in &table.indexes {
+            assert!(create_index(&db, &table, index).unwrap().is_empty());
+        }
+
+        let (name, balance): (String, f64) = db
+            .query_row("SELECT Name, Balance FROM Cust WHERE Id = 7", [], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap();
+        assert_eq!((name.as_str(), balance), ("Smith", 12.5));
+        let sql: String = db
+            .query_row("SELECT sql FROM sqlite_master WHERE name = 'Cust_key1'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(sql, "CREATE INDEX \"Cust_key1\" ON \"Cust\" (\"Name\" DESC)");
+
+        // A repeated value leaves key 0 without its uniqueness
+        db.execute("DROP INDEX Cust_key0", []).unwrap();
+        db.execute("INSERT INTO Cust VALUES (7, 'Again', 0)", []).unwrap();
+        assert_eq!(create_index(&db, &table, &table.indexes[0]).unwrap().len(), 1);
+    }
+}
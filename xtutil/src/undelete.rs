@@ -0,0 +1,111 @@
+//! `xtutil undelete`: list or restore records from a recycle bin
+//!
+//! ```text
+//! xtutil undelete <file> [<n>...] [--owner <name>]
+//! ```
+//!
+//! Without numbers, lists the records in the bin of a file created with
+//! one, most recently deleted first, each with the number to restore it by.
+//! With numbers, inserts those records again and takes them out of the bin.
+
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use xtrieve_client::{BtrieveError, BtrieveFile, DeletedRecord, StatusCode};
+
+use crate::{flag_value, Global};
+
+/// Bytes of each record shown in the list
+const PREVIEW_LEN: usize = 48;
+
+pub fn run(global: &Global, mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut path = None;
+    let mut owner = None;
+    let mut numbers = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--owner" => owner = Some(flag_value(&mut args, "--owner")?),
+            _ if path.is_none() => path = Some(arg),
+            _ => numbers.push(arg.parse::<u32>().with_context(|| format!("bad record number {}", arg))?),
+        }
+    }
+    let Some(path) = path else {
+        bail!("usage: xtutil undelete <file> [<n>...] [--owner <name>]");
+    };
+
+    let client = global.connect()?;
+    let mut file = match &owner {
+        Some(owner) => BtrieveFile::open_with_owner(client, &path, 0, owner),
+        None => BtrieveFile::open(client, &path, 0),
+    }
+    .with_context(|| format!("cannot open {}", path))?;
+
+    if numbers.is_empty() {
+        for back in 0.. {
+            match file.get_deleted(back) {
+                Ok(deleted) => println!("{}", format_entry(back, &deleted)),
+                Err(BtrieveError::Status(StatusCode::EndOfFile)) => break,
+                Err(e) => return Err(e).context("cannot read the recycle bin"),
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Restoring a record moves the older ones up, so go from the oldest
+    numbers.sort_unstable();
+    numbers.dedup();
+    for &back in numbers.iter().rev() {
+        file.undelete(back).with_context(|| format!("cannot restore record {}", back))?;
+        println!("restored record {}", back);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// One line of the list: number, deletion time and the start of the record
+fn format_entry(back: u32, deleted: &DeletedRecord) -> String {
+    let preview: String = deleted.data[..deleted.data.len().min(PREVIEW_LEN)]
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+    format!("{:>4}  {}  {}", back, format_utc(deleted.deleted_at), preview)
+}
+
+/// Seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS` UTC
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_entry() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_utc(1_792_301_686), "2026-10-18 05:34:46");
+
+        let deleted = DeletedRecord { data: b"\x2a\0\0\0ACME WIDGET".to_vec(), deleted_at: 0 };
+        assert_eq!(format_entry(3, &deleted), "   3  1970-01-01 00:00:00  *...ACME WIDGET");
+    }
+}